@@ -1,15 +1,19 @@
 use std::borrow::Cow;
 use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
 
 use bytes::Bytes;
 use futures::{Async, Future, Poll, Stream};
+use futures::future::Executor;
 use futures::sync::{mpsc, oneshot};
 use h2;
 use http::HeaderMap;
 
 use common::Never;
-use super::{Chunk, Payload};
-use super::internal::{FullDataArg, FullDataRet};
+use proto::h2::StreamReset;
+use super::{BlockingRead, Chunk, ChunkExtensions, Payload};
+use super::internal::{FileArg, FileRet, FullDataArg, FullDataRet};
 
 type BodySender = mpsc::Sender<Result<Chunk, ::Error>>;
 
@@ -30,6 +34,20 @@ pub struct Body {
     /// a brand new connection, since the pool didn't know about the idle
     /// connection yet.
     delayed_eof: Option<DelayEof>,
+    /// The timings of the response this is the body of, recording when its
+    /// end is read.
+    timings: Option<::ext::Timings>,
+    /// Counts the bytes read from this body, for an access log.
+    read_count: Option<::instrument::ByteCount>,
+    /// The file of a `Body::from_file`, until the body is first polled and
+    /// the file is read, or it's taken to be sent with `sendfile`.
+    file: Option<PendingFile>,
+}
+
+struct PendingFile {
+    file: File,
+    len: u64,
+    reader_tx: oneshot::Sender<Box<Read + Send>>,
 }
 
 enum Kind {
@@ -38,11 +56,22 @@ enum Kind {
         content_length: Option<u64>,
         abort_rx: oneshot::Receiver<()>,
         rx: mpsc::Receiver<Result<Chunk, ::Error>>,
+        end_rx: oneshot::Receiver<ChanEnd>,
+        /// What followed the data, once it's received.
+        end: Option<ChanEnd>,
     },
-    H2(h2::RecvStream),
+    /// A received HTTP/2 stream, and how to reset it, if it can be.
+    H2(h2::RecvStream, Option<StreamReset>),
     Wrapped(Box<Stream<Item=Chunk, Error=Box<::std::error::Error + Send + Sync>> + Send>),
 }
 
+/// What a channel body ends with, after its data.
+#[derive(Debug, Default)]
+struct ChanEnd {
+    trailers: Option<HeaderMap>,
+    last_chunk_ext: Option<ChunkExtensions>,
+}
+
 type DelayEofUntil = oneshot::Receiver<Never>;
 
 enum DelayEof {
@@ -63,6 +92,16 @@ enum DelayEof {
 pub struct Sender {
     abort_tx: oneshot::Sender<()>,
     tx: BodySender,
+    end_tx: oneshot::Sender<ChanEnd>,
+}
+
+/// A future of the trailers of a `Body`.
+///
+/// Created with [`Body::trailers`](Body::trailers).
+#[must_use = "futures do nothing unless polled"]
+#[derive(Debug)]
+pub struct Trailers<'a> {
+    body: &'a mut Body,
 }
 
 impl Body {
@@ -83,30 +122,123 @@ impl Body {
 
     /// Create a `Body` stream with an associated sender half.
     ///
-    /// Useful when wanting to stream chunks from another thread.
+    /// Useful when wanting to stream chunks from another thread. The
+    /// `Sender` can end the body with trailers, which are sent after the
+    /// data if the connection supports them.
     #[inline]
     pub fn channel() -> (Sender, Body) {
-        Self::new_channel(None)
+        Self::new_channel(None, 1)
+    }
+
+    /// Create a `Body` stream with an associated sender half, that buffers
+    /// up to `capacity` chunks.
+    ///
+    /// The `Sender` isn't ready while the buffer is full. A connection only
+    /// takes chunks out of it when it can write them, so a slow peer (or a
+    /// small HTTP/2 flow-control window) slows down the `Sender`, instead
+    /// of chunks piling up in memory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    #[inline]
+    pub fn channel_with_capacity(capacity: usize) -> (Sender, Body) {
+        assert!(capacity > 0, "body channel capacity must be greater than 0");
+        Self::new_channel(None, capacity)
     }
 
     #[inline]
-    pub(crate) fn new_channel(content_length: Option<u64>) -> (Sender, Body) {
-        let (tx, rx) = mpsc::channel(0);
+    pub(crate) fn new_channel(content_length: Option<u64>, capacity: usize) -> (Sender, Body) {
+        // The channel gives each sender a slot of its own.
+        let (tx, rx) = mpsc::channel(capacity - 1);
         let (abort_tx, abort_rx) = oneshot::channel();
+        let (end_tx, end_rx) = oneshot::channel();
 
         let tx = Sender {
             abort_tx: abort_tx,
             tx: tx,
+            end_tx: end_tx,
         };
         let rx = Body::new(Kind::Chan {
             content_length,
             abort_rx,
             rx,
+            end_rx,
+            end: None,
         });
 
         (tx, rx)
     }
 
+    /// Create a `Body` stream of the bytes of a blocking `Read`.
+    ///
+    /// The reader is read on the `executor`, which should run blocking tasks
+    /// on threads of their own, such as a `futures_cpupool::CpuPool`. Only a
+    /// chunk is buffered ahead of the consumer of the body, so a slow
+    /// connection doesn't make the whole reader pile up in memory. The reader
+    /// stops being read once the `Body` is dropped.
+    ///
+    /// Useful to send files or the output of a subprocess. If the reader
+    /// errors, so does the `Body`.
+    pub fn from_reader<R, E>(reader: R, executor: &E) -> Body
+    where
+        R: Read + Send + 'static,
+        E: Executor<BlockingRead>,
+    {
+        Body::read_on(Box::new(reader), None, executor)
+    }
+
+    /// Create a `Body` stream of the contents of a `File`.
+    ///
+    /// This works like [`from_reader`](Body::from_reader), except that the
+    /// length of the file is known, so it can be sent with a `Content-Length`
+    /// instead of chunked transfer coding. If the file is shorter than its
+    /// length was when creating the `Body`, the `Body` ends early.
+    ///
+    /// With the `sendfile` feature, on Linux, a `Body` sent as the body of
+    /// a message on a plaintext HTTP/1 connection is sent with `sendfile`,
+    /// from the `File` to the socket, instead of being read. Otherwise, such
+    /// as with TLS or HTTP/2, it's read on the `executor`, once polled.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metadata of the file can't be read.
+    pub fn from_file<E>(file: File, executor: &E) -> io::Result<Body>
+    where
+        E: Executor<BlockingRead>,
+    {
+        let len = file.metadata()?.len();
+        let (reader_tx, reader_rx) = oneshot::channel();
+        let (tx, mut body) = Body::new_channel(Some(len), 1);
+        Body::execute_read(BlockingRead::deferred(reader_rx, tx), executor);
+        body.file = Some(PendingFile {
+            file,
+            len,
+            reader_tx,
+        });
+        Ok(body)
+    }
+
+    fn read_on<E>(reader: Box<Read + Send>, content_length: Option<u64>, executor: &E) -> Body
+    where
+        E: Executor<BlockingRead>,
+    {
+        let (tx, body) = Body::new_channel(content_length, 1);
+        Body::execute_read(BlockingRead::new(reader, tx), executor);
+        body
+    }
+
+    fn execute_read<E>(read: BlockingRead, executor: &E)
+    where
+        E: Executor<BlockingRead>,
+    {
+        if let Err(err) = executor.execute(read) {
+            debug!("executor error reading body: {:?}", err.kind());
+            let cause = io::Error::new(io::ErrorKind::Other, "executor failed to read body");
+            err.into_future().fail(::Error::new_body(cause));
+        }
+    }
+
     /// Wrap a futures `Stream` in a box inside `Body`.
     ///
     /// # Example
@@ -139,21 +271,81 @@ impl Body {
         Body::new(Kind::Wrapped(Box::new(mapped)))
     }
 
+    /// Get the trailers of this body.
+    ///
+    /// Trailers are received with HTTP/2 streams and HTTP/1 chunked bodies,
+    /// after the data. This should only be polled once the data of the body
+    /// has been read.
+    pub fn trailers(&mut self) -> Trailers {
+        Trailers {
+            body: self,
+        }
+    }
+
+    /// Get the chunk extensions of the last chunk of this body.
+    ///
+    /// These are received with HTTP/1 chunked bodies, on the last chunk,
+    /// which has no data. They are only available once the data of the
+    /// body has been read.
+    pub fn last_chunk_extensions(&mut self) -> Option<&ChunkExtensions> {
+        match self.kind {
+            Kind::Chan { ref mut end_rx, ref mut end, .. } => {
+                if end.is_none() {
+                    match end_rx.try_recv() {
+                        Ok(Some(received)) => *end = Some(received),
+                        Ok(None) => (),
+                        Err(_canceled) => *end = Some(ChanEnd::default()),
+                    }
+                }
+                end.as_ref().and_then(|end| end.last_chunk_ext.as_ref())
+            },
+            _ => None,
+        }
+    }
+
     fn new(kind: Kind) -> Body {
         Body {
             kind: kind,
             delayed_eof: None,
+            timings: None,
+            read_count: None,
+            file: None,
+        }
+    }
+
+    /// Resets the HTTP/2 stream this body is received on with `reason`,
+    /// and ends the body.
+    ///
+    /// This lets the peer know why the rest of the body isn't wanted, such
+    /// as with `REFUSED_STREAM` or `ENHANCE_YOUR_CALM`, while dropping the
+    /// body always resets the stream with `CANCEL`. Only response bodies
+    /// received by a client over HTTP/2 can be reset; other bodies are just
+    /// ended.
+    pub fn reset(&mut self, reason: ::error::Reason) {
+        if let Kind::H2(_, ref mut stream_reset) = self.kind {
+            if let Some(stream_reset) = stream_reset.take() {
+                stream_reset.reset(reason);
+            }
         }
+        self.kind = Kind::Once(None);
     }
 
-    pub(crate) fn h2(recv: h2::RecvStream) -> Self {
-        Body::new(Kind::H2(recv))
+    pub(crate) fn h2(recv: h2::RecvStream, reset: Option<StreamReset>) -> Self {
+        Body::new(Kind::H2(recv, reset))
     }
 
     pub(crate) fn delayed_eof(&mut self, fut: DelayEofUntil) {
         self.delayed_eof = Some(DelayEof::NotEof(fut));
     }
 
+    pub(crate) fn set_timings(&mut self, timings: ::ext::Timings) {
+        self.timings = Some(timings);
+    }
+
+    pub(crate) fn set_read_count(&mut self, count: ::instrument::ByteCount) {
+        self.read_count = Some(count);
+    }
+
     fn poll_eof(&mut self) -> Poll<Option<Chunk>, ::Error> {
         match self.delayed_eof.take() {
             Some(DelayEof::NotEof(mut delay)) => {
@@ -195,9 +387,9 @@ impl Body {
     fn poll_inner(&mut self) -> Poll<Option<Chunk>, ::Error> {
         match self.kind {
             Kind::Once(ref mut val) => Ok(Async::Ready(val.take())),
-            Kind::Chan { content_length: ref mut len, ref mut rx, ref mut abort_rx } => {
+            Kind::Chan { content_length: ref mut len, ref mut rx, ref mut abort_rx, .. } => {
                 if let Ok(Async::Ready(())) = abort_rx.poll() {
-                    return Err(::Error::new_body_write("body write aborted"));
+                    return Err(::Error::new_body_write_aborted());
                 }
 
                 match rx.poll().expect("mpsc cannot error") {
@@ -213,7 +405,7 @@ impl Body {
                     Async::NotReady => Ok(Async::NotReady),
                 }
             },
-            Kind::H2(ref mut h2) => {
+            Kind::H2(ref mut h2, _) => {
                 h2.poll()
                     .map(|async| {
                         async.map(|opt| {
@@ -243,12 +435,40 @@ impl Payload for Body {
     type Error = ::Error;
 
     fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
-        self.poll_eof()
+        if let Some(pending) = self.file.take() {
+            let reader = Box::new(pending.file.take(pending.len));
+            let _ = pending.reader_tx.send(reader);
+        }
+        let res = self.poll_eof();
+        match res {
+            Ok(Async::Ready(Some(ref chunk))) => {
+                if let Some(ref count) = self.read_count {
+                    count.add(chunk.len());
+                }
+            },
+            Ok(Async::Ready(None)) => {
+                if let Some(timings) = self.timings.take() {
+                    timings.set_response_complete();
+                }
+            },
+            _ => (),
+        }
+        res
     }
 
     fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
         match self.kind {
-            Kind::H2(ref mut h2) => h2.poll_trailers().map_err(::Error::new_h2),
+            Kind::H2(ref mut h2, _) => h2.poll_trailers().map_err(::Error::new_h2),
+            Kind::Chan { ref mut end_rx, ref mut end, .. } => {
+                if end.is_none() {
+                    *end = Some(match end_rx.poll() {
+                        Ok(Async::Ready(received)) => received,
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(_canceled) => ChanEnd::default(),
+                    });
+                }
+                Ok(Async::Ready(end.as_mut().and_then(|end| end.trailers.take())))
+            },
             _ => Ok(Async::Ready(None)),
         }
     }
@@ -257,11 +477,19 @@ impl Payload for Body {
         match self.kind {
             Kind::Once(ref val) => val.is_none(),
             Kind::Chan { content_length: len, .. } => len == Some(0),
-            Kind::H2(ref h2) => h2.is_end_stream(),
+            Kind::H2(ref h2, _) => h2.is_end_stream(),
             Kind::Wrapped(..) => false,
         }
     }
 
+    fn chunk_extensions(&self, data: &Chunk) -> Option<ChunkExtensions> {
+        data.extensions().cloned()
+    }
+
+    fn flush_hint(&self, data: &Chunk) -> bool {
+        data.flush_hint()
+    }
+
     fn content_length(&self) -> Option<u64> {
         match self.kind {
             Kind::Once(Some(ref val)) => Some(val.len() as u64),
@@ -280,6 +508,11 @@ impl Payload for Body {
             _ => FullDataRet(None),
         }
     }
+
+    #[doc(hidden)]
+    fn __hyper_file(&mut self, _: FileArg) -> FileRet {
+        FileRet(self.file.take().map(|pending| (pending.file, pending.len)))
+    }
 }
 
 impl Stream for Body {
@@ -298,8 +531,20 @@ impl fmt::Debug for Body {
     }
 }
 
+impl<'a> Future for Trailers<'a> {
+    type Item = Option<HeaderMap>;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.body.poll_trailers()
+    }
+}
+
 impl Sender {
     /// Check to see if this `Sender` can send more data.
+    ///
+    /// Returns `NotReady` while the buffer of the channel is full, and
+    /// notifies the task once the `Body` has taken a chunk out of it.
     pub fn poll_ready(&mut self) -> Poll<(), ::Error> {
         match self.abort_tx.poll_cancel() {
             Ok(Async::Ready(())) | Err(_) => return Err(::Error::new_closed()),
@@ -309,6 +554,19 @@ impl Sender {
         self.tx.poll_ready().map_err(|_| ::Error::new_closed())
     }
 
+    /// Check to see if the `Body` of this channel was dropped.
+    ///
+    /// That happens once the body isn't wanted anymore, such as when the
+    /// peer reset the connection or the HTTP/2 stream. The task is notified
+    /// when it happens, so that expensive data can stop being generated
+    /// early.
+    pub fn poll_closed(&mut self) -> Async<()> {
+        match self.abort_tx.poll_cancel() {
+            Ok(Async::NotReady) => Async::NotReady,
+            Ok(Async::Ready(())) | Err(()) => Async::Ready(()),
+        }
+    }
+
     /// Sends data on this channel.
     ///
     /// This should be called after `poll_ready` indicated the channel
@@ -321,6 +579,26 @@ impl Sender {
             .map_err(|err| err.into_inner().expect("just sent Ok"))
     }
 
+    /// Ends the body with trailers, to be received after the data.
+    ///
+    /// Returns `Err(HeaderMap)` if the `Body` was dropped.
+    pub fn send_trailers(self, trailers: HeaderMap) -> Result<(), HeaderMap> {
+        self.end_tx
+            .send(ChanEnd {
+                trailers: Some(trailers),
+                last_chunk_ext: None,
+            })
+            .map_err(|end| end.trailers.expect("just sent trailers"))
+    }
+
+    /// Ends the body with what followed the last chunk of a chunked body.
+    pub(crate) fn send_end(self, trailers: Option<HeaderMap>, last_chunk_ext: Option<ChunkExtensions>) {
+        let _ = self.end_tx.send(ChanEnd {
+            trailers: trailers,
+            last_chunk_ext: last_chunk_ext,
+        });
+    }
+
     /// Aborts the body in an abnormal fashion.
     pub fn abort(self) {
         let _ = self.abort_tx.send(());
@@ -422,3 +700,29 @@ fn test_body_stream_concat() {
     assert_eq!(total.as_ref(), b"hello world");
 }
 
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, Async, Future, Stream};
+    use super::Body;
+
+    #[test]
+    fn channel_with_capacity() {
+        let (mut tx, mut body) = Body::channel_with_capacity(2);
+        future::lazy(move || {
+            assert_eq!(tx.poll_ready().unwrap(), Async::Ready(()));
+            tx.send_data("a".into()).unwrap();
+            tx.send_data("b".into()).unwrap();
+            assert_eq!(tx.poll_ready().unwrap(), Async::NotReady);
+            tx.send_data("c".into()).unwrap_err();
+
+            match body.poll().unwrap() {
+                Async::Ready(Some(chunk)) => assert_eq!(&chunk[..], b"a"),
+                other => panic!("unexpected poll: {:?}", other),
+            }
+            assert_eq!(tx.poll_ready().unwrap(), Async::Ready(()));
+            tx.send_data("c".into()).unwrap();
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+    }
+}
@@ -1,3 +1,4 @@
+use std::error::Error as StdError;
 use std::fmt;
 
 use bytes::{Buf, Bytes};
@@ -11,6 +12,22 @@ use bytes::{Buf, Bytes};
 pub struct Chunk {
     /// The buffer of bytes making up this body.
     bytes: Bytes,
+    extensions: Option<ChunkExtensions>,
+    flush: bool,
+}
+
+/// The chunk extensions of a chunk sent with chunked transfer coding.
+///
+/// These are the `name=value` pairs that may follow the size of a chunk,
+/// separated by `;`, which some protocols use to send metadata, such as
+/// signatures of each chunk.
+#[derive(Clone, PartialEq)]
+pub struct ChunkExtensions(Bytes);
+
+/// An error returned when creating `ChunkExtensions` with invalid bytes.
+#[derive(Debug)]
+pub struct InvalidChunkExtensions {
+    _priv: (),
 }
 
 // An unexported type to prevent locking `Chunk::into_iter()` to `Bytes::into_iter()`.
@@ -29,6 +46,138 @@ impl Chunk {
     pub fn into_bytes(self) -> Bytes {
         self.into()
     }
+
+    /// The chunk extensions this data was received with, if any.
+    ///
+    /// When a received chunk is split into several `Chunk`s, only the first
+    /// has the extensions. The extensions of the last chunk, which has no
+    /// data, are on the body, see
+    /// [`Body::last_chunk_extensions`](::Body::last_chunk_extensions).
+    #[inline]
+    pub fn extensions(&self) -> Option<&ChunkExtensions> {
+        self.extensions.as_ref()
+    }
+
+    /// Set the chunk extensions to send with this data.
+    ///
+    /// These are only sent when the body uses HTTP/1 chunked transfer
+    /// coding. If this `Chunk` is empty, they are sent with the last chunk.
+    #[inline]
+    pub fn set_extensions(&mut self, extensions: ChunkExtensions) {
+        self.extensions = Some(extensions);
+    }
+
+    /// Whether this data should be written to the connection immediately.
+    #[inline]
+    pub fn flush_hint(&self) -> bool {
+        self.flush
+    }
+
+    /// Hint that this data should be written to the connection immediately.
+    ///
+    /// Otherwise, hyper may buffer it together with the data that follows,
+    /// to write less often. Flushing each `Chunk` is useful for streams
+    /// where latency matters more than throughput, like Server-Sent Events.
+    #[inline]
+    pub fn set_flush_hint(&mut self, flush: bool) {
+        self.flush = flush;
+    }
+}
+
+impl ChunkExtensions {
+    pub(crate) fn new(bytes: Bytes) -> ChunkExtensions {
+        ChunkExtensions(bytes)
+    }
+
+    /// Create `ChunkExtensions` from the bytes to send after the first `;`,
+    /// such as `chunk-signature=abc;other=1`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bytes contain control characters, such as
+    /// CR or LF.
+    pub fn from_bytes(bytes: Bytes) -> Result<ChunkExtensions, InvalidChunkExtensions> {
+        if bytes.iter().any(|&b| (b < b' ' && b != b'\t') || b == 0x7F) {
+            return Err(InvalidChunkExtensions { _priv: () });
+        }
+        Ok(ChunkExtensions(bytes))
+    }
+
+    /// Create `ChunkExtensions` from a static string.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the string isn't valid, see
+    /// [`from_bytes`](ChunkExtensions::from_bytes).
+    pub fn from_static(src: &'static str) -> ChunkExtensions {
+        ChunkExtensions::from_bytes(Bytes::from_static(src.as_bytes()))
+            .expect("invalid static chunk extensions")
+    }
+
+    /// The bytes of the extensions, without the first `;`.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub(crate) fn into_bytes(self) -> Bytes {
+        self.0
+    }
+
+    /// Get the value of the extension with the given name.
+    ///
+    /// Names are compared case-insensitively, and quotes around a value are
+    /// removed. An extension without a value returns an empty slice.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.0
+            .split(|&b| b == b';')
+            .filter_map(|ext| {
+                let (ext_name, value) = match ext.iter().position(|&b| b == b'=') {
+                    Some(i) => (&ext[..i], &ext[i + 1..]),
+                    None => (ext, &b""[..]),
+                };
+                if trim(ext_name).eq_ignore_ascii_case(name.as_bytes()) {
+                    Some(unquote(trim(value)))
+                } else {
+                    None
+                }
+            })
+            .next()
+    }
+}
+
+impl fmt::Debug for ChunkExtensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("ChunkExtensions")
+            .field(&String::from_utf8_lossy(&self.0))
+            .finish()
+    }
+}
+
+impl fmt::Display for InvalidChunkExtensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+impl StdError for InvalidChunkExtensions {
+    fn description(&self) -> &str {
+        "invalid chunk extensions"
+    }
+}
+
+fn trim(bytes: &[u8]) -> &[u8] {
+    let is_ws = |b: &u8| *b == b' ' || *b == b'\t';
+    let start = bytes.iter().position(|b| !is_ws(b)).unwrap_or(bytes.len());
+    let end = bytes.iter().rposition(|b| !is_ws(b)).map(|i| i + 1).unwrap_or(start);
+    &bytes[start..end]
+}
+
+fn unquote(bytes: &[u8]) -> &[u8] {
+    if bytes.len() >= 2 && bytes[0] == b'"' && bytes[bytes.len() - 1] == b'"' {
+        &bytes[1..bytes.len() - 1]
+    } else {
+        bytes
+    }
 }
 
 impl Buf for Chunk {
@@ -84,6 +233,8 @@ impl From<Bytes> for Chunk {
     fn from(bytes: Bytes) -> Chunk {
         Chunk {
             bytes: bytes,
+            extensions: None,
+            flush: false,
         }
     }
 }
@@ -165,6 +316,25 @@ mod tests {
     #[cfg(feature = "nightly")]
     use test::Bencher;
 
+    use bytes::Bytes;
+    use super::ChunkExtensions;
+
+    #[test]
+    fn chunk_extensions_get() {
+        let ext = ChunkExtensions::new(Bytes::from_static(b"chunk-signature=abc; Name = \"quoted value\";flag"));
+        assert_eq!(ext.get("chunk-signature"), Some(&b"abc"[..]));
+        assert_eq!(ext.get("name"), Some(&b"quoted value"[..]));
+        assert_eq!(ext.get("flag"), Some(&b""[..]));
+        assert_eq!(ext.get("missing"), None);
+    }
+
+    #[test]
+    fn chunk_extensions_from_bytes() {
+        let ext = ChunkExtensions::from_static("chunk-signature=abc");
+        assert_eq!(ext.as_bytes(), b"chunk-signature=abc");
+        ChunkExtensions::from_bytes(Bytes::from_static(b"a=1\r\n0\r\n")).unwrap_err();
+    }
+
     #[cfg(feature = "nightly")]
     #[bench]
     fn bench_chunk_static_buf(b: &mut Bencher) {
@@ -0,0 +1,149 @@
+use bytes::Buf;
+use futures::{Async, Poll, Stream};
+use http::HeaderMap;
+
+use super::{ChunkExtensions, Payload, SizeHint};
+
+/// A `Payload` adapter that errors once its body is larger than a limit.
+///
+/// This can wrap request bodies in a server, or response bodies in a
+/// client, to avoid buffering more than expected. The error it yields
+/// returns `true` from [`is_body_too_large`](::Error::is_body_too_large).
+///
+/// If an HTTP/1 server's `Service` fails with such an error (as its error,
+/// or the cause of it) before writing a response, hyper replies with a
+/// `413 Payload Too Large` and closes the connection.
+#[must_use = "streams do nothing unless polled"]
+#[derive(Debug)]
+pub struct Limited<B> {
+    inner: B,
+    remaining: u64,
+}
+
+impl<B> Limited<B> {
+    /// Wrap a `Payload`, allowing at most `limit` bytes of data.
+    pub fn new(inner: B, limit: u64) -> Limited<B> {
+        Limited {
+            inner,
+            remaining: limit,
+        }
+    }
+
+    /// Get a reference to the inner `Payload`.
+    pub fn get_ref(&self) -> &B {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the inner `Payload`.
+    pub fn get_mut(&mut self) -> &mut B {
+        &mut self.inner
+    }
+
+    /// Unwrap the inner `Payload`.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl<B: Payload> Payload for Limited<B> {
+    type Data = B::Data;
+    type Error = ::Error;
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        // Don't wait for the data, if it's already known to be too much.
+        if self.inner.size_hint().lower() > self.remaining {
+            return Err(::Error::new_body_too_large());
+        }
+
+        match try_ready!(self.inner.poll_data().map_err(::Error::new_body)) {
+            Some(data) => {
+                let len = data.remaining() as u64;
+                if len > self.remaining {
+                    self.remaining = 0;
+                    return Err(::Error::new_body_too_large());
+                }
+                self.remaining -= len;
+                Ok(Async::Ready(Some(data)))
+            },
+            None => Ok(Async::Ready(None)),
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
+        self.inner.poll_trailers().map_err(::Error::new_body)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        self.inner.content_length()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let inner = self.inner.size_hint();
+        let mut hint = SizeHint::new();
+        hint.set_upper(inner.upper().map_or(self.remaining, |upper| upper.min(self.remaining)));
+        hint.set_lower(inner.lower().min(self.remaining));
+        hint
+    }
+
+    fn chunk_extensions(&self, data: &Self::Data) -> Option<ChunkExtensions> {
+        self.inner.chunk_extensions(data)
+    }
+
+    fn flush_hint(&self, data: &Self::Data) -> bool {
+        self.inner.flush_hint(data)
+    }
+}
+
+impl<B: Payload> Stream for Limited<B> {
+    type Item = B::Data;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.poll_data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{Future, Stream};
+    use body::{Body, Chunk};
+    use super::Limited;
+
+    #[test]
+    fn limited_under_limit() {
+        let body = Limited::new(Body::from("hello"), 5);
+        let chunk = body.concat2().wait().unwrap();
+        assert_eq!(&chunk[..], b"hello");
+    }
+
+    #[test]
+    fn limited_size_hint() {
+        use body::Payload;
+
+        let body = Limited::new(Body::from("hello"), 10);
+        assert_eq!(body.size_hint().exact(), Some(5));
+
+        let (_tx, body) = Body::channel();
+        let body = Limited::new(body, 10);
+        assert_eq!(body.size_hint().lower(), 0);
+        assert_eq!(body.size_hint().upper(), Some(10));
+    }
+
+    #[test]
+    fn limited_over_limit() {
+        let known = Limited::new(Body::from("hello"), 4);
+        assert!(known.concat2().wait().unwrap_err().is_body_too_large());
+
+        let chunks = ::futures::stream::iter_ok::<_, ::Error>(vec![
+            Chunk::from("hel"),
+            Chunk::from("lo"),
+        ]);
+        let mut unknown = Limited::new(Body::wrap_stream(chunks), 4);
+        assert_eq!(&unknown.by_ref().wait().next().unwrap().unwrap()[..], b"hel");
+        assert!(unknown.wait().next().unwrap().unwrap_err().is_body_too_large());
+    }
+}
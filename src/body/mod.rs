@@ -14,19 +14,27 @@
 //!  and returned by hyper as a "receive stream" (so, for server requests and
 //!  client responses). It is also a decent default implementation if you don't
 //!  have very custom needs of your send streams.
-pub use self::body::{Body, Sender};
-pub use self::chunk::Chunk;
+pub use self::body::{Body, Sender, Trailers};
+pub use self::chunk::{Chunk, ChunkExtensions, InvalidChunkExtensions};
+pub use self::limited::Limited;
 pub use self::payload::Payload;
+pub use self::reader::BlockingRead;
+pub use self::size_hint::SizeHint;
+#[cfg(feature = "runtime")] pub use self::timeout::DataTimeout;
 
 mod body;
 mod chunk;
+mod limited;
 mod payload;
+mod reader;
+mod size_hint;
+#[cfg(feature = "runtime")] mod timeout;
 
-// The full_data API is not stable, so these types are to try to prevent
-// users from being able to:
+// The full_data and file APIs are not stable, so these types are to try to
+// prevent users from being able to:
 //
-// - Implment `__hyper_full_data` on their own Payloads.
-// - Call `__hyper_full_data` on any Payload.
+// - Implment `__hyper_full_data` or `__hyper_file` on their own Payloads.
+// - Call `__hyper_full_data` or `__hyper_file` on any Payload.
 //
 // That's because to implement it, they need to name these types, and
 // they can't because they aren't exported. And to call it, they would
@@ -36,6 +44,11 @@ pub(crate) mod internal {
     pub struct FullDataArg(pub(crate) ());
     #[allow(missing_debug_implementations)]
     pub struct FullDataRet<B>(pub(crate) Option<B>);
+    #[allow(missing_debug_implementations)]
+    pub struct FileArg(pub(crate) ());
+    /// The file to send, and how many bytes of it from its position.
+    #[allow(missing_debug_implementations)]
+    pub struct FileRet(pub(crate) Option<(::std::fs::File, u64)>);
 }
 
 fn _assert_send_sync() {
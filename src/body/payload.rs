@@ -2,7 +2,8 @@ use bytes::Buf;
 use futures::{Async, Poll};
 use http::HeaderMap;
 
-use super::internal::{FullDataArg, FullDataRet};
+use super::{ChunkExtensions, SizeHint};
+use super::internal::{FileArg, FileRet, FullDataArg, FullDataRet};
 
 /// This trait represents a streaming body of a `Request` or `Response`.
 ///
@@ -10,6 +11,12 @@ use super::internal::{FullDataArg, FullDataRet};
 /// don't need to customize a send stream for your own application.
 pub trait Payload: Send + 'static {
     /// A buffer of bytes representing a single chunk of a body.
+    ///
+    /// The buffer doesn't need to be contiguous. A chunk made of several
+    /// segments, such as a [`Chain`](::bytes::buf::Chain) of a framing
+    /// header and its payload, is written with a single vectored write
+    /// when the transport supports it, using `Buf::bytes_vec`, instead of
+    /// being copied into one buffer first.
     type Data: Buf + Send;
 
     /// The error type of this stream.
@@ -25,7 +32,9 @@ pub trait Payload: Send + 'static {
     ///
     /// This should **only** be called after `poll_data` has ended.
     ///
-    /// Note: Trailers aren't currently used for HTTP/1, only for HTTP/2.
+    /// With HTTP/1, trailers are only sent when the body uses chunked
+    /// transfer coding, and for responses, only if the request had a
+    /// `TE: trailers` header.
     fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
         Ok(Async::Ready(None))
     }
@@ -45,6 +54,25 @@ pub trait Payload: Send + 'static {
         false
     }
 
+    /// Return the chunk extensions to send with a `Data` buffer.
+    ///
+    /// This is called with each buffer yielded by `poll_data` when the body
+    /// is sent with HTTP/1 chunked transfer coding. The extensions of an
+    /// empty buffer are sent with the last chunk.
+    ///
+    /// The default implementation returns `None`.
+    fn chunk_extensions(&self, _data: &Self::Data) -> Option<ChunkExtensions> {
+        None
+    }
+
+    /// Return whether a `Data` buffer should be written to the connection
+    /// immediately, instead of buffered with the data that follows.
+    ///
+    /// The default implementation returns `false`.
+    fn flush_hint(&self, _data: &Self::Data) -> bool {
+        false
+    }
+
     /// Return a length of the total bytes that will be streamed, if known.
     ///
     /// If an exact size of bytes is known, this would allow hyper to send a
@@ -57,6 +85,21 @@ pub trait Payload: Send + 'static {
         None
     }
 
+    /// Return the bounds of the total bytes that will be streamed.
+    ///
+    /// hyper uses the exact size, if it is known, in place of
+    /// `content_length`. Like it, this is only called once to create the
+    /// headers.
+    ///
+    /// The default implementation returns the `content_length` as an exact
+    /// size, if it is known.
+    fn size_hint(&self) -> SizeHint {
+        match self.content_length() {
+            Some(len) => SizeHint::with_exact(len),
+            None => SizeHint::new(),
+        }
+    }
+
     // This API is unstable, and is impossible to use outside of hyper. Some
     // form of it may become stable in a later version.
     //
@@ -66,6 +109,13 @@ pub trait Payload: Send + 'static {
     fn __hyper_full_data(&mut self, FullDataArg) -> FullDataRet<Self::Data> {
         FullDataRet(None)
     }
+
+    // Same as `__hyper_full_data`. The file is taken before the body is
+    // polled, and is then sent instead of it.
+    #[doc(hidden)]
+    fn __hyper_file(&mut self, FileArg) -> FileRet {
+        FileRet(None)
+    }
 }
 
 impl<E: Payload> Payload for Box<E> {
@@ -84,14 +134,31 @@ impl<E: Payload> Payload for Box<E> {
         (**self).is_end_stream()
     }
 
+    fn chunk_extensions(&self, data: &Self::Data) -> Option<ChunkExtensions> {
+        (**self).chunk_extensions(data)
+    }
+
+    fn flush_hint(&self, data: &Self::Data) -> bool {
+        (**self).flush_hint(data)
+    }
+
     fn content_length(&self) -> Option<u64> {
         (**self).content_length()
     }
 
+    fn size_hint(&self) -> SizeHint {
+        (**self).size_hint()
+    }
+
     #[doc(hidden)]
     fn __hyper_full_data(&mut self, arg: FullDataArg) -> FullDataRet<Self::Data> {
         (**self).__hyper_full_data(arg)
     }
+
+    #[doc(hidden)]
+    fn __hyper_file(&mut self, arg: FileArg) -> FileRet {
+        (**self).__hyper_file(arg)
+    }
 }
 
 
@@ -0,0 +1,173 @@
+use std::fmt;
+use std::io::{self, Read};
+
+use futures::{Async, Future, Poll};
+use futures::sync::oneshot;
+
+use super::{Chunk, Sender};
+
+/// The size of the buffers read from a blocking `Read`.
+const READ_BUF_SIZE: usize = 8192;
+
+/// A blocking task that reads into a `Body`, created with
+/// [`Body::from_reader`](::Body::from_reader).
+///
+/// It should be executed on a thread pool meant for blocking work, such as a
+/// `futures_cpupool::CpuPool`.
+#[must_use = "futures do nothing unless polled"]
+pub struct BlockingRead {
+    reader: Option<Box<Read + Send>>,
+    /// Where the reader comes from, if it's only given once the `Body` is
+    /// first polled.
+    reader_rx: Option<oneshot::Receiver<Box<Read + Send>>>,
+    tx: Sender,
+    pending: Option<Result<Chunk, ::Error>>,
+}
+
+impl BlockingRead {
+    pub(super) fn new(reader: Box<Read + Send>, tx: Sender) -> BlockingRead {
+        BlockingRead {
+            reader: Some(reader),
+            reader_rx: None,
+            tx,
+            pending: None,
+        }
+    }
+
+    /// Reads the reader sent on `reader_rx`, or nothing if it's canceled.
+    pub(super) fn deferred(reader_rx: oneshot::Receiver<Box<Read + Send>>, tx: Sender) -> BlockingRead {
+        BlockingRead {
+            reader: None,
+            reader_rx: Some(reader_rx),
+            tx,
+            pending: None,
+        }
+    }
+
+    // Used if the task couldn't be executed.
+    pub(super) fn fail(mut self, err: ::Error) {
+        self.tx.send_error(err);
+    }
+
+    fn read_chunk(&mut self) -> Option<Result<Chunk, ::Error>> {
+        let reader = self.reader.as_mut().expect("read_chunk without a reader");
+        let mut buf = vec![0; READ_BUF_SIZE];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => return None,
+                Ok(n) => {
+                    buf.truncate(n);
+                    return Some(Ok(Chunk::from(buf)));
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Some(Err(::Error::new_body(e))),
+            }
+        }
+    }
+}
+
+impl Future for BlockingRead {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        if let Some(mut rx) = self.reader_rx.take() {
+            match rx.poll() {
+                Ok(Async::Ready(reader)) => self.reader = Some(reader),
+                Ok(Async::NotReady) => {
+                    self.reader_rx = Some(rx);
+                    return Ok(Async::NotReady);
+                },
+                Err(_canceled) => {
+                    trace!("body dropped or sent on its own, stop reading");
+                    return Ok(Async::Ready(()));
+                }
+            }
+        }
+        loop {
+            if self.pending.is_none() {
+                match self.read_chunk() {
+                    Some(result) => self.pending = Some(result),
+                    // dropping the `Sender` ends the body
+                    None => return Ok(Async::Ready(())),
+                }
+            }
+
+            match self.tx.poll_ready() {
+                Ok(Async::Ready(())) => (),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(_closed) => {
+                    trace!("body dropped, stop reading");
+                    return Ok(Async::Ready(()));
+                }
+            }
+
+            match self.pending.take().expect("pending chunk") {
+                Ok(chunk) => {
+                    let _ = self.tx.send_data(chunk);
+                },
+                Err(err) => {
+                    self.tx.send_error(err);
+                    return Ok(Async::Ready(()));
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for BlockingRead {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BlockingRead")
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "runtime"))]
+mod tests {
+    use std::io::{self, Read};
+
+    use futures::{Future, Stream};
+    use futures_cpupool::CpuPool;
+    use body::Body;
+
+    #[test]
+    fn from_reader() {
+        let pool = CpuPool::new(1);
+        let data = vec![b'a'; 20_000];
+        let body = Body::from_reader(io::Cursor::new(data.clone()), &pool);
+        let chunk = body.concat2().wait().unwrap();
+        assert_eq!(&chunk[..], &data[..]);
+    }
+
+    #[test]
+    fn from_reader_error() {
+        struct Broken;
+
+        impl Read for Broken {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::Other, "broken"))
+            }
+        }
+
+        let pool = CpuPool::new(1);
+        let body = Body::from_reader(Broken, &pool);
+        body.concat2().wait().unwrap_err();
+    }
+
+    #[test]
+    fn from_file() {
+        use std::fs::{self, File};
+        use std::io::Write;
+        use body::Payload;
+
+        let path = ::std::env::temp_dir().join("hyper-body-from-file");
+        File::create(&path).unwrap().write_all(b"hello world").unwrap();
+
+        let pool = CpuPool::new(1);
+        let body = Body::from_file(File::open(&path).unwrap(), &pool).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(body.size_hint().exact(), Some(11));
+        let chunk = body.concat2().wait().unwrap();
+        assert_eq!(&chunk[..], b"hello world");
+    }
+}
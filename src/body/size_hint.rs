@@ -0,0 +1,108 @@
+/// A hint of the number of bytes a body will yield.
+///
+/// It has a lower bound, and an upper bound if one is known. When both
+/// bounds are the same, the size is exact, and hyper can send a
+/// `Content-Length` header instead of using chunked transfer coding.
+///
+/// Receivers of a body can also use it to pre-allocate buffers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SizeHint {
+    lower: u64,
+    upper: Option<u64>,
+}
+
+impl SizeHint {
+    /// Create a `SizeHint` knowing nothing about the size.
+    #[inline]
+    pub fn new() -> SizeHint {
+        SizeHint::default()
+    }
+
+    /// Create a `SizeHint` of an exact size.
+    #[inline]
+    pub fn with_exact(value: u64) -> SizeHint {
+        SizeHint {
+            lower: value,
+            upper: Some(value),
+        }
+    }
+
+    /// Returns the lower bound of the size.
+    #[inline]
+    pub fn lower(&self) -> u64 {
+        self.lower
+    }
+
+    /// Set the lower bound of the size.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` is greater than the upper bound.
+    #[inline]
+    pub fn set_lower(&mut self, value: u64) {
+        assert!(self.upper.map_or(true, |upper| value <= upper), "size hint lower bound greater than upper bound");
+        self.lower = value;
+    }
+
+    /// Returns the upper bound of the size, if known.
+    #[inline]
+    pub fn upper(&self) -> Option<u64> {
+        self.upper
+    }
+
+    /// Set the upper bound of the size.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` is less than the lower bound.
+    #[inline]
+    pub fn set_upper(&mut self, value: u64) {
+        assert!(value >= self.lower, "size hint upper bound less than lower bound");
+        self.upper = Some(value);
+    }
+
+    /// Returns the exact size, if the bounds are the same.
+    #[inline]
+    pub fn exact(&self) -> Option<u64> {
+        if Some(self.lower) == self.upper {
+            self.upper
+        } else {
+            None
+        }
+    }
+
+    /// Set both bounds to an exact size.
+    #[inline]
+    pub fn set_exact(&mut self, value: u64) {
+        self.lower = value;
+        self.upper = Some(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SizeHint;
+
+    #[test]
+    fn size_hint_bounds() {
+        let mut hint = SizeHint::new();
+        assert_eq!(hint.lower(), 0);
+        assert_eq!(hint.upper(), None);
+        assert_eq!(hint.exact(), None);
+
+        hint.set_upper(10);
+        hint.set_lower(3);
+        assert_eq!(hint.exact(), None);
+
+        hint.set_lower(10);
+        assert_eq!(hint.exact(), Some(10));
+        assert_eq!(SizeHint::with_exact(10), hint);
+    }
+
+    #[test]
+    #[should_panic]
+    fn size_hint_lower_over_upper() {
+        let mut hint = SizeHint::with_exact(5);
+        hint.set_lower(6);
+    }
+}
@@ -0,0 +1,175 @@
+use std::time::Duration;
+
+use futures::{Async, Future, Poll, Stream};
+use http::HeaderMap;
+
+use clock::{Clock, Delay, Time};
+use super::{ChunkExtensions, Payload, SizeHint};
+
+/// A `Payload` adapter that errors when its body stalls.
+///
+/// The timer only runs while the body is polled and waiting for data, and
+/// restarts every time a data frame arrives. So a long-lived stream that
+/// sends data now and then, like a long-poll or events stream, doesn't time
+/// out, while a peer that stopped sending in the middle of a body does.
+///
+/// The error it yields returns `true` from
+/// [`is_body_timeout`](::Error::is_body_timeout).
+#[must_use = "streams do nothing unless polled"]
+#[derive(Debug)]
+pub struct DataTimeout<B> {
+    inner: B,
+    timeout: Duration,
+    time: Time,
+    delay: Option<Delay>,
+    // Whether data arrived since the delay was last (re)started.
+    progressed: bool,
+}
+
+impl<B> DataTimeout<B> {
+    /// Wrap a `Payload`, erroring if no data arrives for `timeout`.
+    pub fn new(inner: B, timeout: Duration) -> DataTimeout<B> {
+        DataTimeout::with_time(inner, timeout, Time::default())
+    }
+
+    /// Wrap a `Payload`, erroring if no data arrives for `timeout`, as told
+    /// by `clock`.
+    pub fn with_clock<C: Clock>(inner: B, timeout: Duration, clock: C) -> DataTimeout<B> {
+        DataTimeout::with_time(inner, timeout, Time::new(clock))
+    }
+
+    fn with_time(inner: B, timeout: Duration, time: Time) -> DataTimeout<B> {
+        DataTimeout {
+            inner,
+            timeout,
+            time,
+            delay: None,
+            progressed: false,
+        }
+    }
+
+    /// Get a reference to the inner `Payload`.
+    pub fn get_ref(&self) -> &B {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the inner `Payload`.
+    pub fn get_mut(&mut self) -> &mut B {
+        &mut self.inner
+    }
+
+    /// Unwrap the inner `Payload`.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+
+    // Called when the inner body isn't ready, errors if it's been too long
+    // since the last data arrived.
+    fn check_timeout(&mut self) -> ::Result<()> {
+        if self.progressed || self.delay.is_none() {
+            let deadline = self.time.now() + self.timeout;
+            self.delay = Some(self.time.delay(deadline));
+        }
+        self.progressed = false;
+        let delay = self.delay.as_mut().expect("delay was just set");
+
+        match delay.poll() {
+            Ok(Async::Ready(())) => {
+                debug!("body data timed out");
+                Err(::Error::new_body_timeout())
+            },
+            Ok(Async::NotReady) => Ok(()),
+            Err(timer_err) => Err(::Error::new_body(timer_err)),
+        }
+    }
+}
+
+impl<B: Payload> Payload for DataTimeout<B> {
+    type Data = B::Data;
+    type Error = ::Error;
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        match self.inner.poll_data().map_err(::Error::new_body)? {
+            Async::Ready(data) => {
+                self.progressed = true;
+                Ok(Async::Ready(data))
+            },
+            Async::NotReady => {
+                self.check_timeout()?;
+                Ok(Async::NotReady)
+            }
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
+        match self.inner.poll_trailers().map_err(::Error::new_body)? {
+            Async::Ready(trailers) => Ok(Async::Ready(trailers)),
+            Async::NotReady => {
+                self.check_timeout()?;
+                Ok(Async::NotReady)
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        self.inner.content_length()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+
+    fn chunk_extensions(&self, data: &Self::Data) -> Option<ChunkExtensions> {
+        self.inner.chunk_extensions(data)
+    }
+
+    fn flush_hint(&self, data: &Self::Data) -> bool {
+        self.inner.flush_hint(data)
+    }
+}
+
+impl<B: Payload> Stream for DataTimeout<B> {
+    type Item = B::Data;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.poll_data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::{future, Future, Stream};
+
+    use body::Body;
+    use clock::ManualClock;
+    use super::DataTimeout;
+
+    #[test]
+    fn data_timeout_by_clock() {
+        future::lazy(|| {
+            let clock = ManualClock::new();
+            let (mut tx, body) = Body::channel();
+            let mut body = DataTimeout::with_clock(body, Duration::from_secs(10), clock.clone());
+            assert!(body.poll().unwrap().is_not_ready());
+
+            clock.advance(Duration::from_secs(9));
+            tx.send_data("hello".into()).unwrap();
+            assert!(body.poll().unwrap().is_ready());
+
+            // restarted by the data
+            assert!(body.poll().unwrap().is_not_ready());
+            clock.advance(Duration::from_secs(9));
+            assert!(body.poll().unwrap().is_not_ready());
+            clock.advance(Duration::from_secs(1));
+            assert!(body.poll().unwrap_err().is_body_timeout());
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
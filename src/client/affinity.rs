@@ -0,0 +1,99 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use super::pool::Pinned;
+
+/// Pins a sequence of requests to one pooled connection.
+///
+/// Some exchanges only work on a single connection, such as NTLM and
+/// Negotiate authentication handshakes, or backends that bind a session
+/// to it. Insert clones of one `Affinity` in the extensions of each of
+/// their requests: the first is sent on a connection as usual, and the
+/// following ones on that same connection, waiting for it while it's busy
+/// with another of them. Other requests don't use the connection, until
+/// every clone of the `Affinity` is dropped.
+///
+/// The requests that follow should only be sent once the first one got
+/// its connection, such as after its response. If the connection is gone,
+/// closed or expired from the pool, they fail instead of being sent on
+/// another, with an error for which
+/// [`Error::is_affinity_lost`](::Error::is_affinity_lost) is true, as they
+/// do with a different host than the first.
+///
+/// Connections are only pinned by a `Client` that keeps them alive, and an
+/// `Affinity` should only be used with one `Client`.
+///
+/// # Example
+///
+/// ```
+/// # extern crate hyper;
+/// # fn main() {}
+/// # #[cfg(feature = "runtime")]
+/// # fn run() {
+/// use hyper::{Body, Client, Request};
+/// use hyper::client::Affinity;
+///
+/// let client = Client::new();
+/// let affinity = Affinity::new();
+///
+/// let mut req = Request::new(Body::empty());
+/// *req.uri_mut() = "http://example.local/login".parse().unwrap();
+/// req.extensions_mut().insert(affinity.clone());
+/// let fut = client.request(req);
+/// # drop(fut);
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct Affinity {
+    inner: Arc<Mutex<Option<Bound>>>,
+}
+
+struct Bound {
+    pool_key: Arc<String>,
+    id: usize,
+    _pinned: Pinned,
+}
+
+impl Affinity {
+    /// Creates an `Affinity` not pinned to a connection yet.
+    pub fn new() -> Affinity {
+        Affinity::default()
+    }
+
+    /// The id of the connection the requests are pinned to, as in
+    /// [`ConnectionInfo::id`](::ext::ConnectionInfo::id), once the first
+    /// one got it.
+    pub fn connection_id(&self) -> Option<usize> {
+        self.inner.lock().unwrap().as_ref().map(|bound| bound.id)
+    }
+
+    /// The pool key and id of the connection pinned to.
+    pub(super) fn bound(&self) -> Option<(Arc<String>, usize)> {
+        self.inner.lock().unwrap()
+            .as_ref()
+            .map(|bound| (bound.pool_key.clone(), bound.id))
+    }
+
+    /// Pins the requests to the connection, unless they already are.
+    pub(super) fn bind<F>(&self, pool_key: &Arc<String>, id: usize, pin: F)
+    where
+        F: FnOnce() -> Pinned,
+    {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.is_none() {
+            *inner = Some(Bound {
+                pool_key: pool_key.clone(),
+                id,
+                _pinned: pin(),
+            });
+        }
+    }
+}
+
+impl fmt::Debug for Affinity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Affinity")
+            .field("connection_id", &self.connection_id())
+            .finish()
+    }
+}
@@ -0,0 +1,134 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use futures::task::{self, Task};
+
+/// A handle to cancel a request that was sent.
+///
+/// Created with `ResponseFuture::cancel_handle`. Canceling a request that
+/// is still in flight stops it right away: an HTTP/2 stream is reset with
+/// `CANCEL`, and an HTTP/1 connection is closed, since it has no other way
+/// to stop a message. Its response future then fails with an error for
+/// which [`Error::is_request_canceled`](::Error::is_request_canceled) is
+/// true. If the response was already received, its body fails instead.
+///
+/// Canceling a request whose response was fully received does nothing.
+#[derive(Clone)]
+pub struct CancelHandle {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    canceled: AtomicBool,
+    tasks: Mutex<Vec<Task>>,
+}
+
+/// The cancel state of a request, given to the connection in its
+/// extensions.
+pub(crate) struct CancelWatch(pub(crate) CancelHandle);
+
+impl CancelHandle {
+    pub(crate) fn new() -> CancelHandle {
+        CancelHandle {
+            inner: Arc::new(Inner {
+                canceled: AtomicBool::new(false),
+                tasks: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Cancels the request.
+    pub fn cancel(&self) {
+        self.inner.canceled.store(true, Ordering::SeqCst);
+        let tasks = match self.inner.tasks.lock() {
+            Ok(mut tasks) => ::std::mem::replace(&mut *tasks, Vec::new()),
+            Err(_) => return,
+        };
+        for task in tasks {
+            task.notify();
+        }
+    }
+
+    /// Returns true if `cancel` was called.
+    pub fn is_canceled(&self) -> bool {
+        self.inner.canceled.load(Ordering::SeqCst)
+    }
+
+    /// Returns true if canceled, and otherwise registers the current task
+    /// to be notified when it is.
+    pub(crate) fn poll_canceled(&self) -> bool {
+        if self.is_canceled() {
+            return true;
+        }
+        if let Ok(mut tasks) = self.inner.tasks.lock() {
+            if !tasks.iter().any(|t| t.will_notify_current()) {
+                tasks.push(task::current());
+            }
+        }
+        // checked again, in case `cancel` ran before the task was added
+        self.is_canceled()
+    }
+}
+
+impl fmt::Debug for CancelHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CancelHandle")
+            .field("canceled", &self.is_canceled())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::{future, Async};
+    use futures::executor::{self, Notify, NotifyHandle};
+
+    use super::CancelHandle;
+
+    struct Count(AtomicUsize);
+
+    impl Notify for Count {
+        fn notify(&self, _id: usize) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn cancel_notifies_every_task() {
+        let count = Arc::new(Count(AtomicUsize::new(0)));
+        let notify = NotifyHandle::from(count.clone());
+        let handle = CancelHandle::new();
+
+        let watch = handle.clone();
+        let mut task1 = executor::spawn(future::poll_fn(move || {
+            if watch.poll_canceled() {
+                Ok::<_, ()>(Async::Ready(()))
+            } else {
+                Ok(Async::NotReady)
+            }
+        }));
+        let watch = handle.clone();
+        let mut task2 = executor::spawn(future::poll_fn(move || {
+            if watch.poll_canceled() {
+                Ok::<_, ()>(Async::Ready(()))
+            } else {
+                Ok(Async::NotReady)
+            }
+        }));
+
+        assert!(task1.poll_future_notify(&notify, 1).unwrap().is_not_ready());
+        // polling the same task again doesn't register it twice
+        assert!(task1.poll_future_notify(&notify, 1).unwrap().is_not_ready());
+        assert!(task2.poll_future_notify(&notify, 2).unwrap().is_not_ready());
+
+        handle.cancel();
+        assert!(handle.is_canceled());
+        assert_eq!(count.0.load(Ordering::SeqCst), 2);
+        assert!(task1.poll_future_notify(&notify, 1).unwrap().is_ready());
+        assert!(task2.poll_future_notify(&notify, 2).unwrap().is_ready());
+    }
+}
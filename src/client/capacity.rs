@@ -0,0 +1,112 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::{Async, Poll};
+use futures::task::{self, Task};
+
+/// How many requests a `Client` has in flight, shared by its clones.
+///
+/// A request is in flight from the call to `Client::request` until its
+/// response head was received, or it failed.
+pub(crate) struct Capacity {
+    max: Option<usize>,
+    in_flight: AtomicUsize,
+    waiters: Mutex<Vec<Task>>,
+}
+
+/// Counts one request as in flight, until dropped.
+pub(crate) struct InFlight {
+    capacity: Arc<Capacity>,
+}
+
+impl Capacity {
+    pub(crate) fn new(max: Option<usize>) -> Capacity {
+        Capacity {
+            max: max,
+            in_flight: AtomicUsize::new(0),
+            waiters: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    fn is_full(&self) -> bool {
+        match self.max {
+            Some(max) => self.in_flight() >= max,
+            None => false,
+        }
+    }
+
+    /// Returns `Ready` if another request fits, and otherwise registers the
+    /// current task to be notified once one finishes.
+    pub(crate) fn poll_ready(&self) -> Poll<(), ::Error> {
+        if !self.is_full() {
+            return Ok(Async::Ready(()));
+        }
+        if let Ok(mut waiters) = self.waiters.lock() {
+            if !waiters.iter().any(|t| t.will_notify_current()) {
+                waiters.push(task::current());
+            }
+        }
+        // checked again, in case a request finished before the task was added
+        if self.is_full() {
+            Ok(Async::NotReady)
+        } else {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    pub(crate) fn acquire(capacity: &Arc<Capacity>) -> InFlight {
+        capacity.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight {
+            capacity: capacity.clone(),
+        }
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.capacity.in_flight.fetch_sub(1, Ordering::SeqCst);
+        let waiters = match self.capacity.waiters.lock() {
+            Ok(mut waiters) => ::std::mem::replace(&mut *waiters, Vec::new()),
+            Err(_) => return,
+        };
+        for task in waiters {
+            task.notify();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::future;
+    use futures::Future;
+
+    use super::Capacity;
+
+    #[test]
+    fn poll_ready_waits_for_capacity() {
+        future::lazy(|| {
+            let capacity = Arc::new(Capacity::new(Some(2)));
+            let first = Capacity::acquire(&capacity);
+            assert!(capacity.poll_ready().unwrap().is_ready());
+            let second = Capacity::acquire(&capacity);
+            assert!(capacity.poll_ready().unwrap().is_not_ready());
+            assert_eq!(capacity.in_flight(), 2);
+
+            drop(first);
+            assert!(capacity.poll_ready().unwrap().is_ready());
+            drop(second);
+            assert_eq!(capacity.in_flight(), 0);
+
+            let unlimited = Arc::new(Capacity::new(None));
+            let _held = (Capacity::acquire(&unlimited), Capacity::acquire(&unlimited));
+            assert!(unlimited.poll_ready().unwrap().is_ready());
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
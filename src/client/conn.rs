@@ -9,6 +9,7 @@
 //! higher-level [Client](super) API.
 use std::fmt;
 use std::marker::PhantomData;
+use std::sync::Arc;
 
 use bytes::Bytes;
 use futures::{Async, Future, Poll};
@@ -17,7 +18,11 @@ use tokio_io::{AsyncRead, AsyncWrite};
 
 use body::Payload;
 use common::Exec;
+use exec::Spawner;
+use instrument::{Counted, FrameObserver, Instrument, Instrumentation, Tap};
 use proto;
+use service::Service;
+use super::cancel::{CancelHandle, CancelWatch};
 use super::dispatch;
 use {Body, Request, Response, StatusCode};
 
@@ -55,7 +60,7 @@ where
             T,
             proto::ClientUpgradeTransaction,
         >,
-        proto::h2::Client<T, B>,
+        proto::h2::Client<Counted<T>, B>,
     >,
 }
 
@@ -67,7 +72,23 @@ where
 pub struct Builder {
     exec: Exec,
     h1_writev: bool,
+    h1_force_writev: bool,
     h1_title_case_headers: bool,
+    h1_preserve_header_case: bool,
+    h1_record_header_case: bool,
+    h1_record_header_order: bool,
+    h1_obs_fold: bool,
+    h1_spaces_after_header_name: bool,
+    h1_missing_reason: bool,
+    h1_lf_line_endings: bool,
+    h1_framing: ::proxy::FramingPolicy,
+    h1_max_uri_len: Option<usize>,
+    h1_strict_content_length: bool,
+    h1_buf_sizes: proto::h1::BufSizes,
+    h2_body_chunks: proto::h2::BodyChunks,
+    h2_spawn_streams: bool,
+    cancel_dropped_requests: bool,
+    instrument: Option<Instrument>,
     http2: bool,
 }
 
@@ -87,6 +108,7 @@ pub struct ResponseFuture {
     // for now, a Box is used to hide away the internal `B`
     // that can be returned if canceled
     inner: Box<Future<Item=Response<Body>, Error=::Error> + Send>,
+    cancel: CancelHandle,
 }
 
 /// Deconstructed parts of a `Connection`.
@@ -139,6 +161,11 @@ impl<B> SendRequest<B>
 {
     /// Polls to determine whether this sender can be used yet for a request.
     ///
+    /// An HTTP/1 connection is ready once it's done with the previous
+    /// request. An HTTP/2 connection is ready while it can open another
+    /// stream, so it isn't at the peer's limit of concurrent streams.
+    /// `send_request` refuses a request while the connection isn't ready.
+    ///
     /// If the associated connection is closed, this returns an Error.
     pub fn poll_ready(&mut self) -> Poll<(), ::Error> {
         self.dispatch.poll_ready()
@@ -171,7 +198,10 @@ where
 {
     /// Sends a `Request` on the associated connection.
     ///
-    /// Returns a future that if successful, yields the `Response`.
+    /// Returns a future that if successful, yields the `Response`. If the
+    /// request couldn't be sent at all, such as when the connection closed
+    /// first, the error keeps it, and it can be taken back with
+    /// [`Error::take_unsent_request`](::Error::take_unsent_request).
     ///
     /// # Note
     ///
@@ -185,6 +215,8 @@ where
     ///   before calling this method.
     /// - Since absolute-form `Uri`s are not required, if received, they will
     ///   be serialized as-is.
+    /// - This also means an `OPTIONS` request for the whole server can be
+    ///   sent with a `Uri` of `*`, which the `Client` can't express.
     ///
     /// # Example
     ///
@@ -216,27 +248,31 @@ where
     /// # }
     /// # fn main() {}
     /// ```
-    pub fn send_request(&mut self, req: Request<B>) -> ResponseFuture {
-        let inner = match self.dispatch.send(req) {
+    pub fn send_request(&mut self, mut req: Request<B>) -> ResponseFuture {
+        let cancel = CancelHandle::new();
+        req.extensions_mut().insert(CancelWatch(cancel.clone()));
+        let inner = match self.dispatch.try_send(req) {
             Ok(rx) => {
                 Either::A(rx.then(move |res| {
                     match res {
                         Ok(Ok(res)) => Ok(res),
-                        Ok(Err(err)) => Err(err),
+                        Ok(Err((err, Some(req)))) => Err(err.with_unsent_request(req)),
+                        Ok(Err((err, None))) => Err(err),
                         // this is definite bug if it happens, but it shouldn't happen!
                         Err(_) => panic!("dispatch dropped without returning error"),
                     }
                 }))
             },
-            Err(_req) => {
+            Err(req) => {
                 debug!("connection was not ready");
                 let err = ::Error::new_canceled(Some("connection was not ready"));
-                Either::B(future::err(err))
+                Either::B(future::err(err.with_unsent_request(req)))
             }
         };
 
         ResponseFuture {
             inner: Box::new(inner),
+            cancel: cancel,
         }
     }
 
@@ -266,18 +302,23 @@ where
     }
 }
 
-/* TODO(0.12.0): when we change from tokio-service to tower.
-impl<T, B> Service for SendRequest<T, B> {
-    type Request = Request<B>;
-    type Response = Response;
+impl<B> Service for SendRequest<B>
+where
+    B: Payload + 'static,
+{
+    type ReqBody = B;
+    type ResBody = Body;
     type Error = ::Error;
     type Future = ResponseFuture;
 
-    fn call(&self, req: Self::Request) -> Self::Future {
+    fn poll_ready(&mut self) -> Poll<(), ::Error> {
+        SendRequest::poll_ready(self)
+    }
 
+    fn call(&mut self, req: Request<B>) -> ResponseFuture {
+        self.send_request(req)
     }
 }
-*/
 
 impl<B> fmt::Debug for SendRequest<B> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -296,6 +337,14 @@ impl<B> Http2SendRequest<B> {
     pub(super) fn is_closed(&self) -> bool {
         self.dispatch.is_closed()
     }
+
+    pub(super) fn in_flight(&self) -> usize {
+        self.dispatch.in_flight()
+    }
+
+    pub(super) fn is_saturated(&self) -> bool {
+        self.dispatch.is_saturated()
+    }
 }
 
 impl<B> Http2SendRequest<B>
@@ -420,7 +469,23 @@ impl Builder {
         Builder {
             exec: Exec::Default,
             h1_writev: true,
+            h1_force_writev: false,
             h1_title_case_headers: false,
+            h1_preserve_header_case: false,
+            h1_record_header_case: false,
+            h1_record_header_order: false,
+            h1_obs_fold: false,
+            h1_spaces_after_header_name: false,
+            h1_missing_reason: true,
+            h1_lf_line_endings: true,
+            h1_framing: ::proxy::FramingPolicy::Normalize,
+            h1_max_uri_len: None,
+            h1_strict_content_length: false,
+            h1_buf_sizes: proto::h1::BufSizes::default(),
+            h2_body_chunks: proto::h2::BodyChunks::default(),
+            h2_spawn_streams: true,
+            cancel_dropped_requests: true,
+            instrument: None,
             http2: false,
         }
     }
@@ -430,16 +495,91 @@ impl Builder {
         self
     }
 
+    /// Provide a [`Spawner`](::exec::Spawner) to spawn background tasks.
+    ///
+    /// Only HTTP/2 connections spawn tasks, for their requests.
+    ///
+    /// Default uses implicit default (like `tokio::spawn`).
+    pub fn spawner<S: Spawner>(&mut self, spawner: S) -> &mut Builder {
+        self.exec = Exec::Spawner(Arc::new(spawner));
+        self
+    }
+
     pub(super) fn h1_writev(&mut self, enabled: bool) -> &mut Builder {
         self.h1_writev = enabled;
         self
     }
 
+    pub(super) fn h1_force_writev(&mut self, enabled: bool) -> &mut Builder {
+        self.h1_force_writev = enabled;
+        self
+    }
+
     pub(super) fn h1_title_case_headers(&mut self, enabled: bool) -> &mut Builder {
         self.h1_title_case_headers = enabled;
         self
     }
 
+    pub(super) fn h1_preserve_header_case(&mut self, enabled: bool) -> &mut Builder {
+        self.h1_preserve_header_case = enabled;
+        self
+    }
+
+    pub(super) fn h1_record_header_case(&mut self, enabled: bool) -> &mut Builder {
+        self.h1_record_header_case = enabled;
+        self
+    }
+
+    pub(super) fn h1_record_header_order(&mut self, enabled: bool) -> &mut Builder {
+        self.h1_record_header_order = enabled;
+        self
+    }
+
+    pub(super) fn h1_obs_fold(&mut self, enabled: bool) -> &mut Builder {
+        self.h1_obs_fold = enabled;
+        self
+    }
+
+    pub(super) fn h1_spaces_after_header_name(&mut self, enabled: bool) -> &mut Builder {
+        self.h1_spaces_after_header_name = enabled;
+        self
+    }
+
+    pub(super) fn h1_missing_reason(&mut self, enabled: bool) -> &mut Builder {
+        self.h1_missing_reason = enabled;
+        self
+    }
+
+    pub(super) fn h1_lf_line_endings(&mut self, enabled: bool) -> &mut Builder {
+        self.h1_lf_line_endings = enabled;
+        self
+    }
+
+    pub(super) fn h1_framing(&mut self, val: ::proxy::FramingPolicy) -> &mut Builder {
+        self.h1_framing = val;
+        self
+    }
+
+    pub(super) fn h1_max_uri_len(&mut self, val: Option<usize>) -> &mut Builder {
+        self.h1_max_uri_len = val;
+        self
+    }
+
+    pub(super) fn h1_strict_content_length(&mut self, enabled: bool) -> &mut Builder {
+        self.h1_strict_content_length = enabled;
+        self
+    }
+
+    pub(super) fn h1_buf_sizes(&mut self, val: proto::h1::BufSizes) -> &mut Builder {
+        self.h1_buf_sizes = val;
+        self
+    }
+
+    pub(super) fn h2_body_chunks(&mut self, val: proto::h2::BodyChunks) -> &mut Builder {
+        self.h2_body_chunks = val;
+        self
+    }
+
     /// Sets whether HTTP2 is required.
     ///
     /// Default is false.
@@ -448,6 +588,110 @@ impl Builder {
         self
     }
 
+    /// Set the largest body chunk sent at once on HTTP/2 connections.
+    ///
+    /// Larger chunks from the request body are copied and sent in pieces
+    /// of this size. The `h2` library already splits DATA frames to the
+    /// peer's `SETTINGS_MAX_FRAME_SIZE`, but a piece is only handed to it
+    /// once the stream has window for it, so this also limits how much of
+    /// a chunk is buffered at a time.
+    ///
+    /// Default is no limit.
+    ///
+    /// # Panics
+    ///
+    /// The minimum value allowed is 1.
+    pub fn http2_max_send_chunk_size(&mut self, max: usize) -> &mut Builder {
+        assert!(max > 0, "the max send chunk size must be larger than 0");
+        self.h2_body_chunks.max_size = Some(max);
+        self
+    }
+
+    /// Set the smallest body chunk sent on its own on HTTP/2 connections.
+    ///
+    /// Smaller chunks from the request body are coalesced and sent
+    /// together, reducing the frame overhead of bodies made of many tiny
+    /// chunks. Coalesced chunks are still sent whenever the body has no
+    /// more data ready, so they aren't delayed.
+    ///
+    /// Default is 0, which sends every chunk on its own.
+    pub fn http2_min_send_chunk_size(&mut self, min: usize) -> &mut Builder {
+        self.h2_body_chunks.min_size = min;
+        self
+    }
+
+    /// Set whether the request bodies and responses of HTTP/2 connections
+    /// are spawned as tasks.
+    ///
+    /// When enabled, every request spawns a task sending its body, as a
+    /// [`TaskKind::RequestBody`](::exec::TaskKind::RequestBody), and one
+    /// waiting for its response, as a
+    /// [`TaskKind::Response`](::exec::TaskKind::Response).
+    ///
+    /// When disabled, the connection keeps them in a slab, and polls them
+    /// from its own task, only polling again those that were woken up.
+    /// This saves spawning, and allocating, two tasks for every request,
+    /// which adds up on connections with many requests in flight. The
+    /// connection then runs until all of them are done.
+    ///
+    /// Default is `true`.
+    pub fn http2_spawn_streams(&mut self, enabled: bool) -> &mut Builder {
+        self.h2_spawn_streams = enabled;
+        self
+    }
+
+    /// Set whether dropping a `ResponseFuture` cancels its request.
+    ///
+    /// When enabled, a request whose future is dropped isn't sent if it
+    /// wasn't yet, and an HTTP/1 connection that was already sending it, or
+    /// waiting for its response, is closed.
+    ///
+    /// When disabled, such a request is still sent, and its response is
+    /// read and discarded, so that the HTTP/1 connection can be used again.
+    /// This suits fire-and-forget requests. A response body longer than the
+    /// maximum read buffer size isn't drained, and closes the connection
+    /// instead. A [`CancelHandle`](::client::CancelHandle) still cancels
+    /// the request.
+    ///
+    /// Default is `true`.
+    pub fn cancel_dropped_requests(&mut self, enabled: bool) -> &mut Builder {
+        self.cancel_dropped_requests = enabled;
+        self
+    }
+
+    /// Report the events of connections made with this builder to an
+    /// [`Instrumentation`](::instrument::Instrumentation).
+    ///
+    /// Default is to not report anything.
+    pub fn instrumentation<I: Instrumentation>(&mut self, instrumentation: I) -> &mut Builder {
+        self.instrument.get_or_insert_with(Instrument::default).set_instrumentation(instrumentation);
+        self
+    }
+
+    /// Give a copy of the bytes of connections made with this builder to a
+    /// [`Tap`](::instrument::Tap), for debugging.
+    ///
+    /// Default is to not tap connections.
+    pub fn tap<T: Tap>(&mut self, tap: T) -> &mut Builder {
+        self.instrument.get_or_insert_with(Instrument::default).set_tap(tap);
+        self
+    }
+
+    /// Tell `observer` about the control frames of HTTP/2 connections.
+    ///
+    /// See [`FrameObserver`](::instrument::FrameObserver).
+    ///
+    /// Default is to not observe any frames.
+    pub fn http2_frame_observer<O: FrameObserver>(&mut self, observer: O) -> &mut Builder {
+        self.instrument.get_or_insert_with(Instrument::default).set_frame_observer(observer);
+        self
+    }
+
+    pub(super) fn instrument(&mut self, instrument: Option<Instrument>) -> &mut Builder {
+        self.instrument = instrument;
+        self
+    }
+
     /// Constructs a connection with the configured options and IO.
     #[inline]
     pub fn handshake<T, B>(&self, io: T) -> Handshake<T, B>
@@ -518,7 +762,7 @@ where
             T,
             proto::ClientTransaction,
         >,
-        proto::h2::Client<T, B>,
+        proto::h2::Client<Counted<T>, B>,
     >);
     type Error = ::Error;
 
@@ -543,7 +787,7 @@ where
             T,
             R,
         >,
-        proto::h2::Client<T, B>,
+        proto::h2::Client<Counted<T>, B>,
     >);
     type Error = ::Error;
 
@@ -552,17 +796,63 @@ where
         let (tx, rx) = dispatch::channel();
         let either = if !self.builder.http2 {
             let mut conn = proto::Conn::new(io);
+            // the default buffers are already allocated
+            if self.builder.h1_buf_sizes != proto::h1::BufSizes::default() {
+                conn.set_buf_sizes(self.builder.h1_buf_sizes);
+            }
             if !self.builder.h1_writev {
                 conn.set_write_strategy_flatten();
+            } else if self.builder.h1_force_writev {
+                conn.set_write_strategy_queue();
             }
             if self.builder.h1_title_case_headers {
                 conn.set_title_case_headers();
             }
-            let cd = proto::h1::dispatch::Client::new(rx);
+            if self.builder.h1_preserve_header_case {
+                conn.set_preserve_header_case();
+            } else if self.builder.h1_record_header_case {
+                conn.set_record_header_case();
+            }
+            if self.builder.h1_record_header_order {
+                conn.set_record_header_order();
+            }
+            if self.builder.h1_obs_fold {
+                conn.set_allow_obs_fold();
+            }
+            if self.builder.h1_spaces_after_header_name {
+                conn.set_allow_spaces_after_header_name();
+            }
+            if !self.builder.h1_missing_reason {
+                conn.set_allow_missing_reason(false);
+            }
+            if !self.builder.h1_lf_line_endings {
+                conn.set_allow_lf_line_endings(false);
+            }
+            conn.set_framing_policy(self.builder.h1_framing);
+            if let Some(max) = self.builder.h1_max_uri_len {
+                conn.set_max_uri_len(max);
+            }
+            if self.builder.h1_strict_content_length {
+                conn.set_strict_content_length();
+            }
+            if let Some(ref instrument) = self.builder.instrument {
+                conn.set_instrument(instrument.clone());
+            }
+            #[cfg(all(feature = "sendfile", target_os = "linux"))]
+            conn.enable_send_file();
+            let mut cd = proto::h1::dispatch::Client::new(rx);
+            cd.set_cancel_dropped_requests(self.builder.cancel_dropped_requests);
+            if let Some(ref instrument) = self.builder.instrument {
+                cd.set_instrument(instrument.clone());
+            }
             let dispatch = proto::h1::Dispatcher::new(cd, conn);
             Either::A(dispatch)
         } else {
-            let h2 = proto::h2::Client::new(io, rx, self.builder.exec.clone());
+            let io = Counted::new(io, self.builder.instrument.clone());
+            let mut h2 = proto::h2::Client::new(io, rx, self.builder.exec.clone(), self.builder.instrument.clone());
+            h2.set_body_chunks(self.builder.h2_body_chunks);
+            h2.set_cancel_dropped_requests(self.builder.cancel_dropped_requests);
+            h2.set_spawn_streams(self.builder.h2_spawn_streams);
             Either::B(h2)
         };
 
@@ -583,10 +873,21 @@ impl Future for ResponseFuture {
 
     #[inline]
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.cancel.poll_canceled() {
+            return Err(::Error::new_request_canceled());
+        }
         self.inner.poll()
     }
 }
 
+impl ResponseFuture {
+    /// Returns a handle that can cancel this request, even once this
+    /// future has yielded the `Response`.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+}
+
 impl fmt::Debug for ResponseFuture {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("ResponseFuture")
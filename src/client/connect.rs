@@ -5,13 +5,32 @@
 //! - A default [`HttpConnector`](HttpConnector) that does DNS resolution and
 //!   establishes connections over TCP.
 //! - The [`Connect`](Connect) trait and related types to build custom connectors.
+//! - A [`DialConnector`](DialConnector), connecting over TCP with the
+//!   `TcpStream` of another runtime, through the [`Dial`](Dial) trait.
+//!
+//! Only the `HttpConnector` needs tokio, and it's left out without the
+//! `runtime` feature. Together with a [`Spawner`](::exec::Spawner) given to
+//! [`Builder::spawner`](::client::Builder::spawner), a `Client` doesn't
+//! otherwise rely on tokio, except for the timer that removes expired idle
+//! connections from its pool, which without the `runtime` feature is left
+//! to checkouts.
 use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::time::Instant;
 
-use futures::Future;
+use bytes::Bytes;
+use futures::{Async, Future, Poll};
 use http::Uri;
+use http::uri::Scheme;
 use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::io::{write_all, WriteAll};
+
+use proto::proxy;
 
-#[cfg(feature = "runtime")] pub use self::http::HttpConnector;
+#[cfg(feature = "runtime")] pub use self::http::{ConnectAttempts, HttpConnector};
+pub use proto::proxy::ProxyInfo;
 
 /// Connect to a destination, returning an IO transport.
 ///
@@ -44,6 +63,16 @@ pub struct Destination {
 pub struct Connected {
     //alpn: Alpn,
     pub(super) is_proxied: bool,
+    pub(super) alpn: Option<Bytes>,
+    pub(super) times: ConnectTimes,
+}
+
+/// When the stages of establishing a connection completed.
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct ConnectTimes {
+    pub(super) dns_resolved: Option<Instant>,
+    pub(super) connected: Option<Instant>,
+    pub(super) tls_handshaken: Option<Instant>,
 }
 
 /*TODO: when HTTP1 Upgrades to H2 are added, this will be needed
@@ -96,6 +125,8 @@ impl Connected {
         Connected {
             //alpn: Alpn::Http1,
             is_proxied: false,
+            alpn: None,
+            times: ConnectTimes::default(),
         }
     }
 
@@ -111,6 +142,40 @@ impl Connected {
         self
     }
 
+    /// Set the protocol negotiated with ALPN, such as `h2` or `http/1.1`.
+    ///
+    /// This is reported in the [`ConnectionInfo`](::ext::ConnectionInfo)
+    /// of responses received on the connection. It doesn't change which
+    /// protocol the `Client` speaks.
+    pub fn alpn_protocol<P: Into<Bytes>>(mut self, protocol: P) -> Connected {
+        self.alpn = Some(protocol.into());
+        self
+    }
+
+    /// Set when the host name of the destination was resolved.
+    ///
+    /// This, and the other times of a `Connected`, are reported in the
+    /// [`Timings`](::ext::Timings) of requests sent on the connection.
+    pub fn dns_resolved_at(mut self, at: Instant) -> Connected {
+        self.times.dns_resolved = Some(at);
+        self
+    }
+
+    /// Set when the transport was connected.
+    pub fn connected_at(mut self, at: Instant) -> Connected {
+        self.times.connected = Some(at);
+        self
+    }
+
+    /// Set when the TLS handshake of the transport completed.
+    ///
+    /// A TLS connector wrapping another one should call this on the
+    /// `Connected` it received, to keep the times set by the inner one.
+    pub fn tls_handshaken_at(mut self, at: Instant) -> Connected {
+        self.times.tls_handshaken = Some(at);
+        self
+    }
+
     /*
     /// Set that the connected transport negotiated HTTP/2 as it's
     /// next protocol.
@@ -121,6 +186,237 @@ impl Connected {
     */
 }
 
+/// A connector that sends a PROXY protocol header on new connections.
+///
+/// After the inner connector has connected, a version 2 header of the
+/// HAProxy PROXY protocol is written, telling a PROXY-aware upstream the
+/// addresses of the original client connection.
+///
+/// Since pooled connections are reused, a `Client` with this connector
+/// should only be used for requests from the same original client.
+#[derive(Clone, Debug)]
+pub struct ProxyHeaderConnector<C> {
+    inner: C,
+    info: ProxyInfo,
+}
+
+impl<C> ProxyHeaderConnector<C> {
+    /// Wrap a connector, sending `info` on each of its connections.
+    pub fn new(inner: C, info: ProxyInfo) -> ProxyHeaderConnector<C> {
+        ProxyHeaderConnector {
+            inner,
+            info,
+        }
+    }
+}
+
+impl<C: Connect> Connect for ProxyHeaderConnector<C> {
+    type Transport = C::Transport;
+    type Error = Box<StdError + Send + Sync>;
+    type Future = ProxyHeaderConnecting<C::Future, C::Transport>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        ProxyHeaderConnecting {
+            state: ProxyHeaderState::Connecting(
+                self.inner.connect(dst),
+                proxy::encode_v2(&self.info),
+            ),
+        }
+    }
+}
+
+/// A Future writing a PROXY header once connected.
+#[must_use = "futures do nothing unless polled"]
+pub struct ProxyHeaderConnecting<F, T> {
+    state: ProxyHeaderState<F, T>,
+}
+
+enum ProxyHeaderState<F, T> {
+    Connecting(F, Vec<u8>),
+    Writing(WriteAll<T, Vec<u8>>, Connected),
+    Done,
+}
+
+impl<F, T, E> Future for ProxyHeaderConnecting<F, T>
+where
+    F: Future<Item=(T, Connected), Error=E>,
+    E: Into<Box<StdError + Send + Sync>>,
+    T: AsyncWrite,
+{
+    type Item = (T, Connected);
+    type Error = Box<StdError + Send + Sync>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                ProxyHeaderState::Connecting(ref mut fut, ref mut header) => {
+                    let (io, connected) = try_ready!(fut.poll().map_err(Into::into));
+                    trace!("connected, writing PROXY header");
+                    let header = mem::replace(header, Vec::new());
+                    ProxyHeaderState::Writing(write_all(io, header), connected)
+                },
+                ProxyHeaderState::Writing(ref mut fut, _) => {
+                    let (io, _) = try_ready!(fut.poll());
+                    match mem::replace(&mut self.state, ProxyHeaderState::Done) {
+                        ProxyHeaderState::Writing(_, connected) => {
+                            return Ok(Async::Ready((io, connected)));
+                        },
+                        _ => unreachable!(),
+                    }
+                },
+                ProxyHeaderState::Done => panic!("polled after complete"),
+            };
+            self.state = next;
+        }
+    }
+}
+
+impl<F, T> ::std::fmt::Debug for ProxyHeaderConnecting<F, T> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.pad("ProxyHeaderConnecting")
+    }
+}
+
+/// Opens TCP connections, such as with the `TcpStream` of a runtime.
+///
+/// Resolving the host is left to the `Dial`, as the `connect` of a
+/// runtime's `TcpStream` usually does.
+pub trait Dial: Send + Sync {
+    /// The connected IO Stream.
+    type Conn: AsyncRead + AsyncWrite + Send + 'static;
+    /// A Future that will resolve to the connected stream.
+    type Future: Future<Item=Self::Conn, Error=io::Error> + Send;
+    /// Connect to a port of a host, a domain name or an IP address.
+    fn dial(&self, host: &str, port: u16) -> Self::Future;
+}
+
+/// A connector for the `http` scheme, opening connections with a
+/// [`Dial`](Dial).
+///
+/// This checks the `Uri`s of requests, and picks their ports, as the
+/// `HttpConnector` does, so that a `Client` can use the `TcpStream` of
+/// another runtime.
+#[derive(Clone, Debug)]
+pub struct DialConnector<D> {
+    dial: D,
+    enforce_http: bool,
+}
+
+impl<D> DialConnector<D> {
+    /// Construct a new DialConnector.
+    pub fn new(dial: D) -> DialConnector<D> {
+        DialConnector {
+            dial: dial,
+            enforce_http: true,
+        }
+    }
+
+    /// Option to enforce all `Uri`s have the `http` scheme.
+    ///
+    /// Enabled by default.
+    #[inline]
+    pub fn enforce_http(&mut self, is_enforced: bool) {
+        self.enforce_http = is_enforced;
+    }
+}
+
+impl<D: Dial> Connect for DialConnector<D> {
+    type Transport = D::Conn;
+    type Error = io::Error;
+    type Future = Dialing<D::Future>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        trace!(
+            "Dial::connect; scheme={}, host={}, port={:?}",
+            dst.scheme(),
+            dst.host(),
+            dst.port(),
+        );
+
+        let state = match host_port(&dst.uri, self.enforce_http) {
+            Ok((host, port)) => Ok(self.dial.dial(host, port)),
+            Err(err) => Err(Some(io::Error::new(io::ErrorKind::InvalidInput, err))),
+        };
+        Dialing {
+            state: state,
+        }
+    }
+}
+
+/// A Future representing work to connect to a URL with a [`Dial`](Dial).
+#[must_use = "futures do nothing unless polled"]
+pub struct Dialing<F> {
+    state: Result<F, Option<io::Error>>,
+}
+
+impl<F, T> Future for Dialing<F>
+where
+    F: Future<Item=T, Error=io::Error>,
+{
+    type Item = (T, Connected);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.state {
+            Ok(ref mut fut) => {
+                let io = try_ready!(fut.poll());
+                Ok(Async::Ready((io, Connected::new().connected_at(Instant::now()))))
+            },
+            Err(ref mut err) => Err(err.take().expect("polled after complete")),
+        }
+    }
+}
+
+impl<F> fmt::Debug for Dialing<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Dialing")
+    }
+}
+
+/// Returns the host and port to connect to for `uri`.
+fn host_port(uri: &Uri, enforce_http: bool) -> Result<(&str, u16), InvalidUrl> {
+    if enforce_http {
+        if uri.scheme_part() != Some(&Scheme::HTTP) {
+            return Err(InvalidUrl::NotHttp);
+        }
+    } else if uri.scheme_part().is_none() {
+        return Err(InvalidUrl::MissingScheme);
+    }
+
+    let host = match uri.host() {
+        Some(s) => s,
+        None => return Err(InvalidUrl::MissingAuthority),
+    };
+    let port = match uri.port() {
+        Some(port) => port,
+        None => if uri.scheme_part() == Some(&Scheme::HTTPS) { 443 } else { 80 },
+    };
+    Ok((host, port))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum InvalidUrl {
+    MissingScheme,
+    NotHttp,
+    MissingAuthority,
+}
+
+impl fmt::Display for InvalidUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+impl StdError for InvalidUrl {
+    fn description(&self) -> &str {
+        match *self {
+            InvalidUrl::MissingScheme => "invalid URL, missing scheme",
+            InvalidUrl::NotHttp => "invalid URL, scheme must be http",
+            InvalidUrl::MissingAuthority => "invalid URL, missing domain",
+        }
+    }
+}
+
 #[cfg(feature = "runtime")]
 mod http {
     use super::*;
@@ -131,28 +427,32 @@ mod http {
     use std::mem;
     use std::net::{IpAddr, SocketAddr};
     use std::sync::Arc;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
 
     use futures::{Async, Poll};
     use futures::future::{Executor, ExecuteError};
     use futures::sync::oneshot;
     use futures_cpupool::{Builder as CpuPoolBuilder};
-    use http::uri::Scheme;
     use net2::TcpBuilder;
     use tokio_reactor::Handle;
     use tokio_tcp::{TcpStream, ConnectFuture};
 
+    use common::sockopt;
     use super::super::dns;
 
     use self::http_connector::HttpConnectorBlockingTask;
 
 
-    fn connect(addr: &SocketAddr, local_addr: &Option<IpAddr>, handle: &Option<Handle>) -> io::Result<ConnectFuture> {
+    fn connect(addr: &SocketAddr, local_addr: &Option<IpAddr>, tcp_fastopen: bool, handle: &Option<Handle>) -> io::Result<ConnectFuture> {
         let builder = match addr {
             &SocketAddr::V4(_) => TcpBuilder::new_v4()?,
             &SocketAddr::V6(_) => TcpBuilder::new_v6()?,
         };
 
+        if tcp_fastopen {
+            sockopt::set_tcp_fastopen_connect(&builder);
+        }
+
         if let Some(ref local_addr) = *local_addr {
             // Caller has requested this socket be bound before calling connect
             builder.bind(SocketAddr::new(local_addr.clone(), 0))?;
@@ -189,6 +489,7 @@ mod http {
         keep_alive_timeout: Option<Duration>,
         nodelay: bool,
         local_address: Option<IpAddr>,
+        tcp_fastopen: bool,
     }
 
     impl HttpConnector {
@@ -226,6 +527,7 @@ mod http {
                 keep_alive_timeout: None,
                 nodelay: false,
                 local_address: None,
+                tcp_fastopen: false,
             }
         }
 
@@ -264,6 +566,17 @@ mod http {
         pub fn set_local_address(&mut self, addr: Option<IpAddr>) {
             self.local_address = addr;
         }
+
+        /// Set that all sockets use TCP Fast Open, where the platform supports it.
+        ///
+        /// The request head is then sent along with the SYN, saving a round
+        /// trip on connections to servers that have recently been visited.
+        ///
+        /// Default is `false`.
+        #[inline]
+        pub fn set_tcp_fastopen(&mut self, enabled: bool) {
+            self.tcp_fastopen = enabled;
+        }
     }
 
     impl fmt::Debug for HttpConnector {
@@ -287,21 +600,9 @@ mod http {
                 dst.port(),
             );
 
-            if self.enforce_http {
-                if dst.uri.scheme_part() != Some(&Scheme::HTTP) {
-                    return invalid_url(InvalidUrl::NotHttp, &self.handle);
-                }
-            } else if dst.uri.scheme_part().is_none() {
-                return invalid_url(InvalidUrl::MissingScheme, &self.handle);
-            }
-
-            let host = match dst.uri.host() {
-                Some(s) => s,
-                None => return invalid_url(InvalidUrl::MissingAuthority, &self.handle),
-            };
-            let port = match dst.uri.port() {
-                Some(port) => port,
-                None => if dst.uri.scheme_part() == Some(&Scheme::HTTPS) { 443 } else { 80 },
+            let (host, port) = match host_port(&dst.uri, self.enforce_http) {
+                Ok(host_port) => host_port,
+                Err(err) => return invalid_url(err, &self.handle),
             };
 
             HttpConnecting {
@@ -309,6 +610,8 @@ mod http {
                 handle: self.handle.clone(),
                 keep_alive_timeout: self.keep_alive_timeout,
                 nodelay: self.nodelay,
+                tcp_fastopen: self.tcp_fastopen,
+                dns_resolved: None,
             }
         }
     }
@@ -320,29 +623,8 @@ mod http {
             handle: handle.clone(),
             keep_alive_timeout: None,
             nodelay: false,
-        }
-    }
-
-    #[derive(Debug, Clone, Copy)]
-    enum InvalidUrl {
-        MissingScheme,
-        NotHttp,
-        MissingAuthority,
-    }
-
-    impl fmt::Display for InvalidUrl {
-        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            f.write_str(self.description())
-        }
-    }
-
-    impl StdError for InvalidUrl {
-        fn description(&self) -> &str {
-            match *self {
-                InvalidUrl::MissingScheme => "invalid URL, missing scheme",
-                InvalidUrl::NotHttp => "invalid URL, scheme must be http",
-                InvalidUrl::MissingAuthority => "invalid URL, missing domain",
-            }
+            tcp_fastopen: false,
+            dns_resolved: None,
         }
     }
     /// A Future representing work to connect to a URL.
@@ -352,6 +634,8 @@ mod http {
         handle: Option<Handle>,
         keep_alive_timeout: Option<Duration>,
         nodelay: bool,
+        tcp_fastopen: bool,
+        dns_resolved: Option<Instant>,
     }
 
     enum State {
@@ -373,11 +657,7 @@ mod http {
                         // If the host is already an IP addr (v4 or v6),
                         // skip resolving the dns and start connecting right away.
                         if let Some(addrs) = dns::IpAddrs::try_parse(host, port) {
-                            state = State::Connecting(ConnectingTcp {
-                                addrs: addrs,
-                                local_addr: local_addr,
-                                current: None
-                            })
+                            state = State::Connecting(ConnectingTcp::new(addrs, local_addr));
                         } else {
                             let host = mem::replace(host, String::new());
                             let work = dns::Work::new(host, port);
@@ -388,16 +668,13 @@ mod http {
                         match try!(future.poll()) {
                             Async::NotReady => return Ok(Async::NotReady),
                             Async::Ready(addrs) => {
-                                state = State::Connecting(ConnectingTcp {
-                                    addrs: addrs,
-                                    local_addr: local_addr,
-                                    current: None,
-                                })
+                                self.dns_resolved = Some(Instant::now());
+                                state = State::Connecting(ConnectingTcp::new(addrs, local_addr));
                             }
                         };
                     },
                     State::Connecting(ref mut c) => {
-                        let sock = try_ready!(c.poll(&self.handle));
+                        let sock = try_ready!(c.poll(self.tcp_fastopen, &self.handle));
 
                         if let Some(dur) = self.keep_alive_timeout {
                             sock.set_keepalive(Some(dur))?;
@@ -405,7 +682,11 @@ mod http {
 
                         sock.set_nodelay(self.nodelay)?;
 
-                        return Ok(Async::Ready((sock, Connected::new())));
+                        let mut connected = Connected::new().connected_at(Instant::now());
+                        if let Some(at) = self.dns_resolved {
+                            connected = connected.dns_resolved_at(at);
+                        }
+                        return Ok(Async::Ready((sock, connected)));
                     },
                     State::Error(ref mut e) => return Err(e.take().expect("polled more than once")),
                 }
@@ -423,35 +704,97 @@ mod http {
     struct ConnectingTcp {
         addrs: dns::IpAddrs,
         local_addr: Option<IpAddr>,
-        current: Option<ConnectFuture>,
+        current: Option<(SocketAddr, ConnectFuture)>,
+        attempts: Vec<(SocketAddr, io::Error)>,
     }
 
     impl ConnectingTcp {
+        fn new(addrs: dns::IpAddrs, local_addr: Option<IpAddr>) -> ConnectingTcp {
+            ConnectingTcp {
+                addrs: addrs,
+                local_addr: local_addr,
+                current: None,
+                attempts: Vec::new(),
+            }
+        }
+
         // not a Future, since passing a &Handle to poll
-        fn poll(&mut self, handle: &Option<Handle>) -> Poll<TcpStream, io::Error> {
-            let mut err = None;
+        fn poll(&mut self, tcp_fastopen: bool, handle: &Option<Handle>) -> Poll<TcpStream, io::Error> {
             loop {
-                if let Some(ref mut current) = self.current {
+                if let Some((addr, ref mut current)) = self.current {
                     match current.poll() {
                         Ok(ok) => return Ok(ok),
                         Err(e) => {
-                            trace!("connect error {:?}", e);
-                            err = Some(e);
-                            if let Some(addr) = self.addrs.next() {
-                                debug!("connecting to {}", addr);
-                                *current = connect(&addr, &self.local_addr, handle)?;
-                                continue;
-                            }
+                            trace!("connect error to {}: {:?}", addr, e);
+                            self.attempts.push((addr, e));
                         }
                     }
-                } else if let Some(addr) = self.addrs.next() {
-                    debug!("connecting to {}", addr);
-                    self.current = Some(connect(&addr, &self.local_addr, handle)?);
-                    continue;
                 }
 
-                return Err(err.take().expect("missing connect error"));
+                // An address that a socket can't even be set up for, such
+                // as an IPv6 one on a host without IPv6, is skipped too.
+                self.current = None;
+                match self.addrs.next() {
+                    Some(addr) => {
+                        debug!("connecting to {}", addr);
+                        match connect(&addr, &self.local_addr, tcp_fastopen, handle) {
+                            Ok(fut) => self.current = Some((addr, fut)),
+                            Err(e) => {
+                                trace!("connect error to {}: {:?}", addr, e);
+                                self.attempts.push((addr, e));
+                            }
+                        }
+                    },
+                    None => return Err(self.error()),
+                }
+            }
+        }
+
+        fn error(&mut self) -> io::Error {
+            let kind = match self.attempts.last() {
+                Some(&(_, ref err)) => err.kind(),
+                None => return io::Error::new(io::ErrorKind::AddrNotAvailable, "no addresses to connect to"),
+            };
+            let attempts = mem::replace(&mut self.attempts, Vec::new());
+            io::Error::new(kind, ConnectAttempts { attempts: attempts })
+        }
+    }
+
+    /// The failures of connecting to each address of a host.
+    ///
+    /// When no address of a host could be connected to, the `io::Error`
+    /// returned by `HttpConnector` has the kind of the last failure, and
+    /// this as its inner error. It lists every address that was tried, in
+    /// order, such as both the IPv6 and IPv4 addresses of a dual-stack host.
+    ///
+    /// It can be found from a `hyper::Error` with
+    /// [`find_cause`](::Error::find_cause).
+    #[derive(Debug)]
+    pub struct ConnectAttempts {
+        attempts: Vec<(SocketAddr, io::Error)>,
+    }
+
+    impl ConnectAttempts {
+        /// The addresses that were tried, with the error of each.
+        pub fn attempts(&self) -> &[(SocketAddr, io::Error)] {
+            &self.attempts
+        }
+    }
+
+    impl fmt::Display for ConnectAttempts {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(self.description())?;
+            for (i, &(ref addr, ref err)) in self.attempts.iter().enumerate() {
+                let sep = if i == 0 { ": " } else { ", " };
+                write!(f, "{}{} ({})", sep, addr, err)?;
             }
+            Ok(())
+        }
+    }
+
+    impl StdError for ConnectAttempts {
+        fn description(&self) -> &str {
+            "failed to connect to every address"
         }
     }
 
@@ -492,8 +835,9 @@ mod http {
     #[cfg(test)]
     mod tests {
         use std::io;
+        use std::net::TcpListener;
         use futures::Future;
-        use super::{Connect, Destination, HttpConnector};
+        use super::{Connect, ConnectAttempts, Destination, HttpConnector};
 
         #[test]
         fn test_errors_missing_authority() {
@@ -528,6 +872,84 @@ mod http {
 
             assert_eq!(connector.connect(dst).wait().unwrap_err().kind(), io::ErrorKind::InvalidInput);
         }
+
+        #[test]
+        fn test_errors_list_attempts() {
+            // a port that nobody is listening on anymore
+            let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+            let dst = Destination {
+                uri: format!("http://{}", addr).parse().unwrap(),
+            };
+            let connector = HttpConnector::new(1);
+
+            let err = connector.connect(dst).wait().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+            let attempts = err.get_ref()
+                .and_then(|inner| inner.downcast_ref::<ConnectAttempts>())
+                .expect("connect attempts")
+                .attempts();
+            assert_eq!(attempts.len(), 1);
+            assert_eq!(attempts[0].0, addr);
+            assert_eq!(attempts[0].1.kind(), io::ErrorKind::ConnectionRefused);
+            assert!(err.to_string().contains(&addr.to_string()), "{}", err);
+        }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+    use std::sync::{Arc, Mutex};
+    use futures::Future;
+    use futures::future::{self, FutureResult};
+    use super::{Connect, Destination, Dial, DialConnector};
+
+    #[derive(Clone, Default)]
+    struct Dialed(Arc<Mutex<Vec<(String, u16)>>>);
+
+    impl Dial for Dialed {
+        type Conn = Cursor<Vec<u8>>;
+        type Future = FutureResult<Cursor<Vec<u8>>, io::Error>;
+
+        fn dial(&self, host: &str, port: u16) -> Self::Future {
+            self.0.lock().unwrap().push((host.to_owned(), port));
+            future::ok(Cursor::new(Vec::new()))
+        }
+    }
+
+    fn dst(uri: &str) -> Destination {
+        Destination {
+            uri: uri.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn dial_connector_picks_ports() {
+        let dialed = Dialed::default();
+        let mut connector = DialConnector::new(dialed.clone());
+        connector.connect(dst("http://example.domain/foo")).wait().unwrap();
+        connector.connect(dst("http://127.0.0.1:3000")).wait().unwrap();
+
+        connector.enforce_http(false);
+        let (_, connected) = connector.connect(dst("https://example.domain")).wait().unwrap();
+        assert!(connected.times.connected.is_some());
+
+        assert_eq!(*dialed.0.lock().unwrap(), [
+            ("example.domain".to_owned(), 80),
+            ("127.0.0.1".to_owned(), 3000),
+            ("example.domain".to_owned(), 443),
+        ]);
+    }
+
+    #[test]
+    fn dial_connector_checks_uris() {
+        let dialed = Dialed::default();
+        let connector = DialConnector::new(dialed.clone());
+        for uri in &["https://example.domain", "/foo/bar?baz", "example.domain"] {
+            let err = connector.connect(dst(uri)).wait().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{}", uri);
+        }
+        assert!(dialed.0.lock().unwrap().is_empty());
+    }
+}
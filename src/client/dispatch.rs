@@ -1,30 +1,151 @@
-use futures::{Async, Poll, Stream};
-use futures::sync::{mpsc, oneshot};
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use futures::{Async, Future, Poll, Stream};
+use futures::sync::oneshot::Canceled;
+use futures::task::{self, Task};
 use want;
 
 use common::Never;
 
-pub type RetryPromise<T, U> = oneshot::Receiver<Result<U, (::Error, Option<T>)>>;
-pub type Promise<T> = oneshot::Receiver<Result<T, ::Error>>;
+/// The result of a request, with the request itself if it failed before
+/// it was sent.
+type Outcome<T, U> = Result<U, (::Error, Option<T>)>;
 
 pub fn channel<T, U>() -> (Sender<T, U>, Receiver<T, U>) {
-    let (tx, rx) = mpsc::unbounded();
+    let shared = Arc::new(Mutex::new(Shared {
+        queue: VecDeque::new(),
+        slots: Vec::new(),
+        free: Vec::new(),
+        rx_task: None,
+        rx_closed: false,
+        saturated: false,
+        senders: 1,
+    }));
     let (giver, taker) = want::new();
     let tx = Sender {
         buffered_once: false,
         giver: giver,
-        inner: tx,
+        inner: Tx(shared.clone()),
     };
     let rx = Receiver {
-        inner: rx,
+        shared: shared,
         taker: taker,
     };
     (tx, rx)
 }
 
+/// The queue of requests, and the slots their callbacks and promises meet
+/// in.
+///
+/// The slots are reused once both sides are done with them, so that after
+/// the first few requests of a connection, sending one doesn't allocate.
+struct Shared<T, U> {
+    queue: VecDeque<(T, usize)>,
+    slots: Vec<Slot<T, U>>,
+    free: Vec<usize>,
+    rx_task: Option<Task>,
+    rx_closed: bool,
+    /// Whether the receiver can't start more requests right now.
+    saturated: bool,
+    senders: usize,
+}
+
+enum Slot<T, U> {
+    Free,
+    Pending {
+        promise: Option<Task>,
+        callback: Option<Task>,
+        promise_dropped: bool,
+    },
+    /// The callback was sent, and the promise is yet to take it.
+    Done(Outcome<T, U>),
+    /// The callback was dropped without being sent.
+    Dropped,
+}
+
+impl<T, U> Shared<T, U> {
+    fn alloc(&mut self) -> usize {
+        let slot = Slot::Pending {
+            promise: None,
+            callback: None,
+            promise_dropped: false,
+        };
+        match self.free.pop() {
+            Some(index) => {
+                self.slots[index] = slot;
+                index
+            },
+            None => {
+                self.slots.push(slot);
+                self.slots.len() - 1
+            },
+        }
+    }
+
+    /// The requests sent that didn't get their response yet.
+    fn in_flight(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    fn release(&mut self, index: usize) -> Slot<T, U> {
+        self.free.push(index);
+        mem::replace(&mut self.slots[index], Slot::Free)
+    }
+
+    fn send(&mut self, val: T) -> Result<usize, T> {
+        if self.rx_closed {
+            return Err(val);
+        }
+        let index = self.alloc();
+        self.queue.push_back((val, index));
+        if let Some(task) = self.rx_task.take() {
+            task.notify();
+        }
+        Ok(index)
+    }
+}
+
+/// A handle keeping the channel open for the `Receiver`.
+struct Tx<T, U>(Arc<Mutex<Shared<T, U>>>);
+
+impl<T, U> Tx<T, U> {
+    fn send(&self, val: T) -> Result<RetryPromise<T, U>, T> {
+        let index = self.0.lock().unwrap().send(val)?;
+        Ok(RetryPromise {
+            shared: self.0.clone(),
+            index: index,
+            done: false,
+        })
+    }
+}
+
+impl<T, U> Clone for Tx<T, U> {
+    fn clone(&self) -> Tx<T, U> {
+        self.0.lock().unwrap().senders += 1;
+        Tx(self.0.clone())
+    }
+}
+
+impl<T, U> Drop for Tx<T, U> {
+    fn drop(&mut self) {
+        let mut shared = match self.0.lock() {
+            Ok(shared) => shared,
+            Err(_poisoned) => return,
+        };
+        shared.senders -= 1;
+        if shared.senders == 0 {
+            if let Some(task) = shared.rx_task.take() {
+                task.notify();
+            }
+        }
+    }
+}
+
 /// A bounded sender of requests and callbacks for when responses are ready.
 ///
-/// While the inner sender is unbounded, the Giver is used to determine
+/// While the inner queue is unbounded, the Giver is used to determine
 /// if the Receiver is ready for another request.
 pub struct Sender<T, U> {
     /// One message is always allowed, even if the Receiver hasn't asked
@@ -37,7 +158,7 @@ pub struct Sender<T, U> {
     /// for more.
     giver: want::Giver,
     /// Actually bounded by the Giver, plus `buffered_once`.
-    inner: mpsc::UnboundedSender<Envelope<T, U>>,
+    inner: Tx<T, U>,
 }
 
 /// An unbounded version.
@@ -45,9 +166,9 @@ pub struct Sender<T, U> {
 /// Cannot poll the Giver, but can still use it to determine if the Receiver
 /// has been dropped. However, this version can be cloned.
 pub struct UnboundedSender<T, U> {
-    /// Only used for `is_closed`, since mpsc::UnboundedSender cannot be checked.
+    /// Only used for `is_closed`, since the queue is only checked on send.
     giver: want::SharedGiver,
-    inner: mpsc::UnboundedSender<Envelope<T, U>>,
+    inner: Tx<T, U>,
 }
 
 impl<T, U> Sender<T, U> {
@@ -81,20 +202,7 @@ impl<T, U> Sender<T, U> {
         if !self.can_send() {
             return Err(val);
         }
-        let (tx, rx) = oneshot::channel();
-        self.inner.unbounded_send(Envelope(Some((val, Callback::Retry(tx)))))
-            .map(move |_| rx)
-            .map_err(|e| e.into_inner().0.take().expect("envelope not dropped").0)
-    }
-
-    pub fn send(&mut self, val: T) -> Result<Promise<U>, T> {
-        if !self.can_send() {
-            return Err(val);
-        }
-        let (tx, rx) = oneshot::channel();
-        self.inner.unbounded_send(Envelope(Some((val, Callback::NoRetry(tx)))))
-            .map(move |_| rx)
-            .map_err(|e| e.into_inner().0.take().expect("envelope not dropped").0)
+        self.inner.send(val)
     }
 
     pub fn unbound(self) -> UnboundedSender<T, U> {
@@ -114,11 +222,18 @@ impl<T, U> UnboundedSender<T, U> {
         self.giver.is_canceled()
     }
 
+    /// The requests sent that didn't get their response yet.
+    pub fn in_flight(&self) -> usize {
+        (self.inner.0).lock().unwrap().in_flight()
+    }
+
+    /// Whether the receiver said it can't start more requests right now.
+    pub fn is_saturated(&self) -> bool {
+        (self.inner.0).lock().unwrap().saturated
+    }
+
     pub fn try_send(&mut self, val: T) -> Result<RetryPromise<T, U>, T> {
-        let (tx, rx) = oneshot::channel();
-        self.inner.unbounded_send(Envelope(Some((val, Callback::Retry(tx)))))
-            .map(move |_| rx)
-            .map_err(|e| e.into_inner().0.take().expect("envelope not dropped").0)
+        self.inner.send(val)
     }
 }
 
@@ -132,7 +247,7 @@ impl<T, U> Clone for UnboundedSender<T, U> {
 }
 
 pub struct Receiver<T, U> {
-    inner: mpsc::UnboundedReceiver<Envelope<T, U>>,
+    shared: Arc<Mutex<Shared<T, U>>>,
     taker: want::Taker,
 }
 
@@ -141,58 +256,183 @@ impl<T, U> Stream for Receiver<T, U> {
     type Error = Never;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        match self.inner.poll() {
-            Ok(Async::Ready(item)) => Ok(Async::Ready(item.map(|mut env| {
-                env.0.take().expect("envelope not dropped")
-            }))),
-            Ok(Async::NotReady) => {
-                self.taker.want();
-                Ok(Async::NotReady)
+        {
+            let mut shared = self.shared.lock().unwrap();
+            if let Some((val, index)) = shared.queue.pop_front() {
+                let cb = Callback {
+                    shared: self.shared.clone(),
+                    index: index,
+                    sent: false,
+                };
+                return Ok(Async::Ready(Some((val, cb))));
+            }
+            if shared.senders == 0 {
+                return Ok(Async::Ready(None));
             }
-            Err(()) => unreachable!("mpsc never errors"),
+            shared.rx_task = Some(task::current());
         }
+        self.taker.want();
+        Ok(Async::NotReady)
+    }
+}
+
+impl<T, U> Receiver<T, U> {
+    /// Sets whether more requests sent are only queued, such as when an
+    /// HTTP/2 connection reached the peer's limit of concurrent streams.
+    pub fn set_saturated(&self, saturated: bool) {
+        self.shared.lock().unwrap().saturated = saturated;
     }
 }
 
 impl<T, U> Drop for Receiver<T, U> {
     fn drop(&mut self) {
-        // Notify the giver about the closure first, before dropping
-        // the mpsc::Receiver.
+        // Notify the giver about the closure first, before closing the
+        // queue.
         self.taker.cancel();
+        let queued = {
+            let mut shared = match self.shared.lock() {
+                Ok(shared) => shared,
+                Err(_poisoned) => return,
+            };
+            shared.rx_closed = true;
+            shared.rx_task = None;
+            mem::replace(&mut shared.queue, VecDeque::new())
+        };
+        for (val, index) in queued {
+            let cb = Callback {
+                shared: self.shared.clone(),
+                index: index,
+                sent: false,
+            };
+            cb.send(Err((::Error::new_canceled(None::<::Error>), Some(val))));
+        }
     }
 }
 
-struct Envelope<T, U>(Option<(T, Callback<T, U>)>);
+/// A Future of the result of a request sent on the channel.
+///
+/// It fails with `Canceled` only if the `Callback` was dropped without
+/// being sent, which is a bug.
+pub struct RetryPromise<T, U> {
+    shared: Arc<Mutex<Shared<T, U>>>,
+    index: usize,
+    done: bool,
+}
+
+impl<T, U> Future for RetryPromise<T, U> {
+    type Item = Outcome<T, U>;
+    type Error = Canceled;
 
-impl<T, U> Drop for Envelope<T, U> {
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.slots[self.index] {
+            Slot::Pending { ref mut promise, .. } => {
+                *promise = Some(task::current());
+                return Ok(Async::NotReady);
+            },
+            Slot::Done(_) | Slot::Dropped => (),
+            Slot::Free => unreachable!("RetryPromise polled after completion"),
+        }
+        self.done = true;
+        match shared.release(self.index) {
+            Slot::Done(outcome) => Ok(Async::Ready(outcome)),
+            _ => Err(Canceled),
+        }
+    }
+}
+
+impl<T, U> Drop for RetryPromise<T, U> {
     fn drop(&mut self) {
-        if let Some((val, cb)) = self.0.take() {
-            let _ = cb.send(Err((::Error::new_canceled(None::<::Error>), Some(val))));
+        if self.done {
+            return;
+        }
+        let mut shared = match self.shared.lock() {
+            Ok(shared) => shared,
+            Err(_poisoned) => return,
+        };
+        let free = match shared.slots[self.index] {
+            Slot::Pending { ref mut callback, ref mut promise_dropped, .. } => {
+                *promise_dropped = true;
+                if let Some(task) = callback.take() {
+                    task.notify();
+                }
+                false
+            },
+            _ => true,
+        };
+        if free {
+            // the outcome, if any, is dropped outside the lock
+            let slot = shared.release(self.index);
+            drop(shared);
+            drop(slot);
         }
     }
 }
 
-pub enum Callback<T, U> {
-    Retry(oneshot::Sender<Result<U, (::Error, Option<T>)>>),
-    NoRetry(oneshot::Sender<Result<U, ::Error>>),
+impl<T, U> ::std::fmt::Debug for RetryPromise<T, U> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.pad("RetryPromise")
+    }
+}
+
+/// Sends the result of a request, with the request itself if it failed
+/// before it was sent.
+pub struct Callback<T, U> {
+    shared: Arc<Mutex<Shared<T, U>>>,
+    index: usize,
+    sent: bool,
 }
 
 impl<T, U> Callback<T, U> {
     pub fn poll_cancel(&mut self) -> Poll<(), ()> {
-        match *self {
-            Callback::Retry(ref mut tx) => tx.poll_cancel(),
-            Callback::NoRetry(ref mut tx) => tx.poll_cancel(),
+        let mut shared = self.shared.lock().unwrap();
+        match shared.slots[self.index] {
+            Slot::Pending { promise_dropped: true, .. } => Ok(Async::Ready(())),
+            Slot::Pending { ref mut callback, .. } => {
+                *callback = Some(task::current());
+                Ok(Async::NotReady)
+            },
+            _ => unreachable!("Callback polled after it was sent"),
         }
     }
 
-    pub fn send(self, val: Result<U, (::Error, Option<T>)>) {
-        match self {
-            Callback::Retry(tx) => {
-                let _ = tx.send(val);
+    pub fn send(mut self, val: Outcome<T, U>) {
+        self.sent = true;
+        self.complete(Slot::Done(val));
+    }
+
+    fn complete(&mut self, outcome: Slot<T, U>) {
+        let mut shared = match self.shared.lock() {
+            Ok(shared) => shared,
+            Err(_poisoned) => return,
+        };
+        let promise = match shared.slots[self.index] {
+            Slot::Pending { promise_dropped: true, .. } => None,
+            Slot::Pending { ref mut promise, .. } => Some(promise.take()),
+            _ => unreachable!("Callback sent twice"),
+        };
+        match promise {
+            Some(task) => {
+                shared.slots[self.index] = outcome;
+                drop(shared);
+                if let Some(task) = task {
+                    task.notify();
+                }
             },
-            Callback::NoRetry(tx) => {
-                let _ = tx.send(val.map_err(|e| e.0));
-            }
+            None => {
+                // nobody wants the outcome, so it's dropped outside the lock
+                shared.release(self.index);
+                drop(shared);
+                drop(outcome);
+            },
+        }
+    }
+}
+
+impl<T, U> Drop for Callback<T, U> {
+    fn drop(&mut self) {
+        if !self.sent {
+            self.complete(Slot::Dropped);
         }
     }
 }
@@ -203,7 +443,7 @@ mod tests {
     #[cfg(feature = "nightly")]
     extern crate test;
 
-    use futures::{future, Future, Stream};
+    use futures::{future, Async, Future, Stream};
 
 
     #[derive(Debug)]
@@ -271,18 +511,68 @@ mod tests {
         let _ = tx.try_send(Custom(4)).unwrap_err();
     }
 
+    #[test]
+    fn callback_slots_are_reused() {
+        future::lazy(|| {
+            let (tx, mut rx) = super::channel::<Custom, ()>();
+            let mut tx = tx.unbound();
+
+            for i in 0..10 {
+                let promise = tx.try_send(Custom(i)).unwrap();
+                let (_, cb) = match rx.poll().expect("rx") {
+                    Async::Ready(Some(item)) => item,
+                    other => panic!("expected a request, got {:?}", other.map(|_| ())),
+                };
+                cb.send(Ok(()));
+                promise.wait().expect("fulfilled").expect("response");
+            }
+            assert_eq!(rx.shared.lock().unwrap().slots.len(), 1);
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn dropped_promise_cancels_callback() {
+        future::lazy(|| {
+            let (tx, mut rx) = super::channel::<Custom, ()>();
+            let mut tx = tx.unbound();
+
+            let promise = tx.try_send(Custom(1)).unwrap();
+            let mut cb = match rx.poll().expect("rx") {
+                Async::Ready(Some((_, cb))) => cb,
+                _ => panic!("expected a request"),
+            };
+            assert!(cb.poll_cancel().unwrap().is_not_ready());
+            drop(promise);
+            assert!(cb.poll_cancel().unwrap().is_ready());
+            cb.send(Ok(()));
+
+            // a callback dropped without sending fails its promise
+            let promise = tx.try_send(Custom(2)).unwrap();
+            match rx.poll().expect("rx") {
+                Async::Ready(Some((_, cb))) => drop(cb),
+                _ => panic!("expected a request"),
+            }
+            promise.wait().expect_err("callback was dropped");
+            assert_eq!(rx.shared.lock().unwrap().slots.len(), 1);
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
     #[cfg(feature = "nightly")]
     #[bench]
     fn giver_queue_throughput(b: &mut test::Bencher) {
-        let (mut tx, mut rx) = super::channel::<i32, ()>();
+        let (tx, mut rx) = super::channel::<i32, ()>();
+        let mut tx = tx.unbound();
 
         b.iter(move || {
             ::futures::future::lazy(|| {
-                let _ = tx.send(1).unwrap();
+                let _ = tx.try_send(1).unwrap();
                 loop {
-                    let async = rx.poll().unwrap();
-                    if async.is_not_ready() {
-                        break;
+                    match rx.poll().unwrap() {
+                        // frees the slot of the request for the next one
+                        Async::Ready(Some((_, cb))) => cb.send(Ok(())),
+                        _ => break,
                     }
                 }
 
@@ -0,0 +1,262 @@
+//! A client for the web, sending requests with the fetch API.
+//!
+//! On `wasm32-unknown-unknown`, there are no sockets to connect, and the
+//! browser sends requests itself. With the `fetch` feature, this module
+//! provides a [`Client`](Client) with the same `get` and `request` methods
+//! as the [`hyper::Client`](::Client), taking a `Request<Body>` and
+//! resolving to a `Response<Body>`, so client code can be shared between
+//! native targets and the web:
+//!
+//! ```no_run
+//! extern crate futures;
+//! extern crate hyper;
+//!
+//! use futures::{Future, Stream};
+//! #[cfg(target_arch = "wasm32")]
+//! use hyper::client::fetch::Client;
+//! #[cfg(not(target_arch = "wasm32"))]
+//! use hyper::Client;
+//!
+//! fn ip() -> impl Future<Item=hyper::Chunk, Error=hyper::Error> {
+//!     Client::new()
+//!         .get("http://httpbin.org/ip".parse().unwrap())
+//!         .and_then(|res| res.into_body().concat2())
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! The browser manages the connections, so none of the options of
+//! [`Client::builder`](::Client::builder) apply. The body of a request is
+//! read in full before it's sent, and the body of a response is read in
+//! full before the `Response` is yielded.
+//!
+//! The futures are not `Send`, and are run by the event loop of the page,
+//! for instance with `wasm_bindgen_futures::spawn_local` and
+//! [`Compat`](::compat::Compat).
+
+use std::error::Error as StdError;
+use std::fmt;
+
+use futures::{Future, Poll, Stream};
+use futures::future;
+use http::{Request, Response, Uri};
+use http::header::{HeaderName, HeaderValue};
+use js_sys::{self, Array, Promise, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen_futures::JsFuture;
+use web_sys;
+
+use body::{Body, Chunk};
+use compat::Compat01;
+use StatusCode;
+
+#[wasm_bindgen]
+extern "C" {
+    // The `fetch` of the global scope, be it a window, a worker, or Node.js.
+    #[wasm_bindgen(js_name = fetch)]
+    fn global_fetch(input: &web_sys::Request) -> Promise;
+}
+
+/// A Client sending requests with the fetch API.
+#[derive(Clone, Debug, Default)]
+pub struct Client {
+    _priv: (),
+}
+
+impl Client {
+    /// Create a new Client.
+    pub fn new() -> Client {
+        Client {
+            _priv: (),
+        }
+    }
+
+    /// Send a `GET` request to the supplied `Uri`.
+    pub fn get(&self, uri: Uri) -> ResponseFuture {
+        let mut req = Request::new(Body::empty());
+        *req.uri_mut() = uri;
+        self.request(req)
+    }
+
+    /// Send a constructed Request using this Client.
+    pub fn request(&self, req: Request<Body>) -> ResponseFuture {
+        let (head, body) = req.into_parts();
+        let fut = body
+            .concat2()
+            .map_err(::Error::new_user_body)
+            .and_then(move |body| {
+                let req = match to_js(head, body) {
+                    Ok(req) => req,
+                    Err(err) => return future::Either::A(future::err(err)),
+                };
+                future::Either::B(promise(global_fetch(&req))
+                    .map_err(::Error::new_connect)
+                    .and_then(from_js))
+            });
+        ResponseFuture {
+            inner: Box::new(fut),
+        }
+    }
+}
+
+/// A `Future` that will resolve to an HTTP Response.
+#[must_use = "futures do nothing unless polled"]
+pub struct ResponseFuture {
+    inner: Box<Future<Item=Response<Body>, Error=::Error>>,
+}
+
+impl fmt::Debug for ResponseFuture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Future<Response>")
+    }
+}
+
+impl Future for ResponseFuture {
+    type Item = Response<Body>;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.inner.poll()
+    }
+}
+
+/// Converts a request to the one of the fetch API.
+fn to_js(head: ::http::request::Parts, body: Chunk) -> ::Result<web_sys::Request> {
+    let headers = web_sys::Headers::new().map_err(|err| ::Error::new_connect(FetchError::new(err)))?;
+    for (name, value) in &head.headers {
+        let value = value.to_str().map_err(|_| ::Error::new_header())?;
+        headers
+            .append(name.as_str(), value)
+            .map_err(|_| ::Error::new_header())?;
+    }
+
+    let init = web_sys::RequestInit::new();
+    init.set_method(head.method.as_str());
+    init.set_headers(&headers);
+    if !body.is_empty() {
+        init.set_body(&Uint8Array::from(&body[..]));
+    }
+
+    web_sys::Request::new_with_str_and_init(&head.uri.to_string(), &init)
+        .map_err(|err| ::Error::new_connect(FetchError::new(err)))
+}
+
+/// Converts a response of the fetch API, once its body is read.
+fn from_js(res: JsValue) -> impl Future<Item=Response<Body>, Error=::Error> {
+    let res: web_sys::Response = match res.dyn_into() {
+        Ok(res) => res,
+        Err(_) => return future::Either::A(future::err(::Error::new_mismatched_response())),
+    };
+    let mut head = Response::new(());
+    match StatusCode::from_u16(res.status()) {
+        Ok(status) => *head.status_mut() = status,
+        Err(_) => return future::Either::A(future::err(::Error::new_status())),
+    }
+    if let Err(err) = copy_headers(&res.headers(), head.headers_mut()) {
+        return future::Either::A(future::err(err));
+    }
+
+    let body = match res.array_buffer() {
+        Ok(body) => body,
+        Err(err) => return future::Either::A(future::err(::Error::new_body(FetchError::new(err)))),
+    };
+    future::Either::B(promise(body)
+        .map_err(::Error::new_body)
+        .map(move |body| {
+            let body = Uint8Array::new(&body).to_vec();
+            head.map(|()| Body::from(body))
+        }))
+}
+
+/// Copies the headers of a fetch response, in the order it iterates them.
+fn copy_headers(from: &web_sys::Headers, to: &mut ::http::HeaderMap) -> ::Result<()> {
+    let entries = js_sys::try_iter(from)
+        .ok()
+        .and_then(|entries| entries)
+        .ok_or_else(::Error::new_header)?;
+    for entry in entries {
+        let entry: Array = entry.map_err(|_| ::Error::new_header())?.unchecked_into();
+        let name = entry.get(0).as_string().ok_or_else(::Error::new_header)?;
+        let value = entry.get(1).as_string().ok_or_else(::Error::new_header)?;
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| ::Error::new_header())?;
+        let value = HeaderValue::from_str(&value).map_err(|_| ::Error::new_header())?;
+        to.append(name, value);
+    }
+    Ok(())
+}
+
+/// A futures 0.1 `Future` of a JavaScript promise.
+fn promise(promise: Promise) -> impl Future<Item=JsValue, Error=FetchError> {
+    Compat01::new(JsFuture::from(promise)).map_err(FetchError::new)
+}
+
+/// A JavaScript exception, such as the `TypeError` of a failed fetch.
+///
+/// `JsValue`s can't leave their thread, so only their description is kept.
+#[derive(Debug)]
+struct FetchError(String);
+
+impl FetchError {
+    fn new(err: JsValue) -> FetchError {
+        match err.dyn_ref::<js_sys::Error>() {
+            Some(err) => FetchError(err.message().into()),
+            None => FetchError(format!("{:?}", err)),
+        }
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl StdError for FetchError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{Future, Stream};
+    use wasm_bindgen_test::wasm_bindgen_test;
+    use web_sys;
+
+    use compat::Compat;
+    use super::{from_js, Client};
+
+    #[wasm_bindgen_test(async)]
+    fn response_from_js() -> Compat<impl Future<Item=(), Error=::Error>> {
+        let headers = web_sys::Headers::new().unwrap();
+        headers.append("content-type", "text/plain").unwrap();
+        let init = web_sys::ResponseInit::new();
+        init.set_status(201);
+        init.set_headers(&headers);
+        let res = web_sys::Response::new_with_opt_str_and_init(Some("hello"), &init).unwrap();
+
+        let fut = from_js(res.into())
+            .and_then(|res| {
+                assert_eq!(res.status(), 201);
+                assert_eq!(res.headers()["content-type"], "text/plain");
+                res.into_body().concat2()
+            })
+            .map(|body| {
+                assert_eq!(body.as_ref(), b"hello");
+            });
+        Compat::new(fut)
+    }
+
+    #[wasm_bindgen_test(async)]
+    fn refused_is_connect_error() -> Compat<impl Future<Item=(), Error=()>> {
+        let fut = Client::new()
+            .get("http://127.0.0.1:1/".parse().unwrap())
+            .then(|res| {
+                let err = res.expect_err("fetch of a closed port");
+                assert!(err.is_connect(), "{:?}", err);
+                Ok(())
+            });
+        Compat::new(fut)
+    }
+}
@@ -0,0 +1,171 @@
+use std::sync::Arc;
+
+use http::{Method, Response, Uri};
+use http::request::Parts;
+
+use body::Body;
+
+/// Inspects and modifies the requests of a `Client`, and their responses.
+///
+/// Interceptors are added with
+/// [`Builder::intercept`](::client::Builder::intercept), and run in the
+/// order they were added for requests, and in the reverse order for
+/// responses, such as:
+///
+/// ```
+/// use hyper::{Body, Method, Response, Uri};
+/// use hyper::client::Interceptor;
+/// use hyper::header::{HeaderValue, AUTHORIZATION};
+///
+/// struct Auth(HeaderValue);
+///
+/// impl Interceptor for Auth {
+///     fn request(&self, req: &mut http::request::Parts) -> Option<Response<Body>> {
+///         req.headers.insert(AUTHORIZATION, self.0.clone());
+///         None
+///     }
+/// }
+///
+/// struct Log;
+///
+/// impl Interceptor for Log {
+///     fn response(&self, method: &Method, uri: &Uri, res: &mut Response<Body>) {
+///         println!("{} {}: {}", method, uri, res.status());
+///     }
+/// }
+/// # extern crate http;
+/// # extern crate hyper;
+///
+/// # fn main() {
+/// let client = hyper::Client::builder()
+///     .intercept(Auth(HeaderValue::from_static("Bearer token")))
+///     .intercept(Log)
+///     .build_http::<Body>();
+/// # drop(client);
+/// # }
+/// ```
+///
+/// An `Arc<I>` is an `Interceptor` as well.
+pub trait Interceptor: Send + Sync + 'static {
+    /// Called with the head of a request, before it's sent.
+    ///
+    /// Returning a `Response` answers the request with it instead, without
+    /// sending it, nor calling the interceptors added after this one. The
+    /// ones added before are still given the response.
+    ///
+    /// The default does nothing.
+    fn request(&self, _req: &mut Parts) -> Option<Response<Body>> {
+        None
+    }
+
+    /// Called with the response to a request, with the method and `Uri` the
+    /// request was sent with.
+    ///
+    /// The default does nothing.
+    fn response(&self, _method: &Method, _uri: &Uri, _res: &mut Response<Body>) {}
+}
+
+impl<I: Interceptor + ?Sized> Interceptor for Arc<I> {
+    fn request(&self, req: &mut Parts) -> Option<Response<Body>> {
+        (**self).request(req)
+    }
+
+    fn response(&self, method: &Method, uri: &Uri, res: &mut Response<Body>) {
+        (**self).response(method, uri, res)
+    }
+}
+
+/// The interceptors of a `Client`, in the order they were added.
+#[derive(Clone, Default)]
+pub(crate) struct Interceptors {
+    chain: Arc<Vec<Arc<Interceptor>>>,
+}
+
+/// What came of giving a request to the interceptors.
+pub(crate) enum Intercepted {
+    /// The request is to be sent, and its response given to this many of
+    /// the interceptors.
+    Send(usize),
+    /// The request was answered by one of them, and the response was
+    /// already given to the ones before it.
+    Respond(Response<Body>),
+}
+
+impl Interceptors {
+    pub(crate) fn push(&mut self, interceptor: Arc<Interceptor>) {
+        Arc::make_mut(&mut self.chain).push(interceptor);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.chain.is_empty()
+    }
+
+    pub(crate) fn request(&self, req: &mut Parts) -> Intercepted {
+        for (i, interceptor) in self.chain.iter().enumerate() {
+            if let Some(mut res) = interceptor.request(req) {
+                self.response(i, &req.method, &req.uri, &mut res);
+                return Intercepted::Respond(res);
+            }
+        }
+        Intercepted::Send(self.chain.len())
+    }
+
+    /// Gives a response to the first `count` interceptors, last first.
+    pub(crate) fn response(&self, count: usize, method: &Method, uri: &Uri, res: &mut Response<Body>) {
+        for interceptor in self.chain[..count].iter().rev() {
+            interceptor.response(method, uri, res);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use http::{Method, Request, Response, Uri};
+    use http::request::Parts;
+
+    use body::Body;
+    use super::{Intercepted, Interceptor, Interceptors};
+
+    struct Named {
+        name: &'static str,
+        answer: bool,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Interceptor for Named {
+        fn request(&self, _req: &mut Parts) -> Option<Response<Body>> {
+            self.log.lock().unwrap().push(format!("request {}", self.name));
+            if self.answer {
+                Some(Response::new(Body::empty()))
+            } else {
+                None
+            }
+        }
+
+        fn response(&self, _method: &Method, _uri: &Uri, _res: &mut Response<Body>) {
+            self.log.lock().unwrap().push(format!("response {}", self.name));
+        }
+    }
+
+    #[test]
+    fn chain_order_and_short_circuit() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut interceptors = Interceptors::default();
+        for &(name, answer) in &[("a", false), ("b", true), ("c", false)] {
+            interceptors.push(Arc::new(Named {
+                name: name,
+                answer: answer,
+                log: log.clone(),
+            }));
+        }
+
+        let (mut parts, ()) = Request::new(()).into_parts();
+        match interceptors.request(&mut parts) {
+            Intercepted::Respond(_) => (),
+            Intercepted::Send(_) => panic!("b answers the request"),
+        }
+        assert_eq!(*log.lock().unwrap(), ["request a", "request b", "response a"]);
+    }
+}
@@ -75,24 +75,44 @@
 use std::fmt;
 use std::io;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::time::Duration;
 
+use bytes::Bytes;
 use futures::{Async, Future, Poll};
 use futures::future::{self, Either, Executor};
 use futures::sync::oneshot;
 use http::{Method, Request, Response, Uri, Version};
-use http::header::{Entry, HeaderValue, HOST};
+use http::header::{Entry, HeaderValue, CONNECTION, HOST};
 use http::uri::Scheme;
 
 use body::{Body, Payload};
 use common::Exec;
-use self::connect::{Connect, Destination};
+use exec::{Spawner, TaskKind};
+use ext::{ConnectionInfo, RequestTarget, Timings};
+use headers;
+use instrument::{ClientCounters, ClientStats, FrameObserver, Instrument, Instrumentation, PoolEvent, Tap};
+use proto;
+use service::Service;
+use clock::{Clock, Time};
+use self::cancel::CancelWatch;
+use self::capacity::{Capacity, InFlight};
+use self::intercept::{Intercepted, Interceptors};
+use self::connect::{Connect, ConnectTimes, Destination};
 use self::pool::{Pool, Poolable, Reservation};
 
+pub use self::affinity::Affinity;
+pub use self::cancel::CancelHandle;
+pub use self::intercept::Interceptor;
 #[cfg(feature = "runtime")] pub use self::connect::HttpConnector;
 
+mod affinity;
+mod capacity;
+pub(crate) mod cancel;
 pub mod conn;
 pub mod connect;
+#[cfg(all(feature = "fetch", target_arch = "wasm32"))] pub mod fetch;
+mod intercept;
 pub(crate) mod dispatch;
 #[cfg(feature = "runtime")] mod dns;
 mod pool;
@@ -102,10 +122,33 @@ mod tests;
 /// A Client to make outgoing HTTP requests.
 pub struct Client<C, B = Body> {
     connector: Arc<C>,
+    // the id of the next connection
+    conn_ids: Arc<AtomicUsize>,
     executor: Exec,
+    h1_absolute_form: bool,
     h1_writev: bool,
+    h1_force_writev: bool,
     h1_title_case_headers: bool,
+    h1_preserve_header_case: bool,
+    h1_record_header_case: bool,
+    h1_record_header_order: bool,
+    h1_obs_fold: bool,
+    h1_spaces_after_header_name: bool,
+    h1_missing_reason: bool,
+    h1_lf_line_endings: bool,
+    h1_framing: ::proxy::FramingPolicy,
+    h1_max_uri_len: Option<usize>,
+    h1_strict_content_length: bool,
+    h1_buf_sizes: proto::h1::BufSizes,
+    h2_body_chunks: proto::h2::BodyChunks,
+    h2_spawn_streams: bool,
     pool: Pool<PoolClient<B>>,
+    cancel_dropped_requests: bool,
+    capacity: Arc<Capacity>,
+    counters: Arc<ClientCounters>,
+    instrument: Option<Instrument>,
+    interceptors: Interceptors,
+    record_timings: bool,
     retry_canceled_requests: bool,
     set_host: bool,
     ver: Ver,
@@ -186,8 +229,53 @@ where C: Connect + Sync + 'static,
         self.request(req)
     }
 
+    /// Returns the counters of this `Client`, and of its clones.
+    ///
+    /// This is a snapshot of cheap counters that are always kept, such as
+    /// the requests sent and the classes of their responses, for services
+    /// that want a few numbers without an
+    /// [`Instrumentation`](::instrument::Instrumentation).
+    pub fn stats(&self) -> ClientStats {
+        let bytes = self.instrument.as_ref().map(Instrument::stats).unwrap_or_default();
+        self.counters.snapshot(&bytes)
+    }
+
+    /// Polls whether this `Client` can take another request.
+    ///
+    /// This is `NotReady` while the `Client`, with its clones, has as many
+    /// requests in flight as set with
+    /// [`Builder::max_in_flight_requests`](Builder::max_in_flight_requests),
+    /// and the current task is notified once one of them finishes. A request
+    /// is in flight until its response head was received, or it failed.
+    ///
+    /// Without a limit, this is always `Ready`. Requests are never refused
+    /// because of it: `request` still queues them for a connection.
+    pub fn poll_ready(&mut self) -> Poll<(), ::Error> {
+        self.capacity.poll_ready()
+    }
+
     /// Send a constructed Request using this Client.
-    pub fn request(&self, mut req: Request<B>) -> ResponseFuture {
+    pub fn request(&self, req: Request<B>) -> ResponseFuture {
+        if self.interceptors.is_empty() {
+            return self.send(req);
+        }
+        let (mut parts, body) = req.into_parts();
+        let count = match self.interceptors.request(&mut parts) {
+            Intercepted::Send(count) => count,
+            Intercepted::Respond(res) => return ResponseFuture::new(Box::new(future::ok(res))),
+        };
+        let method = parts.method.clone();
+        let uri = parts.uri.clone();
+        let mut fut = self.send(Request::from_parts(parts, body));
+        let interceptors = self.interceptors.clone();
+        fut.inner = Box::new(fut.inner.map(move |mut res| {
+            interceptors.response(count, &method, &uri, &mut res);
+            res
+        }));
+        fut
+    }
+
+    fn send(&self, mut req: Request<B>) -> ResponseFuture {
         match req.version() {
             Version::HTTP_10 |
             Version::HTTP_11 => (),
@@ -231,6 +319,23 @@ where C: Connect + Sync + 'static,
             }
         }
 
+        // HTTP/1.0 servers close the connection unless asked not to.
+        if req.version() == Version::HTTP_10 && self.ver == Ver::Http1 && self.pool.is_enabled() {
+            if let Entry::Vacant(entry) = req.headers_mut().entry(CONNECTION).expect("CONNECTION is always valid header name") {
+                entry.insert(headers::keep_alive_value());
+            }
+        }
+
+        self.counters.request();
+        let in_flight = Capacity::acquire(&self.capacity);
+        let cancel = CancelHandle::new();
+        req.extensions_mut().insert(CancelWatch(cancel.clone()));
+        if self.record_timings && req.extensions().get::<Timings>().is_none() {
+            req.extensions_mut().insert(Timings::new());
+        }
+        if let Some(timings) = req.extensions().get::<Timings>() {
+            timings.set_queued();
+        }
 
         let client = self.clone();
         let uri = req.uri().clone();
@@ -238,39 +343,115 @@ where C: Connect + Sync + 'static,
             client: client,
             future: self.send_request(req, &domain),
             domain: domain,
+            in_flight: Some(in_flight),
             uri: uri,
         };
-        ResponseFuture::new(Box::new(fut))
+        let inner: Box<Future<Item=Response<Body>, Error=::Error> + Send> = if self.cancel_dropped_requests {
+            Box::new(fut)
+        } else {
+            // Spawned on the first poll, so the request carries on if this
+            // future is dropped.
+            let executor = self.executor.clone();
+            Box::new(future::lazy(move || {
+                let (tx, rx) = oneshot::channel();
+                executor.execute(TaskKind::Request, fut.then(move |res| {
+                    let _ = tx.send(res);
+                    Ok(())
+                }));
+                rx.then(|res| match res {
+                    Ok(res) => res,
+                    Err(_canceled) => Err(::Error::new_canceled(Some("request task was dropped"))),
+                })
+            }))
+        };
+        ResponseFuture {
+            inner: inner,
+            cancel: cancel,
+        }
     }
 
     //TODO: replace with `impl Future` when stable
     fn send_request(&self, mut req: Request<B>, domain: &str) -> Box<Future<Item=Response<Body>, Error=ClientError<B>> + Send> {
         let url = req.uri().clone();
         let ver = self.ver;
+        let h1_absolute_form = self.h1_absolute_form;
         let pool_key = (Arc::new(domain.to_string()), self.ver);
+        let affinity = req.extensions().get::<Affinity>().cloned();
+        let pinned = match affinity.as_ref().and_then(Affinity::bound) {
+            Some((ref key, _)) if *key != pool_key.0 => {
+                return Box::new(future::err(ClientError::Normal(::Error::new_affinity_lost())));
+            },
+            Some((_, id)) => Some(self.pool.checkout_pinned(pool_key.clone(), id)),
+            None => None,
+        };
+        let pin_key = pool_key.clone();
         let checkout = self.pool.checkout(pool_key.clone());
+        let time = self.pool.time().clone();
+        let checkout_start = time.now();
+        let info_key = pool_key.0.clone();
+        // whether `connect` is establishing a connection
+        let started = Arc::new(AtomicBool::new(false));
         let connect = {
             let executor = self.executor.clone();
             let pool = self.pool.clone();
             let h1_writev = self.h1_writev;
+            let h1_force_writev = self.h1_force_writev;
             let h1_title_case_headers = self.h1_title_case_headers;
+            let h1_preserve_header_case = self.h1_preserve_header_case;
+            let h1_record_header_case = self.h1_record_header_case;
+            let h1_record_header_order = self.h1_record_header_order;
+            let h1_obs_fold = self.h1_obs_fold;
+            let h1_spaces_after_header_name = self.h1_spaces_after_header_name;
+            let h1_missing_reason = self.h1_missing_reason;
+            let h1_lf_line_endings = self.h1_lf_line_endings;
+            let h1_framing = self.h1_framing;
+            let h1_max_uri_len = self.h1_max_uri_len;
+            let h1_strict_content_length = self.h1_strict_content_length;
+            let h1_buf_sizes = self.h1_buf_sizes;
+            let h2_body_chunks = self.h2_body_chunks;
+            let h2_spawn_streams = self.h2_spawn_streams;
+            let cancel_dropped_requests = self.cancel_dropped_requests;
+            let instrument = self.instrument.clone();
+            let counters = self.counters.clone();
+            let conn_ids = self.conn_ids.clone();
             let connector = self.connector.clone();
             let dst = Destination {
                 uri: url,
             };
+            let started = started.clone();
             future::lazy(move || {
                 if let Some(connecting) = pool.connecting(&pool_key) {
+                    started.store(true, Ordering::Relaxed);
                     Either::A(connector.connect(dst)
-                        .map_err(::Error::new_connect)
+                        .map_err(move |err| {
+                            counters.connect_error();
+                            ::Error::new_connect(err)
+                        })
                         .and_then(move |(io, connected)| {
                             conn::Builder::new()
                                 .exec(executor.clone())
                                 .h1_writev(h1_writev)
+                                .h1_force_writev(h1_force_writev)
                                 .h1_title_case_headers(h1_title_case_headers)
+                                .h1_preserve_header_case(h1_preserve_header_case)
+                                .h1_record_header_case(h1_record_header_case)
+                                .h1_record_header_order(h1_record_header_order)
+                                .h1_obs_fold(h1_obs_fold)
+                                .h1_spaces_after_header_name(h1_spaces_after_header_name)
+                                .h1_missing_reason(h1_missing_reason)
+                                .h1_lf_line_endings(h1_lf_line_endings)
+                                .h1_framing(h1_framing)
+                                .h1_max_uri_len(h1_max_uri_len)
+                                .h1_strict_content_length(h1_strict_content_length)
+                                .h1_buf_sizes(h1_buf_sizes)
+                                .h2_body_chunks(h2_body_chunks)
+                                .http2_spawn_streams(h2_spawn_streams)
+                                .cancel_dropped_requests(cancel_dropped_requests)
+                                .instrument(instrument)
                                 .http2_only(pool_key.1 == Ver::Http2)
                                 .handshake_no_upgrades(io)
                                 .and_then(move |(tx, conn)| {
-                                    executor.execute(conn.map_err(|e| {
+                                    executor.execute(TaskKind::Connection, conn.map_err(|e| {
                                         debug!("client connection error: {}", e)
                                     }));
 
@@ -280,6 +461,9 @@ where C: Connect + Sync + 'static,
                                 })
                                 .map(move |tx| {
                                     pool.pooled(connecting, PoolClient {
+                                        id: conn_ids.fetch_add(1, Ordering::Relaxed),
+                                        alpn: connected.alpn,
+                                        connect_times: connected.times,
                                         is_proxied: connected.is_proxied,
                                         tx: match ver {
                                             Ver::Http1 => PoolTx::Http1(tx),
@@ -295,8 +479,24 @@ where C: Connect + Sync + 'static,
             })
         };
 
+        let fill = self.pool.clone();
+        let fill_key = (info_key.clone(), ver);
+        let fill_exec = self.executor.clone();
         let race = checkout.select(connect)
-            .map(|(pooled, _work)| pooled)
+            .map(move |(pooled, work)| {
+                // With more HTTP/2 connections to the host, the idle one
+                // is used right away, while another is established for the
+                // pool, or keeps being established if it started already.
+                if ver == Ver::Http2 && (started.load(Ordering::Relaxed) || fill.wants_connection(&fill_key)) {
+                    fill_exec.execute(TaskKind::Pool, work.then(|res| {
+                        if let Err(err) = res {
+                            debug!("client connection error: {}", err);
+                        }
+                        Ok(())
+                    }));
+                }
+                pooled
+            })
             .or_else(|(e, other)| {
                 // Either checkout or connect could get canceled:
                 //
@@ -313,12 +513,46 @@ where C: Connect + Sync + 'static,
                     Either::B(future::err(ClientError::Normal(e)))
                 }
             });
+        // Requests pinned to a connection only wait for it.
+        let race = match pinned {
+            Some(checkout) => Either::A(checkout.map_err(ClientError::Normal)),
+            None => Either::B(race),
+        };
 
         let executor = self.executor.clone();
+        let instrument = self.instrument.clone();
+        let pool = self.pool.clone();
         let resp = race.and_then(move |mut pooled| {
+            if let Some(ref affinity) = affinity {
+                affinity.bind(&pin_key.0, pooled.id, || pool.pin(&pin_key, pooled.id));
+            }
             let conn_reused = pooled.is_reused();
+            if let Some(ref instrument) = instrument {
+                instrument.pool_checkout(conn_reused);
+                instrument.pool_event(PoolEvent::CheckoutWaited(&info_key, time.now().duration_since(checkout_start)));
+            }
+            let info = ConnectionInfo {
+                reused: conn_reused,
+                pool_key: info_key,
+                id: pooled.id,
+                version: if ver == Ver::Http2 { Version::HTTP_2 } else { req.version() },
+                alpn: pooled.alpn.clone(),
+                is_proxied: pooled.is_proxied,
+            };
+            if let Some(timings) = req.extensions().get::<Timings>() {
+                if !conn_reused {
+                    let times = pooled.connect_times;
+                    timings.set_connect(times.dns_resolved, times.connected, times.tls_handshaken);
+                }
+                timings.set_connection_assigned();
+            }
             if ver == Ver::Http1 {
-                set_relative_uri(req.uri_mut(), pooled.is_proxied);
+                let absolute_form = match req.extensions().get::<RequestTarget>() {
+                    Some(&RequestTarget::Origin) => false,
+                    Some(&RequestTarget::Absolute) => true,
+                    None => pooled.is_proxied || h1_absolute_form,
+                };
+                set_relative_uri(req.uri_mut(), absolute_form);
             }
             let fut = pooled.send_request_retryable(req);
 
@@ -342,6 +576,10 @@ where C: Connect + Sync + 'static,
                         } else {
                             ClientError::Normal(err)
                         }
+                    })
+                    .map(move |mut res| {
+                        res.extensions_mut().insert(info);
+                        res
                     });
                 Either::A(fut)
             } else {
@@ -358,6 +596,8 @@ where C: Connect + Sync + 'static,
                         }
                     })
                     .and_then(move |mut res| {
+                        res.extensions_mut().insert(info);
+
                         // If pooled is HTTP/2, we can toss this reference immediately.
                         //
                         // when pooled is dropped, it will try to insert back into the
@@ -374,6 +614,7 @@ where C: Connect + Sync + 'static,
                             let (delayed_tx, delayed_rx) = oneshot::channel();
                             res.body_mut().delayed_eof(delayed_rx);
                             executor.execute(
+                                TaskKind::Pool,
                                 future::poll_fn(move || {
                                     pooled.poll_ready()
                                 })
@@ -388,6 +629,7 @@ where C: Connect + Sync + 'static,
                             // There's no body to delay, but the connection isn't
                             // ready yet. Only re-insert when it's ready
                             executor.execute(
+                                TaskKind::Pool,
                                 future::poll_fn(move || {
                                     pooled.poll_ready()
                                 })
@@ -404,14 +646,57 @@ where C: Connect + Sync + 'static,
     }
 }
 
+impl<C, B> Service for Client<C, B>
+where C: Connect + Sync + 'static,
+      C::Transport: 'static,
+      C::Future: 'static,
+      B: Payload + Send + 'static,
+      B::Data: Send,
+{
+    type ReqBody = B;
+    type ResBody = Body;
+    type Error = ::Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self) -> Poll<(), ::Error> {
+        Client::poll_ready(self)
+    }
+
+    fn call(&mut self, req: Request<B>) -> ResponseFuture {
+        self.request(req)
+    }
+}
+
 impl<C, B> Clone for Client<C, B> {
     fn clone(&self) -> Client<C, B> {
         Client {
             connector: self.connector.clone(),
+            conn_ids: self.conn_ids.clone(),
             executor: self.executor.clone(),
+            h1_absolute_form: self.h1_absolute_form,
             h1_writev: self.h1_writev,
+            h1_force_writev: self.h1_force_writev,
             h1_title_case_headers: self.h1_title_case_headers,
+            h1_preserve_header_case: self.h1_preserve_header_case,
+            h1_record_header_case: self.h1_record_header_case,
+            h1_record_header_order: self.h1_record_header_order,
+            h1_obs_fold: self.h1_obs_fold,
+            h1_spaces_after_header_name: self.h1_spaces_after_header_name,
+            h1_missing_reason: self.h1_missing_reason,
+            h1_lf_line_endings: self.h1_lf_line_endings,
+            h1_framing: self.h1_framing,
+            h1_max_uri_len: self.h1_max_uri_len,
+            h1_strict_content_length: self.h1_strict_content_length,
+            h1_buf_sizes: self.h1_buf_sizes,
+            h2_body_chunks: self.h2_body_chunks,
+            h2_spawn_streams: self.h2_spawn_streams,
             pool: self.pool.clone(),
+            cancel_dropped_requests: self.cancel_dropped_requests,
+            capacity: self.capacity.clone(),
+            counters: self.counters.clone(),
+            instrument: self.instrument.clone(),
+            interceptors: self.interceptors.clone(),
+            record_timings: self.record_timings,
             retry_canceled_requests: self.retry_canceled_requests,
             set_host: self.set_host,
             ver: self.ver,
@@ -430,14 +715,22 @@ impl<C, B> fmt::Debug for Client<C, B> {
 #[must_use = "futures do nothing unless polled"]
 pub struct ResponseFuture {
     inner: Box<Future<Item=Response<Body>, Error=::Error> + Send>,
+    cancel: CancelHandle,
 }
 
 impl ResponseFuture {
     fn new(fut: Box<Future<Item=Response<Body>, Error=::Error> + Send>) -> Self {
         Self {
             inner: fut,
+            cancel: CancelHandle::new(),
         }
     }
+
+    /// Returns a handle that can cancel this request, even once this
+    /// future has yielded the `Response`.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
 }
 
 impl fmt::Debug for ResponseFuture {
@@ -451,6 +744,9 @@ impl Future for ResponseFuture {
     type Error = ::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.cancel.poll_canceled() {
+            return Err(::Error::new_request_canceled());
+        }
         self.inner.poll()
     }
 }
@@ -459,6 +755,7 @@ struct RetryableSendRequest<C, B> {
     client: Client<C, B>,
     domain: String,
     future: Box<Future<Item=Response<Body>, Error=ClientError<B>> + Send>,
+    in_flight: Option<InFlight>,
     uri: Uri,
 }
 
@@ -475,9 +772,16 @@ where
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         loop {
             match self.future.poll() {
-                Ok(Async::Ready(resp)) => return Ok(Async::Ready(resp)),
+                Ok(Async::Ready(resp)) => {
+                    self.in_flight.take();
+                    self.client.counters.response(resp.status());
+                    return Ok(Async::Ready(resp));
+                },
                 Ok(Async::NotReady) => return Ok(Async::NotReady),
-                Err(ClientError::Normal(err)) => return Err(err),
+                Err(ClientError::Normal(err)) => {
+                    self.in_flight.take();
+                    return Err(err);
+                },
                 Err(ClientError::Canceled {
                     connection_reused,
                     mut req,
@@ -486,10 +790,13 @@ where
                     if !self.client.retry_canceled_requests || !connection_reused {
                         // if client disabled, don't retry
                         // a fresh connection means we definitely can't retry
-                        return Err(reason);
+                        *req.uri_mut() = self.uri.clone();
+                        self.in_flight.take();
+                        return Err(reason.with_unsent_request(req));
                     }
 
                     trace!("unstarted request canceled, trying again (reason={:?})", reason);
+                    self.client.counters.retry();
                     *req.uri_mut() = self.uri.clone();
                     self.future = self.client.send_request(req, &self.domain);
                 }
@@ -499,6 +806,9 @@ where
 }
 
 struct PoolClient<B> {
+    id: usize,
+    alpn: Option<Bytes>,
+    connect_times: ConnectTimes,
     is_proxied: bool,
     tx: PoolTx<B>,
 }
@@ -555,20 +865,47 @@ where
         }
     }
 
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn in_flight(&self) -> usize {
+        match self.tx {
+            PoolTx::Http1(_) => 0,
+            PoolTx::Http2(ref tx) => tx.in_flight(),
+        }
+    }
+
+    fn is_saturated(&self) -> bool {
+        match self.tx {
+            PoolTx::Http1(_) => false,
+            PoolTx::Http2(ref tx) => tx.is_saturated(),
+        }
+    }
+
     fn reserve(self) -> Reservation<Self> {
         match self.tx {
             PoolTx::Http1(tx) => {
                 Reservation::Unique(PoolClient {
+                    id: self.id,
+                    alpn: self.alpn,
+                    connect_times: self.connect_times,
                     is_proxied: self.is_proxied,
                     tx: PoolTx::Http1(tx),
                 })
             },
             PoolTx::Http2(tx) => {
                 let b = PoolClient {
+                    id: self.id,
+                    alpn: self.alpn.clone(),
+                    connect_times: self.connect_times,
                     is_proxied: self.is_proxied,
                     tx: PoolTx::Http2(tx.clone()),
                 };
                 let a = PoolClient {
+                    id: self.id,
+                    alpn: self.alpn.clone(),
+                    connect_times: self.connect_times,
                     is_proxied: self.is_proxied,
                     tx: PoolTx::Http2(tx),
                 };
@@ -616,12 +953,35 @@ fn set_relative_uri(uri: &mut Uri, is_proxied: bool) {
 pub struct Builder {
     //connect_timeout: Duration,
     exec: Exec,
+    time: Time,
     keep_alive: bool,
     keep_alive_timeout: Option<Duration>,
+    h1_absolute_form: bool,
     h1_writev: bool,
+    h1_force_writev: bool,
     h1_title_case_headers: bool,
+    h1_preserve_header_case: bool,
+    h1_record_header_case: bool,
+    h1_record_header_order: bool,
+    h1_obs_fold: bool,
+    h1_spaces_after_header_name: bool,
+    h1_missing_reason: bool,
+    h1_lf_line_endings: bool,
+    h1_framing: ::proxy::FramingPolicy,
+    h1_max_uri_len: Option<usize>,
+    h1_strict_content_length: bool,
+    h1_buf_sizes: proto::h1::BufSizes,
+    h2_body_chunks: proto::h2::BodyChunks,
+    h2_spawn_streams: bool,
+    h2_connections: usize,
+    h2_max_connections: Option<usize>,
     //TODO: make use of max_idle config
     max_idle: usize,
+    max_in_flight: Option<usize>,
+    cancel_dropped_requests: bool,
+    instrument: Option<Instrument>,
+    interceptors: Interceptors,
+    record_timings: bool,
     retry_canceled_requests: bool,
     set_host: bool,
     ver: Ver,
@@ -631,11 +991,34 @@ impl Default for Builder {
     fn default() -> Self {
         Self {
             exec: Exec::Default,
+            time: Time::default(),
             keep_alive: true,
             keep_alive_timeout: Some(Duration::from_secs(90)),
+            h1_absolute_form: false,
             h1_writev: true,
+            h1_force_writev: false,
             h1_title_case_headers: false,
+            h1_preserve_header_case: false,
+            h1_record_header_case: false,
+            h1_record_header_order: false,
+            h1_obs_fold: false,
+            h1_spaces_after_header_name: false,
+            h1_missing_reason: true,
+            h1_lf_line_endings: true,
+            h1_framing: ::proxy::FramingPolicy::Normalize,
+            h1_max_uri_len: None,
+            h1_strict_content_length: false,
+            h1_buf_sizes: proto::h1::BufSizes::default(),
+            h2_body_chunks: proto::h2::BodyChunks::default(),
+            h2_spawn_streams: true,
+            h2_connections: 1,
+            h2_max_connections: None,
             max_idle: 5,
+            max_in_flight: None,
+            cancel_dropped_requests: true,
+            instrument: None,
+            interceptors: Interceptors::default(),
+            record_timings: false,
             retry_canceled_requests: true,
             set_host: true,
             ver: Ver::Http1,
@@ -667,6 +1050,23 @@ impl Builder {
         self
     }
 
+    /// Set whether HTTP/1 requests are sent with an absolute-form target.
+    ///
+    /// When the connector always connects to an HTTP forward proxy, the
+    /// request-line of plaintext requests must contain the full URI, such
+    /// as `GET http://example.com/ HTTP/1.1`. Connectors that only proxy
+    /// some connections can instead use [`Connected::proxy`](connect::Connected::proxy).
+    ///
+    /// Requests to `https` URIs are always sent in origin-form, since they
+    /// would be tunneled through the proxy. A request can pick its form with
+    /// a [`RequestTarget`](::ext::RequestTarget) in its extensions.
+    ///
+    /// Default is `false`.
+    pub fn http1_absolute_form(&mut self, val: bool) -> &mut Self {
+        self.h1_absolute_form = val;
+        self
+    }
+
     /// Set whether HTTP/1 connections should try to use vectored writes,
     /// or always flatten into a single buffer.
     ///
@@ -681,6 +1081,20 @@ impl Builder {
         self
     }
 
+    /// Set whether HTTP/1 connections should always queue buffers for
+    /// vectored writes.
+    ///
+    /// By default, that's only done once the connector's transport is seen
+    /// using vectored writes, and small buffers are copied into one even
+    /// then. This has no effect if `http1_writev` is `false`.
+    ///
+    /// Default is `false`.
+    #[inline]
+    pub fn http1_force_writev(&mut self, val: bool) -> &mut Self {
+        self.h1_force_writev = val;
+        self
+    }
+
     /// Set whether HTTP/1 connections will write header names as title case at
     /// the socket level.
     ///
@@ -692,6 +1106,212 @@ impl Builder {
         self
     }
 
+    /// Set whether HTTP/1 connections will preserve the case of header names.
+    ///
+    /// When enabled, the exact casing of header names in each response is
+    /// recorded in a [`HeaderCaseMap`](::ext::HeaderCaseMap) extension, and
+    /// requests carrying a `HeaderCaseMap` in their extensions are written
+    /// with the casing it contains. This is for peers that wrongly match
+    /// header names case-sensitively.
+    ///
+    /// Note that this setting does not affect HTTP/2.
+    ///
+    /// Default is false.
+    pub fn http1_preserve_header_case(&mut self, val: bool) -> &mut Self {
+        self.h1_preserve_header_case = val;
+        self
+    }
+
+    /// Set whether HTTP/1 connections will record the case of header names.
+    ///
+    /// When enabled, the exact casing of header names in each response is
+    /// recorded in a [`HeaderCaseMap`](::ext::HeaderCaseMap) extension, but
+    /// unlike [`http1_preserve_header_case`](Builder::http1_preserve_header_case),
+    /// requests are still written with lowercase names.
+    ///
+    /// Note that this setting does not affect HTTP/2.
+    ///
+    /// Default is false.
+    pub fn http1_record_header_case(&mut self, val: bool) -> &mut Self {
+        self.h1_record_header_case = val;
+        self
+    }
+
+    /// Set whether HTTP/1 connections will record the order of headers.
+    ///
+    /// When enabled, the order of the header fields in each response is
+    /// recorded in a [`HeaderOrder`](::ext::HeaderOrder) extension.
+    ///
+    /// Requests carrying a `HeaderOrder` are always written in that order,
+    /// whether or not this is enabled.
+    ///
+    /// Default is false.
+    pub fn http1_record_header_order(&mut self, val: bool) -> &mut Self {
+        self.h1_record_header_order = val;
+        self
+    }
+
+    /// Set whether HTTP/1 connections will accept obsolete line folding
+    /// in the header values of responses.
+    ///
+    /// Some old servers continue a header value on the next line if it
+    /// starts with a space or tab. This was deprecated by [RFC 7230], and
+    /// such responses are normally rejected. When enabled, the line breaks
+    /// are replaced with spaces, joining the lines into one value.
+    ///
+    /// [RFC 7230]: https://tools.ietf.org/html/rfc7230#section-3.2.4
+    ///
+    /// Default is false.
+    pub fn http1_allow_obsolete_multiline_headers_in_responses(&mut self, val: bool) -> &mut Self {
+        self.h1_obs_fold = val;
+        self
+    }
+
+    /// Set whether HTTP/1 connections will accept spaces between header
+    /// names and the colon in responses.
+    ///
+    /// This isn't allowed by [RFC 7230], and such responses are normally
+    /// rejected. When enabled, the spaces are ignored.
+    ///
+    /// [RFC 7230]: https://tools.ietf.org/html/rfc7230#section-3.2.4
+    ///
+    /// Default is false.
+    pub fn http1_allow_spaces_after_header_name_in_responses(&mut self, val: bool) -> &mut Self {
+        self.h1_spaces_after_header_name = val;
+        self
+    }
+
+    /// Set whether HTTP/1 connections will accept responses with no
+    /// reason-phrase, such as `HTTP/1.1 200`.
+    ///
+    /// The reason-phrase may be empty, but the space before it is required
+    /// by [RFC 7230]. Setting this to false rejects responses missing it.
+    ///
+    /// [RFC 7230]: https://tools.ietf.org/html/rfc7230#section-3.1.2
+    ///
+    /// Default is true.
+    pub fn http1_allow_missing_reason_phrase(&mut self, val: bool) -> &mut Self {
+        self.h1_missing_reason = val;
+        self
+    }
+
+    /// Set whether HTTP/1 connections will accept responses whose lines
+    /// end with a bare LF instead of CRLF.
+    ///
+    /// [RFC 7230] allows recipients to accept these. Setting this to false
+    /// rejects them.
+    ///
+    /// [RFC 7230]: https://tools.ietf.org/html/rfc7230#section-3.5
+    ///
+    /// Default is true.
+    pub fn http1_allow_lf_line_endings(&mut self, val: bool) -> &mut Self {
+        self.h1_lf_line_endings = val;
+        self
+    }
+
+    /// Set how HTTP/1 connections handle responses with ambiguous framing
+    /// headers, such as both `Content-Length` and `Transfer-Encoding`.
+    ///
+    /// See [`FramingPolicy`](::proxy::FramingPolicy) for details.
+    ///
+    /// Default is `FramingPolicy::Normalize`.
+    pub fn http1_framing_policy(&mut self, policy: ::proxy::FramingPolicy) -> &mut Self {
+        self.h1_framing = policy;
+        self
+    }
+
+    /// Set the maximum length of the URI written in HTTP/1 requests.
+    ///
+    /// Requests with a longer URI fail with a user error instead of being
+    /// sent. Depending on the connection, the URI is written either in
+    /// origin-form, such as `/path?query`, or in absolute-form.
+    ///
+    /// Default is no limit.
+    pub fn http1_max_uri_length(&mut self, max: usize) -> &mut Self {
+        self.h1_max_uri_len = Some(max);
+        self
+    }
+
+    /// Set whether HTTP/1 response bodies that don't match their
+    /// `Content-Length` are an error.
+    ///
+    /// When enabled, a body that ends before its declared `Content-Length`,
+    /// or that is followed by more bytes, yields an error for which
+    /// [`Error::is_content_length_mismatch`](::Error::is_content_length_mismatch)
+    /// returns true, and the connection is closed. This catches broken
+    /// upstreams. Extra bytes are only noticed if they arrived with the body.
+    ///
+    /// Default is `false`.
+    pub fn http1_strict_content_length(&mut self, enabled: bool) -> &mut Self {
+        self.h1_strict_content_length = enabled;
+        self
+    }
+
+    /// Set the initial size of the read buffer of HTTP/1 connections.
+    ///
+    /// The buffer grows as needed, up to its maximum size. A smaller
+    /// initial size saves memory when many connections are pooled, at the
+    /// cost of more reallocations for responses with larger heads.
+    ///
+    /// Default is 8192.
+    ///
+    /// # Panics
+    ///
+    /// The value must be between 1 and 8192.
+    pub fn http1_init_read_buf_size(&mut self, size: usize) -> &mut Self {
+        assert!(
+            size > 0 && size <= proto::h1::MINIMUM_MAX_BUFFER_SIZE,
+            "the initial read buffer size must be between 1 and the minimum max buffer size"
+        );
+        self.h1_buf_sizes.read_init = size;
+        self
+    }
+
+    /// Set the maximum size of the read buffer of HTTP/1 connections.
+    ///
+    /// This limits the size of a response's head. Responses whose head
+    /// doesn't fit are an error.
+    ///
+    /// Default is ~400kb.
+    ///
+    /// # Panics
+    ///
+    /// The minimum value allowed is 8192.
+    pub fn http1_max_read_buf_size(&mut self, max: usize) -> &mut Self {
+        assert!(
+            max >= proto::h1::MINIMUM_MAX_BUFFER_SIZE,
+            "the max read buffer size cannot be smaller than the minimum that h1 specifies."
+        );
+        self.h1_buf_sizes.read_max = max;
+        self
+    }
+
+    /// Set the initial size of the buffer holding the heads of HTTP/1
+    /// requests before they are written.
+    ///
+    /// Default is 8192.
+    pub fn http1_init_write_buf_size(&mut self, size: usize) -> &mut Self {
+        self.h1_buf_sizes.write_init = size;
+        self
+    }
+
+    /// Set how many bytes HTTP/1 connections buffer before they need to
+    /// flush them to the transport.
+    ///
+    /// Default is ~400kb.
+    ///
+    /// # Panics
+    ///
+    /// The minimum value allowed is 8192.
+    pub fn http1_max_write_buf_size(&mut self, max: usize) -> &mut Self {
+        assert!(
+            max >= proto::h1::MINIMUM_MAX_BUFFER_SIZE,
+            "the max write buffer size cannot be smaller than the minimum that h1 specifies."
+        );
+        self.h1_buf_sizes.write_max = max;
+        self
+    }
+
     /// Set whether the connection **must** use HTTP/2.
     ///
     /// Note that setting this to true prevents HTTP/1 from being allowed.
@@ -706,6 +1326,82 @@ impl Builder {
         self
     }
 
+    /// Set the largest body chunk sent at once on HTTP/2 connections.
+    ///
+    /// See [`conn::Builder::http2_max_send_chunk_size`](conn::Builder::http2_max_send_chunk_size).
+    ///
+    /// Default is no limit.
+    ///
+    /// # Panics
+    ///
+    /// The minimum value allowed is 1.
+    pub fn http2_max_send_chunk_size(&mut self, max: usize) -> &mut Self {
+        assert!(max > 0, "the max send chunk size must be larger than 0");
+        self.h2_body_chunks.max_size = Some(max);
+        self
+    }
+
+    /// Set the smallest body chunk sent on its own on HTTP/2 connections.
+    ///
+    /// See [`conn::Builder::http2_min_send_chunk_size`](conn::Builder::http2_min_send_chunk_size).
+    ///
+    /// Default is 0, which sends every chunk on its own.
+    pub fn http2_min_send_chunk_size(&mut self, min: usize) -> &mut Self {
+        self.h2_body_chunks.min_size = min;
+        self
+    }
+
+    /// Set whether the request bodies and responses of HTTP/2 connections
+    /// are spawned as tasks.
+    ///
+    /// See [`conn::Builder::http2_spawn_streams`](conn::Builder::http2_spawn_streams).
+    ///
+    /// Default is `true`.
+    pub fn http2_spawn_streams(&mut self, enabled: bool) -> &mut Self {
+        self.h2_spawn_streams = enabled;
+        self
+    }
+
+    /// Set how many HTTP/2 connections are kept to the same host.
+    ///
+    /// A single connection limits the throughput to a host, and when its
+    /// packets are lost, all of its requests wait for them. With more
+    /// connections, every request is sent on the one with the fewest
+    /// requests in flight. Until there are as many as configured, another
+    /// one is established in the background whenever a request is sent,
+    /// and the request still goes on an existing connection.
+    ///
+    /// Default is 1.
+    ///
+    /// # Panics
+    ///
+    /// The minimum value allowed is 1.
+    pub fn http2_connections_per_host(&mut self, max: usize) -> &mut Self {
+        assert!(max > 0, "the HTTP/2 connections per host must be at least 1");
+        self.h2_connections = max;
+        self
+    }
+
+    /// Set how many HTTP/2 connections can be made to the same host, when
+    /// the ones open are saturated.
+    ///
+    /// A connection is saturated once it has as many requests in flight as
+    /// the peer allows concurrent streams, and further requests sent on it
+    /// wait for the earlier ones to finish. When all the connections to a
+    /// host are saturated, a request instead establishes another one, as
+    /// long as there are fewer than this many. Once there are as many, the
+    /// requests are queued on the least loaded connection.
+    ///
+    /// This can't be smaller than
+    /// [`http2_connections_per_host`](Builder::http2_connections_per_host).
+    ///
+    /// Default is the same as `http2_connections_per_host`, so no more
+    /// connections are made.
+    pub fn http2_max_connections_per_host(&mut self, max: usize) -> &mut Self {
+        self.h2_max_connections = Some(max);
+        self
+    }
+
     /// Set whether to retry requests that get disrupted before ever starting
     /// to write.
     ///
@@ -723,6 +1419,88 @@ impl Builder {
         self
     }
 
+    /// Set how many requests the `Client` can have in flight before
+    /// [`Client::poll_ready`](Client::poll_ready) is `NotReady`.
+    ///
+    /// This is shared by the clones of the built `Client`. It only affects
+    /// `poll_ready`, such as when the `Client` is used as a `Service`.
+    ///
+    /// Default is no limit.
+    #[inline]
+    pub fn max_in_flight_requests(&mut self, max: usize) -> &mut Self {
+        self.max_in_flight = Some(max);
+        self
+    }
+
+    /// Set whether dropping a `ResponseFuture` cancels its request.
+    ///
+    /// When disabled, a request whose future was polled at least once
+    /// keeps being sent in the background, and its response is read and
+    /// discarded, so its connection can go back to the pool.
+    ///
+    /// See [`conn::Builder::cancel_dropped_requests`](conn::Builder::cancel_dropped_requests).
+    ///
+    /// Default is `true`.
+    #[inline]
+    pub fn cancel_dropped_requests(&mut self, val: bool) -> &mut Self {
+        self.cancel_dropped_requests = val;
+        self
+    }
+
+    /// Add an [`Interceptor`](Interceptor) to the requests of this `Client`.
+    ///
+    /// Interceptors can modify requests before they're sent, or answer them
+    /// right away, and modify their responses. They're called in the order
+    /// they were added for requests, and in the reverse order for responses.
+    pub fn intercept<I: Interceptor>(&mut self, interceptor: I) -> &mut Self {
+        self.interceptors.push(Arc::new(interceptor));
+        self
+    }
+
+    /// Report the events of this `Client` to an
+    /// [`Instrumentation`](::instrument::Instrumentation).
+    ///
+    /// This includes the events of its connections, and of its pool.
+    ///
+    /// Default is to not report anything.
+    pub fn instrumentation<I: Instrumentation>(&mut self, instrumentation: I) -> &mut Self {
+        self.instrument.get_or_insert_with(Instrument::default).set_instrumentation(instrumentation);
+        self
+    }
+
+    /// Give a copy of the bytes of this `Client`'s connections to a
+    /// [`Tap`](::instrument::Tap), for debugging.
+    ///
+    /// Default is to not tap connections.
+    pub fn tap<T: Tap>(&mut self, tap: T) -> &mut Self {
+        self.instrument.get_or_insert_with(Instrument::default).set_tap(tap);
+        self
+    }
+
+    /// Tell `observer` about the control frames of HTTP/2 connections, such
+    /// as `SETTINGS`, `GOAWAY` and `RST_STREAM`.
+    ///
+    /// See [`FrameObserver`](::instrument::FrameObserver).
+    ///
+    /// Default is to not observe any frames.
+    pub fn http2_frame_observer<O: FrameObserver>(&mut self, observer: O) -> &mut Self {
+        self.instrument.get_or_insert_with(Instrument::default).set_frame_observer(observer);
+        self
+    }
+
+    /// Set whether to record when each stage of a request happened.
+    ///
+    /// When enabled, requests and their responses have a
+    /// [`Timings`](::ext::Timings) extension. Requests that already have
+    /// one keep it, which is always recorded, even when this is disabled.
+    ///
+    /// Default is `false`.
+    #[inline]
+    pub fn record_timings(&mut self, val: bool) -> &mut Self {
+        self.record_timings = val;
+        self
+    }
+
     /// Set whether to automatically add the `Host` header to requests.
     ///
     /// If true, and a request does not include a `Host` header, one will be
@@ -744,6 +1522,24 @@ impl Builder {
         self
     }
 
+    /// Provide a [`Spawner`](::exec::Spawner) to spawn background tasks.
+    ///
+    /// This replaces an `executor`, and is told what each task is for,
+    /// such as driving a connection, or returning one to the pool.
+    pub fn spawner<S: Spawner>(&mut self, spawner: S) -> &mut Self {
+        self.exec = Exec::Spawner(Arc::new(spawner));
+        self
+    }
+
+    /// Provide a [`Clock`](::clock::Clock) for the pool to tell the time with.
+    ///
+    /// Idle connections expire, and are removed, by the time of this clock,
+    /// instead of `Instant::now` and the tokio timer.
+    pub fn clock<C: Clock>(&mut self, clock: C) -> &mut Self {
+        self.time = Time::new(clock);
+        self
+    }
+
     /// Builder a client with this configuration and the default `HttpConnector`.
     #[cfg(feature = "runtime")]
     pub fn build_http<B>(&self) -> Client<HttpConnector, B>
@@ -758,6 +1554,79 @@ impl Builder {
         self.build(connector)
     }
 
+    /// Checks that the settings of this builder work together.
+    ///
+    /// It's an error to combine `http2_only` with any `http1_` setting,
+    /// to allow 0 requests in flight, to make the smallest chunk sent on
+    /// its own larger than the largest, or to start the write buffer
+    /// larger than its maximum. The error returns `true` from
+    /// [`is_config`](::Error::is_config).
+    pub fn validate(&self) -> ::Result<()> {
+        if self.ver == Ver::Http2 {
+            let default = Builder::default();
+            let h1_settings = [
+                ("http1_absolute_form", self.h1_absolute_form != default.h1_absolute_form),
+                ("http1_writev", self.h1_writev != default.h1_writev),
+                ("http1_force_writev", self.h1_force_writev != default.h1_force_writev),
+                ("http1_title_case_headers", self.h1_title_case_headers != default.h1_title_case_headers),
+                ("http1_preserve_header_case", self.h1_preserve_header_case != default.h1_preserve_header_case),
+                ("http1_record_header_case", self.h1_record_header_case != default.h1_record_header_case),
+                ("http1_record_header_order", self.h1_record_header_order != default.h1_record_header_order),
+                ("http1_allow_obsolete_multiline_headers_in_responses", self.h1_obs_fold != default.h1_obs_fold),
+                ("http1_allow_spaces_after_header_name_in_responses", self.h1_spaces_after_header_name != default.h1_spaces_after_header_name),
+                ("http1_allow_missing_reason_phrase", self.h1_missing_reason != default.h1_missing_reason),
+                ("http1_allow_lf_line_endings", self.h1_lf_line_endings != default.h1_lf_line_endings),
+                ("http1_framing_policy", self.h1_framing != default.h1_framing),
+                ("http1_max_uri_length", self.h1_max_uri_len != default.h1_max_uri_len),
+                ("http1_strict_content_length", self.h1_strict_content_length != default.h1_strict_content_length),
+                ("the http1 buffer sizes", self.h1_buf_sizes != default.h1_buf_sizes),
+            ];
+            if let Some(&(name, _)) = h1_settings.iter().find(|&&(_, set)| set) {
+                return Err(::Error::new_config(format!("http2_only can't be used with {}", name)));
+            }
+        }
+        if let Some(max) = self.h2_max_connections {
+            if max < self.h2_connections {
+                return Err(::Error::new_config("http2_max_connections_per_host can't be smaller than http2_connections_per_host"));
+            }
+        }
+        if self.max_in_flight == Some(0) {
+            return Err(::Error::new_config("max_in_flight_requests of 0 never sends a request"));
+        }
+        self.h2_body_chunks.check()?;
+        self.h1_buf_sizes.check()
+    }
+
+    /// Like [`build_http`](Builder::build_http), but returns an error if
+    /// the settings don't work together.
+    ///
+    /// See [`validate`](Builder::validate).
+    #[cfg(feature = "runtime")]
+    pub fn try_build_http<B>(&self) -> ::Result<Client<HttpConnector, B>>
+    where
+        B: Payload + Send,
+        B::Data: Send,
+    {
+        self.validate()?;
+        Ok(self.build_http())
+    }
+
+    /// Like [`build`](Builder::build), but returns an error if the settings
+    /// don't work together.
+    ///
+    /// See [`validate`](Builder::validate).
+    pub fn try_build<C, B>(&self, connector: C) -> ::Result<Client<C, B>>
+    where
+        C: Connect,
+        C::Transport: 'static,
+        C::Future: 'static,
+        B: Payload + Send,
+        B::Data: Send,
+    {
+        self.validate()?;
+        Ok(self.build(connector))
+    }
+
     /// Combine the configuration of this builder with a connector to create a `Client`.
     pub fn build<C, B>(&self, connector: C) -> Client<C, B>
     where
@@ -767,12 +1636,39 @@ impl Builder {
         B: Payload + Send,
         B::Data: Send,
     {
+        // always counted, for `Client::stats`
+        let instrument = Instrument::with_stats(&self.instrument);
+        let pool = Pool::new(self.keep_alive, self.keep_alive_timeout, &self.exec, &self.time, Some(instrument.clone()));
+        let h2_max_connections = ::std::cmp::max(self.h2_max_connections.unwrap_or(0), self.h2_connections);
+        pool.set_http2_connections(self.h2_connections, h2_max_connections);
         Client {
             connector: Arc::new(connector),
+            conn_ids: Arc::new(AtomicUsize::new(0)),
             executor: self.exec.clone(),
+            h1_absolute_form: self.h1_absolute_form,
             h1_writev: self.h1_writev,
+            h1_force_writev: self.h1_force_writev,
             h1_title_case_headers: self.h1_title_case_headers,
-            pool: Pool::new(self.keep_alive, self.keep_alive_timeout, &self.exec),
+            h1_preserve_header_case: self.h1_preserve_header_case,
+            h1_record_header_case: self.h1_record_header_case,
+            h1_record_header_order: self.h1_record_header_order,
+            h1_obs_fold: self.h1_obs_fold,
+            h1_spaces_after_header_name: self.h1_spaces_after_header_name,
+            h1_missing_reason: self.h1_missing_reason,
+            h1_lf_line_endings: self.h1_lf_line_endings,
+            h1_framing: self.h1_framing,
+            h1_max_uri_len: self.h1_max_uri_len,
+            h1_strict_content_length: self.h1_strict_content_length,
+            h1_buf_sizes: self.h1_buf_sizes,
+            h2_body_chunks: self.h2_body_chunks,
+            h2_spawn_streams: self.h2_spawn_streams,
+            pool: pool,
+            cancel_dropped_requests: self.cancel_dropped_requests,
+            capacity: Arc::new(Capacity::new(self.max_in_flight)),
+            counters: Arc::new(ClientCounters::default()),
+            instrument: Some(instrument),
+            interceptors: self.interceptors.clone(),
+            record_timings: self.record_timings,
             retry_canceled_requests: self.retry_canceled_requests,
             set_host: self.set_host,
             ver: self.ver,
@@ -785,7 +1681,9 @@ impl fmt::Debug for Builder {
         f.debug_struct("Builder")
             .field("keep_alive", &self.keep_alive)
             .field("keep_alive_timeout", &self.keep_alive_timeout)
+            .field("http1_absolute_form", &self.h1_absolute_form)
             .field("http1_writev", &self.h1_writev)
+            .field("http1_force_writev", &self.h1_force_writev)
             .field("max_idle", &self.max_idle)
             .field("set_host", &self.set_host)
             .field("version", &self.ver)
@@ -804,4 +1702,25 @@ mod unit_tests {
 
         assert_eq!(uri.to_string(), "/");
     }
+
+    #[test]
+    fn builder_validate() {
+        let mut builder = Client::builder();
+        builder.validate().expect("default is valid");
+
+        builder.http2_only(true);
+        builder.validate().expect("http2_only alone is valid");
+        builder.http1_title_case_headers(true);
+        assert!(builder.validate().unwrap_err().is_config());
+
+        let mut builder = Client::builder();
+        builder.max_in_flight_requests(0);
+        assert!(builder.validate().unwrap_err().is_config());
+
+        let mut builder = Client::builder();
+        builder
+            .http2_max_send_chunk_size(1024)
+            .http2_min_send_chunk_size(4096);
+        assert!(builder.validate().unwrap_err().is_config());
+    }
 }
@@ -6,10 +6,13 @@ use std::time::{Duration, Instant};
 
 use futures::{Future, Async, Poll};
 use futures::sync::oneshot;
-#[cfg(feature = "runtime")]
-use tokio_timer::Interval;
-
 use common::Exec;
+#[cfg(feature = "runtime")]
+use exec::TaskKind;
+use instrument::{Instrument, PoolEvent};
+#[cfg(feature = "runtime")]
+use clock::Delay;
+use clock::Time;
 use super::Ver;
 
 pub(super) struct Pool<T> {
@@ -23,6 +26,14 @@ pub(super) struct Pool<T> {
 // See https://github.com/hyperium/hyper/issues/1429
 pub(super) trait Poolable: Send + Sized + 'static {
     fn is_open(&self) -> bool;
+    /// Identifies the connection, to pin requests to it.
+    fn id(&self) -> usize;
+    /// The requests in flight on this connection, to share HTTP/2
+    /// connections by.
+    fn in_flight(&self) -> usize;
+    /// Whether the connection can't start more requests right now, such
+    /// as an HTTP/2 connection at the peer's limit of concurrent streams.
+    fn is_saturated(&self) -> bool;
     /// Reserve this connection.
     ///
     /// Allows for HTTP/2 to return a shared reservation.
@@ -50,13 +61,20 @@ type Key = (Arc<String>, Ver);
 struct PoolInner<T> {
     connections: Mutex<Connections<T>>,
     enabled: bool,
+    instrument: Option<Instrument>,
+    time: Time,
 }
 
 struct Connections<T> {
-    // A flag that a connection is being estabilished, and the connection
-    // should be shared. This prevents making multiple HTTP/2 connections
-    // to the same host.
-    connecting: HashSet<Key>,
+    // The HTTP/2 connections being estabilished, that will be shared.
+    // This prevents making more HTTP/2 connections to the same host than
+    // `h2_max_connections`.
+    connecting: HashMap<Key, usize>,
+    // How many HTTP/2 connections are kept to the same host.
+    h2_connections: usize,
+    // How many HTTP/2 connections can be made to the same host, when the
+    // ones open are saturated.
+    h2_max_connections: usize,
     // These are internal Conns sitting in the event loop in the KeepAlive
     // state, waiting to receive a new Request to send on the socket.
     idle: HashMap<Key, Vec<Idle<T>>>,
@@ -70,6 +88,13 @@ struct Connections<T> {
     // them that the Conn could be used instead of waiting for a brand new
     // connection.
     waiters: HashMap<Key, VecDeque<oneshot::Sender<T>>>,
+    // The connections that requests are pinned to, which other requests
+    // don't use, and of those, the HTTP/1 ones that are checked out.
+    pinned: HashSet<usize>,
+    pinned_out: HashSet<usize>,
+    // The Checkouts waiting for a pinned HTTP/1 connection to be put back,
+    // by its id.
+    pinned_waiters: HashMap<usize, VecDeque<oneshot::Sender<T>>>,
     // A oneshot channel is used to allow the interval to be notified when
     // the Pool completely drops. That way, the interval can cancel immediately.
     #[cfg(feature = "runtime")]
@@ -79,29 +104,60 @@ struct Connections<T> {
     timeout: Option<Duration>,
 }
 
+fn evicted(instrument: &Option<Instrument>, key: &Key, expired: bool) {
+    if let Some(ref instrument) = *instrument {
+        instrument.pool_evicted();
+        instrument.pool_event(if expired {
+            PoolEvent::IdleExpired(&key.0)
+        } else {
+            PoolEvent::Evicted(&key.0)
+        });
+    }
+}
+
 // This is because `Weak::new()` *allocates* space for `T`, even if it
 // doesn't need it!
 struct WeakOpt<T>(Option<Weak<T>>);
 
 impl<T> Pool<T> {
-    pub fn new(enabled: bool, timeout: Option<Duration>, __exec: &Exec) -> Pool<T> {
+    pub fn new(enabled: bool, timeout: Option<Duration>, __exec: &Exec, time: &Time, instrument: Option<Instrument>) -> Pool<T> {
         Pool {
             inner: Arc::new(PoolInner {
                 connections: Mutex::new(Connections {
-                    connecting: HashSet::new(),
+                    connecting: HashMap::new(),
+                    h2_connections: 1,
+                    h2_max_connections: 1,
                     idle: HashMap::new(),
                     #[cfg(feature = "runtime")]
                     idle_interval_ref: None,
                     waiters: HashMap::new(),
+                    pinned: HashSet::new(),
+                    pinned_out: HashSet::new(),
+                    pinned_waiters: HashMap::new(),
                     #[cfg(feature = "runtime")]
                     exec: __exec.clone(),
                     timeout,
                 }),
                 enabled,
+                instrument: instrument,
+                time: time.clone(),
             }),
         }
     }
 
+    /// Sets how many HTTP/2 connections are kept to the same host, and
+    /// how many can be made when they are saturated.
+    pub(super) fn set_http2_connections(&self, keep: usize, max: usize) {
+        debug_assert!(keep <= max);
+        let mut inner = self.inner.connections.lock().unwrap();
+        inner.h2_connections = keep;
+        inner.h2_max_connections = max;
+    }
+
+    pub(super) fn time(&self) -> &Time {
+        &self.inner.time
+    }
+
     #[cfg(test)]
     pub(super) fn no_timer(&self) {
         // Prevent an actual interval from being created for this pool...
@@ -123,22 +179,63 @@ impl<T: Poolable> Pool<T> {
             key,
             pool: self.clone(),
             waiter: None,
+            pinned: None,
+        }
+    }
+
+    /// Returns a `Checkout` of the connection that requests were pinned
+    /// to, which waits for it if it's checked out.
+    pub(super) fn checkout_pinned(&self, key: Key, id: usize) -> Checkout<T> {
+        Checkout {
+            key,
+            pool: self.clone(),
+            waiter: None,
+            pinned: Some(id),
         }
     }
 
-    /// Ensure that there is only ever 1 connecting task for HTTP/2
-    /// connections. This does nothing for HTTP/1.
+    /// Pins requests to the connection, which was just checked out, until
+    /// the returned `Pinned` is dropped. Other requests don't use it
+    /// meanwhile.
+    pub(super) fn pin(&self, key: &Key, id: usize) -> Pinned {
+        if !self.inner.enabled {
+            return Pinned {
+                id,
+                pool: None,
+            };
+        }
+        let mut inner = self.inner.connections.lock().unwrap();
+        inner.pinned.insert(id);
+        if key.1 == Ver::Http1 {
+            inner.pinned_out.insert(id);
+        }
+        let pool: Arc<Pins> = self.inner.clone();
+        Pinned {
+            id,
+            pool: Some(Arc::downgrade(&pool)),
+        }
+    }
+
+    /// Whether connections are kept alive to be reused.
+    pub(super) fn is_enabled(&self) -> bool {
+        self.inner.enabled
+    }
+
+    /// Ensure that there are never more HTTP/2 connections to a host, and
+    /// tasks connecting to it, than `h2_max_connections`. This does nothing for
+    /// HTTP/1.
     pub(super) fn connecting(&self, key: &Key) -> Option<Connecting<T>> {
         if key.1 == Ver::Http2 && self.inner.enabled {
             let mut inner = self.inner.connections.lock().unwrap();
-            if inner.connecting.insert(key.clone()) {
+            if inner.h2_can_connect(key) {
+                *inner.connecting.entry(key.clone()).or_insert(0) += 1;
                 let connecting = Connecting {
                     key: key.clone(),
                     pool: WeakOpt::downgrade(&self.inner),
                 };
                 Some(connecting)
             } else {
-                trace!("HTTP/2 connecting already in progress for {:?}", key.0);
+                trace!("HTTP/2 connections already open or in progress for {:?}", key.0);
                 None
             }
         } else {
@@ -151,31 +248,51 @@ impl<T: Poolable> Pool<T> {
         }
     }
 
+    /// Whether another HTTP/2 connection to the host should be made, to
+    /// share its requests with.
+    pub(super) fn wants_connection(&self, key: &Key) -> bool {
+        if key.1 != Ver::Http2 || !self.inner.enabled {
+            return false;
+        }
+        let inner = self.inner.connections.lock().unwrap();
+        let keep = inner.h2_connections;
+        keep > 1 && inner.h2_has_room(key, keep)
+    }
+
     fn take(&self, key: &Key) -> Option<Pooled<T>> {
         let entry = {
             let mut inner = self.inner.connections.lock().unwrap();
-            let expiration = Expiration::new(inner.timeout);
+            let now = self.inner.time.now();
+            let expiration = Expiration::new(inner.timeout, now);
+            // a request waits for another connection, rather than for the
+            // saturated ones, if one is being established, or can be
+            let max = inner.h2_max_connections;
+            let open_more = key.1 == Ver::Http2 &&
+                (inner.connecting.contains_key(key) || inner.h2_has_room(key, max));
+            let inner = &mut *inner;
+            let pinned = &inner.pinned;
             let maybe_entry = inner.idle.get_mut(key)
-                .and_then(|list| {
+                .map(|list| {
                     trace!("take? {:?}: expiration = {:?}", key, expiration.0);
                     // A block to end the mutable borrow on list,
-                    // so the map below can check is_empty()
-                    {
+                    // so the tuple below can check is_empty()
+                    let entry = {
                         let popper = IdlePopper {
                             key,
                             list,
+                            instrument: &self.inner.instrument,
+                            now: now,
+                            open_more: open_more,
+                            pinned: pinned,
                         };
                         popper.pop(&expiration)
-                    }
-                        .map(|e| (e, list.is_empty()))
+                    };
+                    // saturated HTTP/2 connections are kept, even though
+                    // none was taken
+                    (entry, list.is_empty())
                 });
 
-            let (entry, empty) = if let Some((e, empty)) = maybe_entry {
-                (Some(e), empty)
-            } else {
-                // No entry found means nuke the list for sure.
-                (None, true)
-            };
+            let (entry, empty) = maybe_entry.unwrap_or((None, true));
             if empty {
                 //TODO: This could be done with the HashMap::entry API instead.
                 inner.idle.remove(key);
@@ -186,6 +303,65 @@ impl<T: Poolable> Pool<T> {
         entry.map(|e| self.reuse(key, e.value))
     }
 
+    /// Takes the pinned connection, or has `tx` sent it once it's put
+    /// back. Fails if the connection is gone.
+    fn take_pinned(&self, key: &Key, id: usize, tx: oneshot::Sender<T>) -> Result<Option<Pooled<T>>, ()> {
+        let value = {
+            let mut inner = self.inner.connections.lock().unwrap();
+            let now = self.inner.time.now();
+            let expiration = Expiration::new(inner.timeout, now);
+            let index = inner.idle.get(key)
+                .and_then(|list| list.iter().position(|entry| entry.value.id() == id));
+            let index = match index {
+                Some(index) => index,
+                None if inner.pinned_out.contains(&id) => {
+                    trace!("checkout waiting for pinned connection: {:?}", key);
+                    inner.pinned_waiters.entry(id)
+                        .or_insert(VecDeque::new())
+                        .push_back(tx);
+                    return Ok(None);
+                },
+                None => return Err(()),
+            };
+            let (entry, empty) = {
+                let list = inner.idle.get_mut(key).expect("pinned connection is idle");
+                let entry = list.remove(index);
+                let open = entry.value.is_open();
+                if !open || expiration.expires(entry.idle_at) {
+                    trace!("removing pinned connection for {:?}", key);
+                    evicted(&self.inner.instrument, key, open);
+                    (None, list.is_empty())
+                } else {
+                    let value = match entry.value.reserve() {
+                        Reservation::Shared(to_reinsert, to_checkout) => {
+                            list.push(Idle {
+                                idle_at: now,
+                                value: to_reinsert,
+                            });
+                            to_checkout
+                        },
+                        Reservation::Unique(unique) => unique,
+                    };
+                    (Some(value), list.is_empty())
+                }
+            };
+            if empty {
+                inner.idle.remove(key);
+            }
+            match entry {
+                Some(value) => {
+                    if key.1 == Ver::Http1 {
+                        inner.pinned_out.insert(id);
+                    }
+                    value
+                },
+                None => return Err(()),
+            }
+        };
+
+        Ok(Some(self.reuse(key, value)))
+    }
+
     pub(super) fn pooled(&self, mut connecting: Connecting<T>, value: T) -> Pooled<T> {
         let (value, pool_ref)  = if self.inner.enabled {
             match value.reserve() {
@@ -222,6 +398,9 @@ impl<T: Poolable> Pool<T> {
 
             (value, WeakOpt::none())
         };
+        if let Some(ref instrument) = self.inner.instrument {
+            instrument.pool_event(PoolEvent::Created(&connecting.key.0));
+        }
         Pooled {
             key: connecting.key.clone(),
             is_reused: false,
@@ -232,6 +411,9 @@ impl<T: Poolable> Pool<T> {
 
     fn reuse(&self, key: &Key, value: T) -> Pooled<T> {
         debug!("reuse idle connection for {:?}", key);
+        if let Some(ref instrument) = self.inner.instrument {
+            instrument.pool_event(PoolEvent::Reused(&key.0));
+        }
         // TODO: unhack this
         // In Pool::pooled(), which is used for inserting brand new connections,
         // there's some code that adjusts the pool reference taken depending
@@ -267,15 +449,31 @@ impl<T: Poolable> Pool<T> {
 struct IdlePopper<'a, T: 'a> {
     key: &'a Key,
     list: &'a mut Vec<Idle<T>>,
+    instrument: &'a Option<Instrument>,
+    now: Instant,
+    /// Whether another HTTP/2 connection can be opened to the host.
+    open_more: bool,
+    /// The connections pinned to other requests, which are skipped.
+    pinned: &'a HashSet<usize>,
 }
 
 impl<'a, T: Poolable + 'a> IdlePopper<'a, T> {
     fn pop(self, expiration: &Expiration) -> Option<Idle<T>> {
-        while let Some(entry) = self.list.pop() {
+        if self.key.1 == Ver::Http2 {
+            return self.pop_least_loaded(expiration);
+        }
+        let mut index = self.list.len();
+        while index > 0 {
+            index -= 1;
+            if self.pinned.contains(&self.list[index].value.id()) {
+                continue;
+            }
+            let entry = self.list.remove(index);
             // If the connection has been closed, or is older than our idle
             // timeout, simply drop it and keep looking...
             if !entry.value.is_open() {
                 trace!("removing closed connection for {:?}", self.key);
+                evicted(self.instrument, self.key, false);
                 continue;
             }
             // TODO: Actually, since the `idle` list is pushed to the end always,
@@ -286,13 +484,14 @@ impl<'a, T: Poolable + 'a> IdlePopper<'a, T> {
             // whole list...
             if expiration.expires(entry.idle_at) {
                 trace!("removing expired connection for {:?}", self.key);
+                evicted(self.instrument, self.key, true);
                 continue;
             }
 
             let value = match entry.value.reserve() {
                 Reservation::Shared(to_reinsert, to_checkout) => {
                     self.list.push(Idle {
-                        idle_at: Instant::now(),
+                        idle_at: self.now,
                         value: to_reinsert,
                     });
                     to_checkout
@@ -304,18 +503,110 @@ impl<'a, T: Poolable + 'a> IdlePopper<'a, T> {
 
             return Some(Idle {
                 idle_at: entry.idle_at,
-                value,
+                value: value,
             });
         }
 
         None
     }
+
+    /// The HTTP/2 connections stay in the list, and the one with the least
+    /// requests in flight is shared, preferring those that aren't
+    /// saturated. If they all are, and another can be opened, none is.
+    fn pop_least_loaded(self, expiration: &Expiration) -> Option<Idle<T>> {
+        let (key, instrument) = (self.key, self.instrument);
+        self.list.retain(|entry| {
+            if !entry.value.is_open() {
+                trace!("removing closed connection for {:?}", key);
+                evicted(instrument, key, false);
+                return false;
+            }
+            if expiration.expires(entry.idle_at) {
+                trace!("removing expired connection for {:?}", key);
+                evicted(instrument, key, true);
+                return false;
+            }
+            true
+        });
+
+        let pinned = self.pinned;
+        let index = self.list.iter()
+            .enumerate()
+            .filter(|&(_, entry)| !pinned.contains(&entry.value.id()))
+            .min_by_key(|&(_, entry)| (entry.value.is_saturated(), entry.value.in_flight()))
+            .map(|(index, _)| index)?;
+        if self.open_more && self.list[index].value.is_saturated() {
+            trace!("idle HTTP/2 connections saturated for {:?}", self.key);
+            return None;
+        }
+        // moved to the end, so that connections with as many requests take
+        // turns
+        let entry = self.list.remove(index);
+        let value = match entry.value.reserve() {
+            Reservation::Shared(to_reinsert, to_checkout) => {
+                self.list.push(Idle {
+                    idle_at: self.now,
+                    value: to_reinsert,
+                });
+                to_checkout
+            },
+            Reservation::Unique(unique) => {
+                unique
+            }
+        };
+
+        Some(Idle {
+            idle_at: entry.idle_at,
+            value: value,
+        })
+    }
 }
 
 impl<T: Poolable> Connections<T> {
-    fn put(&mut self, key: Key, value: T, __pool_ref: &Arc<PoolInner<T>>) {
-        if key.1 == Ver::Http2 && self.idle.contains_key(&key) {
-            trace!("put; existing idle HTTP/2 connection for {:?}", key);
+    /// Whether there are fewer HTTP/2 connections to the host, including
+    /// those being established, than `limit`.
+    fn h2_has_room(&self, key: &Key, limit: usize) -> bool {
+        let connecting = self.connecting.get(key).cloned().unwrap_or(0);
+        connecting + self.open(key) < limit
+    }
+
+    /// Whether another HTTP/2 connection to the host can be established.
+    ///
+    /// Beyond the `h2_connections` kept, one is only established once all
+    /// of them are saturated, and only one at a time.
+    fn h2_can_connect(&self, key: &Key) -> bool {
+        if self.h2_has_room(key, self.h2_connections) {
+            return true;
+        }
+        let saturated = self.idle.get(key)
+            .map(|list| {
+                list.iter()
+                    .filter(|entry| self.is_available(&entry.value))
+                    .all(|entry| entry.value.is_saturated())
+            })
+            .unwrap_or(true);
+        saturated && !self.connecting.contains_key(key) && self.h2_has_room(key, self.h2_max_connections)
+    }
+
+    /// The pooled connections to the host that weren't closed, and
+    /// aren't pinned.
+    fn open(&self, key: &Key) -> usize {
+        self.idle.get(key)
+            .map(|list| list.iter().filter(|entry| self.is_available(&entry.value)).count())
+            .unwrap_or(0)
+    }
+
+    fn is_available(&self, value: &T) -> bool {
+        value.is_open() && !self.pinned.contains(&value.id())
+    }
+
+    fn put(&mut self, key: Key, value: T, pool_ref: &Arc<PoolInner<T>>) {
+        if key.1 == Ver::Http2 && self.open(&key) >= self.h2_max_connections {
+            trace!("put; existing idle HTTP/2 connections for {:?}", key);
+            return;
+        }
+        if key.1 == Ver::Http1 && self.pinned.contains(&value.id()) {
+            self.put_pinned(key, value, pool_ref);
             return;
         }
         trace!("put; add idle connection for {:?}", key);
@@ -355,32 +646,73 @@ impl<T: Poolable> Connections<T> {
         }
 
         match value {
-            Some(value) => {
-                debug!("pooling idle connection for {:?}", key);
-                self.idle.entry(key)
-                     .or_insert(Vec::new())
-                     .push(Idle {
-                         value: value,
-                         idle_at: Instant::now(),
-                     });
-
-                #[cfg(feature = "runtime")]
-                {
-                    self.spawn_idle_interval(__pool_ref);
+            Some(value) => self.push_idle(key, value, pool_ref),
+            None => trace!("put; found waiter for {:?}", key),
+        }
+    }
+
+    /// A pinned connection is only sent to the checkouts of its pinned
+    /// requests.
+    fn put_pinned(&mut self, key: Key, value: T, pool_ref: &Arc<PoolInner<T>>) {
+        let id = value.id();
+        self.pinned_out.remove(&id);
+        let mut value = Some(value);
+        if let Some(mut waiters) = self.pinned_waiters.remove(&id) {
+            while let Some(tx) = waiters.pop_front() {
+                match tx.send(value.take().expect("value already sent")) {
+                    Ok(()) => {
+                        trace!("put; found pinned waiter for {:?}", key);
+                        self.pinned_out.insert(id);
+                        break;
+                    },
+                    Err(e) => value = Some(e),
                 }
             }
-            None => trace!("put; found waiter for {:?}", key),
+            if !waiters.is_empty() {
+                self.pinned_waiters.insert(id, waiters);
+            }
+        }
+        if let Some(value) = value {
+            self.push_idle(key, value, pool_ref);
+        }
+    }
+
+    fn push_idle(&mut self, key: Key, value: T, pool_ref: &Arc<PoolInner<T>>) {
+        debug!("pooling idle connection for {:?}", key);
+        if let Some(ref instrument) = pool_ref.instrument {
+            instrument.pool_idle();
+        }
+        self.idle.entry(key)
+             .or_insert(Vec::new())
+             .push(Idle {
+                 value: value,
+                 idle_at: pool_ref.time.now(),
+             });
+
+        #[cfg(feature = "runtime")]
+        {
+            self.spawn_idle_interval(pool_ref);
         }
     }
 
     /// A `Connecting` task is complete. Not necessarily successfully,
     /// but the lock is going away, so clean up.
     fn connected(&mut self, key: &Key) {
-        let existed = self.connecting.remove(key);
-        debug_assert!(
-            existed,
-            "Connecting dropped, key not in pool.connecting"
-        );
+        let left = match self.connecting.get_mut(key) {
+            Some(count) => {
+                *count -= 1;
+                *count
+            },
+            None => {
+                debug_assert!(false, "Connecting dropped, key not in pool.connecting");
+                return;
+            },
+        };
+        if left > 0 {
+            // the waiters can still get the other connections
+            return;
+        }
+        self.connecting.remove(key);
         // cancel any waiters. if there are any, it's because
         // this Connecting task didn't complete successfully.
         // those waiters would never receive a connection.
@@ -405,11 +737,13 @@ impl<T: Poolable> Connections<T> {
             }
         };
 
-        let start = Instant::now() + dur;
+        let deadline = pool_ref.time.now() + dur;
 
-        let interval = Interval::new(start, dur);
-        self.exec.execute(IdleInterval {
-            interval: interval,
+        self.exec.execute(TaskKind::Pool, IdleInterval {
+            delay: pool_ref.time.delay(deadline),
+            deadline: deadline,
+            dur: dur,
+            time: pool_ref.time.clone(),
             pool: WeakOpt::downgrade(pool_ref),
             pool_drop_notifier: rx,
         });
@@ -433,25 +767,53 @@ impl<T> Connections<T> {
             self.waiters.remove(key);
         }
     }
+
+    fn clean_pinned_waiters(&mut self, id: usize) {
+        let mut remove_waiters = false;
+        if let Some(waiters) = self.pinned_waiters.get_mut(&id) {
+            waiters.retain(|tx| {
+                !tx.is_canceled()
+            });
+            remove_waiters = waiters.is_empty();
+        }
+        if remove_waiters {
+            self.pinned_waiters.remove(&id);
+        }
+    }
+
+    /// A pinned connection that was checked out is gone, so the requests
+    /// waiting for it are failed.
+    fn pinned_closed(&mut self, id: usize) {
+        if self.pinned_out.remove(&id) {
+            self.pinned_waiters.remove(&id);
+        }
+    }
+
+    fn unpin(&mut self, id: usize) {
+        self.pinned.remove(&id);
+        self.pinned_out.remove(&id);
+        self.pinned_waiters.remove(&id);
+    }
 }
 
 #[cfg(feature = "runtime")]
 impl<T: Poolable> Connections<T> {
     /// This should *only* be called by the IdleInterval.
-    fn clear_expired(&mut self) {
+    fn clear_expired(&mut self, now: Instant, instrument: &Option<Instrument>) {
         let dur = self.timeout.expect("interval assumes timeout");
 
-        let now = Instant::now();
         //self.last_idle_check_at = now;
 
         self.idle.retain(|key, values| {
             values.retain(|entry| {
                 if !entry.value.is_open() {
                     trace!("idle interval evicting closed for {:?}", key);
+                    evicted(instrument, key, false);
                     return false;
                 }
                 if now - entry.idle_at > dur {
                     trace!("idle interval evicting expired for {:?}", key);
+                    evicted(instrument, key, true);
                     return false;
                 }
 
@@ -519,6 +881,11 @@ impl<T: Poolable> Drop for Pooled<T> {
             if !value.is_open() {
                 // If we *already* know the connection is done here,
                 // it shouldn't be re-inserted back into the pool.
+                if let Some(pool) = self.pool.upgrade() {
+                    if let Ok(mut inner) = pool.connections.lock() {
+                        inner.pinned_closed(value.id());
+                    }
+                }
                 return;
             }
 
@@ -556,25 +923,33 @@ pub(super) struct Checkout<T> {
     key: Key,
     pool: Pool<T>,
     waiter: Option<oneshot::Receiver<T>>,
+    /// The id of the connection requests were pinned to.
+    pinned: Option<usize>,
 }
 
 impl<T: Poolable> Checkout<T> {
     fn poll_waiter(&mut self) -> Poll<Option<Pooled<T>>, ::Error> {
         static CANCELED: &str = "pool checkout failed";
         if let Some(mut rx) = self.waiter.take() {
+            let pinned = self.pinned.is_some();
+            let failed = || if pinned {
+                ::Error::new_affinity_lost()
+            } else {
+                ::Error::new_canceled(Some(CANCELED))
+            };
             match rx.poll() {
                 Ok(Async::Ready(value)) => {
                     if value.is_open() {
                         Ok(Async::Ready(Some(self.pool.reuse(&self.key, value))))
                     } else {
-                        Err(::Error::new_canceled(Some(CANCELED)))
+                        Err(failed())
                     }
                 },
                 Ok(Async::NotReady) => {
                     self.waiter = Some(rx);
                     Ok(Async::NotReady)
                 },
-                Err(_canceled) => Err(::Error::new_canceled(Some(CANCELED))),
+                Err(_canceled) => Err(failed()),
             }
         } else {
             Ok(Async::Ready(None))
@@ -600,6 +975,19 @@ impl<T: Poolable> Future for Checkout<T> {
             return Ok(Async::Ready(pooled));
         }
 
+        if let Some(id) = self.pinned {
+            let (tx, mut rx) = oneshot::channel();
+            return match self.pool.take_pinned(&self.key, id, tx) {
+                Ok(Some(pooled)) => Ok(Async::Ready(pooled)),
+                Ok(None) => {
+                    let _ = rx.poll(); // park this task
+                    self.waiter = Some(rx);
+                    Ok(Async::NotReady)
+                },
+                Err(()) => Err(::Error::new_affinity_lost()),
+            };
+        }
+
         let entry = self.pool.take(&self.key);
 
         if let Some(pooled) = entry {
@@ -615,7 +1003,10 @@ impl<T> Drop for Checkout<T> {
     fn drop(&mut self) {
         if self.waiter.take().is_some() {
             if let Ok(mut inner) = self.pool.inner.connections.lock() {
-                inner.clean_waiters(&self.key);
+                match self.pinned {
+                    Some(id) => inner.clean_pinned_waiters(id),
+                    None => inner.clean_waiters(&self.key),
+                }
             }
         }
     }
@@ -642,16 +1033,43 @@ impl<T: Poolable> Drop for Connecting<T> {
     }
 }
 
-struct Expiration(Option<Duration>);
+/// Keeps a connection pinned, until dropped.
+pub(super) struct Pinned {
+    id: usize,
+    pool: Option<Weak<Pins>>,
+}
+
+/// Unpins connections, for a `Pinned` that doesn't know the pool's type.
+trait Pins: Send + Sync {
+    fn unpin(&self, id: usize);
+}
+
+impl<T: Poolable> Pins for PoolInner<T> {
+    fn unpin(&self, id: usize) {
+        if let Ok(mut inner) = self.connections.lock() {
+            inner.unpin(id);
+        }
+    }
+}
+
+impl Drop for Pinned {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.as_ref().and_then(Weak::upgrade) {
+            pool.unpin(self.id);
+        }
+    }
+}
+
+struct Expiration(Option<Duration>, Instant);
 
 impl Expiration {
-    fn new(dur: Option<Duration>) -> Expiration {
-        Expiration(dur)
+    fn new(dur: Option<Duration>, now: Instant) -> Expiration {
+        Expiration(dur, now)
     }
 
     fn expires(&self, instant: Instant) -> bool {
         match self.0 {
-            Some(timeout) => instant.elapsed() > timeout,
+            Some(timeout) => self.1.duration_since(instant) > timeout,
             None => false,
         }
     }
@@ -659,7 +1077,10 @@ impl Expiration {
 
 #[cfg(feature = "runtime")]
 struct IdleInterval<T> {
-    interval: Interval,
+    delay: Delay,
+    deadline: Instant,
+    dur: Duration,
+    time: Time,
     pool: WeakOpt<PoolInner<T>>,
     // This allows the IdleInterval to be notified as soon as the entire
     // Pool is fully dropped, and shutdown. This channel is never sent on,
@@ -673,9 +1094,6 @@ impl<T: Poolable + 'static> Future for IdleInterval<T> {
     type Error = ();
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        // Interval is a Stream
-        use futures::Stream;
-
         loop {
             match self.pool_drop_notifier.poll() {
                 Ok(Async::Ready(n)) => match n {},
@@ -686,14 +1104,22 @@ impl<T: Poolable + 'static> Future for IdleInterval<T> {
                 }
             }
 
-            try_ready!(self.interval.poll().map_err(|err| {
+            try_ready!(self.delay.poll().map_err(|err| {
                 error!("idle interval timer error: {}", err);
             }));
 
-            if let Some(inner) = self.pool.upgrade() {
-                if let Ok(mut inner) = inner.connections.lock() {
+            let now = self.time.now();
+            self.deadline += self.dur;
+            if self.deadline <= now {
+                // the clock jumped past more than one interval
+                self.deadline = now + self.dur;
+            }
+            self.delay = self.time.delay(self.deadline);
+
+            if let Some(pool) = self.pool.upgrade() {
+                if let Ok(mut inner) = pool.connections.lock() {
                     trace!("idle interval checking for expired");
-                    inner.clear_expired();
+                    inner.clear_expired(now, &pool.instrument);
                     continue;
                 }
             }
@@ -725,6 +1151,7 @@ mod tests {
     use futures::{Async, Future};
     use futures::future;
     use common::Exec;
+    use clock::Time;
     use super::{Connecting, Key, Poolable, Pool, Reservation, Ver, WeakOpt};
 
     /// Test unique reservations.
@@ -736,6 +1163,18 @@ mod tests {
             true
         }
 
+        fn id(&self) -> usize {
+            0
+        }
+
+        fn in_flight(&self) -> usize {
+            0
+        }
+
+        fn is_saturated(&self) -> bool {
+            false
+        }
+
         fn reserve(self) -> Reservation<Self> {
             Reservation::Unique(self)
         }
@@ -749,7 +1188,7 @@ mod tests {
     }
 
     fn pool_no_timer<T>() -> Pool<T> {
-        let pool = Pool::new(true, Some(Duration::from_millis(100)), &Exec::Default);
+        let pool = Pool::new(true, Some(Duration::from_millis(100)), &Exec::Default, &Time::default(), None);
         pool.no_timer();
         pool
     }
@@ -802,13 +1241,75 @@ mod tests {
         }).wait().unwrap();
     }
 
+    #[test]
+    fn test_pool_checkout_expires_by_clock() {
+        use clock::ManualClock;
+
+        future::lazy(|| {
+            let clock = ManualClock::new();
+            let pool = Pool::new(true, Some(Duration::from_secs(90)), &Exec::Default, &Time::new(clock.clone()), None);
+            pool.no_timer();
+            let key = (Arc::new("foo".to_string()), Ver::Http1);
+
+            drop(pool.pooled(c(key.clone()), Uniq(41)));
+            clock.advance(Duration::from_secs(90));
+            match pool.checkout(key.clone()).poll().unwrap() {
+                Async::Ready(pooled) => drop(pooled),
+                _ => panic!("not expired yet"),
+            }
+
+            clock.advance(Duration::from_secs(91));
+            assert!(pool.checkout(key).poll().unwrap().is_not_ready());
+            ::futures::future::ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn test_pool_reports_events() {
+        use std::sync::Mutex;
+        use instrument::{Instrument, Instrumentation, PoolEvent};
+
+        #[derive(Default)]
+        struct Events(Mutex<Vec<String>>);
+
+        impl Instrumentation for Events {
+            fn pool_event(&self, event: PoolEvent) {
+                self.0.lock().unwrap().push(format!("{:?}", event));
+            }
+        }
+
+        future::lazy(|| {
+            let events = Arc::new(Events::default());
+            let mut instrument = Instrument::default();
+            instrument.set_instrumentation(events.clone());
+            let pool = Pool::new(true, Some(Duration::from_millis(100)), &Exec::Default, &Time::default(), Some(instrument));
+            pool.no_timer();
+            let key = (Arc::new("foo".to_string()), Ver::Http1);
+
+            drop(pool.pooled(c(key.clone()), Uniq(41)));
+            match pool.checkout(key.clone()).poll().unwrap() {
+                Async::Ready(pooled) => drop(pooled),
+                _ => panic!("not ready"),
+            }
+            ::std::thread::sleep(pool.inner.connections.lock().unwrap().timeout.unwrap());
+            assert!(pool.checkout(key).poll().unwrap().is_not_ready());
+
+            assert_eq!(*events.0.lock().unwrap(), [
+                "Created(\"foo\")",
+                "Reused(\"foo\")",
+                "IdleExpired(\"foo\")",
+            ]);
+            ::futures::future::ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
     #[cfg(feature = "runtime")]
     #[test]
     fn test_pool_timer_removes_expired() {
         use std::sync::Arc;
         let runtime = ::tokio::runtime::Runtime::new().unwrap();
         let executor = runtime.executor();
-        let pool = Pool::new(true, Some(Duration::from_millis(100)), &Exec::Executor(Arc::new(executor)));
+        let pool = Pool::new(true, Some(Duration::from_millis(100)), &Exec::Executor(Arc::new(executor)), &Time::default(), None);
 
         let key = (Arc::new("foo".to_string()), Ver::Http1);
 
@@ -878,6 +1379,18 @@ mod tests {
             !self.closed
         }
 
+        fn id(&self) -> usize {
+            self.val as usize
+        }
+
+        fn in_flight(&self) -> usize {
+            0
+        }
+
+        fn is_saturated(&self) -> bool {
+            false
+        }
+
         fn reserve(self) -> Reservation<Self> {
             Reservation::Unique(self)
         }
@@ -894,4 +1407,126 @@ mod tests {
 
         assert!(!pool.inner.connections.lock().unwrap().idle.contains_key(&key));
     }
+
+    #[test]
+    fn pinned_checkout_waits_for_its_connection() {
+        let pool = pool_no_timer();
+        let key = (Arc::new("localhost:12345".to_string()), Ver::Http1);
+        let first = pool.pooled(c(key.clone()), CanClose { val: 1, closed: false });
+        let second = pool.pooled(c(key.clone()), CanClose { val: 2, closed: false });
+        let pinned = pool.pin(&key, 1);
+        drop(second);
+        drop(first);
+
+        future::lazy(|| {
+            // other checkouts skip the pinned connection
+            let other = pool.checkout(key.clone()).poll().unwrap();
+            assert!(other.is_ready());
+
+            let mut pooled = match pool.checkout_pinned(key.clone(), 1).poll().unwrap() {
+                Async::Ready(pooled) => pooled,
+                _ => panic!("pinned connection is idle"),
+            };
+            assert_eq!(pooled.val, 1);
+            let mut checkout = pool.checkout_pinned(key.clone(), 1);
+            assert!(checkout.poll().unwrap().is_not_ready());
+            drop(pooled);
+            pooled = match checkout.poll().unwrap() {
+                Async::Ready(pooled) => pooled,
+                _ => panic!("pinned connection was put back"),
+            };
+
+            pooled.closed = true;
+            drop(pooled);
+            let err = pool.checkout_pinned(key.clone(), 1).poll().unwrap_err();
+            assert!(err.is_affinity_lost());
+            ::futures::future::ok::<(), ()>(())
+        }).wait().unwrap();
+
+        drop(pinned);
+        assert!(pool.inner.connections.lock().unwrap().pinned.is_empty());
+    }
+
+    /// An HTTP/2 connection, with its requests in flight.
+    #[derive(Clone, Debug)]
+    struct Multiplexed {
+        id: i32,
+        in_flight: usize,
+        saturated: bool,
+    }
+
+    impl Poolable for Multiplexed {
+        fn is_open(&self) -> bool {
+            true
+        }
+
+        fn id(&self) -> usize {
+            self.id as usize
+        }
+
+        fn in_flight(&self) -> usize {
+            self.in_flight
+        }
+
+        fn is_saturated(&self) -> bool {
+            self.saturated
+        }
+
+        fn reserve(self) -> Reservation<Self> {
+            Reservation::Shared(self.clone(), self)
+        }
+    }
+
+    #[test]
+    fn http2_checkout_least_loaded() {
+        let pool = pool_no_timer();
+        pool.set_http2_connections(2, 2);
+        let key = (Arc::new("localhost:12345".to_string()), Ver::Http2);
+
+        let first = pool.connecting(&key).expect("first connecting");
+        assert!(pool.wants_connection(&key));
+        let second = pool.connecting(&key).expect("second connecting");
+        assert!(pool.connecting(&key).is_none(), "more connections than configured");
+        assert!(!pool.wants_connection(&key));
+
+        pool.pooled(first, Multiplexed { id: 1, in_flight: 3, saturated: false });
+        pool.pooled(second, Multiplexed { id: 2, in_flight: 1, saturated: false });
+        assert!(!pool.wants_connection(&key));
+
+        for _ in 0..2 {
+            let pooled = pool.checkout(key.clone()).wait().unwrap();
+            assert_eq!(pooled.id, 2);
+        }
+        assert_eq!(pool.inner.connections.lock().unwrap().idle.get(&key).map(Vec::len), Some(2));
+    }
+
+    #[test]
+    fn http2_saturated_checkout_waits_for_another_connection() {
+        let pool = pool_no_timer();
+        pool.set_http2_connections(1, 2);
+        let key = (Arc::new("localhost:12345".to_string()), Ver::Http2);
+        let connecting = pool.connecting(&key).expect("first connecting");
+        pool.pooled(connecting, Multiplexed { id: 1, in_flight: 2, saturated: true });
+
+        future::lazy(|| {
+            let mut checkout = pool.checkout(key.clone());
+            assert!(checkout.poll().unwrap().is_not_ready());
+            let connecting = pool.connecting(&key).expect("second connecting");
+            assert!(pool.connecting(&key).is_none(), "one connection at a time");
+            pool.pooled(connecting, Multiplexed { id: 2, in_flight: 0, saturated: true });
+            match checkout.poll().unwrap() {
+                Async::Ready(pooled) => assert_eq!(pooled.id, 2),
+                Async::NotReady => panic!("checkout not given the new connection"),
+            }
+
+            // once no more can be made, the saturated ones are used
+            let pooled = pool.checkout(key.clone()).poll().unwrap();
+            match pooled {
+                Async::Ready(pooled) => assert_eq!(pooled.id, 2),
+                Async::NotReady => panic!("checkout waiting at the limit"),
+            }
+            assert!(pool.connecting(&key).is_none());
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
 }
@@ -0,0 +1,258 @@
+//! Sources of time for the timeouts of hyper.
+//!
+//! A `Client`'s pool expires idle connections, a
+//! [`DataTimeout`](::body::DataTimeout) errors when a body stalls, and an
+//! [`AddrIncoming`](::server::conn::AddrIncoming) sleeps after accept
+//! errors. By default they tell the time with `Instant::now`, and wait with
+//! the tokio timer. A [`Clock`](Clock) given to them is used instead, such
+//! as a [`ManualClock`](ManualClock), so tests can advance the time they
+//! see, instead of sleeping.
+//!
+//! # Example
+//!
+//! ```
+//! use std::time::Duration;
+//! use hyper::clock::ManualClock;
+//!
+//! # #[cfg(feature = "runtime")]
+//! # fn doc() {
+//! let clock = ManualClock::new();
+//! let client = hyper::Client::builder()
+//!     .keep_alive_timeout(Duration::from_secs(90))
+//!     .clock(clock.clone())
+//!     .build_http::<hyper::Body>();
+//!
+//! // idle connections of `client` expire now
+//! clock.advance(Duration::from_secs(91));
+//! # drop(client);
+//! # }
+//! # fn main() {}
+//! ```
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+use futures::task::{self, Task};
+
+/// Tells the time, and waits for it.
+///
+/// An `Arc<C>` is a `Clock` as well.
+pub trait Clock: Send + Sync + 'static {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+
+    /// Returns a `Delay` that completes once `now` reaches `deadline`.
+    fn delay(&self, deadline: Instant) -> Delay;
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn delay(&self, deadline: Instant) -> Delay {
+        (**self).delay(deadline)
+    }
+}
+
+/// A future that completes at a deadline, as returned by a
+/// [`Clock`](Clock).
+#[must_use = "futures do nothing unless polled"]
+pub struct Delay {
+    inner: Box<Future<Item=(), Error=Box<StdError + Send + Sync>> + Send>,
+}
+
+impl Delay {
+    /// Wraps a future completing at the deadline.
+    pub fn new<F>(fut: F) -> Delay
+    where
+        F: Future<Item=()> + Send + 'static,
+        F::Error: Into<Box<StdError + Send + Sync>>,
+    {
+        Delay {
+            inner: Box::new(fut.map_err(Into::into)),
+        }
+    }
+}
+
+impl Future for Delay {
+    type Item = ();
+    type Error = Box<StdError + Send + Sync>;
+
+    fn poll(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll()
+    }
+}
+
+impl fmt::Debug for Delay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Delay")
+            .finish()
+    }
+}
+
+/// A `Clock` whose time only moves when it's advanced.
+///
+/// It starts at the `Instant` it was created. Clones share their time, so a
+/// test can keep one, and give another to hyper.
+#[derive(Clone)]
+pub struct ManualClock {
+    state: Arc<Mutex<Manual>>,
+}
+
+struct Manual {
+    now: Instant,
+    // the tasks waiting on delays, notified when the time moves
+    waiters: Vec<Task>,
+}
+
+impl ManualClock {
+    /// Creates a `ManualClock` at the current time.
+    pub fn new() -> ManualClock {
+        ManualClock {
+            state: Arc::new(Mutex::new(Manual {
+                now: Instant::now(),
+                waiters: Vec::new(),
+            })),
+        }
+    }
+
+    /// Moves the time forward by `dur`, completing the delays it reaches.
+    pub fn advance(&self, dur: Duration) {
+        let waiters = {
+            let mut state = self.state.lock().unwrap();
+            state.now += dur;
+            ::std::mem::replace(&mut state.waiters, Vec::new())
+        };
+        for task in waiters {
+            task.notify();
+        }
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> ManualClock {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.state.lock().unwrap().now
+    }
+
+    fn delay(&self, deadline: Instant) -> Delay {
+        Delay::new(ManualDelay {
+            state: self.state.clone(),
+            deadline: deadline,
+        })
+    }
+}
+
+impl fmt::Debug for ManualClock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ManualClock")
+            .field("now", &self.now())
+            .finish()
+    }
+}
+
+struct ManualDelay {
+    state: Arc<Mutex<Manual>>,
+    deadline: Instant,
+}
+
+impl Future for ManualDelay {
+    type Item = ();
+    type Error = ::common::Never;
+
+    fn poll(&mut self) -> Poll<(), Self::Error> {
+        let mut state = self.state.lock().unwrap();
+        if state.now >= self.deadline {
+            Ok(Async::Ready(()))
+        } else {
+            state.waiters.push(task::current());
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// Either the user provides a clock, or we use `Instant::now` and the
+/// tokio timer.
+#[derive(Clone)]
+pub(crate) enum Time {
+    Default,
+    Clock(Arc<Clock>),
+}
+
+impl Time {
+    pub(crate) fn new<C: Clock>(clock: C) -> Time {
+        Time::Clock(Arc::new(clock))
+    }
+
+    pub(crate) fn now(&self) -> Instant {
+        match *self {
+            Time::Default => Instant::now(),
+            Time::Clock(ref clock) => clock.now(),
+        }
+    }
+
+    pub(crate) fn delay(&self, deadline: Instant) -> Delay {
+        match *self {
+            Time::Default => {
+                #[cfg(feature = "runtime")]
+                {
+                    Delay::new(::tokio_timer::Delay::new(deadline))
+                }
+                #[cfg(not(feature = "runtime"))]
+                {
+                    let _ = deadline;
+                    // If no runtime, we need a clock!
+                    panic!("clock must be set")
+                }
+            },
+            Time::Clock(ref clock) => clock.delay(deadline),
+        }
+    }
+}
+
+impl Default for Time {
+    fn default() -> Time {
+        Time::Default
+    }
+}
+
+impl fmt::Debug for Time {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Time")
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::{future, Async, Future};
+
+    use super::{Clock, ManualClock};
+
+    #[test]
+    fn manual_clock_delay() {
+        future::lazy(|| {
+            let clock = ManualClock::new();
+            let start = clock.now();
+            let mut delay = clock.delay(start + Duration::from_secs(10));
+            assert_eq!(delay.poll().unwrap(), Async::NotReady);
+            clock.advance(Duration::from_secs(9));
+            assert_eq!(delay.poll().unwrap(), Async::NotReady);
+            clock.advance(Duration::from_secs(1));
+            assert_eq!(delay.poll().unwrap(), Async::Ready(()));
+            assert_eq!(clock.now() - start, Duration::from_secs(10));
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+use futures::task;
+
+/// How many operations a connection task does each time it's polled.
+pub(crate) const POLL_BUDGET: usize = 32;
+
+/// Bounds the work a connection task does each time it's polled.
+///
+/// A connection that always has more work ready, such as a peer pipelining
+/// requests as fast as they are answered, would otherwise keep polling on
+/// its executor thread, starving the other connections there. Once the
+/// budget is spent, the task is notified to be polled again, and yields.
+#[derive(Debug)]
+pub(crate) struct Budget {
+    left: usize,
+}
+
+impl Budget {
+    pub(crate) fn new() -> Budget {
+        Budget {
+            left: POLL_BUDGET,
+        }
+    }
+
+    /// Refills the budget, at the start of a poll.
+    pub(crate) fn reset(&mut self) {
+        self.left = POLL_BUDGET;
+    }
+
+    /// Spends an operation of the budget.
+    ///
+    /// Returns false if it's spent already, after notifying the current
+    /// task to be polled again. The caller should then return `NotReady`.
+    pub(crate) fn spend(&mut self) -> bool {
+        if self.left == 0 {
+            trace!("poll budget spent, yielding");
+            task::current().notify();
+            return false;
+        }
+        self.left -= 1;
+        true
+    }
+
+    /// Whether the budget is spent.
+    pub(crate) fn is_spent(&self) -> bool {
+        self.left == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, Future};
+
+    use super::{Budget, POLL_BUDGET};
+
+    #[test]
+    fn spend_until_reset() {
+        future::lazy(|| {
+            let mut budget = Budget::new();
+            for _ in 0..POLL_BUDGET {
+                assert!(budget.spend());
+            }
+            assert!(budget.is_spent());
+            assert!(!budget.spend());
+            budget.reset();
+            assert!(budget.spend());
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
@@ -3,22 +3,26 @@ use std::sync::Arc;
 
 use futures::future::{Executor, Future};
 
+use exec::{Spawner, Task, TaskKind};
+
 /// Either the user provides an executor for background tasks, or we use
 /// `tokio::spawn`.
 #[derive(Clone)]
 pub(crate) enum Exec {
     Default,
     Executor(Arc<Executor<Box<Future<Item=(), Error=()> + Send>> + Send + Sync>),
+    Spawner(Arc<Spawner>),
 }
 
 
 impl Exec {
-    pub(crate) fn execute<F>(&self, fut: F)
+    pub(crate) fn execute<F>(&self, kind: TaskKind, fut: F)
     where
         F: Future<Item=(), Error=()> + Send + 'static,
     {
         match *self {
             Exec::Default => {
+                let _ = kind;
                 #[cfg(feature = "runtime")]
                 {
                     ::tokio_executor::spawn(fut)
@@ -35,6 +39,9 @@ impl Exec {
                         panic!("executor error: {:?}", err.kind());
                     });
             },
+            Exec::Spawner(ref spawner) => {
+                spawner.spawn(Task::new(kind, Box::new(fut)));
+            },
         }
     }
 }
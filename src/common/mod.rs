@@ -1,7 +1,13 @@
 mod buf;
+mod budget;
+pub(crate) mod drain;
 mod exec;
 mod never;
+pub(crate) mod slab;
+pub(crate) mod span;
+#[cfg(feature = "runtime")] pub(crate) mod sockopt;
 
 pub(crate) use self::buf::StaticBuf;
+pub(crate) use self::budget::Budget;
 pub(crate) use self::exec::Exec;
 pub use self::never::Never;
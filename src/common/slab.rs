@@ -0,0 +1,158 @@
+use std::sync::{Arc, Mutex};
+
+use futures::{Async, Future};
+use futures::executor::{self, Notify, NotifyHandle};
+use futures::task::AtomicTask;
+
+/// The futures of a connection's streams, polled by the connection task.
+///
+/// Instead of spawning every stream as its own task, a connection keeps
+/// them in a slab, and polls them itself. Each future is polled with its
+/// index as the notify id, so a wakeup of a stream only has that one
+/// polled again, on the connection task. The slots of finished streams
+/// are reused, so a busy connection stops allocating for them.
+pub(crate) struct Slab<F> {
+    entries: Vec<Option<F>>,
+    free: Vec<usize>,
+    len: usize,
+    notify: Arc<Ready>,
+    handle: NotifyHandle,
+    /// The indices being polled, kept to reuse its allocation.
+    polling: Vec<usize>,
+}
+
+/// The indices of the streams notified since they were last polled.
+struct Ready {
+    indices: Mutex<Vec<usize>>,
+    task: AtomicTask,
+}
+
+impl Notify for Ready {
+    fn notify(&self, id: usize) {
+        self.indices.lock().unwrap().push(id);
+        self.task.notify();
+    }
+}
+
+impl<F> Slab<F>
+where
+    F: Future<Item=(), Error=()>,
+{
+    pub(crate) fn new() -> Slab<F> {
+        let notify = Arc::new(Ready {
+            indices: Mutex::new(Vec::new()),
+            task: AtomicTask::new(),
+        });
+        Slab {
+            entries: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+            handle: NotifyHandle::from(notify.clone()),
+            notify,
+            polling: Vec::new(),
+        }
+    }
+
+    /// The streams that didn't finish yet.
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Adds a stream, to be polled on the next `poll`.
+    pub(crate) fn insert(&mut self, fut: F) {
+        let fut = Some(fut);
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.entries[index] = fut;
+                index
+            },
+            None => {
+                self.entries.push(fut);
+                self.entries.len() - 1
+            },
+        };
+        self.len += 1;
+        self.notify.indices.lock().unwrap().push(index);
+    }
+
+    /// Polls the streams that were notified.
+    ///
+    /// Returns `Ready` once there are no streams left, and otherwise has
+    /// the current task notified when any of them can make progress.
+    pub(crate) fn poll(&mut self) -> Async<()> {
+        self.notify.task.register();
+        loop {
+            {
+                let mut indices = self.notify.indices.lock().unwrap();
+                if indices.is_empty() {
+                    break;
+                }
+                ::std::mem::swap(&mut *indices, &mut self.polling);
+            }
+            for index in self.polling.drain(..) {
+                // the stream may be done already, if it was notified twice,
+                // or after it finished
+                let done = match self.entries[index] {
+                    Some(ref mut fut) => match executor::with_notify(&self.handle, index, || fut.poll()) {
+                        Ok(Async::NotReady) => false,
+                        Ok(Async::Ready(())) | Err(()) => true,
+                    },
+                    None => false,
+                };
+                if done {
+                    self.entries[index] = None;
+                    self.free.push(index);
+                    self.len -= 1;
+                }
+            }
+        }
+        if self.len == 0 {
+            Async::Ready(())
+        } else {
+            Async::NotReady
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, Async, Future};
+    use futures::sync::oneshot::{self, Canceled};
+
+    use super::Slab;
+
+    fn stream() -> (oneshot::Sender<()>, future::MapErr<oneshot::Receiver<()>, fn(Canceled)>) {
+        fn ignore(_: Canceled) {}
+        let (tx, rx) = oneshot::channel();
+        (tx, rx.map_err(ignore as fn(Canceled)))
+    }
+
+    #[test]
+    fn polls_notified_streams_and_reuses_slots() {
+        future::lazy(|| {
+            let mut slab = Slab::new();
+            let (tx1, rx1) = stream();
+            let (tx2, rx2) = stream();
+            slab.insert(rx1);
+            slab.insert(rx2);
+            assert_eq!(slab.poll(), Async::NotReady);
+            assert_eq!(slab.len(), 2);
+
+            tx1.send(()).unwrap();
+            assert_eq!(slab.poll(), Async::NotReady);
+            assert_eq!(slab.len(), 1);
+
+            // the finished stream's slot is used again
+            let (tx3, rx3) = stream();
+            slab.insert(rx3);
+            assert_eq!(slab.entries.len(), 2);
+
+            tx2.send(()).unwrap();
+            tx3.send(()).unwrap();
+            assert_eq!(slab.poll(), Async::Ready(()));
+            assert_eq!(slab.len(), 0);
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+//! Socket options that `net2` doesn't expose.
+//!
+//! Options that aren't available on a platform are logged and ignored,
+//! since they are only ever optimizations.
+
+use std::time::Duration;
+
+use net2::TcpBuilder;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod sys {
+    use std::io;
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+
+    use libc::{self, c_int};
+
+    // Defined here since older versions of `libc` don't have them.
+    pub const TCP_FASTOPEN: c_int = 23;
+    pub const TCP_FASTOPEN_CONNECT: c_int = 30;
+    pub const TCP_DEFER_ACCEPT: c_int = 9;
+
+    pub fn set_tcp_opt<S: AsRawFd>(sock: &S, opt: c_int, val: c_int) -> io::Result<()> {
+        let ret = unsafe {
+            libc::setsockopt(
+                sock.as_raw_fd(),
+                libc::IPPROTO_TCP,
+                opt,
+                &val as *const c_int as *const libc::c_void,
+                mem::size_of::<c_int>() as libc::socklen_t,
+            )
+        };
+        if ret == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    pub fn get_tcp_opt<S: AsRawFd>(sock: &S, opt: c_int) -> io::Result<c_int> {
+        let mut val: c_int = 0;
+        let mut len = mem::size_of::<c_int>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                sock.as_raw_fd(),
+                libc::IPPROTO_TCP,
+                opt,
+                &mut val as *mut c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(val)
+        }
+    }
+}
+
+// So the tests can read the options back.
+#[cfg(all(test, any(target_os = "linux", target_os = "android")))]
+pub(crate) use self::sys::{get_tcp_opt, TCP_DEFER_ACCEPT, TCP_FASTOPEN};
+
+/// Enable TCP Fast Open on a listening socket, allowing data in the SYN.
+///
+/// `queue_len` is the maximum number of pending fast open requests.
+/// This must be called before `listen`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn set_tcp_fastopen(sock: &TcpBuilder, queue_len: u32) {
+    if let Err(e) = sys::set_tcp_opt(sock, sys::TCP_FASTOPEN, queue_len as i32) {
+        debug!("TCP_FASTOPEN not set: {}", e);
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn set_tcp_fastopen(_sock: &TcpBuilder, _queue_len: u32) {
+    debug!("TCP_FASTOPEN is not supported on this platform");
+}
+
+/// Enable TCP Fast Open for an outgoing connection.
+///
+/// The `connect` will return immediately, and the first write on the
+/// socket will be sent with the SYN. This must be called before `connect`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn set_tcp_fastopen_connect(sock: &TcpBuilder) {
+    if let Err(e) = sys::set_tcp_opt(sock, sys::TCP_FASTOPEN_CONNECT, 1) {
+        debug!("TCP_FASTOPEN_CONNECT not set: {}", e);
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn set_tcp_fastopen_connect(_sock: &TcpBuilder) {
+    debug!("TCP_FASTOPEN_CONNECT is not supported on this platform");
+}
+
+/// Defer accepting connections until data has arrived on them.
+///
+/// The kernel waits up to `timeout` (rounded to seconds) for data before
+/// completing the connection anyway.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn set_tcp_defer_accept(sock: &TcpBuilder, timeout: Duration) {
+    let secs = timeout.as_secs() + if timeout.subsec_nanos() > 0 { 1 } else { 0 };
+    let secs = if secs > i32::max_value() as u64 { i32::max_value() } else { secs as i32 };
+    if let Err(e) = sys::set_tcp_opt(sock, sys::TCP_DEFER_ACCEPT, secs) {
+        debug!("TCP_DEFER_ACCEPT not set: {}", e);
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn set_tcp_defer_accept(_sock: &TcpBuilder, _timeout: Duration) {
+    debug!("TCP_DEFER_ACCEPT is not supported on this platform");
+}
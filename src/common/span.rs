@@ -0,0 +1,89 @@
+//! The spans of the `tracing` feature.
+//!
+//! Without the feature, a `Span` is empty, and entering it does nothing.
+
+use std::marker::PhantomData;
+
+use futures::{Future, Poll};
+use http::{Method, Uri};
+
+/// A span of a connection or a request.
+#[derive(Clone, Debug)]
+pub(crate) struct Span {
+    #[cfg(feature = "tracing")]
+    inner: ::tracing::Span,
+}
+
+/// Keeps a `Span` entered until dropped.
+pub(crate) struct Entered<'a> {
+    #[cfg(feature = "tracing")]
+    _inner: ::tracing::span::Entered<'a>,
+    _span: PhantomData<&'a Span>,
+}
+
+impl Span {
+    /// The span of a connection, `role` being "client" or "server", and
+    /// `protocol` "http1" or "h2".
+    pub(crate) fn connection(role: &'static str, protocol: &'static str) -> Span {
+        #[cfg(feature = "tracing")]
+        {
+            Span {
+                inner: ::tracing::debug_span!("connection", role = role, protocol = protocol),
+            }
+        }
+        #[cfg(not(feature = "tracing"))]
+        {
+            let _ = (role, protocol);
+            Span {}
+        }
+    }
+
+    /// The span of a request, inside the span entered when it's made,
+    /// usually the one of its connection.
+    pub(crate) fn request(method: &Method, uri: &Uri) -> Span {
+        #[cfg(feature = "tracing")]
+        {
+            Span {
+                inner: ::tracing::debug_span!("request", method = %method, uri = %uri),
+            }
+        }
+        #[cfg(not(feature = "tracing"))]
+        {
+            let _ = (method, uri);
+            Span {}
+        }
+    }
+
+    pub(crate) fn enter(&self) -> Entered {
+        Entered {
+            #[cfg(feature = "tracing")]
+            _inner: self.inner.enter(),
+            _span: PhantomData,
+        }
+    }
+
+    /// Wraps `fut` so that it's polled inside this span.
+    pub(crate) fn instrument<F>(self, fut: F) -> Instrumented<F> {
+        Instrumented {
+            span: self,
+            inner: fut,
+        }
+    }
+}
+
+/// A future polled inside a `Span`.
+#[must_use = "futures do nothing unless polled"]
+pub(crate) struct Instrumented<F> {
+    span: Span,
+    inner: F,
+}
+
+impl<F: Future> Future for Instrumented<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let _entered = self.span.enter();
+        self.inner.poll()
+    }
+}
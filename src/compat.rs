@@ -0,0 +1,261 @@
+//! Adapters between hyper's futures and `std::future`.
+//!
+//! hyper is built on the `Future` and `Stream` traits of `futures` 0.1. An
+//! application using `async`/`await` can wrap any of them, such as a
+//! `ResponseFuture`, a `Connection`, or a `Body`, in a [`Compat`](Compat) to
+//! get a `std::future::Future`, or a stream with a `next` method. Going the
+//! other way, [`Compat01`](Compat01) turns a `std::future::Future`, such as
+//! an `async` block, into a 0.1 `Future`, so it can be returned from a
+//! `Service`.
+//!
+//! This needs the `std-future` feature, and a compiler that has
+//! `std::future`.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use hyper::{Client, Uri};
+//! use hyper::client::HttpConnector;
+//! use hyper::compat::Compat;
+//!
+//! async fn get(client: &Client<HttpConnector>, uri: Uri) -> hyper::Result<Vec<u8>> {
+//!     let res = Compat::new(client.get(uri)).await?;
+//!     let mut body = Compat::new(res.into_body());
+//!     let mut bytes = Vec::new();
+//!     while let Some(chunk) = body.next().await {
+//!         bytes.extend_from_slice(&chunk?);
+//!     }
+//!     Ok(bytes)
+//! }
+//! ```
+
+use std::fmt;
+use std::future::Future as StdFuture;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+use futures::{Async, Future, Stream};
+use futures::executor::{self, Notify, NotifyHandle, Spawn};
+use futures::task::{self, Task};
+
+/// A 0.1 `Future` or `Stream`, usable with `std::future`.
+///
+/// As a `Future`, this is a `std::future::Future` of a `Result`. As a
+/// `Stream`, it has a `next` method, whose future resolves to the next item,
+/// or `None` once the stream ended.
+#[must_use = "futures do nothing unless polled"]
+pub struct Compat<T> {
+    inner: Spawn<T>,
+}
+
+// 0.1 futures can always be moved.
+impl<T> Unpin for Compat<T> {}
+
+impl<T> Compat<T> {
+    /// Wraps a 0.1 `Future` or `Stream`.
+    pub fn new(inner: T) -> Compat<T> {
+        Compat {
+            inner: executor::spawn(inner),
+        }
+    }
+
+    /// Returns the wrapped `Future` or `Stream`.
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: Stream> Compat<T> {
+    /// Polls for the next item of the stream.
+    pub fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<T::Item, T::Error>>> {
+        let notify = WakerNotify::handle(cx.waker());
+        match self.get_mut().inner.poll_stream_notify(&notify, 0) {
+            Ok(Async::Ready(Some(item))) => Poll::Ready(Some(Ok(item))),
+            Ok(Async::Ready(None)) => Poll::Ready(None),
+            Ok(Async::NotReady) => Poll::Pending,
+            Err(err) => Poll::Ready(Some(Err(err))),
+        }
+    }
+
+    /// Returns a future of the next item of the stream.
+    pub fn next(&mut self) -> Next<'_, T> {
+        Next {
+            stream: self,
+        }
+    }
+}
+
+impl<F: Future> StdFuture for Compat<F> {
+    type Output = Result<F::Item, F::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let notify = WakerNotify::handle(cx.waker());
+        match self.get_mut().inner.poll_future_notify(&notify, 0) {
+            Ok(Async::Ready(item)) => Poll::Ready(Ok(item)),
+            Ok(Async::NotReady) => Poll::Pending,
+            Err(err) => Poll::Ready(Err(err)),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Compat<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Compat")
+            .finish()
+    }
+}
+
+/// The future of the next item of a stream, returned by
+/// [`Compat::next`](Compat::next).
+#[must_use = "futures do nothing unless polled"]
+pub struct Next<'a, S: 'a> {
+    stream: &'a mut Compat<S>,
+}
+
+impl<'a, S: Stream> StdFuture for Next<'a, S> {
+    type Output = Option<Result<S::Item, S::Error>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        Pin::new(&mut *self.get_mut().stream).poll_next(cx)
+    }
+}
+
+impl<'a, S> fmt::Debug for Next<'a, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Next")
+            .finish()
+    }
+}
+
+/// Notifies a `std::task::Waker` when a 0.1 future is ready.
+struct WakerNotify(Waker);
+
+impl WakerNotify {
+    fn handle(waker: &Waker) -> NotifyHandle {
+        NotifyHandle::from(Arc::new(WakerNotify(waker.clone())))
+    }
+}
+
+impl Notify for WakerNotify {
+    fn notify(&self, _id: usize) {
+        self.0.wake_by_ref();
+    }
+}
+
+/// A `std::future::Future` of a `Result`, usable as a 0.1 `Future`.
+///
+/// The future must be `Unpin`, so an `async` block needs to be pinned
+/// first, such as with `Box::pin`. It must be polled from a 0.1 task, as
+/// futures given to hyper always are.
+#[must_use = "futures do nothing unless polled"]
+pub struct Compat01<F> {
+    inner: F,
+}
+
+impl<F> Compat01<F> {
+    /// Wraps a `std::future::Future`.
+    pub fn new(inner: F) -> Compat01<F> {
+        Compat01 {
+            inner: inner,
+        }
+    }
+
+    /// Returns the wrapped future.
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+}
+
+impl<F, T, E> Future for Compat01<F>
+where
+    F: StdFuture<Output=Result<T, E>> + Unpin,
+{
+    type Item = T;
+    type Error = E;
+
+    fn poll(&mut self) -> ::futures::Poll<T, E> {
+        let waker = Waker::from(Arc::new(TaskWaker(task::current())));
+        let mut cx = Context::from_waker(&waker);
+        match Pin::new(&mut self.inner).poll(&mut cx) {
+            Poll::Ready(Ok(item)) => Ok(Async::Ready(item)),
+            Poll::Ready(Err(err)) => Err(err),
+            Poll::Pending => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl<F> fmt::Debug for Compat01<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Compat01")
+            .finish()
+    }
+}
+
+/// Wakes a 0.1 task.
+struct TaskWaker(Task);
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.notify();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.notify();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future as StdFuture;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    use futures::{self, Future, Stream};
+    use futures::sync::{mpsc, oneshot};
+
+    use super::{Compat, Compat01};
+
+    struct Noop;
+
+    impl Wake for Noop {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    #[test]
+    fn compat_future_and_stream() {
+        let waker = Waker::from(Arc::new(Noop));
+        let mut cx = Context::from_waker(&waker);
+
+        let (tx, rx) = oneshot::channel::<i32>();
+        let mut fut = Compat::new(rx);
+        assert!(Pin::new(&mut fut).poll(&mut cx).is_pending());
+        tx.send(5).unwrap();
+        assert_eq!(Pin::new(&mut fut).poll(&mut cx), Poll::Ready(Ok(5)));
+
+        let (tx, rx) = mpsc::unbounded::<i32>();
+        tx.unbounded_send(1).unwrap();
+        drop(tx);
+        let mut stream = Compat::new(rx);
+        assert_eq!(Pin::new(&mut stream.next()).poll(&mut cx), Poll::Ready(Some(Ok(1))));
+        assert_eq!(Pin::new(&mut stream.next()).poll(&mut cx), Poll::Ready(None));
+    }
+
+    #[test]
+    fn compat01_future() {
+        let (tx, rx) = oneshot::channel::<i32>();
+        // a `std::future::Future` waiting on a 0.1 one
+        let fut = Compat01::new(Compat::new(rx));
+        let res = futures::future::lazy(move || {
+            tx.send(7).unwrap();
+            fut
+        }).wait();
+        assert_eq!(res, Ok(7));
+
+        let (tx, rx) = mpsc::unbounded::<i32>();
+        let fut = Compat01::new(Compat::new(rx.into_future().map(|(item, _rx)| item).map_err(|_| ())));
+        tx.unbounded_send(3).unwrap();
+        assert_eq!(fut.wait(), Ok(Some(3)));
+    }
+}
@@ -1,11 +1,16 @@
 //! Error and Result module.
+use std::any::Any;
 use std::error::Error as StdError;
 use std::fmt;
 use std::io;
+use std::sync::Mutex;
 
 use httparse;
 use http;
 
+/// An HTTP/2 error code, such as those streams are reset with.
+pub use h2::Reason;
+
 /// Result type often returned from methods that can have hyper `Error`s.
 pub type Result<T> = ::std::result::Result<T, Error>;
 
@@ -19,6 +24,9 @@ pub struct Error {
 struct ErrorImpl {
     kind: Kind,
     cause: Option<Cause>,
+    // A request that was never sent, boxed so that `Error` isn't generic
+    // over its body. The `Mutex` only makes it `Sync`.
+    unsent: Option<Mutex<Box<Any + Send>>>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -30,6 +38,11 @@ pub(crate) enum Kind {
     MismatchedResponse,
     /// A pending item was dropped before ever being processed.
     Canceled,
+    /// A request was canceled with its `CancelHandle`.
+    RequestCanceled,
+    /// A request couldn't be sent on the connection its `Affinity` pinned
+    /// it to.
+    AffinityLost,
     /// Indicates a connection is closed.
     Closed,
     /// An `io::Error` that occurred while trying to read or write to a network stream.
@@ -47,8 +60,17 @@ pub(crate) enum Kind {
     Service,
     /// Error while reading a body from connection.
     Body,
+    /// A received body was shorter or longer than its `Content-Length`.
+    ContentLengthMismatch,
+    /// A body was larger than its limit.
+    BodyTooLarge,
+    /// No data of a body was received for too long.
+    #[cfg(feature = "runtime")]
+    BodyTimeout,
     /// Error while writing a body to connection.
     BodyWrite,
+    /// A body `Sender` was aborted.
+    BodyWriteAborted,
     /// Error calling user's Payload::poll_data().
     BodyUser,
     /// Error calling AsyncWrite::shutdown()
@@ -61,6 +83,12 @@ pub(crate) enum Kind {
     UnsupportedVersion,
     /// User tried to create a CONNECT Request with the Client.
     UnsupportedRequestMethod,
+    /// User tried to send a Request with a URI longer than allowed.
+    UriTooLong,
+    /// User's Payload produced a body not matching its `Content-Length`.
+    BodyLengthMismatch,
+    /// User configured a builder with settings that don't work together.
+    Config,
 }
 
 #[derive(Debug, PartialEq)]
@@ -69,9 +97,12 @@ pub(crate) enum Parse {
     Version,
     VersionH2,
     Uri,
+    UriTooLong,
     Header,
     TooLarge,
     Status,
+    /// An invalid PROXY protocol header.
+    Proxy,
 
     /// A protocol upgrade was encountered, but not yet supported in hyper.
     UpgradeNotSupported,
@@ -87,13 +118,6 @@ pub(crate) enum User {
 */
 
 impl Error {
-    //TODO(error): should there be these kinds of inspection methods?
-    //
-    // - is_io()
-    // - is_connect()
-    // - is_closed()
-    // - etc?
-
     /// Returns true if this was an HTTP parse error.
     pub fn is_parse(&self) -> bool {
         match self.inner.kind {
@@ -110,21 +134,217 @@ impl Error {
             Kind::Service |
             Kind::Closed |
             Kind::UnsupportedVersion |
-            Kind::UnsupportedRequestMethod => true,
+            Kind::UnsupportedRequestMethod |
+            Kind::UriTooLong |
+            Kind::BodyLengthMismatch |
+            Kind::BodyWriteAborted |
+            Kind::RequestCanceled |
+            Kind::Config => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the peer didn't follow the HTTP protocol.
+    ///
+    /// This includes messages that couldn't be parsed, responses sent
+    /// without a request, bodies not matching their `Content-Length`, and
+    /// HTTP/2 protocol errors.
+    pub fn is_protocol(&self) -> bool {
+        match self.inner.kind {
+            Kind::Parse(_) |
+            Kind::MismatchedResponse |
+            Kind::ContentLengthMismatch => true,
+            Kind::Http2 => {
+                let reason = self.cause2()
+                    .and_then(|cause| cause.downcast_ref::<::h2::Error>())
+                    .and_then(|err| err.reason());
+                match reason {
+                    Some(Reason::PROTOCOL_ERROR) |
+                    Some(Reason::FLOW_CONTROL_ERROR) |
+                    Some(Reason::FRAME_SIZE_ERROR) |
+                    Some(Reason::COMPRESSION_ERROR) => true,
+                    _ => false,
+                }
+            },
             _ => false,
         }
     }
 
+    /// Returns the HTTP/2 error code of this error, if it was caused by an
+    /// HTTP/2 stream or connection error, such as a stream that was
+    /// reset with `REFUSED_STREAM`.
+    pub fn h2_reason(&self) -> Option<Reason> {
+        self.find_cause::<::h2::Error>()
+            .and_then(|h2_err| h2_err.reason())
+    }
+
+    /// Returns the first error of type `E` in the chain of causes of this
+    /// error.
+    ///
+    /// The chain goes through the hyper errors causing this one, the inner
+    /// errors of `io::Error`s, and the `io::Error` of HTTP/2 errors, so
+    /// that, for instance, the `io::Error` of a connection reset while
+    /// reading a body can be found from the error the body returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io;
+    ///
+    /// fn is_connection_reset(err: &hyper::Error) -> bool {
+    ///     err.find_cause::<io::Error>()
+    ///         .map(|io| io.kind() == io::ErrorKind::ConnectionReset)
+    ///         .unwrap_or(false)
+    /// }
+    /// # fn main() {}
+    /// ```
+    pub fn find_cause<E: StdError + 'static>(&self) -> Option<&E> {
+        let mut cause = self.cause2();
+        while let Some(err) = cause {
+            if let Some(err) = err.downcast_ref::<E>() {
+                return Some(err);
+            }
+            cause = if let Some(err) = err.downcast_ref::<Error>() {
+                err.cause2()
+            } else if let Some(err) = err.downcast_ref::<io::Error>() {
+                err.get_ref()
+            } else if let Some(err) = err.downcast_ref::<::h2::Error>() {
+                err.get_io().map(|io| io as &(StdError + 'static + Sync + Send))
+            } else {
+                None
+            };
+        }
+        None
+    }
+
+    /// Returns true if a builder was configured with settings that don't
+    /// work together, such as by `Builder::try_build`.
+    pub fn is_config(&self) -> bool {
+        self.inner.kind == Kind::Config
+    }
+
+    /// Returns true if this was an error from connecting.
+    pub fn is_connect(&self) -> bool {
+        self.inner.kind == Kind::Connect
+    }
+
+    /// Returns true if the connection closed before a message was complete.
+    pub fn is_incomplete_message(&self) -> bool {
+        self.inner.kind == Kind::Incomplete
+    }
+
+    /// Returns true if a body was aborted by its
+    /// [`Sender`](::body::Sender).
+    ///
+    /// This is also true for errors caused by such an error, like when a
+    /// request failed because of it.
+    pub fn is_body_write_aborted(&self) -> bool {
+        self.any_in_chain(|err| err.inner.kind == Kind::BodyWriteAborted)
+    }
+
+    /// Returns true if something timed out, such as a stalled body or an IO
+    /// operation.
+    ///
+    /// This is also true for errors caused by such an error.
+    pub fn is_timeout(&self) -> bool {
+        self.any_in_chain(|err| {
+            err.is_body_timeout_() || err.cause2()
+                .and_then(|cause| cause.downcast_ref::<io::Error>())
+                .map(|cause| cause.kind() == io::ErrorKind::TimedOut)
+                .unwrap_or(false)
+        })
+    }
+
+    // Whether `f` is true for this error, or any hyper error causing it.
+    fn any_in_chain<F>(&self, f: F) -> bool
+    where
+        F: Fn(&Error) -> bool,
+    {
+        let mut err = self;
+        loop {
+            if f(err) {
+                return true;
+            }
+            match err.cause2().and_then(|cause| cause.downcast_ref::<Error>()) {
+                Some(cause) => err = cause,
+                None => return false,
+            }
+        }
+    }
+
+    #[cfg(feature = "runtime")]
+    fn is_body_timeout_(&self) -> bool {
+        self.inner.kind == Kind::BodyTimeout
+    }
+
+    #[cfg(not(feature = "runtime"))]
+    fn is_body_timeout_(&self) -> bool {
+        false
+    }
+
     /// Returns true if this was about a `Request` that was canceled.
     pub fn is_canceled(&self) -> bool {
         self.inner.kind == Kind::Canceled
     }
 
+    /// Returns true if a request was canceled with its
+    /// [`CancelHandle`](::client::CancelHandle).
+    pub fn is_request_canceled(&self) -> bool {
+        self.inner.kind == Kind::RequestCanceled
+    }
+
+    /// Returns true if a request couldn't be sent on the connection its
+    /// [`Affinity`](::client::Affinity) pinned it to.
+    pub fn is_affinity_lost(&self) -> bool {
+        self.inner.kind == Kind::AffinityLost
+    }
+
+    /// Returns true if a received body didn't match its `Content-Length`.
+    pub fn is_content_length_mismatch(&self) -> bool {
+        self.inner.kind == Kind::ContentLengthMismatch
+    }
+
+    /// Returns true if a body stalled, such as one wrapped with
+    /// [`DataTimeout`](::body::DataTimeout).
+    #[cfg(feature = "runtime")]
+    pub fn is_body_timeout(&self) -> bool {
+        self.inner.kind == Kind::BodyTimeout
+    }
+
+    /// Returns true if a body was larger than its limit, such as one
+    /// wrapped with [`Limited`](::body::Limited).
+    ///
+    /// This is also true for errors caused by such an error, like when a
+    /// `Service` failed because of it.
+    pub fn is_body_too_large(&self) -> bool {
+        self.any_in_chain(|err| err.inner.kind == Kind::BodyTooLarge)
+    }
+
     /// Returns true if a sender's channel is closed.
     pub fn is_closed(&self) -> bool {
         self.inner.kind == Kind::Closed
     }
 
+    /// Takes the request that couldn't be sent, if this error happened
+    /// before any of it was written to the connection.
+    ///
+    /// Such a request can be sent again, such as with another client,
+    /// without having cloned it up front. `B` must be the body type of the
+    /// request, or this returns `None`.
+    pub fn take_unsent_request<B: Send + 'static>(&mut self) -> Option<http::Request<B>> {
+        let unsent = match self.inner.unsent.take() {
+            Some(unsent) => unsent.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner()),
+            None => return None,
+        };
+        match unsent.downcast::<http::Request<B>>() {
+            Ok(req) => Some(*req),
+            Err(unsent) => {
+                self.inner.unsent = Some(Mutex::new(unsent));
+                None
+            },
+        }
+    }
+
     /// Returns the error's cause.
     /// 
     /// This is identical to `Error::cause` except that it provides extra
@@ -143,10 +363,17 @@ impl Error {
             inner: Box::new(ErrorImpl {
                 kind,
                 cause,
+                unsent: None,
             }),
         }
     }
 
+    // `req` is a `Request<B>`, to be taken with `take_unsent_request`.
+    pub(crate) fn with_unsent_request<R: Send + 'static>(mut self, req: R) -> Error {
+        self.inner.unsent = Some(Mutex::new(Box::new(req)));
+        self
+    }
+
     pub(crate) fn kind(&self) -> &Kind {
         &self.inner.kind
     }
@@ -155,6 +382,14 @@ impl Error {
         Error::new(Kind::Canceled, cause.map(Into::into))
     }
 
+    pub(crate) fn new_request_canceled() -> Error {
+        Error::new(Kind::RequestCanceled, None)
+    }
+
+    pub(crate) fn new_affinity_lost() -> Error {
+        Error::new(Kind::AffinityLost, None)
+    }
+
     pub(crate) fn new_incomplete() -> Error {
         Error::new(Kind::Incomplete, None)
     }
@@ -204,10 +439,28 @@ impl Error {
         Error::new(Kind::Body, Some(cause.into()))
     }
 
+    pub(crate) fn new_content_length_mismatch<E: Into<Cause>>(cause: Option<E>) -> Error {
+        Error::new(Kind::ContentLengthMismatch, cause.map(Into::into))
+    }
+
+    pub(crate) fn new_body_too_large() -> Error {
+        Error::new(Kind::BodyTooLarge, None)
+    }
+
+    #[cfg(feature = "runtime")]
+    pub(crate) fn new_body_timeout() -> Error {
+        let cause = io::Error::new(io::ErrorKind::TimedOut, "no body data received in time");
+        Error::new(Kind::BodyTimeout, Some(cause.into()))
+    }
+
     pub(crate) fn new_body_write<E: Into<Cause>>(cause: E) -> Error {
         Error::new(Kind::BodyWrite, Some(cause.into()))
     }
 
+    pub(crate) fn new_body_write_aborted() -> Error {
+        Error::new(Kind::BodyWriteAborted, None)
+    }
+
     pub(crate) fn new_user_unsupported_version() -> Error {
         Error::new(Kind::UnsupportedVersion, None)
     }
@@ -216,6 +469,14 @@ impl Error {
         Error::new(Kind::UnsupportedRequestMethod, None)
     }
 
+    pub(crate) fn new_user_uri_too_long() -> Error {
+        Error::new(Kind::UriTooLong, None)
+    }
+
+    pub(crate) fn new_user_body_length<E: Into<Cause>>(cause: E) -> Error {
+        Error::new(Kind::BodyLengthMismatch, Some(cause.into()))
+    }
+
     pub(crate) fn new_user_new_service<E: Into<Cause>>(cause: E) -> Error {
         Error::new(Kind::NewService, Some(cause.into()))
     }
@@ -235,6 +496,10 @@ impl Error {
     pub(crate) fn new_h2(cause: ::h2::Error) -> Error {
         Error::new(Kind::Http2, Some(Box::new(cause)))
     }
+
+    pub(crate) fn new_config<M: Into<Cause>>(msg: M) -> Error {
+        Error::new(Kind::Config, Some(msg.into()))
+    }
 }
 
 impl fmt::Debug for Error {
@@ -263,27 +528,39 @@ impl StdError for Error {
             Kind::Parse(Parse::Version) => "invalid HTTP version specified",
             Kind::Parse(Parse::VersionH2) => "invalid HTTP version specified (Http2)",
             Kind::Parse(Parse::Uri) => "invalid URI",
+            Kind::Parse(Parse::UriTooLong) => "request-line is too long",
             Kind::Parse(Parse::Header) => "invalid Header provided",
             Kind::Parse(Parse::TooLarge) => "message head is too large",
             Kind::Parse(Parse::Status) => "invalid Status provided",
+            Kind::Parse(Parse::Proxy) => "invalid PROXY protocol header",
             Kind::Parse(Parse::UpgradeNotSupported) => "unsupported protocol upgrade",
             Kind::Incomplete => "message is incomplete",
             Kind::MismatchedResponse => "response received without matching request",
             Kind::Closed => "connection closed",
             Kind::Connect => "an error occurred trying to connect",
             Kind::Canceled => "an operation was canceled internally before starting",
+            Kind::RequestCanceled => "request canceled",
+            Kind::AffinityLost => "connection pinned by affinity is gone",
             #[cfg(feature = "runtime")]
             Kind::Listen => "error creating server listener",
             Kind::Accept => "error accepting connection",
             Kind::NewService => "calling user's new_service failed",
             Kind::Service => "error from user's server service",
             Kind::Body => "error reading a body from connection",
+            Kind::ContentLengthMismatch => "body length doesn't match content-length",
+            Kind::BodyTooLarge => "body is larger than its limit",
+            #[cfg(feature = "runtime")]
+            Kind::BodyTimeout => "body data timed out",
             Kind::BodyWrite => "error writing a body to connection",
+            Kind::BodyWriteAborted => "body write aborted",
             Kind::BodyUser => "error from user's Payload stream",
             Kind::Shutdown => "error shutting down connection",
             Kind::Http2 => "http2 general error",
             Kind::UnsupportedVersion => "request has unsupported HTTP version",
             Kind::UnsupportedRequestMethod => "request has unsupported HTTP method",
+            Kind::UriTooLong => "request URI is too long",
+            Kind::BodyLengthMismatch => "user's body length doesn't match content-length",
+            Kind::Config => "invalid configuration",
 
             Kind::Io => "an IO error occurred",
         }
@@ -337,6 +614,12 @@ impl From<http::uri::InvalidUri> for Parse {
     }
 }
 
+impl From<http::uri::InvalidUriBytes> for Parse {
+    fn from(_: http::uri::InvalidUriBytes) -> Parse {
+        Parse::Uri
+    }
+}
+
 #[doc(hidden)]
 trait AssertSendSync: Send + Sync + 'static {}
 #[doc(hidden)]
@@ -344,4 +627,83 @@ impl AssertSendSync for Error {}
 
 #[cfg(test)]
 mod tests {
+    use std::io;
+    use super::*;
+
+    #[test]
+    fn error_predicates() {
+        let err = Error::new_connect(io::Error::new(io::ErrorKind::ConnectionRefused, "refused"));
+        assert!(err.is_connect());
+        assert!(!err.is_timeout());
+
+        let err = Error::new_connect(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+        assert!(err.is_connect());
+        assert!(err.is_timeout());
+
+        // the cause is checked too
+        let err = Error::new_user_service(Error::new_io(io::Error::new(io::ErrorKind::TimedOut, "timed out")));
+        assert!(err.is_timeout());
+
+        assert!(Error::new_incomplete().is_incomplete_message());
+        assert!(Error::new_body_write_aborted().is_body_write_aborted());
+        assert!(Error::new_body_write_aborted().is_user());
+        assert!(!Error::new_body_write("oh no").is_body_write_aborted());
+
+        assert!(Error::new_request_canceled().is_request_canceled());
+        assert!(Error::new_request_canceled().is_user());
+        assert!(!Error::new_request_canceled().is_canceled());
+
+        assert!(Error::new_header().is_protocol());
+        assert!(Error::new_mismatched_response().is_protocol());
+        assert!(Error::new_h2(Reason::PROTOCOL_ERROR.into()).is_protocol());
+        assert!(!Error::new_h2(Reason::CANCEL.into()).is_protocol());
+        assert!(!Error::new_closed().is_protocol());
+
+        let err = Error::new_user_service(Error::new_h2(Reason::REFUSED_STREAM.into()));
+        assert_eq!(err.h2_reason(), Some(Reason::REFUSED_STREAM));
+        assert_eq!(Error::new_closed().h2_reason(), None);
+    }
+
+    #[test]
+    fn find_cause() {
+        let reset = io::Error::new(io::ErrorKind::ConnectionReset, "reset");
+        let err = Error::new_body(Error::new_io(reset));
+        let io = err.find_cause::<io::Error>().expect("io cause");
+        assert_eq!(io.kind(), io::ErrorKind::ConnectionReset);
+        assert!(err.find_cause::<::h2::Error>().is_none());
+
+        // through the inner error of an io::Error
+        let inner = io::Error::new(io::ErrorKind::Other, Error::new_h2(Reason::CANCEL.into()));
+        let err = Error::new_user_service(Error::new_io(inner));
+        assert_eq!(err.h2_reason(), Some(Reason::CANCEL));
+
+        // hyper errors are in the chain too
+        let err = Error::new_user_service(Error::new_body_too_large());
+        assert!(err.find_cause::<Error>().expect("hyper cause").is_body_too_large());
+    }
+
+    #[cfg(feature = "runtime")]
+    #[test]
+    fn body_timeout_has_io_cause() {
+        let err = Error::new_body(Error::new_body_timeout());
+        assert!(err.is_timeout());
+        let io = err.find_cause::<io::Error>().expect("io cause");
+        assert_eq!(io.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn take_unsent_request() {
+        let req = http::Request::builder()
+            .uri("/a")
+            .body("hello")
+            .unwrap();
+        let mut err = Error::new_canceled(None::<Error>).with_unsent_request(req);
+
+        // a different body type keeps it in the error
+        assert!(err.take_unsent_request::<Vec<u8>>().is_none());
+        let req = err.take_unsent_request::<&'static str>().expect("unsent request");
+        assert_eq!(req.uri(), "/a");
+        assert_eq!(*req.body(), "hello");
+        assert!(err.take_unsent_request::<&'static str>().is_none());
+    }
 }
@@ -0,0 +1,117 @@
+//! Spawning the background tasks of hyper.
+//!
+//! Clients and servers spawn tasks of their own, such as the ones driving
+//! connections, or piping request bodies into HTTP/2 streams. By default
+//! these are spawned with `tokio::spawn`. A [`Spawner`](Spawner) given to
+//! a builder is given each of them as a [`Task`](Task) instead, which
+//! tells what it's for, so that it can be run on another runtime or
+//! thread pool, or be counted.
+//!
+//! # Example
+//!
+//! ```
+//! use std::sync::atomic::{AtomicUsize, Ordering};
+//! use hyper::exec::{Spawner, Task};
+//! # #[cfg(feature = "runtime")]
+//! # fn doc() {
+//!
+//! struct Counting(AtomicUsize);
+//!
+//! impl Spawner for Counting {
+//!     fn spawn(&self, task: Task) {
+//!         self.0.fetch_add(1, Ordering::Relaxed);
+//!         hyper::rt::spawn(task);
+//!     }
+//! }
+//!
+//! let client = hyper::Client::builder()
+//!     .spawner(Counting(AtomicUsize::new(0)))
+//!     .build_http::<hyper::Body>();
+//! # drop(client);
+//! # }
+//! # fn main() {}
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+
+use futures::{Future, Poll};
+
+/// Runs the background tasks of hyper.
+///
+/// Every `Task` given to `spawn` must be polled to completion, or else the
+/// connection or request it belongs to stops making progress.
+///
+/// An `Arc<S>` is a `Spawner` as well.
+pub trait Spawner: Send + Sync + 'static {
+    /// Runs `task` in the background.
+    fn spawn(&self, task: Task);
+}
+
+impl<S: Spawner + ?Sized> Spawner for Arc<S> {
+    fn spawn(&self, task: Task) {
+        (**self).spawn(task)
+    }
+}
+
+/// A background task of hyper, as given to a [`Spawner`](Spawner).
+#[must_use = "futures do nothing unless polled"]
+pub struct Task {
+    kind: TaskKind,
+    future: Box<Future<Item=(), Error=()> + Send>,
+}
+
+impl Task {
+    pub(crate) fn new(kind: TaskKind, future: Box<Future<Item=(), Error=()> + Send>) -> Task {
+        Task {
+            kind: kind,
+            future: future,
+        }
+    }
+
+    /// What this task does.
+    pub fn kind(&self) -> TaskKind {
+        self.kind
+    }
+}
+
+impl Future for Task {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        self.future.poll()
+    }
+}
+
+impl fmt::Debug for Task {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Task")
+            .field("kind", &self.kind)
+            .finish()
+    }
+}
+
+/// What a [`Task`](Task) does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TaskKind {
+    /// Drives a connection, which runs for as long as it is open.
+    ///
+    /// These are the connections of a `Client`, and the ones a `Server`
+    /// accepted.
+    Connection,
+    /// Serves a request received on an HTTP/2 connection, by calling the
+    /// `Service` and writing its response.
+    Stream,
+    /// Writes the body of a request sent on an HTTP/2 connection.
+    RequestBody,
+    /// Waits for the response to a request sent on an HTTP/2 connection.
+    Response,
+    /// Sends a request whose `ResponseFuture` may be dropped, when dropped
+    /// requests aren't canceled.
+    Request,
+    /// Keeps a `Client`'s pool, such as by returning a connection to it
+    /// once it's ready again, establishing an HTTP/2 connection for it, or
+    /// removing its expired connections.
+    Pool,
+}
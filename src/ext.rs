@@ -0,0 +1,676 @@
+//! Extensions that hyper may add to HTTP messages.
+//!
+//! These are found in the `extensions` of a `Request` or `Response`,
+//! depending on how the connection was configured.
+
+use std::fmt;
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use bytes::Bytes;
+use http::{HeaderMap, Version};
+use http::header::{self, HeaderName, InvalidHeaderName, ValueIter};
+
+/// The raw request-target of a request that isn't a valid `Uri`.
+///
+/// When a server allows lenient request-targets, requests whose target
+/// couldn't be parsed are passed to the service with a `Uri` of `/`, and
+/// this extension containing the bytes that were in the request-line.
+///
+/// See [`Http::http1_lenient_target`](::server::conn::Http::http1_lenient_target).
+#[derive(Clone, PartialEq)]
+pub struct RawTarget(Bytes);
+
+impl RawTarget {
+    pub(crate) fn new(bytes: Bytes) -> RawTarget {
+        RawTarget(bytes)
+    }
+
+    /// The bytes of the request-target.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for RawTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("RawTarget")
+            .field(&String::from_utf8_lossy(&self.0))
+            .finish()
+    }
+}
+
+/// The request-line of a request, as it was received.
+///
+/// This contains the method, request-target and version exactly as sent by
+/// the client, without the line ending. It is meant for logging and
+/// debugging, since the target in `Request::uri()` may have been rewritten,
+/// such as by lenient URI parsing.
+///
+/// See [`Http::http1_record_request_line`](::server::conn::Http::http1_record_request_line).
+#[derive(Clone, PartialEq)]
+pub struct RawRequestLine(Bytes);
+
+impl RawRequestLine {
+    pub(crate) fn new(bytes: Bytes) -> RawRequestLine {
+        RawRequestLine(bytes)
+    }
+
+    /// The bytes of the request-line.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for RawRequestLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("RawRequestLine")
+            .field(&String::from_utf8_lossy(&self.0))
+            .finish()
+    }
+}
+
+/// The framing hyper uses to write the body of an HTTP/1 message.
+///
+/// By default, a body is framed with `Content-Length` when its length is
+/// known, and with chunked encoding otherwise, unless the message already
+/// has framing headers. Adding this to the extensions of a `Request` or
+/// `Response` picks the framing instead, whenever it is allowed, such as
+/// for a proxy that must keep the framing of the message it forwards.
+/// Framing headers that conflict with it are removed.
+///
+/// It is ignored for messages without a body, and for HTTP/2.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Framing {
+    /// Use `Content-Length`, if the length of the body is known from the
+    /// `Payload` or a `Content-Length` header.
+    Length,
+    /// Use chunked encoding, if the message can be chunked.
+    ///
+    /// HTTP/1.0 messages, and responses that have no body, such as to a
+    /// `HEAD` request, can't be chunked.
+    Chunked,
+    /// End the body by closing the connection.
+    ///
+    /// Only responses can be close-delimited, so this is ignored for
+    /// requests.
+    CloseDelimited,
+    /// Write the headers exactly as given, and the body bytes unchanged.
+    ///
+    /// hyper doesn't add, remove or check any framing headers, nor add a
+    /// `Date` header, and the body ends when the `Payload` ends. This lets
+    /// a proxy forward a message byte for byte, such as a chunked body
+    /// with the chunk sizes it was received with, but the headers and body
+    /// must then agree on the framing themselves.
+    ///
+    /// A response whose headers have neither `Content-Length` nor chunked
+    /// `Transfer-Encoding` is read until the connection closes, so hyper
+    /// closes the connection after it. Unlike the other framings, this one
+    /// is also used for messages without a body.
+    Raw,
+}
+
+/// How the framing headers of a received message were normalized.
+///
+/// This is added to a message when a connection with the
+/// [`Normalize`](::proxy::FramingPolicy::Normalize) framing policy changed
+/// its headers.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FramingNormalization {
+    pub(crate) content_length_removed: bool,
+    pub(crate) content_length_merged: bool,
+    pub(crate) unknown_transfer_coding: bool,
+}
+
+impl FramingNormalization {
+    /// Whether `Content-Length` was removed, since `Transfer-Encoding` was
+    /// also present.
+    pub fn content_length_removed(&self) -> bool {
+        self.content_length_removed
+    }
+
+    /// Whether duplicate `Content-Length` values were merged into one.
+    pub fn content_length_merged(&self) -> bool {
+        self.content_length_merged
+    }
+
+    /// Whether `Transfer-Encoding` contains a transfer-coding that hyper
+    /// doesn't know, and was allowed anyway.
+    pub fn unknown_transfer_coding(&self) -> bool {
+        self.unknown_transfer_coding
+    }
+}
+
+/// The form of the request-target a `Client` sends an HTTP/1 request with.
+///
+/// Adding this to the extensions of a `Request` picks the form for that
+/// request, instead of [`Builder::http1_absolute_form`](::client::Builder::http1_absolute_form)
+/// and [`Connected::proxy`](::client::connect::Connected::proxy), such as
+/// when only some requests are sent to an HTTP forward proxy.
+///
+/// It is ignored for HTTP/2.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestTarget {
+    /// Only the path and query, like `GET /foo HTTP/1.1`.
+    Origin,
+    /// The full URI, like `GET http://example.com/foo HTTP/1.1`.
+    ///
+    /// Requests to `https` URIs are still sent in origin-form, since they
+    /// would be tunneled through a proxy.
+    Absolute,
+}
+
+/// The ID of a request a server received.
+///
+/// When enabled with [`Http::request_id`](::server::conn::Http::request_id),
+/// every request has one in its extensions, and its
+/// [`Exchange`](::instrument::Exchange) in the access log does as well, so
+/// that the logs of the `Service` and of the server can be matched. An ID
+/// is either taken from a header of the request, such as `X-Request-Id`,
+/// or made up by hyper, unique to the server.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RequestId(String);
+
+impl RequestId {
+    pub(crate) fn new(id: String) -> RequestId {
+        RequestId(id)
+    }
+
+    /// The ID, as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The connection a `Client` response was received on.
+///
+/// The `Client` adds this to every response, telling whether the request
+/// was sent on a connection taken from its pool, or on one that was
+/// established for it. This helps explain latency outliers, since a new
+/// connection has to connect, and maybe do a TLS handshake, first.
+///
+/// It also tells which protocol the request was sent with, since the
+/// version of a `Response` is only what the server wrote in it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectionInfo {
+    pub(crate) reused: bool,
+    pub(crate) pool_key: Arc<String>,
+    pub(crate) id: usize,
+    pub(crate) version: Version,
+    pub(crate) alpn: Option<Bytes>,
+    pub(crate) is_proxied: bool,
+}
+
+impl ConnectionInfo {
+    /// Whether the connection was reused from the pool.
+    pub fn is_reused(&self) -> bool {
+        self.reused
+    }
+
+    /// The key of the pool the connection belongs to.
+    ///
+    /// This is the scheme and authority of the request, such as
+    /// `https://hyper.rs`.
+    pub fn pool_key(&self) -> &str {
+        &self.pool_key
+    }
+
+    /// An id of the connection, unique among those of its `Client`.
+    ///
+    /// Responses with the same id were received on the same connection.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// The HTTP version the request was sent with.
+    ///
+    /// This is `HTTP/2` for HTTP/2 connections, and the version of the
+    /// request otherwise.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// The protocol negotiated with ALPN, if the connector reported one.
+    ///
+    /// See [`Connected::alpn_protocol`](::client::connect::Connected::alpn_protocol).
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn.as_ref().map(|alpn| alpn.as_ref())
+    }
+
+    /// Whether the connection is to an HTTP proxy.
+    pub fn is_proxied(&self) -> bool {
+        self.is_proxied
+    }
+}
+
+/// When each stage of a client request happened.
+///
+/// A `Client` configured to [record timings](::client::Builder::record_timings)
+/// adds this to every request, and moves it to its response. With the
+/// lower-level [`conn`](::client::conn) API, add one made with
+/// `Timings::new` to the extensions of a request.
+///
+/// The stages are recorded as they happen, so a stage that is still to
+/// come, such as the end of a body that is being read, or that didn't
+/// apply, such as connecting, for a reused connection, is `None`. The
+/// connection stages are recorded by the connector, so those depend on it.
+///
+/// A `Client` keeps a `Timings` already in the extensions of a request, so
+/// one made with [`on_stage`](Timings::on_stage) can be used to be told of
+/// each stage of that request as it happens.
+///
+/// Clones of a `Timings` share the same record.
+#[derive(Clone)]
+pub struct Timings {
+    inner: Arc<Mutex<TimingsInner>>,
+    on_stage: Option<Arc<Fn(RequestStage) + Send + Sync>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TimingsInner {
+    start: Instant,
+    queued: Option<Instant>,
+    connection_assigned: Option<Instant>,
+    dns_resolved: Option<Instant>,
+    connected: Option<Instant>,
+    tls_handshaken: Option<Instant>,
+    headers_written: Option<Instant>,
+    request_written: Option<Instant>,
+    first_byte: Option<Instant>,
+    response_complete: Option<Instant>,
+}
+
+/// A stage of a client request, as given to
+/// [`Timings::on_stage`](Timings::on_stage).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestStage {
+    /// The `Client` was given the request, and is waiting for a connection
+    /// to send it on.
+    Queued,
+    /// A connection was taken for the request, from the pool or newly
+    /// connected.
+    ConnectionAssigned,
+    /// The head of the request was given to the connection to be written.
+    HeadersWritten,
+    /// The request, including its body, was written to the connection.
+    RequestWritten,
+    /// The head of the response was received.
+    ResponseHeaders,
+    /// The end of the response body was read.
+    ResponseComplete,
+}
+
+impl Timings {
+    /// Creates a record of timings, starting now.
+    pub fn new() -> Timings {
+        Timings {
+            inner: Arc::new(Mutex::new(TimingsInner {
+                start: Instant::now(),
+                queued: None,
+                connection_assigned: None,
+                dns_resolved: None,
+                connected: None,
+                tls_handshaken: None,
+                headers_written: None,
+                request_written: None,
+                first_byte: None,
+                response_complete: None,
+            })),
+            on_stage: None,
+        }
+    }
+
+    /// Calls `on_stage` once each stage of the request is recorded.
+    ///
+    /// It is called from the task that recorded the stage, right after the
+    /// time was recorded, so it should be quick.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hyper::{Body, Request};
+    /// use hyper::ext::{RequestStage, Timings};
+    ///
+    /// let mut req = Request::new(Body::empty());
+    /// let timings = Timings::new().on_stage(|stage| {
+    ///     if stage == RequestStage::ConnectionAssigned {
+    ///         // the time spent queued ends here
+    ///     }
+    /// });
+    /// req.extensions_mut().insert(timings);
+    /// ```
+    pub fn on_stage<F>(mut self, on_stage: F) -> Timings
+    where
+        F: Fn(RequestStage) + Send + Sync + 'static,
+    {
+        self.on_stage = Some(Arc::new(on_stage));
+        self
+    }
+
+    fn get(&self) -> TimingsInner {
+        *self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn set<F: FnOnce(&mut TimingsInner)>(&self, f: F) {
+        f(&mut *self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+
+    fn stage(&self, stage: RequestStage) {
+        if let Some(ref on_stage) = self.on_stage {
+            on_stage(stage);
+        }
+    }
+
+    /// When the request started.
+    pub fn start(&self) -> Instant {
+        self.get().start
+    }
+
+    /// When the `Client` was given the request.
+    pub fn queued(&self) -> Option<Instant> {
+        self.get().queued
+    }
+
+    /// When a connection was taken for the request.
+    pub fn connection_assigned(&self) -> Option<Instant> {
+        self.get().connection_assigned
+    }
+
+    /// When the host name of a new connection was resolved.
+    pub fn dns_resolved(&self) -> Option<Instant> {
+        self.get().dns_resolved
+    }
+
+    /// When a new connection was connected.
+    pub fn connected(&self) -> Option<Instant> {
+        self.get().connected
+    }
+
+    /// When the TLS handshake of a new connection completed.
+    pub fn tls_handshaken(&self) -> Option<Instant> {
+        self.get().tls_handshaken
+    }
+
+    /// When the head of the request was given to the connection to be
+    /// written.
+    pub fn headers_written(&self) -> Option<Instant> {
+        self.get().headers_written
+    }
+
+    /// When the request, including its body, was written to the connection.
+    pub fn request_written(&self) -> Option<Instant> {
+        self.get().request_written
+    }
+
+    /// When the head of the response was received.
+    pub fn first_byte(&self) -> Option<Instant> {
+        self.get().first_byte
+    }
+
+    /// When the end of the response body was read.
+    pub fn response_complete(&self) -> Option<Instant> {
+        self.get().response_complete
+    }
+
+    pub(crate) fn set_connect(&self, dns_resolved: Option<Instant>, connected: Option<Instant>, tls_handshaken: Option<Instant>) {
+        self.set(|t| {
+            t.dns_resolved = dns_resolved;
+            t.connected = connected;
+            t.tls_handshaken = tls_handshaken;
+        })
+    }
+
+    pub(crate) fn set_queued(&self) {
+        self.set(|t| t.queued = Some(Instant::now()));
+        self.stage(RequestStage::Queued);
+    }
+
+    pub(crate) fn set_connection_assigned(&self) {
+        self.set(|t| t.connection_assigned = Some(Instant::now()));
+        self.stage(RequestStage::ConnectionAssigned);
+    }
+
+    pub(crate) fn set_headers_written(&self) {
+        self.set(|t| t.headers_written = Some(Instant::now()));
+        self.stage(RequestStage::HeadersWritten);
+    }
+
+    pub(crate) fn set_request_written(&self) {
+        self.set(|t| t.request_written = Some(Instant::now()));
+        self.stage(RequestStage::RequestWritten);
+    }
+
+    pub(crate) fn set_first_byte(&self) {
+        self.set(|t| t.first_byte = Some(Instant::now()));
+        self.stage(RequestStage::ResponseHeaders);
+    }
+
+    pub(crate) fn set_response_complete(&self) {
+        let mut first = false;
+        self.set(|t| if t.response_complete.is_none() {
+            t.response_complete = Some(Instant::now());
+            first = true;
+        });
+        if first {
+            self.stage(RequestStage::ResponseComplete);
+        }
+    }
+}
+
+impl fmt::Debug for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let t = self.get();
+        f.debug_struct("Timings")
+            .field("start", &t.start)
+            .field("queued", &t.queued)
+            .field("connection_assigned", &t.connection_assigned)
+            .field("dns_resolved", &t.dns_resolved)
+            .field("connected", &t.connected)
+            .field("tls_handshaken", &t.tls_handshaken)
+            .field("headers_written", &t.headers_written)
+            .field("request_written", &t.request_written)
+            .field("first_byte", &t.first_byte)
+            .field("response_complete", &t.response_complete)
+            .finish()
+    }
+}
+
+/// The original casing of header names.
+///
+/// `HeaderMap` always stores names in lowercase. When a connection is
+/// configured to record or preserve header case, this records the exact
+/// spelling of each received header name. Connections preserving header
+/// case also use it to spell the names of headers when writing a message
+/// that carries one in its extensions.
+///
+/// A proxy can move the map of a received message to the message it
+/// forwards, or read the spellings and apply them some other way.
+///
+/// Names are recorded once per value, in order. So when writing the 2nd
+/// value of a header, its 2nd recorded spelling is used, falling back to
+/// the last one, or to the lowercase name if none were recorded.
+///
+/// This only affects HTTP/1, since HTTP/2 requires lowercase names.
+#[derive(Clone, Debug, Default)]
+pub struct HeaderCaseMap(HeaderMap<Bytes>);
+
+impl HeaderCaseMap {
+    /// Create an empty `HeaderCaseMap`.
+    pub fn new() -> HeaderCaseMap {
+        HeaderCaseMap::default()
+    }
+
+    /// Record a spelling of a header name, such as `X-Custom-HEADER`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `original` isn't a valid header name.
+    pub fn append(&mut self, original: &str) -> Result<(), InvalidHeaderName> {
+        let name = HeaderName::from_bytes(original.as_bytes())?;
+        self.append_raw(name, Bytes::from(original));
+        Ok(())
+    }
+
+    /// Get all the recorded spellings of a header name, in order.
+    pub fn get_all(&self, name: &HeaderName) -> GetAll {
+        GetAll {
+            inner: self.0.get_all(name).iter(),
+        }
+    }
+
+    /// Iterate over every header name and spelling, grouped by name.
+    pub fn iter(&self) -> Iter {
+        Iter {
+            inner: self.0.iter(),
+        }
+    }
+
+    /// Returns true if no spellings have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn append_raw(&mut self, name: HeaderName, original: Bytes) {
+        self.0.append(name, original);
+    }
+
+    /// The spelling to use when writing the value at `index` of a header.
+    pub(crate) fn get(&self, name: &HeaderName, index: usize) -> Option<&[u8]> {
+        let mut last = None;
+        for (i, original) in self.0.get_all(name).iter().enumerate() {
+            last = Some(original);
+            if i == index {
+                break;
+            }
+        }
+        last.map(|original| &original[..])
+    }
+}
+
+/// The order header fields were received in.
+///
+/// hyper writes the headers of a message in the iteration order of its
+/// `HeaderMap`: each name in the order it was first inserted, followed by
+/// all of its values in the order they were appended. Removing a header
+/// from a `HeaderMap` may change the position of other names. This means
+/// the fields of a received message, such as `A`, `B`, `A`, can't always be
+/// reproduced from its `HeaderMap` alone.
+///
+/// When a connection is configured to record header order, this lists the
+/// name of every received field, in order. When a client writes a request
+/// carrying one in its extensions, the headers named in it are written in
+/// that order, so a proxy can forward fields exactly as it received them.
+/// Any values not accounted for, such as headers added after receiving the
+/// message, are written afterwards, in `HeaderMap` order.
+///
+/// This only affects HTTP/1.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HeaderOrder(Vec<HeaderName>);
+
+impl HeaderOrder {
+    /// Create an empty `HeaderOrder`.
+    pub fn new() -> HeaderOrder {
+        HeaderOrder::default()
+    }
+
+    /// Append the name of the next header field.
+    pub fn push(&mut self, name: HeaderName) {
+        self.0.push(name);
+    }
+
+    /// The names of the header fields, in order.
+    pub fn names(&self) -> &[HeaderName] {
+        &self.0
+    }
+}
+
+/// An iterator of the spellings of a header name.
+///
+/// Created with [`HeaderCaseMap::get_all`](HeaderCaseMap::get_all).
+pub struct GetAll<'a> {
+    inner: ValueIter<'a, Bytes>,
+}
+
+impl<'a> Iterator for GetAll<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.inner.next().map(spelling)
+    }
+}
+
+impl<'a> fmt::Debug for GetAll<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("GetAll")
+    }
+}
+
+/// An iterator of the header names and spellings in a `HeaderCaseMap`.
+///
+/// Created with [`HeaderCaseMap::iter`](HeaderCaseMap::iter).
+pub struct Iter<'a> {
+    inner: header::Iter<'a, Bytes>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a HeaderName, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(name, original)| (name, spelling(original)))
+    }
+}
+
+impl<'a> fmt::Debug for Iter<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Iter")
+    }
+}
+
+fn spelling(original: &Bytes) -> &str {
+    // Only valid header names are recorded, which are always ASCII.
+    str::from_utf8(original).expect("header name is ASCII")
+}
+
+#[cfg(test)]
+mod tests {
+    use http::header::HeaderName;
+    use super::*;
+
+    #[test]
+    fn header_case_map_get() {
+        let mut map = HeaderCaseMap::new();
+        map.append("X-Foo").unwrap();
+        map.append("x-FOO").unwrap();
+        map.append("bad name").unwrap_err();
+
+        let name = HeaderName::from_static("x-foo");
+        assert_eq!(map.get(&name, 0), Some(&b"X-Foo"[..]));
+        assert_eq!(map.get(&name, 1), Some(&b"x-FOO"[..]));
+        assert_eq!(map.get(&name, 2), Some(&b"x-FOO"[..]));
+        assert_eq!(map.get(&HeaderName::from_static("x-bar"), 0), None);
+    }
+
+    #[test]
+    fn header_case_map_iter() {
+        let mut map = HeaderCaseMap::new();
+        assert!(map.is_empty());
+        map.append("X-Foo").unwrap();
+        map.append("Host").unwrap();
+        map.append("x-FOO").unwrap();
+
+        let name = HeaderName::from_static("x-foo");
+        assert_eq!(map.get_all(&name).collect::<Vec<_>>(), ["X-Foo", "x-FOO"]);
+
+        let all = map.iter()
+            .map(|(name, original)| (name.as_str(), original))
+            .collect::<Vec<_>>();
+        assert_eq!(all, [("x-foo", "X-Foo"), ("x-foo", "x-FOO"), ("host", "Host")]);
+    }
+}
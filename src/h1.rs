@@ -0,0 +1,54 @@
+//! HTTP/1 connections without IO.
+//!
+//! hyper's own HTTP/1 parsing and encoding, as used by its `Client` and
+//! `Server`, for connections that hyper doesn't drive itself, such as over a
+//! transport without `AsyncRead` and `AsyncWrite`, or in a blocking loop.
+//!
+//! A [`ServerConn`](ServerConn) or [`ClientConn`](ClientConn) is given the
+//! bytes read from a connection, and returns the bytes to write to it. It
+//! only keeps track of the messages of the connection, while reading,
+//! writing, and any timeouts are up to its user.
+//!
+//! # Example
+//!
+//! ```
+//! use hyper::{Response, StatusCode};
+//! use hyper::h1::{Event, ServerConn};
+//!
+//! # fn run() -> hyper::Result<()> {
+//! let mut conn = ServerConn::new();
+//! conn.receive(b"GET /hello HTTP/1.1\r\nHost: example.com\r\n\r\n");
+//!
+//! match conn.poll_event()? {
+//!     Some(Event::Head(req)) => assert_eq!(req.uri().path(), "/hello"),
+//!     _ => unreachable!("the request head was received"),
+//! }
+//! match conn.poll_event()? {
+//!     Some(Event::End(None)) => (),
+//!     _ => unreachable!("the request has no body"),
+//! }
+//!
+//! let mut res = Response::new(());
+//! *res.status_mut() = StatusCode::OK;
+//! let mut bytes = conn.send_head(res, Some(5))?;
+//! bytes.extend(conn.send_data(b"hello")?);
+//! bytes.extend(conn.send_end()?);
+//! assert!(bytes.starts_with(b"HTTP/1.1 200 OK\r\n"));
+//! assert!(bytes.ends_with(b"\r\n\r\nhello"));
+//! # Ok(())
+//! # }
+//! # fn main() { run().unwrap() }
+//! ```
+
+pub use proto::h1::sansio::{ClientConn, Event, ServerConn};
+
+pub mod fuzz {
+    //! hyper's HTTP/1 parsers, over byte slices.
+    //!
+    //! Each function runs one parser over arbitrary bytes, without a
+    //! connection, so fuzzers and property tests can target them directly.
+    //! Any input is meant to return either a result or an error, and never
+    //! to panic.
+
+    pub use proto::h1::fuzz::{decode_chunked, parse_request, parse_response};
+}
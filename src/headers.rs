@@ -2,12 +2,60 @@ use std::fmt::Write;
 
 use bytes::BytesMut;
 use http::HeaderMap;
-use http::header::{CONTENT_LENGTH, TRANSFER_ENCODING};
-use http::header::{HeaderValue, OccupiedEntry, ValueIter};
+use http::header::{self, CONTENT_LENGTH, TRANSFER_ENCODING};
+use http::header::{HeaderName, HeaderValue, OccupiedEntry, ValueIter};
 
 /// Maximum number of bytes needed to serialize a u64 into ASCII decimal.
 const MAX_DECIMAL_U64_BYTES: usize = 20;
 
+/// Fields needed for framing, routing or authentication, which are not
+/// allowed to be sent as trailers.
+///
+/// https://tools.ietf.org/html/rfc7230#section-4.1.2
+pub const FORBIDDEN_TRAILERS: [HeaderName; 10] = [
+    header::TRANSFER_ENCODING,
+    header::CONTENT_LENGTH,
+    header::HOST,
+    header::TE,
+    header::AUTHORIZATION,
+    header::SET_COOKIE,
+    header::CONTENT_ENCODING,
+    header::CONTENT_RANGE,
+    header::CONTENT_TYPE,
+    header::TRAILER,
+];
+
+/// Content lengths below this get an interned `HeaderValue`.
+const INTERNED_LENGTHS: u64 = 1024;
+
+thread_local!(static INTERNED: Interned = Interned::new());
+
+/// The values hyper adds to messages itself, made once per thread instead
+/// of for every message.
+struct Interned {
+    chunked: HeaderValue,
+    keep_alive: HeaderValue,
+    lengths: Vec<HeaderValue>,
+}
+
+impl Interned {
+    fn new() -> Interned {
+        Interned {
+            chunked: HeaderValue::from_static("chunked"),
+            keep_alive: HeaderValue::from_static("keep-alive"),
+            lengths: (0..INTERNED_LENGTHS).map(format_content_length).collect(),
+        }
+    }
+}
+
+pub fn chunked_value() -> HeaderValue {
+    INTERNED.with(|interned| interned.chunked.clone())
+}
+
+pub fn keep_alive_value() -> HeaderValue {
+    INTERNED.with(|interned| interned.keep_alive.clone())
+}
+
 pub fn connection_keep_alive(value: &HeaderValue) -> bool {
     connection_has(value, "keep-alive")
 }
@@ -19,7 +67,7 @@ pub fn connection_close(value: &HeaderValue) -> bool {
 fn connection_has(value: &HeaderValue, needle: &str) -> bool {
     if let Ok(s) = value.to_str() {
         for val in s.split(',') {
-            if eq_ascii(val.trim(), needle) {
+            if val.trim().eq_ignore_ascii_case(needle) {
                 return true;
             }
         }
@@ -69,6 +117,13 @@ pub fn content_length_parse_all_values(values: ValueIter<HeaderValue>) -> Option
 }
 
 pub fn content_length_value(len: u64) -> HeaderValue {
+    if len < INTERNED_LENGTHS {
+        return INTERNED.with(|interned| interned.lengths[len as usize].clone());
+    }
+    format_content_length(len)
+}
+
+fn format_content_length(len: u64) -> HeaderValue {
     let mut len_buf = BytesMut::with_capacity(MAX_DECIMAL_U64_BYTES);
     write!(len_buf, "{}", len)
         .expect("BytesMut can hold a decimal u64");
@@ -102,13 +157,29 @@ pub fn is_chunked_(value: &HeaderValue) -> bool {
     // chunked must always be the last encoding, according to spec
     if let Ok(s) = value.to_str() {
         if let Some(encoding) = s.rsplit(',').next() {
-            return eq_ascii(encoding.trim(), "chunked");
+            return encoding.trim().eq_ignore_ascii_case("chunked");
         }
     }
 
     false
 }
 
+/// Whether a `Transfer-Encoding` value contains a coding that isn't in the
+/// [registry](https://tools.ietf.org/html/rfc7230#section-8.4.2).
+pub fn has_unknown_coding(value: &HeaderValue) -> bool {
+    const KNOWN: &'static [&'static str] = &[
+        "chunked", "compress", "deflate", "gzip", "x-compress", "x-gzip",
+    ];
+
+    match value.to_str() {
+        Ok(s) => s.split(',')
+            .map(|coding| coding.trim())
+            .filter(|coding| !coding.is_empty())
+            .any(|coding| !KNOWN.iter().any(|known| coding.eq_ignore_ascii_case(known))),
+        Err(_) => true,
+    }
+}
+
 pub fn add_chunked(mut entry: OccupiedEntry<HeaderValue>) {
     const CHUNKED: &'static str = "chunked";
 
@@ -125,18 +196,7 @@ pub fn add_chunked(mut entry: OccupiedEntry<HeaderValue>) {
         return;
     }
 
-    entry.insert(HeaderValue::from_static(CHUNKED));
-}
-
-fn eq_ascii(left: &str, right: &str) -> bool {
-    // As of Rust 1.23, str gained this method inherently, and so the
-    // compiler says this trait is unused.
-    //
-    // TODO: Once our minimum Rust compiler version is >=1.23, this can be removed.
-    #[allow(unused, deprecated)]
-    use std::ascii::AsciiExt;
-
-    left.eq_ignore_ascii_case(right)
+    entry.insert(chunked_value());
 }
 
 #[cfg(test)]
@@ -148,4 +208,27 @@ mod tests {
             ::std::u64::MAX.to_string().len()
         );
     }
+
+    #[test]
+    fn interned_content_lengths() {
+        for &len in &[0, 7, 1023, 1024, 65_536] {
+            let value = super::content_length_value(len);
+            assert_eq!(value, super::format_content_length(len));
+            assert_eq!(value.to_str().unwrap(), len.to_string());
+        }
+        assert_eq!(super::chunked_value(), "chunked");
+        assert_eq!(super::keep_alive_value(), "keep-alive");
+    }
+
+    #[test]
+    fn has_unknown_coding() {
+        use http::header::HeaderValue;
+
+        let unknown = |s| super::has_unknown_coding(&HeaderValue::from_static(s));
+        assert!(!unknown("chunked"));
+        assert!(!unknown("gzip, CHUNKED"));
+        assert!(!unknown("x-gzip,, chunked"));
+        assert!(unknown("foo, chunked"));
+        assert!(unknown("chunked;ext=1"));
+    }
 }
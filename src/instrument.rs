@@ -0,0 +1,1323 @@
+//! Hooks for collecting metrics from clients and servers.
+//!
+//! An [`Instrumentation`](Instrumentation) given to a `Client` or `Server`
+//! builder is told about the connections, requests, and bytes they handle,
+//! so that a metrics system can count them without wrapping every IO type
+//! or `Service`. A [`Tap`](Tap) is given a copy of the bytes themselves,
+//! for debugging, and an access log hook is given an [`Exchange`](Exchange)
+//! for every request a server answered. A
+//! [`FrameObserver`](FrameObserver) is told about the control frames of
+//! HTTP/2 connections.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, BufMut};
+use futures::Poll;
+use h2::Reason;
+use http::{HeaderMap, Method, StatusCode, Uri, Version};
+use http::header::{HeaderName, HeaderValue};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use ext::RequestId;
+
+/// Callbacks for the events of a `Client` or `Server`.
+///
+/// Every method does nothing by default, so an implementation only needs
+/// to override the ones it cares about. They are called from the tasks
+/// driving connections, and so should be quick, such as incrementing a
+/// counter.
+///
+/// An `Arc<I>` is an `Instrumentation` as well, so the same
+/// implementation can be given to several builders.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use hyper::instrument::Instrumentation;
+///
+/// #[derive(Default)]
+/// struct Counters {
+///     connections: AtomicUsize,
+///     bytes_read: AtomicUsize,
+/// }
+///
+/// impl Instrumentation for Counters {
+///     fn connection_opened(&self) {
+///         self.connections.fetch_add(1, Ordering::Relaxed);
+///     }
+///
+///     fn connection_closed(&self) {
+///         self.connections.fetch_sub(1, Ordering::Relaxed);
+///     }
+///
+///     fn bytes_read(&self, n: usize) {
+///         self.bytes_read.fetch_add(n, Ordering::Relaxed);
+///     }
+/// }
+/// ```
+pub trait Instrumentation: Send + Sync + 'static {
+    /// A connection was opened.
+    ///
+    /// For a client, this is once a connection finished its handshake. For
+    /// a server, this is once an accepted connection is being served.
+    fn connection_opened(&self) {}
+
+    /// A connection, that `connection_opened` was called for, was closed.
+    fn connection_closed(&self) {}
+
+    /// A request with this method was started.
+    ///
+    /// For a client, this is when a request is taken by a connection to be
+    /// sent. For a server, this is when a request was received.
+    fn request_started(&self, _method: &Method) {}
+
+    /// A request, that `request_started` was called for, finished.
+    ///
+    /// For a client, a request is finished once its response head was
+    /// received. For a server, it's finished once its response was
+    /// written. `status` is the status of the response, or `None` if there
+    /// was no response, such as when the connection or `Service` failed.
+    fn request_finished(&self, _status: Option<StatusCode>, _elapsed: Duration) {}
+
+    /// This many bytes were read from a connection.
+    fn bytes_read(&self, _n: usize) {}
+
+    /// This many bytes were written to a connection.
+    fn bytes_written(&self, _n: usize) {}
+
+    /// The `Client` took a connection for a request.
+    ///
+    /// `reused` is true if it was an idle connection from the pool, and
+    /// false if the connection was established for it.
+    fn pool_checkout(&self, _reused: bool) {}
+
+    /// A connection was put back in the `Client`'s pool, to be reused.
+    fn pool_idle(&self) {}
+
+    /// An idle connection was removed from the `Client`'s pool, because it
+    /// expired or was closed.
+    fn pool_evicted(&self) {}
+
+    /// Something happened in the `Client`'s pool.
+    ///
+    /// This is told more about each event than the other `pool_*` methods,
+    /// such as which host it was for.
+    fn pool_event(&self, _event: PoolEvent) {}
+
+    /// A connection told its peer about an error.
+    ///
+    /// This is meant to attribute the failures of a conformance suite,
+    /// such as h2spec, to the checks that caused them. The HTTP/2 errors
+    /// are found in the frames sent, which are only parsed with
+    /// [`Http::strict_conformance`](::server::conn::Http::strict_conformance)
+    /// or a [`FrameObserver`](FrameObserver) set.
+    fn error_sent(&self, _error: &SentError) {}
+}
+
+/// An error a connection told its peer about, as given to
+/// [`Instrumentation::error_sent`](Instrumentation::error_sent).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SentError {
+    /// An HTTP/1 error response, written by hyper for a request it
+    /// couldn't parse, such as a `400 Bad Request`.
+    Status(StatusCode),
+    /// An HTTP/2 `RST_STREAM` frame, with an error code other than
+    /// `NO_ERROR`.
+    Reset {
+        /// The stream that was reset.
+        stream_id: u32,
+        /// The error code.
+        reason: Reason,
+    },
+    /// An HTTP/2 `GOAWAY` frame, with an error code other than `NO_ERROR`.
+    GoAway {
+        /// The last stream that may have been processed.
+        last_stream_id: u32,
+        /// The error code.
+        reason: Reason,
+    },
+}
+
+/// An event of a `Client`'s pool, as given to
+/// [`Instrumentation::pool_event`](Instrumentation::pool_event).
+///
+/// Each event has the key of the pool it is for, as in
+/// [`ConnectionInfo::pool_key`](::ext::ConnectionInfo::pool_key).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PoolEvent<'a> {
+    /// A new connection was added to the pool.
+    Created(&'a str),
+    /// An idle connection was taken from the pool for a request.
+    Reused(&'a str),
+    /// An idle connection was removed, since it was idle for longer than
+    /// the idle timeout.
+    IdleExpired(&'a str),
+    /// An idle connection was removed, since it was closed.
+    Evicted(&'a str),
+    /// A request waited this long for a connection, whether reused or new.
+    CheckoutWaited(&'a str, Duration),
+}
+
+impl<I: Instrumentation + ?Sized> Instrumentation for Arc<I> {
+    fn connection_opened(&self) {
+        (**self).connection_opened()
+    }
+
+    fn connection_closed(&self) {
+        (**self).connection_closed()
+    }
+
+    fn request_started(&self, method: &Method) {
+        (**self).request_started(method)
+    }
+
+    fn request_finished(&self, status: Option<StatusCode>, elapsed: Duration) {
+        (**self).request_finished(status, elapsed)
+    }
+
+    fn bytes_read(&self, n: usize) {
+        (**self).bytes_read(n)
+    }
+
+    fn bytes_written(&self, n: usize) {
+        (**self).bytes_written(n)
+    }
+
+    fn pool_checkout(&self, reused: bool) {
+        (**self).pool_checkout(reused)
+    }
+
+    fn pool_idle(&self) {
+        (**self).pool_idle()
+    }
+
+    fn pool_evicted(&self) {
+        (**self).pool_evicted()
+    }
+
+    fn pool_event(&self, event: PoolEvent) {
+        (**self).pool_event(event)
+    }
+
+    fn error_sent(&self, error: &SentError) {
+        (**self).error_sent(error)
+    }
+}
+
+/// A debugging hook that is given a copy of every byte read from and
+/// written to a connection.
+///
+/// The bytes are those hyper reads and writes, so for a connection over
+/// TLS they are the decrypted HTTP messages and HTTP/2 frames, which makes
+/// this a way to inspect the traffic without any packet capture tools.
+///
+/// Copying the bytes has a cost, and writes are no longer vectored when a
+/// tap is set, so this is meant for debugging rather than production use.
+///
+/// An `Arc<T>` is a `Tap` as well.
+///
+/// # Example
+///
+/// ```
+/// use hyper::instrument::Tap;
+///
+/// struct Dump;
+///
+/// impl Tap for Dump {
+///     fn data_read(&self, data: &[u8]) {
+///         eprintln!("<< {:?}", String::from_utf8_lossy(data));
+///     }
+///
+///     fn data_written(&self, data: &[u8]) {
+///         eprintln!(">> {:?}", String::from_utf8_lossy(data));
+///     }
+/// }
+/// ```
+pub trait Tap: Send + Sync + 'static {
+    /// These bytes were read from a connection.
+    fn data_read(&self, _data: &[u8]) {}
+
+    /// These bytes were written to a connection.
+    fn data_written(&self, _data: &[u8]) {}
+}
+
+impl<T: Tap + ?Sized> Tap for Arc<T> {
+    fn data_read(&self, data: &[u8]) {
+        (**self).data_read(data)
+    }
+
+    fn data_written(&self, data: &[u8]) {
+        (**self).data_written(data)
+    }
+}
+
+/// A debugging hook that is told about the control frames of HTTP/2
+/// connections, such as which settings a peer sent, or why it went away.
+///
+/// Like a [`Tap`](Tap), this needs the bytes of the connection, so writes
+/// are no longer vectored when one is set.
+///
+/// An `Arc<O>` is a `FrameObserver` as well.
+pub trait FrameObserver: Send + Sync + 'static {
+    /// This frame was received from the peer.
+    fn frame_received(&self, _frame: &ControlFrame) {}
+
+    /// This frame was sent to the peer.
+    fn frame_sent(&self, _frame: &ControlFrame) {}
+}
+
+impl<O: FrameObserver + ?Sized> FrameObserver for Arc<O> {
+    fn frame_received(&self, frame: &ControlFrame) {
+        (**self).frame_received(frame)
+    }
+
+    fn frame_sent(&self, frame: &ControlFrame) {
+        (**self).frame_sent(frame)
+    }
+}
+
+/// An HTTP/2 control frame, as given to a [`FrameObserver`](FrameObserver).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ControlFrame {
+    /// A `SETTINGS` frame, with the identifier and value of each setting.
+    Settings {
+        /// Whether this acknowledges the peer's settings.
+        ack: bool,
+        /// The settings, in the order they were in the frame.
+        params: Vec<(u16, u32)>,
+    },
+    /// A `GOAWAY` frame.
+    GoAway {
+        /// The last stream the sender may have processed.
+        last_stream_id: u32,
+        /// Why the connection is being closed.
+        reason: Reason,
+        /// The opaque debug data, which is often a message.
+        debug_data: Vec<u8>,
+    },
+    /// A `PING` frame.
+    Ping {
+        /// Whether this answers a ping.
+        ack: bool,
+        /// The opaque data of the ping.
+        payload: [u8; 8],
+    },
+    /// A `WINDOW_UPDATE` frame, for the connection if `stream_id` is 0.
+    WindowUpdate {
+        /// The stream whose window grew.
+        stream_id: u32,
+        /// How much the window grew.
+        increment: u32,
+    },
+    /// A `RST_STREAM` frame.
+    Reset {
+        /// The stream that was reset.
+        stream_id: u32,
+        /// Why the stream was reset.
+        reason: Reason,
+    },
+}
+
+/// A request a server answered, as given to an access log hook.
+///
+/// See [`Http::access_log`](::server::conn::Http::access_log).
+#[derive(Debug)]
+pub struct Exchange {
+    method: Method,
+    uri: Uri,
+    version: Version,
+    status: Option<StatusCode>,
+    body_read: usize,
+    body_written: usize,
+    elapsed: Duration,
+    remote_addr: Option<SocketAddr>,
+    connection: ConnectionStats,
+    request_id: Option<RequestId>,
+}
+
+impl Exchange {
+    /// The method of the request.
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// The target of the request.
+    pub fn uri(&self) -> &Uri {
+        &self.uri
+    }
+
+    /// The version of the request.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// The status of the response, or `None` if there was no response,
+    /// such as when the connection or `Service` failed.
+    pub fn status(&self) -> Option<StatusCode> {
+        self.status
+    }
+
+    /// The bytes of the request body read, by the time the response was
+    /// written.
+    pub fn bytes_read(&self) -> usize {
+        self.body_read
+    }
+
+    /// The bytes of the response body written.
+    pub fn bytes_written(&self) -> usize {
+        self.body_written
+    }
+
+    /// How long it took from receiving the request to writing its response.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// The address of the client.
+    ///
+    /// This is the source address of the PROXY protocol header, when it is
+    /// enabled, or else the remote address of connections accepted by a
+    /// `Server`. It's `None` for connections given to
+    /// `Http::serve_connection`, which doesn't know their address.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    /// The counters of the connection the request was received on, so far.
+    pub fn connection(&self) -> &ConnectionStats {
+        &self.connection
+    }
+
+    /// The ID of the request, if request IDs are enabled.
+    ///
+    /// See [`Http::request_id`](::server::conn::Http::request_id).
+    pub fn request_id(&self) -> Option<&RequestId> {
+        self.request_id.as_ref()
+    }
+}
+
+/// The counters of a server connection.
+///
+/// This is a handle to counters that are updated as the connection is
+/// served, so one taken with
+/// [`Connection::stats`](::server::conn::Connection::stats) can be kept
+/// after the `Connection` is spawned.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionStats {
+    inner: Arc<Stats>,
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    bytes_read: AtomicUsize,
+    bytes_written: AtomicUsize,
+    requests: AtomicUsize,
+}
+
+impl ConnectionStats {
+    /// The bytes read from the connection.
+    pub fn bytes_read(&self) -> usize {
+        self.inner.bytes_read.load(Ordering::Relaxed)
+    }
+
+    /// The bytes written to the connection.
+    pub fn bytes_written(&self) -> usize {
+        self.inner.bytes_written.load(Ordering::Relaxed)
+    }
+
+    /// The requests received on the connection.
+    pub fn requests(&self) -> usize {
+        self.inner.requests.load(Ordering::Relaxed)
+    }
+}
+
+/// The counters of a `Client`, as taken by
+/// [`Client::stats`](::Client::stats).
+///
+/// These cover every clone of the `Client`, since it was built.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClientStats {
+    requests: usize,
+    retries: usize,
+    connect_errors: usize,
+    responses: [usize; 5],
+    bytes_read: usize,
+    bytes_written: usize,
+}
+
+impl ClientStats {
+    /// The requests the `Client` sent, not counting retries.
+    pub fn requests(&self) -> usize {
+        self.requests
+    }
+
+    /// The times a request was sent again, since its connection closed
+    /// before it could be.
+    pub fn retries(&self) -> usize {
+        self.retries
+    }
+
+    /// The times connecting failed.
+    pub fn connect_errors(&self) -> usize {
+        self.connect_errors
+    }
+
+    /// The responses received with a `1xx` status.
+    pub fn informational_responses(&self) -> usize {
+        self.responses[0]
+    }
+
+    /// The responses received with a `2xx` status.
+    pub fn success_responses(&self) -> usize {
+        self.responses[1]
+    }
+
+    /// The responses received with a `3xx` status.
+    pub fn redirection_responses(&self) -> usize {
+        self.responses[2]
+    }
+
+    /// The responses received with a `4xx` status.
+    pub fn client_error_responses(&self) -> usize {
+        self.responses[3]
+    }
+
+    /// The responses received with a `5xx` status.
+    pub fn server_error_responses(&self) -> usize {
+        self.responses[4]
+    }
+
+    /// The bytes read from the `Client`'s connections.
+    pub fn bytes_read(&self) -> usize {
+        self.bytes_read
+    }
+
+    /// The bytes written to the `Client`'s connections.
+    pub fn bytes_written(&self) -> usize {
+        self.bytes_written
+    }
+}
+
+/// The counters behind `ClientStats`, shared by the clones of a `Client`.
+#[derive(Debug, Default)]
+pub(crate) struct ClientCounters {
+    requests: AtomicUsize,
+    retries: AtomicUsize,
+    connect_errors: AtomicUsize,
+    responses: [AtomicUsize; 5],
+}
+
+impl ClientCounters {
+    pub(crate) fn request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn connect_error(&self) {
+        self.connect_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn response(&self, status: StatusCode) {
+        let class = (status.as_u16() / 100) as usize;
+        if class >= 1 && class <= 5 {
+            self.responses[class - 1].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Takes a snapshot, with the bytes counted in `bytes`.
+    pub(crate) fn snapshot(&self, bytes: &ConnectionStats) -> ClientStats {
+        let mut responses = [0; 5];
+        for (n, count) in responses.iter_mut().zip(self.responses.iter()) {
+            *n = count.load(Ordering::Relaxed);
+        }
+        ClientStats {
+            requests: self.requests.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            connect_errors: self.connect_errors.load(Ordering::Relaxed),
+            responses: responses,
+            bytes_read: bytes.bytes_read(),
+            bytes_written: bytes.bytes_written(),
+        }
+    }
+}
+
+type AccessLog = Fn(&Exchange) + Send + Sync;
+
+/// A count of body bytes, shared with the body reading them.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ByteCount(Arc<AtomicUsize>);
+
+impl ByteCount {
+    pub(crate) fn add(&self, n: usize) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A shared `Instrumentation`, `Tap`, `FrameObserver` and access log, as
+/// kept by builders and connections.
+#[derive(Clone, Default)]
+pub(crate) struct Instrument {
+    instrumentation: Option<Arc<Instrumentation>>,
+    tap: Option<Arc<Tap>>,
+    frames: Option<Arc<FrameObserver>>,
+    access_log: Option<Arc<AccessLog>>,
+    /// Whether HTTP/2 frames are parsed even without a `FrameObserver`,
+    /// to report the errors sent.
+    parse_frames: bool,
+    /// The address of the client of a server connection.
+    remote_addr: Option<SocketAddr>,
+    /// The counters of a server connection.
+    stats: Option<ConnectionStats>,
+    request_ids: Option<RequestIds>,
+}
+
+/// Makes the `RequestId`s of a server's requests.
+#[derive(Clone)]
+struct RequestIds {
+    /// The header an ID is adopted from, and echoed in.
+    header: Option<HeaderName>,
+    /// Keeps the IDs of different servers and processes apart.
+    prefix: Arc<str>,
+    next: Arc<AtomicUsize>,
+}
+
+/// Longer adopted IDs are replaced, so that a peer can't make logs huge.
+const MAX_ADOPTED_ID_LEN: usize = 128;
+
+impl RequestIds {
+    fn new(header: Option<HeaderName>) -> RequestIds {
+        let now = ::time::get_time();
+        RequestIds {
+            header: header,
+            prefix: format!("{:x}{:08x}", now.sec, now.nsec).into(),
+            next: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn id_for(&self, headers: &HeaderMap) -> RequestId {
+        let adopted = self.header.as_ref()
+            .and_then(|name| headers.get(name))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|id| if !id.is_empty() && id.len() <= MAX_ADOPTED_ID_LEN { Some(id) } else { None });
+        if let Some(id) = adopted {
+            return RequestId::new(id.to_owned());
+        }
+        let n = self.next.fetch_add(1, Ordering::Relaxed);
+        RequestId::new(format!("{}-{}", self.prefix, n))
+    }
+}
+
+impl Instrument {
+    pub(crate) fn set_instrumentation<I: Instrumentation>(&mut self, instrumentation: I) {
+        self.instrumentation = Some(Arc::new(instrumentation));
+    }
+
+    pub(crate) fn set_tap<T: Tap>(&mut self, tap: T) {
+        self.tap = Some(Arc::new(tap));
+    }
+
+    pub(crate) fn set_frame_observer<O: FrameObserver>(&mut self, observer: O) {
+        self.frames = Some(Arc::new(observer));
+    }
+
+    pub(crate) fn set_parse_frames(&mut self, enabled: bool) {
+        self.parse_frames = enabled;
+    }
+
+    pub(crate) fn set_access_log<F>(&mut self, access_log: F)
+    where
+        F: Fn(&Exchange) + Send + Sync + 'static,
+    {
+        self.access_log = Some(Arc::new(access_log));
+    }
+
+    pub(crate) fn set_request_ids(&mut self, header: Option<HeaderName>) {
+        self.request_ids = Some(RequestIds::new(header));
+    }
+
+    pub(crate) fn set_remote_addr(&mut self, addr: SocketAddr) {
+        self.remote_addr = Some(addr);
+    }
+
+    /// Returns an `Instrument` which counts what it's used for, such as a
+    /// new server connection, or all the connections of a `Client`.
+    pub(crate) fn with_stats(instrument: &Option<Instrument>) -> Instrument {
+        let mut instrument = instrument.clone().unwrap_or_default();
+        instrument.stats = Some(ConnectionStats::default());
+        instrument
+    }
+
+    pub(crate) fn stats(&self) -> ConnectionStats {
+        self.stats.clone().unwrap_or_default()
+    }
+
+    /// Reports a connection opened, and closed once the guard is dropped.
+    pub(crate) fn connection(&self) -> ConnectionGuard {
+        if let Some(ref instrumentation) = self.instrumentation {
+            instrumentation.connection_opened();
+        }
+        ConnectionGuard(self.clone())
+    }
+
+    /// Reports a request started, and finished once the guard is dropped.
+    pub(crate) fn request(&self, method: &Method) -> RequestGuard {
+        if let Some(ref instrumentation) = self.instrumentation {
+            instrumentation.request_started(method);
+        }
+        RequestGuard {
+            instrument: self.clone(),
+            started: Instant::now(),
+            status: None,
+            exchange: None,
+            request_id: None,
+        }
+    }
+
+    /// Like `request`, for a request received by a server, which is given
+    /// to the access log as well.
+    ///
+    /// The request is given an ID, from its `headers` when it has one, if
+    /// request IDs are enabled.
+    pub(crate) fn exchange(&self, method: &Method, uri: &Uri, version: Version, headers: &HeaderMap) -> RequestGuard {
+        if let Some(ref stats) = self.stats {
+            stats.inner.requests.fetch_add(1, Ordering::Relaxed);
+        }
+        let mut guard = self.request(method);
+        guard.request_id = self.request_ids.as_ref().map(|ids| ids.id_for(headers));
+        if self.access_log.is_some() {
+            guard.exchange = Some(Box::new(ExchangeState {
+                method: method.clone(),
+                uri: uri.clone(),
+                version: version,
+                body_read: ByteCount::default(),
+                body_written: 0,
+            }));
+        }
+        guard
+    }
+
+    /// Whether the bytes themselves are needed, rather than only their
+    /// count.
+    pub(crate) fn is_tapped(&self) -> bool {
+        self.tap.is_some()
+    }
+
+    pub(crate) fn bytes_read(&self, n: usize) {
+        if let Some(ref instrumentation) = self.instrumentation {
+            instrumentation.bytes_read(n);
+        }
+        if let Some(ref stats) = self.stats {
+            stats.inner.bytes_read.fetch_add(n, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn bytes_written(&self, n: usize) {
+        if let Some(ref instrumentation) = self.instrumentation {
+            instrumentation.bytes_written(n);
+        }
+        if let Some(ref stats) = self.stats {
+            stats.inner.bytes_written.fetch_add(n, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn error_sent(&self, error: SentError) {
+        if let Some(ref instrumentation) = self.instrumentation {
+            instrumentation.error_sent(&error);
+        }
+    }
+
+    fn frame_received(&self, frame: &ControlFrame) {
+        if let Some(ref observer) = self.frames {
+            observer.frame_received(frame);
+        }
+    }
+
+    fn frame_sent(&self, frame: &ControlFrame) {
+        if let Some(ref observer) = self.frames {
+            observer.frame_sent(frame);
+        }
+        match *frame {
+            ControlFrame::Reset { stream_id, reason } if reason != Reason::NO_ERROR => {
+                self.error_sent(SentError::Reset {
+                    stream_id: stream_id,
+                    reason: reason,
+                });
+            },
+            ControlFrame::GoAway { last_stream_id, reason, .. } if reason != Reason::NO_ERROR => {
+                self.error_sent(SentError::GoAway {
+                    last_stream_id: last_stream_id,
+                    reason: reason,
+                });
+            },
+            _ => (),
+        }
+    }
+
+    /// Reports the bytes read, to the tap as well.
+    pub(crate) fn data_read(&self, data: &[u8]) {
+        self.bytes_read(data.len());
+        if let Some(ref tap) = self.tap {
+            tap.data_read(data);
+        }
+    }
+
+    /// Reports the bytes written, to the tap as well.
+    pub(crate) fn data_written(&self, data: &[u8]) {
+        self.bytes_written(data.len());
+        if let Some(ref tap) = self.tap {
+            tap.data_written(data);
+        }
+    }
+
+    pub(crate) fn pool_checkout(&self, reused: bool) {
+        if let Some(ref instrumentation) = self.instrumentation {
+            instrumentation.pool_checkout(reused);
+        }
+    }
+
+    pub(crate) fn pool_idle(&self) {
+        if let Some(ref instrumentation) = self.instrumentation {
+            instrumentation.pool_idle();
+        }
+    }
+
+    pub(crate) fn pool_evicted(&self) {
+        if let Some(ref instrumentation) = self.instrumentation {
+            instrumentation.pool_evicted();
+        }
+    }
+
+    pub(crate) fn pool_event(&self, event: PoolEvent) {
+        if let Some(ref instrumentation) = self.instrumentation {
+            instrumentation.pool_event(event);
+        }
+    }
+}
+
+impl fmt::Debug for Instrument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Instrument")
+            .finish()
+    }
+}
+
+pub(crate) struct ConnectionGuard(Instrument);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if let Some(ref instrumentation) = (self.0).instrumentation {
+            instrumentation.connection_closed();
+        }
+    }
+}
+
+pub(crate) struct RequestGuard {
+    instrument: Instrument,
+    started: Instant,
+    status: Option<StatusCode>,
+    exchange: Option<Box<ExchangeState>>,
+    request_id: Option<RequestId>,
+}
+
+/// What an `Exchange` is made of, until its response was written.
+struct ExchangeState {
+    method: Method,
+    uri: Uri,
+    version: Version,
+    body_read: ByteCount,
+    body_written: usize,
+}
+
+impl RequestGuard {
+    pub(crate) fn set_status(&mut self, status: StatusCode) {
+        self.status = Some(status);
+    }
+
+    /// The ID given to the request, to insert into its extensions.
+    pub(crate) fn request_id(&self) -> Option<&RequestId> {
+        self.request_id.as_ref()
+    }
+
+    /// Echoes the ID of the request in its response, if the IDs have a
+    /// header and the response doesn't have it already.
+    pub(crate) fn set_request_id_header(&self, headers: &mut HeaderMap) {
+        let header = self.instrument.request_ids.as_ref().and_then(|ids| ids.header.as_ref());
+        if let (Some(name), Some(id)) = (header, self.request_id.as_ref()) {
+            if !headers.contains_key(name) {
+                if let Ok(value) = HeaderValue::from_str(id.as_str()) {
+                    headers.insert(name.clone(), value);
+                }
+            }
+        }
+    }
+
+    /// The count of request body bytes to keep, if they are logged.
+    pub(crate) fn body_read(&self) -> Option<ByteCount> {
+        self.exchange.as_ref().map(|exchange| exchange.body_read.clone())
+    }
+
+    pub(crate) fn body_written(&mut self, n: usize) {
+        if let Some(ref mut exchange) = self.exchange {
+            exchange.body_written += n;
+        }
+    }
+
+    pub(crate) fn set_body_written(&mut self, n: usize) {
+        if let Some(ref mut exchange) = self.exchange {
+            exchange.body_written = n;
+        }
+    }
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed();
+        if let Some(ref instrumentation) = self.instrument.instrumentation {
+            instrumentation.request_finished(self.status, elapsed);
+        }
+        if let (Some(exchange), Some(access_log)) = (self.exchange.take(), self.instrument.access_log.as_ref()) {
+            let exchange = *exchange;
+            (**access_log)(&Exchange {
+                method: exchange.method,
+                uri: exchange.uri,
+                version: exchange.version,
+                status: self.status,
+                body_read: exchange.body_read.get(),
+                body_written: exchange.body_written,
+                elapsed: elapsed,
+                remote_addr: self.instrument.remote_addr,
+                connection: self.instrument.stats(),
+                request_id: self.request_id.take(),
+            });
+        }
+    }
+}
+
+/// An IO that reports the bytes read and written through it.
+///
+/// The HTTP/1 connections count their bytes themselves, this is for the
+/// IO given to `h2`.
+#[derive(Debug)]
+pub(crate) struct Counted<T> {
+    inner: T,
+    instrument: Option<Instrument>,
+    frames: Option<Frames>,
+}
+
+/// The frames read and written so far, for a `FrameObserver`, or the
+/// errors sent.
+struct Frames {
+    read: FrameParser,
+    written: FrameParser,
+}
+
+impl<T> Counted<T> {
+    pub(crate) fn new(inner: T, instrument: Option<Instrument>) -> Counted<T> {
+        let frames = instrument
+            .as_ref()
+            .and_then(|instrument| if instrument.frames.is_some() || instrument.parse_frames {
+                Some(Frames {
+                    read: FrameParser::new(),
+                    written: FrameParser::new(),
+                })
+            } else {
+                None
+            });
+        Counted {
+            inner: inner,
+            instrument: instrument,
+            frames: frames,
+        }
+    }
+
+    /// Passes bytes that were read before this wrapped the IO to the
+    /// `FrameObserver`, without counting them again.
+    pub(crate) fn already_read(&mut self, data: &[u8]) {
+        if let (Some(frames), Some(instrument)) = (self.frames.as_mut(), self.instrument.as_ref()) {
+            frames.read.parse(data, |frame| instrument.frame_received(&frame));
+        }
+    }
+
+    fn is_tapped(&self) -> bool {
+        self.frames.is_some() ||
+            self.instrument.as_ref().map(Instrument::is_tapped).unwrap_or(false)
+    }
+}
+
+impl<T: Read> Read for Counted<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(ref instrument) = self.instrument {
+            instrument.data_read(&buf[..n]);
+        }
+        self.already_read(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for Counted<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if let Some(ref instrument) = self.instrument {
+            instrument.data_written(&buf[..n]);
+        }
+        if let (Some(frames), Some(instrument)) = (self.frames.as_mut(), self.instrument.as_ref()) {
+            frames.written.parse(&buf[..n], |frame| instrument.frame_sent(&frame));
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for Counted<T> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.inner.prepare_uninitialized_buffer(buf)
+    }
+
+    fn read_buf<B: BufMut>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        if self.is_tapped() {
+            // the tap needs the bytes, which are only known when reading
+            // into a slice
+            let n = unsafe {
+                let n = {
+                    let bytes = buf.bytes_mut();
+                    self.prepare_uninitialized_buffer(bytes);
+                    try_nb!(self.read(bytes))
+                };
+                buf.advance_mut(n);
+                n
+            };
+            return Ok(n.into());
+        }
+        let n = try_ready!(self.inner.read_buf(buf));
+        if let Some(ref instrument) = self.instrument {
+            instrument.bytes_read(n);
+        }
+        Ok(n.into())
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for Counted<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        AsyncWrite::shutdown(&mut self.inner)
+    }
+
+    fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        if self.is_tapped() {
+            let n = try_nb!(self.write(buf.bytes()));
+            buf.advance(n);
+            return Ok(n.into());
+        }
+        let n = try_ready!(self.inner.write_buf(buf));
+        if let Some(ref instrument) = self.instrument {
+            instrument.bytes_written(n);
+        }
+        Ok(n.into())
+    }
+}
+
+impl fmt::Debug for Frames {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Frames")
+            .finish()
+    }
+}
+
+const PREFACE: &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Larger frames, which can only be `GOAWAY`s with a lot of debug data,
+/// aren't reported.
+const MAX_KEPT_PAYLOAD: usize = 16_384;
+
+/// Finds the control frames in the bytes of one direction of an HTTP/2
+/// connection.
+#[derive(Debug)]
+struct FrameParser {
+    /// How much of the client preface was seen, until it was all seen or
+    /// the bytes didn't start with it.
+    preface: Option<usize>,
+    head: [u8; 9],
+    head_len: usize,
+    /// The bytes of the current frame's payload that are still to come.
+    remaining: usize,
+    payload: Option<Vec<u8>>,
+    /// Set when the bytes stopped making sense, and nothing more is parsed.
+    failed: bool,
+}
+
+impl FrameParser {
+    fn new() -> FrameParser {
+        FrameParser {
+            preface: Some(0),
+            head: [0; 9],
+            head_len: 0,
+            remaining: 0,
+            payload: None,
+            failed: false,
+        }
+    }
+
+    fn parse<F: FnMut(ControlFrame)>(&mut self, mut data: &[u8], mut on_frame: F) {
+        while !data.is_empty() && !self.failed {
+            if let Some(seen) = self.preface {
+                if data[0] == PREFACE[seen] {
+                    self.preface = if seen + 1 == PREFACE.len() { None } else { Some(seen + 1) };
+                    data = &data[1..];
+                } else if seen == 0 {
+                    // the server side doesn't start with the preface
+                    self.preface = None;
+                } else {
+                    self.failed = true;
+                }
+                continue;
+            }
+
+            if self.head_len < self.head.len() {
+                let n = ::std::cmp::min(self.head.len() - self.head_len, data.len());
+                self.head[self.head_len..self.head_len + n].copy_from_slice(&data[..n]);
+                self.head_len += n;
+                data = &data[n..];
+                if self.head_len < self.head.len() {
+                    return;
+                }
+                self.remaining = read_u24(&self.head[..3]);
+                self.payload = match self.head[3] {
+                    3 | 4 | 6 | 7 | 8 if self.remaining <= MAX_KEPT_PAYLOAD => {
+                        Some(Vec::with_capacity(self.remaining))
+                    },
+                    _ => None,
+                };
+            }
+
+            let n = ::std::cmp::min(self.remaining, data.len());
+            if let Some(ref mut payload) = self.payload {
+                payload.extend_from_slice(&data[..n]);
+            }
+            self.remaining -= n;
+            data = &data[n..];
+
+            if self.remaining == 0 {
+                self.head_len = 0;
+                if let Some(payload) = self.payload.take() {
+                    if let Some(frame) = control_frame(&self.head, &payload) {
+                        on_frame(frame);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn control_frame(head: &[u8; 9], payload: &[u8]) -> Option<ControlFrame> {
+    let ack = head[4] & 0x1 == 0x1;
+    let stream_id = read_u32(&head[5..]) & 0x7FFF_FFFF;
+    let frame = match head[3] {
+        3 if payload.len() == 4 => ControlFrame::Reset {
+            stream_id: stream_id,
+            reason: Reason::from(read_u32(payload)),
+        },
+        4 if payload.len() % 6 == 0 => ControlFrame::Settings {
+            ack: ack,
+            params: payload
+                .chunks(6)
+                .map(|param| (((param[0] as u16) << 8) | param[1] as u16, read_u32(&param[2..])))
+                .collect(),
+        },
+        6 if payload.len() == 8 => {
+            let mut data = [0; 8];
+            data.copy_from_slice(payload);
+            ControlFrame::Ping {
+                ack: ack,
+                payload: data,
+            }
+        },
+        7 if payload.len() >= 8 => ControlFrame::GoAway {
+            last_stream_id: read_u32(payload) & 0x7FFF_FFFF,
+            reason: Reason::from(read_u32(&payload[4..])),
+            debug_data: payload[8..].to_vec(),
+        },
+        8 if payload.len() == 4 => ControlFrame::WindowUpdate {
+            stream_id: stream_id,
+            increment: read_u32(payload) & 0x7FFF_FFFF,
+        },
+        _ => return None,
+    };
+    Some(frame)
+}
+
+fn read_u24(buf: &[u8]) -> usize {
+    ((buf[0] as usize) << 16) | ((buf[1] as usize) << 8) | buf[2] as usize
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    ((buf[0] as u32) << 24) | ((buf[1] as u32) << 16) | ((buf[2] as u32) << 8) | buf[3] as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Events(Mutex<Vec<String>>);
+
+    impl Instrumentation for Events {
+        fn connection_opened(&self) {
+            self.0.lock().unwrap().push("opened".to_owned());
+        }
+
+        fn connection_closed(&self) {
+            self.0.lock().unwrap().push("closed".to_owned());
+        }
+
+        fn request_started(&self, method: &Method) {
+            self.0.lock().unwrap().push(format!("started {}", method));
+        }
+
+        fn request_finished(&self, status: Option<StatusCode>, _elapsed: Duration) {
+            self.0.lock().unwrap().push(format!("finished {:?}", status.map(|s| s.as_u16())));
+        }
+
+        fn bytes_read(&self, n: usize) {
+            self.0.lock().unwrap().push(format!("read {}", n));
+        }
+
+        fn bytes_written(&self, n: usize) {
+            self.0.lock().unwrap().push(format!("written {}", n));
+        }
+    }
+
+    impl Tap for Events {
+        fn data_read(&self, data: &[u8]) {
+            self.0.lock().unwrap().push(format!("tap read {:?}", String::from_utf8_lossy(data)));
+        }
+
+        fn data_written(&self, data: &[u8]) {
+            self.0.lock().unwrap().push(format!("tap written {:?}", String::from_utf8_lossy(data)));
+        }
+    }
+
+    fn instrument(events: &Arc<Events>) -> Instrument {
+        let mut instrument = Instrument::default();
+        instrument.set_instrumentation(events.clone());
+        instrument
+    }
+
+    #[test]
+    fn guards_report_when_dropped() {
+        let events = Arc::new(Events::default());
+        let instrument = instrument(&events);
+
+        let conn = instrument.connection();
+        let mut req = instrument.request(&Method::GET);
+        req.set_status(StatusCode::OK);
+        drop(req);
+        drop(instrument.request(&Method::POST));
+        drop(conn);
+
+        assert_eq!(*events.0.lock().unwrap(), [
+            "opened",
+            "started GET",
+            "finished Some(200)",
+            "started POST",
+            "finished None",
+            "closed",
+        ]);
+    }
+
+    #[test]
+    fn counted_io() {
+        let events = Arc::new(Events::default());
+        let mut io = Counted::new(io::Cursor::new(b"hello".to_vec()), Some(instrument(&events)));
+
+        let mut buf = [0; 3];
+        io.read(&mut buf).unwrap();
+        io.write(b"ab").unwrap();
+        assert_eq!(*events.0.lock().unwrap(), ["read 3", "written 2"]);
+    }
+
+    #[test]
+    fn tapped_io() {
+        let events = Arc::new(Events::default());
+        let mut instrument = instrument(&events);
+        instrument.set_tap(events.clone());
+        let mut io = Counted::new(io::Cursor::new(b"hello".to_vec()), Some(instrument));
+
+        let mut buf = [0; 3];
+        io.read_buf(&mut io::Cursor::new(&mut buf[..])).unwrap();
+        io.write_buf(&mut io::Cursor::new(b"ab")).unwrap();
+        assert_eq!(*events.0.lock().unwrap(), [
+            "read 3",
+            "tap read \"hel\"",
+            "written 2",
+            "tap written \"ab\"",
+        ]);
+    }
+
+    #[test]
+    fn parse_control_frames() {
+        let mut bytes = PREFACE.to_vec();
+        // SETTINGS: ENABLE_PUSH = 0
+        bytes.extend_from_slice(&[0, 0, 6, 4, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0]);
+        // HEADERS on stream 1, which isn't a control frame
+        bytes.extend_from_slice(&[0, 0, 2, 1, 4, 0, 0, 0, 1, 0x82, 0x84]);
+        // SETTINGS ack
+        bytes.extend_from_slice(&[0, 0, 0, 4, 1, 0, 0, 0, 0]);
+        // WINDOW_UPDATE on stream 1
+        bytes.extend_from_slice(&[0, 0, 4, 8, 0, 0, 0, 0, 1, 0, 0, 1, 0]);
+        // RST_STREAM on stream 1, CANCEL
+        bytes.extend_from_slice(&[0, 0, 4, 3, 0, 0, 0, 0, 1, 0, 0, 0, 8]);
+        // PING ack
+        bytes.extend_from_slice(&[0, 0, 8, 6, 1, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8]);
+        // GOAWAY, last stream 1, NO_ERROR, "bye"
+        bytes.extend_from_slice(&[0, 0, 11, 7, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"bye");
+
+        let expected = vec![
+            ControlFrame::Settings { ack: false, params: vec![(2, 0)] },
+            ControlFrame::Settings { ack: true, params: vec![] },
+            ControlFrame::WindowUpdate { stream_id: 1, increment: 256 },
+            ControlFrame::Reset { stream_id: 1, reason: Reason::CANCEL },
+            ControlFrame::Ping { ack: true, payload: [1, 2, 3, 4, 5, 6, 7, 8] },
+            ControlFrame::GoAway {
+                last_stream_id: 1,
+                reason: Reason::NO_ERROR,
+                debug_data: b"bye".to_vec(),
+            },
+        ];
+
+        // in one piece, and a byte at a time
+        let mut frames = Vec::new();
+        FrameParser::new().parse(&bytes, |frame| frames.push(frame));
+        assert_eq!(frames, expected);
+
+        let mut frames = Vec::new();
+        let mut parser = FrameParser::new();
+        for byte in &bytes[PREFACE.len()..] {
+            parser.parse(&[*byte], |frame| frames.push(frame));
+        }
+        assert_eq!(frames, expected);
+
+        // not HTTP/2 at all
+        let mut frames = Vec::new();
+        FrameParser::new().parse(b"PRI * HTTP/1.1\r\n", |frame| frames.push(frame));
+        assert!(frames.is_empty());
+    }
+}
@@ -21,7 +21,9 @@ extern crate h2;
 extern crate http;
 extern crate httparse;
 extern crate iovec;
+#[cfg(all(feature = "runtime", unix))] extern crate libc;
 #[macro_use] extern crate log;
+#[cfg(all(feature = "io-uring", target_os = "linux"))] extern crate mio;
 #[cfg(feature = "runtime")] extern crate net2;
 extern crate time;
 #[cfg(feature = "runtime")] extern crate tokio;
@@ -30,10 +32,17 @@ extern crate time;
 #[cfg(feature = "runtime")] extern crate tokio_reactor;
 #[cfg(feature = "runtime")] extern crate tokio_tcp;
 #[cfg(feature = "runtime")] extern crate tokio_timer;
+#[cfg(feature = "tracing")] extern crate tracing;
+#[cfg(all(feature = "fetch", target_arch = "wasm32"))] extern crate js_sys;
+#[cfg(all(feature = "fetch", target_arch = "wasm32"))] extern crate wasm_bindgen;
+#[cfg(all(feature = "fetch", target_arch = "wasm32"))] extern crate wasm_bindgen_futures;
+#[cfg(all(feature = "fetch", target_arch = "wasm32"))] extern crate web_sys;
 extern crate want;
 
 #[cfg(all(test, feature = "nightly"))]
 extern crate test;
+#[cfg(all(test, feature = "fetch", target_arch = "wasm32"))]
+extern crate wasm_bindgen_test;
 
 pub use http::{
     header,
@@ -52,13 +61,20 @@ pub use body::{Body, Chunk};
 pub use server::Server;
 
 mod common;
-#[cfg(test)]
-mod mock;
+#[cfg(feature = "std-future")] pub mod compat;
+pub mod mock;
 pub mod body;
 pub mod client;
+pub mod clock;
 pub mod error;
+pub mod exec;
+pub mod ext;
+pub mod h1;
 mod headers;
+pub mod instrument;
 mod proto;
+pub mod proxy;
 pub mod server;
 pub mod service;
 #[cfg(feature = "runtime")] pub mod rt;
+#[cfg(all(feature = "io-uring", target_os = "linux"))] pub mod uring;
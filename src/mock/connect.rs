@@ -0,0 +1,311 @@
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use futures::{future, Async, Poll};
+use futures::task::{self, Task};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use client::connect::{Connect, Connected, Destination};
+
+/// A connector that gives out transports set up ahead of time.
+///
+/// Each connection to an authority, such as `"example.com"` or
+/// `"localhost:8080"`, is given the next transport added for it, whether a
+/// [`Script`](Script) or any other IO, like one end of a
+/// [`duplex`](super::duplex) pair. Connecting to an authority without
+/// transports left fails with `ConnectionRefused`.
+///
+/// Clones share their transports, so one clone can be given to a `Client`
+/// while another keeps adding to it.
+///
+/// # Example
+///
+/// ```
+/// # extern crate futures;
+/// # extern crate hyper;
+/// use futures::Future;
+/// use hyper::{Body, Client};
+/// use hyper::mock::{Connector, Script};
+///
+/// # #[cfg(feature = "runtime")]
+/// # fn main() {
+/// let connector = Connector::new();
+/// connector.add("example.com", Script::new()
+///     .expect("GET /hello HTTP/1.1\r\nhost: example.com\r\n\r\n")
+///     .respond("HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nhi"));
+///
+/// let client = Client::builder().build::<_, Body>(connector);
+/// let fut = client.get("http://example.com/hello".parse().unwrap())
+///     .map(|res| assert_eq!(res.status(), 200))
+///     .map_err(|e| panic!("request error: {}", e));
+///
+/// hyper::rt::run(fut);
+/// # }
+/// # #[cfg(not(feature = "runtime"))]
+/// # fn main() {}
+/// ```
+#[derive(Clone, Default)]
+pub struct Connector {
+    transports: Arc<Mutex<HashMap<String, VecDeque<MockStream>>>>,
+}
+
+/// A transport given out by a [`Connector`](Connector).
+pub struct MockStream {
+    io: Box<Io + Send>,
+}
+
+trait Io: AsyncRead + AsyncWrite {}
+
+impl<T: AsyncRead + AsyncWrite> Io for T {}
+
+impl Connector {
+    /// Creates a `Connector` without any transports.
+    pub fn new() -> Connector {
+        Connector::default()
+    }
+
+    /// Adds a transport for the next connection to `authority`.
+    pub fn add<T>(&self, authority: &str, io: T) -> &Connector
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let mut transports = self.transports.lock().unwrap();
+        transports.entry(authority.to_owned())
+            .or_insert_with(VecDeque::new)
+            .push_back(MockStream {
+                io: Box::new(io),
+            });
+        self
+    }
+
+    /// Returns how many transports weren't used yet.
+    pub fn remaining(&self) -> usize {
+        let transports = self.transports.lock().unwrap();
+        transports.values().map(VecDeque::len).sum()
+    }
+}
+
+impl Connect for Connector {
+    type Transport = MockStream;
+    type Error = io::Error;
+    type Future = future::FutureResult<(MockStream, Connected), io::Error>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        let authority = match dst.port() {
+            Some(port) => format!("{}:{}", dst.host(), port),
+            None => dst.host().to_owned(),
+        };
+        let io = self.transports.lock().unwrap()
+            .get_mut(&authority)
+            .and_then(VecDeque::pop_front);
+        match io {
+            Some(io) => future::ok((io, Connected::new())),
+            None => future::err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("no mock transport for {:?}", authority),
+            )),
+        }
+    }
+}
+
+impl fmt::Debug for Connector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Connector")
+            .field("remaining", &self.remaining())
+            .finish()
+    }
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io.read(buf)
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl AsyncRead for MockStream {}
+
+impl AsyncWrite for MockStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+impl fmt::Debug for MockStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MockStream")
+            .finish()
+    }
+}
+
+/// A transport that plays a scripted exchange of bytes.
+///
+/// The steps run in order: an `expect` step waits for the bytes to be
+/// written, and a `respond` step gives bytes to read. Writing anything other
+/// than the bytes expected next fails with `InvalidData`, as does writing
+/// after the script ended. Reading after the script ended returns EOF.
+pub struct Script {
+    steps: VecDeque<Step>,
+    reader: Option<Task>,
+}
+
+enum Step {
+    Expect(Vec<u8>),
+    Respond(Vec<u8>),
+}
+
+impl Script {
+    /// Creates an empty script.
+    pub fn new() -> Script {
+        Script {
+            steps: VecDeque::new(),
+            reader: None,
+        }
+    }
+
+    /// Adds a step waiting for `bytes` to be written.
+    pub fn expect<B: AsRef<[u8]>>(mut self, bytes: B) -> Script {
+        self.steps.push_back(Step::Expect(bytes.as_ref().to_vec()));
+        self
+    }
+
+    /// Adds a step giving `bytes` to read.
+    pub fn respond<B: AsRef<[u8]>>(mut self, bytes: B) -> Script {
+        self.steps.push_back(Step::Respond(bytes.as_ref().to_vec()));
+        self
+    }
+
+    fn skip_empty(&mut self) {
+        while let Some(true) = self.steps.front().map(|step| match *step {
+            Step::Expect(ref bytes) | Step::Respond(ref bytes) => bytes.is_empty(),
+        }) {
+            self.steps.pop_front();
+        }
+    }
+}
+
+impl Default for Script {
+    fn default() -> Script {
+        Script::new()
+    }
+}
+
+impl Read for Script {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.skip_empty();
+        let n = match self.steps.front_mut() {
+            Some(&mut Step::Respond(ref mut bytes)) => {
+                let n = cmp::min(buf.len(), bytes.len());
+                buf[..n].copy_from_slice(&bytes[..n]);
+                bytes.drain(..n);
+                n
+            },
+            Some(&mut Step::Expect(_)) => {
+                // nothing to read until the expected bytes are written
+                self.reader = Some(task::current());
+                return Err(io::ErrorKind::WouldBlock.into());
+            },
+            None => return Ok(0),
+        };
+        self.skip_empty();
+        Ok(n)
+    }
+}
+
+impl Write for Script {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.skip_empty();
+        let n = match self.steps.front_mut() {
+            Some(&mut Step::Expect(ref mut bytes)) => {
+                let n = cmp::min(buf.len(), bytes.len());
+                if buf[..n] != bytes[..n] {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                        "mock script expected {:?}, but got {:?}",
+                        String::from_utf8_lossy(bytes),
+                        String::from_utf8_lossy(buf),
+                    )));
+                }
+                bytes.drain(..n);
+                n
+            },
+            Some(&mut Step::Respond(ref bytes)) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                    "mock script was to respond with {:?}, but got {:?} written",
+                    String::from_utf8_lossy(bytes),
+                    String::from_utf8_lossy(buf),
+                )));
+            },
+            None => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                    "mock script ended, but got {:?} written",
+                    String::from_utf8_lossy(buf),
+                )));
+            },
+        };
+        self.skip_empty();
+        if let Some(task) = self.reader.take() {
+            task.notify();
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for Script {}
+
+impl AsyncWrite for Script {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Script")
+            .field("steps", &self.steps.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+
+    use futures::{future, Future};
+
+    use super::Script;
+
+    #[test]
+    fn script_steps() {
+        future::lazy(|| {
+            let mut script = Script::new()
+                .expect("ping")
+                .respond("pong");
+            let mut buf = [0; 8];
+            assert_eq!(script.read(&mut buf).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+            assert_eq!(script.write(b"pi").unwrap(), 2);
+            assert_eq!(script.write(b"nx").unwrap_err().kind(), io::ErrorKind::InvalidData);
+            assert_eq!(script.write(b"ng").unwrap(), 2);
+            assert_eq!(script.read(&mut buf).unwrap(), 4);
+            assert_eq!(&buf[..4], b"pong");
+            assert_eq!(script.read(&mut buf).unwrap(), 0);
+            assert_eq!(script.write(b"more").unwrap_err().kind(), io::ErrorKind::InvalidData);
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
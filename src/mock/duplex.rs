@@ -0,0 +1,199 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use futures::{Async, Poll};
+use futures::task::{self, Task};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Creates a pair of connected in-memory transports.
+///
+/// What is written to one of them is read from the other. Each direction
+/// buffers up to `capacity` bytes, after which writing is `NotReady` until
+/// the other end read some of them, so a small capacity exercises how a
+/// connection handles backpressure.
+///
+/// Shutting down one end, or dropping it, ends what the other end reads.
+/// Writing to an end whose peer was dropped fails with `BrokenPipe`.
+///
+/// Like other transports, these must be used from within a task.
+///
+/// # Panics
+///
+/// If `capacity` is 0.
+pub fn duplex(capacity: usize) -> (DuplexStream, DuplexStream) {
+    assert!(capacity > 0, "duplex capacity must be more than 0");
+    let one = Arc::new(Mutex::new(Pipe::new(capacity)));
+    let two = Arc::new(Mutex::new(Pipe::new(capacity)));
+    let a = DuplexStream {
+        read: one.clone(),
+        write: two.clone(),
+    };
+    let b = DuplexStream {
+        read: two,
+        write: one,
+    };
+    (a, b)
+}
+
+/// One end of a [`duplex`](duplex) pair.
+pub struct DuplexStream {
+    read: Arc<Mutex<Pipe>>,
+    write: Arc<Mutex<Pipe>>,
+}
+
+/// The bytes going one way.
+struct Pipe {
+    buf: VecDeque<u8>,
+    capacity: usize,
+    // the writing end shut down, or was dropped
+    write_closed: bool,
+    // the reading end was dropped
+    read_closed: bool,
+    reader: Option<Task>,
+    writer: Option<Task>,
+}
+
+impl Pipe {
+    fn new(capacity: usize) -> Pipe {
+        Pipe {
+            buf: VecDeque::new(),
+            capacity: capacity,
+            write_closed: false,
+            read_closed: false,
+            reader: None,
+            writer: None,
+        }
+    }
+
+    fn notify_reader(&mut self) {
+        if let Some(task) = self.reader.take() {
+            task.notify();
+        }
+    }
+
+    fn notify_writer(&mut self) {
+        if let Some(task) = self.writer.take() {
+            task.notify();
+        }
+    }
+}
+
+fn lock(pipe: &Mutex<Pipe>) -> ::std::sync::MutexGuard<Pipe> {
+    // a panic while holding the lock doesn't leave the pipe inconsistent
+    match pipe.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+impl Read for DuplexStream {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        let mut pipe = lock(&self.read);
+        if pipe.buf.is_empty() {
+            if pipe.write_closed || dst.is_empty() {
+                return Ok(0);
+            }
+            pipe.reader = Some(task::current());
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let n = cmp::min(dst.len(), pipe.buf.len());
+        for (dst, src) in dst.iter_mut().zip(pipe.buf.drain(..n)) {
+            *dst = src;
+        }
+        pipe.notify_writer();
+        Ok(n)
+    }
+}
+
+impl Write for DuplexStream {
+    fn write(&mut self, src: &[u8]) -> io::Result<usize> {
+        let mut pipe = lock(&self.write);
+        if pipe.read_closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        if pipe.write_closed {
+            return Err(io::Error::new(io::ErrorKind::Other, "duplex stream was shut down"));
+        }
+        let available = pipe.capacity - pipe.buf.len();
+        if available == 0 {
+            pipe.writer = Some(task::current());
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let n = cmp::min(available, src.len());
+        pipe.buf.extend(&src[..n]);
+        pipe.notify_reader();
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for DuplexStream {}
+
+impl AsyncWrite for DuplexStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        let mut pipe = lock(&self.write);
+        pipe.write_closed = true;
+        pipe.notify_reader();
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Drop for DuplexStream {
+    fn drop(&mut self) {
+        let mut write = lock(&self.write);
+        write.write_closed = true;
+        write.notify_reader();
+        drop(write);
+        let mut read = lock(&self.read);
+        read.read_closed = true;
+        read.notify_writer();
+    }
+}
+
+impl fmt::Debug for DuplexStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DuplexStream")
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+
+    use futures::{future, Future};
+    use tokio_io::AsyncWrite;
+
+    use super::duplex;
+
+    #[test]
+    fn duplex_capacity_and_close() {
+        future::lazy(|| {
+            let (mut a, mut b) = duplex(4);
+            assert_eq!(a.write(b"hello").unwrap(), 4);
+            assert_eq!(a.write(b"o").unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+            let mut buf = [0; 8];
+            assert_eq!(b.read(&mut buf).unwrap(), 4);
+            assert_eq!(&buf[..4], b"hell");
+            assert_eq!(b.read(&mut buf).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+            assert_eq!(a.write(b"o").unwrap(), 1);
+            a.shutdown().unwrap();
+            assert_eq!(b.read(&mut buf).unwrap(), 1);
+            assert_eq!(b.read(&mut buf).unwrap(), 0);
+
+            // the other way is still open, until `a` is dropped
+            assert_eq!(b.write(b"hi").unwrap(), 2);
+            drop(a);
+            assert_eq!(b.write(b"hi").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
@@ -0,0 +1,354 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use futures::{Async, Future, Poll};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use clock::{Clock, Delay, Time};
+
+/// A policy of network failures to inject into a transport.
+///
+/// Each fault happens at random, with the given probability, from a
+/// generator seeded with `seed`, so that a failing test can be run again
+/// with the same faults. [`wrap`](Faults::wrap) applies the policy to an
+/// IO, such as a [`Script`](super::Script) or a `TcpStream`.
+///
+/// # Example
+///
+/// ```
+/// use hyper::mock::{duplex, Faults};
+///
+/// let faults = Faults::new(42)
+///     .partial_writes(0.5)
+///     .resets(0.01);
+/// let (client_io, server_io) = duplex(4096);
+/// let client_io = faults.wrap(client_io);
+/// # drop((client_io, server_io));
+/// ```
+#[derive(Clone)]
+pub struct Faults {
+    seed: u64,
+    latency: Option<Duration>,
+    partial_writes: f64,
+    resets: f64,
+    corruption: f64,
+    time: Time,
+}
+
+impl Faults {
+    /// Creates a policy without any faults, with the generator seeded with
+    /// `seed`.
+    pub fn new(seed: u64) -> Faults {
+        Faults {
+            seed: seed,
+            latency: None,
+            partial_writes: 0.0,
+            resets: 0.0,
+            corruption: 0.0,
+            time: Time::default(),
+        }
+    }
+
+    /// Delays each read and write by a random duration of up to `max`.
+    ///
+    /// The delays are timed by the tokio timer, unless a
+    /// [`clock`](Faults::clock) is set.
+    pub fn latency(mut self, max: Duration) -> Faults {
+        self.latency = Some(max);
+        self
+    }
+
+    /// Sets the [`Clock`](::clock::Clock) the latency is timed by.
+    pub fn clock<C: Clock>(mut self, clock: C) -> Faults {
+        self.time = Time::new(clock);
+        self
+    }
+
+    /// Makes a write only write some of its bytes, with probability `p`.
+    pub fn partial_writes(mut self, p: f64) -> Faults {
+        self.partial_writes = p;
+        self
+    }
+
+    /// Resets the connection before a read or write, with probability `p`.
+    ///
+    /// Once reset, every read and write fails with `ConnectionReset`.
+    pub fn resets(mut self, p: f64) -> Faults {
+        self.resets = p;
+        self
+    }
+
+    /// Flips a bit of each byte read or written, with probability `p`.
+    pub fn corruption(mut self, p: f64) -> Faults {
+        self.corruption = p;
+        self
+    }
+
+    /// Wraps `io`, injecting the faults of this policy.
+    pub fn wrap<T>(&self, io: T) -> FaultyIo<T> {
+        FaultyIo {
+            io: io,
+            faults: self.clone(),
+            rng: Rng::new(self.seed),
+            reset: false,
+            read_delay: Latency::default(),
+            write_delay: Latency::default(),
+        }
+    }
+}
+
+impl fmt::Debug for Faults {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Faults")
+            .field("seed", &self.seed)
+            .field("latency", &self.latency)
+            .field("partial_writes", &self.partial_writes)
+            .field("resets", &self.resets)
+            .field("corruption", &self.corruption)
+            .finish()
+    }
+}
+
+/// A transport injecting the faults of a [`Faults`](Faults) policy.
+pub struct FaultyIo<T> {
+    io: T,
+    faults: Faults,
+    rng: Rng,
+    reset: bool,
+    read_delay: Latency,
+    write_delay: Latency,
+}
+
+/// The delay before the next read, or write.
+#[derive(Default)]
+struct Latency {
+    delay: Option<Delay>,
+    // the delay ended, but the operation it was for didn't happen yet
+    elapsed: bool,
+}
+
+impl<T> FaultyIo<T> {
+    /// Get a reference to the wrapped IO.
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+
+    /// Get a mutable reference to the wrapped IO.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.io
+    }
+
+    /// Unwrap the wrapped IO.
+    pub fn into_inner(self) -> T {
+        self.io
+    }
+
+    fn check_reset(&mut self) -> io::Result<()> {
+        if !self.reset && self.rng.chance(self.faults.resets) {
+            trace!("injecting connection reset");
+            self.reset = true;
+        }
+        if self.reset {
+            Err(io::ErrorKind::ConnectionReset.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn corrupt(&mut self, bytes: &mut [u8]) {
+        if self.faults.corruption <= 0.0 {
+            return;
+        }
+        for byte in bytes {
+            if self.rng.chance(self.faults.corruption) {
+                *byte ^= 1 << (self.rng.next() % 8);
+            }
+        }
+    }
+}
+
+fn wait(latency: &mut Latency, faults: &Faults, rng: &mut Rng) -> io::Result<()> {
+    let max = match faults.latency {
+        Some(max) => max,
+        None => return Ok(()),
+    };
+    if latency.elapsed {
+        return Ok(());
+    }
+    if latency.delay.is_none() {
+        let max_nanos = max.as_secs() * 1_000_000_000 + u64::from(max.subsec_nanos());
+        let nanos = if max_nanos == 0 { 0 } else { rng.next() % (max_nanos + 1) };
+        let deadline = faults.time.now() + Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32);
+        latency.delay = Some(faults.time.delay(deadline));
+    }
+    match latency.delay.as_mut().expect("delay was just set").poll() {
+        Ok(Async::Ready(())) => {
+            latency.delay = None;
+            latency.elapsed = true;
+            Ok(())
+        },
+        Ok(Async::NotReady) => Err(io::ErrorKind::WouldBlock.into()),
+        Err(err) => Err(io::Error::new(io::ErrorKind::Other, err)),
+    }
+}
+
+impl<T: Read> Read for FaultyIo<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        wait(&mut self.read_delay, &self.faults, &mut self.rng)?;
+        self.check_reset()?;
+        let n = self.io.read(buf)?;
+        self.read_delay.elapsed = false;
+        self.corrupt(&mut buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for FaultyIo<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        wait(&mut self.write_delay, &self.faults, &mut self.rng)?;
+        self.check_reset()?;
+        let mut len = buf.len();
+        if len > 1 && self.rng.chance(self.faults.partial_writes) {
+            len = 1 + (self.rng.next() % (len as u64 - 1)) as usize;
+            trace!("injecting partial write of {} of {} bytes", len, buf.len());
+        }
+        let n = if self.faults.corruption > 0.0 {
+            let mut copy = buf[..len].to_vec();
+            self.corrupt(&mut copy);
+            self.io.write(&copy)?
+        } else {
+            self.io.write(&buf[..len])?
+        };
+        self.write_delay.elapsed = false;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.reset {
+            return Err(io::ErrorKind::ConnectionReset.into());
+        }
+        self.io.flush()
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for FaultyIo<T> {}
+
+impl<T: AsyncWrite> AsyncWrite for FaultyIo<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        if self.reset {
+            return Err(io::ErrorKind::ConnectionReset.into());
+        }
+        self.io.shutdown()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for FaultyIo<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FaultyIo")
+            .field("io", &self.io)
+            .field("faults", &self.faults)
+            .field("reset", &self.reset)
+            .finish()
+    }
+}
+
+/// A xorshift64* generator, good enough to pick faults.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // the state must never be 0
+        Rng((seed ^ 0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        if p <= 0.0 {
+            false
+        } else if p >= 1.0 {
+            true
+        } else {
+            ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < p
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+    use std::time::Duration;
+
+    use futures::{future, Future};
+
+    use clock::ManualClock;
+    use super::super::duplex;
+    use super::Faults;
+
+    #[test]
+    fn partial_writes_are_seeded() {
+        future::lazy(|| {
+            let lens = |seed| {
+                let (a, _b) = duplex(1024);
+                let mut a = Faults::new(seed).partial_writes(1.0).wrap(a);
+                (0..8).map(|_| a.write(b"hello world").unwrap()).collect::<Vec<_>>()
+            };
+            let first = lens(7);
+            assert_eq!(first, lens(7));
+            assert!(first.iter().all(|&n| n > 0 && n < 11), "{:?}", first);
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn resets_and_corruption() {
+        future::lazy(|| {
+            let (a, mut b) = duplex(1024);
+            let mut a = Faults::new(1).corruption(1.0).wrap(a);
+            a.write_all(b"abcd").unwrap();
+            let mut buf = [0; 4];
+            b.read_exact(&mut buf).unwrap();
+            for (got, sent) in buf.iter().zip(b"abcd") {
+                assert_eq!((got ^ sent).count_ones(), 1);
+            }
+
+            let mut a = Faults::new(1).resets(1.0).wrap(a.into_inner());
+            assert_eq!(a.write(b"a").unwrap_err().kind(), io::ErrorKind::ConnectionReset);
+            assert_eq!(a.read(&mut buf).unwrap_err().kind(), io::ErrorKind::ConnectionReset);
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn latency_by_clock() {
+        future::lazy(|| {
+            let clock = ManualClock::new();
+            let (a, _b) = duplex(1024);
+            let mut a = Faults::new(3)
+                .latency(Duration::from_secs(10))
+                .clock(clock.clone())
+                .wrap(a);
+            let mut wrote = false;
+            for _ in 0..11 {
+                match a.write(b"hi") {
+                    Ok(n) => {
+                        assert_eq!(n, 2);
+                        wrote = true;
+                        break;
+                    },
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                    Err(e) => panic!("write error: {}", e),
+                }
+                clock.advance(Duration::from_secs(1));
+            }
+            assert!(wrote, "latency is at most 10 seconds");
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
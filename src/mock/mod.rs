@@ -0,0 +1,66 @@
+//! Transports for testing, that don't need a network.
+//!
+//! A [`duplex`](duplex) pair connects a `Client`, or a
+//! [`client::conn`](::client::conn) handshake, to a
+//! [`server::conn`](::server::conn) connection within the same process,
+//! while a [`Connector`](Connector) gives a `Client` transports set up ahead
+//! of time, such as a [`Script`](Script) of the bytes to be exchanged.
+//! A [`Recorder`](Recorder) captures the bytes of a real connection, for a
+//! [`Recording`](Recording) to replay them later, and [`Faults`](Faults)
+//! inject network failures into any of them. When a test does need a real
+//! port, a [`TestServer`](TestServer) serves on an ephemeral one.
+//!
+//! # Example
+//!
+//! ```
+//! # extern crate futures;
+//! # extern crate hyper;
+//! use futures::Future;
+//! use hyper::{Body, Request, Response};
+//! use hyper::client::conn;
+//! use hyper::server::conn::Http;
+//! use hyper::service::service_fn_ok;
+//!
+//! # #[cfg(feature = "runtime")]
+//! # fn main() {
+//! let (client_io, server_io) = hyper::mock::duplex(4096);
+//!
+//! let server = Http::new()
+//!     .serve_connection(server_io, service_fn_ok(|_req| {
+//!         Response::new(Body::from("hello"))
+//!     }))
+//!     .map_err(|e| panic!("server error: {}", e));
+//!
+//! let client = conn::handshake(client_io)
+//!     .and_then(|(mut tx, conn)| {
+//!         hyper::rt::spawn(conn.map_err(|e| panic!("client error: {}", e)));
+//!         tx.send_request(Request::new(Body::empty()))
+//!     })
+//!     .map(|res| assert_eq!(res.status(), 200))
+//!     .map_err(|e| panic!("request error: {}", e));
+//!
+//! hyper::rt::run(futures::future::lazy(move || {
+//!     hyper::rt::spawn(server);
+//!     client
+//! }));
+//! # }
+//! # #[cfg(not(feature = "runtime"))]
+//! # fn main() {}
+//! ```
+
+pub use self::connect::{Connector, MockStream, Script};
+pub use self::duplex::{duplex, DuplexStream};
+pub use self::fault::{Faults, FaultyIo};
+pub use self::record::{Recorder, Recording};
+#[cfg(feature = "runtime")] pub use self::server::{Shutdown, TestServer};
+
+#[cfg(test)] pub(crate) use self::async_io::AsyncIo;
+#[cfg(all(test, feature = "runtime"))] pub(crate) use self::async_io::MockConnector;
+
+#[cfg(test)]
+mod async_io;
+mod connect;
+mod duplex;
+mod fault;
+mod record;
+#[cfg(feature = "runtime")] mod server;
@@ -0,0 +1,256 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use futures::Poll;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use super::Script;
+
+/// A transport wrapper that records the bytes exchanged on a connection.
+///
+/// Everything read from and written to the wrapped IO is appended to the
+/// `sink`, such as a `File`, as it happens. A [`Recording`](Recording) loads
+/// it back, to replay the connection later.
+///
+/// Each chunk is recorded as a line of `<` for bytes read, or `>` for bytes
+/// written, a space and the number of bytes, followed by the bytes and a
+/// newline.
+pub struct Recorder<T, W> {
+    io: T,
+    sink: W,
+}
+
+impl<T> Recorder<T, File> {
+    /// Records the bytes exchanged on `io` to a new file at `path`.
+    pub fn create<P: AsRef<Path>>(io: T, path: P) -> io::Result<Recorder<T, File>> {
+        let file = File::create(path)?;
+        Ok(Recorder::new(io, file))
+    }
+}
+
+impl<T, W: Write> Recorder<T, W> {
+    /// Records the bytes exchanged on `io` to `sink`.
+    pub fn new(io: T, sink: W) -> Recorder<T, W> {
+        Recorder {
+            io: io,
+            sink: sink,
+        }
+    }
+
+    /// Returns the wrapped IO and the sink.
+    pub fn into_inner(self) -> (T, W) {
+        (self.io, self.sink)
+    }
+
+    fn record(&mut self, dir: Direction, bytes: &[u8]) -> io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        write!(self.sink, "{} {}\n", dir.as_str(), bytes.len())?;
+        self.sink.write_all(bytes)?;
+        self.sink.write_all(b"\n")
+    }
+}
+
+impl<T: Read, W: Write> Read for Recorder<T, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.io.read(buf)?;
+        self.record(Direction::Read, &buf[..n])?;
+        Ok(n)
+    }
+}
+
+impl<T: Write, W: Write> Write for Recorder<T, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.io.write(buf)?;
+        self.record(Direction::Write, &buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()?;
+        self.sink.flush()
+    }
+}
+
+impl<T: AsyncRead, W: Write> AsyncRead for Recorder<T, W> {}
+
+impl<T: AsyncWrite, W: Write> AsyncWrite for Recorder<T, W> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+impl<T: fmt::Debug, W> fmt::Debug for Recorder<T, W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Recorder")
+            .field("io", &self.io)
+            .finish()
+    }
+}
+
+/// The bytes exchanged on a connection, as written by a
+/// [`Recorder`](Recorder).
+///
+/// Consecutive chunks going the same way are joined, so a replay doesn't
+/// depend on how the bytes happened to be split into reads and writes.
+///
+/// # Example
+///
+/// A `Client` connection recorded in production can be replayed, with
+/// [`Recording::remote`](Recording::remote) playing the server, to check
+/// the `Client` still sends the same bytes:
+///
+/// ```no_run
+/// use hyper::mock::{Connector, Recording};
+///
+/// # fn run() -> ::std::io::Result<()> {
+/// let recording = Recording::open("tests/fixtures/upstream.rec")?;
+/// let connector = Connector::new();
+/// connector.add("upstream.local", recording.remote());
+/// # Ok(())
+/// # }
+/// # fn main() {}
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Recording {
+    chunks: Vec<(Direction, Vec<u8>)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Direction {
+    Read,
+    Write,
+}
+
+impl Direction {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            Direction::Read => "<",
+            Direction::Write => ">",
+        }
+    }
+}
+
+impl Recording {
+    /// Loads the recording in the file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Recording> {
+        let file = File::open(path)?;
+        Recording::from_reader(file)
+    }
+
+    /// Loads a recording from `reader`.
+    pub fn from_reader<R: Read>(reader: R) -> io::Result<Recording> {
+        let mut reader = BufReader::new(reader);
+        let mut recording = Recording::default();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(recording);
+            }
+            let mut parts = line.trim_right_matches('\n').splitn(2, ' ');
+            let dir = match parts.next() {
+                Some("<") => Direction::Read,
+                Some(">") => Direction::Write,
+                _ => return Err(invalid(&line)),
+            };
+            let len = match parts.next().and_then(|len| len.parse::<usize>().ok()) {
+                Some(len) => len,
+                None => return Err(invalid(&line)),
+            };
+            let mut bytes = vec![0; len + 1];
+            reader.read_exact(&mut bytes)?;
+            if bytes.pop() != Some(b'\n') {
+                return Err(invalid(&line));
+            }
+            recording.push(dir, bytes);
+        }
+    }
+
+    fn push(&mut self, dir: Direction, bytes: Vec<u8>) {
+        if let Some(&mut (last, ref mut prev)) = self.chunks.last_mut() {
+            if last == dir {
+                prev.extend_from_slice(&bytes);
+                return;
+            }
+        }
+        self.chunks.push((dir, bytes));
+    }
+
+    /// Returns a `Script` playing the other end of the recorded connection.
+    ///
+    /// It expects what was written, and responds with what was read, in
+    /// the recorded order.
+    pub fn remote(&self) -> Script {
+        self.script(Direction::Write)
+    }
+
+    /// Returns a `Script` playing the recorded end of the connection again.
+    ///
+    /// It expects what was read, and responds with what was written, such
+    /// as to replay a recorded server to a `Client`.
+    pub fn local(&self) -> Script {
+        self.script(Direction::Read)
+    }
+
+    fn script(&self, expected: Direction) -> Script {
+        self.chunks.iter().fold(Script::new(), |script, &(dir, ref bytes)| {
+            if dir == expected {
+                script.expect(bytes)
+            } else {
+                script.respond(bytes)
+            }
+        })
+    }
+}
+
+fn invalid(line: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid recording line: {:?}", line))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use futures::{future, Future};
+
+    use super::super::Script;
+    use super::{Recorder, Recording};
+
+    #[test]
+    fn record_and_replay() {
+        future::lazy(|| {
+            let script = Script::new()
+                .expect("ping\n")
+                .respond("pong")
+                .respond("\n");
+            let mut rec = Recorder::new(script, Vec::new());
+            let mut buf = [0; 8];
+            rec.write_all(b"pi").unwrap();
+            rec.write_all(b"ng\n").unwrap();
+            assert_eq!(rec.read(&mut buf).unwrap(), 4);
+            assert_eq!(rec.read(&mut buf).unwrap(), 1);
+            let (_, sink) = rec.into_inner();
+            assert_eq!(sink, b"> 2\npi\n> 3\nng\n\n< 4\npong\n< 1\n\n\n".to_vec());
+
+            let recording = Recording::from_reader(&sink[..]).unwrap();
+
+            let mut remote = recording.remote();
+            remote.write_all(b"ping\n").unwrap();
+            assert_eq!(remote.read(&mut buf).unwrap(), 5);
+            assert_eq!(&buf[..5], b"pong\n");
+
+            let mut local = recording.local();
+            assert_eq!(local.read(&mut buf).unwrap(), 5);
+            assert_eq!(&buf[..5], b"ping\n");
+            local.write_all(b"pong\n").unwrap();
+            assert_eq!(local.read(&mut buf).unwrap(), 0);
+
+            assert!(Recording::from_reader(&b"? 1\nx\n"[..]).is_err());
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
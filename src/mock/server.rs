@@ -0,0 +1,194 @@
+use std::fmt;
+use std::net::SocketAddr;
+
+use futures::{Async, Future, Poll};
+use futures::future::Executor;
+use futures::sync::oneshot;
+use http::Uri;
+
+use body::{Body, Payload};
+use server::{Builder, Server};
+use server::conn::AddrIncoming;
+use service::{NewService, Service};
+
+/// A `Server` listening on an ephemeral port of `127.0.0.1`, for tests.
+///
+/// The server is spawned on the given executor, and keeps running until
+/// [`shutdown`](TestServer::shutdown) is called, or the `TestServer` is
+/// dropped.
+///
+/// # Example
+///
+/// ```
+/// # extern crate futures;
+/// # extern crate hyper;
+/// # extern crate tokio;
+/// use futures::{Future, Stream};
+/// use hyper::{Body, Client, Response};
+/// use hyper::mock::TestServer;
+/// use hyper::service::service_fn_ok;
+///
+/// # fn main() {
+/// let mut runtime = tokio::runtime::Runtime::new().unwrap();
+/// let server = TestServer::spawn(|| {
+///     service_fn_ok(|_req| Response::new(Body::from("hello")))
+/// }, &runtime.executor());
+///
+/// let client = Client::new();
+/// let body = runtime.block_on(client.get(server.uri("/"))
+///     .and_then(|res| res.into_body().concat2()))
+///     .unwrap();
+/// assert_eq!(&body[..], b"hello");
+///
+/// runtime.block_on(server.shutdown()).unwrap();
+/// # }
+/// ```
+pub struct TestServer {
+    addr: SocketAddr,
+    signal: Option<oneshot::Sender<()>>,
+    done: Option<oneshot::Receiver<::Result<()>>>,
+}
+
+/// A future completing once a [`TestServer`](TestServer) has shut down.
+#[must_use = "futures do nothing unless polled"]
+pub struct Shutdown {
+    done: Option<oneshot::Receiver<::Result<()>>>,
+}
+
+impl TestServer {
+    /// Serves `new_service` on an ephemeral port, spawned on `executor`.
+    ///
+    /// # Panics
+    ///
+    /// If the port can't be bound, or `executor` fails to spawn the server.
+    pub fn spawn<S, B, E>(new_service: S, executor: &E) -> TestServer
+    where
+        S: NewService<ReqBody=Body, ResBody=B> + Send + 'static,
+        S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+        S::Service: Send,
+        S::Future: Send + 'static,
+        <S::Service as Service>::Future: Send + 'static,
+        B: Payload,
+        E: Executor<Box<Future<Item=(), Error=()> + Send>>,
+    {
+        TestServer::spawn_with(|builder| builder, new_service, executor)
+    }
+
+    /// Like [`spawn`](TestServer::spawn), but `configure` sets up the
+    /// `Builder` first, such as to make it `http2_only`.
+    ///
+    /// # Panics
+    ///
+    /// If the port can't be bound, or `executor` fails to spawn the server.
+    pub fn spawn_with<F, S, B, E>(configure: F, new_service: S, executor: &E) -> TestServer
+    where
+        F: FnOnce(Builder<AddrIncoming>) -> Builder<AddrIncoming>,
+        S: NewService<ReqBody=Body, ResBody=B> + Send + 'static,
+        S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+        S::Service: Send,
+        S::Future: Send + 'static,
+        <S::Service as Service>::Future: Send + 'static,
+        B: Payload,
+        E: Executor<Box<Future<Item=(), Error=()> + Send>>,
+    {
+        let builder = Server::try_bind(&([127, 0, 0, 1], 0).into())
+            .unwrap_or_else(|e| panic!("test server bind error: {}", e));
+        let server = configure(builder).serve(new_service);
+        let addr = server.local_addr();
+
+        let (signal_tx, signal_rx) = oneshot::channel();
+        let (done_tx, done_rx) = oneshot::channel();
+        let fut = server
+            .with_graceful_shutdown(signal_rx.then(|_| Ok::<(), ()>(())))
+            .then(move |res| {
+                if let Err(ref e) = res {
+                    debug!("test server error: {}", e);
+                }
+                let _ = done_tx.send(res);
+                Ok(())
+            });
+        if executor.execute(Box::new(fut)).is_err() {
+            panic!("executor failed to spawn the test server");
+        }
+
+        TestServer {
+            addr: addr,
+            signal: Some(signal_tx),
+            done: Some(done_rx),
+        }
+    }
+
+    /// The address the server is listening on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// An `http` URI of `path` on the server, such as to give to a `Client`.
+    ///
+    /// # Panics
+    ///
+    /// If `path` isn't a valid path, such as one not starting with `/`.
+    pub fn uri(&self, path: &str) -> Uri {
+        format!("http://{}{}", self.addr, path)
+            .parse()
+            .unwrap_or_else(|e| panic!("invalid test server path {:?}: {}", path, e))
+    }
+
+    /// Starts a graceful shutdown of the server.
+    ///
+    /// The returned future completes once the server stopped accepting
+    /// connections, and the open ones have finished, with the error the
+    /// server ended with, if any.
+    pub fn shutdown(mut self) -> Shutdown {
+        self.signal();
+        Shutdown {
+            done: self.done.take(),
+        }
+    }
+
+    fn signal(&mut self) {
+        if let Some(tx) = self.signal.take() {
+            let _ = tx.send(());
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.signal();
+    }
+}
+
+impl fmt::Debug for TestServer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TestServer")
+            .field("addr", &self.addr)
+            .finish()
+    }
+}
+
+impl Future for Shutdown {
+    type Item = ();
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let res = match self.done {
+            Some(ref mut done) => match done.poll() {
+                Ok(Async::Ready(res)) => res,
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                // the executor dropped the server without finishing it
+                Err(_canceled) => Ok(()),
+            },
+            None => Ok(()),
+        };
+        self.done = None;
+        res.map(Async::Ready)
+    }
+}
+
+impl fmt::Debug for Shutdown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Shutdown")
+            .finish()
+    }
+}
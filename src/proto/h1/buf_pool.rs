@@ -0,0 +1,221 @@
+use std::fmt;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+use bytes::BytesMut;
+
+use super::io::INIT_BUFFER_SIZE;
+
+/// Buffers that grew larger than this aren't kept, so that a few large
+/// messages don't pin their memory.
+const MAX_POOLED_CAPACITY: usize = INIT_BUFFER_SIZE * 4;
+
+/// The default number of buffers of each kind kept in a pool.
+// Note: if this changes, update server::conn::Http::http1_buf_pool_size docs.
+pub(crate) const DEFAULT_MAX_POOLED_BUFS: usize = 32;
+
+/// A pool of read and write buffers, reused by the connections sharing it.
+#[derive(Clone)]
+pub(crate) struct BufPool {
+    inner: Arc<Mutex<Bufs>>,
+}
+
+struct Bufs {
+    max: usize,
+    reads: Vec<BytesMut>,
+    writes: Vec<Vec<u8>>,
+}
+
+impl BufPool {
+    /// Creates a pool keeping at most `max` buffers of each kind.
+    pub(crate) fn new(max: usize) -> BufPool {
+        BufPool {
+            inner: Arc::new(Mutex::new(Bufs {
+                max: max,
+                reads: Vec::new(),
+                writes: Vec::new(),
+            })),
+        }
+    }
+
+    fn read_buf(&self) -> Option<BytesMut> {
+        self.inner.lock().ok().and_then(|mut bufs| bufs.reads.pop())
+    }
+
+    fn write_buf(&self) -> Option<Vec<u8>> {
+        self.inner.lock().ok().and_then(|mut bufs| bufs.writes.pop())
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> (usize, usize) {
+        let bufs = self.inner.lock().unwrap();
+        (bufs.reads.len(), bufs.writes.len())
+    }
+}
+
+impl fmt::Debug for BufPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BufPool")
+            .finish()
+    }
+}
+
+/// A buffer type that can be kept in a `BufPool`.
+pub(super) trait Recycle: Default {
+    fn take(pool: &BufPool) -> Option<Self>;
+    fn recycle(self, pool: &BufPool);
+}
+
+impl Recycle for BytesMut {
+    fn take(pool: &BufPool) -> Option<BytesMut> {
+        pool.read_buf()
+    }
+
+    fn recycle(mut self, pool: &BufPool) {
+        let cap = self.capacity();
+        if cap == 0 || cap > MAX_POOLED_CAPACITY {
+            return;
+        }
+        self.clear();
+        if let Ok(mut bufs) = pool.inner.lock() {
+            if bufs.reads.len() < bufs.max {
+                bufs.reads.push(self);
+            }
+        }
+    }
+}
+
+impl Recycle for Vec<u8> {
+    fn take(pool: &BufPool) -> Option<Vec<u8>> {
+        pool.write_buf()
+    }
+
+    fn recycle(mut self, pool: &BufPool) {
+        let cap = self.capacity();
+        if cap == 0 || cap > MAX_POOLED_CAPACITY {
+            return;
+        }
+        self.clear();
+        if let Ok(mut bufs) = pool.inner.lock() {
+            if bufs.writes.len() < bufs.max {
+                bufs.writes.push(self);
+            }
+        }
+    }
+}
+
+/// A buffer that is given back to its pool, if it has one, when dropped.
+pub(super) struct Pooled<B: Recycle> {
+    buf: B,
+    pool: Option<BufPool>,
+}
+
+impl<B: Recycle> Pooled<B> {
+    pub(super) fn new(buf: B) -> Pooled<B> {
+        Pooled {
+            buf: buf,
+            pool: None,
+        }
+    }
+
+    /// Returns this buffer to `pool` once dropped, and replaces it with
+    /// one from the pool, if there is one and `replace` is true.
+    pub(super) fn set_pool(&mut self, pool: BufPool, replace: bool) {
+        if replace {
+            if let Some(buf) = B::take(&pool) {
+                self.buf = buf;
+            }
+        }
+        self.pool = Some(pool);
+    }
+
+    /// Takes the buffer out, so it won't be returned to the pool.
+    pub(super) fn into_inner(mut self) -> B {
+        self.pool = None;
+        mem::replace(&mut self.buf, B::default())
+    }
+}
+
+impl<B: Recycle> Deref for Pooled<B> {
+    type Target = B;
+
+    fn deref(&self) -> &B {
+        &self.buf
+    }
+}
+
+impl<B: Recycle> DerefMut for Pooled<B> {
+    fn deref_mut(&mut self) -> &mut B {
+        &mut self.buf
+    }
+}
+
+impl<B: Recycle> AsRef<[u8]> for Pooled<B>
+where
+    B: AsRef<[u8]>,
+{
+    fn as_ref(&self) -> &[u8] {
+        self.buf.as_ref()
+    }
+}
+
+impl<B: Recycle + fmt::Debug> fmt::Debug for Pooled<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.buf.fmt(f)
+    }
+}
+
+impl<B: Recycle> Drop for Pooled<B> {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            mem::replace(&mut self.buf, B::default()).recycle(&pool);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pooled_bufs_are_reused() {
+        let pool = BufPool::new(1);
+
+        let mut write = Pooled::new(Vec::with_capacity(64));
+        write.set_pool(pool.clone(), true);
+        write.extend_from_slice(b"hello");
+        drop(write);
+        assert_eq!(pool.len(), (0, 1));
+
+        let mut write = Pooled::new(Vec::new());
+        write.set_pool(pool.clone(), true);
+        assert!(write.is_empty());
+        assert_eq!(write.capacity(), 64);
+        assert_eq!(pool.len(), (0, 0));
+
+        // taken out buffers aren't returned
+        let read = Pooled::new(BytesMut::with_capacity(64));
+        let mut read2 = Pooled::new(BytesMut::with_capacity(64));
+        read2.set_pool(pool.clone(), false);
+        drop(read2.into_inner());
+        drop(read);
+        assert_eq!(pool.len(), (0, 0));
+    }
+
+    #[test]
+    fn pool_keeps_at_most_max() {
+        let pool = BufPool::new(1);
+        for _ in 0..2 {
+            let mut read = Pooled::new(BytesMut::with_capacity(64));
+            read.set_pool(pool.clone(), false);
+        }
+        assert_eq!(pool.len(), (1, 0));
+
+        // large buffers are dropped
+        let mut write = Pooled::new(Vec::with_capacity(MAX_POOLED_CAPACITY + 1));
+        write.set_pool(pool.clone(), false);
+        drop(write);
+        assert_eq!(pool.len(), (1, 0));
+    }
+}
@@ -1,16 +1,27 @@
 use std::fmt;
 use std::io::{self};
 use std::marker::PhantomData;
+use std::mem;
 
-use bytes::{Buf, Bytes};
+use bytes::{Buf, Bytes, BytesMut};
 use futures::{Async, Poll};
 use http::{HeaderMap, Method, Version};
+use http::header::{HeaderValue, CONNECTION};
 use tokio_io::{AsyncRead, AsyncWrite};
 
 use ::Chunk;
+use body::ChunkExtensions;
+use ext::Framing;
+use headers;
+use instrument::{Instrument, SentError};
 use proto::{BodyLength, MessageHead};
-use super::io::{Buffered};
-use super::{EncodedBuf, Encode, Encoder, Decode, Decoder, Http1Transaction, ParseContext};
+use proxy::FramingPolicy;
+use server::conn::ConnectionState;
+use super::buf_pool::BufPool;
+use super::io::{Buffered, BufSizes};
+#[cfg(all(feature = "sendfile", target_os = "linux"))] use super::sendfile::{self, SendFile};
+#[cfg(all(feature = "sendfile", target_os = "linux"))] use tokio_tcp::TcpStream;
+use super::{EncodedBuf, Encode, Encoder, Decode, Decoder, Http1Transaction, ParseConfig, ParseContext};
 
 const H2_PREFACE: &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
 
@@ -24,6 +35,10 @@ const H2_PREFACE: &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
 pub(crate) struct Conn<I, B, T> {
     io: Buffered<I, EncodedBuf<B>>,
     state: State,
+    /// Finds the TCP stream of the transport, if bodies can be sent with
+    /// `sendfile`.
+    #[cfg(all(feature = "sendfile", target_os = "linux"))]
+    tcp_stream: Option<fn(&I) -> Option<&TcpStream>>,
     _marker: PhantomData<T>
 }
 
@@ -40,34 +55,130 @@ where I: AsyncRead + AsyncWrite,
                 error: None,
                 keep_alive: KA::Busy,
                 method: None,
+                parse_config: ParseConfig::default(),
+                preserve_header_case: false,
+                max_uri_len: None,
                 title_case_headers: false,
                 notify_read: false,
+                trailers: None,
+                last_chunk_ext: None,
+                allow_trailers: !T::should_read_first(),
+                strict_content_length: false,
                 reading: Reading::Init,
                 writing: Writing::Init,
                 // We assume a modern world where the remote speaks HTTP/1.1.
                 // If they tell us otherwise, we'll downgrade in `read_head`.
                 version: Version::HTTP_11,
             },
+            #[cfg(all(feature = "sendfile", target_os = "linux"))]
+            tcp_stream: None,
             _marker: PhantomData,
         }
     }
 
+    pub fn set_read_buf(&mut self, buf: BytesMut) {
+        self.io.set_read_buf(buf);
+    }
+
     pub fn set_flush_pipeline(&mut self, enabled: bool) {
         self.io.set_flush_pipeline(enabled);
     }
 
-    pub fn set_max_buf_size(&mut self, max: usize) {
-        self.io.set_max_buf_size(max);
+    pub(crate) fn set_buf_sizes(&mut self, sizes: BufSizes) {
+        self.io.set_buf_sizes(sizes);
+    }
+
+    pub(crate) fn set_buf_pool(&mut self, pool: BufPool) {
+        self.io.set_buf_pool(pool);
+    }
+
+    /// The maximum size of the read buffer, which also bounds how much of a
+    /// dropped body is drained.
+    pub(crate) fn max_buf_size(&self) -> usize {
+        self.io.max_buf_size()
+    }
+
+    pub(crate) fn set_instrument(&mut self, instrument: Instrument) {
+        self.io.set_instrument(instrument);
     }
 
     pub fn set_write_strategy_flatten(&mut self) {
         self.io.set_write_strategy_flatten();
     }
 
+    pub fn set_write_strategy_queue(&mut self) {
+        self.io.set_write_strategy_queue();
+    }
+
     pub fn set_title_case_headers(&mut self) {
         self.state.title_case_headers = true;
     }
 
+    pub fn set_absolute_form(&mut self, enabled: bool) {
+        self.state.parse_config.absolute_form = enabled;
+    }
+
+    pub fn set_lenient_target(&mut self, enabled: bool) {
+        self.state.parse_config.lenient_target = enabled;
+    }
+
+    pub fn set_lenient_uri(&mut self, enabled: bool) {
+        self.state.parse_config.lenient_uri = enabled;
+    }
+
+    pub fn set_record_request_line(&mut self) {
+        self.state.parse_config.record_request_line = true;
+    }
+
+    pub fn set_record_header_case(&mut self) {
+        self.state.parse_config.record_header_case = true;
+    }
+
+    pub fn set_record_header_order(&mut self) {
+        self.state.parse_config.record_header_order = true;
+    }
+
+    pub fn set_allow_obs_fold(&mut self) {
+        self.state.parse_config.obs_fold = true;
+    }
+
+    pub fn set_allow_spaces_after_header_name(&mut self) {
+        self.state.parse_config.spaces_after_header_name = true;
+    }
+
+    pub fn set_allow_missing_reason(&mut self, enabled: bool) {
+        self.state.parse_config.missing_reason = enabled;
+    }
+
+    pub fn set_allow_lf_line_endings(&mut self, enabled: bool) {
+        self.state.parse_config.lf_line_endings = enabled;
+    }
+
+    pub fn set_framing_policy(&mut self, policy: FramingPolicy) {
+        self.state.parse_config.framing = policy;
+    }
+
+    pub fn set_max_request_line(&mut self, max: usize) {
+        self.state.parse_config.max_request_line = Some(max);
+    }
+
+    pub fn set_require_host(&mut self) {
+        self.state.parse_config.require_host = true;
+    }
+
+    pub fn set_strict_content_length(&mut self) {
+        self.state.strict_content_length = true;
+    }
+
+    pub fn set_max_uri_len(&mut self, max: usize) {
+        self.state.max_uri_len = Some(max);
+    }
+
+    pub fn set_preserve_header_case(&mut self) {
+        self.state.parse_config.record_header_case = true;
+        self.state.preserve_header_case = true;
+    }
+
     pub fn into_inner(self) -> (I, Bytes) {
         self.io.into_inner()
     }
@@ -80,6 +191,28 @@ where I: AsyncRead + AsyncWrite,
         self.state.is_write_closed()
     }
 
+    pub(crate) fn connection_state(&self) -> ConnectionState {
+        ConnectionState {
+            version: Some(self.state.version),
+            reading: match self.state.reading {
+                Reading::Init => "head",
+                Reading::Body(..) => "body",
+                Reading::KeepAlive => "keep-alive",
+                Reading::Closed => "closed",
+            },
+            writing: match self.state.writing {
+                Writing::Init => "head",
+                Writing::Body(..) => "body",
+                Writing::KeepAlive => "keep-alive",
+                Writing::Closed => "closed",
+            },
+            keep_alive: self.state.wants_keep_alive(),
+            read_buffered: self.io.read_buf().len(),
+            write_buffered: self.io.write_buffered(),
+            in_flight: 0,
+        }
+    }
+
     pub fn can_read_head(&self) -> bool {
         match self.state.reading {
             //Reading::Init => true,
@@ -122,6 +255,7 @@ where I: AsyncRead + AsyncWrite,
             let msg = match self.io.parse::<T>(ParseContext {
                 cached_headers: &mut self.state.cached_headers,
                 req_method: &mut self.state.method,
+                config: self.state.parse_config,
             }) {
                 Ok(Async::Ready(msg)) => msg,
                 Ok(Async::NotReady) => return Ok(Async::NotReady),
@@ -146,6 +280,9 @@ where I: AsyncRead + AsyncWrite,
             };
 
             self.state.version = msg.head.version;
+            if T::should_read_first() {
+                self.state.allow_trailers = accepts_trailers(&msg.head.headers);
+            }
             let head = msg.head;
             let decoder = match msg.decode {
                 Decode::Normal(d) => {
@@ -191,7 +328,7 @@ where I: AsyncRead + AsyncWrite,
         }
     }
 
-    pub fn read_body(&mut self) -> Poll<Option<Chunk>, io::Error> {
+    pub fn read_body(&mut self) -> Poll<Option<Chunk>, ::Error> {
         debug_assert!(self.can_read_body());
 
         trace!("Conn::read_body");
@@ -200,10 +337,24 @@ where I: AsyncRead + AsyncWrite,
             Reading::Body(ref mut decoder) => {
                 match decoder.decode(&mut self.io) {
                     Ok(Async::Ready(slice)) => {
+                        let ext = decoder.take_chunk_extensions().map(ChunkExtensions::new);
                         let (reading, chunk) = if !slice.is_empty() {
-                            return Ok(Async::Ready(Some(Chunk::from(slice))));
+                            let mut chunk = Chunk::from(slice);
+                            if let Some(ext) = ext {
+                                chunk.set_extensions(ext);
+                            }
+                            return Ok(Async::Ready(Some(chunk)));
                         } else if decoder.is_eof() {
+                            if self.state.strict_content_length && decoder.is_length() &&
+                                self.io.read_buf().iter().any(|&b| b != b'\r' && b != b'\n') {
+                                debug!("incoming body is longer than its content-length");
+                                self.state.close();
+                                return Err(::Error::new_content_length_mismatch(None::<io::Error>));
+                            }
                             debug!("incoming body completed");
+                            self.state.trailers = decoder.take_trailers();
+                            // the extensions of the last chunk, which has no data
+                            self.state.last_chunk_ext = ext;
                             (Reading::KeepAlive, None)
                         } else {
                             trace!("decode stream unexpectedly ended");
@@ -217,7 +368,14 @@ where I: AsyncRead + AsyncWrite,
                     Ok(Async::NotReady) => return Ok(Async::NotReady),
                     Err(e) => {
                         trace!("decode stream error: {}", e);
-                        (Reading::Closed, Err(e))
+                        let err = if self.state.strict_content_length && decoder.is_length() &&
+                            e.kind() == io::ErrorKind::UnexpectedEof {
+                            debug!("incoming body is shorter than its content-length");
+                            ::Error::new_content_length_mismatch(Some(e))
+                        } else {
+                            ::Error::new_body(e)
+                        };
+                        (Reading::Closed, Err(err))
                     },
                 }
             },
@@ -229,6 +387,47 @@ where I: AsyncRead + AsyncWrite,
         ret
     }
 
+    /// Reads the whole incoming body at once, if it has a known length and
+    /// all of it is already in the read buffer.
+    ///
+    /// The chunk is a slice of the read buffer, so small messages don't need
+    /// a body channel, nor any copies.
+    pub fn read_buffered_body(&mut self) -> Option<Chunk> {
+        if self.state.strict_content_length {
+            // a longer body is reported as an error of the body
+            return None;
+        }
+        let buffered = self.io.read_buf().len() as u64;
+        match self.state.reading {
+            Reading::Body(ref decoder) => match decoder.remaining_length() {
+                Some(len) if len <= buffered => (),
+                _ => return None,
+            },
+            _ => return None,
+        }
+        trace!("Conn::read_buffered_body");
+        let chunk = match self.read_body() {
+            Ok(Async::Ready(Some(chunk))) => chunk,
+            _ => unreachable!("buffered body wasn't read at once"),
+        };
+        // the decoder is at the end of the body, which this notices
+        match self.read_body() {
+            Ok(Async::Ready(None)) => Some(chunk),
+            _ => unreachable!("buffered body didn't end"),
+        }
+    }
+
+    /// Takes the trailers of the body that was just read, if any.
+    pub fn take_trailers(&mut self) -> Option<HeaderMap> {
+        self.state.trailers.take()
+    }
+
+    /// Takes the extensions of the last chunk of the body that was just
+    /// read, if any.
+    pub fn take_last_chunk_extensions(&mut self) -> Option<ChunkExtensions> {
+        self.state.last_chunk_ext.take()
+    }
+
     pub fn read_keep_alive(&mut self) -> Result<(), ::Error> {
         debug_assert!(!self.can_read_head() && !self.can_read_body());
 
@@ -236,10 +435,40 @@ where I: AsyncRead + AsyncWrite,
 
         if !self.is_mid_message() {
             self.require_empty_read().map_err(::Error::new_io)?;
+        } else if T::should_read_first() {
+            self.mid_message_detect_reset().map_err(::Error::new_io)?;
         }
         Ok(())
     }
 
+    // A server still watches the connection while it is responding, so
+    // that a reset by the client is noticed while waiting for the service or
+    // the response body, instead of only when writing to it.
+    fn mid_message_detect_reset(&mut self) -> io::Result<()> {
+        match self.state.reading {
+            Reading::KeepAlive => (),
+            _ => return Ok(()),
+        }
+        // Don't buffer up pipelined requests, one read is enough to notice.
+        if !self.io.read_buf().is_empty() {
+            return Ok(());
+        }
+        match self.io.read_from_io() {
+            Ok(Async::Ready(0)) => {
+                // A clean EOF isn't a reset, the client may have only
+                // closed its write half, and still be waiting for the
+                // response. The EOF is read again for the next message.
+                trace!("mid_message_detect_reset; found EOF on connection");
+                Ok(())
+            },
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.state.close();
+                Err(e)
+            },
+        }
+    }
+
     fn is_mid_message(&self) -> bool {
         match (&self.state.reading, &self.state.writing) {
             (&Reading::Init, &Writing::Init) => false,
@@ -402,13 +631,22 @@ where I: AsyncRead + AsyncWrite,
         }
     }
 
-    pub fn write_full_msg(&mut self, head: MessageHead<T::Outgoing>, body: B) {
-        if let Some(encoder) = self.encode_head(head, Some(BodyLength::Known(body.remaining() as u64))) {
+    pub fn write_full_msg(&mut self, head: MessageHead<T::Outgoing>, body: B, chunk_ext: Option<Bytes>) -> ::Result<()> {
+        if let Some(mut encoder) = self.encode_head(head, Some(BodyLength::Known(body.remaining() as u64))) {
+            if let Some(ext) = chunk_ext {
+                encoder.set_chunk_extensions(ext);
+            }
             let is_last = encoder.is_last();
             // Make sure we don't write a body if we weren't actually allowed
             // to do so, like because its a HEAD request.
             if !encoder.is_eof() {
-                encoder.danger_full_buf(body, self.io.write_buf());
+                // a user's Content-Length may not match the body
+                self.state.writing = Writing::Body(encoder);
+                self.check_body_length(body.remaining(), true)?;
+                match mem::replace(&mut self.state.writing, Writing::Init) {
+                    Writing::Body(encoder) => encoder.danger_full_buf(body, self.io.write_buf()),
+                    _ => unreachable!("write_full_msg writing state"),
+                }
             }
             self.state.writing = if is_last {
                 Writing::Closed
@@ -416,6 +654,7 @@ where I: AsyncRead + AsyncWrite,
                 Writing::KeepAlive
             }
         }
+        Ok(())
     }
 
     fn encode_head(&mut self, mut head: MessageHead<T::Outgoing>, body: Option<BodyLength>) -> Option<Encoder> {
@@ -425,7 +664,7 @@ where I: AsyncRead + AsyncWrite,
             self.state.busy();
         }
 
-        self.enforce_version(&mut head);
+        self.enforce_version(&mut head, body.as_ref());
 
         let buf = self.io.headers_buf();
         match T::encode(Encode {
@@ -434,6 +673,8 @@ where I: AsyncRead + AsyncWrite,
             keep_alive: self.state.wants_keep_alive(),
             req_method: &mut self.state.method,
             title_case_headers: self.state.title_case_headers,
+            preserve_header_case: self.state.preserve_header_case,
+            max_uri_len: self.state.max_uri_len,
         }, buf) {
             Ok(encoder) => {
                 debug_assert!(self.state.cached_headers.is_none());
@@ -444,6 +685,11 @@ where I: AsyncRead + AsyncWrite,
             Err(err) => {
                 self.state.error = Some(err);
                 self.state.writing = Writing::Closed;
+                if !T::should_read_first() {
+                    // A client won't get a response to a request that
+                    // couldn't be written, so stop waiting for one.
+                    self.state.close_read();
+                }
                 None
             },
         }
@@ -451,13 +697,14 @@ where I: AsyncRead + AsyncWrite,
 
     // If we know the remote speaks an older version, we try to fix up any messages
     // to work with our older peer.
-    fn enforce_version(&mut self, head: &mut MessageHead<T::Outgoing>) {
+    fn enforce_version(&mut self, head: &mut MessageHead<T::Outgoing>, body: Option<&BodyLength>) {
 
         match self.state.version {
             Version::HTTP_10 => {
                 // If the remote only knows HTTP/1.0, we should force ourselves
                 // to do only speak HTTP/1.0 as well.
                 head.version = Version::HTTP_10;
+                self.fix_keep_alive(head, body);
             },
             _ => {
                 // If the remote speaks HTTP/1.1, then it *should* be fine with
@@ -467,11 +714,40 @@ where I: AsyncRead + AsyncWrite,
         }
     }
 
-    pub fn write_body(&mut self, chunk: B) {
+    // HTTP/1.0 connections are only kept alive if the message asks for it,
+    // so ask for it if we still want to.
+    fn fix_keep_alive(&mut self, head: &mut MessageHead<T::Outgoing>, body: Option<&BodyLength>) {
+        let asks = head.headers
+            .get_all(CONNECTION)
+            .iter()
+            .any(headers::connection_keep_alive);
+        if asks || !self.state.wants_keep_alive() {
+            return;
+        }
+        // HTTP/1.0 can't be chunked, so a response body of unknown length
+        // is ended by closing the connection.
+        let close_delimited = T::should_read_first() && match body {
+            Some(_) if head.extensions.get::<Framing>() == Some(&Framing::CloseDelimited) => true,
+            Some(&BodyLength::Unknown) => headers::content_length_parse_all(&head.headers).is_none(),
+            _ => false,
+        };
+        if close_delimited {
+            self.state.disable_keep_alive();
+        } else if head.headers.contains_key(CONNECTION) || head.extensions.get::<Framing>() == Some(&Framing::Raw) {
+            // the message won't ask for it, as sent
+            self.state.disable_keep_alive();
+        } else {
+            head.headers.insert(CONNECTION, headers::keep_alive_value());
+        }
+    }
+
+    pub fn write_body(&mut self, chunk: B) -> ::Result<()> {
         debug_assert!(self.can_write_body() && self.can_buffer_body());
         // empty chunks should be discarded at Dispatcher level
         debug_assert!(chunk.remaining() != 0);
 
+        self.check_body_length(chunk.remaining(), false)?;
+
         let state = match self.state.writing {
             Writing::Body(ref mut encoder) => {
                 self.io.buffer(encoder.encode(chunk));
@@ -483,20 +759,75 @@ where I: AsyncRead + AsyncWrite,
                         Writing::KeepAlive
                     }
                 } else {
-                    return;
+                    return Ok(());
                 }
             },
             _ => unreachable!("write_body invalid state: {:?}", self.state.writing),
         };
 
         self.state.writing = state;
+        Ok(())
+    }
+
+    // Closes the connection if writing `len` more bytes, and ending the body
+    // if `is_end`, doesn't match the `Content-Length`, since the message
+    // can't be framed correctly anymore.
+    fn check_body_length(&mut self, len: usize, is_end: bool) -> ::Result<()> {
+        let remaining = match self.state.writing {
+            Writing::Body(ref encoder) => encoder.remaining_length(),
+            _ => None,
+        };
+        let err = match remaining {
+            Some(remaining) if len as u64 > remaining => {
+                format!("body is longer than its content-length, by at least {} bytes", len as u64 - remaining)
+            },
+            Some(remaining) if is_end && (len as u64) < remaining => {
+                format!("body is shorter than its content-length, by {} bytes", remaining - len as u64)
+            },
+            _ => return Ok(()),
+        };
+        debug!("{}", err);
+        self.state.close();
+        Err(::Error::new_user_body_length(err))
     }
 
-    pub fn write_body_and_end(&mut self, chunk: B) {
+    /// Sets the chunk extensions of the next chunk written, or of the last
+    /// chunk, if the body is chunked.
+    pub fn set_chunk_extensions(&mut self, ext: Bytes, last: bool) {
+        if let Writing::Body(ref mut encoder) = self.state.writing {
+            if last {
+                encoder.set_last_chunk_extensions(ext);
+            } else {
+                encoder.set_chunk_extensions(ext);
+            }
+        }
+    }
+
+    /// Whether the body being written can end with trailers.
+    pub fn wants_trailers(&self) -> bool {
+        match self.state.writing {
+            Writing::Body(ref encoder) => encoder.is_chunked() && self.state.allow_trailers,
+            _ => false,
+        }
+    }
+
+    /// Sets the trailers to send when the body ends.
+    pub fn set_trailers(&mut self, trailers: HeaderMap) {
+        match self.state.writing {
+            Writing::Body(ref mut encoder) if self.state.allow_trailers => {
+                encoder.set_trailers(trailers);
+            },
+            _ => debug!("trailers cannot be sent, dropping them"),
+        }
+    }
+
+    pub fn write_body_and_end(&mut self, chunk: B) -> ::Result<()> {
         debug_assert!(self.can_write_body() && self.can_buffer_body());
         // empty chunks should be discarded at Dispatcher level
         debug_assert!(chunk.remaining() != 0);
 
+        self.check_body_length(chunk.remaining(), true)?;
+
         let state = match self.state.writing {
             Writing::Body(ref encoder) => {
                 let can_keep_alive = encoder.encode_and_end(chunk, self.io.write_buf());
@@ -510,11 +841,14 @@ where I: AsyncRead + AsyncWrite,
         };
 
         self.state.writing = state;
+        Ok(())
     }
 
-    pub fn end_body(&mut self) {
+    pub fn end_body(&mut self) -> ::Result<()> {
         debug_assert!(self.can_write_body());
 
+        self.check_body_length(0, true)?;
+
         let state = match self.state.writing {
             Writing::Body(ref mut encoder) => {
                 // end of stream, that means we should try to eof
@@ -532,11 +866,11 @@ where I: AsyncRead + AsyncWrite,
                     Err(_not_eof) => Writing::Closed,
                 }
             },
-            _ => return,
+            _ => return Ok(()),
         };
 
         self.state.writing = state;
-
+        Ok(())
     }
 
     // When we get a parse error, depending on what side we are, we might be able
@@ -552,6 +886,7 @@ where I: AsyncRead + AsyncWrite,
                     return Err(::Error::new_version_h2())
                 }
                 if let Some(msg) = T::on_error(&err) {
+                    self.report_error(&msg);
                     // Drop the cached headers so as to not trigger a debug
                     // assert in `write_head`...
                     self.state.cached_headers.take();
@@ -567,6 +902,31 @@ where I: AsyncRead + AsyncWrite,
         Err(err)
     }
 
+    // When a service fails before writing a response, some errors still
+    // have an automatic response, like a request body over its limit.
+    pub fn on_service_error(&mut self, err: ::Error) -> ::Result<()> {
+        if let Writing::Init = self.state.writing {
+            if let Some(mut msg) = T::on_error(&err) {
+                self.report_error(&msg);
+                // The rest of the request body is garbage now, so the
+                // connection is closed after the response.
+                msg.headers.insert(CONNECTION, HeaderValue::from_static("close"));
+                self.state.close_read();
+                self.state.cached_headers.take();
+                self.write_head(msg, None);
+                self.state.error = Some(err);
+                return Ok(());
+            }
+        }
+        Err(err)
+    }
+
+    fn report_error(&self, msg: &MessageHead<T::Outgoing>) {
+        if let (Some(status), Some(instrument)) = (T::error_status(msg), self.io.instrument()) {
+            instrument.error_sent(SentError::Status(status));
+        }
+    }
+
     pub fn flush(&mut self) -> Poll<(), io::Error> {
         try_ready!(self.io.flush());
         self.try_keep_alive();
@@ -619,6 +979,82 @@ where I: AsyncRead + AsyncWrite,
     }
 }
 
+#[cfg(all(feature = "sendfile", target_os = "linux"))]
+impl<I, B, T> Conn<I, B, T>
+where I: AsyncRead + AsyncWrite,
+      B: Buf,
+      T: Http1Transaction,
+{
+    /// Sends the file bodies with `sendfile`, if the transport turns out
+    /// to be plaintext TCP.
+    pub(crate) fn enable_send_file(&mut self)
+    where
+        I: 'static,
+    {
+        self.tcp_stream = Some(sendfile::tcp_stream::<I>);
+    }
+
+    fn tcp_stream(&self) -> Option<&TcpStream> {
+        self.tcp_stream.and_then(|tcp_stream| tcp_stream(self.io.io_ref()))
+    }
+
+    /// The length of the body being written, if it can be sent with
+    /// `sendfile`: it has a `Content-Length`, the transport is plaintext
+    /// TCP, and the bytes written aren't tapped.
+    pub(super) fn send_file_len(&self) -> Option<u64> {
+        let tapped = self.io.instrument().map(Instrument::is_tapped).unwrap_or(false);
+        if tapped || self.tcp_stream().is_none() {
+            return None;
+        }
+        match self.state.writing {
+            Writing::Body(ref encoder) => encoder.remaining_length(),
+            _ => None,
+        }
+    }
+
+    /// Sends the rest of the body from `file`, once the head was flushed.
+    pub(super) fn poll_send_file(&mut self, file: &mut SendFile) -> Poll<(), ::Error> {
+        debug_assert!(self.can_write_body());
+        let remaining = file.remaining();
+        let res = {
+            let tcp = self.tcp_stream().expect("send_file_len checked the transport");
+            file.poll_send(tcp)
+        };
+        let counted = match remaining.checked_sub(file.remaining()) {
+            Some(sent) => self.send_file_written(sent),
+            None => Err(io::Error::new(io::ErrorKind::Other, "sendfile remaining grew")),
+        };
+        if let Err(err) = counted {
+            debug!("sendfile error: {}", err);
+            self.state.close();
+            return Err(::Error::new_body_write(err));
+        }
+        match res {
+            // ending the body checks that the whole file was sent
+            Ok(Async::Ready(())) => self.end_body().map(Async::Ready),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(err) => {
+                debug!("sendfile error: {}", err);
+                self.state.close();
+                Err(::Error::new_body_write(err))
+            },
+        }
+    }
+
+    fn send_file_written(&mut self, sent: u64) -> io::Result<()> {
+        if sent == 0 {
+            return Ok(());
+        }
+        if let Writing::Body(ref mut encoder) = self.state.writing {
+            encoder.written(sent)?;
+        }
+        if let Some(instrument) = self.io.instrument() {
+            instrument.bytes_written(sent as usize);
+        }
+        Ok(())
+    }
+}
+
 impl<I, B: Buf, T> fmt::Debug for Conn<I, B, T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Conn")
@@ -628,6 +1064,16 @@ impl<I, B: Buf, T> fmt::Debug for Conn<I, B, T> {
     }
 }
 
+// Whether a request's `TE` header includes `trailers`.
+fn accepts_trailers(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(::http::header::TE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| coding.split(';').next().unwrap_or("").trim().eq_ignore_ascii_case("trailers"))
+}
+
 struct State {
     /// Re-usable HeaderMap to reduce allocating new ones.
     cached_headers: Option<HeaderMap>,
@@ -641,10 +1087,24 @@ struct State {
     /// This is used to know things such as if the message can include
     /// a body or not.
     method: Option<Method>,
+    parse_config: ParseConfig,
+    preserve_header_case: bool,
+    max_uri_len: Option<usize>,
     title_case_headers: bool,
     /// Set to true when the Dispatcher should poll read operations
     /// again. See the `maybe_notify` method for more.
     notify_read: bool,
+    /// The trailers of the last chunked body read, until they are taken.
+    trailers: Option<HeaderMap>,
+    /// The extensions of the last chunk of the last chunked body read,
+    /// until they are taken.
+    last_chunk_ext: Option<ChunkExtensions>,
+    /// Whether a received body not matching its `Content-Length` is an
+    /// error that closes the connection.
+    strict_content_length: bool,
+    /// Whether trailers may be sent with a chunked body. Servers only send
+    /// them if the request had `TE: trailers`.
+    allow_trailers: bool,
     /// State of allowed reads
     reading: Reading,
     /// State of allowed writes
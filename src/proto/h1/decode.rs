@@ -1,10 +1,16 @@
 use std::error::Error as StdError;
 use std::fmt;
+use std::mem;
 use std::usize;
 use std::io;
 
 use futures::{Async, Poll};
 use bytes::Bytes;
+use http::HeaderMap;
+use http::header::{HeaderName, HeaderValue};
+use httparse;
+
+use headers;
 
 use super::io::MemRead;
 use super::BodyLength;
@@ -18,8 +24,23 @@ use self::Kind::{Length, Chunked, Eof};
 #[derive(Clone, PartialEq)]
 pub struct Decoder {
     kind: Kind,
+    /// The extensions of the chunk size line being read.
+    chunk_ext: Vec<u8>,
+    /// The extensions of the current chunk, until they are taken.
+    chunk_ext_ready: Option<Bytes>,
+    /// The trailer section being read.
+    trailer_buf: Vec<u8>,
+    /// The parsed trailers, until they are taken.
+    trailers: Option<HeaderMap>,
 }
 
+/// The maximum length of the chunk extensions of a single chunk.
+const MAX_CHUNK_EXTENSIONS_LEN: usize = 16 * 1024;
+/// The maximum length of the trailer section of a chunked body.
+const MAX_TRAILERS_LEN: usize = 16 * 1024;
+/// The maximum number of trailer fields of a chunked body.
+const MAX_TRAILERS: usize = 64;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum Kind {
     /// A Reader used when a Content-Length header is passed with a positive integer.
@@ -56,6 +77,7 @@ enum ChunkedState {
     BodyLf,
     EndCr,
     EndLf,
+    Trailer,
     End,
 }
 
@@ -63,19 +85,36 @@ impl Decoder {
     // constructors
 
     pub fn length(x: u64) -> Decoder {
-        Decoder { kind: Kind::Length(x) }
+        Decoder::new(Kind::Length(x))
     }
 
     pub fn chunked() -> Decoder {
-        Decoder { kind: Kind::Chunked(ChunkedState::Size, 0) }
+        Decoder::new(Kind::Chunked(ChunkedState::Size, 0))
     }
 
     pub fn eof() -> Decoder {
-        Decoder { kind: Kind::Eof(false) }
+        Decoder::new(Kind::Eof(false))
+    }
+
+    fn new(kind: Kind) -> Decoder {
+        Decoder {
+            kind,
+            chunk_ext: Vec::new(),
+            chunk_ext_ready: None,
+            trailer_buf: Vec::new(),
+            trailers: None,
+        }
     }
 
     // methods
 
+    pub fn is_length(&self) -> bool {
+        match self.kind {
+            Length(_) => true,
+            _ => false,
+        }
+    }
+
     pub fn is_eof(&self) -> bool {
         match self.kind {
             Length(0) |
@@ -85,6 +124,24 @@ impl Decoder {
         }
     }
 
+    /// Takes the chunk extensions of the chunk that was last decoded.
+    pub fn take_chunk_extensions(&mut self) -> Option<Bytes> {
+        self.chunk_ext_ready.take()
+    }
+
+    /// Takes the trailers of a chunked body, once it has ended.
+    pub fn take_trailers(&mut self) -> Option<HeaderMap> {
+        self.trailers.take()
+    }
+
+    /// The bytes left of a body with a known length.
+    pub fn remaining_length(&self) -> Option<u64> {
+        match self.kind {
+            Length(remaining) => Some(remaining),
+            _ => None,
+        }
+    }
+
     pub fn content_length(&self) -> Option<BodyLength> {
         match self.kind {
             Length(0) |
@@ -119,9 +176,18 @@ impl Decoder {
                 loop {
                     let mut buf = None;
                     // advances the chunked state
-                    *state = try_ready!(state.step(body, size, &mut buf));
+                    *state = try_ready!(state.step(body, size, &mut buf, &mut self.chunk_ext, &mut self.trailer_buf));
+                    if !self.chunk_ext.is_empty() &&
+                        (*state == ChunkedState::Body || *state == ChunkedState::EndCr) {
+                        let ext = mem::replace(&mut self.chunk_ext, Vec::new());
+                        self.chunk_ext_ready = Some(Bytes::from(ext));
+                    }
                     if *state == ChunkedState::End {
                         trace!("end of chunked");
+                        if !self.trailer_buf.is_empty() {
+                            let trailer_buf = mem::replace(&mut self.trailer_buf, Vec::new());
+                            self.trailers = Some(parse_trailers(&trailer_buf)?);
+                        }
                         return Ok(Async::Ready(Bytes::new()));
                     }
                     if let Some(buf) = buf {
@@ -178,19 +244,22 @@ impl ChunkedState {
     fn step<R: MemRead>(&self,
                         body: &mut R,
                         size: &mut u64,
-                        buf: &mut Option<Bytes>)
+                        buf: &mut Option<Bytes>,
+                        ext: &mut Vec<u8>,
+                        trailers: &mut Vec<u8>)
                         -> Poll<ChunkedState, io::Error> {
         use self::ChunkedState::*;
         match *self {
             Size => ChunkedState::read_size(body, size),
             SizeLws => ChunkedState::read_size_lws(body),
-            Extension => ChunkedState::read_extension(body),
+            Extension => ChunkedState::read_extension(body, ext),
             SizeLf => ChunkedState::read_size_lf(body, *size),
             Body => ChunkedState::read_body(body, size, buf),
             BodyCr => ChunkedState::read_body_cr(body),
             BodyLf => ChunkedState::read_body_lf(body),
-            EndCr => ChunkedState::read_end_cr(body),
+            EndCr => ChunkedState::read_end_cr(body, trailers),
             EndLf => ChunkedState::read_end_lf(body),
+            Trailer => ChunkedState::read_trailer(body, trailers),
             End => Ok(Async::Ready(ChunkedState::End)),
         }
     }
@@ -233,11 +302,17 @@ impl ChunkedState {
             }
         }
     }
-    fn read_extension<R: MemRead>(rdr: &mut R) -> Poll<ChunkedState, io::Error> {
+    fn read_extension<R: MemRead>(rdr: &mut R, ext: &mut Vec<u8>) -> Poll<ChunkedState, io::Error> {
         trace!("read_extension");
         match byte!(rdr) {
             b'\r' => Ok(Async::Ready(ChunkedState::SizeLf)),
-            _ => Ok(Async::Ready(ChunkedState::Extension)), // no supported extensions
+            _ if ext.len() >= MAX_CHUNK_EXTENSIONS_LEN => {
+                Err(io::Error::new(io::ErrorKind::InvalidData, "chunk extensions too long"))
+            },
+            b => {
+                ext.push(b);
+                Ok(Async::Ready(ChunkedState::Extension))
+            },
         }
     }
     fn read_size_lf<R: MemRead>(rdr: &mut R, size: u64) -> Poll<ChunkedState, io::Error> {
@@ -297,10 +372,14 @@ impl ChunkedState {
         }
     }
 
-    fn read_end_cr<R: MemRead>(rdr: &mut R) -> Poll<ChunkedState, io::Error> {
+    fn read_end_cr<R: MemRead>(rdr: &mut R, trailers: &mut Vec<u8>) -> Poll<ChunkedState, io::Error> {
         match byte!(rdr) {
             b'\r' => Ok(Async::Ready(ChunkedState::EndLf)),
-            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid chunk end CR")),
+            b'\n' => Ok(Async::Ready(ChunkedState::End)),
+            b => {
+                trailers.push(b);
+                Ok(Async::Ready(ChunkedState::Trailer))
+            },
         }
     }
     fn read_end_lf<R: MemRead>(rdr: &mut R) -> Poll<ChunkedState, io::Error> {
@@ -309,6 +388,43 @@ impl ChunkedState {
             _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid chunk end LF")),
         }
     }
+    fn read_trailer<R: MemRead>(rdr: &mut R, trailers: &mut Vec<u8>) -> Poll<ChunkedState, io::Error> {
+        trace!("read_trailer");
+        if trailers.len() >= MAX_TRAILERS_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "chunked trailers too long"));
+        }
+        trailers.push(byte!(rdr));
+        // fields may end with a bare LF, as with headers
+        if trailers.ends_with(b"\n\r\n") || trailers.ends_with(b"\n\n") {
+            Ok(Async::Ready(ChunkedState::End))
+        } else {
+            Ok(Async::Ready(ChunkedState::Trailer))
+        }
+    }
+}
+
+// Parses the trailer section of a chunked body.
+//
+// Fields not allowed to be sent as trailers are dropped.
+fn parse_trailers(buf: &[u8]) -> Result<HeaderMap, io::Error> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid chunked trailers");
+    let mut headers = [httparse::EMPTY_HEADER; MAX_TRAILERS];
+    let fields = match httparse::parse_headers(buf, &mut headers) {
+        Ok(httparse::Status::Complete((_, fields))) => fields,
+        _ => return Err(invalid()),
+    };
+
+    let mut trailers = HeaderMap::with_capacity(fields.len());
+    for field in fields {
+        let name = HeaderName::from_bytes(field.name.as_bytes()).map_err(|_| invalid())?;
+        if headers::FORBIDDEN_TRAILERS.contains(&name) {
+            debug!("dropping disallowed trailer field: {}", name);
+            continue;
+        }
+        let value = HeaderValue::from_bytes(field.value).map_err(|_| invalid())?;
+        trailers.append(name, value);
+    }
+    Ok(trailers)
 }
 
 #[derive(Debug)]
@@ -380,7 +496,7 @@ mod tests {
             let rdr = &mut s.as_bytes();
             let mut size = 0;
             loop {
-                let result = state.step(rdr, &mut size, &mut None);
+                let result = state.step(rdr, &mut size, &mut None, &mut Vec::new(), &mut Vec::new());
                 let desc = format!("read_size failed for {:?}", s);
                 state = result.expect(desc.as_str()).unwrap();
                 if state == ChunkedState::Body || state == ChunkedState::EndCr {
@@ -395,7 +511,7 @@ mod tests {
             let rdr = &mut s.as_bytes();
             let mut size = 0;
             loop {
-                let result = state.step(rdr, &mut size, &mut None);
+                let result = state.step(rdr, &mut size, &mut None, &mut Vec::new(), &mut Vec::new());
                 state = match result {
                     Ok(s) => s.unwrap(),
                     Err(e) => {
@@ -491,6 +607,65 @@ mod tests {
         assert_eq!(0, buf.len());
     }
 
+    #[test]
+    fn test_read_chunked_extensions() {
+        let mut mock_buf = &b"3;sig=a1\r\nfoo\r\n3\r\nbar\r\n0; sig=\"b2\"\r\n\r\n"[..];
+        let mut decoder = Decoder::chunked();
+
+        assert_eq!(decoder.decode(&mut mock_buf).unwrap().unwrap(), "foo");
+        assert_eq!(decoder.take_chunk_extensions().unwrap(), "sig=a1");
+        assert_eq!(decoder.decode(&mut mock_buf).unwrap().unwrap(), "bar");
+        assert!(decoder.take_chunk_extensions().is_none());
+        assert!(decoder.decode(&mut mock_buf).unwrap().unwrap().is_empty());
+        assert_eq!(decoder.take_chunk_extensions().unwrap(), " sig=\"b2\"");
+        assert!(decoder.is_eof());
+
+        let long = format!("1;{}\r\n", "a".repeat(super::MAX_CHUNK_EXTENSIONS_LEN + 1));
+        let e = Decoder::chunked().decode(&mut long.as_bytes()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_chunked_trailers() {
+        let mut mock_buf = &b"3\r\nfoo\r\n0\r\nChecksum: abc\r\nContent-Length: 3\r\nX-Done: 1\r\n\r\n"[..];
+        let mut decoder = Decoder::chunked();
+
+        assert_eq!(decoder.decode(&mut mock_buf).unwrap().unwrap(), "foo");
+        assert!(decoder.take_trailers().is_none());
+        assert!(decoder.decode(&mut mock_buf).unwrap().unwrap().is_empty());
+        assert!(decoder.is_eof());
+        let trailers = decoder.take_trailers().expect("trailers");
+        assert_eq!(trailers.len(), 2);
+        assert_eq!(trailers["checksum"], "abc");
+        assert_eq!(trailers["x-done"], "1");
+
+        let mut mock_buf = &b"0\r\nChecksum: abc\nX-Done: 1\n\n"[..];
+        let mut decoder = Decoder::chunked();
+        assert!(decoder.decode(&mut mock_buf).unwrap().unwrap().is_empty());
+        assert!(decoder.is_eof());
+        let trailers = decoder.take_trailers().expect("trailers");
+        assert_eq!(trailers["checksum"], "abc");
+        assert_eq!(trailers["x-done"], "1");
+
+        for end in &[&b"0\r\n\r\n"[..], &b"0\r\n\n"[..]] {
+            let mut mock_buf = *end;
+            let mut decoder = Decoder::chunked();
+            assert!(decoder.decode(&mut mock_buf).unwrap().unwrap().is_empty());
+            assert!(decoder.is_eof());
+            assert!(decoder.take_trailers().is_none());
+        }
+
+        let mut mock_buf = &b"0\r\nbad header\r\n\r\n"[..];
+        let e = Decoder::chunked().decode(&mut mock_buf).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_chunked_trailers_async() {
+        let content = "3\r\nfoo\r\n0\r\nChecksum: abc\r\n\r\n";
+        all_async_cases(content, "foo", Decoder::chunked());
+    }
+
     // perform an async read using a custom buffer size and causing a blocking
     // read at the specified byte
     fn read_async(mut decoder: Decoder,
@@ -1,12 +1,22 @@
 use bytes::{Buf, Bytes};
 use futures::{Async, Future, Poll, Stream};
+use futures::task;
 use http::{Request, Response, StatusCode};
 use tokio_io::{AsyncRead, AsyncWrite};
 
-use body::{Body, Payload};
+use body::{Body, ChunkExtensions, Payload};
 use body::internal::FullDataArg;
+#[cfg(all(feature = "sendfile", target_os = "linux"))] use body::internal::FileArg;
+use client::CancelHandle;
+use client::cancel::CancelWatch;
+use common::Budget;
+use common::span::Span;
+use ext::Timings;
+use instrument::{ConnectionGuard, Instrument, RequestGuard};
 use proto::{BodyLength, Conn, MessageHead, RequestHead, RequestLine, ResponseHead};
+use proto::proxy::ProxyInfo;
 use super::Http1Transaction;
+#[cfg(all(feature = "sendfile", target_os = "linux"))] use super::sendfile::SendFile;
 use service::Service;
 
 pub(crate) struct Dispatcher<D, Bs: Payload, I, T> {
@@ -14,7 +24,23 @@ pub(crate) struct Dispatcher<D, Bs: Payload, I, T> {
     dispatch: D,
     body_tx: Option<::body::Sender>,
     body_rx: Option<Bs>,
+    /// Whether the data of `body_rx` has ended, and its trailers are next.
+    is_polling_trailers: bool,
+    /// The file of `body_rx`, sent in its place.
+    #[cfg(all(feature = "sendfile", target_os = "linux"))]
+    send_file: Option<SendFile>,
+    /// Whether the body being read is discarded, since its receiver was
+    /// dropped.
+    is_draining: bool,
+    /// How many more bytes of the dropped body are drained, before closing
+    /// the connection instead.
+    drain_left: usize,
+    /// Whether a message was started, but not yet fully written and flushed.
+    is_writing_msg: bool,
     is_closing: bool,
+    /// Bounds the messages and body chunks handled in a single poll.
+    budget: Budget,
+    span: Span,
 }
 
 pub(crate) trait Dispatch {
@@ -23,18 +49,52 @@ pub(crate) trait Dispatch {
     type RecvItem;
     fn poll_msg(&mut self) -> Poll<Option<(Self::PollItem, Self::PollBody)>, ::Error>;
     fn recv_msg(&mut self, msg: ::Result<(Self::RecvItem, Body)>) -> ::Result<()>;
-    fn poll_ready(&mut self) -> Poll<(), ()>;
+    /// Errors with `None` once no more messages are wanted, or with the
+    /// error of a service that can't take any.
+    fn poll_ready(&mut self) -> Poll<(), Option<::Error>>;
     fn should_poll(&self) -> bool;
+    /// Returns true if the message in flight was canceled, and the
+    /// connection must be closed. `body_in_flight` is whether parts of it
+    /// are still being read or written by the `Dispatcher`.
+    fn poll_canceled(&mut self, body_in_flight: bool) -> bool;
+    /// Returns true if the body of the received message should still be
+    /// read, once its receiver was dropped, to keep the connection.
+    fn should_drain_dropped_body(&self) -> bool;
+    /// Called once the polled message, including its body, was flushed.
+    fn on_msg_written(&mut self);
+    /// Called with the length of each chunk of the polled message's body
+    /// that is written.
+    fn on_body_written(&mut self, n: usize);
 }
 
 pub struct Server<S: Service> {
     in_flight: Option<S::Future>,
+    instrument: Option<Instrument>,
+    proxy_info: Option<ProxyInfo>,
+    /// The request being served, until its response is written.
+    request: Option<RequestGuard>,
     pub(crate) service: S,
+    /// The span of the request being served, entered while calling the
+    /// service and polling its future.
+    span: Option<Span>,
 }
 
 pub struct Client<B> {
     callback: Option<::client::dispatch::Callback<Request<B>, Response<Body>>>,
+    cancel: Option<CancelHandle>,
+    cancel_dropped_requests: bool,
+    /// Reports the connection closed once dropped.
+    connection: Option<ConnectionGuard>,
+    instrument: Option<Instrument>,
+    /// The request in flight, until its response is received.
+    request: Option<RequestGuard>,
+    /// Whether the response being read was for a dropped `ResponseFuture`.
+    response_dropped: bool,
+    /// The `Timings` of the request in flight, if recorded.
+    timings: Option<Timings>,
     rx: ClientRx<B>,
+    /// The span of the request in flight, until its response is received.
+    span: Option<Span>,
 }
 
 type ClientRx<B> = ::client::dispatch::Receiver<Request<B>, Response<Body>>;
@@ -52,7 +112,16 @@ where
             dispatch: dispatch,
             body_tx: None,
             body_rx: None,
+            is_polling_trailers: false,
+            #[cfg(all(feature = "sendfile", target_os = "linux"))]
+            send_file: None,
+            is_draining: false,
+            drain_left: 0,
+            is_writing_msg: false,
             is_closing: false,
+            budget: Budget::new(),
+            // only servers read first
+            span: Span::connection(if T::should_read_first() { "server" } else { "client" }, "http1"),
         }
     }
 
@@ -60,6 +129,14 @@ where
         self.conn.disable_keep_alive()
     }
 
+    #[cfg(all(feature = "sendfile", target_os = "linux"))]
+    pub(crate) fn enable_send_file(&mut self)
+    where
+        I: 'static,
+    {
+        self.conn.enable_send_file()
+    }
+
     pub fn into_inner(self) -> (I, Bytes, D) {
         let (io, buf) = self.conn.into_inner();
         (io, buf, self.dispatch)
@@ -80,6 +157,8 @@ where
     }
 
     fn poll_catch(&mut self, should_shutdown: bool) -> Poll<(), ::Error> {
+        let span = self.span.clone();
+        let _entered = span.enter();
         self.poll_inner(should_shutdown).or_else(|e| {
             // An error means we're shutting down either way.
             // We just try to give the error to the user,
@@ -91,6 +170,15 @@ where
 
     fn poll_inner(&mut self, should_shutdown: bool) -> Poll<(), ::Error> {
         T::update_date();
+        self.budget.reset();
+        if !self.is_closing && self.dispatch.poll_canceled(self.body_tx.is_some() || self.body_rx.is_some()) {
+            trace!("in flight message canceled, closing");
+            if let Some(mut body) = self.body_tx.take() {
+                body.send_error(::Error::new_request_canceled());
+            }
+            self.body_rx = None;
+            self.close();
+        }
         loop {
             self.poll_read()?;
             self.poll_write()?;
@@ -107,6 +195,11 @@ where
             if !self.conn.wants_read_again() {
                 break;
             }
+            if self.budget.is_spent() {
+                // there's more to read, once the task is polled again
+                task::current().notify();
+                break;
+            }
         }
 
         if self.is_done() {
@@ -120,12 +213,46 @@ where
         }
     }
 
+    fn start_draining(&mut self) {
+        self.is_draining = true;
+        self.drain_left = self.conn.max_buf_size();
+    }
+
     fn poll_read(&mut self) -> Poll<(), ::Error> {
         loop {
+            if !self.budget.spend() {
+                return Ok(Async::NotReady);
+            }
             if self.is_closing {
                 return Ok(Async::Ready(()));
             } else if self.conn.can_read_head() {
                 try_ready!(self.poll_read_head());
+            } else if self.is_draining {
+                if !self.conn.can_read_body() {
+                    self.is_draining = false;
+                    continue;
+                }
+                match self.conn.read_body() {
+                    Ok(Async::Ready(Some(chunk))) => {
+                        if chunk.len() > self.drain_left {
+                            debug!("dropped body is too long to drain, closing");
+                            self.is_draining = false;
+                            self.conn.close_read();
+                        } else {
+                            self.drain_left -= chunk.len();
+                        }
+                    },
+                    Ok(Async::Ready(None)) => {
+                        trace!("dropped body drained");
+                        self.is_draining = false;
+                    },
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => {
+                        debug!("error draining dropped body: {}", e);
+                        self.is_draining = false;
+                        self.conn.close_read();
+                    }
+                }
             } else if let Some(mut body) = self.body_tx.take() {
                 if self.conn.can_read_body() {
                     match body.poll_ready() {
@@ -135,6 +262,11 @@ where
                             return Ok(Async::NotReady);
                         },
                         Err(_canceled) => {
+                            if self.dispatch.should_drain_dropped_body() {
+                                trace!("body receiver dropped before eof, draining");
+                                self.start_draining();
+                                continue;
+                            }
                             // user doesn't care about the body
                             // so we should stop reading
                             trace!("body receiver dropped before eof, closing");
@@ -149,7 +281,10 @@ where
                                     self.body_tx = Some(body);
                                 },
                                 Err(_canceled) => {
-                                    if self.conn.can_read_body() {
+                                    if self.dispatch.should_drain_dropped_body() {
+                                        trace!("body receiver dropped before eof, draining");
+                                        self.start_draining();
+                                    } else if self.conn.can_read_body() {
                                         trace!("body receiver dropped before eof, closing");
                                         self.conn.close_read();
                                     }
@@ -158,6 +293,11 @@ where
                             }
                         },
                         Ok(Async::Ready(None)) => {
+                            let trailers = self.conn.take_trailers();
+                            let ext = self.conn.take_last_chunk_extensions();
+                            if trailers.is_some() || ext.is_some() {
+                                body.send_end(trailers, ext);
+                            }
                             // just drop, the body will close automatically
                         },
                         Ok(Async::NotReady) => {
@@ -165,7 +305,7 @@ where
                             return Ok(Async::NotReady);
                         }
                         Err(e) => {
-                            body.send_error(::Error::new_body(e));
+                            body.send_error(e);
                         }
                     }
                 } else {
@@ -181,23 +321,31 @@ where
         // can dispatch receive, or does it still care about, an incoming message?
         match self.dispatch.poll_ready() {
             Ok(Async::Ready(())) => (),
-            Ok(Async::NotReady) => unreachable!("dispatch not ready when conn is"),
-            Err(()) => {
+            // the service isn't ready, and will notify this task once it is
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(None) => {
                 trace!("dispatch no longer receiving messages");
                 self.close();
                 return Ok(Async::Ready(()));
-            }
+            },
+            Err(Some(err)) => {
+                debug!("service not ready: {}", err);
+                self.close();
+                return Err(err);
+            },
         }
         // dispatch is ready for a message, try to read one
         match self.conn.read_head() {
             Ok(Async::Ready(Some((head, body_len)))) => {
-                let body = if let Some(body_len) = body_len {
+                let body = if let Some(chunk) = self.conn.read_buffered_body() {
+                    Body::from(chunk)
+                } else if let Some(body_len) = body_len {
                     let (mut tx, rx) =
                         Body::new_channel(if let BodyLength::Known(len) = body_len {
                             Some(len)
                         } else {
                             None
-                        });
+                        }, 1);
                     let _ = tx.poll_ready(); // register this task if rx is dropped
                     self.body_tx = Some(tx);
                     rx
@@ -225,29 +373,52 @@ where
 
     fn poll_write(&mut self) -> Poll<(), ::Error> {
         loop {
+            if !self.budget.spend() {
+                return Ok(Async::NotReady);
+            }
+            #[cfg(all(feature = "sendfile", target_os = "linux"))]
+            {
+                if !self.is_closing && self.send_file.is_some() {
+                    try_ready!(self.poll_send_file());
+                    continue;
+                }
+            }
             if self.is_closing {
                 return Ok(Async::Ready(()));
             } else if self.body_rx.is_none() && self.conn.can_write_head() && self.dispatch.should_poll() {
-                if let Some((head, mut body)) = try_ready!(self.dispatch.poll_msg()) {
+                let msg = match self.dispatch.poll_msg() {
+                    Ok(Async::Ready(msg)) => msg,
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(err) => {
+                        self.conn.on_service_error(err)?;
+                        continue;
+                    }
+                };
+                if let Some((head, mut body)) = msg {
+                    self.is_writing_msg = true;
                     // Check if the body knows its full data immediately.
                     //
                     // If so, we can skip a bit of bookkeeping that streaming
                     // bodies need to do.
                     if let Some(full) = body.__hyper_full_data(FullDataArg(())).0 {
-                        self.conn.write_full_msg(head, full);
+                        self.dispatch.on_body_written(full.remaining());
+                        let chunk_ext = body.chunk_extensions(&full).map(ChunkExtensions::into_bytes);
+                        self.conn.write_full_msg(head, full, chunk_ext)?;
                         return Ok(Async::Ready(()));
                     }
                     let body_type = if body.is_end_stream() {
                         self.body_rx = None;
                         None
                     } else {
-                        let btype = body.content_length()
+                        let btype = body.size_hint().exact()
                             .map(BodyLength::Known)
                             .or_else(|| Some(BodyLength::Unknown));
                         self.body_rx = Some(body);
                         btype
                     };
                     self.conn.write_head(head, body_type);
+                    #[cfg(all(feature = "sendfile", target_os = "linux"))]
+                    self.take_send_file()?;
                 } else {
                     self.close();
                     return Ok(Async::Ready(()));
@@ -262,15 +433,42 @@ where
                     );
                     continue;
                 }
+                if self.is_polling_trailers {
+                    match body.poll_trailers().map_err(::Error::new_user_body)? {
+                        Async::Ready(trailers) => {
+                            self.is_polling_trailers = false;
+                            if let Some(trailers) = trailers {
+                                self.conn.set_trailers(trailers);
+                            }
+                            self.conn.end_body()?;
+                        },
+                        Async::NotReady => {
+                            self.body_rx = Some(body);
+                            return Ok(Async::NotReady);
+                        }
+                    }
+                    continue;
+                }
                 match body.poll_data().map_err(::Error::new_user_body)? {
                     Async::Ready(Some(chunk)) => {
+                        self.dispatch.on_body_written(chunk.remaining());
                         let eos = body.is_end_stream();
-                        if eos {
+                        let flush = body.flush_hint(&chunk);
+                        if let Some(ext) = body.chunk_extensions(&chunk) {
+                            self.conn.set_chunk_extensions(ext.into_bytes(), chunk.remaining() == 0);
+                        }
+                        if eos && self.conn.wants_trailers() {
+                            self.is_polling_trailers = true;
+                            self.body_rx = Some(body);
+                            if chunk.remaining() != 0 {
+                                self.conn.write_body(chunk)?;
+                            }
+                        } else if eos {
                             if chunk.remaining() == 0 {
                                 trace!("discarding empty chunk");
-                                self.conn.end_body();
+                                self.conn.end_body()?;
                             } else {
-                                self.conn.write_body_and_end(chunk);
+                                self.conn.write_body_and_end(chunk)?;
                             }
                         } else {
                             self.body_rx = Some(body);
@@ -278,11 +476,20 @@ where
                                 trace!("discarding empty chunk");
                                 continue;
                             }
-                            self.conn.write_body(chunk);
+                            self.conn.write_body(chunk)?;
+                        }
+                        if flush {
+                            trace!("flushing body chunk");
+                            try_ready!(self.poll_flush());
                         }
                     },
                     Async::Ready(None) => {
-                        self.conn.end_body();
+                        if self.conn.wants_trailers() {
+                            self.is_polling_trailers = true;
+                            self.body_rx = Some(body);
+                        } else {
+                            self.conn.end_body()?;
+                        }
                     },
                     Async::NotReady => {
                         self.body_rx = Some(body);
@@ -295,11 +502,54 @@ where
         }
     }
 
+    // Takes the file of the body, to send it with `sendfile` instead, if
+    // it's the whole body and the connection can.
+    #[cfg(all(feature = "sendfile", target_os = "linux"))]
+    fn take_send_file(&mut self) -> ::Result<()> {
+        let len = match self.conn.send_file_len() {
+            Some(len) => len,
+            None => return Ok(()),
+        };
+        let body = match self.body_rx {
+            Some(ref mut body) if body.size_hint().exact() == Some(len) => body,
+            _ => return Ok(()),
+        };
+        if let Some((file, _)) = body.__hyper_file(FileArg(())).0 {
+            trace!("sending body file with sendfile, {} bytes", len);
+            self.send_file = Some(SendFile::new(file, len).map_err(::Error::new_user_body)?);
+        }
+        Ok(())
+    }
+
+    #[cfg(all(feature = "sendfile", target_os = "linux"))]
+    fn poll_send_file(&mut self) -> Poll<(), ::Error> {
+        // the head is written first
+        try_ready!(self.poll_flush());
+        let mut send_file = self.send_file.take().expect("poll_send_file without a file");
+        let remaining = send_file.remaining();
+        let res = self.conn.poll_send_file(&mut send_file);
+        // the connection errors if the file's remaining bytes ever grew
+        if let Some(sent) = remaining.checked_sub(send_file.remaining()) {
+            self.dispatch.on_body_written(sent as usize);
+        }
+        match res {
+            Ok(Async::NotReady) => self.send_file = Some(send_file),
+            // the body it was taken from was never polled, and isn't needed
+            _ => self.body_rx = None,
+        }
+        res
+    }
+
     fn poll_flush(&mut self) -> Poll<(), ::Error> {
-        self.conn.flush().map_err(|err| {
+        try_ready!(self.conn.flush().map_err(|err| {
             debug!("error writing: {}", err);
             ::Error::new_body_write(err)
-        })
+        }));
+        if self.is_writing_msg && self.body_rx.is_none() && !self.conn.can_write_body() {
+            self.is_writing_msg = false;
+            self.dispatch.on_msg_written();
+        }
+        Ok(Async::Ready(()))
     }
 
     fn close(&mut self) {
@@ -343,15 +593,48 @@ where
     }
 }
 
+impl<S, Bs, I, T> Dispatcher<Server<S>, Bs, I, T>
+where
+    S: Service,
+    I: AsyncRead + AsyncWrite,
+    T: Http1Transaction,
+    Bs: Payload,
+{
+    pub(crate) fn connection_state(&self) -> ::server::conn::ConnectionState {
+        let mut state = self.conn.connection_state();
+        let busy = self.dispatch.in_flight.is_some()
+            || self.body_tx.is_some()
+            || self.body_rx.is_some()
+            || self.is_writing_msg;
+        state.in_flight = if busy { 1 } else { 0 };
+        state
+    }
+}
+
 // ===== impl Server =====
 
 impl<S> Server<S> where S: Service {
     pub fn new(service: S) -> Server<S> {
         Server {
             in_flight: None,
+            instrument: None,
+            proxy_info: None,
+            request: None,
             service: service,
+            span: None,
         }
     }
+
+    /// Report the requests served to `instrument`.
+    pub fn set_instrument(&mut self, instrument: Instrument) {
+        self.instrument = Some(instrument);
+    }
+
+    /// Insert the `ProxyInfo` into every request received.
+    pub fn set_proxy_info(&mut self, info: ProxyInfo) {
+        self.proxy_info = Some(info);
+    }
+
     pub fn into_service(self) -> S {
         self.service
     }
@@ -369,6 +652,8 @@ where
 
     fn poll_msg(&mut self) -> Poll<Option<(Self::PollItem, Self::PollBody)>, ::Error> {
         if let Some(mut fut) = self.in_flight.take() {
+            let span = self.span.clone().expect("span of in flight request");
+            let _entered = span.enter();
             let resp = match fut.poll().map_err(::Error::new_user_service)? {
                 Async::Ready(res) => res,
                 Async::NotReady => {
@@ -376,11 +661,16 @@ where
                     return Ok(Async::NotReady);
                 }
             };
-            let (parts, body) = resp.into_parts();
+            let (mut parts, body) = resp.into_parts();
+            if let Some(ref mut request) = self.request {
+                request.set_status(parts.status);
+                request.set_request_id_header(&mut parts.headers);
+            }
             let head = MessageHead {
                 version: parts.version,
                 subject: parts.status,
                 headers: parts.headers,
+                extensions: parts.extensions,
             };
             Ok(Async::Ready(Some((head, body))))
         } else {
@@ -389,27 +679,63 @@ where
     }
 
     fn recv_msg(&mut self, msg: ::Result<(Self::RecvItem, Body)>) -> ::Result<()> {
-        let (msg, body) = msg?;
+        let (msg, mut body) = msg?;
+        self.request = self.instrument.as_ref()
+            .map(|instrument| instrument.exchange(&msg.subject.0, &msg.subject.1, msg.version, &msg.headers));
+        if let Some(count) = self.request.as_ref().and_then(RequestGuard::body_read) {
+            body.set_read_count(count);
+        }
+        let request_id = self.request.as_ref().and_then(RequestGuard::request_id).cloned();
         let mut req = Request::new(body);
         *req.method_mut() = msg.subject.0;
         *req.uri_mut() = msg.subject.1;
         *req.headers_mut() = msg.headers;
         *req.version_mut() = msg.version;
-        self.in_flight = Some(self.service.call(req));
+        *req.extensions_mut() = msg.extensions;
+        if let Some(ref info) = self.proxy_info {
+            req.extensions_mut().insert(info.clone());
+        }
+        if let Some(id) = request_id {
+            req.extensions_mut().insert(id);
+        }
+        let span = Span::request(req.method(), req.uri());
+        {
+            let _entered = span.enter();
+            self.in_flight = Some(self.service.call(req));
+        }
+        self.span = Some(span);
         Ok(())
     }
 
-    fn poll_ready(&mut self) -> Poll<(), ()> {
+    fn poll_ready(&mut self) -> Poll<(), Option<::Error>> {
         if self.in_flight.is_some() {
-            Ok(Async::NotReady)
-        } else {
-            Ok(Async::Ready(()))
+            return Ok(Async::NotReady);
         }
+        self.service.poll_ready().map_err(|err| Some(::Error::new_user_service(err)))
     }
 
     fn should_poll(&self) -> bool {
         self.in_flight.is_some()
     }
+
+    fn poll_canceled(&mut self, _body_in_flight: bool) -> bool {
+        false
+    }
+
+    fn should_drain_dropped_body(&self) -> bool {
+        false
+    }
+
+    fn on_msg_written(&mut self) {
+        self.request = None;
+        self.span = None;
+    }
+
+    fn on_body_written(&mut self, n: usize) {
+        if let Some(ref mut request) = self.request {
+            request.body_written(n);
+        }
+    }
 }
 
 // ===== impl Client =====
@@ -419,9 +745,30 @@ impl<B> Client<B> {
     pub fn new(rx: ClientRx<B>) -> Client<B> {
         Client {
             callback: None,
+            cancel: None,
+            cancel_dropped_requests: true,
+            connection: None,
+            instrument: None,
+            request: None,
+            response_dropped: false,
+            timings: None,
             rx: rx,
+            span: None,
         }
     }
+
+    /// Sets whether dropping a response future cancels its request, or
+    /// whether the request is still sent, and its response discarded.
+    pub fn set_cancel_dropped_requests(&mut self, enabled: bool) {
+        self.cancel_dropped_requests = enabled;
+    }
+
+    /// Report the connection, and the requests sent on it, to
+    /// `instrument`.
+    pub fn set_instrument(&mut self, instrument: Instrument) {
+        self.connection = Some(instrument.connection());
+        self.instrument = Some(instrument);
+    }
 }
 
 impl<B> Dispatch for Client<B>
@@ -433,44 +780,78 @@ where
     type RecvItem = ResponseHead;
 
     fn poll_msg(&mut self) -> Poll<Option<(Self::PollItem, Self::PollBody)>, ::Error> {
-        match self.rx.poll() {
-            Ok(Async::Ready(Some((req, mut cb)))) => {
-                // check that future hasn't been canceled already
-                match cb.poll_cancel().expect("poll_cancel cannot error") {
-                    Async::Ready(()) => {
-                        trace!("request canceled");
-                        Ok(Async::Ready(None))
-                    },
-                    Async::NotReady => {
-                        let (parts, body) = req.into_parts();
-                        let head = RequestHead {
-                            version: parts.version,
-                            subject: RequestLine(parts.method, parts.uri),
-                            headers: parts.headers,
-                        };
-                        self.callback = Some(cb);
-                        Ok(Async::Ready(Some((head, body))))
+        loop {
+            return match self.rx.poll() {
+                Ok(Async::Ready(Some((mut req, mut cb)))) => {
+                    let cancel = req.extensions_mut().remove::<CancelWatch>().map(|watch| watch.0);
+                    if cancel.as_ref().map(CancelHandle::is_canceled).unwrap_or(false) {
+                        trace!("request canceled before it was sent");
+                        let _ = cb.send(Err((::Error::new_request_canceled(), None)));
+                        continue;
                     }
-                }
-            },
-            Ok(Async::Ready(None)) => {
-                trace!("client tx closed");
-                // user has dropped sender handle
-                Ok(Async::Ready(None))
-            },
-            Ok(Async::NotReady) => return Ok(Async::NotReady),
-            Err(never) => match never {},
+                    // check that future hasn't been canceled already
+                    match cb.poll_cancel().expect("poll_cancel cannot error") {
+                        Async::Ready(()) if self.cancel_dropped_requests => {
+                            trace!("request canceled");
+                            Ok(Async::Ready(None))
+                        },
+                        _ => {
+                            let (parts, body) = req.into_parts();
+                            let head = RequestHead {
+                                version: parts.version,
+                                subject: RequestLine(parts.method, parts.uri),
+                                headers: parts.headers,
+                                extensions: parts.extensions,
+                            };
+                            self.request = self.instrument.as_ref()
+                                .map(|instrument| instrument.request(&head.subject.0));
+                            self.span = Some(Span::request(&head.subject.0, &head.subject.1));
+                            self.callback = Some(cb);
+                            self.cancel = cancel;
+                            self.timings = head.extensions.get::<Timings>().cloned();
+                            if let Some(ref timings) = self.timings {
+                                timings.set_headers_written();
+                            }
+                            Ok(Async::Ready(Some((head, body))))
+                        }
+                    }
+                },
+                Ok(Async::Ready(None)) => {
+                    trace!("client tx closed");
+                    // user has dropped sender handle
+                    Ok(Async::Ready(None))
+                },
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(never) => match never {},
+            };
         }
     }
 
     fn recv_msg(&mut self, msg: ::Result<(Self::RecvItem, Body)>) -> ::Result<()> {
         match msg {
-            Ok((msg, body)) => {
-                if let Some(cb) = self.callback.take() {
+            Ok((mut msg, body)) => {
+                if let Some(mut cb) = self.callback.take() {
+                    let span = self.span.take();
+                    let _entered = span.as_ref().map(Span::enter);
+                    if let Some(mut request) = self.request.take() {
+                        request.set_status(msg.subject);
+                    }
+                    self.response_dropped = cb.poll_cancel().expect("poll_cancel cannot error").is_ready();
+                    let mut body = body;
+                    if let Some(ref timings) = self.timings {
+                        timings.set_first_byte();
+                        if body.is_end_stream() {
+                            timings.set_response_complete();
+                        } else {
+                            body.set_timings(timings.clone());
+                        }
+                        msg.extensions.insert(timings.clone());
+                    }
                     let mut res = Response::new(body);
                     *res.status_mut() = msg.subject;
                     *res.headers_mut() = msg.headers;
                     *res.version_mut() = msg.version;
+                    *res.extensions_mut() = msg.extensions;
                     let _ = cb.send(Ok(res));
                     Ok(())
                 } else {
@@ -478,6 +859,8 @@ where
                 }
             },
             Err(err) => {
+                self.request = None;
+                self.span = None;
                 if let Some(cb) = self.callback.take() {
                     let _ = cb.send(Err((err, None)));
                     Ok(())
@@ -494,23 +877,55 @@ where
         }
     }
 
-    fn poll_ready(&mut self) -> Poll<(), ()> {
+    fn poll_ready(&mut self) -> Poll<(), Option<::Error>> {
         match self.callback {
             Some(ref mut cb) => match cb.poll_cancel() {
-                Ok(Async::Ready(())) => {
+                Ok(Async::Ready(())) if self.cancel_dropped_requests => {
                     trace!("callback receiver has dropped");
-                    Err(())
+                    Err(None)
                 },
-                Ok(Async::NotReady) => Ok(Async::Ready(())),
+                Ok(_) => Ok(Async::Ready(())),
                 Err(_) => unreachable!("oneshot poll_cancel cannot error"),
             },
-            None => Err(()),
+            None => Err(None),
         }
     }
 
     fn should_poll(&self) -> bool {
         self.callback.is_none()
     }
+
+    fn poll_canceled(&mut self, body_in_flight: bool) -> bool {
+        if self.callback.is_none() && !body_in_flight {
+            // the response was fully received
+            self.cancel = None;
+        }
+        let canceled = match self.cancel {
+            Some(ref cancel) => cancel.poll_canceled(),
+            None => false,
+        };
+        if canceled {
+            self.cancel = None;
+            self.request = None;
+            self.span = None;
+            if let Some(cb) = self.callback.take() {
+                let _ = cb.send(Err((::Error::new_request_canceled(), None)));
+            }
+        }
+        canceled
+    }
+
+    fn should_drain_dropped_body(&self) -> bool {
+        !self.cancel_dropped_requests && self.response_dropped
+    }
+
+    fn on_msg_written(&mut self) {
+        if let Some(ref timings) = self.timings {
+            timings.set_request_written();
+        }
+    }
+
+    fn on_body_written(&mut self, _n: usize) {}
 }
 
 #[cfg(test)]
@@ -573,4 +988,136 @@ mod tests {
             Ok::<(), ()>(())
         }).wait().unwrap();
     }
+
+    #[test]
+    fn buffered_body_is_read_at_once() {
+        let _ = pretty_env_logger::try_init();
+        ::futures::lazy(|| {
+            let raw = b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello".to_vec();
+            let io = AsyncIo::new_buf(raw, 0);
+            let (mut tx, rx) = ::client::dispatch::channel();
+            let conn = Conn::<_, ::Chunk, ClientTransaction>::new(io);
+            let mut dispatcher = Dispatcher::new(Client::new(rx), conn);
+            assert!(dispatcher.poll().expect("nothing is ready").is_not_ready());
+
+            let res_rx = tx.try_send(::Request::new(::Body::empty())).unwrap();
+            let _ = dispatcher.poll().expect("dispatcher poll");
+            dispatcher.conn.io_mut().block_in(100);
+            let _ = dispatcher.poll().expect("dispatcher poll");
+            let res = res_rx.wait()
+                .expect("callback poll")
+                .expect("callback response");
+            let body = res.into_body();
+            // the whole body was read with the head, without a channel
+            assert!(dispatcher.body_tx.is_none());
+            assert!(!dispatcher.conn.can_read_body());
+            assert_eq!(body.concat2().wait().unwrap().as_ref(), b"hello");
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn partial_body_is_streamed() {
+        let _ = pretty_env_logger::try_init();
+        ::futures::lazy(|| {
+            let raw = b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nhello".to_vec();
+            let io = AsyncIo::new_buf(raw, 0);
+            let (mut tx, rx) = ::client::dispatch::channel();
+            let conn = Conn::<_, ::Chunk, ClientTransaction>::new(io);
+            let mut dispatcher = Dispatcher::new(Client::new(rx), conn);
+            assert!(dispatcher.poll().expect("nothing is ready").is_not_ready());
+
+            let res_rx = tx.try_send(::Request::new(::Body::empty())).unwrap();
+            let _ = dispatcher.poll().expect("dispatcher poll");
+            dispatcher.conn.io_mut().block_in(100);
+            let _ = dispatcher.poll().expect("dispatcher poll");
+            let res = res_rx.wait()
+                .expect("callback poll")
+                .expect("callback response");
+            assert!(dispatcher.body_tx.is_some());
+            assert!(!res.body().is_end_stream());
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn server_calls_service_in_request_span() {
+        use std::fmt;
+        use std::sync::{Arc, Mutex};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tracing::{self, Event, Metadata, Subscriber};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use proto::ServerTransaction;
+
+        // Names the spans, with the values of their fields, and keeps the
+        // ones entered.
+        #[derive(Default)]
+        struct Spans {
+            next_id: AtomicUsize,
+            names: Mutex<Vec<String>>,
+            entered: Arc<Mutex<Vec<String>>>,
+        }
+
+        struct Fields(String);
+
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &fmt::Debug) {
+                self.0.push_str(&format!(" {}={:?}", field.name(), value));
+            }
+        }
+
+        impl Subscriber for Spans {
+            fn enabled(&self, _: &Metadata) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes) -> Id {
+                let mut fields = Fields(span.metadata().name().to_owned());
+                span.record(&mut fields);
+                self.names.lock().unwrap().push(fields.0);
+                Id::from_u64(self.next_id.fetch_add(1, Ordering::SeqCst) as u64 + 1)
+            }
+
+            fn record(&self, _: &Id, _: &Record) {}
+
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+            fn event(&self, _: &Event) {}
+
+            fn enter(&self, span: &Id) {
+                let name = self.names.lock().unwrap()[span.into_u64() as usize - 1].clone();
+                self.entered.lock().unwrap().push(name);
+            }
+
+            fn exit(&self, _: &Id) {
+                self.entered.lock().unwrap().pop();
+            }
+        }
+
+        let _ = pretty_env_logger::try_init();
+        let spans = Spans::default();
+        let entered = spans.entered.clone();
+        let in_service = Arc::new(Mutex::new(Vec::new()));
+        let in_service2 = in_service.clone();
+        tracing::subscriber::with_default(spans, || {
+            ::futures::lazy(move || {
+                let io = AsyncIo::new_buf(b"GET /a HTTP/1.1\r\nhost: b\r\n\r\n".to_vec(), 1024);
+                let conn = Conn::<_, ::Chunk, ServerTransaction>::new(io);
+                let service = ::service::service_fn_ok(move |_req| {
+                    *in_service2.lock().unwrap() = entered.lock().unwrap().clone();
+                    ::Response::new(::Body::empty())
+                });
+                let mut dispatcher = Dispatcher::new(Server::new(service), conn);
+                // the mock reads back the response, as a bad request
+                let _ = dispatcher.poll();
+                Ok::<(), ()>(())
+            }).wait().unwrap();
+        });
+        assert_eq!(*in_service.lock().unwrap(), [
+            "connection role=\"server\" protocol=\"http1\"",
+            "request method=GET uri=/a",
+        ]);
+    }
 }
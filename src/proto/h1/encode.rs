@@ -1,10 +1,13 @@
 use std::fmt;
+use std::io::Cursor;
 
-use bytes::{Buf, IntoBuf};
+use bytes::{Buf, BufMut, Bytes, BytesMut, IntoBuf};
 use bytes::buf::{Chain, Take};
+use http::HeaderMap;
 use iovec::IoVec;
 
 use common::StaticBuf;
+use headers;
 use super::io::WriteBuf;
 
 /// Encoders to handle different Transfer-Encodings.
@@ -12,6 +15,12 @@ use super::io::WriteBuf;
 pub struct Encoder {
     kind: Kind,
     is_last: bool,
+    /// Chunk extensions to send with the next chunk.
+    chunk_ext: Option<Bytes>,
+    /// Chunk extensions to send with the last chunk.
+    last_chunk_ext: Option<Bytes>,
+    /// Trailers to send after the last chunk.
+    trailers: Option<HeaderMap>,
 }
 
 #[derive(Debug)]
@@ -35,6 +44,10 @@ enum Kind {
     /// This is mostly only used with HTTP/1.0 with a length. This kind requires
     /// the connection to be closed when the body is finished.
     CloseDelimited,
+    /// An Encoder that writes the body as given, for `Framing::Raw`.
+    ///
+    /// The body is framed by the user, so it ends whenever they end it.
+    Raw,
 }
 
 #[derive(Debug)]
@@ -42,7 +55,9 @@ enum BufKind<B> {
     Exact(B),
     Limited(Take<B>),
     Chunked(Chain<Chain<ChunkSize, B>, StaticBuf>),
+    ChunkedExt(Chain<Chain<Cursor<Bytes>, B>, Cursor<Bytes>>),
     ChunkedEnd(StaticBuf),
+    ChunkedEndExt(Cursor<Bytes>),
 }
 
 impl Encoder {
@@ -50,6 +65,9 @@ impl Encoder {
         Encoder {
             kind: kind,
             is_last: false,
+            chunk_ext: None,
+            last_chunk_ext: None,
+            trailers: None,
         }
     }
     pub fn chunked() -> Encoder {
@@ -64,6 +82,35 @@ impl Encoder {
         Encoder::new(Kind::CloseDelimited)
     }
 
+    pub fn raw() -> Encoder {
+        Encoder::new(Kind::Raw)
+    }
+
+    pub fn is_chunked(&self) -> bool {
+        self.kind == Kind::Chunked
+    }
+
+    /// The number of bytes left before reaching a `Content-Length`.
+    pub fn remaining_length(&self) -> Option<u64> {
+        match self.kind {
+            Kind::Length(remaining) => Some(remaining),
+            _ => None,
+        }
+    }
+
+    /// Counts `n` bytes of the body as written, outside of the encoder.
+    ///
+    /// This is an error if that's more than the `Content-Length` left.
+    #[cfg(all(feature = "sendfile", target_os = "linux"))]
+    pub(super) fn written(&mut self, n: u64) -> ::std::io::Result<()> {
+        if let Kind::Length(ref mut remaining) = self.kind {
+            *remaining = remaining.checked_sub(n).ok_or_else(|| {
+                ::std::io::Error::new(::std::io::ErrorKind::InvalidData, "body longer than content-length")
+            })?;
+        }
+        Ok(())
+    }
+
     pub fn is_eof(&self) -> bool {
         match self.kind {
             Kind::Length(0) => true,
@@ -80,11 +127,44 @@ impl Encoder {
         self.is_last
     }
 
+    /// Sets the chunk extensions of the next chunk, if chunked.
+    pub fn set_chunk_extensions(&mut self, ext: Bytes) {
+        if self.kind == Kind::Chunked {
+            self.chunk_ext = Some(ext);
+        }
+    }
+
+    /// Sets the chunk extensions of the last chunk, if chunked.
+    pub fn set_last_chunk_extensions(&mut self, ext: Bytes) {
+        if self.kind == Kind::Chunked {
+            self.last_chunk_ext = Some(ext);
+        }
+    }
+
+    /// Sets the trailers to send after the last chunk, if chunked.
+    ///
+    /// Fields not allowed to be sent as trailers are dropped.
+    pub fn set_trailers(&mut self, mut trailers: HeaderMap) {
+        if self.kind == Kind::Chunked {
+            for name in headers::FORBIDDEN_TRAILERS.iter() {
+                if trailers.remove(name).is_some() {
+                    debug!("dropping disallowed trailer field: {}", name);
+                }
+            }
+            self.trailers = Some(trailers);
+        }
+    }
+
     pub fn end<B>(&self) -> Result<Option<EncodedBuf<B>>, NotEof> {
         match self.kind {
-            Kind::Length(0) => Ok(None),
+            Kind::Length(0) |
+            Kind::Raw => Ok(None),
             Kind::Chunked => Ok(Some(EncodedBuf {
-                kind: BufKind::ChunkedEnd(StaticBuf(b"0\r\n\r\n")),
+                kind: if self.last_chunk_ext.is_none() && self.trailers.is_none() {
+                    BufKind::ChunkedEnd(StaticBuf(b"0\r\n\r\n"))
+                } else {
+                    BufKind::ChunkedEndExt(self.last_chunk(b""))
+                },
             })),
             _ => Err(NotEof),
         }
@@ -101,10 +181,20 @@ impl Encoder {
         let kind = match self.kind {
             Kind::Chunked => {
                 trace!("encoding chunked {}B", len);
-                let buf = ChunkSize::new(len)
-                    .chain(msg)
-                    .chain(StaticBuf(b"\r\n"));
-                BufKind::Chunked(buf)
+                match self.chunk_ext.take() {
+                    Some(ext) => {
+                        let buf = chunk_size_ext(len, &ext)
+                            .chain(msg)
+                            .chain(Bytes::from_static(b"\r\n").into_buf());
+                        BufKind::ChunkedExt(buf)
+                    },
+                    None => {
+                        let buf = ChunkSize::new(len)
+                            .chain(msg)
+                            .chain(StaticBuf(b"\r\n"));
+                        BufKind::Chunked(buf)
+                    },
+                }
             },
             Kind::Length(ref mut remaining) => {
                 trace!("sized write, len = {}", len);
@@ -120,7 +210,11 @@ impl Encoder {
             Kind::CloseDelimited => {
                 trace!("close delimited write {}B", len);
                 BufKind::Exact(msg)
-            }
+            },
+            Kind::Raw => {
+                trace!("raw write {}B", len);
+                BufKind::Exact(msg)
+            },
         };
         EncodedBuf {
             kind,
@@ -138,10 +232,7 @@ impl Encoder {
         match self.kind {
             Kind::Chunked => {
                 trace!("encoding chunked {}B", len);
-                let buf = ChunkSize::new(len)
-                    .chain(msg)
-                    .chain(StaticBuf(b"\r\n0\r\n\r\n"));
-                dst.buffer(buf);
+                self.buffer_last_chunked(len, msg, dst);
                 !self.is_last
             },
             Kind::Length(remaining) => {
@@ -167,7 +258,12 @@ impl Encoder {
                 trace!("close delimited write {}B", len);
                 dst.buffer(msg);
                 false
-            }
+            },
+            Kind::Raw => {
+                trace!("raw write {}B", len);
+                dst.buffer(msg);
+                !self.is_last
+            },
         }
     }
 
@@ -191,18 +287,71 @@ impl Encoder {
             Kind::Chunked => {
                 let len = msg.remaining();
                 trace!("encoding chunked {}B", len);
-                let buf = ChunkSize::new(len)
-                    .chain(msg)
-                    .chain(StaticBuf(b"\r\n0\r\n\r\n"));
-                dst.buffer(buf);
+                self.buffer_last_chunked(len, msg, dst);
             },
             _ => {
                 dst.buffer(msg);
             },
         }
     }
+
+    // Buffers `msg` as a chunk, followed by the last chunk.
+    fn buffer_last_chunked<B: Buf>(&self, len: usize, msg: B, dst: &mut WriteBuf<EncodedBuf<B>>) {
+        if self.chunk_ext.is_none() && self.last_chunk_ext.is_none() && self.trailers.is_none() {
+            let buf = ChunkSize::new(len)
+                .chain(msg)
+                .chain(StaticBuf(b"\r\n0\r\n\r\n"));
+            dst.buffer(buf);
+            return;
+        }
+
+        let head = match self.chunk_ext {
+            Some(ref ext) => chunk_size_ext(len, ext),
+            None => Bytes::from(ChunkSize::new(len).bytes()).into_buf(),
+        };
+        dst.buffer(EncodedBuf {
+            kind: BufKind::ChunkedExt(head.chain(msg).chain(self.last_chunk(b"\r\n"))),
+        });
+    }
+
+    // The last chunk, with its extensions and trailers if any, after `prefix`.
+    fn last_chunk(&self, prefix: &[u8]) -> Cursor<Bytes> {
+        let ext_len = self.last_chunk_ext.as_ref().map(|ext| ext.len() + 1).unwrap_or(0);
+        let trailers_len = self.trailers.iter()
+            .flat_map(|trailers| trailers.iter())
+            .map(|(name, value)| name.as_str().len() + value.len() + 4)
+            .sum::<usize>();
+        let mut buf = BytesMut::with_capacity(prefix.len() + ext_len + trailers_len + 5);
+        buf.put(prefix);
+        buf.put(&b"0"[..]);
+        if let Some(ref ext) = self.last_chunk_ext {
+            buf.put(&b";"[..]);
+            buf.put(&ext[..]);
+        }
+        buf.put(&b"\r\n"[..]);
+        if let Some(ref trailers) = self.trailers {
+            for (name, value) in trailers {
+                buf.put(name.as_str());
+                buf.put(&b": "[..]);
+                buf.put(value.as_bytes());
+                buf.put(&b"\r\n"[..]);
+            }
+        }
+        buf.put(&b"\r\n"[..]);
+        buf.freeze().into_buf()
+    }
 }
 
+// The size line of a chunk with extensions, such as `1A;name=value\r\n`.
+fn chunk_size_ext(len: usize, ext: &[u8]) -> Cursor<Bytes> {
+    let mut line = BytesMut::with_capacity(CHUNK_SIZE_MAX_BYTES + ext.len() + 3);
+    line.put(format!("{:X};", len));
+    line.put(ext);
+    line.put(&b"\r\n"[..]);
+    line.freeze().into_buf()
+}
+
+
 impl<B> Buf for EncodedBuf<B>
 where
     B: Buf,
@@ -213,7 +362,9 @@ where
             BufKind::Exact(ref b) => b.remaining(),
             BufKind::Limited(ref b) => b.remaining(),
             BufKind::Chunked(ref b) => b.remaining(),
+            BufKind::ChunkedExt(ref b) => b.remaining(),
             BufKind::ChunkedEnd(ref b) => b.remaining(),
+            BufKind::ChunkedEndExt(ref b) => b.remaining(),
         }
     }
 
@@ -223,7 +374,9 @@ where
             BufKind::Exact(ref b) => b.bytes(),
             BufKind::Limited(ref b) => b.bytes(),
             BufKind::Chunked(ref b) => b.bytes(),
+            BufKind::ChunkedExt(ref b) => b.bytes(),
             BufKind::ChunkedEnd(ref b) => b.bytes(),
+            BufKind::ChunkedEndExt(ref b) => b.bytes(),
         }
     }
 
@@ -233,7 +386,9 @@ where
             BufKind::Exact(ref mut b) => b.advance(cnt),
             BufKind::Limited(ref mut b) => b.advance(cnt),
             BufKind::Chunked(ref mut b) => b.advance(cnt),
+            BufKind::ChunkedExt(ref mut b) => b.advance(cnt),
             BufKind::ChunkedEnd(ref mut b) => b.advance(cnt),
+            BufKind::ChunkedEndExt(ref mut b) => b.advance(cnt),
         }
     }
 
@@ -243,7 +398,9 @@ where
             BufKind::Exact(ref b) => b.bytes_vec(dst),
             BufKind::Limited(ref b) => b.bytes_vec(dst),
             BufKind::Chunked(ref b) => b.bytes_vec(dst),
+            BufKind::ChunkedExt(ref b) => b.bytes_vec(dst),
             BufKind::ChunkedEnd(ref b) => b.bytes_vec(dst),
+            BufKind::ChunkedEndExt(ref b) => b.bytes_vec(dst),
         }
     }
 }
@@ -369,6 +526,72 @@ mod tests {
         assert_eq!(dst, b"7\r\nfoo bar\r\nD\r\nbaz quux herp\r\n0\r\n\r\n".as_ref());
     }
 
+    #[cfg(all(feature = "sendfile", target_os = "linux"))]
+    #[test]
+    fn written_past_length() {
+        let mut encoder = Encoder::length(10);
+        encoder.written(4).unwrap();
+        assert_eq!(encoder.remaining_length(), Some(6));
+        encoder.written(7).unwrap_err();
+        assert_eq!(encoder.remaining_length(), Some(6));
+        encoder.written(6).unwrap();
+        assert!(encoder.is_eof());
+    }
+
+    #[test]
+    fn chunked_extensions() {
+        use bytes::Bytes;
+
+        let mut encoder = Encoder::chunked();
+        let mut dst = Vec::new();
+
+        encoder.set_chunk_extensions(Bytes::from_static(b"sig=a"));
+        dst.put(encoder.encode(b"foo".as_ref()));
+        dst.put(encoder.encode(b"bar".as_ref()));
+        encoder.set_last_chunk_extensions(Bytes::from_static(b"sig=b"));
+        dst.put(encoder.end::<Cursor<Vec<u8>>>().unwrap().unwrap());
+        assert_eq!(dst, b"3;sig=a\r\nfoo\r\n3\r\nbar\r\n0;sig=b\r\n\r\n".as_ref());
+
+        let mut encoder = Encoder::length(3);
+        encoder.set_chunk_extensions(Bytes::from_static(b"sig=a"));
+        let mut dst = Vec::new();
+        dst.put(encoder.encode(b"foo".as_ref()));
+        assert_eq!(dst, b"foo");
+    }
+
+    #[test]
+    fn chunked_trailers() {
+        use http::HeaderMap;
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("checksum", "abc".parse().unwrap());
+
+        let mut encoder = Encoder::chunked();
+        let mut dst = Vec::new();
+        dst.put(encoder.encode(b"foo".as_ref()));
+        encoder.set_trailers(trailers.clone());
+        dst.put(encoder.end::<Cursor<Vec<u8>>>().unwrap().unwrap());
+        assert_eq!(dst, b"3\r\nfoo\r\n0\r\nchecksum: abc\r\n\r\n".as_ref());
+
+        // Fields not allowed as trailers are dropped.
+        let mut disallowed = trailers.clone();
+        disallowed.insert("content-length", "3".parse().unwrap());
+        disallowed.insert("host", "example.domain".parse().unwrap());
+        let mut encoder = Encoder::chunked();
+        let mut dst = Vec::new();
+        encoder.set_trailers(disallowed);
+        dst.put(encoder.end::<Cursor<Vec<u8>>>().unwrap().unwrap());
+        assert_eq!(dst, b"0\r\nchecksum: abc\r\n\r\n".as_ref());
+
+        // Only chunked bodies can have trailers.
+        let mut encoder = Encoder::length(3);
+        encoder.set_trailers(trailers);
+        let mut dst = Vec::new();
+        dst.put(encoder.encode(b"foo".as_ref()));
+        assert!(encoder.end::<()>().unwrap().is_none());
+        assert_eq!(dst, b"foo");
+    }
+
     #[test]
     fn length() {
         let max_len = 8;
@@ -418,4 +641,17 @@ mod tests {
         assert!(!encoder.is_eof());
         encoder.end::<()>().unwrap_err();
     }
+
+    #[test]
+    fn raw() {
+        let mut encoder = Encoder::raw();
+        let mut dst = Vec::new();
+
+        dst.put(encoder.encode(b"3\r\nfoo\r\n".as_ref()));
+        assert!(!encoder.is_eof());
+        dst.put(encoder.encode(b"0\r\n\r\n".as_ref()));
+
+        assert_eq!(dst, b"3\r\nfoo\r\n0\r\n\r\n".as_ref());
+        assert!(encoder.end::<()>().unwrap().is_none());
+    }
 }
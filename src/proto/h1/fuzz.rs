@@ -0,0 +1,100 @@
+use bytes::BytesMut;
+use futures::Async;
+use http::{HeaderMap, Method, Request, Response};
+
+use super::{ClientTransaction, Decoder, Http1Transaction, ParseContext, ServerTransaction};
+use super::sansio::{into_request, into_response, ReadBuf};
+
+/// Parses the head of a request at the start of `bytes`, as a `Server` does.
+///
+/// Returns the request and the length of its head, or `None` if `bytes`
+/// ends before the head does.
+pub fn parse_request(bytes: &[u8]) -> ::Result<Option<(Request<()>, usize)>> {
+    let mut buf = BytesMut::from(bytes);
+    let parsed = ServerTransaction::parse(&mut buf, ParseContext {
+        cached_headers: &mut None,
+        req_method: &mut None,
+        config: Default::default(),
+    })?;
+    Ok(parsed.map(|msg| (into_request(msg.head), bytes.len() - buf.len())))
+}
+
+/// Parses the head of a response at the start of `bytes`, as a `Client`
+/// does, for a request with `method`.
+///
+/// Returns the response and the length of its head, or `None` if `bytes`
+/// ends before the head does.
+pub fn parse_response(bytes: &[u8], method: &Method) -> ::Result<Option<(Response<()>, usize)>> {
+    let mut buf = BytesMut::from(bytes);
+    let parsed = ClientTransaction::parse(&mut buf, ParseContext {
+        cached_headers: &mut None,
+        req_method: &mut Some(method.clone()),
+        config: Default::default(),
+    })?;
+    Ok(parsed.map(|msg| (into_response(msg.head), bytes.len() - buf.len())))
+}
+
+/// Decodes a chunked body at the start of `bytes`.
+///
+/// Returns the data of the body, with its trailers if it had any, or `None`
+/// if `bytes` ends before the body does.
+pub fn decode_chunked(bytes: &[u8]) -> ::Result<Option<(Vec<u8>, Option<HeaderMap>)>> {
+    let mut buf = BytesMut::from(bytes);
+    let mut decoder = Decoder::chunked();
+    let mut body = Vec::new();
+    loop {
+        let before = buf.len();
+        let data = match decoder.decode(&mut ReadBuf {
+            buf: &mut buf,
+            eof: false,
+        }) {
+            Ok(Async::Ready(data)) => data,
+            Ok(Async::NotReady) => return Ok(None),
+            Err(err) => return Err(::Error::new_body(err)),
+        };
+        if data.is_empty() {
+            if decoder.is_eof() {
+                return Ok(Some((body, decoder.take_trailers())));
+            }
+            if buf.len() == before {
+                return Ok(None);
+            }
+        }
+        body.extend_from_slice(&data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+
+    use super::{decode_chunked, parse_request, parse_response};
+
+    #[test]
+    fn parse_heads() {
+        let (req, len) = parse_request(b"GET /a HTTP/1.1\r\nHost: x\r\n\r\nbody")
+            .unwrap()
+            .unwrap();
+        assert_eq!(req.uri(), "/a");
+        assert_eq!(len, 28);
+        assert!(parse_request(b"GET /a HTTP/1.1\r\nHost").unwrap().is_none());
+        assert!(parse_request(b"\0\r\n\r\n").is_err());
+
+        let (res, len) = parse_response(b"HTTP/1.1 404 Not Found\r\n\r\n", &Method::GET)
+            .unwrap()
+            .unwrap();
+        assert_eq!(res.status(), 404);
+        assert_eq!(len, 26);
+    }
+
+    #[test]
+    fn decode_chunks() {
+        let (body, trailers) = decode_chunked(b"3\r\nfoo\r\n3\r\nbar\r\n0\r\nx: y\r\n\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(body, b"foobar");
+        assert_eq!(trailers.unwrap()["x"], "y");
+        assert!(decode_chunked(b"3\r\nfo").unwrap().is_none());
+        assert!(decode_chunked(b"zz\r\n").is_err());
+    }
+}
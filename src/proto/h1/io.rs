@@ -8,7 +8,9 @@ use futures::{Async, Poll};
 use iovec::IoVec;
 use tokio_io::{AsyncRead, AsyncWrite};
 
+use instrument::Instrument;
 use super::{Http1Transaction, ParseContext, ParsedMessage};
+use super::buf_pool::{BufPool, Pooled};
 
 /// The initial buffer size allocated before trying to read from IO.
 pub(crate) const INIT_BUFFER_SIZE: usize = 8192;
@@ -28,12 +30,54 @@ pub(crate) const DEFAULT_MAX_BUFFER_SIZE: usize = 8192 + 4096 * 100;
 /// forces a flush if the queue gets this big.
 const MAX_BUF_LIST_BUFFERS: usize = 16;
 
+/// The average size under which queued `Buf`s are copied into a single
+/// buffer before a flush, when the strategy was detected automatically.
+///
+/// Small buffers would otherwise use up the limited number of `IoVec`s a
+/// vectored write can take, splitting one flush into several syscalls.
+const MIN_VECTORED_BUF_SIZE: usize = 256;
+
+/// The initial and maximum sizes of a connection's read and write buffers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct BufSizes {
+    pub(crate) read_init: usize,
+    pub(crate) read_max: usize,
+    pub(crate) write_init: usize,
+    pub(crate) write_max: usize,
+}
+
+impl Default for BufSizes {
+    fn default() -> BufSizes {
+        BufSizes {
+            read_init: INIT_BUFFER_SIZE,
+            read_max: DEFAULT_MAX_BUFFER_SIZE,
+            write_init: INIT_BUFFER_SIZE,
+            write_max: DEFAULT_MAX_BUFFER_SIZE,
+        }
+    }
+}
+
+impl BufSizes {
+    pub(crate) fn check(&self) -> ::Result<()> {
+        if self.write_init > self.write_max {
+            return Err(::Error::new_config(format!(
+                "http1_init_write_buf_size ({}) is larger than http1_max_write_buf_size ({})",
+                self.write_init,
+                self.write_max,
+            )));
+        }
+        Ok(())
+    }
+}
+
 pub struct Buffered<T, B> {
     flush_pipeline: bool,
+    instrument: Option<Instrument>,
     io: T,
     max_buf_size: usize,
     read_blocked: bool,
-    read_buf: BytesMut,
+    read_buf_init: usize,
+    read_buf: Pooled<BytesMut>,
     write_buf: WriteBuf<B>,
 }
 
@@ -57,14 +101,22 @@ where
     pub fn new(io: T) -> Buffered<T, B> {
         Buffered {
             flush_pipeline: false,
+            instrument: None,
             io: io,
             max_buf_size: DEFAULT_MAX_BUFFER_SIZE,
-            read_buf: BytesMut::with_capacity(0),
+            read_buf_init: INIT_BUFFER_SIZE,
+            read_buf: Pooled::new(BytesMut::with_capacity(0)),
             write_buf: WriteBuf::new(),
             read_blocked: false,
         }
     }
 
+    /// Seed the read buffer with bytes already read from the `io`.
+    pub fn set_read_buf(&mut self, buf: BytesMut) {
+        debug_assert!(self.read_buf.is_empty());
+        *self.read_buf = buf;
+    }
+
     pub fn set_flush_pipeline(&mut self, enabled: bool) {
         debug_assert!(!self.write_buf.has_remaining());
         self.flush_pipeline = enabled;
@@ -73,13 +125,38 @@ where
         }
     }
 
-    pub fn set_max_buf_size(&mut self, max: usize) {
-        assert!(
-            max >= MINIMUM_MAX_BUFFER_SIZE,
-            "The max_buf_size cannot be smaller than the initial buffer size."
-        );
-        self.max_buf_size = max;
-        self.write_buf.max_buf_size = max;
+    pub(crate) fn set_buf_sizes(&mut self, sizes: BufSizes) {
+        debug_assert!(sizes.read_init > 0 && sizes.read_init <= MINIMUM_MAX_BUFFER_SIZE);
+        debug_assert!(sizes.read_max >= MINIMUM_MAX_BUFFER_SIZE);
+        debug_assert!(!self.write_buf.has_remaining());
+        self.read_buf_init = sizes.read_init;
+        self.max_buf_size = sizes.read_max;
+        self.write_buf.max_buf_size = sizes.write_max;
+        self.write_buf.headers = Cursor::new(Pooled::new(Vec::with_capacity(sizes.write_init)));
+    }
+
+    /// The maximum size of the read buffer.
+    pub(crate) fn max_buf_size(&self) -> usize {
+        self.max_buf_size
+    }
+
+    /// Report the bytes read and written to `instrument`.
+    pub(crate) fn set_instrument(&mut self, instrument: Instrument) {
+        self.instrument = Some(instrument);
+    }
+
+    pub(crate) fn instrument(&self) -> Option<&Instrument> {
+        self.instrument.as_ref()
+    }
+
+    /// Take the buffers from `pool` if it has any, and give them back to it
+    /// once this connection is dropped.
+    pub(crate) fn set_buf_pool(&mut self, pool: BufPool) {
+        debug_assert!(!self.write_buf.has_remaining());
+        // a seeded read buffer must be kept
+        let replace_read = self.read_buf.capacity() == 0;
+        self.read_buf.set_pool(pool.clone(), replace_read);
+        self.write_buf.headers.bytes.set_pool(pool, true);
     }
 
     pub fn set_write_strategy_flatten(&mut self) {
@@ -89,6 +166,13 @@ where
         self.write_buf.set_strategy(Strategy::Flatten);
     }
 
+    pub fn set_write_strategy_queue(&mut self) {
+        // this should always be called only at construction time,
+        // so this assert is here to catch myself
+        debug_assert!(self.write_buf.queue.bufs.is_empty());
+        self.write_buf.set_strategy(Strategy::Queue);
+    }
+
     pub fn read_buf(&self) -> &[u8] {
         self.read_buf.as_ref()
     }
@@ -102,6 +186,11 @@ where
         &mut self.write_buf
     }
 
+    /// The bytes queued to be written, but not flushed yet.
+    pub(super) fn write_buffered(&self) -> usize {
+        self.write_buf.remaining()
+    }
+
     pub fn buffer<BB: Buf + Into<B>>(&mut self, buf: BB) {
         self.write_buf.buffer(buf)
     }
@@ -129,7 +218,7 @@ where
         S: Http1Transaction,
     {
         loop {
-            match try!(S::parse(&mut self.read_buf, ParseContext { cached_headers: ctx.cached_headers, req_method: ctx.req_method, })) {
+            match try!(S::parse(&mut self.read_buf, ParseContext { cached_headers: ctx.cached_headers, req_method: ctx.req_method, config: ctx.config, })) {
                 Some(msg) => {
                     debug!("parsed {} headers", msg.head.headers.len());
                     return Ok(Async::Ready(msg))
@@ -154,13 +243,17 @@ where
     pub fn read_from_io(&mut self) -> Poll<usize, io::Error> {
         use bytes::BufMut;
         self.read_blocked = false;
-        if self.read_buf.remaining_mut() < INIT_BUFFER_SIZE {
-            self.read_buf.reserve(INIT_BUFFER_SIZE);
+        if self.read_buf.remaining_mut() < self.read_buf_init {
+            self.read_buf.reserve(self.read_buf_init);
         }
-        self.io.read_buf(&mut self.read_buf).map(|ok| {
+        self.io.read_buf(&mut *self.read_buf).map(|ok| {
             match ok {
                 Async::Ready(n) => {
                     debug!("read {} bytes", n);
+                    if let Some(ref instrument) = self.instrument {
+                        let len = self.read_buf.len();
+                        instrument.data_read(&self.read_buf[len - n..]);
+                    }
                     Async::Ready(n)
                 },
                 Async::NotReady => {
@@ -172,13 +265,18 @@ where
     }
 
     pub fn into_inner(self) -> (T, Bytes) {
-        (self.io, self.read_buf.freeze())
+        (self.io, self.read_buf.into_inner().freeze())
     }
 
     pub fn io_mut(&mut self) -> &mut T {
         &mut self.io
     }
 
+    #[cfg(all(feature = "sendfile", target_os = "linux"))]
+    pub(crate) fn io_ref(&self) -> &T {
+        &self.io
+    }
+
     pub fn is_read_blocked(&self) -> bool {
         self.read_blocked
     }
@@ -191,10 +289,27 @@ where
         } else {
             match self.write_buf.strategy {
                 Strategy::Flatten => return self.flush_flattened(),
+                Strategy::Adaptive => self.write_buf.flatten_small_bufs(),
                 _ => (),
             }
+            let tapped = self.instrument.as_ref().map(Instrument::is_tapped).unwrap_or(false);
             loop {
-                let n = try_ready!(self.io.write_buf(&mut self.write_buf.auto()));
+                let n = if tapped {
+                    // the tap needs the bytes, so write a single buffer
+                    // at a time
+                    let n = try_nb!(self.io.write(self.write_buf.bytes()));
+                    if let Some(ref instrument) = self.instrument {
+                        instrument.data_written(&self.write_buf.bytes()[..n]);
+                    }
+                    self.write_buf.advance(n);
+                    n
+                } else {
+                    let n = try_ready!(self.io.write_buf(&mut self.write_buf.auto()));
+                    if let Some(ref instrument) = self.instrument {
+                        instrument.bytes_written(n);
+                    }
+                    n
+                };
                 debug!("flushed {} bytes", n);
                 if self.write_buf.remaining() == 0 {
                     break;
@@ -216,6 +331,9 @@ where
         loop {
             let n = try_nb!(self.io.write(self.write_buf.headers.bytes()));
             debug!("flushed {} bytes", n);
+            if let Some(ref instrument) = self.instrument {
+                instrument.data_written(&self.write_buf.headers.bytes()[..n]);
+            }
             self.write_buf.headers.advance(n);
             if self.write_buf.headers.remaining() == 0 {
                 self.write_buf.headers.reset();
@@ -266,7 +384,7 @@ impl<T: AsRef<[u8]>> Cursor<T> {
     }
 }
 
-impl Cursor<Vec<u8>> {
+impl Cursor<Pooled<Vec<u8>>> {
     fn reset(&mut self) {
         self.pos = 0;
         unsafe {
@@ -305,7 +423,7 @@ impl<T: AsRef<[u8]>> Buf for Cursor<T> {
 // an internal buffer to collect writes before flushes
 pub(super) struct WriteBuf<B> {
     /// Re-usable buffer that holds message headers
-    headers: Cursor<Vec<u8>>,
+    headers: Cursor<Pooled<Vec<u8>>>,
     max_buf_size: usize,
     /// Deque of user buffers if strategy is Queue
     queue: BufDeque<B>,
@@ -315,7 +433,7 @@ pub(super) struct WriteBuf<B> {
 impl<B> WriteBuf<B> {
     fn new() -> WriteBuf<B> {
         WriteBuf {
-            headers: Cursor::new(Vec::with_capacity(INIT_BUFFER_SIZE)),
+            headers: Cursor::new(Pooled::new(Vec::with_capacity(INIT_BUFFER_SIZE))),
             max_buf_size: DEFAULT_MAX_BUFFER_SIZE,
             queue: BufDeque::new(),
             strategy: Strategy::Auto,
@@ -356,7 +474,7 @@ where
                     buf.advance(adv);
                 }
             },
-            Strategy::Auto | Strategy::Queue => {
+            Strategy::Auto | Strategy::Adaptive | Strategy::Queue => {
                 self.queue.bufs.push_back(buf.into());
             },
         }
@@ -367,14 +485,28 @@ where
             Strategy::Flatten => {
                 self.remaining() < self.max_buf_size
             },
-            Strategy::Auto | Strategy::Queue => {
+            Strategy::Auto | Strategy::Adaptive | Strategy::Queue => {
                 self.queue.bufs.len() < MAX_BUF_LIST_BUFFERS
                     && self.remaining() < self.max_buf_size
             },
         }
     }
 
-    fn headers_mut(&mut self) -> &mut Cursor<Vec<u8>> {
+    /// Copies the queued buffers after the headers if they are small on
+    /// average, so they can be written from one contiguous buffer.
+    fn flatten_small_bufs(&mut self) {
+        let bufs = self.queue.bufs.len();
+        if bufs < 2 {
+            return;
+        }
+        let remaining = self.queue.remaining();
+        if remaining / bufs < MIN_VECTORED_BUF_SIZE {
+            trace!("flattening {} small buffers ({} bytes)", bufs, remaining);
+            self.headers.bytes.put(&mut self.queue);
+        }
+    }
+
+    fn headers_mut(&mut self) -> &mut Cursor<Pooled<Vec<u8>>> {
         debug_assert!(!self.queue.has_remaining());
         &mut self.headers
     }
@@ -472,7 +604,7 @@ impl<'a, B: Buf + 'a> Drop for WriteBufAuto<'a, B> {
     fn drop(&mut self) {
         if let Strategy::Auto = self.inner.strategy {
             if self.bytes_vec_called.get() {
-                self.inner.strategy = Strategy::Queue;
+                self.inner.strategy = Strategy::Adaptive;
             } else if self.bytes_called.get() {
                 trace!("detected no usage of vectored write, flattening");
                 self.inner.strategy = Strategy::Flatten;
@@ -485,7 +617,10 @@ impl<'a, B: Buf + 'a> Drop for WriteBufAuto<'a, B> {
 
 #[derive(Debug)]
 enum Strategy {
+    /// Detect whether the transport uses vectored writes on the next flush.
     Auto,
+    /// The transport uses vectored writes, but small buffers are flattened.
+    Adaptive,
     Flatten,
     Queue,
 }
@@ -594,11 +729,25 @@ mod tests {
         let ctx = ParseContext {
             cached_headers: &mut None,
             req_method: &mut None,
+            config: Default::default(),
         };
         assert!(buffered.parse::<::proto::ClientTransaction>(ctx).unwrap().is_not_ready());
         assert!(buffered.io.blocked());
     }
 
+    #[test]
+    fn read_buf_init_size() {
+        let mock = AsyncIo::new_buf(vec![b'a'; 100], 100);
+        let mut buffered = Buffered::<_, Cursor<Vec<u8>>>::new(mock);
+        buffered.set_buf_sizes(BufSizes {
+            read_init: 64,
+            .. BufSizes::default()
+        });
+
+        buffered.read_from_io().unwrap();
+        assert!(buffered.read_buf.capacity() < INIT_BUFFER_SIZE);
+    }
+
     #[test]
     #[should_panic]
     fn write_buf_requires_non_empty_bufs() {
@@ -629,6 +778,26 @@ mod tests {
         assert_eq!(buffered.write_buf.queue.bufs.len(), 0);
     }
 
+    #[test]
+    fn write_buf_queue_chained_bufs() {
+        use bytes::buf::Chain;
+
+        let mock = AsyncIo::new_buf(vec![], 1024);
+        let mut buffered = Buffered::<_, Chain<Cursor<Vec<u8>>, Cursor<Vec<u8>>>>::new(mock);
+
+        buffered.headers_buf().extend(b"hello ");
+        buffered.buffer(Cursor::new(b"world, ".to_vec()).chain(Cursor::new(b"it's hyper!".to_vec())));
+        buffered.flush().unwrap();
+
+        assert_eq!(buffered.io, b"hello world, it's hyper!");
+        assert_eq!(buffered.io.num_writes(), 1);
+        // the segments weren't copied into the headers buffer
+        match buffered.write_buf.strategy {
+            Strategy::Adaptive => (),
+            ref other => panic!("expected Adaptive strategy, was {:?}", other),
+        }
+    }
+
     #[test]
     fn write_buf_flatten() {
         extern crate pretty_env_logger;
@@ -674,6 +843,39 @@ mod tests {
         assert_eq!(buffered.write_buf.queue.bufs.len(), 0);
     }
 
+    #[test]
+    fn write_buf_adaptive_flattens_small_bufs() {
+        extern crate pretty_env_logger;
+        let _ = pretty_env_logger::try_init();
+
+        let mut mock = AsyncIo::new_buf(vec![], 4096);
+        mock.max_read_vecs(2);
+        let mut buffered = Buffered::<_, Cursor<Vec<u8>>>::new(mock);
+        buffered.write_buf.set_strategy(Strategy::Adaptive);
+
+        // small buffers are copied into one, instead of needing 2 writes
+        // of 2 vecs each
+        buffered.headers_buf().extend(b"hello ");
+        buffered.buffer(Cursor::new(b"world, ".to_vec()));
+        buffered.buffer(Cursor::new(b"it's ".to_vec()));
+        buffered.buffer(Cursor::new(b"hyper!".to_vec()));
+        buffered.flush().unwrap();
+
+        assert_eq!(buffered.io, b"hello world, it's hyper!");
+        assert_eq!(buffered.io.num_writes(), 1);
+
+        // large buffers are kept, and written with vectored writes
+        let big = vec![b'a'; MIN_VECTORED_BUF_SIZE];
+        buffered.headers_buf().extend(b"hello ");
+        buffered.buffer(Cursor::new(big.clone()));
+        buffered.buffer(Cursor::new(big.clone()));
+        buffered.buffer(Cursor::new(big.clone()));
+        buffered.flush().unwrap();
+
+        assert_eq!(buffered.io.num_writes(), 3);
+        assert_eq!(buffered.write_buf.queue.bufs.len(), 0);
+    }
+
     #[test]
     fn write_buf_queue_disable_auto() {
         extern crate pretty_env_logger;
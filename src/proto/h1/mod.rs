@@ -1,22 +1,31 @@
 use bytes::BytesMut;
-use http::{HeaderMap, Method};
+use http::{HeaderMap, Method, StatusCode};
 
 use proto::{MessageHead, BodyLength};
+use proxy::FramingPolicy;
 
 pub(crate) use self::conn::Conn;
 pub(crate) use self::dispatch::Dispatcher;
 pub use self::decode::Decoder;
 pub use self::encode::{EncodedBuf, Encoder};
 pub use self::io::Cursor; //TODO: move out of h1::io
+pub(crate) use self::buf_pool::{BufPool, DEFAULT_MAX_POOLED_BUFS};
+pub(crate) use self::io::BufSizes;
 pub use self::io::MINIMUM_MAX_BUFFER_SIZE;
 
+mod buf_pool;
 mod conn;
 mod date;
 mod decode;
 pub(crate) mod dispatch;
 mod encode;
+pub(crate) mod fuzz;
 mod io;
 mod role;
+mod scan;
+#[cfg(all(feature = "sendfile", target_os = "linux"))]
+pub(crate) mod sendfile;
+pub(crate) mod sansio;
 
 
 pub(crate) type ServerTransaction = self::role::Server<self::role::YesUpgrades>;
@@ -33,6 +42,10 @@ pub(crate) trait Http1Transaction {
     fn encode(enc: Encode<Self::Outgoing>, dst: &mut Vec<u8>) -> ::Result<Encoder>;
 
     fn on_error(err: &::Error) -> Option<MessageHead<Self::Outgoing>>;
+    /// The status of an error response made by `on_error`.
+    fn error_status(_head: &MessageHead<Self::Outgoing>) -> Option<StatusCode> {
+        None
+    }
 
     fn should_error_on_parse_eof() -> bool;
     fn should_read_first() -> bool;
@@ -53,6 +66,68 @@ pub(crate) struct ParsedMessage<T> {
 pub(crate) struct ParseContext<'a> {
     cached_headers: &'a mut Option<HeaderMap>,
     req_method: &'a mut Option<Method>,
+    config: ParseConfig,
+}
+
+/// Options changing how incoming messages are parsed.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ParseConfig {
+    /// Whether a server accepts requests with an absolute-form target,
+    /// such as those sent to forward proxies.
+    absolute_form: bool,
+    /// Whether a server passes requests with an invalid target to the
+    /// service, as a `RawTarget` extension.
+    lenient_target: bool,
+    /// Whether a server percent-encodes the bytes of a request-target that
+    /// strict parsing rejects, such as spaces.
+    lenient_uri: bool,
+    /// Whether a server records the request-line as received, as a
+    /// `RawRequestLine` extension.
+    record_request_line: bool,
+    /// Whether the original casing of header names is recorded, as a
+    /// `HeaderCaseMap` extension.
+    record_header_case: bool,
+    /// Whether the order of header fields is recorded, as a `HeaderOrder`
+    /// extension.
+    record_header_order: bool,
+    /// Whether a client accepts responses with header values using
+    /// obsolete line folding, replacing it with spaces.
+    obs_fold: bool,
+    /// Whether a client accepts responses with spaces between a header
+    /// name and the colon.
+    spaces_after_header_name: bool,
+    /// Whether a client accepts responses without a reason-phrase.
+    missing_reason: bool,
+    /// Whether lines may end with a bare LF instead of CRLF.
+    lf_line_endings: bool,
+    /// How messages with ambiguous framing headers are handled. Servers
+    /// always set this, to reject them by default.
+    framing: FramingPolicy,
+    /// The maximum length of a request-line a server accepts.
+    max_request_line: Option<usize>,
+    /// Whether a server rejects HTTP/1.1 requests without exactly one
+    /// `Host` header.
+    require_host: bool,
+}
+
+impl Default for ParseConfig {
+    fn default() -> ParseConfig {
+        ParseConfig {
+            absolute_form: true,
+            lenient_target: false,
+            lenient_uri: false,
+            record_request_line: false,
+            record_header_case: false,
+            record_header_order: false,
+            obs_fold: false,
+            spaces_after_header_name: false,
+            missing_reason: true,
+            lf_line_endings: true,
+            framing: FramingPolicy::Normalize,
+            max_request_line: None,
+            require_host: false,
+        }
+    }
 }
 
 /// Passed to Http1Transaction::encode
@@ -62,6 +137,8 @@ pub(crate) struct Encode<'a, T: 'a> {
     keep_alive: bool,
     req_method: &'a mut Option<Method>,
     title_case_headers: bool,
+    preserve_header_case: bool,
+    max_uri_len: Option<usize>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fmt::{self, Write};
 use std::mem;
 
@@ -7,9 +8,14 @@ use http::{HeaderMap, Method, StatusCode, Version};
 use httparse;
 
 use error::Parse;
+use ext::{Framing, FramingNormalization, HeaderCaseMap, HeaderOrder, RawRequestLine, RawTarget};
 use headers;
 use proto::{BodyLength, MessageHead, RequestLine, RequestHead};
 use proto::h1::{Decode, Decoder, Encode, Encoder, Http1Transaction, ParseResult, ParseContext, ParsedMessage, date};
+use proto::h1::scan::{self, has_bare_lf};
+use proxy::FramingPolicy;
+#[cfg(test)]
+use proto::h1::ParseConfig;
 
 const MAX_HEADERS: usize = 100;
 const AVERAGE_HEADER_SIZE: usize = 30; // totally scientific
@@ -34,12 +40,40 @@ where
         if buf.len() == 0 {
             return Ok(None);
         }
+        if let Some(max) = ctx.config.max_request_line {
+            // https://tools.ietf.org/html/rfc7230#section-3.1.1
+            // A server that receives a request-target longer than any URI it
+            // wishes to parse MUST respond with a 414 status code. This is
+            // checked before the line is complete, to stop reading early.
+            // Only the bytes that could still fit the limit are scanned.
+            let scanned = ::std::cmp::min(buf.len(), max.saturating_add(2));
+            let line_len = match scan::find(b'\n', &buf[..scanned]) {
+                Some(i) if i > 0 && buf[i - 1] == b'\r' => i - 1,
+                Some(i) => i,
+                None => scanned,
+            };
+            if line_len > max {
+                debug!("request-line is longer than {} bytes", max);
+                return Err(Parse::UriTooLong);
+            }
+        }
+        let mut raw_line = None;
+        if ctx.config.lenient_uri {
+            if ctx.config.record_request_line {
+                // Copied before the target is rewritten.
+                raw_line = request_line_range(buf).map(|(start, end)| Bytes::from(&buf[start..end]));
+            }
+            if !encode_lenient_target(buf) {
+                // The whole request-line is needed to know where the target ends.
+                return Ok(None);
+            }
+        }
         // Unsafe: both headers_indices and headers are using unitialized memory,
         // but we *never* read any of it until after httparse has assigned
         // values into it. By not zeroing out the stack memory, this saves
         // a good ~5% on pipeline benchmarks.
         let mut headers_indices: [HeaderIndices; MAX_HEADERS] = unsafe { mem::uninitialized() };
-        let (len, subject, version, headers_len) = {
+        let (len, method, target, version, headers_len) = {
             let mut headers: [httparse::Header; MAX_HEADERS] = unsafe { mem::uninitialized() };
             trace!("Request.parse([Header; {}], [u8; {}])", headers.len(), buf.len());
             let mut req = httparse::Request::new(&mut headers);
@@ -47,9 +81,22 @@ where
             match req.parse(bytes)? {
                 httparse::Status::Complete(len) => {
                     trace!("Request.parse Complete({})", len);
+                    if !ctx.config.lf_line_endings && has_bare_lf(&bytes[..len]) {
+                        debug!("request head has bare LF line endings");
+                        return Err(Parse::Header);
+                    }
                     let method = Method::from_bytes(req.method.unwrap().as_bytes())?;
-                    let path = req.path.unwrap().parse()?;
-                    let subject = RequestLine(method, path);
+                    let target = req.path.unwrap();
+                    // https://tools.ietf.org/html/rfc7230#section-5.3.4
+                    // The asterisk-form is only used with OPTIONS.
+                    if target == "*" && method != Method::OPTIONS {
+                        debug!("request target is asterisk-form, but method is {}", method);
+                        return Err(Parse::Uri);
+                    }
+                    // The target is parsed from the head's bytes once they
+                    // are split off, to share them instead of copying.
+                    let start = target.as_ptr() as usize - bytes.as_ptr() as usize;
+                    let target = (start, start + target.len());
                     let version = if req.version.unwrap() == 1 {
                         Version::HTTP_11
                     } else {
@@ -58,7 +105,7 @@ where
 
                     record_header_indices(bytes, &req.headers, &mut headers_indices);
                     let headers_len = req.headers.len();
-                    (len, subject, version, headers_len)
+                    (len, method, target, version, headers_len)
                 }
                 httparse::Status::Partial => return Ok(None),
             }
@@ -66,6 +113,31 @@ where
 
         let slice = buf.split_to(len).freeze();
 
+        let mut raw_target = None;
+        let target = slice.slice(target.0, target.1);
+        let path = match ::Uri::from_shared(target.clone()) {
+            Ok(path) => path,
+            Err(ref e) if ctx.config.lenient_target => {
+                debug!("passing invalid request target to service: {}", e);
+                raw_target = Some(RawTarget::new(target));
+                ::Uri::default()
+            },
+            Err(e) => return Err(e.into()),
+        };
+        if !ctx.config.absolute_form && path.scheme_part().is_some() {
+            debug!("request target is absolute-form, but not allowed");
+            return Err(Parse::Uri);
+        }
+        let subject = RequestLine(method, path);
+
+        let raw_line = if ctx.config.record_request_line {
+            raw_line.or_else(|| {
+                request_line_range(&slice).map(|(start, end)| slice.slice(start, end))
+            })
+        } else {
+            None
+        };
+
         // According to https://tools.ietf.org/html/rfc7230#section-3.3.3
         // 1. (irrelevant to Request)
         // 2. (irrelevant to Request)
@@ -82,6 +154,8 @@ where
         let mut con_len = None;
         let mut is_te = false;
         let mut is_te_chunked = false;
+        let mut normalized = None::<FramingNormalization>;
+        let reject = ctx.config.framing == FramingPolicy::Reject;
 
         let mut headers = ctx.cached_headers
             .take()
@@ -89,9 +163,23 @@ where
 
         headers.reserve(headers_len);
 
+        let mut header_case = if ctx.config.record_header_case {
+            Some(HeaderCaseMap::new())
+        } else {
+            None
+        };
+        let mut header_order = if ctx.config.record_header_order {
+            Some(HeaderOrder::new())
+        } else {
+            None
+        };
+
         for header in &headers_indices[..headers_len] {
             let name = HeaderName::from_bytes(&slice[header.name.0..header.name.1])
                 .expect("header name already validated");
+            if let Some(ref mut case) = header_case {
+                case.append_raw(name.clone(), slice.slice(header.name.0, header.name.1));
+            }
             let val = slice.slice(header.value.0, header.value.1);
             // Unsafe: httparse already validated header value
             let value = unsafe {
@@ -108,16 +196,39 @@ where
                         debug!("HTTP/1.0 cannot have Transfer-Encoding header");
                         return Err(Parse::Header);
                     }
-                    is_te = true;
-                    if headers::is_chunked_(&value) {
-                        is_te_chunked = true;
-                        decoder = Some(Decoder::chunked());
-                        //debug!("request with transfer-encoding header, but not chunked, bad request");
-                        //return Err(Parse::Header);
+                    if headers::has_unknown_coding(&value) {
+                        if reject {
+                            debug!("request with unknown transfer-coding: {:?}", value);
+                            return Err(Parse::Header);
+                        }
+                        normalized.get_or_insert_with(Default::default).unknown_transfer_coding = true;
                     }
+                    if con_len.is_some() {
+                        if reject {
+                            debug!("request with both content-length and transfer-encoding");
+                            return Err(Parse::Header);
+                        }
+                        headers.remove(header::CONTENT_LENGTH);
+                        normalized.get_or_insert_with(Default::default).content_length_removed = true;
+                        con_len = None;
+                    }
+                    is_te = true;
+                    // Only the last coding of the last header counts, so a
+                    // later `Transfer-Encoding` can undo an earlier chunked.
+                    is_te_chunked = headers::is_chunked_(&value);
+                    decoder = if is_te_chunked {
+                        Some(Decoder::chunked())
+                    } else {
+                        None
+                    };
                 },
                 header::CONTENT_LENGTH => {
                     if is_te {
+                        if reject {
+                            debug!("request with both transfer-encoding and content-length");
+                            return Err(Parse::Header);
+                        }
+                        normalized.get_or_insert_with(Default::default).content_length_removed = true;
                         continue;
                     }
                     let len = value.to_str()
@@ -132,7 +243,12 @@ where
                             );
                             return Err(Parse::Header);
                         }
+                        if reject {
+                            debug!("request with duplicate content-length headers");
+                            return Err(Parse::Header);
+                        }
                         // we don't need to append this secondary length
+                        normalized.get_or_insert_with(Default::default).content_length_merged = true;
                         continue;
                     }
                     con_len = Some(len);
@@ -156,26 +272,52 @@ where
                 _ => (),
             }
 
+            if let Some(ref mut order) = header_order {
+                order.push(name.clone());
+            }
             headers.append(name, value);
         }
 
-        let decoder = if let Some(decoder) = decoder {
-            decoder
-        } else {
-            if is_te && !is_te_chunked {
-                debug!("request with transfer-encoding header, but not chunked, bad request");
-                return Err(Parse::Header);
-            }
-            Decoder::length(0)
-        };
+        if is_te && !is_te_chunked {
+            debug!("request with transfer-encoding header, but not chunked, bad request");
+            return Err(Parse::Header);
+        }
+        let decoder = decoder.unwrap_or_else(|| Decoder::length(0));
+
+        // https://tools.ietf.org/html/rfc7230#section-5.4
+        // A server MUST respond with a 400 to any HTTP/1.1 request message
+        // that lacks a Host header field, or has more than one.
+        if ctx.config.require_host && version == Version::HTTP_11
+            && headers.get_all(header::HOST).iter().count() != 1 {
+            debug!("HTTP/1.1 request without exactly one host header");
+            return Err(Parse::Header);
+        }
 
         *ctx.req_method = Some(subject.0.clone());
 
+        let mut extensions = ::http::Extensions::new();
+        if let Some(raw_target) = raw_target {
+            extensions.insert(raw_target);
+        }
+        if let Some(raw_line) = raw_line {
+            extensions.insert(RawRequestLine::new(raw_line));
+        }
+        if let Some(header_case) = header_case {
+            extensions.insert(header_case);
+        }
+        if let Some(header_order) = header_order {
+            extensions.insert(header_order);
+        }
+        if let Some(normalized) = normalized {
+            extensions.insert(normalized);
+        }
+
         Ok(Some(ParsedMessage {
             head: MessageHead {
                 version,
                 subject,
                 headers,
+                extensions,
             },
             decode: Decode::Normal(decoder),
             expect_continue,
@@ -214,10 +356,31 @@ where
             dst.truncate(orig_len);
         };
 
+        let header_case = if msg.preserve_header_case {
+            msg.head.extensions.remove::<HeaderCaseMap>()
+        } else {
+            None
+        };
+        let case = header_case.as_ref();
+        let raw = msg.head.extensions.get::<Framing>() == Some(&Framing::Raw);
+
+        // the framing the user picked, if it can be used for this response
+        let framing = match msg.head.extensions.get::<Framing>() {
+            _ if msg.body.is_none() || !Server::can_have_body(msg.req_method, msg.head.subject) => None,
+            Some(&Framing::Length) => match msg.body {
+                Some(BodyLength::Known(_)) => Some(Framing::Length),
+                _ => headers::content_length_parse_all(&msg.head.headers).map(|_| Framing::Length),
+            },
+            Some(&Framing::Chunked)
+                if msg.head.version == Version::HTTP_10
+                    || !Server::can_chunked(msg.req_method, msg.head.subject) => None,
+            framing => framing.cloned(),
+        };
+
         let init_cap = 30 + msg.head.headers.len() * AVERAGE_HEADER_SIZE;
         dst.reserve(init_cap);
-        if msg.head.version == Version::HTTP_11 && msg.head.subject == StatusCode::OK {
-            extend(dst, b"HTTP/1.1 200 OK\r\n");
+        if let Some(line) = cached_status_line(msg.head.version, msg.head.subject) {
+            extend(dst, line);
         } else {
             match msg.head.version {
                 Version::HTTP_10 => extend(dst, b"HTTP/1.0 "),
@@ -232,68 +395,53 @@ where
             extend(dst, b"\r\n");
         }
 
+        if raw {
+            let has_body = msg.body.is_some() && Server::can_have_body(msg.req_method, msg.head.subject);
+            let headers = &msg.head.headers;
+            if headers.get_all(header::CONNECTION).iter().any(headers::connection_close) {
+                is_last = true;
+            }
+            if has_body
+                && headers::content_length_parse_all(headers).is_none()
+                && !headers::transfer_encoding_is_chunked(headers)
+            {
+                // the peer reads this body until the connection closes
+                is_last = true;
+            }
+            write_headers_as_given(msg.head, header_case.as_ref(), false, dst);
+            extend(dst, b"\r\n");
+            msg.head.headers.clear();
+            let encoder = if has_body {
+                Encoder::raw()
+            } else {
+                Encoder::length(0)
+            };
+            return ret.map(|()| encoder.set_last(is_last));
+        }
+
         let mut encoder = Encoder::length(0);
         let mut wrote_len = false;
         let mut wrote_date = false;
         'headers: for (name, mut values) in msg.head.headers.drain() {
             match name {
                 header::CONTENT_LENGTH => {
+                    match framing {
+                        Some(Framing::Chunked) | Some(Framing::CloseDelimited) => continue 'headers,
+                        _ => (),
+                    }
                     if wrote_len {
                         warn!("transfer-encoding and content-length both found, canceling");
                         rewind(dst);
                         return Err(::Error::new_header());
                     }
                     match msg.body {
-                        Some(BodyLength::Known(known_len)) => {
-                            // The Payload claims to know a length, and
-                            // the headers are already set. For performance
-                            // reasons, we are just going to trust that
-                            // the values match.
-                            //
-                            // In debug builds, we'll assert they are the
-                            // same to help developers find bugs.
-                            encoder = Encoder::length(known_len);
-
-                            #[cfg(debug_assertions)]
-                            {
-                                let mut folded = None::<(u64, HeaderValue)>;
-                                for value in values {
-                                    if let Some(len) = headers::content_length_parse(&value) {
-                                        if let Some(fold) = folded {
-                                            if fold.0 != len {
-                                                panic!("multiple Content-Length values found: [{}, {}]", fold.0, len);
-                                            }
-                                            folded = Some(fold);
-                                        } else {
-                                            folded = Some((len, value));
-                                        }
-                                    } else {
-                                        panic!("illegal Content-Length value: {:?}", value);
-                                    }
-                                }
-                                if let Some((len, value)) = folded {
-                                    assert!(
-                                        len == known_len,
-                                        "payload claims content-length of {}, custom content-length header claims {}",
-                                        known_len,
-                                        len,
-                                    );
-                                    extend(dst, b"content-length: ");
-                                    extend(dst, value.as_bytes());
-                                    extend(dst, b"\r\n");
-                                    wrote_len = true;
-                                    continue 'headers;
-                                } else {
-                                    // No values in content-length... ignore?
-                                    continue 'headers;
-                                }
-                            }
-                        },
+                        Some(BodyLength::Known(_)) |
                         Some(BodyLength::Unknown) => {
-                            // The Payload impl didn't know how long the
-                            // body is, but a length header was included.
-                            // We have to parse the value to return our
-                            // Encoder...
+                            // A length header was included, which is used
+                            // even if the Payload knows its length, so a
+                            // body that doesn't match it is noticed when
+                            // written. We have to parse the value to
+                            // return our Encoder...
                             let mut folded = None::<(u64, HeaderValue)>;
                             for value in values {
                                 if let Some(len) = headers::content_length_parse(&value) {
@@ -315,7 +463,8 @@ where
                             }
                             if let Some((len, value)) = folded {
                                 encoder = Encoder::length(len);
-                                extend(dst, b"content-length: ");
+                                extend_name(dst, &name, case, 0);
+                                extend(dst, b": ");
                                 extend(dst, value.as_bytes());
                                 extend(dst, b"\r\n");
                                 wrote_len = true;
@@ -347,6 +496,10 @@ where
                     wrote_len = true;
                 },
                 header::TRANSFER_ENCODING => {
+                    match framing {
+                        Some(Framing::Length) | Some(Framing::CloseDelimited) => continue 'headers,
+                        _ => (),
+                    }
                     if wrote_len {
                         warn!("transfer-encoding and content-length both found, canceling");
                         rewind(dst);
@@ -359,7 +512,8 @@ where
                     wrote_len = true;
                     encoder = Encoder::chunked();
 
-                    extend(dst, b"transfer-encoding: ");
+                    extend_name(dst, &name, case, 0);
+                    extend(dst, b": ");
 
                     let mut saw_chunked;
                     if let Some(te) = values.next() {
@@ -383,8 +537,8 @@ where
                 },
                 header::CONNECTION => {
                     if !is_last {
-                        for value in values {
-                            extend(dst, name.as_str().as_bytes());
+                        for (i, value) in values.enumerate() {
+                            extend_name(dst, &name, case, i);
                             extend(dst, b": ");
                             extend(dst, value.as_bytes());
                             extend(dst, b"\r\n");
@@ -403,8 +557,8 @@ where
             }
             //TODO: this should perhaps instead combine them into
             //single lines, as RFC7230 suggests is preferable.
-            for value in values {
-                extend(dst, name.as_str().as_bytes());
+            for (i, value) in values.enumerate() {
+                extend_name(dst, &name, case, i);
                 extend(dst, b": ");
                 extend(dst, value.as_bytes());
                 extend(dst, b"\r\n");
@@ -413,21 +567,32 @@ where
 
         if !wrote_len {
             encoder = match msg.body {
+                _ if framing == Some(Framing::Chunked) => {
+                    extend_default(dst, &header::TRANSFER_ENCODING, case, b"transfer-encoding: chunked\r\n");
+                    Encoder::chunked()
+                },
+                _ if framing == Some(Framing::CloseDelimited) => {
+                    is_last = true;
+                    Encoder::close_delimited()
+                },
                 Some(BodyLength::Unknown) => {
                     if msg.head.version == Version::HTTP_10 || !Server::can_chunked(msg.req_method, msg.head.subject) {
+                        // the end of the body is only known by closing
+                        is_last = true;
                         Encoder::close_delimited()
                     } else {
-                        extend(dst, b"transfer-encoding: chunked\r\n");
+                        extend_default(dst, &header::TRANSFER_ENCODING, case, b"transfer-encoding: chunked\r\n");
                         Encoder::chunked()
                     }
                 },
                 None |
                 Some(BodyLength::Known(0)) => {
-                    extend(dst, b"content-length: 0\r\n");
+                    extend_default(dst, &header::CONTENT_LENGTH, case, b"content-length: 0\r\n");
                     Encoder::length(0)
                 },
                 Some(BodyLength::Known(len)) => {
-                    let _ = write!(FastWrite(dst), "content-length: {}\r\n", len);
+                    extend_default(dst, &header::CONTENT_LENGTH, case, b"content-length: ");
+                    let _ = write!(FastWrite(dst), "{}\r\n", len);
                     Encoder::length(len)
                 },
             };
@@ -441,7 +606,7 @@ where
         // cached date is much faster than formatting every request
         if !wrote_date {
             dst.reserve(date::DATE_VALUE_LENGTH + 8);
-            extend(dst, b"date: ");
+            extend_default(dst, &header::DATE, case, b"date: ");
             date::extend(dst);
             extend(dst, b"\r\n\r\n");
         } else {
@@ -463,15 +628,25 @@ where
             Kind::Parse(Parse::TooLarge) => {
                 StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
             },
+            Kind::Parse(Parse::UriTooLong) => {
+                StatusCode::URI_TOO_LONG
+            },
+            _ if err.is_body_too_large() => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            },
             _ => return None,
         };
 
-        debug!("sending automatic response ({}) for parse error", status);
+        debug!("sending automatic response ({}) for error: {}", status, err);
         let mut msg = MessageHead::default();
         msg.subject = status;
         Some(msg)
     }
 
+    fn error_status(head: &MessageHead<Self::Outgoing>) -> Option<StatusCode> {
+        Some(head.subject)
+    }
+
     fn should_error_on_parse_eof() -> bool {
         false
     }
@@ -526,9 +701,27 @@ where
             trace!("Response.parse([Header; {}], [u8; {}])", headers.len(), buf.len());
             let mut res = httparse::Response::new(&mut headers);
             let bytes = buf.as_ref();
-            match res.parse(bytes)? {
+            let mut config = httparse::ParserConfig::default();
+            config.allow_obsolete_multiline_headers_in_responses(ctx.config.obs_fold);
+            config.allow_spaces_after_header_name_in_responses(ctx.config.spaces_after_header_name);
+            match config.parse_response(&mut res, bytes)? {
                 httparse::Status::Complete(len) => {
                     trace!("Response.parse Complete({})", len);
+                    if !ctx.config.lf_line_endings && has_bare_lf(&bytes[..len]) {
+                        debug!("response head has bare LF line endings");
+                        return Err(Parse::Header);
+                    }
+                    // https://tools.ietf.org/html/rfc7230#section-3.1.2
+                    // The reason-phrase may be empty, but not the space before it.
+                    if !ctx.config.missing_reason && res.reason == Some("") {
+                        let start = bytes.iter()
+                            .position(|&b| b != b'\r' && b != b'\n')
+                            .unwrap_or(0);
+                        if bytes.get(start + "HTTP/1.1 200".len()) != Some(&b' ') {
+                            debug!("response status line has no reason-phrase");
+                            return Err(Parse::Status);
+                        }
+                    }
                     let status = StatusCode::from_u16(res.code.unwrap())?;
                     let version = if res.version.unwrap() == 1 {
                         Version::HTTP_11
@@ -543,6 +736,19 @@ where
             }
         };
 
+        // https://tools.ietf.org/html/rfc7230#section-3.2.4
+        // A user agent may replace each obs-fold with one or more SP octets,
+        // which keeps the header indices valid.
+        if ctx.config.obs_fold {
+            for header in &headers_indices[..headers_len] {
+                for b in &mut buf[header.value.0..header.value.1] {
+                    if *b == b'\r' || *b == b'\n' {
+                        *b = b' ';
+                    }
+                }
+            }
+        }
+
         let slice = buf.split_to(len).freeze();
 
         let mut headers = ctx.cached_headers
@@ -550,14 +756,51 @@ where
             .unwrap_or_else(HeaderMap::new);
 
         headers.reserve(headers_len);
-        fill_headers(&mut headers, slice, &headers_indices[..headers_len]);
+        let mut header_case = if ctx.config.record_header_case {
+            Some(HeaderCaseMap::new())
+        } else {
+            None
+        };
+        let mut header_order = if ctx.config.record_header_order {
+            Some(HeaderOrder::new())
+        } else {
+            None
+        };
+        fill_headers(
+            &mut headers,
+            header_case.as_mut(),
+            header_order.as_mut(),
+            slice,
+            &headers_indices[..headers_len],
+        );
 
-        let keep_alive = version == Version::HTTP_11;
+        // keep_alive is set to the default for Version, and then
+        // changed by any Connection headers
+        let mut keep_alive = version == Version::HTTP_11;
+        for value in headers.get_all(header::CONNECTION) {
+            if keep_alive {
+                keep_alive = !headers::connection_close(value);
+            } else {
+                keep_alive = headers::connection_keep_alive(value);
+            }
+        }
+
+        let mut extensions = ::http::Extensions::new();
+        if let Some(header_case) = header_case {
+            extensions.insert(header_case);
+        }
+        if let Some(header_order) = header_order {
+            extensions.insert(header_order);
+        }
+        if let Some(normalized) = normalize_framing(&mut headers, ctx.config.framing)? {
+            extensions.insert(normalized);
+        }
 
         let head = MessageHead {
             version,
             subject: status,
             headers,
+            extensions,
         };
         let decode = Client::<T>::decoder(&head, ctx.req_method)?;
 
@@ -574,7 +817,19 @@ where
 
         *msg.req_method = Some(msg.head.subject.0.clone());
 
-        let body = Client::set_length(msg.head, msg.body);
+        if let Some(max) = msg.max_uri_len {
+            let len = uri_len(&msg.head.subject.1);
+            if len > max {
+                debug!("request URI is {} bytes, longer than {}", len, max);
+                return Err(::Error::new_user_uri_too_long());
+            }
+        }
+
+        let body = match msg.head.extensions.get::<Framing>() {
+            Some(&Framing::Raw) if msg.body.is_some() => Encoder::raw(),
+            Some(&Framing::Raw) => Encoder::length(0),
+            _ => Client::set_length(msg.head, msg.body),
+        };
 
         let init_cap = 30 + msg.head.headers.len() * AVERAGE_HEADER_SIZE;
         dst.reserve(init_cap);
@@ -592,11 +847,13 @@ where
         }
         extend(dst, b"\r\n");
 
-        if msg.title_case_headers {
-            write_headers_title_case(&msg.head.headers, dst);
+        let header_case = if msg.preserve_header_case {
+            msg.head.extensions.remove::<HeaderCaseMap>()
         } else {
-            write_headers(&msg.head.headers, dst);
-        }
+            None
+        };
+
+        write_headers_as_given(msg.head, header_case.as_ref(), msg.title_case_headers, dst);
         extend(dst, b"\r\n");
         msg.head.headers.clear(); //TODO: remove when switching to drain()
 
@@ -689,6 +946,33 @@ impl Client<()> {
                 && (head.subject.0 != Method::HEAD)
                 && (head.subject.0 != Method::GET)
                 && (head.subject.0 != Method::CONNECT);
+            match head.extensions.get::<Framing>() {
+                Some(&Framing::Chunked) if can_chunked => {
+                    head.headers.remove(header::CONTENT_LENGTH);
+                    match head.headers.entry(header::TRANSFER_ENCODING)
+                        .expect("TRANSFER_ENCODING is valid HeaderName") {
+                        Entry::Occupied(te) => {
+                            if !headers::is_chunked(te.iter()) {
+                                headers::add_chunked(te);
+                            }
+                        },
+                        Entry::Vacant(te) => {
+                            te.insert(headers::chunked_value());
+                        },
+                    }
+                    return Encoder::chunked();
+                },
+                Some(&Framing::Length) => {
+                    let known = match body {
+                        BodyLength::Known(_) => true,
+                        BodyLength::Unknown => headers::content_length_parse_all(&head.headers).is_some(),
+                    };
+                    if known {
+                        head.headers.remove(header::TRANSFER_ENCODING);
+                    }
+                },
+                _ => (),
+            }
             set_length(&mut head.headers, body, can_chunked)
         } else {
             head.headers.remove(header::TRANSFER_ENCODING);
@@ -745,7 +1029,7 @@ fn set_length(headers: &mut HeaderMap, body: BodyLength, can_chunked: bool) -> E
                     Some(Encoder::length(len))
                 } else if let BodyLength::Unknown = body {
                     should_remove_con_len = true;
-                    te.insert(HeaderValue::from_static("chunked"));
+                    te.insert(headers::chunked_value());
                     Some(Encoder::chunked())
                 } else {
                     None
@@ -881,6 +1165,107 @@ struct HeaderIndices {
     value: (usize, usize),
 }
 
+// https://tools.ietf.org/html/rfc7230#section-3.3.3
+fn normalize_framing(headers: &mut HeaderMap, policy: FramingPolicy) -> Result<Option<FramingNormalization>, Parse> {
+    let reject = policy == FramingPolicy::Reject;
+    let mut normalized = None::<FramingNormalization>;
+
+    if headers.contains_key(header::TRANSFER_ENCODING) {
+        if headers.get_all(header::TRANSFER_ENCODING).iter().any(headers::has_unknown_coding) {
+            if reject {
+                debug!("response with unknown transfer-coding");
+                return Err(Parse::Header);
+            }
+            normalized.get_or_insert_with(Default::default).unknown_transfer_coding = true;
+        }
+        if headers.contains_key(header::CONTENT_LENGTH) {
+            if reject {
+                debug!("response with both content-length and transfer-encoding");
+                return Err(Parse::Header);
+            }
+            headers.remove(header::CONTENT_LENGTH);
+            normalized.get_or_insert_with(Default::default).content_length_removed = true;
+        }
+    } else if headers.get_all(header::CONTENT_LENGTH).iter().nth(1).is_some() {
+        if reject {
+            debug!("response with duplicate content-length headers");
+            return Err(Parse::Header);
+        }
+        // Differing values are left for the decoder to reject.
+        if let Some(len) = headers::content_length_parse_all(headers) {
+            headers.insert(header::CONTENT_LENGTH, headers::content_length_value(len));
+            normalized.get_or_insert_with(Default::default).content_length_merged = true;
+        }
+    }
+
+    Ok(normalized)
+}
+
+// Percent-encodes the bytes of the request-target in `buf` that strict
+// parsing rejects, such as spaces, `|` and `^` sent by old devices. The
+// target is everything between the first and last space of the line.
+//
+// Returns false if the request-line isn't complete yet.
+fn encode_lenient_target(buf: &mut BytesMut) -> bool {
+    fn needs_encoding(b: u8) -> bool {
+        match b {
+            b' ' | b'"' | b'<' | b'>' | b'\\' | b'^' | b'`' | b'{' | b'|' | b'}' => true,
+            b => b >= 0x80,
+        }
+    }
+
+    let (start, end) = {
+        let (line_start, line_end) = match request_line_range(buf) {
+            Some(range) => range,
+            None => return false,
+        };
+        let line = &buf[line_start..line_end];
+        match (line.iter().position(|&b| b == b' '), line.iter().rposition(|&b| b == b' ')) {
+            (Some(first), Some(last)) if first < last => (line_start + first + 1, line_start + last),
+            _ => return true,
+        }
+    };
+
+    if !buf[start..end].iter().cloned().any(needs_encoding) {
+        return true;
+    }
+
+    const HEX: &'static [u8; 16] = b"0123456789ABCDEF";
+    let mut encoded = BytesMut::with_capacity(buf.len() + (end - start) * 2);
+    encoded.extend_from_slice(&buf[..start]);
+    for &b in &buf[start..end] {
+        if needs_encoding(b) {
+            encoded.extend_from_slice(&[b'%', HEX[(b >> 4) as usize], HEX[(b & 0x0F) as usize]]);
+        } else {
+            encoded.extend_from_slice(&[b]);
+        }
+    }
+    encoded.extend_from_slice(&buf[end..]);
+    trace!("percent-encoded lenient request-target");
+    *buf = encoded;
+    true
+}
+
+// The range of the request-line in `buf`, without the line ending and any
+// empty lines before it, or `None` if the line isn't complete yet.
+fn request_line_range(buf: &[u8]) -> Option<(usize, usize)> {
+    let start = buf.iter().position(|&b| b != b'\r' && b != b'\n')?;
+    let len = scan::find(b'\n', &buf[start..])?;
+    let mut end = start + len;
+    if buf[end - 1] == b'\r' {
+        end -= 1;
+    }
+    Some((start, end))
+}
+
+// The length of a `Uri` as written by its `Display` impl.
+fn uri_len(uri: &::Uri) -> usize {
+    uri.scheme_part().map(|scheme| scheme.as_str().len() + "://".len()).unwrap_or(0)
+        + uri.authority_part().map(|auth| auth.as_str().len()).unwrap_or(0)
+        + uri.path().len()
+        + uri.query().map(|query| query.len() + 1).unwrap_or(0)
+}
+
 fn record_header_indices(bytes: &[u8], headers: &[httparse::Header], indices: &mut [HeaderIndices]) {
     let bytes_ptr = bytes.as_ptr() as usize;
     for (header, indices) in headers.iter().zip(indices.iter_mut()) {
@@ -893,10 +1278,22 @@ fn record_header_indices(bytes: &[u8], headers: &[httparse::Header], indices: &m
     }
 }
 
-fn fill_headers(headers: &mut HeaderMap, slice: Bytes, indices: &[HeaderIndices]) {
+fn fill_headers(
+    headers: &mut HeaderMap,
+    mut case: Option<&mut HeaderCaseMap>,
+    mut order: Option<&mut HeaderOrder>,
+    slice: Bytes,
+    indices: &[HeaderIndices],
+) {
     for header in indices {
         let name = HeaderName::from_bytes(&slice[header.name.0..header.name.1])
             .expect("header name already validated");
+        if let Some(ref mut case) = case {
+            case.append_raw(name.clone(), slice.slice(header.name.0, header.name.1));
+        }
+        if let Some(ref mut order) = order {
+            order.push(name.clone());
+        }
         let value = unsafe {
             HeaderValue::from_shared_unchecked(
                 slice.slice(header.value.0, header.value.1)
@@ -935,6 +1332,27 @@ fn title_case(dst: &mut Vec<u8>, name: &[u8]) {
     }
 }
 
+// Writes the headers of `head`, in the order and spelling recorded in its
+// extensions, if any.
+fn write_headers_as_given<S>(
+    head: &mut MessageHead<S>,
+    case: Option<&HeaderCaseMap>,
+    title_case_headers: bool,
+    dst: &mut Vec<u8>,
+) {
+    let header_order = head.extensions.remove::<HeaderOrder>();
+
+    if let Some(ref order) = header_order {
+        write_headers_ordered(&head.headers, order, case, title_case_headers, dst);
+    } else if let Some(case) = case {
+        write_headers_original_case(&head.headers, case, title_case_headers, dst);
+    } else if title_case_headers {
+        write_headers_title_case(&head.headers, dst);
+    } else {
+        write_headers(&head.headers, dst);
+    }
+}
+
 fn write_headers_title_case(headers: &HeaderMap, dst: &mut Vec<u8>) {
     for (name, value) in headers {
         title_case(dst, name.as_str().as_bytes());
@@ -944,6 +1362,64 @@ fn write_headers_title_case(headers: &HeaderMap, dst: &mut Vec<u8>) {
     }
 }
 
+fn write_headers_original_case(headers: &HeaderMap, case: &HeaderCaseMap, title_case_headers: bool, dst: &mut Vec<u8>) {
+    // HeaderMap iterates all the values of a name before the next name.
+    let mut prev: Option<(&HeaderName, usize)> = None;
+    for (name, value) in headers {
+        let index = match prev {
+            Some((prev_name, i)) if prev_name == name => i + 1,
+            _ => 0,
+        };
+        prev = Some((name, index));
+
+        write_header(dst, name, value, index, Some(case), title_case_headers);
+    }
+}
+
+fn write_headers_ordered(
+    headers: &HeaderMap,
+    order: &HeaderOrder,
+    case: Option<&HeaderCaseMap>,
+    title_case_headers: bool,
+    dst: &mut Vec<u8>,
+) {
+    // The number of values of each name that have been written.
+    let mut written = HashMap::<&HeaderName, usize>::new();
+
+    for name in order.names() {
+        let index = written.entry(name).or_insert(0);
+        if let Some(value) = headers.get_all(name).iter().nth(*index) {
+            write_header(dst, name, value, *index, case, title_case_headers);
+            *index += 1;
+        }
+    }
+
+    for name in headers.keys() {
+        let skip = written.get(name).cloned().unwrap_or(0);
+        for (index, value) in headers.get_all(name).iter().enumerate().skip(skip) {
+            write_header(dst, name, value, index, case, title_case_headers);
+        }
+    }
+}
+
+fn write_header(
+    dst: &mut Vec<u8>,
+    name: &HeaderName,
+    value: &HeaderValue,
+    index: usize,
+    case: Option<&HeaderCaseMap>,
+    title_case_headers: bool,
+) {
+    match case.and_then(|case| case.get(name, index)) {
+        Some(original) => extend(dst, original),
+        None if title_case_headers => title_case(dst, name.as_str().as_bytes()),
+        None => extend(dst, name.as_str().as_bytes()),
+    }
+    extend(dst, b": ");
+    extend(dst, value.as_bytes());
+    extend(dst, b"\r\n");
+}
+
 fn write_headers(headers: &HeaderMap, dst: &mut Vec<u8>) {
     for (name, value) in headers {
         extend(dst, name.as_str().as_bytes());
@@ -968,6 +1444,82 @@ impl<'a> fmt::Write for FastWrite<'a> {
     }
 }
 
+fn extend_name(dst: &mut Vec<u8>, name: &HeaderName, case: Option<&HeaderCaseMap>, index: usize) {
+    match case.and_then(|case| case.get(name, index)) {
+        Some(original) => extend(dst, original),
+        None => extend(dst, name.as_str().as_bytes()),
+    }
+}
+
+/// Writes a header hyper adds itself, which `line` has already encoded
+/// up to its value, unless the name's case is to be preserved.
+fn extend_default(dst: &mut Vec<u8>, name: &HeaderName, case: Option<&HeaderCaseMap>, line: &'static [u8]) {
+    match case.and_then(|case| case.get(name, 0)) {
+        Some(original) => {
+            extend(dst, original);
+            extend(dst, &line[name.as_str().len()..]);
+        },
+        None => extend(dst, line),
+    }
+}
+
+macro_rules! status_lines {
+    ($($code:tt $reason:tt,)+) => {
+        /// The status line of a common status, already encoded, so it's
+        /// copied instead of formatted for each response.
+        fn cached_status_line(version: Version, status: StatusCode) -> Option<&'static [u8]> {
+            if version != Version::HTTP_11 {
+                return None;
+            }
+            match status.as_u16() {
+                $(
+                    $code => Some(concat!("HTTP/1.1 ", $code, " ", $reason, "\r\n").as_bytes()),
+                )+
+                _ => None,
+            }
+        }
+
+        #[cfg(test)]
+        const CACHED_STATUSES: &'static [u16] = &[$($code),+];
+    };
+}
+
+status_lines! {
+    200 "OK",
+    201 "Created",
+    202 "Accepted",
+    204 "No Content",
+    206 "Partial Content",
+    301 "Moved Permanently",
+    302 "Found",
+    303 "See Other",
+    304 "Not Modified",
+    307 "Temporary Redirect",
+    308 "Permanent Redirect",
+    400 "Bad Request",
+    401 "Unauthorized",
+    403 "Forbidden",
+    404 "Not Found",
+    405 "Method Not Allowed",
+    408 "Request Timeout",
+    409 "Conflict",
+    410 "Gone",
+    411 "Length Required",
+    412 "Precondition Failed",
+    413 "Payload Too Large",
+    414 "URI Too Long",
+    415 "Unsupported Media Type",
+    416 "Range Not Satisfiable",
+    422 "Unprocessable Entity",
+    429 "Too Many Requests",
+    431 "Request Header Fields Too Large",
+    500 "Internal Server Error",
+    501 "Not Implemented",
+    502 "Bad Gateway",
+    503 "Service Unavailable",
+    504 "Gateway Timeout",
+}
+
 #[inline]
 fn extend(dst: &mut Vec<u8>, data: &[u8]) {
     dst.extend_from_slice(data);
@@ -992,6 +1544,7 @@ mod tests {
         let msg = Server::parse(&mut raw, ParseContext {
             cached_headers: &mut None,
             req_method: &mut method,
+            config: Default::default(),
         }).unwrap().unwrap();
         assert_eq!(raw.len(), 0);
         assert_eq!(msg.head.subject.0, ::Method::GET);
@@ -1003,6 +1556,30 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_parse_request_long_target() {
+        // long enough that the target's bytes aren't stored inline
+        let mut raw = BytesMut::from(b"GET /a/rather/long/path/to/a/resource?with=a&query=string HTTP/1.1\r\n\r\n".to_vec());
+        let msg = Server::parse(&mut raw, ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            config: Default::default(),
+        }).unwrap().unwrap();
+        assert_eq!(msg.head.subject.1.path(), "/a/rather/long/path/to/a/resource");
+        assert_eq!(msg.head.subject.1.query(), Some("with=a&query=string"));
+    }
+
+    #[test]
+    fn test_cached_status_lines() {
+        for &code in CACHED_STATUSES {
+            let status = StatusCode::from_u16(code).unwrap();
+            let line = format!("HTTP/1.1 {} {}\r\n", code, status.canonical_reason().unwrap());
+            assert_eq!(cached_status_line(Version::HTTP_11, status), Some(line.as_bytes()));
+        }
+        assert_eq!(cached_status_line(Version::HTTP_10, StatusCode::OK), None);
+        assert_eq!(cached_status_line(Version::HTTP_11, StatusCode::IM_A_TEAPOT), None);
+    }
+
     #[test]
     fn test_parse_response() {
         extern crate pretty_env_logger;
@@ -1011,6 +1588,7 @@ mod tests {
         let ctx = ParseContext {
             cached_headers: &mut None,
             req_method: &mut Some(::Method::GET),
+            config: Default::default(),
         };
         let msg = Client::parse(&mut raw, ctx).unwrap().unwrap();
         assert_eq!(raw.len(), 0);
@@ -1026,10 +1604,132 @@ mod tests {
         let ctx = ParseContext {
             cached_headers: &mut None,
             req_method: &mut None,
+            config: Default::default(),
         };
         Server::parse(&mut raw, ctx).unwrap_err();
     }
 
+    #[test]
+    fn test_parse_request_asterisk_form() {
+        let mut raw = BytesMut::from(b"OPTIONS * HTTP/1.1\r\nHost: hyper.rs\r\n\r\n".to_vec());
+        let msg = Server::parse(&mut raw, ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            config: Default::default(),
+        }).unwrap().unwrap();
+        assert_eq!(msg.head.subject.0, ::Method::OPTIONS);
+        assert_eq!(msg.head.subject.1, "*");
+        assert_eq!(msg.head.subject.1.path(), "*");
+
+        let mut raw = BytesMut::from(b"GET * HTTP/1.1\r\nHost: hyper.rs\r\n\r\n".to_vec());
+        let err = Server::parse(&mut raw, ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            config: Default::default(),
+        }).unwrap_err();
+        assert_eq!(err, Parse::Uri);
+    }
+
+    #[test]
+    fn test_parse_request_lenient_target() {
+        let mut raw = BytesMut::from(b"GET htt:p// HTTP/1.1\r\nHost: hyper.rs\r\n\r\n".to_vec());
+        let mut config = ParseConfig::default();
+        config.lenient_target = true;
+        let msg = Server::parse(&mut raw, ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            config,
+        }).unwrap().unwrap();
+        assert_eq!(msg.head.subject.1, "/");
+        let raw_target = msg.head.extensions.get::<RawTarget>().expect("raw target");
+        assert_eq!(raw_target.as_bytes(), b"htt:p//");
+
+        let mut raw = BytesMut::from(b"GET /valid HTTP/1.1\r\nHost: hyper.rs\r\n\r\n".to_vec());
+        let msg = Server::parse(&mut raw, ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            config,
+        }).unwrap().unwrap();
+        assert_eq!(msg.head.subject.1, "/valid");
+        assert!(msg.head.extensions.get::<RawTarget>().is_none());
+    }
+
+    #[test]
+    fn test_parse_request_lenient_uri() {
+        let mut config = ParseConfig::default();
+        config.lenient_uri = true;
+        let parse = |raw: &str| Server::parse(&mut BytesMut::from(raw), ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            config,
+        });
+
+        let msg = parse("GET /a b|c^d{}?e f HTTP/1.1\r\nHost: hyper.rs\r\n\r\n").unwrap().unwrap();
+        assert_eq!(msg.head.subject.1, "/a%20b%7Cc%5Ed%7B%7D?e%20f");
+        assert_eq!(msg.head.headers["host"], "hyper.rs");
+
+        let msg = parse("GET /plain HTTP/1.1\r\n\r\n").unwrap().unwrap();
+        assert_eq!(msg.head.subject.1, "/plain");
+
+        // Waits for the whole request-line.
+        assert!(parse("GET /a b HTT").unwrap().is_none());
+
+        Server::parse(&mut BytesMut::from("GET /a{b} HTTP/1.1\r\n\r\n"), ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            config: Default::default(),
+        }).unwrap_err();
+    }
+
+    #[test]
+    fn test_parse_request_record_request_line() {
+        let mut config = ParseConfig::default();
+        config.record_request_line = true;
+        let parse = |raw: &str, config| Server::parse(&mut BytesMut::from(raw), ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            config,
+        });
+
+        let msg = parse("\r\nGET /echo?x HTTP/1.1\r\nHost: hyper.rs\r\n\r\n", config).unwrap().unwrap();
+        let line = msg.head.extensions.get::<RawRequestLine>().expect("raw request line");
+        assert_eq!(line.as_bytes(), b"GET /echo?x HTTP/1.1");
+
+        let msg = parse("GET / HTTP/1.0\n\n", config).unwrap().unwrap();
+        let line = msg.head.extensions.get::<RawRequestLine>().expect("raw request line");
+        assert_eq!(line.as_bytes(), b"GET / HTTP/1.0");
+
+        // Recorded before lenient parsing encodes the target.
+        config.lenient_uri = true;
+        let msg = parse("GET /a b HTTP/1.1\r\n\r\n", config).unwrap().unwrap();
+        assert_eq!(msg.head.subject.1, "/a%20b");
+        let line = msg.head.extensions.get::<RawRequestLine>().expect("raw request line");
+        assert_eq!(line.as_bytes(), b"GET /a b HTTP/1.1");
+
+        let msg = parse("GET / HTTP/1.1\r\n\r\n", Default::default()).unwrap().unwrap();
+        assert!(msg.head.extensions.get::<RawRequestLine>().is_none());
+    }
+
+    #[test]
+    fn test_parse_request_absolute_form() {
+        let mut raw = BytesMut::from(b"GET http://hyper.rs/echo HTTP/1.1\r\n\r\n".to_vec());
+        let msg = Server::parse(&mut raw.clone(), ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            config: Default::default(),
+        }).unwrap().unwrap();
+        assert_eq!(msg.head.subject.1, "http://hyper.rs/echo");
+
+        let mut config = ParseConfig::default();
+        config.absolute_form = false;
+        let err = Server::parse(&mut raw, ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            config,
+        }).unwrap_err();
+        assert_eq!(err, Parse::Uri);
+    }
+
 
     #[test]
     fn test_decoder_request() {
@@ -1040,6 +1740,7 @@ mod tests {
             Server::parse(&mut bytes, ParseContext {
                 cached_headers: &mut None,
                 req_method: &mut None,
+                config: Default::default(),
             })
                 .expect("parse ok")
                 .expect("parse complete")
@@ -1050,6 +1751,7 @@ mod tests {
             Server::parse(&mut bytes, ParseContext {
                 cached_headers: &mut None,
                 req_method: &mut None,
+                config: Default::default(),
             })
                 .expect_err(comment)
         }
@@ -1176,6 +1878,7 @@ mod tests {
             Client::parse(&mut bytes, ParseContext {
                 cached_headers: &mut None,
                 req_method: &mut Some(m),
+                config: Default::default(),
             })
                 .expect("parse ok")
                 .expect("parse complete")
@@ -1186,6 +1889,7 @@ mod tests {
             Client::parse(&mut bytes, ParseContext {
                 cached_headers: &mut None,
                 req_method: &mut Some(Method::GET),
+                config: Default::default(),
             })
                 .expect_err("parse should err")
         }
@@ -1314,11 +2018,489 @@ mod tests {
             keep_alive: true,
             req_method: &mut None,
             title_case_headers: true,
+            preserve_header_case: false,
+            max_uri_len: None,
         }, &mut vec).unwrap();
 
         assert_eq!(vec, b"GET / HTTP/1.1\r\nContent-Length: 10\r\nContent-Type: application/json\r\n\r\n".to_vec());
     }
 
+    #[test]
+    fn test_parse_record_header_case() {
+        let mut config = ParseConfig::default();
+        config.record_header_case = true;
+
+        let mut raw = BytesMut::from(b"GET / HTTP/1.1\r\nHost: hyper.rs\r\nX-FOO: 1\r\nx-Foo: 2\r\n\r\n".to_vec());
+        let msg = Server::parse(&mut raw, ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            config,
+        }).unwrap().unwrap();
+        let case = msg.head.extensions.get::<HeaderCaseMap>().expect("header case");
+        assert_eq!(case.get(&header::HOST, 0), Some(&b"Host"[..]));
+        let foo = HeaderName::from_static("x-foo");
+        assert_eq!(case.get(&foo, 0), Some(&b"X-FOO"[..]));
+        assert_eq!(case.get(&foo, 1), Some(&b"x-Foo"[..]));
+
+        let mut raw = BytesMut::from(b"HTTP/1.1 200 OK\r\nCONTENT-length: 0\r\n\r\n".to_vec());
+        let msg = Client::parse(&mut raw, ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut Some(Method::GET),
+            config,
+        }).unwrap().unwrap();
+        let case = msg.head.extensions.get::<HeaderCaseMap>().expect("header case");
+        assert_eq!(case.get(&header::CONTENT_LENGTH, 0), Some(&b"CONTENT-length"[..]));
+
+        let mut raw = BytesMut::from(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n".to_vec());
+        let msg = Client::parse(&mut raw, ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut Some(Method::GET),
+            config: Default::default(),
+        }).unwrap().unwrap();
+        assert!(msg.head.extensions.get::<HeaderCaseMap>().is_none());
+    }
+
+    #[test]
+    fn test_parse_response_obs_fold() {
+        let raw = b"HTTP/1.1 200 OK\r\nX-Folded: a\r\n b\r\n\tc\r\nContent-Length: 0\r\n\r\n";
+
+        let err = Client::parse(&mut BytesMut::from(raw.to_vec()), ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut Some(Method::GET),
+            config: Default::default(),
+        }).unwrap_err();
+        assert_eq!(err, Parse::Header);
+
+        let mut config = ParseConfig::default();
+        config.obs_fold = true;
+        let msg = Client::parse(&mut BytesMut::from(raw.to_vec()), ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut Some(Method::GET),
+            config,
+        }).unwrap().unwrap();
+        assert_eq!(msg.head.headers["x-folded"], "a   b  \tc");
+        assert_eq!(msg.head.headers["content-length"], "0");
+    }
+
+    #[test]
+    fn test_parse_lenient_toggles() {
+        fn client(raw: &[u8], config: ParseConfig) -> Result<ParsedMessage<StatusCode>, Parse> {
+            Client::parse(&mut BytesMut::from(raw.to_vec()), ParseContext {
+                cached_headers: &mut None,
+                req_method: &mut Some(Method::GET),
+                config,
+            }).map(|msg| msg.unwrap())
+        }
+
+        let strict = ParseConfig {
+            missing_reason: false,
+            lf_line_endings: false,
+            ..ParseConfig::default()
+        };
+
+        let spaces = b"HTTP/1.1 200 OK\r\nContent-Length : 0\r\n\r\n";
+        assert_eq!(client(spaces, ParseConfig::default()).unwrap_err(), Parse::Header);
+        let msg = client(spaces, ParseConfig {
+            spaces_after_header_name: true,
+            ..ParseConfig::default()
+        }).unwrap();
+        assert_eq!(msg.head.headers["content-length"], "0");
+
+        let no_reason = b"HTTP/1.1 200\r\nContent-Length: 0\r\n\r\n";
+        client(no_reason, ParseConfig::default()).unwrap();
+        assert_eq!(client(no_reason, strict).unwrap_err(), Parse::Status);
+        client(b"HTTP/1.1 200 \r\nContent-Length: 0\r\n\r\n", strict).unwrap();
+
+        let lf = b"HTTP/1.1 200 OK\nContent-Length: 0\n\n";
+        client(lf, ParseConfig::default()).unwrap();
+        assert_eq!(client(lf, strict).unwrap_err(), Parse::Header);
+
+        let lf = b"GET / HTTP/1.1\r\nHost: hyper.rs\n\r\n";
+        let server = |config| Server::parse(&mut BytesMut::from(lf.to_vec()), ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            config,
+        }).map(|msg| msg.unwrap());
+        server(ParseConfig::default()).unwrap();
+        assert_eq!(server(strict).unwrap_err(), Parse::Header);
+    }
+
+    #[test]
+    fn test_parse_require_host() {
+        fn server(raw: &str, require_host: bool) -> Result<ParsedMessage<RequestLine>, Parse> {
+            Server::parse(&mut BytesMut::from(raw), ParseContext {
+                cached_headers: &mut None,
+                req_method: &mut None,
+                config: ParseConfig {
+                    require_host,
+                    ..ParseConfig::default()
+                },
+            }).map(|msg| msg.unwrap())
+        }
+
+        let missing = "GET / HTTP/1.1\r\n\r\n";
+        let twice = "GET / HTTP/1.1\r\nhost: a\r\nhost: b\r\n\r\n";
+        server(missing, false).unwrap();
+        server(twice, false).unwrap();
+        assert_eq!(server(missing, true).unwrap_err(), Parse::Header);
+        assert_eq!(server(twice, true).unwrap_err(), Parse::Header);
+        server("GET / HTTP/1.1\r\nhost: a\r\n\r\n", true).unwrap();
+        // HTTP/1.0 doesn't need one
+        server("GET / HTTP/1.0\r\n\r\n", true).unwrap();
+    }
+
+    #[test]
+    fn test_parse_framing_policy() {
+        fn server(raw: &str, framing: FramingPolicy) -> Result<ParsedMessage<RequestLine>, Parse> {
+            Server::parse(&mut BytesMut::from(raw), ParseContext {
+                cached_headers: &mut None,
+                req_method: &mut None,
+                config: ParseConfig {
+                    framing,
+                    ..ParseConfig::default()
+                },
+            }).map(|msg| msg.unwrap())
+        }
+
+        fn client(raw: &str, framing: FramingPolicy) -> Result<ParsedMessage<StatusCode>, Parse> {
+            Client::parse(&mut BytesMut::from(raw), ParseContext {
+                cached_headers: &mut None,
+                req_method: &mut Some(Method::GET),
+                config: ParseConfig {
+                    framing,
+                    ..ParseConfig::default()
+                },
+            }).map(|msg| msg.unwrap())
+        }
+
+        let both = "POST / HTTP/1.1\r\ncontent-length: 10\r\ntransfer-encoding: chunked\r\n\r\n";
+        let duplicate = "POST / HTTP/1.1\r\ncontent-length: 10\r\ncontent-length: 10\r\n\r\n";
+        let unknown = "POST / HTTP/1.1\r\ntransfer-encoding: foo, chunked\r\n\r\n";
+        for raw in &[both, duplicate, unknown] {
+            assert_eq!(server(raw, FramingPolicy::Reject).unwrap_err(), Parse::Header, "{:?}", raw);
+        }
+
+        let msg = server(both, FramingPolicy::Normalize).unwrap();
+        assert_eq!(msg.decode, Decode::Normal(Decoder::chunked()));
+        assert!(!msg.head.headers.contains_key(header::CONTENT_LENGTH));
+        let normalized = msg.head.extensions.get::<FramingNormalization>().unwrap();
+        assert!(normalized.content_length_removed());
+        assert!(!normalized.content_length_merged());
+
+        let msg = server(duplicate, FramingPolicy::Normalize).unwrap();
+        assert_eq!(msg.head.headers.get_all(header::CONTENT_LENGTH).iter().count(), 1);
+        assert!(msg.head.extensions.get::<FramingNormalization>().unwrap().content_length_merged());
+
+        let msg = server(unknown, FramingPolicy::Normalize).unwrap();
+        assert!(msg.head.extensions.get::<FramingNormalization>().unwrap().unknown_transfer_coding());
+
+        // The length is dropped for a transfer-encoding, which then has
+        // to end with chunked.
+        let gzip = "POST / HTTP/1.1\r\ncontent-length: 10\r\ntransfer-encoding: gzip\r\n\r\n";
+        assert_eq!(server(gzip, FramingPolicy::Normalize).unwrap_err(), Parse::Header);
+        let chunked_gzip = "POST / HTTP/1.1\r\ntransfer-encoding: chunked\r\ntransfer-encoding: gzip\r\n\r\n";
+        assert_eq!(server(chunked_gzip, FramingPolicy::Normalize).unwrap_err(), Parse::Header);
+        let gzip_chunked = "POST / HTTP/1.1\r\ncontent-length: 10\r\ntransfer-encoding: gzip\r\ntransfer-encoding: chunked\r\n\r\n";
+        let msg = server(gzip_chunked, FramingPolicy::Normalize).unwrap();
+        assert_eq!(msg.decode, Decode::Normal(Decoder::chunked()));
+        assert!(!msg.head.headers.contains_key(header::CONTENT_LENGTH));
+
+        // Differing lengths can't be normalized.
+        let differing = "POST / HTTP/1.1\r\ncontent-length: 10\r\ncontent-length: 11\r\n\r\n";
+        assert_eq!(server(differing, FramingPolicy::Normalize).unwrap_err(), Parse::Header);
+
+        let msg = server("POST / HTTP/1.1\r\ncontent-length: 10\r\n\r\n", FramingPolicy::Reject).unwrap();
+        assert!(msg.head.extensions.get::<FramingNormalization>().is_none());
+
+        let both = "HTTP/1.1 200 OK\r\ncontent-length: 10\r\ntransfer-encoding: chunked\r\n\r\n";
+        assert_eq!(client(both, FramingPolicy::Reject).unwrap_err(), Parse::Header);
+        let msg = client(both, FramingPolicy::Normalize).unwrap();
+        assert_eq!(msg.decode, Decode::Normal(Decoder::chunked()));
+        assert!(!msg.head.headers.contains_key(header::CONTENT_LENGTH));
+        assert!(msg.head.extensions.get::<FramingNormalization>().unwrap().content_length_removed());
+
+        let duplicate = "HTTP/1.1 200 OK\r\ncontent-length: 10\r\ncontent-length: 10\r\n\r\n";
+        assert_eq!(client(duplicate, FramingPolicy::Reject).unwrap_err(), Parse::Header);
+        let msg = client(duplicate, FramingPolicy::Normalize).unwrap();
+        assert_eq!(msg.decode, Decode::Normal(Decoder::length(10)));
+        assert_eq!(msg.head.headers.get_all(header::CONTENT_LENGTH).iter().count(), 1);
+        assert!(msg.head.extensions.get::<FramingNormalization>().unwrap().content_length_merged());
+    }
+
+    #[test]
+    fn test_parse_max_request_line() {
+        let mut config = ParseConfig::default();
+        config.max_request_line = Some("GET /12345 HTTP/1.1".len());
+        let parse = |raw: &str| Server::parse(&mut BytesMut::from(raw), ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            config,
+        });
+
+        parse("GET /12345 HTTP/1.1\r\n\r\n").unwrap().unwrap();
+        assert_eq!(parse("GET /123456 HTTP/1.1\r\n\r\n").unwrap_err(), Parse::UriTooLong);
+        // Rejected before the request-line is complete.
+        assert_eq!(parse("GET /1234567890123456").unwrap_err(), Parse::UriTooLong);
+        assert!(parse("GET /12345").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_client_request_encode_max_uri_len() {
+        let encode = |uri: &str, max_uri_len| {
+            let mut head = MessageHead::<RequestLine>::default();
+            head.subject.1 = uri.parse().unwrap();
+            let mut vec = Vec::new();
+            Client::encode(Encode {
+                head: &mut head,
+                body: None,
+                keep_alive: true,
+                req_method: &mut None,
+                title_case_headers: false,
+                preserve_header_case: false,
+                max_uri_len,
+            }, &mut vec).map(|_| vec)
+        };
+
+        encode("/foo?bar", Some(8)).unwrap();
+        assert!(encode("/foo?barr", Some(8)).unwrap_err().is_user());
+        encode("http://hyper.rs/foo", Some(19)).unwrap();
+        assert!(encode("http://hyper.rs/foo", Some(18)).unwrap_err().is_user());
+        encode("/".repeat(100).as_str(), None).unwrap();
+
+        for uri in &["/foo?bar", "http://hyper.rs/foo", "http://hyper.rs", "hyper.rs:443"] {
+            let uri = uri.parse().unwrap();
+            assert_eq!(uri_len(&uri), uri.to_string().len(), "{}", uri);
+        }
+    }
+
+    #[test]
+    fn test_parse_record_header_order() {
+        let mut config = ParseConfig::default();
+        config.record_header_order = true;
+
+        let mut raw = BytesMut::from(b"GET / HTTP/1.1\r\nX-Foo: 1\r\nHost: hyper.rs\r\nx-foo: 2\r\n\r\n".to_vec());
+        let msg = Server::parse(&mut raw, ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut None,
+            config,
+        }).unwrap().unwrap();
+        let foo = HeaderName::from_static("x-foo");
+        let order = msg.head.extensions.get::<HeaderOrder>().expect("header order");
+        assert_eq!(order.names(), &[foo.clone(), header::HOST, foo][..]);
+
+        let mut raw = BytesMut::from(b"HTTP/1.1 200 OK\r\nX-Foo: 1\r\nContent-Length: 0\r\n\r\n".to_vec());
+        let msg = Client::parse(&mut raw, ParseContext {
+            cached_headers: &mut None,
+            req_method: &mut Some(Method::GET),
+            config,
+        }).unwrap().unwrap();
+        let order = msg.head.extensions.get::<HeaderOrder>().expect("header order");
+        assert_eq!(order.names(), &[HeaderName::from_static("x-foo"), header::CONTENT_LENGTH][..]);
+    }
+
+    #[test]
+    fn test_client_request_encode_header_order() {
+        use http::header::HeaderValue;
+
+        let mut head = MessageHead::default();
+        head.headers.insert("x-foo", HeaderValue::from_static("1"));
+        head.headers.insert("host", HeaderValue::from_static("hyper.rs"));
+        head.headers.append("x-foo", HeaderValue::from_static("2"));
+        head.headers.append("x-foo", HeaderValue::from_static("3"));
+        head.headers.insert("x-bar", HeaderValue::from_static("added"));
+
+        let foo = HeaderName::from_static("x-foo");
+        let mut order = HeaderOrder::new();
+        order.push(foo.clone());
+        order.push(header::HOST);
+        order.push(foo.clone());
+        // No longer in the headers, so skipped.
+        order.push(HeaderName::from_static("x-removed"));
+        head.extensions.insert(order);
+
+        let mut vec = Vec::new();
+        Client::encode(Encode {
+            head: &mut head,
+            body: None,
+            keep_alive: true,
+            req_method: &mut None,
+            title_case_headers: false,
+            preserve_header_case: false,
+            max_uri_len: None,
+        }, &mut vec).unwrap();
+
+        assert_eq!(
+            ::std::str::from_utf8(&vec).unwrap(),
+            "GET / HTTP/1.1\r\nx-foo: 1\r\nhost: hyper.rs\r\nx-foo: 2\r\nx-foo: 3\r\nx-bar: added\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_client_request_encode_preserve_header_case() {
+        use http::header::HeaderValue;
+        use proto::BodyLength;
+
+        let mut head = MessageHead::default();
+        head.headers.insert("content-length", HeaderValue::from_static("10"));
+        head.headers.append("x-foo", HeaderValue::from_static("1"));
+        head.headers.append("x-foo", HeaderValue::from_static("2"));
+        let mut case = HeaderCaseMap::new();
+        case.append("X-foo").unwrap();
+        case.append("X-FOO").unwrap();
+        head.extensions.insert(case);
+
+        let mut vec = Vec::new();
+        Client::encode(Encode {
+            head: &mut head,
+            body: Some(BodyLength::Known(10)),
+            keep_alive: true,
+            req_method: &mut None,
+            title_case_headers: true,
+            preserve_header_case: true,
+            max_uri_len: None,
+        }, &mut vec).unwrap();
+
+        assert_eq!(vec, b"GET / HTTP/1.1\r\nContent-Length: 10\r\nX-foo: 1\r\nX-FOO: 2\r\n\r\n".to_vec());
+    }
+
+    #[test]
+    fn test_server_response_encode_preserve_header_case() {
+        use http::header::HeaderValue;
+        use proto::BodyLength;
+
+        let mut head = MessageHead::default();
+        head.headers.insert("x-foo", HeaderValue::from_static("bar"));
+        head.headers.insert("date", HeaderValue::from_static("Thu, 01 Jan 1970 00:00:00 GMT"));
+        let mut case = HeaderCaseMap::new();
+        case.append("X-Foo").unwrap();
+        case.append("Content-Length").unwrap();
+        head.extensions.insert(case);
+
+        let mut vec = Vec::new();
+        S::<NoUpgrades>::encode(Encode {
+            head: &mut head,
+            body: Some(BodyLength::Known(5)),
+            keep_alive: true,
+            req_method: &mut None,
+            title_case_headers: false,
+            preserve_header_case: true,
+            max_uri_len: None,
+        }, &mut vec).unwrap();
+
+        assert_eq!(
+            ::std::str::from_utf8(&vec).unwrap(),
+            "HTTP/1.1 200 OK\r\nX-Foo: bar\r\ndate: Thu, 01 Jan 1970 00:00:00 GMT\r\nContent-Length: 5\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_server_response_encode_framing() {
+        use http::header::HeaderValue;
+        use proto::BodyLength;
+
+        fn encode(framing: Framing, body: BodyLength, headers: &[(&'static str, &'static str)]) -> (String, Encoder) {
+            let mut head = MessageHead::default();
+            head.headers.insert("date", HeaderValue::from_static("Thu, 01 Jan 1970 00:00:00 GMT"));
+            for &(name, value) in headers {
+                head.headers.insert(name, HeaderValue::from_static(value));
+            }
+            head.extensions.insert(framing);
+            let mut vec = Vec::new();
+            let encoder = S::<NoUpgrades>::encode(Encode {
+                head: &mut head,
+                body: Some(body),
+                keep_alive: true,
+                req_method: &mut None,
+                title_case_headers: false,
+                preserve_header_case: false,
+                max_uri_len: None,
+            }, &mut vec).unwrap();
+            (String::from_utf8(vec).unwrap(), encoder)
+        }
+
+        let (head, encoder) = encode(Framing::Chunked, BodyLength::Known(5), &[("content-length", "5")]);
+        assert_eq!(head, "HTTP/1.1 200 OK\r\ndate: Thu, 01 Jan 1970 00:00:00 GMT\r\ntransfer-encoding: chunked\r\n\r\n");
+        assert!(encoder.is_chunked());
+
+        let (head, encoder) = encode(Framing::CloseDelimited, BodyLength::Unknown, &[("content-length", "5")]);
+        assert_eq!(head, "HTTP/1.1 200 OK\r\ndate: Thu, 01 Jan 1970 00:00:00 GMT\r\n\r\n");
+        assert_eq!(encoder, Encoder::close_delimited().set_last(true));
+
+        let (head, encoder) = encode(Framing::Length, BodyLength::Known(5), &[("transfer-encoding", "chunked")]);
+        assert_eq!(head, "HTTP/1.1 200 OK\r\ndate: Thu, 01 Jan 1970 00:00:00 GMT\r\ncontent-length: 5\r\n\r\n");
+        assert_eq!(encoder, Encoder::length(5));
+
+        // the length isn't known, so it can't be used
+        let (head, encoder) = encode(Framing::Length, BodyLength::Unknown, &[("transfer-encoding", "chunked")]);
+        assert_eq!(head, "HTTP/1.1 200 OK\r\ndate: Thu, 01 Jan 1970 00:00:00 GMT\r\ntransfer-encoding: chunked\r\n\r\n");
+        assert!(encoder.is_chunked());
+
+        // raw framing writes the headers as given, even conflicting ones
+        let (head, encoder) = encode(Framing::Raw, BodyLength::Known(5), &[("content-length", "3"), ("transfer-encoding", "chunked")]);
+        assert_eq!(head, "HTTP/1.1 200 OK\r\ndate: Thu, 01 Jan 1970 00:00:00 GMT\r\ncontent-length: 3\r\ntransfer-encoding: chunked\r\n\r\n");
+        assert_eq!(encoder, Encoder::raw());
+
+        let (head, encoder) = encode(Framing::Raw, BodyLength::Unknown, &[]);
+        assert_eq!(head, "HTTP/1.1 200 OK\r\ndate: Thu, 01 Jan 1970 00:00:00 GMT\r\n\r\n");
+        assert_eq!(encoder, Encoder::raw().set_last(true));
+    }
+
+    #[test]
+    fn test_client_request_encode_framing() {
+        use http::header::HeaderValue;
+        use proto::BodyLength;
+
+        let mut head: RequestHead = MessageHead::default();
+        head.subject.0 = Method::POST;
+        head.headers.insert("content-length", HeaderValue::from_static("5"));
+        head.extensions.insert(Framing::Chunked);
+        let mut vec = Vec::new();
+        let encoder = Client::encode(Encode {
+            head: &mut head,
+            body: Some(BodyLength::Known(5)),
+            keep_alive: true,
+            req_method: &mut None,
+            title_case_headers: false,
+            preserve_header_case: false,
+            max_uri_len: None,
+        }, &mut vec).unwrap();
+        assert_eq!(vec, b"POST / HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n".to_vec());
+        assert!(encoder.is_chunked());
+
+        let mut head: RequestHead = MessageHead::default();
+        head.subject.0 = Method::POST;
+        head.headers.insert("transfer-encoding", HeaderValue::from_static("chunked"));
+        head.extensions.insert(Framing::Length);
+        let mut vec = Vec::new();
+        let encoder = Client::encode(Encode {
+            head: &mut head,
+            body: Some(BodyLength::Known(5)),
+            keep_alive: true,
+            req_method: &mut None,
+            title_case_headers: false,
+            preserve_header_case: false,
+            max_uri_len: None,
+        }, &mut vec).unwrap();
+        assert_eq!(vec, b"POST / HTTP/1.1\r\ncontent-length: 5\r\n\r\n".to_vec());
+        assert_eq!(encoder, Encoder::length(5));
+
+        let mut head: RequestHead = MessageHead::default();
+        head.subject.0 = Method::POST;
+        head.extensions.insert(Framing::Raw);
+        let mut vec = Vec::new();
+        let encoder = Client::encode(Encode {
+            head: &mut head,
+            body: Some(BodyLength::Known(5)),
+            keep_alive: true,
+            req_method: &mut None,
+            title_case_headers: false,
+            preserve_header_case: false,
+            max_uri_len: None,
+        }, &mut vec).unwrap();
+        assert_eq!(vec, b"POST / HTTP/1.1\r\n\r\n".to_vec());
+        assert_eq!(encoder, Encoder::raw());
+    }
+
     #[test]
     fn test_server_no_upgrades_connect_method() {
         let mut head = MessageHead::default();
@@ -1330,6 +2512,8 @@ mod tests {
             keep_alive: true,
             req_method: &mut Some(Method::CONNECT),
             title_case_headers: false,
+            preserve_header_case: false,
+            max_uri_len: None,
         }, &mut vec).unwrap_err();
 
         assert!(err.is_user());
@@ -1347,6 +2531,8 @@ mod tests {
             keep_alive: true,
             req_method: &mut Some(Method::CONNECT),
             title_case_headers: false,
+            preserve_header_case: false,
+            max_uri_len: None,
         }, &mut vec).unwrap();
 
         assert!(encoder.is_last());
@@ -1385,6 +2571,7 @@ mod tests {
             let msg = Server::parse(&mut raw, ParseContext {
                 cached_headers: &mut headers,
                 req_method: &mut None,
+                config: Default::default(),
             }).unwrap().unwrap();
             headers = Some(msg.head.headers);
             restart(&mut raw, len);
@@ -1413,6 +2600,7 @@ mod tests {
             let msg = Server::parse(&mut raw, ParseContext {
                 cached_headers: &mut headers,
                 req_method: &mut None,
+                config: Default::default(),
             }).unwrap().unwrap();
             headers = Some(msg.head.headers);
             restart(&mut raw, len);
@@ -1450,6 +2638,8 @@ mod tests {
                 keep_alive: true,
                 req_method: &mut Some(Method::GET),
                 title_case_headers: false,
+                preserve_header_case: false,
+                max_uri_len: None,
             }, &mut vec).unwrap();
             assert_eq!(vec.len(), len);
             ::test::black_box(vec);
@@ -1474,6 +2664,8 @@ mod tests {
                 keep_alive: true,
                 req_method: &mut Some(Method::GET),
                 title_case_headers: false,
+                preserve_header_case: false,
+                max_uri_len: None,
             }, &mut vec).unwrap();
             assert_eq!(vec.len(), len);
             ::test::black_box(vec);
@@ -0,0 +1,652 @@
+use std::io::{self, Cursor};
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{Async, Poll};
+use http::{HeaderMap, Method, Request, Response, StatusCode, Version};
+
+use proto::{BodyLength, MessageHead, RequestLine};
+use proxy::FramingPolicy;
+use super::{ClientTransaction, Decode, Decoder, Encode, Encoder, Http1Transaction, ParseConfig, ParseContext, ServerTransaction};
+use super::io::MemRead;
+
+/// Something that happened on the incoming side of an HTTP/1 connection.
+#[derive(Debug)]
+pub enum Event<H> {
+    /// The head of a message, either a `Request<()>` or a `Response<()>`.
+    Head(H),
+    /// Data of the body of the message.
+    Data(Bytes),
+    /// The end of the message, with its trailers, if it had any.
+    End(Option<HeaderMap>),
+}
+
+/// The server side of an HTTP/1 connection, without any IO.
+///
+/// The bytes read from the connection are given to
+/// [`receive`](ServerConn::receive), and [`poll_event`](ServerConn::poll_event)
+/// then parses them into requests. Responses are encoded into the bytes to
+/// write with [`send_head`](ServerConn::send_head),
+/// [`send_data`](ServerConn::send_data) and [`send_end`](ServerConn::send_end).
+///
+/// Once a request and its response are both done, the next request is read,
+/// unless either of them closes the connection.
+#[derive(Debug)]
+pub struct ServerConn {
+    state: State,
+}
+
+/// The client side of an HTTP/1 connection, without any IO.
+///
+/// Requests are encoded into the bytes to write with
+/// [`send_head`](ClientConn::send_head), [`send_data`](ClientConn::send_data)
+/// and [`send_end`](ClientConn::send_end). The bytes read from the
+/// connection are given to [`receive`](ClientConn::receive), and
+/// [`poll_event`](ClientConn::poll_event) then parses the response.
+///
+/// Once a request and its response are both done, the next request can be
+/// sent, unless either of them closes the connection.
+#[derive(Debug)]
+pub struct ClientConn {
+    state: State,
+}
+
+#[derive(Debug)]
+struct State {
+    read_buf: BytesMut,
+    read_eof: bool,
+    reading: Reading,
+    writing: Writing,
+    cached_headers: Option<HeaderMap>,
+    method: Option<Method>,
+    config: ParseConfig,
+    keep_alive: bool,
+    version: Version,
+}
+
+#[derive(Debug)]
+enum Reading {
+    Head,
+    Body(Decoder),
+    Done,
+    Closed,
+}
+
+#[derive(Debug)]
+enum Writing {
+    Head,
+    Body(Encoder),
+    Done,
+    Closed,
+}
+
+/// The read buffer, as seen by a `Decoder`.
+pub(super) struct ReadBuf<'a> {
+    pub(super) buf: &'a mut BytesMut,
+    pub(super) eof: bool,
+}
+
+impl<'a> MemRead for ReadBuf<'a> {
+    fn read_mem(&mut self, len: usize) -> Poll<Bytes, io::Error> {
+        if self.buf.is_empty() {
+            if self.eof {
+                Ok(Async::Ready(Bytes::new()))
+            } else {
+                Ok(Async::NotReady)
+            }
+        } else {
+            let n = ::std::cmp::min(len, self.buf.len());
+            Ok(Async::Ready(self.buf.split_to(n).freeze()))
+        }
+    }
+}
+
+impl State {
+    fn new(config: ParseConfig) -> State {
+        State {
+            read_buf: BytesMut::new(),
+            read_eof: false,
+            reading: Reading::Head,
+            writing: Writing::Head,
+            cached_headers: None,
+            method: None,
+            config: config,
+            keep_alive: true,
+            version: Version::HTTP_11,
+        }
+    }
+
+    fn receive(&mut self, bytes: &[u8]) {
+        self.read_buf.extend_from_slice(bytes);
+    }
+
+    fn poll_head<T: Http1Transaction>(&mut self) -> ::Result<Option<MessageHead<T::Incoming>>> {
+        loop {
+            let msg = match T::parse(&mut self.read_buf, ParseContext {
+                cached_headers: &mut self.cached_headers,
+                req_method: &mut self.method,
+                config: self.config,
+            }) {
+                Ok(Some(msg)) => msg,
+                Ok(None) if self.read_eof => {
+                    self.reading = Reading::Closed;
+                    return if self.read_buf.is_empty() {
+                        Ok(None)
+                    } else {
+                        Err(::Error::new_incomplete())
+                    };
+                },
+                Ok(None) => return Ok(None),
+                Err(err) => {
+                    self.close();
+                    return Err(err.into());
+                },
+            };
+            let decoder = match msg.decode {
+                Decode::Normal(decoder) => decoder,
+                Decode::Final(decoder) => {
+                    self.keep_alive = false;
+                    decoder
+                },
+                Decode::Ignore => continue,
+            };
+            self.version = msg.head.version;
+            self.keep_alive &= msg.keep_alive;
+            // even an empty body is decoded, to report its end
+            self.reading = Reading::Body(decoder);
+            return Ok(Some(msg.head));
+        }
+    }
+
+    fn poll_body(&mut self) -> ::Result<Option<Event<()>>> {
+        let event = match self.reading {
+            Reading::Body(ref mut decoder) => {
+                let mut buf = ReadBuf {
+                    buf: &mut self.read_buf,
+                    eof: self.read_eof,
+                };
+                match decoder.decode(&mut buf) {
+                    Ok(Async::Ready(ref data)) if data.is_empty() && decoder.is_eof() => {
+                        Event::End(decoder.take_trailers())
+                    },
+                    Ok(Async::Ready(data)) => {
+                        if data.is_empty() {
+                            // more of a chunked body's framing is needed
+                            return Ok(None);
+                        }
+                        return Ok(Some(Event::Data(data)));
+                    },
+                    Ok(Async::NotReady) => return Ok(None),
+                    Err(err) => {
+                        self.close();
+                        return Err(::Error::new_body(err));
+                    },
+                }
+            },
+            _ => return Ok(None),
+        };
+        self.reading = Reading::Done;
+        Ok(Some(event))
+    }
+
+    fn send_head<T: Http1Transaction>(&mut self, mut head: MessageHead<T::Outgoing>, body: Option<u64>) -> ::Result<Vec<u8>> {
+        T::update_date();
+        if self.version == Version::HTTP_10 {
+            head.version = Version::HTTP_10;
+        }
+        let mut dst = Vec::new();
+        let no_body = body == Some(0);
+        let encoder = T::encode(Encode {
+            head: &mut head,
+            body: match body {
+                Some(0) => None,
+                Some(len) => Some(BodyLength::Known(len)),
+                None => Some(BodyLength::Unknown),
+            },
+            keep_alive: self.keep_alive,
+            req_method: &mut self.method,
+            title_case_headers: false,
+            preserve_header_case: false,
+            max_uri_len: None,
+        }, &mut dst);
+        match encoder {
+            Ok(encoder) => {
+                self.cached_headers = Some(head.headers);
+                if encoder.is_last() {
+                    self.keep_alive = false;
+                }
+                // without a body, the encoder isn't meant to be used
+                self.writing = Writing::Body(if no_body {
+                    Encoder::length(0)
+                } else {
+                    encoder
+                });
+                Ok(dst)
+            },
+            Err(err) => {
+                self.close();
+                Err(err)
+            },
+        }
+    }
+
+    fn send_data(&mut self, data: &[u8]) -> ::Result<Vec<u8>> {
+        let mut dst = Vec::new();
+        if data.is_empty() {
+            return Ok(dst);
+        }
+        match self.writing {
+            Writing::Body(ref mut encoder) => {
+                match encoder.remaining_length() {
+                    Some(remaining) if data.len() as u64 > remaining => {
+                        return Err(::Error::new_user_body_length(format!(
+                            "body is longer than its content-length, by at least {} bytes",
+                            data.len() as u64 - remaining,
+                        )));
+                    },
+                    _ => (),
+                }
+                extend(&mut dst, encoder.encode(Bytes::from(data)));
+            },
+            _ => panic!("send_data called without a message head"),
+        }
+        Ok(dst)
+    }
+
+    fn send_end(&mut self) -> ::Result<Vec<u8>> {
+        let mut dst = Vec::new();
+        match self.writing {
+            Writing::Body(ref encoder) => match encoder.end::<Cursor<Bytes>>() {
+                Ok(Some(end)) => extend(&mut dst, end),
+                Ok(None) => (),
+                Err(_not_eof) => match encoder.remaining_length() {
+                    Some(remaining) => {
+                        return Err(::Error::new_user_body_length(format!(
+                            "body is shorter than its content-length, by {} bytes",
+                            remaining,
+                        )));
+                    },
+                    // a close-delimited body is ended by closing
+                    None => self.keep_alive = false,
+                },
+            },
+            _ => panic!("send_end called without a message head"),
+        }
+        self.writing = Writing::Done;
+        Ok(dst)
+    }
+
+    /// Starts the next message once both sides are done.
+    fn try_keep_alive(&mut self) {
+        match (&self.reading, &self.writing) {
+            (&Reading::Done, &Writing::Done) => (),
+            _ => return,
+        }
+        // nothing more can be read once the peer closed
+        if self.keep_alive && !self.read_eof {
+            self.reading = Reading::Head;
+            self.writing = Writing::Head;
+            self.method = None;
+        } else {
+            self.close();
+        }
+    }
+
+    fn close(&mut self) {
+        self.reading = Reading::Closed;
+        self.writing = Writing::Closed;
+        self.keep_alive = false;
+    }
+
+    fn is_closed(&self) -> bool {
+        match (&self.reading, &self.writing) {
+            (&Reading::Closed, &Writing::Closed) => true,
+            _ => false,
+        }
+    }
+}
+
+pub(super) fn into_request(head: MessageHead<RequestLine>) -> Request<()> {
+    let mut req = Request::new(());
+    *req.method_mut() = head.subject.0;
+    *req.uri_mut() = head.subject.1;
+    *req.version_mut() = head.version;
+    *req.headers_mut() = head.headers;
+    *req.extensions_mut() = head.extensions;
+    req
+}
+
+pub(super) fn into_response(head: MessageHead<StatusCode>) -> Response<()> {
+    let mut res = Response::new(());
+    *res.status_mut() = head.subject;
+    *res.version_mut() = head.version;
+    *res.headers_mut() = head.headers;
+    *res.extensions_mut() = head.extensions;
+    res
+}
+
+fn extend<B: Buf>(dst: &mut Vec<u8>, mut buf: B) {
+    while buf.has_remaining() {
+        let n = {
+            let bytes = buf.bytes();
+            dst.extend_from_slice(bytes);
+            bytes.len()
+        };
+        buf.advance(n);
+    }
+}
+
+impl ServerConn {
+    /// Creates the server side of a new connection.
+    pub fn new() -> ServerConn {
+        let mut config = ParseConfig::default();
+        config.absolute_form = false;
+        config.framing = FramingPolicy::Reject;
+        ServerConn {
+            state: State::new(config),
+        }
+    }
+
+    /// Gives bytes read from the connection.
+    pub fn receive(&mut self, bytes: &[u8]) {
+        self.state.receive(bytes);
+    }
+
+    /// Tells that the connection was closed for reading.
+    pub fn receive_eof(&mut self) {
+        self.state.read_eof = true;
+    }
+
+    /// Parses the next `Event` of the incoming request.
+    ///
+    /// Returns `None` if more bytes need to be received first, or if the
+    /// request is done and its response wasn't sent yet.
+    pub fn poll_event(&mut self) -> ::Result<Option<Event<Request<()>>>> {
+        if let Reading::Head = self.state.reading {
+            return match self.state.poll_head::<ServerTransaction>()? {
+                Some(head) => Ok(Some(Event::Head(into_request(head)))),
+                None => Ok(None),
+            };
+        }
+        if let Reading::Done = self.state.reading {
+            // the end was already reported
+            return Ok(None);
+        }
+        let event = self.state.poll_body()?;
+        self.state.try_keep_alive();
+        Ok(event.map(without_head))
+    }
+
+    /// Encodes the head of the response, with the length of its body if
+    /// it's known, and otherwise chunked.
+    ///
+    /// # Panics
+    ///
+    /// If no request was received yet, or the response was already started.
+    pub fn send_head(&mut self, res: Response<()>, body_len: Option<u64>) -> ::Result<Vec<u8>> {
+        match (&self.state.reading, &self.state.writing) {
+            (&Reading::Body(_), &Writing::Head) |
+            (&Reading::Done, &Writing::Head) => (),
+            _ => panic!("send_head called without a request to respond to"),
+        }
+        let (parts, ()) = res.into_parts();
+        let head = MessageHead {
+            version: parts.version,
+            subject: parts.status,
+            headers: parts.headers,
+            extensions: parts.extensions,
+        };
+        self.state.send_head::<ServerTransaction>(head, body_len)
+    }
+
+    /// Encodes data of the response body.
+    ///
+    /// # Panics
+    ///
+    /// If the response head wasn't sent.
+    pub fn send_data(&mut self, data: &[u8]) -> ::Result<Vec<u8>> {
+        self.state.send_data(data)
+    }
+
+    /// Encodes the end of the response body.
+    ///
+    /// # Panics
+    ///
+    /// If the response head wasn't sent.
+    pub fn send_end(&mut self) -> ::Result<Vec<u8>> {
+        let end = self.state.send_end()?;
+        self.state.try_keep_alive();
+        Ok(end)
+    }
+
+    /// Returns true if the connection is done, and should be closed.
+    pub fn is_closed(&self) -> bool {
+        self.state.is_closed()
+    }
+}
+
+impl Default for ServerConn {
+    fn default() -> ServerConn {
+        ServerConn::new()
+    }
+}
+
+impl ClientConn {
+    /// Creates the client side of a new connection.
+    pub fn new() -> ClientConn {
+        ClientConn {
+            state: State::new(ParseConfig::default()),
+        }
+    }
+
+    /// Encodes the head of a request, with the length of its body if it's
+    /// known, and otherwise chunked.
+    ///
+    /// # Panics
+    ///
+    /// If the previous request or its response isn't done.
+    pub fn send_head(&mut self, req: Request<()>, body_len: Option<u64>) -> ::Result<Vec<u8>> {
+        match (&self.state.reading, &self.state.writing) {
+            (&Reading::Head, &Writing::Head) => (),
+            _ => panic!("send_head called while a request is in flight"),
+        }
+        let (parts, ()) = req.into_parts();
+        let head = MessageHead {
+            version: parts.version,
+            subject: RequestLine(parts.method, parts.uri),
+            headers: parts.headers,
+            extensions: parts.extensions,
+        };
+        self.state.send_head::<ClientTransaction>(head, body_len)
+    }
+
+    /// Encodes data of the request body.
+    ///
+    /// # Panics
+    ///
+    /// If the request head wasn't sent.
+    pub fn send_data(&mut self, data: &[u8]) -> ::Result<Vec<u8>> {
+        self.state.send_data(data)
+    }
+
+    /// Encodes the end of the request body.
+    ///
+    /// # Panics
+    ///
+    /// If the request head wasn't sent.
+    pub fn send_end(&mut self) -> ::Result<Vec<u8>> {
+        let end = self.state.send_end()?;
+        self.state.try_keep_alive();
+        Ok(end)
+    }
+
+    /// Gives bytes read from the connection.
+    pub fn receive(&mut self, bytes: &[u8]) {
+        self.state.receive(bytes);
+    }
+
+    /// Tells that the connection was closed for reading, which ends a
+    /// response body that is delimited by closing.
+    pub fn receive_eof(&mut self) {
+        self.state.read_eof = true;
+    }
+
+    /// Parses the next `Event` of the response.
+    ///
+    /// Returns `None` if more bytes need to be received first, or if no
+    /// request was sent.
+    pub fn poll_event(&mut self) -> ::Result<Option<Event<Response<()>>>> {
+        if let Reading::Head = self.state.reading {
+            if let Writing::Head = self.state.writing {
+                return Ok(None);
+            }
+            return match self.state.poll_head::<ClientTransaction>()? {
+                Some(head) => Ok(Some(Event::Head(into_response(head)))),
+                None => Ok(None),
+            };
+        }
+        if let Reading::Done = self.state.reading {
+            return Ok(None);
+        }
+        let event = self.state.poll_body()?;
+        self.state.try_keep_alive();
+        Ok(event.map(without_head))
+    }
+
+    /// Returns true if the connection is done, and should be closed.
+    pub fn is_closed(&self) -> bool {
+        self.state.is_closed()
+    }
+}
+
+impl Default for ClientConn {
+    fn default() -> ClientConn {
+        ClientConn::new()
+    }
+}
+
+fn without_head<H>(event: Event<()>) -> Event<H> {
+    match event {
+        Event::Head(()) => unreachable!("poll_body doesn't parse heads"),
+        Event::Data(data) => Event::Data(data),
+        Event::End(trailers) => Event::End(trailers),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{Method, Request, Response, StatusCode};
+
+    use super::{ClientConn, Event, ServerConn};
+
+    #[test]
+    fn server_conn_keep_alive() {
+        let mut conn = ServerConn::new();
+        conn.receive(b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel");
+
+        match conn.poll_event().unwrap() {
+            Some(Event::Head(req)) => {
+                assert_eq!(req.method(), Method::POST);
+                assert_eq!(req.uri(), "/a");
+            },
+            other => panic!("expected head, got {:?}", other),
+        }
+        match conn.poll_event().unwrap() {
+            Some(Event::Data(data)) => assert_eq!(data, "hel"),
+            other => panic!("expected data, got {:?}", other),
+        }
+        assert!(conn.poll_event().unwrap().is_none());
+
+        conn.receive(b"lo\r\n0\r\n\r\nGET /b HTTP/1.1\r\n\r\n");
+        match conn.poll_event().unwrap() {
+            Some(Event::Data(data)) => assert_eq!(data, "lo"),
+            other => panic!("expected data, got {:?}", other),
+        }
+        match conn.poll_event().unwrap() {
+            Some(Event::End(None)) => (),
+            other => panic!("expected end, got {:?}", other),
+        }
+        // the next request waits for the response
+        assert!(conn.poll_event().unwrap().is_none());
+
+        let head = conn.send_head(Response::new(()), None).unwrap();
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", head);
+        assert!(head.contains("transfer-encoding: chunked\r\n"), "{:?}", head);
+        assert_eq!(conn.send_data(b"ok").unwrap(), b"2\r\nok\r\n");
+        assert_eq!(conn.send_end().unwrap(), b"0\r\n\r\n");
+        assert!(!conn.is_closed());
+
+        match conn.poll_event().unwrap() {
+            Some(Event::Head(req)) => assert_eq!(req.uri(), "/b"),
+            other => panic!("expected head, got {:?}", other),
+        }
+        match conn.poll_event().unwrap() {
+            Some(Event::End(None)) => (),
+            other => panic!("expected end, got {:?}", other),
+        }
+        let mut res = Response::new(());
+        *res.status_mut() = StatusCode::NOT_FOUND;
+        res.headers_mut().insert("connection", "close".parse().unwrap());
+        conn.send_head(res, Some(0)).unwrap();
+        conn.send_data(b"").unwrap();
+        conn.send_end().unwrap();
+        assert!(conn.is_closed());
+    }
+
+    #[test]
+    fn server_conn_body_too_long() {
+        let mut conn = ServerConn::new();
+        conn.receive(b"GET / HTTP/1.1\r\n\r\n");
+        conn.poll_event().unwrap();
+        conn.send_head(Response::new(()), Some(2)).unwrap();
+        assert!(conn.send_data(b"abc").unwrap_err().is_user());
+    }
+
+    #[test]
+    fn client_conn_responses() {
+        let mut conn = ClientConn::new();
+        // nothing was sent yet
+        conn.receive(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi");
+        assert!(conn.poll_event().unwrap().is_none());
+
+        let req = Request::get("/").body(()).unwrap();
+        let head = conn.send_head(req, Some(0)).unwrap();
+        assert_eq!(head, b"GET / HTTP/1.1\r\n\r\n");
+        assert!(conn.send_end().unwrap().is_empty());
+
+        match conn.poll_event().unwrap() {
+            Some(Event::Head(res)) => assert_eq!(res.status(), StatusCode::OK),
+            other => panic!("expected head, got {:?}", other),
+        }
+        match conn.poll_event().unwrap() {
+            Some(Event::Data(data)) => assert_eq!(data, "hi"),
+            other => panic!("expected data, got {:?}", other),
+        }
+        match conn.poll_event().unwrap() {
+            Some(Event::End(None)) => (),
+            other => panic!("expected end, got {:?}", other),
+        }
+
+        // a body ended by closing the connection
+        let req = Request::post("/").body(()).unwrap();
+        conn.send_head(req, Some(3)).unwrap();
+        assert_eq!(conn.send_data(b"abc").unwrap(), b"abc");
+        conn.send_end().unwrap();
+        conn.receive(b"HTTP/1.1 200 OK\r\n\r\nuntil eof");
+        match conn.poll_event().unwrap() {
+            Some(Event::Head(_)) => (),
+            other => panic!("expected head, got {:?}", other),
+        }
+        match conn.poll_event().unwrap() {
+            Some(Event::Data(data)) => assert_eq!(data, "until eof"),
+            other => panic!("expected data, got {:?}", other),
+        }
+        assert!(conn.poll_event().unwrap().is_none());
+        conn.receive_eof();
+        match conn.poll_event().unwrap() {
+            Some(Event::End(None)) => (),
+            other => panic!("expected end, got {:?}", other),
+        }
+        assert!(conn.is_closed());
+    }
+}
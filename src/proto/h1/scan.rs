@@ -0,0 +1,82 @@
+//! Fast byte scanning for the request and response heads.
+//!
+//! `httparse` already vectorizes the header parsing itself; these cover the
+//! extra passes over a head, such as looking for line endings, a word at a
+//! time instead of a byte at a time.
+
+use std::mem;
+use std::ptr;
+
+const WORD: usize = mem::size_of::<usize>();
+const LO: usize = ::std::usize::MAX / 0xFF;
+const HI: usize = LO << 7;
+
+// Whether any byte of `x` is zero.
+#[inline]
+fn has_zero_byte(x: usize) -> bool {
+    x.wrapping_sub(LO) & !x & HI != 0
+}
+
+/// The index of the first `needle` in `haystack`.
+#[inline]
+pub(super) fn find(needle: u8, haystack: &[u8]) -> Option<usize> {
+    let repeated = LO * needle as usize;
+    let mut i = 0;
+    while i + WORD <= haystack.len() {
+        // Unsafe: `i + WORD` is in bounds, and the read doesn't need to be
+        // aligned.
+        let word = unsafe {
+            ptr::read_unaligned(haystack.as_ptr().offset(i as isize) as *const usize)
+        };
+        if has_zero_byte(word ^ repeated) {
+            break;
+        }
+        i += WORD;
+    }
+    haystack[i..].iter().position(|&b| b == needle).map(|pos| i + pos)
+}
+
+/// Whether `head` has a `\n` not preceded by a `\r`.
+pub(super) fn has_bare_lf(head: &[u8]) -> bool {
+    let mut from = 0;
+    while let Some(pos) = find(b'\n', &head[from..]) {
+        let i = from + pos;
+        if i == 0 || head[i - 1] != b'\r' {
+            return true;
+        }
+        from = i + 1;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, has_bare_lf};
+
+    #[test]
+    fn find_matches_position() {
+        let haystack = b"GET /some/long/path HTTP/1.1\r\nHost: example.domain\r\n\r\n";
+        for len in 0..haystack.len() {
+            let bytes = &haystack[..len];
+            for &needle in &[b'\n', b'\r', b' ', b'G', b':', 0] {
+                assert_eq!(
+                    find(needle, bytes),
+                    bytes.iter().position(|&b| b == needle),
+                    "find({:?}) in {:?}", needle, bytes
+                );
+            }
+        }
+        // bytes with the high bit set can't be mistaken for a match
+        assert_eq!(find(b'\n', &[0x8A; 32]), None);
+        assert_eq!(find(0x8A, b"\n\n\n\n\n\n\n\n\n\n\x8A"), Some(10));
+    }
+
+    #[test]
+    fn bare_lf() {
+        assert!(!has_bare_lf(b""));
+        assert!(!has_bare_lf(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n"));
+        assert!(has_bare_lf(b"\n"));
+        assert!(has_bare_lf(b"GET / HTTP/1.1\r\nHost: a\n\r\n"));
+        assert!(has_bare_lf(b"GET / HTTP/1.1\r\nHost: a\r\n\n"));
+    }
+}
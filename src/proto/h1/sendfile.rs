@@ -0,0 +1,177 @@
+//! Sending file bodies with `sendfile`, from the file to a TCP socket.
+//!
+//! Only bodies made by `Body::from_file` give up their file, and only
+//! plaintext TCP transports are written to this way, found by downcasting
+//! the transport. Anything else, such as TLS, is written through the
+//! buffers as usual.
+
+use std::any::Any;
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+
+use futures::{Async, Poll};
+use libc;
+use tokio_tcp::TcpStream;
+
+/// The most bytes sent by a single call, so a large file doesn't hold up
+/// the other connections of the thread.
+const MAX_SEND: usize = 1024 * 1024;
+/// The size of the bytes written through the stream, when the socket
+/// stops being writable.
+const PROBE_SIZE: usize = 8192;
+
+/// The rest of a file to send.
+pub(crate) struct SendFile {
+    file: File,
+    offset: u64,
+    remaining: u64,
+}
+
+impl SendFile {
+    /// Sends `len` bytes of `file`, from its current position.
+    pub(crate) fn new(mut file: File, len: u64) -> io::Result<SendFile> {
+        let offset = file.seek(SeekFrom::Current(0))?;
+        Ok(SendFile {
+            file: file,
+            offset: offset,
+            remaining: len,
+        })
+    }
+
+    pub(crate) fn remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    /// Sends the rest of the file to `tcp`, until it's not writable.
+    ///
+    /// This is ready once the file was sent, or ended early.
+    pub(crate) fn poll_send(&mut self, tcp: &TcpStream) -> Poll<(), io::Error> {
+        while self.remaining > 0 {
+            try_ready!(tcp.poll_write_ready());
+            let mut offset = self.offset as libc::off_t;
+            let len = cmp::min(self.remaining, MAX_SEND as u64) as usize;
+            let n = unsafe {
+                libc::sendfile(tcp.as_raw_fd(), self.file.as_raw_fd(), &mut offset, len)
+            };
+            if n < 0 {
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    io::ErrorKind::WouldBlock => {
+                        if try_ready!(self.probe(tcp)) == 0 {
+                            return Ok(Async::Ready(()));
+                        }
+                        continue;
+                    },
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(err),
+                }
+            } else if n == 0 {
+                trace!("sendfile reached the end of the file, {} bytes short", self.remaining);
+                return Ok(Async::Ready(()));
+            }
+            trace!("sendfile sent {} bytes", n);
+            self.advance(n as u64);
+        }
+        Ok(Async::Ready(()))
+    }
+
+    // The stream still thinks the socket is writable, and only a write to
+    // it that would block resets that and waits for it to be writable
+    // again. So the next bytes are written through it instead.
+    fn probe(&mut self, mut tcp: &TcpStream) -> Poll<usize, io::Error> {
+        let mut buf = [0; PROBE_SIZE];
+        let len = cmp::min(self.remaining, PROBE_SIZE as u64) as usize;
+        let n = unsafe {
+            libc::pread(
+                self.file.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                len,
+                self.offset as libc::off_t,
+            )
+        };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        } else if n == 0 {
+            return Ok(Async::Ready(0));
+        }
+        match tcp.write(&buf[..n as usize]) {
+            Ok(n) => {
+                self.advance(n as u64);
+                Ok(Async::Ready(n))
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn advance(&mut self, n: u64) {
+        self.offset += n;
+        self.remaining -= n;
+    }
+}
+
+/// The TCP stream of a transport, if it's plaintext TCP.
+pub(crate) fn tcp_stream<I: 'static>(io: &I) -> Option<&TcpStream> {
+    let io = io as &Any;
+    ::server::tcp_stream(io).or_else(|| io.downcast_ref::<TcpStream>())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use futures::{Async, Future};
+    use futures::future;
+    use tokio::runtime::Runtime;
+    use tokio_tcp::TcpStream;
+
+    use super::SendFile;
+
+    #[test]
+    fn send_file_while_socket_fills_up() {
+        let data = (0..1024 * 1024).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+        let path = ::std::env::temp_dir().join("hyper-send-file-while-socket-fills-up");
+        File::create(&path).unwrap().write_all(&data).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let reader = thread::spawn(move || {
+            let (mut tcp, _) = listener.accept().unwrap();
+            let mut buf = Vec::new();
+            let mut chunk = [0; 4096];
+            loop {
+                // slowly, so each send waits on the socket to be writable
+                thread::sleep(Duration::from_millis(1));
+                match tcp.read(&mut chunk).unwrap() {
+                    0 => return buf,
+                    n => buf.extend_from_slice(&chunk[..n]),
+                }
+            }
+        });
+
+        let mut rt = Runtime::new().unwrap();
+        let mut file = File::open(&path).unwrap();
+        // starts from the position of the file
+        file.read_exact(&mut [0; 10]).unwrap();
+        let mut send_file = SendFile::new(file, data.len() as u64 - 10).unwrap();
+        let remaining = rt.block_on(TcpStream::connect(&addr).and_then(move |tcp| {
+            tcp.set_send_buffer_size(4096).unwrap();
+            future::poll_fn(move || {
+                try_ready!(send_file.poll_send(&tcp));
+                Ok(Async::Ready(send_file.remaining()))
+            })
+        }));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(remaining.unwrap(), 0);
+
+        let read = reader.join().unwrap();
+        assert_eq!(read.len(), data.len() - 10);
+        assert!(&read[..] == &data[10..], "bytes sent out of order");
+    }
+}
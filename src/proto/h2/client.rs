@@ -1,17 +1,25 @@
 use bytes::IntoBuf;
 use futures::{Async, Future, Poll, Stream};
 use futures::future::{self, Either};
-use futures::sync::mpsc;
-use h2::client::{Builder, Handshake, SendRequest};
+use futures::sync::{mpsc, oneshot};
+use h2::client::{Builder, Handshake, ResponseFuture, SendRequest};
 use tokio_io::{AsyncRead, AsyncWrite};
 
 use body::Payload;
-use ::common::{Exec, Never};
+use client::CancelHandle;
+use client::cancel::CancelWatch;
+use ext::Timings;
+use ::common::{Budget, Exec, Never};
+use ::common::slab::Slab;
+use ::common::span::{Instrumented, Span};
+use ::exec::TaskKind;
+use ::instrument::{ConnectionGuard, Instrument, RequestGuard};
 use headers;
-use super::{PipeToSendStream, SendBuf};
+use super::{BodyChunks, PipeToSendStream, SendBuf, StreamReset};
 use ::{Body, Request, Response};
 
 type ClientRx<B> = ::client::dispatch::Receiver<Request<B>, Response<Body>>;
+type Callback<B> = ::client::dispatch::Callback<Request<B>, Response<Body>>;
 /// An mpsc channel is used to help notify the `Connection` task when *all*
 /// other handles to it have been dropped, so that it can shutdown.
 type ConnDropRef = mpsc::Sender<Never>;
@@ -20,8 +28,25 @@ pub struct Client<T, B>
 where
     B: Payload,
 {
+    body_chunks: BodyChunks,
+    /// Bounds the requests sent in a single poll.
+    budget: Budget,
+    cancel_dropped_requests: bool,
+    /// Reports the connection closed once dropped, from when its
+    /// handshake finished.
+    _connection: Option<ConnectionGuard>,
     executor: Exec,
+    instrument: Option<Instrument>,
+    /// The request bodies and responses, when they are polled by the
+    /// connection instead of being spawned.
+    local_streams: Option<Slab<Instrumented<StreamTask<B>>>>,
+    /// Whether `rx` was closed, with streams still left in `local_streams`.
+    requests_done: bool,
     rx: ClientRx<B>,
+    /// Whether h2 holds back the last request sent, as the peer's limit of
+    /// concurrent streams was reached.
+    saturated: bool,
+    span: Span,
     state: State<T, SendBuf<B::Data>>,
 }
 
@@ -35,18 +60,43 @@ where
     T: AsyncRead + AsyncWrite + Send + 'static,
     B: Payload,
 {
-    pub(crate) fn new(io: T, rx: ClientRx<B>, exec: Exec) -> Client<T, B> {
+    pub(crate) fn new(io: T, rx: ClientRx<B>, exec: Exec, instrument: Option<Instrument>) -> Client<T, B> {
         let handshake = Builder::new()
             // we don't expose PUSH promises yet
             .enable_push(false)
             .handshake(io);
 
         Client {
+            body_chunks: BodyChunks::default(),
+            budget: Budget::new(),
+            cancel_dropped_requests: true,
+            _connection: None,
             executor: exec,
+            instrument,
+            local_streams: None,
+            requests_done: false,
             rx: rx,
+            saturated: false,
+            span: Span::connection("client", "h2"),
             state: State::Handshaking(handshake),
         }
     }
+
+    pub(crate) fn set_body_chunks(&mut self, chunks: BodyChunks) {
+        self.body_chunks = chunks;
+    }
+
+    pub(crate) fn set_cancel_dropped_requests(&mut self, enabled: bool) {
+        self.cancel_dropped_requests = enabled;
+    }
+
+    pub(crate) fn set_spawn_streams(&mut self, enabled: bool) {
+        self.local_streams = if enabled {
+            None
+        } else {
+            Some(Slab::new())
+        };
+    }
 }
 
 impl<T, B> Future for Client<T, B>
@@ -58,6 +108,34 @@ where
     type Error = ::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let span = self.span.clone();
+        let _entered = span.enter();
+        if !self.requests_done {
+            if let Async::Ready(()) = self.poll_requests()? {
+                self.requests_done = true;
+            }
+        }
+        // the connection is only done once its streams are
+        if let Some(ref mut local) = self.local_streams {
+            if local.poll().is_not_ready() {
+                return Ok(Async::NotReady);
+            }
+        }
+        if self.requests_done {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+impl<T, B> Client<T, B>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+    B: Payload + 'static,
+{
+    fn poll_requests(&mut self) -> Poll<(), ::Error> {
+        self.budget.reset();
         loop {
             let next = match self.state {
                 State::Handshaking(ref mut h) => {
@@ -92,26 +170,53 @@ where
                             }
                             Err(Either::B((never, _))) => match never {},
                         });
-                    self.executor.execute(fut);
+                    self.executor.execute(TaskKind::Connection, self.span.clone().instrument(fut));
+                    self._connection = self.instrument.as_ref().map(Instrument::connection);
                     State::Ready(request_tx, tx)
                 },
                 State::Ready(ref mut tx, ref conn_dropper) => {
-                    try_ready!(tx.poll_ready().map_err(::Error::new_h2));
+                    if !self.budget.spend() {
+                        return Ok(Async::NotReady);
+                    }
+                    let ready = tx.poll_ready().map_err(::Error::new_h2)?;
+                    if ready.is_ready() == self.saturated {
+                        self.saturated = !ready.is_ready();
+                        self.rx.set_saturated(self.saturated);
+                    }
+                    if ready.is_not_ready() {
+                        return Ok(Async::NotReady);
+                    }
                     match self.rx.poll() {
                         Ok(Async::Ready(Some((req, mut cb)))) => {
                             // check that future hasn't been canceled already
                             if let Async::Ready(()) = cb.poll_cancel().expect("poll_cancel cannot error") {
-                                trace!("request canceled");
+                                if self.cancel_dropped_requests {
+                                    trace!("request canceled");
+                                    continue;
+                                }
+                            }
+                            let (mut head, body) = req.into_parts();
+                            let cancel = head.extensions.remove::<CancelWatch>().map(|watch| watch.0);
+                            let timings = head.extensions.get::<Timings>().cloned();
+                            if cancel.as_ref().map(CancelHandle::is_canceled).unwrap_or(false) {
+                                trace!("request canceled before it was sent");
+                                let _ = cb.send(Err((::Error::new_request_canceled(), None)));
                                 continue;
                             }
-                            let (head, body) = req.into_parts();
-                            let mut req = ::http::Request::from_parts(head, ());
-                            super::strip_connection_headers(req.headers_mut());
-                            if let Some(len) = body.content_length() {
-                                headers::set_content_length_if_missing(req.headers_mut(), len);
+                            ::proxy::strip_connection_headers(::http::Version::HTTP_2, &mut head.headers);
+                            if let Some(len) = body.size_hint().exact() {
+                                headers::set_content_length_if_missing(&mut head.headers, len);
                             }
+                            let content_length = headers::content_length_parse_all(&head.headers);
                             let eos = body.is_end_stream();
-                            let (fut, body_tx) = match tx.send_request(req, eos) {
+                            let request = self.instrument.as_ref()
+                                .map(|instrument| instrument.request(&head.method));
+                            let span = Span::request(&head.method, &head.uri);
+                            // h2 takes the head, so the request can't be
+                            // returned if sending it fails. Having just been
+                            // ready, the connection only fails a request it
+                            // can't send at all.
+                            let (fut, body_tx) = match tx.send_request(::http::Request::from_parts(head, ()), eos) {
                                 Ok(ok) => ok,
                                 Err(err) => {
                                     debug!("client send request error: {}", err);
@@ -119,32 +224,36 @@ where
                                     continue;
                                 }
                             };
-                            if !eos {
-                                let conn_drop_ref = conn_dropper.clone();
-                                let pipe = PipeToSendStream::new(body, body_tx)
-                                    .map_err(|e| debug!("client request body error: {}", e))
-                                    .then(move |x| {
-                                        drop(conn_drop_ref);
-                                        x
-                                    });
-                                self.executor.execute(pipe);
+                            if let Some(ref timings) = timings {
+                                timings.set_headers_written();
+                                if eos {
+                                    timings.set_request_written();
+                                }
                             }
-
-                            let fut = fut
-                                .then(move |result| {
-                                    match result {
-                                        Ok(res) => {
-                                            let res = res.map(::Body::h2);
-                                            let _ = cb.send(Ok(res));
-                                        },
-                                        Err(err) => {
-                                            debug!("client response error: {}", err);
-                                            let _ = cb.send(Err((::Error::new_h2(err), None)));
-                                        }
-                                    }
-                                    Ok(())
-                                });
-                            self.executor.execute(fut);
+                            let reset = if eos && cancel.is_none() {
+                                // Nothing is left to pipe, so the response
+                                // body resets the stream itself.
+                                StreamReset::Stream(Box::new(body_tx))
+                            } else {
+                                // The pipe keeps the stream even once the body
+                                // is sent, so that the response body can reset it.
+                                let (reset_tx, reset_rx) = oneshot::channel();
+                                let pipe = PipeToSendStream::new(body, body_tx, content_length, self.body_chunks)
+                                    .reset_on(reset_rx, eos)
+                                    .cancel_on(cancel)
+                                    .record_sent(if eos { None } else { timings.clone() });
+                                let body = StreamTask::Body(pipe, Some(conn_dropper.clone()));
+                                spawn(&self.executor, &mut self.local_streams, TaskKind::RequestBody, &span, body);
+                                StreamReset::Pipe(reset_tx)
+                            };
+                            let response = StreamTask::Response(RecvResponse {
+                                fut,
+                                request,
+                                reset: Some(reset),
+                                timings,
+                                cb: Some(cb),
+                            });
+                            spawn(&self.executor, &mut self.local_streams, TaskKind::Response, &span, response);
                             continue;
                         },
 
@@ -162,3 +271,97 @@ where
         }
     }
 }
+
+fn spawn<B>(exec: &Exec, local: &mut Option<Slab<Instrumented<StreamTask<B>>>>, kind: TaskKind, span: &Span, task: StreamTask<B>)
+where
+    B: Payload + 'static,
+{
+    let task = span.clone().instrument(task);
+    match *local {
+        Some(ref mut local) => local.insert(task),
+        None => exec.execute(kind, task),
+    }
+}
+
+/// The work of a request, once it was sent: piping its body, or waiting
+/// for its response.
+enum StreamTask<B>
+where
+    B: Payload,
+{
+    /// The pipe keeps the connection alive until the body is sent.
+    Body(PipeToSendStream<B>, Option<ConnDropRef>),
+    Response(RecvResponse<B>),
+}
+
+struct RecvResponse<B>
+where
+    B: Payload,
+{
+    fut: ResponseFuture,
+    request: Option<RequestGuard>,
+    reset: Option<StreamReset>,
+    timings: Option<Timings>,
+    cb: Option<Callback<B>>,
+}
+
+impl<B> Future for StreamTask<B>
+where
+    B: Payload,
+{
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match *self {
+            StreamTask::Body(ref mut pipe, ref mut conn_drop_ref) => {
+                let res = pipe.poll()
+                    .map_err(|e| debug!("client request body error: {}", e));
+                if let Ok(Async::NotReady) = res {
+                    return res;
+                }
+                drop(conn_drop_ref.take());
+                res
+            },
+            StreamTask::Response(ref mut recv) => recv.poll(),
+        }
+    }
+}
+
+impl<B> RecvResponse<B>
+where
+    B: Payload,
+{
+    fn poll(&mut self) -> Poll<(), ()> {
+        let result = match self.fut.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(res)) => Ok(res),
+            Err(err) => Err(err),
+        };
+        let cb = self.cb.take().expect("polled after complete");
+        match result {
+            Ok(res) => {
+                if let Some(mut request) = self.request.take() {
+                    request.set_status(res.status());
+                }
+                let reset = self.reset.take();
+                let mut res = res.map(|recv| ::Body::h2(recv, reset));
+                if let Some(timings) = self.timings.take() {
+                    timings.set_first_byte();
+                    if res.body().is_end_stream() {
+                        timings.set_response_complete();
+                    } else {
+                        res.body_mut().set_timings(timings.clone());
+                    }
+                    res.extensions_mut().insert(timings);
+                }
+                let _ = cb.send(Ok(res));
+            },
+            Err(err) => {
+                debug!("client response error: {}", err);
+                let _ = cb.send(Err((::Error::new_h2(err), None)));
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+}
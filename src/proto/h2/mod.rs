@@ -1,56 +1,74 @@
-use bytes::Buf;
+use std::cmp;
+use std::io::Cursor;
+
+use bytes::{Buf, Bytes, BytesMut, IntoBuf};
 use futures::{Async, Future, Poll};
+use futures::sync::oneshot;
 use h2::{Reason, SendStream};
-use http::header::{
-    HeaderName, CONNECTION, PROXY_AUTHENTICATE, PROXY_AUTHORIZATION, TE, TRAILER,
-    TRANSFER_ENCODING, UPGRADE,
-};
-use http::HeaderMap;
+use iovec::IoVec;
 
 use body::Payload;
+use client::CancelHandle;
+use ext::Timings;
 
 mod client;
+mod schedule;
 mod server;
 
 pub(crate) use self::client::Client;
+pub use self::schedule::SchedulingPolicy;
 pub(crate) use self::server::Server;
+use self::schedule::Turn;
+
+/// How a received body resets the stream of the request it's the response
+/// to.
+pub(crate) enum StreamReset {
+    /// The request body is still piped, or watched for a cancel, so the
+    /// pipe is sent the reason to reset the stream with.
+    Pipe(oneshot::Sender<Reason>),
+    /// The request was sent in full, so the stream is reset directly.
+    Stream(Box<ResetStream + Send + Sync>),
+}
 
-fn strip_connection_headers(headers: &mut HeaderMap) {
-    // List of connection headers from:
-    // https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Connection
-    let connection_headers = [
-        HeaderName::from_lowercase(b"keep-alive").unwrap(),
-        HeaderName::from_lowercase(b"proxy-connection").unwrap(),
-        PROXY_AUTHENTICATE,
-        PROXY_AUTHORIZATION,
-        TE,
-        TRAILER,
-        TRANSFER_ENCODING,
-        UPGRADE,
-    ];
-
-    for header in connection_headers.iter() {
-        if headers.remove(header).is_some() {
-            warn!("Connection header illegal in HTTP/2: {}", header.as_str());
-        }
-    }
-
-    if let Some(header) = headers.remove(CONNECTION) {
-        warn!(
-            "Connection header illegal in HTTP/2: {}",
-            CONNECTION.as_str()
-        );
-        let header_contents = header.to_str().unwrap();
-
-        // A `Connection` header may have a comma-separated list of names of other headers that
-        // are meant for only this specific connection.
-        //
-        // Iterate these names and remove them as headers. Connection-specific headers are
-        // forbidden in HTTP2, as that information has been moved into frame types of the h2
-        // protocol.
-        for name in header_contents.split(',') {
-            let name = name.trim();
-            headers.remove(name);
+impl StreamReset {
+    pub(crate) fn reset(self, reason: Reason) {
+        match self {
+            StreamReset::Pipe(tx) => {
+                let _ = tx.send(reason);
+            },
+            StreamReset::Stream(mut stream) => stream.send_reset(reason),
+        }
+    }
+}
+
+pub(crate) trait ResetStream {
+    fn send_reset(&mut self, reason: Reason);
+}
+
+impl<B: IntoBuf> ResetStream for SendStream<B> {
+    fn send_reset(&mut self, reason: Reason) {
+        SendStream::send_reset(self, reason)
+    }
+}
+
+/// How bodies split and coalesce their chunks into DATA frames.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct BodyChunks {
+    /// Chunks larger than this are sent in pieces.
+    pub(crate) max_size: Option<usize>,
+    /// Chunks smaller than this are coalesced with the following ones.
+    pub(crate) min_size: usize,
+}
+
+impl BodyChunks {
+    pub(crate) fn check(&self) -> ::Result<()> {
+        match self.max_size {
+            Some(max) if self.min_size > max => Err(::Error::new_config(format!(
+                "http2_min_send_chunk_size ({}) is larger than http2_max_send_chunk_size ({})",
+                self.min_size,
+                max,
+            ))),
+            _ => Ok(()),
         }
     }
 }
@@ -62,7 +80,25 @@ where
     S: Payload,
 {
     body_tx: SendStream<SendBuf<S::Data>>,
+    body_sent: bool,
+    chunks: BodyChunks,
+    // small chunks waiting to be sent together
+    coalesced: BytesMut,
     data_done: bool,
+    // a chunk sent in pieces, whether it ends the stream, and the piece size
+    oversized: Option<(S::Data, bool, usize)>,
+    // bytes left to send, if the headers have a content-length
+    remaining: Option<u64>,
+    // bytes of the body taken to be sent
+    sent: usize,
+    // a reason to reset the stream with, sent by the received body
+    reset_rx: Option<oneshot::Receiver<Reason>>,
+    // resets the stream with `CANCEL` once canceled by the user
+    cancel: Option<CancelHandle>,
+    // records when the body was sent
+    timings: Option<Timings>,
+    // when the body may send, among the other responses of the connection
+    turn: Option<Turn>,
     stream: S,
 }
 
@@ -70,14 +106,97 @@ impl<S> PipeToSendStream<S>
 where
     S: Payload,
 {
-    fn new(stream: S, tx: SendStream<SendBuf<S::Data>>) -> PipeToSendStream<S> {
+    fn new(stream: S, tx: SendStream<SendBuf<S::Data>>, content_length: Option<u64>, chunks: BodyChunks) -> PipeToSendStream<S> {
         PipeToSendStream {
             body_tx: tx,
+            body_sent: false,
+            chunks,
+            coalesced: BytesMut::new(),
             data_done: false,
+            oversized: None,
+            remaining: content_length,
+            sent: 0,
+            reset_rx: None,
+            cancel: None,
+            timings: None,
+            turn: None,
             stream: stream,
         }
     }
 
+    /// Keeps the stream after the body is sent, until `rx` either sends a
+    /// reason to reset it with, or is canceled.
+    fn reset_on(mut self, rx: oneshot::Receiver<Reason>, body_sent: bool) -> PipeToSendStream<S> {
+        self.reset_rx = Some(rx);
+        self.body_sent = body_sent;
+        self
+    }
+
+    fn cancel_on(mut self, cancel: Option<CancelHandle>) -> PipeToSendStream<S> {
+        self.cancel = cancel;
+        self
+    }
+
+    fn record_sent(mut self, timings: Option<Timings>) -> PipeToSendStream<S> {
+        self.timings = timings;
+        self
+    }
+
+    fn schedule(mut self, turn: Turn) -> PipeToSendStream<S> {
+        self.turn = Some(turn);
+        self
+    }
+
+    // Lets the other responses send, while this body has nothing to.
+    fn idle(&mut self) {
+        if let Some(ref mut turn) = self.turn {
+            turn.idle();
+        }
+    }
+
+    fn bytes_sent(&self) -> usize {
+        self.sent
+    }
+
+    fn poll_reset_rx(&mut self) -> Option<Reason> {
+        if self.cancel.as_ref().map(CancelHandle::poll_canceled).unwrap_or(false) {
+            return Some(Reason::CANCEL);
+        }
+        let res = match self.reset_rx {
+            Some(ref mut rx) => rx.poll(),
+            None => return None,
+        };
+        match res {
+            Ok(Async::Ready(reason)) => Some(reason),
+            Ok(Async::NotReady) => None,
+            Err(_canceled) => {
+                self.reset_rx = None;
+                None
+            },
+        }
+    }
+
+    // Resets the stream if sending `len` more bytes, and ending the body if
+    // `is_end`, doesn't match the content-length of the headers.
+    fn check_length(&mut self, len: usize, is_end: bool) -> ::Result<()> {
+        let err = match self.remaining {
+            Some(remaining) if len as u64 > remaining => {
+                format!("body is longer than its content-length, by at least {} bytes", len as u64 - remaining)
+            },
+            Some(remaining) if is_end && (len as u64) < remaining => {
+                format!("body is shorter than its content-length, by {} bytes", remaining - len as u64)
+            },
+            Some(remaining) => {
+                self.remaining = Some(remaining - len as u64);
+                return Ok(());
+            },
+            None => return Ok(()),
+        };
+        debug!("send body {}", err);
+        self.body_tx.send_reset(Reason::INTERNAL_ERROR);
+        Err(::Error::new_user_body_length(err))
+    }
+
     fn on_err(&mut self, err: S::Error) -> ::Error {
         let err = ::Error::new_user_body(err);
         trace!("send body user stream error: {}", err);
@@ -85,12 +204,58 @@ where
         err
     }
 
+    // Checks if the peer reset the stream while waiting on the body, so that
+    // the body is dropped promptly.
+    fn check_reset(&mut self) -> ::Result<()> {
+        match self.body_tx.poll_reset() {
+            Ok(Async::Ready(reason)) => {
+                debug!("stream received RST_STREAM: {:?}", reason);
+                Err(::Error::new_h2(reason.into()))
+            },
+            Ok(Async::NotReady) => Ok(()),
+            Err(e) => Err(::Error::new_h2(e)),
+        }
+    }
+
     fn send_eos_frame(&mut self) -> ::Result<()> {
         trace!("send body eos");
         self.body_tx
-            .send_data(SendBuf(None), true)
+            .send_data(SendBuf::None, true)
             .map_err(::Error::new_body_write)
     }
+
+    fn send_coalesced(&mut self, is_eos: bool) -> ::Result<()> {
+        let bytes = self.coalesced.take().freeze();
+        trace!("send coalesced body chunks: {} bytes, eos={}", bytes.len(), is_eos);
+        self.body_tx
+            .send_data(SendBuf::Bytes(bytes.into_buf()), is_eos)
+            .map_err(::Error::new_body_write)
+    }
+
+    // Sends the next piece of an oversized chunk, returning if it was the
+    // end of the stream.
+    fn send_oversized(&mut self) -> ::Result<bool> {
+        let (mut chunk, is_eos, max) = self.oversized.take().expect("oversized chunk");
+        if chunk.remaining() <= max {
+            self.body_tx
+                .send_data(SendBuf::Buf(chunk), is_eos)
+                .map_err(::Error::new_body_write)?;
+            return Ok(is_eos);
+        }
+
+        let mut piece = BytesMut::with_capacity(max);
+        while piece.len() < max {
+            let n = cmp::min(chunk.bytes().len(), max - piece.len());
+            piece.extend_from_slice(&chunk.bytes()[..n]);
+            chunk.advance(n);
+        }
+        trace!("send body chunk piece: {} bytes, {} left", max, chunk.remaining());
+        self.oversized = Some((chunk, is_eos, max));
+        self.body_tx
+            .send_data(SendBuf::Bytes(piece.freeze().into_buf()), false)
+            .map_err(::Error::new_body_write)?;
+        Ok(false)
+    }
 }
 
 impl<S> Future for PipeToSendStream<S>
@@ -101,6 +266,32 @@ where
     type Error = ::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(reason) = self.poll_reset_rx() {
+            debug!("resetting stream: {:?}", reason);
+            self.body_tx.send_reset(reason);
+            return Ok(Async::Ready(()));
+        }
+        if !self.body_sent {
+            try_ready!(self.poll_body());
+            self.body_sent = true;
+            if let Some(timings) = self.timings.take() {
+                timings.set_request_written();
+            }
+        }
+        if self.reset_rx.is_some() {
+            // the stream can still be reset, until the receiver is dropped
+            Ok(Async::NotReady)
+        } else {
+            Ok(Async::Ready(()))
+        }
+    }
+}
+
+impl<S> PipeToSendStream<S>
+where
+    S: Payload,
+{
+    fn poll_body(&mut self) -> Poll<(), ::Error> {
         loop {
             if !self.data_done {
                 // we don't have the next chunk of data yet, so just reserve 1 byte to make
@@ -110,16 +301,45 @@ where
 
                 if self.body_tx.capacity() == 0 {
                     loop {
-                        match try_ready!(self.body_tx.poll_capacity().map_err(::Error::new_h2)) {
-                            Some(0) => {}
-                            Some(_) => break,
-                            None => return Err(::Error::new_canceled(None::<::Error>)),
+                        match self.body_tx.poll_capacity().map_err(::Error::new_h2)? {
+                            Async::Ready(Some(0)) => {}
+                            Async::Ready(Some(_)) => break,
+                            Async::Ready(None) => return Err(::Error::new_canceled(None::<::Error>)),
+                            Async::NotReady => {
+                                self.idle();
+                                return Ok(Async::NotReady);
+                            },
                         }
                     }
                 }
 
-                match try_ready!(self.stream.poll_data().map_err(|e| self.on_err(e))) {
-                    Some(chunk) => {
+                if let Some(ref mut turn) = self.turn {
+                    if let Async::NotReady = turn.poll_turn() {
+                        return Ok(Async::NotReady);
+                    }
+                }
+
+                if self.oversized.is_some() {
+                    if self.send_oversized()? {
+                        return Ok(Async::Ready(()));
+                    }
+                    continue;
+                }
+
+                let data = match self.stream.poll_data().map_err(|e| self.on_err(e))? {
+                    Async::Ready(data) => data,
+                    Async::NotReady => {
+                        self.check_reset()?;
+                        // don't hold the coalesced chunks back while waiting
+                        if !self.coalesced.is_empty() {
+                            self.send_coalesced(false)?;
+                        }
+                        self.idle();
+                        return Ok(Async::NotReady);
+                    }
+                };
+                match data {
+                    Some(mut chunk) => {
                         let is_eos = self.stream.is_end_stream();
                         trace!(
                             "send body chunk: {} bytes, eos={}",
@@ -127,9 +347,36 @@ where
                             is_eos,
                         );
 
-                        let buf = SendBuf(Some(chunk));
+                        self.check_length(chunk.remaining(), is_eos)?;
+                        self.sent += chunk.remaining();
+
+                        if !is_eos && chunk.remaining() < self.chunks.min_size {
+                            while chunk.has_remaining() {
+                                let n = chunk.bytes().len();
+                                self.coalesced.extend_from_slice(chunk.bytes());
+                                chunk.advance(n);
+                            }
+                            if self.coalesced.len() >= self.chunks.min_size {
+                                self.send_coalesced(false)?;
+                            }
+                            continue;
+                        }
+                        if !self.coalesced.is_empty() {
+                            self.send_coalesced(false)?;
+                        }
+
+                        let max = self.chunks.max_size
+                            .or_else(|| self.turn.as_ref().and_then(Turn::quantum));
+                        match max {
+                            Some(max) if chunk.remaining() > max => {
+                                self.oversized = Some((chunk, is_eos, max));
+                                continue;
+                            },
+                            _ => (),
+                        }
+
                         self.body_tx
-                            .send_data(buf, is_eos)
+                            .send_data(SendBuf::Buf(chunk), is_eos)
                             .map_err(::Error::new_body_write)?;
 
                         if is_eos {
@@ -138,7 +385,14 @@ where
                     }
                     None => {
                         self.body_tx.reserve_capacity(0);
+                        self.check_length(0, true)?;
                         let is_eos = self.stream.is_end_stream();
+                        if !self.coalesced.is_empty() {
+                            self.send_coalesced(is_eos)?;
+                            if is_eos {
+                                return Ok(Async::Ready(()));
+                            }
+                        }
                         if is_eos {
                             return self.send_eos_frame().map(Async::Ready);
                         } else {
@@ -148,7 +402,15 @@ where
                     }
                 }
             } else {
-                match try_ready!(self.stream.poll_trailers().map_err(|e| self.on_err(e))) {
+                let trailers = match self.stream.poll_trailers().map_err(|e| self.on_err(e))? {
+                    Async::Ready(trailers) => trailers,
+                    Async::NotReady => {
+                        self.check_reset()?;
+                        self.idle();
+                        return Ok(Async::NotReady);
+                    }
+                };
+                match trailers {
                     Some(trailers) => {
                         self.body_tx
                             .send_trailers(trailers)
@@ -165,21 +427,47 @@ where
     }
 }
 
-struct SendBuf<B>(Option<B>);
+enum SendBuf<B> {
+    Buf(B),
+    // coalesced or split chunks
+    Bytes(Cursor<Bytes>),
+    None,
+}
 
 impl<B: Buf> Buf for SendBuf<B> {
     #[inline]
     fn remaining(&self) -> usize {
-        self.0.as_ref().map(|b| b.remaining()).unwrap_or(0)
+        match *self {
+            SendBuf::Buf(ref b) => b.remaining(),
+            SendBuf::Bytes(ref b) => b.remaining(),
+            SendBuf::None => 0,
+        }
     }
 
     #[inline]
     fn bytes(&self) -> &[u8] {
-        self.0.as_ref().map(|b| b.bytes()).unwrap_or(&[])
+        match *self {
+            SendBuf::Buf(ref b) => b.bytes(),
+            SendBuf::Bytes(ref b) => b.bytes(),
+            SendBuf::None => &[],
+        }
     }
 
     #[inline]
     fn advance(&mut self, cnt: usize) {
-        self.0.as_mut().map(|b| b.advance(cnt));
+        match *self {
+            SendBuf::Buf(ref mut b) => b.advance(cnt),
+            SendBuf::Bytes(ref mut b) => b.advance(cnt),
+            SendBuf::None => (),
+        }
+    }
+
+    #[inline]
+    fn bytes_vec<'t>(&'t self, dst: &mut [&'t IoVec]) -> usize {
+        match *self {
+            SendBuf::Buf(ref b) => b.bytes_vec(dst),
+            SendBuf::Bytes(ref b) => b.bytes_vec(dst),
+            SendBuf::None => 0,
+        }
     }
 }
@@ -0,0 +1,273 @@
+use std::sync::{Arc, Mutex};
+
+use futures::Async;
+use futures::task::{self, Task};
+use http::HeaderMap;
+
+/// The largest piece of a body chunk sent at once while other responses
+/// share the connection, the default `SETTINGS_MAX_FRAME_SIZE`.
+const QUANTUM: usize = 16_384;
+
+/// How an HTTP/2 server shares a connection among the responses with the
+/// same urgency.
+///
+/// Responses are sent by their priority, of the `priority` header of
+/// [RFC 9218](https://tools.ietf.org/html/rfc9218), which the `Service` can
+/// also set on the response to override the request's. A response with a
+/// more urgent priority is sent first, while the others wait. The policy
+/// picks what the responses without an `incremental` parameter do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchedulingPolicy {
+    /// Responses take turns, interleaving their DATA frames.
+    RoundRobin,
+    /// Responses are sent one at a time, in the order of their requests.
+    Sequential,
+}
+
+impl Default for SchedulingPolicy {
+    fn default() -> SchedulingPolicy {
+        SchedulingPolicy::RoundRobin
+    }
+}
+
+/// The priority of a response, as in RFC 9218.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Priority {
+    /// From 0, the most urgent, to 7.
+    urgency: u8,
+    incremental: bool,
+}
+
+impl Priority {
+    /// The priority of the request with `headers`.
+    pub(crate) fn of_request(headers: &HeaderMap, policy: SchedulingPolicy) -> Priority {
+        let mut priority = Priority {
+            urgency: 3,
+            incremental: policy == SchedulingPolicy::RoundRobin,
+        };
+        priority.update(headers);
+        priority
+    }
+
+    /// Updates this priority with the parameters of the `priority` header
+    /// in `headers`, if any.
+    pub(crate) fn update(&mut self, headers: &HeaderMap) {
+        for value in headers.get_all("priority") {
+            let value = match value.to_str() {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+            for param in value.split(',') {
+                let mut parts = param.trim().splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some("u"), Some(urgency)) => match urgency.trim().parse::<u8>() {
+                        Ok(urgency) if urgency <= 7 => self.urgency = urgency,
+                        _ => debug!("invalid priority urgency: {:?}", urgency),
+                    },
+                    (Some("i"), None) | (Some("i"), Some("?1")) => self.incremental = true,
+                    (Some("i"), Some("?0")) => self.incremental = false,
+                    // unknown parameters are ignored
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+/// Decides which of the responses of a connection may send their bodies.
+#[derive(Clone, Default)]
+pub(crate) struct Scheduler {
+    inner: Arc<Mutex<Streams>>,
+}
+
+#[derive(Default)]
+struct Streams {
+    next_seq: u64,
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    seq: u64,
+    priority: Priority,
+    /// Whether the stream is sending, or waiting for its turn to.
+    pending: bool,
+    task: Option<Task>,
+}
+
+impl Entry {
+    // Whether `other` sends before this entry, when both are pending.
+    fn is_after(&self, other: &Entry) -> bool {
+        if other.priority.urgency != self.priority.urgency {
+            return other.priority.urgency < self.priority.urgency;
+        }
+        !self.priority.incremental && !other.priority.incremental && other.seq < self.seq
+    }
+}
+
+impl Streams {
+    fn notify_waiting(&mut self) {
+        for entry in &mut self.entries {
+            if let Some(task) = entry.task.take() {
+                task.notify();
+            }
+        }
+    }
+}
+
+impl Scheduler {
+    /// Adds a response body with `priority` to be scheduled.
+    pub(crate) fn register(&self, priority: Priority) -> Turn {
+        let mut streams = self.inner.lock().unwrap();
+        let seq = streams.next_seq;
+        streams.next_seq += 1;
+        streams.entries.push(Entry {
+            seq: seq,
+            priority: priority,
+            pending: false,
+            task: None,
+        });
+        Turn {
+            scheduler: self.clone(),
+            seq: seq,
+        }
+    }
+}
+
+/// The place of a response body in its connection's `Scheduler`.
+///
+/// Dropping it lets the other responses go.
+pub(crate) struct Turn {
+    scheduler: Scheduler,
+    seq: u64,
+}
+
+impl Turn {
+    /// Checks if the body may send now.
+    ///
+    /// If not, the current task is notified once another response stops
+    /// sending.
+    pub(crate) fn poll_turn(&mut self) -> Async<()> {
+        let mut streams = self.scheduler.inner.lock().unwrap();
+        let i = streams.entries.iter()
+            .position(|entry| entry.seq == self.seq)
+            .expect("scheduled stream is registered");
+        streams.entries[i].pending = true;
+        let first = {
+            let this = &streams.entries[i];
+            !streams.entries.iter().any(|other| other.pending && this.is_after(other))
+        };
+        if first {
+            streams.entries[i].task = None;
+            Async::Ready(())
+        } else {
+            trace!("stream waits for a more urgent response");
+            streams.entries[i].task = Some(task::current());
+            Async::NotReady
+        }
+    }
+
+    /// The body has nothing to send for now, such as while waiting for
+    /// more data or flow control window.
+    pub(crate) fn idle(&mut self) {
+        let mut streams = self.scheduler.inner.lock().unwrap();
+        let was_pending = streams.entries.iter_mut()
+            .find(|entry| entry.seq == self.seq)
+            .map(|entry| ::std::mem::replace(&mut entry.pending, false))
+            .unwrap_or(false);
+        if was_pending {
+            streams.notify_waiting();
+        }
+    }
+
+    /// The largest piece of a chunk to send at once, if other responses
+    /// share the connection with this one.
+    pub(crate) fn quantum(&self) -> Option<usize> {
+        let streams = self.scheduler.inner.lock().unwrap();
+        if streams.entries.len() > 1 {
+            Some(QUANTUM)
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        let mut streams = match self.scheduler.inner.lock() {
+            Ok(streams) => streams,
+            Err(_poisoned) => return,
+        };
+        streams.entries.retain(|entry| entry.seq != self.seq);
+        streams.notify_waiting();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, Async, Future};
+    use http::HeaderMap;
+    use http::header::HeaderValue;
+
+    use super::{Priority, Scheduler, SchedulingPolicy};
+
+    fn priority(value: &str, policy: SchedulingPolicy) -> Priority {
+        let mut headers = HeaderMap::new();
+        headers.insert("priority", HeaderValue::from_str(value).unwrap());
+        Priority::of_request(&headers, policy)
+    }
+
+    #[test]
+    fn parse_priority() {
+        let p = priority("u=1, i", SchedulingPolicy::Sequential);
+        assert_eq!(p, Priority { urgency: 1, incremental: true });
+        let p = priority("i=?0, foo=bar", SchedulingPolicy::RoundRobin);
+        assert_eq!(p, Priority { urgency: 3, incremental: false });
+        let p = priority("u=9", SchedulingPolicy::RoundRobin);
+        assert_eq!(p, Priority { urgency: 3, incremental: true });
+
+        let mut p = Priority::of_request(&HeaderMap::new(), SchedulingPolicy::Sequential);
+        let mut res = HeaderMap::new();
+        res.insert("priority", HeaderValue::from_static("u=0"));
+        p.update(&res);
+        assert_eq!(p, Priority { urgency: 0, incremental: false });
+    }
+
+    #[test]
+    fn urgent_streams_go_first() {
+        future::lazy(|| {
+            let scheduler = Scheduler::default();
+            let mut low = scheduler.register(priority("u=5", SchedulingPolicy::RoundRobin));
+            let mut high = scheduler.register(priority("u=1", SchedulingPolicy::RoundRobin));
+            assert_eq!(low.quantum(), Some(super::QUANTUM));
+
+            assert_eq!(high.poll_turn(), Async::Ready(()));
+            assert_eq!(low.poll_turn(), Async::NotReady);
+            high.idle();
+            assert_eq!(low.poll_turn(), Async::Ready(()));
+            drop(high);
+            assert_eq!(low.quantum(), None);
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn round_robin_and_sequential() {
+        future::lazy(|| {
+            let scheduler = Scheduler::default();
+            let mut a = scheduler.register(priority("u=3", SchedulingPolicy::RoundRobin));
+            let mut b = scheduler.register(priority("u=3", SchedulingPolicy::RoundRobin));
+            assert_eq!(a.poll_turn(), Async::Ready(()));
+            assert_eq!(b.poll_turn(), Async::Ready(()));
+            drop((a, b));
+
+            let mut a = scheduler.register(priority("u=3", SchedulingPolicy::Sequential));
+            let mut b = scheduler.register(priority("u=3", SchedulingPolicy::Sequential));
+            assert_eq!(b.poll_turn(), Async::Ready(()));
+            assert_eq!(a.poll_turn(), Async::Ready(()));
+            assert_eq!(b.poll_turn(), Async::NotReady);
+            drop(a);
+            assert_eq!(b.poll_turn(), Async::Ready(()));
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
@@ -1,13 +1,23 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use futures::{Async, Future, Poll, Stream};
 use h2::Reason;
 use h2::server::{Builder, Connection, Handshake, SendResponse};
 use tokio_io::{AsyncRead, AsyncWrite};
 
 use ::body::Payload;
-use ::common::Exec;
+use ::common::{Budget, Exec};
+use ::common::slab::Slab;
+use ::common::span::Span;
+use ::exec::TaskKind;
+use ::instrument::{Instrument, RequestGuard};
+use ::proto::proxy::ProxyInfo;
+use ::server::conn::ConnectionState;
 use ::headers;
 use ::service::Service;
-use super::{PipeToSendStream, SendBuf};
+use super::{BodyChunks, PipeToSendStream, SchedulingPolicy, SendBuf};
+use super::schedule::{Priority, Scheduler};
 
 use ::{Body, Response};
 
@@ -16,9 +26,19 @@ where
     S: Service,
     B: Payload,
 {
+    body_chunks: BodyChunks,
     exec: Exec,
+    instrument: Option<Instrument>,
+    /// The streams, when they are polled by the connection instead of
+    /// being spawned.
+    local_streams: Option<Slab<H2Stream<S::Future, B>>>,
+    proxy_info: Option<ProxyInfo>,
+    scheduling: SchedulingPolicy,
     service: S,
+    span: Span,
     state: State<T, B>,
+    /// The streams spawned, that didn't finish yet.
+    streams: Arc<AtomicUsize>,
 }
 
 enum State<T, B>
@@ -35,6 +55,10 @@ where
     B: Payload,
 {
     conn: Connection<T, SendBuf<B::Data>>,
+    /// Bounds the streams accepted in a single poll.
+    budget: Budget,
+    /// Shares the connection among the response bodies.
+    scheduler: Scheduler,
 }
 
 
@@ -46,16 +70,70 @@ where
     S::Future: Send + 'static,
     B: Payload,
 {
-    pub(crate) fn new(io: T, service: S, exec: Exec) -> Server<T, S, B> {
-        let handshake = Builder::new()
-            .handshake(io);
+    pub(crate) fn new(
+        io: T,
+        service: S,
+        exec: Exec,
+        instrument: Option<Instrument>,
+        max_concurrent_streams: Option<u32>,
+    ) -> Server<T, S, B> {
+        let mut builder = Builder::new();
+        if let Some(max) = max_concurrent_streams {
+            builder.max_concurrent_streams(max);
+        }
+        let handshake = builder.handshake(io);
         Server {
+            body_chunks: BodyChunks::default(),
             exec,
+            instrument,
+            local_streams: None,
+            proxy_info: None,
+            scheduling: SchedulingPolicy::default(),
             state: State::Handshaking(handshake),
             service,
+            span: Span::connection("server", "h2"),
+            streams: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub(crate) fn connection_state(&self) -> ConnectionState {
+        let phase = match self.state {
+            State::Handshaking(..) => "handshake",
+            State::Serving(..) => "frames",
+            State::Closed => "closed",
+        };
+        ConnectionState {
+            version: Some(::http::Version::HTTP_2),
+            reading: phase,
+            writing: phase,
+            keep_alive: phase != "closed",
+            read_buffered: 0,
+            write_buffered: 0,
+            in_flight: self.streams.load(Ordering::Relaxed),
         }
     }
 
+    /// Insert the `ProxyInfo` into every request received.
+    pub(crate) fn set_proxy_info(&mut self, info: Option<ProxyInfo>) {
+        self.proxy_info = info;
+    }
+
+    pub(crate) fn set_body_chunks(&mut self, chunks: BodyChunks) {
+        self.body_chunks = chunks;
+    }
+
+    pub(crate) fn set_scheduling_policy(&mut self, policy: SchedulingPolicy) {
+        self.scheduling = policy;
+    }
+
+    pub(crate) fn set_spawn_streams(&mut self, enabled: bool) {
+        self.local_streams = if enabled {
+            None
+        } else {
+            Some(Slab::new())
+        };
+    }
+
     pub fn graceful_shutdown(&mut self) {
         trace!("graceful_shutdown");
         match self.state {
@@ -86,16 +164,27 @@ where
     type Error = ::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let span = self.span.clone();
+        let _entered = span.enter();
         loop {
             let next = match self.state {
                 State::Handshaking(ref mut h) => {
                     let conn = try_ready!(h.poll().map_err(::Error::new_h2));
                     State::Serving(Serving {
                         conn: conn,
+                        budget: Budget::new(),
+                        scheduler: Scheduler::default(),
                     })
                 },
                 State::Serving(ref mut srv) => {
-                    return srv.poll_server(&mut self.service, &self.exec);
+                    let incoming = srv.poll_server(&mut self.service, &self.exec, &mut self.local_streams, &self.proxy_info, &self.instrument, self.body_chunks, self.scheduling, &self.streams)?;
+                    // the connection is only done once its streams are
+                    if let Some(ref mut local) = self.local_streams {
+                        if local.poll().is_not_ready() {
+                            return Ok(Async::NotReady);
+                        }
+                    }
+                    return Ok(incoming);
                 }
                 State::Closed => {
                     // graceful_shutdown was called before handshaking finished,
@@ -113,7 +202,17 @@ where
     T: AsyncRead + AsyncWrite,
     B: Payload,
 {
-    fn poll_server<S>(&mut self, service: &mut S, exec: &Exec) -> Poll<(), ::Error>
+    fn poll_server<S>(
+        &mut self,
+        service: &mut S,
+        exec: &Exec,
+        local_streams: &mut Option<Slab<H2Stream<S::Future, B>>>,
+        proxy_info: &Option<ProxyInfo>,
+        instrument: &Option<Instrument>,
+        body_chunks: BodyChunks,
+        scheduling: SchedulingPolicy,
+        streams: &Arc<AtomicUsize>,
+    ) -> Poll<(), ::Error>
     where
         S: Service<
             ReqBody=Body,
@@ -122,11 +221,46 @@ where
         S::Error: Into<Box<::std::error::Error + Send + Sync>>,
         S::Future: Send + 'static,
     {
-        while let Some((req, respond)) = try_ready!(self.conn.poll().map_err(::Error::new_h2)) {
+        self.budget.reset();
+        loop {
+            if !self.budget.spend() {
+                return Ok(Async::NotReady);
+            }
+            // don't accept another stream until the service can take it,
+            // but keep driving the connection for the streams accepted
+            if let Async::NotReady = service.poll_ready().map_err(::Error::new_user_service)? {
+                try_ready!(self.conn.poll_close().map_err(::Error::new_h2));
+                break;
+            }
+            let (req, respond) = match try_ready!(self.conn.poll().map_err(::Error::new_h2)) {
+                Some(incoming) => incoming,
+                None => break,
+            };
             trace!("incoming request");
-            let req = req.map(::Body::h2);
-            let fut = H2Stream::new(service.call(req), respond);
-            exec.execute(fut);
+            let priority = Priority::of_request(req.headers(), scheduling);
+            let request = instrument.as_ref()
+                .map(|instrument| instrument.exchange(req.method(), req.uri(), req.version(), req.headers()));
+            let mut req = req.map(|recv| ::Body::h2(recv, None));
+            if let Some(count) = request.as_ref().and_then(RequestGuard::body_read) {
+                req.body_mut().set_read_count(count);
+            }
+            if let Some(id) = request.as_ref().and_then(RequestGuard::request_id) {
+                req.extensions_mut().insert(id.clone());
+            }
+            if let Some(ref info) = *proxy_info {
+                req.extensions_mut().insert(info.clone());
+            }
+            let span = Span::request(req.method(), req.uri());
+            let fut = {
+                let _entered = span.enter();
+                service.call(req)
+            };
+            let fut = H2Stream::new(fut, respond, body_chunks, request, ActiveStream::new(streams), span.clone())
+                .schedule(self.scheduler.clone(), priority);
+            match *local_streams {
+                Some(ref mut local) => local.insert(fut),
+                None => exec.execute(TaskKind::Stream, fut),
+            }
         }
 
         // no more incoming streams...
@@ -139,8 +273,31 @@ struct H2Stream<F, B>
 where
     B: Payload,
 {
+    body_chunks: BodyChunks,
     reply: SendResponse<SendBuf<B::Data>>,
+    /// Reports the request finished once the stream is dropped.
+    request: Option<RequestGuard>,
+    /// The connection's scheduler, and the priority the request asked for.
+    schedule: Option<(Scheduler, Priority)>,
+    span: Span,
     state: H2StreamState<F, B>,
+    /// Counts the stream as active, until it is dropped.
+    _active: ActiveStream,
+}
+
+struct ActiveStream(Arc<AtomicUsize>);
+
+impl ActiveStream {
+    fn new(streams: &Arc<AtomicUsize>) -> ActiveStream {
+        streams.fetch_add(1, Ordering::Relaxed);
+        ActiveStream(streams.clone())
+    }
+}
+
+impl Drop for ActiveStream {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 enum H2StreamState<F, B>
@@ -157,22 +314,45 @@ where
     F::Error: Into<Box<::std::error::Error + Send + Sync>>,
     B: Payload,
 {
-    fn new(fut: F, respond: SendResponse<SendBuf<B::Data>>) -> H2Stream<F, B> {
+    fn new(
+        fut: F,
+        respond: SendResponse<SendBuf<B::Data>>,
+        body_chunks: BodyChunks,
+        request: Option<RequestGuard>,
+        active: ActiveStream,
+        span: Span,
+    ) -> H2Stream<F, B> {
         H2Stream {
+            body_chunks,
             reply: respond,
+            request,
+            schedule: None,
+            span,
             state: H2StreamState::Service(fut),
+            _active: active,
         }
     }
 
+    fn schedule(mut self, scheduler: Scheduler, priority: Priority) -> H2Stream<F, B> {
+        self.schedule = Some((scheduler, priority));
+        self
+    }
+
     fn poll2(&mut self) -> Poll<(), ::Error> {
         loop {
             let next = match self.state {
                 H2StreamState::Service(ref mut h) => {
                     let res = try_ready!(h.poll().map_err(::Error::new_user_service));
                     let (head, body) = res.into_parts();
+                    if let Some(ref mut request) = self.request {
+                        request.set_status(head.status);
+                    }
                     let mut res = ::http::Response::from_parts(head, ());
-                    super::strip_connection_headers(res.headers_mut());
-                    if let Some(len) = body.content_length() {
+                    ::proxy::strip_connection_headers(::http::Version::HTTP_2, res.headers_mut());
+                    if let Some(ref request) = self.request {
+                        request.set_request_id_header(res.headers_mut());
+                    }
+                    if let Some(len) = body.size_hint().exact() {
                         headers::set_content_length_if_missing(res.headers_mut(), len);
                     }
                     macro_rules! reply {
@@ -188,15 +368,29 @@ where
                         })
                     }
                     if !body.is_end_stream() {
+                        let content_length = headers::content_length_parse_all(res.headers());
+                        let turn = self.schedule.take().map(|(scheduler, mut priority)| {
+                            // the service can override the priority of the request
+                            priority.update(res.headers());
+                            scheduler.register(priority)
+                        });
                         let body_tx = reply!(false);
-                        H2StreamState::Body(PipeToSendStream::new(body, body_tx))
+                        let mut pipe = PipeToSendStream::new(body, body_tx, content_length, self.body_chunks);
+                        if let Some(turn) = turn {
+                            pipe = pipe.schedule(turn);
+                        }
+                        H2StreamState::Body(pipe)
                     } else {
                         reply!(true);
                         return Ok(Async::Ready(()));
                     }
                 },
                 H2StreamState::Body(ref mut pipe) => {
-                    return pipe.poll();
+                    let res = pipe.poll();
+                    if let Some(ref mut request) = self.request {
+                        request.set_body_written(pipe.bytes_sent());
+                    }
+                    return res;
                 }
             };
             self.state = next;
@@ -214,6 +408,8 @@ where
     type Error = ();
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let span = self.span.clone();
+        let _entered = span.enter();
         self.poll2()
             .map_err(|e| debug!("stream error: {}", e))
     }
@@ -1,14 +1,15 @@
 //! Pieces pertaining to the HTTP message protocol.
-use http::{HeaderMap, Method, StatusCode, Uri, Version};
+use http::{Extensions, HeaderMap, Method, StatusCode, Uri, Version};
 
 pub(crate) use self::h1::{dispatch, Conn, ClientTransaction, ClientUpgradeTransaction, ServerTransaction};
 
 pub(crate) mod h1;
 pub(crate) mod h2;
+pub(crate) mod proxy;
 
 
 /// An Incoming Message head. Includes request/status line, and headers.
-#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(Debug, Default)]
 pub struct MessageHead<S> {
     /// HTTP version of the message.
     pub version: Version,
@@ -16,6 +17,28 @@ pub struct MessageHead<S> {
     pub subject: S,
     /// Headers of the Incoming message.
     pub headers: HeaderMap,
+    /// Extensions of the message, such as those added while parsing.
+    pub extensions: Extensions,
+}
+
+// Extensions can't be cloned nor compared, so only the message itself is.
+impl<S: Clone> Clone for MessageHead<S> {
+    fn clone(&self) -> MessageHead<S> {
+        MessageHead {
+            version: self.version,
+            subject: self.subject.clone(),
+            headers: self.headers.clone(),
+            extensions: Extensions::new(),
+        }
+    }
+}
+
+impl<S: PartialEq> PartialEq for MessageHead<S> {
+    fn eq(&self, other: &MessageHead<S>) -> bool {
+        self.version == other.version
+            && self.subject == other.subject
+            && self.headers == other.headers
+    }
 }
 
 /// An incoming request message.
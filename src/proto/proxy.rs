@@ -0,0 +1,396 @@
+//! The HAProxy PROXY protocol, versions 1 and 2.
+//!
+//! A proxy in front of a server sends this preamble before any HTTP bytes,
+//! to tell the server the addresses of the original connection.
+//!
+//! See <https://www.haproxy.org/download/1.8/doc/proxy-protocol.txt>.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str;
+
+use bytes::BytesMut;
+
+/// The signature starting a version 2 header.
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+/// A version 1 header is at most 107 bytes, including the CRLF.
+const V1_MAX_LEN: usize = 107;
+
+/// The addresses of a connection, as reported by a PROXY protocol header.
+///
+/// When enabled on a server, this is inserted into the extensions of every
+/// request received on the connection. A client can send one to upstream
+/// servers with a [`ProxyHeaderConnector`](::client::connect::ProxyHeaderConnector).
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProxyInfo {
+    source: Option<SocketAddr>,
+    destination: Option<SocketAddr>,
+}
+
+impl ProxyInfo {
+    /// Create a `ProxyInfo` for a connection from `source` to `destination`.
+    pub fn new(source: SocketAddr, destination: SocketAddr) -> ProxyInfo {
+        ProxyInfo {
+            source: Some(source),
+            destination: Some(destination),
+        }
+    }
+
+    fn unknown() -> ProxyInfo {
+        ProxyInfo {
+            source: None,
+            destination: None,
+        }
+    }
+
+    /// The address of the original client.
+    ///
+    /// This is `None` if the proxy didn't know it, such as for `UNKNOWN`
+    /// or `LOCAL` connections, or for address families other than TCP.
+    pub fn source(&self) -> Option<SocketAddr> {
+        self.source
+    }
+
+    /// The address the original client connected to.
+    ///
+    /// This is `None` whenever `source` is `None`.
+    pub fn destination(&self) -> Option<SocketAddr> {
+        self.destination
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Invalid;
+
+/// Encode a version 2 PROXY header for `info`.
+///
+/// Unknown addresses are sent with the `LOCAL` command, meaning the
+/// receiver should use the real addresses of the connection.
+pub(crate) fn encode_v2(info: &ProxyInfo) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(16 + 36);
+    buf.extend_from_slice(V2_SIGNATURE);
+
+    let (src, dst) = match (info.source, info.destination) {
+        (Some(src), Some(dst)) => (src, dst),
+        _ => {
+            buf.extend_from_slice(&[0x20, 0x00, 0, 0]);
+            return buf;
+        }
+    };
+
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+            buf.extend_from_slice(&[0x21, 0x11, 0, 12]);
+            buf.extend_from_slice(&src_ip.octets());
+            buf.extend_from_slice(&dst_ip.octets());
+        },
+        (src_ip, dst_ip) => {
+            buf.extend_from_slice(&[0x21, 0x21, 0, 36]);
+            buf.extend_from_slice(&to_ipv6(src_ip).octets());
+            buf.extend_from_slice(&to_ipv6(dst_ip).octets());
+        },
+    }
+    buf.extend_from_slice(&[(src.port() >> 8) as u8, src.port() as u8]);
+    buf.extend_from_slice(&[(dst.port() >> 8) as u8, dst.port() as u8]);
+    buf
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// Try to parse a PROXY header from the start of `buf`.
+///
+/// Returns `Ok(None)` if more bytes are needed. On success, the header is
+/// removed from `buf`.
+pub(crate) fn parse(buf: &mut BytesMut) -> Result<Option<ProxyInfo>, Invalid> {
+    if buf.is_empty() {
+        return Ok(None);
+    }
+    if buf[0] == b'P' {
+        parse_v1(buf)
+    } else if buf[0] == b'\r' {
+        parse_v2(buf)
+    } else {
+        Err(Invalid)
+    }
+}
+
+fn parse_v1(buf: &mut BytesMut) -> Result<Option<ProxyInfo>, Invalid> {
+    let len = match buf.windows(2).position(|w| w == b"\r\n") {
+        Some(pos) => pos + 2,
+        None => {
+            let prefix = &b"PROXY "[..];
+            let n = ::std::cmp::min(buf.len(), prefix.len());
+            if buf[..n] != prefix[..n] || buf.len() >= V1_MAX_LEN {
+                return Err(Invalid);
+            }
+            return Ok(None);
+        }
+    };
+    if len > V1_MAX_LEN {
+        return Err(Invalid);
+    }
+
+    let info = {
+        let line = str::from_utf8(&buf[..len - 2]).map_err(|_| Invalid)?;
+        let mut parts = line.split(' ');
+        if parts.next() != Some("PROXY") {
+            return Err(Invalid);
+        }
+        match parts.next() {
+            Some(proto @ "TCP4") | Some(proto @ "TCP6") => {
+                let v6 = proto == "TCP6";
+                let src_ip = parse_ip(&mut parts, v6)?;
+                let dst_ip = parse_ip(&mut parts, v6)?;
+                let src_port = parse_next::<u16>(&mut parts)?;
+                let dst_port = parse_next::<u16>(&mut parts)?;
+                if parts.next().is_some() {
+                    return Err(Invalid);
+                }
+                ProxyInfo::new(
+                    SocketAddr::new(src_ip, src_port),
+                    SocketAddr::new(dst_ip, dst_port),
+                )
+            },
+            // the rest of the line is ignored
+            Some("UNKNOWN") => ProxyInfo::unknown(),
+            _ => return Err(Invalid),
+        }
+    };
+
+    buf.split_to(len);
+    Ok(Some(info))
+}
+
+// An address of the family of the protocol, IPv6 for `TCP6`.
+fn parse_ip(parts: &mut str::Split<char>, v6: bool) -> Result<IpAddr, Invalid> {
+    let ip = parse_next::<IpAddr>(parts)?;
+    if ip.is_ipv6() != v6 {
+        return Err(Invalid);
+    }
+    Ok(ip)
+}
+
+fn parse_next<T: str::FromStr>(parts: &mut str::Split<char>) -> Result<T, Invalid> {
+    parts
+        .next()
+        .and_then(|s| s.parse().ok())
+        .ok_or(Invalid)
+}
+
+fn parse_v2(buf: &mut BytesMut) -> Result<Option<ProxyInfo>, Invalid> {
+    let n = ::std::cmp::min(buf.len(), V2_SIGNATURE.len());
+    if buf[..n] != V2_SIGNATURE[..n] {
+        return Err(Invalid);
+    }
+    if buf.len() < 16 {
+        return Ok(None);
+    }
+
+    let ver_cmd = buf[12];
+    let family = buf[13];
+    let len = ((buf[14] as usize) << 8) | buf[15] as usize;
+    if buf.len() < 16 + len {
+        return Ok(None);
+    }
+
+    if ver_cmd >> 4 != 2 {
+        return Err(Invalid);
+    }
+
+    let info = {
+        let addrs = &buf[16..16 + len];
+        match ver_cmd & 0x0F {
+            // LOCAL: health checks from the proxy itself
+            0x0 => ProxyInfo::unknown(),
+            // PROXY
+            0x1 => match family {
+                // TCP over IPv4
+                0x11 => {
+                    if addrs.len() < 12 {
+                        return Err(Invalid);
+                    }
+                    let src = Ipv4Addr::new(addrs[0], addrs[1], addrs[2], addrs[3]);
+                    let dst = Ipv4Addr::new(addrs[4], addrs[5], addrs[6], addrs[7]);
+                    ProxyInfo::new(
+                        SocketAddr::new(IpAddr::V4(src), be_u16(&addrs[8..10])),
+                        SocketAddr::new(IpAddr::V4(dst), be_u16(&addrs[10..12])),
+                    )
+                },
+                // TCP over IPv6
+                0x21 => {
+                    if addrs.len() < 36 {
+                        return Err(Invalid);
+                    }
+                    let src = ipv6(&addrs[0..16]);
+                    let dst = ipv6(&addrs[16..32]);
+                    ProxyInfo::new(
+                        SocketAddr::new(IpAddr::V6(src), be_u16(&addrs[32..34])),
+                        SocketAddr::new(IpAddr::V6(dst), be_u16(&addrs[34..36])),
+                    )
+                },
+                // UNSPEC, UDP, and unix sockets
+                _ => ProxyInfo::unknown(),
+            },
+            _ => return Err(Invalid),
+        }
+    };
+
+    buf.split_to(16 + len);
+    Ok(Some(info))
+}
+
+fn be_u16(bytes: &[u8]) -> u16 {
+    ((bytes[0] as u16) << 8) | bytes[1] as u16
+}
+
+fn ipv6(bytes: &[u8]) -> Ipv6Addr {
+    let mut segments = [0u16; 8];
+    for (i, segment) in segments.iter_mut().enumerate() {
+        *segment = be_u16(&bytes[i * 2..i * 2 + 2]);
+    }
+    Ipv6Addr::new(
+        segments[0], segments[1], segments[2], segments[3],
+        segments[4], segments[5], segments[6], segments[7],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(s: &[u8]) -> Result<Option<ProxyInfo>, Invalid> {
+        parse(&mut BytesMut::from(s))
+    }
+
+    #[test]
+    fn v1_tcp4() {
+        let mut buf = BytesMut::from(&b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\nGET / HTTP/1.1\r\n"[..]);
+        let info = parse(&mut buf).unwrap().unwrap();
+        assert_eq!(info.source(), Some("192.168.0.1:56324".parse().unwrap()));
+        assert_eq!(info.destination(), Some("192.168.0.11:443".parse().unwrap()));
+        assert_eq!(buf, "GET / HTTP/1.1\r\n");
+    }
+
+    #[test]
+    fn v1_tcp6() {
+        let info = parse_str(b"PROXY TCP6 ::1 2001:db8::1 1234 80\r\n").unwrap().unwrap();
+        assert_eq!(info.source(), Some("[::1]:1234".parse().unwrap()));
+        assert_eq!(info.destination(), Some("[2001:db8::1]:80".parse().unwrap()));
+    }
+
+    #[test]
+    fn v1_unknown() {
+        let info = parse_str(b"PROXY UNKNOWN ffff::1 ffff::2 1 2\r\n").unwrap().unwrap();
+        assert_eq!(info.source(), None);
+        assert_eq!(info.destination(), None);
+    }
+
+    #[test]
+    fn v1_partial() {
+        assert_eq!(parse_str(b"PRO"), Ok(None));
+        assert_eq!(parse_str(b"PROXY TCP4 192.168.0.1"), Ok(None));
+    }
+
+    #[test]
+    fn v1_invalid() {
+        assert_eq!(parse_str(b"GET / HTTP/1.1\r\n"), Err(Invalid));
+        assert_eq!(parse_str(b"PROXY TCP4 1.2.3.4 5.6.7.8 1 99999\r\n"), Err(Invalid));
+        assert_eq!(parse_str(b"PROXY TCP4 1.2.3.4 5.6.7.8 1\r\n"), Err(Invalid));
+        assert_eq!(parse_str(b"PROXY UDP4 1.2.3.4 5.6.7.8 1 2\r\n"), Err(Invalid));
+        assert_eq!(parse_str(&[b'P'; V1_MAX_LEN][..]), Err(Invalid));
+    }
+
+    #[test]
+    fn v1_mismatched_family() {
+        assert_eq!(parse_str(b"PROXY TCP4 ::1 2001:db8::1 1234 80\r\n"), Err(Invalid));
+        assert_eq!(parse_str(b"PROXY TCP4 1.2.3.4 2001:db8::1 1234 80\r\n"), Err(Invalid));
+        assert_eq!(parse_str(b"PROXY TCP6 1.2.3.4 5.6.7.8 1234 80\r\n"), Err(Invalid));
+        assert_eq!(parse_str(b"PROXY TCP6 ::1 5.6.7.8 1234 80\r\n"), Err(Invalid));
+    }
+
+    #[test]
+    fn v2_tcp4() {
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0x21, 0x11, 0, 12]);
+        bytes.extend_from_slice(&[127, 0, 0, 1, 10, 0, 0, 1, 0x1F, 0x90, 0, 80]);
+        bytes.extend_from_slice(b"GET");
+        let mut buf = BytesMut::from(bytes);
+        let info = parse(&mut buf).unwrap().unwrap();
+        assert_eq!(info.source(), Some("127.0.0.1:8080".parse().unwrap()));
+        assert_eq!(info.destination(), Some("10.0.0.1:80".parse().unwrap()));
+        assert_eq!(buf, "GET");
+    }
+
+    #[test]
+    fn v2_tcp6_with_tlvs() {
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0x21, 0x21, 0, 40]);
+        bytes.extend_from_slice(&[0; 15]);
+        bytes.push(1);
+        bytes.extend_from_slice(&[0; 15]);
+        bytes.push(2);
+        bytes.extend_from_slice(&[0, 1, 0, 2]);
+        // a NOOP TLV
+        bytes.extend_from_slice(&[0x04, 0, 1, 0]);
+        let mut buf = BytesMut::from(bytes);
+        let info = parse(&mut buf).unwrap().unwrap();
+        assert_eq!(info.source(), Some("[::1]:1".parse().unwrap()));
+        assert_eq!(info.destination(), Some("[::2]:2".parse().unwrap()));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn v2_local() {
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0x20, 0x00, 0, 0]);
+        let info = parse_str(&bytes).unwrap().unwrap();
+        assert_eq!(info.source(), None);
+    }
+
+    #[test]
+    fn v2_partial() {
+        assert_eq!(parse_str(&V2_SIGNATURE[..5]), Ok(None));
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0x21, 0x11, 0, 12, 127, 0]);
+        assert_eq!(parse_str(&bytes), Ok(None));
+    }
+
+    #[test]
+    fn v2_encode_roundtrip() {
+        let info = ProxyInfo::new(
+            "192.168.0.1:56324".parse().unwrap(),
+            "10.0.0.1:443".parse().unwrap(),
+        );
+        let mut buf = BytesMut::from(encode_v2(&info));
+        assert_eq!(parse(&mut buf), Ok(Some(info)));
+        assert!(buf.is_empty());
+
+        let info = ProxyInfo::new(
+            "127.0.0.1:1".parse().unwrap(),
+            "[2001:db8::1]:2".parse().unwrap(),
+        );
+        let parsed = parse_str(&encode_v2(&info)).unwrap().unwrap();
+        assert_eq!(parsed.source(), Some("[::ffff:127.0.0.1]:1".parse().unwrap()));
+        assert_eq!(parsed.destination(), info.destination());
+
+        let parsed = parse_str(&encode_v2(&ProxyInfo::unknown())).unwrap().unwrap();
+        assert_eq!(parsed, ProxyInfo::unknown());
+    }
+
+    #[test]
+    fn v2_invalid() {
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0x11, 0x11, 0, 0]);
+        assert_eq!(parse_str(&bytes), Err(Invalid));
+
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0x21, 0x11, 0, 4, 1, 2, 3, 4]);
+        assert_eq!(parse_str(&bytes), Err(Invalid));
+
+        assert_eq!(parse_str(b"\r\n\r\nnope"), Err(Invalid));
+    }
+}
@@ -0,0 +1,199 @@
+//! Utilities for proxies and gateways built with hyper.
+//!
+//! A proxy forwarding a message must remove the headers that only apply to
+//! the connection it was received on, and should add itself to the `Via`
+//! header, as described in [RFC 7230, section 5.7.1][via] and
+//! [section 6.1][hop].
+//!
+//! [via]: https://tools.ietf.org/html/rfc7230#section-5.7.1
+//! [hop]: https://tools.ietf.org/html/rfc7230#section-6.1
+
+use http::header::{
+    HeaderName, HeaderValue, InvalidHeaderValue, CONNECTION, PROXY_AUTHENTICATE,
+    PROXY_AUTHORIZATION, TE, TRAILER, TRANSFER_ENCODING, UPGRADE, VIA,
+};
+use http::{HeaderMap, Version};
+
+/// How an HTTP/1 connection handles a received message whose framing
+/// headers are ambiguous.
+///
+/// This covers messages with both `Content-Length` and `Transfer-Encoding`,
+/// with more than one `Content-Length` value, or with a transfer-coding
+/// hyper doesn't know. Proxies can disagree about the length of such
+/// messages, which can be used to smuggle requests past them, as described
+/// in [RFC 7230, section 3.3.3][rfc].
+///
+/// Messages that can't be framed at all, such as those with differing
+/// `Content-Length` values, are always rejected.
+///
+/// [rfc]: https://tools.ietf.org/html/rfc7230#section-3.3.3
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FramingPolicy {
+    /// Reject the message.
+    ///
+    /// This is the default for servers.
+    Reject,
+    /// Normalize the framing headers, so that they can be safely forwarded.
+    ///
+    /// `Content-Length` is removed when `Transfer-Encoding` is present,
+    /// and duplicate `Content-Length` values are merged into one. Unknown
+    /// transfer-codings are allowed. Every change is recorded in a
+    /// [`FramingNormalization`](::ext::FramingNormalization) extension.
+    ///
+    /// This is the default for clients, and is meant for proxy chains where
+    /// every hop is controlled.
+    Normalize,
+}
+
+/// Remove the hop-by-hop headers of a message.
+///
+/// This removes the `Connection` header, any headers it names, and the
+/// headers that are always connection-specific, such as `Keep-Alive`,
+/// `Transfer-Encoding` and `Upgrade`.
+///
+/// The `version` is the version the message will be sent with. HTTP/2
+/// forbids all of these headers, except for `TE: trailers`, which is kept.
+pub fn strip_connection_headers(version: Version, headers: &mut HeaderMap) {
+    let is_h2 = version == Version::HTTP_2;
+
+    // List of connection headers from:
+    // https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Connection
+    let connection_headers = [
+        HeaderName::from_lowercase(b"keep-alive").unwrap(),
+        HeaderName::from_lowercase(b"proxy-connection").unwrap(),
+        PROXY_AUTHENTICATE,
+        PROXY_AUTHORIZATION,
+        TRAILER,
+        TRANSFER_ENCODING,
+        UPGRADE,
+    ];
+
+    for header in connection_headers.iter() {
+        if headers.remove(header).is_some() && is_h2 {
+            warn!("Connection header illegal in HTTP/2: {}", header.as_str());
+        }
+    }
+
+    let te_trailers = is_h2 && headers
+        .get(TE)
+        .map(|te| te == "trailers")
+        .unwrap_or(false);
+    if !te_trailers && headers.remove(TE).is_some() && is_h2 {
+        warn!("Connection header illegal in HTTP/2: {}", TE.as_str());
+    }
+
+    // The header is removed even if it isn't valid, but then there is no
+    // way to know which other headers it meant to name.
+    let names = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|s| s.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect::<Vec<_>>();
+
+    if headers.remove(CONNECTION).is_some() && is_h2 {
+        warn!(
+            "Connection header illegal in HTTP/2: {}",
+            CONNECTION.as_str()
+        );
+    }
+
+    // A `Connection` header may have a comma-separated list of names of other headers that
+    // are meant for only this specific connection.
+    for name in names {
+        headers.remove(name);
+    }
+}
+
+/// Append an entry for this proxy to the `Via` header.
+///
+/// The `version` is the version the message was received with, and
+/// `pseudonym` names this proxy, usually by its host name. Any existing
+/// `Via` values are kept, as a proxy must not remove them.
+///
+/// # Errors
+///
+/// Returns an error if `pseudonym` isn't valid in a header value.
+pub fn append_via(version: Version, pseudonym: &str, headers: &mut HeaderMap) -> Result<(), InvalidHeaderValue> {
+    let protocol = if version == Version::HTTP_2 {
+        "2"
+    } else if version == Version::HTTP_10 {
+        "1.0"
+    } else if version == Version::HTTP_09 {
+        "0.9"
+    } else {
+        "1.1"
+    };
+    let value = HeaderValue::from_str(&format!("{} {}", protocol, pseudonym))?;
+    headers.append(VIA, value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use http::header::{CONNECTION, CONTENT_TYPE, TE, TRANSFER_ENCODING, VIA};
+    use http::{HeaderMap, Version};
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for &(name, value) in pairs {
+            headers.append(name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn strip_named_and_hop_by_hop() {
+        let mut map = headers(&[
+            ("connection", "keep-alive, x-foo"),
+            ("connection", "x-bar"),
+            ("keep-alive", "timeout=5"),
+            ("transfer-encoding", "chunked"),
+            ("x-foo", "1"),
+            ("x-bar", "2"),
+            ("content-type", "text/plain"),
+        ]);
+        strip_connection_headers(Version::HTTP_11, &mut map);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map[CONTENT_TYPE], "text/plain");
+    }
+
+    #[test]
+    fn strip_keeps_te_trailers_for_h2() {
+        let mut map = headers(&[("te", "trailers")]);
+        strip_connection_headers(Version::HTTP_11, &mut map);
+        assert!(map.get(TE).is_none());
+
+        let mut map = headers(&[("te", "trailers")]);
+        strip_connection_headers(Version::HTTP_2, &mut map);
+        assert_eq!(map[TE], "trailers");
+
+        let mut map = headers(&[("te", "gzip"), ("transfer-encoding", "chunked")]);
+        strip_connection_headers(Version::HTTP_2, &mut map);
+        assert!(map.get(TE).is_none());
+        assert!(map.get(TRANSFER_ENCODING).is_none());
+    }
+
+    #[test]
+    fn strip_invalid_connection_header() {
+        let mut map = HeaderMap::new();
+        map.insert(CONNECTION, HeaderValue::from_bytes(b"\xFAx-foo").unwrap());
+        map.insert("x-foo", "1".parse().unwrap());
+        strip_connection_headers(Version::HTTP_11, &mut map);
+        assert!(map.get(CONNECTION).is_none());
+        assert_eq!(map["x-foo"], "1");
+    }
+
+    #[test]
+    fn append_via_entries() {
+        let mut map = headers(&[("via", "1.0 fred")]);
+        append_via(Version::HTTP_11, "hyper.rs", &mut map).unwrap();
+        append_via(Version::HTTP_2, "proxy2", &mut map).unwrap();
+        let vias = map.get_all(VIA).iter().collect::<Vec<_>>();
+        assert_eq!(vias, ["1.0 fred", "1.1 hyper.rs", "2 proxy2"]);
+
+        append_via(Version::HTTP_11, "bad\nname", &mut map).unwrap_err();
+    }
+}
@@ -0,0 +1,320 @@
+//! Accepting connections from listeners of other runtimes.
+//!
+//! `Server::bind` listens with tokio's `TcpListener`. A listener of another
+//! runtime implements [`Listener`](Listener) instead, and is given to
+//! [`Server::builder`](::Server::builder) as an [`Incoming`](Incoming),
+//! which accepts its connections the way an `AddrIncoming` does, sleeping
+//! on errors, and tells their remote addresses to the access log.
+//!
+//! # Example
+//!
+//! ```
+//! # extern crate futures;
+//! # extern crate hyper;
+//! # extern crate tokio_io;
+//! use std::io;
+//! use std::net::SocketAddr;
+//!
+//! use futures::Poll;
+//! use hyper::server::accept::{Incoming, Listener};
+//! # use tokio_io::{AsyncRead, AsyncWrite};
+//!
+//! // the types of some other runtime
+//! # struct TcpStream;
+//! # impl io::Read for TcpStream {
+//! #     fn read(&mut self, _: &mut [u8]) -> io::Result<usize> { Ok(0) }
+//! # }
+//! # impl io::Write for TcpStream {
+//! #     fn write(&mut self, buf: &[u8]) -> io::Result<usize> { Ok(buf.len()) }
+//! #     fn flush(&mut self) -> io::Result<()> { Ok(()) }
+//! # }
+//! # impl AsyncRead for TcpStream {}
+//! # impl AsyncWrite for TcpStream {
+//! #     fn shutdown(&mut self) -> Poll<(), io::Error> { Ok(().into()) }
+//! # }
+//! # struct TcpListener;
+//! # impl TcpListener {
+//! #     fn poll_accept(&mut self) -> Poll<(TcpStream, SocketAddr), io::Error> {
+//! #         Ok(futures::Async::NotReady)
+//! #     }
+//! #     fn local_addr(&self) -> io::Result<SocketAddr> {
+//! #         Ok(([127, 0, 0, 1], 3000).into())
+//! #     }
+//! # }
+//! struct MyListener(TcpListener);
+//!
+//! impl Listener for MyListener {
+//!     type Conn = TcpStream;
+//!
+//!     fn poll_accept(&mut self) -> Poll<(TcpStream, SocketAddr), io::Error> {
+//!         self.0.poll_accept()
+//!     }
+//!
+//!     fn local_addr(&self) -> io::Result<SocketAddr> {
+//!         self.0.local_addr()
+//!     }
+//! }
+//!
+//! # fn main() {
+//! let server = hyper::Server::builder(Incoming::new(MyListener(TcpListener)));
+//! # drop(server);
+//! # }
+//! ```
+
+use std::any::Any;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures::{Async, Future, Poll, Stream};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use clock::{Clock, Delay, Time};
+
+/// A listener of connections, such as the `TcpListener` of a runtime.
+pub trait Listener {
+    /// The accepted connections.
+    type Conn: AsyncRead + AsyncWrite;
+
+    /// Accepts the next connection, along with its remote address.
+    ///
+    /// Like any `poll`, this must arrange for the current task to be
+    /// notified once a connection is ready, if it returns `NotReady`.
+    fn poll_accept(&mut self) -> Poll<(Self::Conn, SocketAddr), io::Error>;
+
+    /// Returns the local address the listener is bound to.
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+/// A stream of the connections of a [`Listener`](Listener).
+#[must_use = "streams do nothing unless polled"]
+pub struct Incoming<L> {
+    listener: L,
+    errors: AcceptErrors,
+}
+
+impl<L: Listener> Incoming<L> {
+    /// Accepts the connections of `listener`.
+    pub fn new(listener: L) -> Incoming<L> {
+        Incoming {
+            listener: listener,
+            errors: AcceptErrors::new(cfg!(feature = "runtime")),
+        }
+    }
+
+    /// Get the local address bound to the listener.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Get a reference to the listener.
+    pub fn get_ref(&self) -> &L {
+        &self.listener
+    }
+
+    /// Get a mutable reference to the listener.
+    pub fn get_mut(&mut self) -> &mut L {
+        &mut self.listener
+    }
+
+    /// Set whether to sleep on accept errors.
+    ///
+    /// See [`AddrIncoming::set_sleep_on_errors`](::server::conn::AddrIncoming::set_sleep_on_errors).
+    ///
+    /// Default is `true` with the `runtime` feature. Without it, sleeping
+    /// needs a clock from [`set_clock`](Incoming::set_clock), and the
+    /// default is `false`.
+    pub fn set_sleep_on_errors(&mut self, val: bool) {
+        self.errors.sleep = val;
+    }
+
+    /// Set the [`Clock`](::clock::Clock) to sleep on accept errors with.
+    pub fn set_clock<C: Clock>(&mut self, clock: C) {
+        self.errors.time = Time::new(clock);
+    }
+}
+
+impl<L> Stream for Incoming<L>
+where
+    L: Listener,
+    L::Conn: Send + 'static,
+{
+    type Item = Accepted;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let (io, remote_addr) = try_ready!(self.errors.poll_accept(&mut self.listener));
+        Ok(Async::Ready(Some(Accepted {
+            io: Box::new(io),
+            remote_addr: remote_addr,
+        })))
+    }
+}
+
+impl<L: fmt::Debug> fmt::Debug for Incoming<L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Incoming")
+            .field("listener", &self.listener)
+            .field("sleep_on_errors", &self.errors.sleep)
+            .finish()
+    }
+}
+
+/// A connection accepted by an [`Incoming`](Incoming).
+pub struct Accepted {
+    io: Box<Io + Send>,
+    remote_addr: SocketAddr,
+}
+
+trait Io: AsyncRead + AsyncWrite {}
+
+impl<T: AsyncRead + AsyncWrite> Io for T {}
+
+impl Accepted {
+    /// Returns the remote address of the connection.
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+}
+
+impl Read for Accepted {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io.read(buf)
+    }
+}
+
+impl Write for Accepted {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl AsyncRead for Accepted {
+    #[inline]
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.io.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl AsyncWrite for Accepted {
+    #[inline]
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+impl fmt::Debug for Accepted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Accepted")
+            .field("remote_addr", &self.remote_addr)
+            .finish()
+    }
+}
+
+/// Returns the remote address of `io`, if it's an `Accepted` or an
+/// `AddrStream`.
+pub(super) fn remote_addr(io: &Any) -> Option<SocketAddr> {
+    if let Some(accepted) = io.downcast_ref::<Accepted>() {
+        return Some(accepted.remote_addr);
+    }
+    #[cfg(feature = "runtime")]
+    {
+        super::tcp::remote_addr(io)
+    }
+    #[cfg(not(feature = "runtime"))]
+    {
+        None
+    }
+}
+
+/// How a stream of connections handles accept errors.
+pub(super) struct AcceptErrors {
+    pub(super) sleep: bool,
+    pub(super) time: Time,
+    timeout: Option<Delay>,
+}
+
+impl AcceptErrors {
+    pub(super) fn new(sleep: bool) -> AcceptErrors {
+        AcceptErrors {
+            sleep: sleep,
+            time: Time::default(),
+            timeout: None,
+        }
+    }
+
+    /// Accepts the next connection of `listener`, skipping the errors of
+    /// single connections, and sleeping on others if enabled.
+    pub(super) fn poll_accept<L: Listener>(&mut self, listener: &mut L) -> Poll<(L::Conn, SocketAddr), io::Error> {
+        // Check if a previous timeout is active that was set by IO errors.
+        if let Some(ref mut to) = self.timeout {
+            match to.poll() {
+                Ok(Async::Ready(())) => {}
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(err) => {
+                    error!("sleep timer error: {}", err);
+                }
+            }
+        }
+        self.timeout = None;
+        loop {
+            match listener.poll_accept() {
+                Ok(Async::Ready(accepted)) => return Ok(Async::Ready(accepted)),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => {
+                    if self.sleep {
+                        // Connection errors can be ignored directly, continue by
+                        // accepting the next request.
+                        if is_connection_error(&e) {
+                            debug!("accepted connection already errored: {}", e);
+                            continue;
+                        }
+                        // Sleep 1s.
+                        let delay = self.time.now() + Duration::from_secs(1);
+                        let mut timeout = self.time.delay(delay);
+
+                        match timeout.poll() {
+                            Ok(Async::Ready(())) => {
+                                // Wow, it's been a second already? Ok then...
+                                error!("accept error: {}", e);
+                                continue
+                            },
+                            Ok(Async::NotReady) => {
+                                error!("accept error: {}", e);
+                                self.timeout = Some(timeout);
+                                return Ok(Async::NotReady);
+                            },
+                            Err(timer_err) => {
+                                error!("couldn't sleep on error, timer error: {}", timer_err);
+                                return Err(e);
+                            }
+                        }
+                    } else {
+                        return Err(e);
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// This function defines errors that are per-connection. Which basically
+/// means that if we get this error from `accept()` system call it means
+/// next connection might be ready to be accepted.
+///
+/// All other errors will incur a timeout before next `accept()` is performed.
+/// The timeout is useful to handle resource exhaustion errors like ENFILE
+/// and EMFILE. Otherwise, could enter into tight loop.
+fn is_connection_error(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::ConnectionRefused ||
+    e.kind() == io::ErrorKind::ConnectionAborted ||
+    e.kind() == io::ErrorKind::ConnectionReset
+}
@@ -9,24 +9,39 @@
 //! higher-level [Server](super) API.
 
 use std::fmt;
-#[cfg(feature = "runtime")] use std::net::SocketAddr;
+use std::net::SocketAddr;
 use std::sync::Arc;
 #[cfg(feature = "runtime")] use std::time::Duration;
 
 use super::rewind::Rewind;
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use futures::{Async, Future, Poll, Stream};
 use futures::future::{Either, Executor};
+use http::Version;
+use http::header::HeaderName;
 use tokio_io::{AsyncRead, AsyncWrite};
 #[cfg(feature = "runtime")] use tokio_reactor::Handle;
 
 use common::Exec;
+use exec::{Spawner, TaskKind};
+use common::drain;
+use instrument::{ConnectionGuard, ConnectionStats, Counted, Exchange, FrameObserver, Instrument, Instrumentation, Tap};
 use proto;
+use proto::proxy;
 use body::{Body, Payload};
 use service::{NewService, Service};
 use error::{Kind, Parse};
 
-#[cfg(feature = "runtime")] pub use super::tcp::AddrIncoming;
+#[cfg(feature = "runtime")] pub use super::tcp::{AddrIncoming, ListenOptions, MultiAddrIncoming};
+pub use proto::h2::SchedulingPolicy;
+pub use proto::proxy::ProxyInfo;
+
+/// How many bytes to read at a time while looking for a PROXY header.
+const PROXY_READ_SIZE: usize = 256;
+
+/// The `SETTINGS_MAX_CONCURRENT_STREAMS` advertised in strict conformance
+/// mode, the smallest value RFC 7540 recommends.
+const STRICT_MAX_CONCURRENT_STREAMS: u32 = 100;
 
 /// A lower-level configuration of the HTTP protocol.
 ///
@@ -37,11 +52,29 @@ use error::{Kind, Parse};
 #[derive(Clone, Debug)]
 pub struct Http {
     exec: Exec,
+    h1_absolute_form: bool,
+    h1_buf_pool: Option<proto::h1::BufPool>,
+    h1_buf_sizes: proto::h1::BufSizes,
+    h1_lenient_target: bool,
+    h1_lenient_uri: bool,
+    h1_record_request_line: bool,
+    h1_preserve_header_case: bool,
+    h1_record_header_case: bool,
+    h1_record_header_order: bool,
+    h1_lf_line_endings: bool,
+    h1_framing: ::proxy::FramingPolicy,
+    h1_max_request_line: Option<usize>,
     h1_writev: bool,
+    h1_force_writev: bool,
+    h2_body_chunks: proto::h2::BodyChunks,
+    h2_scheduling: SchedulingPolicy,
+    h2_spawn_streams: bool,
+    instrument: Option<Instrument>,
     mode: ConnectionMode,
     keep_alive: bool,
-    max_buf_size: Option<usize>,
     pipeline_flush: bool,
+    proxy_protocol: bool,
+    strict: bool,
 }
 
 /// The internal mode of HTTP protocol which indicates the behavior when an parse error occurs.
@@ -101,12 +134,66 @@ where
             proto::ServerTransaction,
         >,
         proto::h2::Server<
-            Rewind<T>,
+            Rewind<Counted<T>>,
             S,
             S::ResBody,
         >,
     >>,
+    /// Reports the connection closed once dropped.
+    _connection: ConnectionGuard,
+    /// Spawns the streams of an HTTP/2 connection, if it falls back to it.
+    exec: Exec,
     fallback: bool,
+    h2_body_chunks: proto::h2::BodyChunks,
+    h2_max_concurrent_streams: Option<u32>,
+    h2_scheduling: SchedulingPolicy,
+    h2_spawn_streams: bool,
+    instrument: Instrument,
+    proxy: Option<ProxyPreamble<T, S>>,
+    proxy_info: Option<ProxyInfo>,
+}
+
+/// A future binding an HTTP/1 connection with a Service, where neither
+/// need to be `Send`.
+///
+/// Returned by [`Http::serve_connection_local`](Http::serve_connection_local).
+#[must_use = "futures do nothing unless polled"]
+pub struct LocalConnection<T, S>
+where
+    S: Service,
+{
+    conn: proto::h1::Dispatcher<
+        proto::h1::dispatch::Server<S>,
+        S::ResBody,
+        T,
+        proto::ServerTransaction,
+    >,
+    /// Reports the connection closed once dropped.
+    _connection: ConnectionGuard,
+    instrument: Instrument,
+}
+
+/// A connection still waiting on its PROXY protocol header.
+struct ProxyPreamble<T, S> {
+    io: T,
+    read_buf: BytesMut,
+    service: S,
+    protocol: Http,
+}
+
+/// A snapshot of the state of a server connection, for debugging.
+///
+/// Taken by [`Connection::state`](Connection::state), its `Debug` output
+/// shows what a connection that seems stuck is waiting on.
+#[derive(Clone, Debug)]
+pub struct ConnectionState {
+    pub(crate) version: Option<Version>,
+    pub(crate) reading: &'static str,
+    pub(crate) writing: &'static str,
+    pub(crate) keep_alive: bool,
+    pub(crate) read_buffered: usize,
+    pub(crate) write_buffered: usize,
+    pub(crate) in_flight: usize,
 }
 
 /// Deconstructed parts of a `Connection`.
@@ -139,11 +226,29 @@ impl Http {
     pub fn new() -> Http {
         Http {
             exec: Exec::Default,
+            h1_absolute_form: true,
+            h1_buf_pool: Some(proto::h1::BufPool::new(proto::h1::DEFAULT_MAX_POOLED_BUFS)),
+            h1_buf_sizes: proto::h1::BufSizes::default(),
+            h1_lenient_target: false,
+            h1_lenient_uri: false,
+            h1_record_request_line: false,
+            h1_preserve_header_case: false,
+            h1_record_header_case: false,
+            h1_record_header_order: false,
+            h1_lf_line_endings: true,
+            h1_framing: ::proxy::FramingPolicy::Reject,
+            h1_max_request_line: None,
             h1_writev: true,
+            h1_force_writev: false,
+            h2_body_chunks: proto::h2::BodyChunks::default(),
+            h2_scheduling: SchedulingPolicy::default(),
+            h2_spawn_streams: true,
+            instrument: None,
             mode: ConnectionMode::Fallback,
             keep_alive: true,
-            max_buf_size: None,
             pipeline_flush: false,
+            proxy_protocol: false,
+            strict: false,
         }
     }
 
@@ -159,6 +264,143 @@ impl Http {
         self
     }
 
+    /// Set whether HTTP/1 requests may have an absolute-form target.
+    ///
+    /// Clients of forward proxies send the full URI in the request-line,
+    /// such as `GET http://example.com/ HTTP/1.1`, which is then kept in
+    /// `Request::uri()`. Origin servers that want to stay strict can disable
+    /// this, rejecting such requests with `400 Bad Request`.
+    ///
+    /// Default is `true`.
+    pub fn http1_absolute_form(&mut self, val: bool) -> &mut Self {
+        self.h1_absolute_form = val;
+        self
+    }
+
+    /// Set whether HTTP/1 requests with an invalid request-target are
+    /// passed to the service.
+    ///
+    /// Some devices and scanners send targets that aren't valid URIs.
+    /// Normally such a request is answered with `400 Bad Request` and the
+    /// connection is closed. When enabled, the request is instead given a
+    /// `Uri` of `/`, and the original bytes are available as a
+    /// [`RawTarget`](::ext::RawTarget) in its extensions.
+    ///
+    /// Default is `false`.
+    pub fn http1_lenient_target(&mut self, val: bool) -> &mut Self {
+        self.h1_lenient_target = val;
+        self
+    }
+
+    /// Set whether HTTP/1 connections will percent-encode characters in
+    /// request-targets that strict URI parsing rejects.
+    ///
+    /// Some old clients send targets with unencoded spaces, `|` or `^`.
+    /// When enabled, these are percent-encoded, so that `Request::uri()`
+    /// contains `%20`, `%7C` or `%5E` instead, rather than the request
+    /// being answered with a `400 Bad Request`.
+    ///
+    /// Default is `false`.
+    pub fn http1_lenient_uri(&mut self, val: bool) -> &mut Self {
+        self.h1_lenient_uri = val;
+        self
+    }
+
+    /// Set whether HTTP/1 connections will record the request-line of each
+    /// request as it was received.
+    ///
+    /// When enabled, the method, target and version sent by the client are
+    /// added in a [`RawRequestLine`](::ext::RawRequestLine) extension. This
+    /// is useful for diagnosing misbehaving clients, especially along with
+    /// [`http1_lenient_uri`](Http::http1_lenient_uri), which changes the
+    /// target seen in `Request::uri()`.
+    ///
+    /// Default is `false`.
+    pub fn http1_record_request_line(&mut self, val: bool) -> &mut Self {
+        self.h1_record_request_line = val;
+        self
+    }
+
+    /// Set whether HTTP/1 connections will preserve the case of header names.
+    ///
+    /// When enabled, the exact casing of header names in each request is
+    /// recorded in a [`HeaderCaseMap`](::ext::HeaderCaseMap) extension, and
+    /// responses carrying a `HeaderCaseMap` in their extensions are written
+    /// with the casing it contains. This is for peers that wrongly match
+    /// header names case-sensitively.
+    ///
+    /// Default is `false`.
+    pub fn http1_preserve_header_case(&mut self, val: bool) -> &mut Self {
+        self.h1_preserve_header_case = val;
+        self
+    }
+
+    /// Set whether HTTP/1 connections will record the case of header names.
+    ///
+    /// When enabled, the exact casing of header names in each request is
+    /// recorded in a [`HeaderCaseMap`](::ext::HeaderCaseMap) extension, but
+    /// unlike [`http1_preserve_header_case`](Http::http1_preserve_header_case),
+    /// responses are still written with lowercase names. A proxy can use
+    /// this to forward requests with their original casing.
+    ///
+    /// Default is `false`.
+    pub fn http1_record_header_case(&mut self, val: bool) -> &mut Self {
+        self.h1_record_header_case = val;
+        self
+    }
+
+    /// Set whether HTTP/1 connections will record the order of headers.
+    ///
+    /// When enabled, the order of the header fields in each request is
+    /// recorded in a [`HeaderOrder`](::ext::HeaderOrder) extension. A proxy
+    /// can use this to forward requests with their fields in the same order.
+    ///
+    /// Responses are always written in the order of their `HeaderMap`.
+    ///
+    /// Default is `false`.
+    pub fn http1_record_header_order(&mut self, val: bool) -> &mut Self {
+        self.h1_record_header_order = val;
+        self
+    }
+
+    /// Set whether HTTP/1 connections will accept requests whose lines end
+    /// with a bare LF instead of CRLF.
+    ///
+    /// [RFC 7230] allows recipients to accept these. Setting this to false
+    /// rejects them with a `400 Bad Request`.
+    ///
+    /// [RFC 7230]: https://tools.ietf.org/html/rfc7230#section-3.5
+    ///
+    /// Default is `true`.
+    pub fn http1_allow_lf_line_endings(&mut self, val: bool) -> &mut Self {
+        self.h1_lf_line_endings = val;
+        self
+    }
+
+    /// Set how HTTP/1 connections handle requests with ambiguous framing
+    /// headers, such as both `Content-Length` and `Transfer-Encoding`.
+    ///
+    /// Rejected requests are answered with a `400 Bad Request`. See
+    /// [`FramingPolicy`](::proxy::FramingPolicy) for details.
+    ///
+    /// Default is `FramingPolicy::Reject`.
+    pub fn http1_framing_policy(&mut self, policy: ::proxy::FramingPolicy) -> &mut Self {
+        self.h1_framing = policy;
+        self
+    }
+
+    /// Set the maximum length of the request-line of HTTP/1 requests, such
+    /// as `GET /index.html HTTP/1.1`, not including the line ending.
+    ///
+    /// Requests with a longer request-line are answered with a
+    /// `414 URI Too Long`.
+    ///
+    /// Default is no limit, other than the maximum buffer size.
+    pub fn http1_max_request_line(&mut self, max: usize) -> &mut Self {
+        self.h1_max_request_line = Some(max);
+        self
+    }
+
     /// Set whether HTTP/1 connections should try to use vectored writes,
     /// or always flatten into a single buffer.
     ///
@@ -173,6 +415,19 @@ impl Http {
         self
     }
 
+    /// Set whether HTTP/1 connections should always use vectored writes,
+    /// without detecting whether the transport does.
+    ///
+    /// Otherwise, small buffers are still copied into one before they are
+    /// written. This has no effect if `http1_writev` is `false`.
+    ///
+    /// Default is `false`.
+    #[inline]
+    pub fn http1_force_writev(&mut self, val: bool) -> &mut Self {
+        self.h1_force_writev = val;
+        self
+    }
+
     /// Sets whether HTTP2 is required.
     ///
     /// Default is false
@@ -185,6 +440,74 @@ impl Http {
         self
     }
 
+    /// Set the largest body chunk sent at once on HTTP/2 connections.
+    ///
+    /// Larger chunks from the response body are copied and sent in pieces
+    /// of this size. The `h2` library already splits DATA frames to the
+    /// peer's `SETTINGS_MAX_FRAME_SIZE`, but a piece is only handed to it
+    /// once the stream has window for it, so this also limits how much of
+    /// a chunk is buffered at a time.
+    ///
+    /// Default is no limit.
+    ///
+    /// # Panics
+    ///
+    /// The minimum value allowed is 1.
+    pub fn http2_max_send_chunk_size(&mut self, max: usize) -> &mut Self {
+        assert!(max > 0, "the max send chunk size must be larger than 0");
+        self.h2_body_chunks.max_size = Some(max);
+        self
+    }
+
+    /// Set the smallest body chunk sent on its own on HTTP/2 connections.
+    ///
+    /// Smaller chunks from the response body are coalesced and sent
+    /// together, reducing the frame overhead of bodies made of many tiny
+    /// chunks. Coalesced chunks are still sent whenever the body has no
+    /// more data ready, so they aren't delayed.
+    ///
+    /// Default is 0, which sends every chunk on its own.
+    pub fn http2_min_send_chunk_size(&mut self, min: usize) -> &mut Self {
+        self.h2_body_chunks.min_size = min;
+        self
+    }
+
+    /// Set how HTTP/2 connections share their bandwidth among responses.
+    ///
+    /// Responses are sent by the priority in the `priority` header of the
+    /// request, or of the response, as in RFC 9218, with the more urgent
+    /// responses going first. Among the responses of the same urgency, the
+    /// policy applies to those that don't ask to be `incremental` or not.
+    /// To take turns, bodies are sent in pieces of the default frame size
+    /// while other responses share the connection.
+    ///
+    /// The `PRIORITY` frames of RFC 7540, which RFC 9113 deprecated, aren't
+    /// used.
+    ///
+    /// Default is `SchedulingPolicy::RoundRobin`.
+    pub fn http2_scheduling_policy(&mut self, policy: SchedulingPolicy) -> &mut Self {
+        self.h2_scheduling = policy;
+        self
+    }
+
+    /// Set whether the streams of HTTP/2 connections are spawned as tasks.
+    ///
+    /// When enabled, every stream is spawned on the executor, as a
+    /// [`TaskKind::Stream`](::exec::TaskKind::Stream).
+    ///
+    /// When disabled, the connection keeps its streams in a slab, and polls
+    /// them from its own task, only polling again the streams that were
+    /// woken up. This saves spawning, and allocating, a task for every
+    /// request, which adds up on connections with many short requests.
+    /// The streams then share the thread of their connection, and they
+    /// end when it does.
+    ///
+    /// Default is `true`.
+    pub fn http2_spawn_streams(&mut self, enabled: bool) -> &mut Self {
+        self.h2_spawn_streams = enabled;
+        self
+    }
+
     /// Enables or disables HTTP keep-alive.
     ///
     /// Default is true.
@@ -205,7 +528,90 @@ impl Http {
             max >= proto::h1::MINIMUM_MAX_BUFFER_SIZE,
             "the max_buf_size cannot be smaller than the minimum that h1 specifies."
         );
-        self.max_buf_size = Some(max);
+        self.h1_buf_sizes.read_max = max;
+        self.h1_buf_sizes.write_max = max;
+        self
+    }
+
+    /// Set the initial size of the read buffer of HTTP/1 connections.
+    ///
+    /// The buffer grows as needed, up to its maximum size. A smaller
+    /// initial size saves memory on servers with many connections, at the
+    /// cost of more reallocations for messages with larger heads.
+    ///
+    /// Default is 8192.
+    ///
+    /// # Panics
+    ///
+    /// The value must be between 1 and 8192.
+    pub fn http1_init_read_buf_size(&mut self, size: usize) -> &mut Self {
+        assert!(
+            size > 0 && size <= proto::h1::MINIMUM_MAX_BUFFER_SIZE,
+            "the initial read buffer size must be between 1 and the minimum max_buf_size"
+        );
+        self.h1_buf_sizes.read_init = size;
+        self
+    }
+
+    /// Set the maximum size of the read buffer of HTTP/1 connections.
+    ///
+    /// This limits the size of a request's head. Requests whose head
+    /// doesn't fit are answered with a `431 Request Header Fields Too Large`.
+    ///
+    /// Default is ~400kb.
+    ///
+    /// # Panics
+    ///
+    /// The minimum value allowed is 8192.
+    pub fn http1_max_read_buf_size(&mut self, max: usize) -> &mut Self {
+        assert!(
+            max >= proto::h1::MINIMUM_MAX_BUFFER_SIZE,
+            "the max read buffer size cannot be smaller than the minimum that h1 specifies."
+        );
+        self.h1_buf_sizes.read_max = max;
+        self
+    }
+
+    /// Set the initial size of the buffer holding the heads of HTTP/1
+    /// responses before they are written.
+    ///
+    /// Default is 8192.
+    pub fn http1_init_write_buf_size(&mut self, size: usize) -> &mut Self {
+        self.h1_buf_sizes.write_init = size;
+        self
+    }
+
+    /// Set how many bytes HTTP/1 connections buffer before they need to
+    /// flush them to the transport.
+    ///
+    /// Default is ~400kb.
+    ///
+    /// # Panics
+    ///
+    /// The minimum value allowed is 8192.
+    pub fn http1_max_write_buf_size(&mut self, max: usize) -> &mut Self {
+        assert!(
+            max >= proto::h1::MINIMUM_MAX_BUFFER_SIZE,
+            "the max write buffer size cannot be smaller than the minimum that h1 specifies."
+        );
+        self.h1_buf_sizes.write_max = max;
+        self
+    }
+
+    /// Set how many read and write buffers of finished HTTP/1 connections
+    /// are kept, to be reused by the next connections.
+    ///
+    /// The buffers are shared by the connections served with this `Http`
+    /// and its clones. Buffers that grew larger than 32kb aren't kept.
+    /// Setting this to `0` disables the pool.
+    ///
+    /// Default is 32 of each.
+    pub fn http1_buf_pool_size(&mut self, max: usize) -> &mut Self {
+        self.h1_buf_pool = if max > 0 {
+            Some(proto::h1::BufPool::new(max))
+        } else {
+            None
+        };
         self
     }
 
@@ -219,6 +625,107 @@ impl Http {
         self
     }
 
+    /// Sets whether connections start with a PROXY protocol header.
+    ///
+    /// When enabled, every connection must start with a version 1 or 2
+    /// header of the HAProxy PROXY protocol, or it is closed. The addresses
+    /// it contains are available as a [`ProxyInfo`](ProxyInfo) in the
+    /// extensions of each request. Only enable this when all connections
+    /// come from a trusted proxy, as clients could otherwise spoof them.
+    ///
+    /// Default is false.
+    pub fn proxy_protocol(&mut self, enabled: bool) -> &mut Self {
+        self.proxy_protocol = enabled;
+        self
+    }
+
+    /// Sets whether connections are served in strict conformance mode.
+    ///
+    /// This is meant for running conformance suites, such as h2spec,
+    /// against a server. HTTP/1 requests with bare LF line endings, or
+    /// HTTP/1.1 requests without exactly one `Host` header, are answered
+    /// with `400 Bad Request`. HTTP/2 connections advertise a
+    /// `SETTINGS_MAX_CONCURRENT_STREAMS` of 100, and enforce it, and their
+    /// frames are parsed so that the errors sent are reported to
+    /// [`Instrumentation::error_sent`](::instrument::Instrumentation::error_sent),
+    /// to tell which check a failing test ran into. As with a
+    /// `FrameObserver`, writes of HTTP/2 connections are no longer vectored.
+    ///
+    /// Default is false.
+    pub fn strict_conformance(&mut self, enabled: bool) -> &mut Self {
+        self.strict = enabled;
+        if enabled || self.instrument.is_some() {
+            self.instrument.get_or_insert_with(Instrument::default).set_parse_frames(enabled);
+        }
+        self
+    }
+
+    /// Report the events of connections served with this to an
+    /// [`Instrumentation`](::instrument::Instrumentation).
+    ///
+    /// Default is to not report anything.
+    pub fn instrumentation<I: Instrumentation>(&mut self, instrumentation: I) -> &mut Self {
+        self.instrument.get_or_insert_with(Instrument::default).set_instrumentation(instrumentation);
+        self
+    }
+
+    /// Give a copy of the bytes of connections served with this to a
+    /// [`Tap`](::instrument::Tap), for debugging.
+    ///
+    /// Default is to not tap connections.
+    pub fn tap<T: Tap>(&mut self, tap: T) -> &mut Self {
+        self.instrument.get_or_insert_with(Instrument::default).set_tap(tap);
+        self
+    }
+
+    /// Tell `observer` about the control frames of HTTP/2 connections, such
+    /// as `SETTINGS`, `GOAWAY` and `RST_STREAM`.
+    ///
+    /// This is meant for debugging problems with specific peers. See
+    /// [`FrameObserver`](::instrument::FrameObserver).
+    ///
+    /// Default is to not observe any frames.
+    pub fn http2_frame_observer<O: FrameObserver>(&mut self, observer: O) -> &mut Self {
+        self.instrument.get_or_insert_with(Instrument::default).set_frame_observer(observer);
+        self
+    }
+
+    /// Call `access_log` once for every request a connection served with
+    /// this answered, once its response was written.
+    ///
+    /// The [`Exchange`](::instrument::Exchange) it's given has what an
+    /// access log usually records, such as the status of the response and
+    /// the bytes of its body, without having to wrap the `Service`.
+    ///
+    /// Default is to not call anything.
+    pub fn access_log<F>(&mut self, access_log: F) -> &mut Self
+    where
+        F: Fn(&Exchange) + Send + Sync + 'static,
+    {
+        self.instrument.get_or_insert_with(Instrument::default).set_access_log(access_log);
+        self
+    }
+
+    /// Give every request a [`RequestId`](::ext::RequestId), in its
+    /// extensions and in its [`Exchange`](::instrument::Exchange).
+    ///
+    /// With a `header`, such as `x-request-id`, a request that has it keeps
+    /// the ID it was given, so that IDs are the same across proxies and
+    /// services. The ID is then written in that header of the response as
+    /// well, unless the `Service` set it. Other requests are given an ID
+    /// that is unique to this `Http`.
+    ///
+    /// Default is to not give requests an ID.
+    pub fn request_id(&mut self, header: Option<HeaderName>) -> &mut Self {
+        self.instrument.get_or_insert_with(Instrument::default).set_request_ids(header);
+        self
+    }
+
+    /// Sets the remote address given to the access log, if there is one.
+    fn set_remote_addr(&mut self, addr: SocketAddr) {
+        self.instrument.get_or_insert_with(Instrument::default).set_remote_addr(addr);
+    }
+
     /// Set the executor used to spawn background tasks.
     ///
     /// Default uses implicit default (like `tokio::spawn`).
@@ -230,6 +737,75 @@ impl Http {
         self
     }
 
+    /// Set the [`Spawner`](::exec::Spawner) used to spawn background tasks.
+    ///
+    /// This replaces an `executor`, and is told what each task is for.
+    ///
+    /// Default uses implicit default (like `tokio::spawn`).
+    pub fn spawner<S: Spawner>(&mut self, spawner: S) -> &mut Self {
+        self.exec = Exec::Spawner(Arc::new(spawner));
+        self
+    }
+
+    /// Checks that the settings of this `Http` work together.
+    ///
+    /// It's an error to combine `http2_only` with any `http1_` setting, or
+    /// `http1_only` with any `http2_` setting, to make the smallest chunk
+    /// sent on its own larger than the largest, to start the write buffer
+    /// larger than its maximum, or to loosen the parsing of requests in
+    /// `strict_conformance` mode. The error returns `true` from
+    /// [`is_config`](::Error::is_config).
+    pub fn validate(&self) -> ::Result<()> {
+        let default = Http::new();
+        match self.mode {
+            ConnectionMode::H2Only => {
+                let h1_settings = [
+                    ("http1_absolute_form", self.h1_absolute_form != default.h1_absolute_form),
+                    ("http1_lenient_target", self.h1_lenient_target != default.h1_lenient_target),
+                    ("http1_lenient_uri", self.h1_lenient_uri != default.h1_lenient_uri),
+                    ("http1_record_request_line", self.h1_record_request_line != default.h1_record_request_line),
+                    ("http1_preserve_header_case", self.h1_preserve_header_case != default.h1_preserve_header_case),
+                    ("http1_record_header_case", self.h1_record_header_case != default.h1_record_header_case),
+                    ("http1_record_header_order", self.h1_record_header_order != default.h1_record_header_order),
+                    ("http1_allow_lf_line_endings", self.h1_lf_line_endings != default.h1_lf_line_endings),
+                    ("http1_framing_policy", self.h1_framing != default.h1_framing),
+                    ("http1_max_request_line", self.h1_max_request_line != default.h1_max_request_line),
+                    ("http1_writev", self.h1_writev != default.h1_writev),
+                    ("http1_force_writev", self.h1_force_writev != default.h1_force_writev),
+                    ("the http1 buffer sizes", self.h1_buf_sizes != default.h1_buf_sizes),
+                    ("pipeline_flush", self.pipeline_flush != default.pipeline_flush),
+                ];
+                if let Some(&(name, _)) = h1_settings.iter().find(|&&(_, set)| set) {
+                    return Err(::Error::new_config(format!("http2_only can't be used with {}", name)));
+                }
+            },
+            ConnectionMode::H1Only => {
+                if self.h2_body_chunks != default.h2_body_chunks {
+                    return Err(::Error::new_config("http1_only can't be used with the http2 send chunk sizes"));
+                }
+                if self.h2_scheduling != default.h2_scheduling {
+                    return Err(::Error::new_config("http1_only can't be used with http2_scheduling_policy"));
+                }
+                if self.h2_spawn_streams != default.h2_spawn_streams {
+                    return Err(::Error::new_config("http1_only can't be used with http2_spawn_streams"));
+                }
+            },
+            ConnectionMode::Fallback => (),
+        }
+        if self.strict {
+            let lenient = [
+                ("http1_lenient_target", self.h1_lenient_target),
+                ("http1_lenient_uri", self.h1_lenient_uri),
+                ("http1_framing_policy(Normalize)", self.h1_framing == ::proxy::FramingPolicy::Normalize),
+            ];
+            if let Some(&(name, _)) = lenient.iter().find(|&&(_, set)| set) {
+                return Err(::Error::new_config(format!("strict_conformance can't be used with {}", name)));
+            }
+        }
+        self.h2_body_chunks.check()?;
+        self.h1_buf_sizes.check()
+    }
+
     /// Bind a connection together with a [`Service`](::service::Service).
     ///
     /// This returns a Future that must be polled in order for HTTP to be
@@ -272,35 +848,206 @@ impl Http {
         S::Error: Into<Box<::std::error::Error + Send + Sync>>,
         S::Future: Send + 'static,
         Bd: Payload,
+        I: AsyncRead + AsyncWrite + 'static,
+    {
+        let fallback = self.mode == ConnectionMode::Fallback;
+        let instrument = Instrument::with_stats(&self.instrument);
+        let connection = instrument.connection();
+
+        if self.proxy_protocol {
+            return Connection {
+                conn: None,
+                _connection: connection,
+                exec: self.exec.clone(),
+                fallback,
+                h2_body_chunks: self.h2_body_chunks,
+                h2_max_concurrent_streams: self.h2_max_concurrent_streams(),
+                h2_scheduling: self.h2_scheduling,
+                h2_spawn_streams: self.h2_spawn_streams,
+                instrument,
+                proxy: Some(ProxyPreamble {
+                    io,
+                    read_buf: BytesMut::new(),
+                    service,
+                    protocol: self.clone(),
+                }),
+                proxy_info: None,
+            };
+        }
+
+        Connection {
+            conn: Some(self.start_connection(io, service, BytesMut::new(), None, instrument.clone())),
+            _connection: connection,
+            exec: self.exec.clone(),
+            fallback,
+            h2_body_chunks: self.h2_body_chunks,
+            h2_max_concurrent_streams: self.h2_max_concurrent_streams(),
+            h2_scheduling: self.h2_scheduling,
+            h2_spawn_streams: self.h2_spawn_streams,
+            instrument,
+            proxy: None,
+            proxy_info: None,
+        }
+    }
+
+    /// Bind a connection together with a [`Service`](::service::Service),
+    /// without requiring either to be `Send`.
+    ///
+    /// This is for single-threaded executors, such as tokio's
+    /// `current_thread`, and so services can use `Rc` and `RefCell`. The
+    /// connection is always served with HTTP/1, since HTTP/2 spawns a task
+    /// for every request, and so requires `serve_connection`. The PROXY
+    /// protocol isn't supported either, so `proxy_protocol` is ignored.
+    pub fn serve_connection_local<S, I, Bd>(&self, io: I, service: S) -> LocalConnection<I, S>
+    where
+        S: Service<ReqBody=Body, ResBody=Bd>,
+        S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+        Bd: Payload,
         I: AsyncRead + AsyncWrite,
     {
-        let either = match self.mode {
+        let instrument = Instrument::with_stats(&self.instrument);
+        LocalConnection {
+            conn: self.start_h1(io, service, BytesMut::new(), None, instrument.clone()),
+            _connection: instrument.connection(),
+            instrument,
+        }
+    }
+
+    fn h2_max_concurrent_streams(&self) -> Option<u32> {
+        if self.strict {
+            Some(STRICT_MAX_CONCURRENT_STREAMS)
+        } else {
+            None
+        }
+    }
+
+    fn start_connection<S, I, Bd>(
+        &self,
+        io: I,
+        service: S,
+        read_buf: BytesMut,
+        proxy_info: Option<ProxyInfo>,
+        instrument: Instrument,
+    ) -> Either<
+        proto::h1::Dispatcher<proto::h1::dispatch::Server<S>, Bd, I, proto::ServerTransaction>,
+        proto::h2::Server<Rewind<Counted<I>>, S, Bd>,
+    >
+    where
+        S: Service<ReqBody=Body, ResBody=Bd>,
+        S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+        S::Future: Send + 'static,
+        Bd: Payload,
+        I: AsyncRead + AsyncWrite + 'static,
+    {
+        match self.mode {
             ConnectionMode::H1Only | ConnectionMode::Fallback => {
-                let mut conn = proto::Conn::new(io);
-                if !self.keep_alive {
-                    conn.disable_keep_alive();
-                }
-                if !self.h1_writev {
-                    conn.set_write_strategy_flatten();
-                }
-                conn.set_flush_pipeline(self.pipeline_flush);
-                if let Some(max) = self.max_buf_size {
-                    conn.set_max_buf_size(max);
-                }
-                let sd = proto::h1::dispatch::Server::new(service);
-                Either::A(proto::h1::Dispatcher::new(sd, conn))
+                let h1 = self.start_h1(io, service, read_buf, proxy_info, instrument);
+                // finding the TCP stream of the transport needs it to be `'static`
+                #[cfg(all(feature = "sendfile", target_os = "linux"))]
+                let h1 = {
+                    let mut h1 = h1;
+                    h1.enable_send_file();
+                    h1
+                };
+                Either::A(h1)
             }
             ConnectionMode::H2Only => {
-                let rewind_io = Rewind::new(io);
-                let h2 = proto::h2::Server::new(rewind_io, service, self.exec.clone());
+                let mut io = Counted::new(io, Some(instrument.clone()));
+                let rewind_io = if !read_buf.is_empty() {
+                    io.already_read(&read_buf);
+                    let mut rewind_io = Rewind::new(io);
+                    rewind_io.rewind(read_buf.freeze());
+                    rewind_io
+                } else {
+                    Rewind::new(io)
+                };
+                let mut h2 = proto::h2::Server::new(
+                    rewind_io,
+                    service,
+                    self.exec.clone(),
+                    Some(instrument),
+                    self.h2_max_concurrent_streams(),
+                );
+                h2.set_proxy_info(proxy_info);
+                h2.set_body_chunks(self.h2_body_chunks);
+                h2.set_scheduling_policy(self.h2_scheduling);
+                h2.set_spawn_streams(self.h2_spawn_streams);
                 Either::B(h2)
             }
-        };
+        }
+    }
 
-        Connection {
-            conn: Some(either),
-            fallback: self.mode == ConnectionMode::Fallback,
+    fn start_h1<S, I, Bd>(
+        &self,
+        io: I,
+        service: S,
+        read_buf: BytesMut,
+        proxy_info: Option<ProxyInfo>,
+        instrument: Instrument,
+    ) -> proto::h1::Dispatcher<proto::h1::dispatch::Server<S>, Bd, I, proto::ServerTransaction>
+    where
+        S: Service<ReqBody=Body, ResBody=Bd>,
+        S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+        Bd: Payload,
+        I: AsyncRead + AsyncWrite,
+    {
+        let mut conn = proto::Conn::new(io);
+        if !read_buf.is_empty() {
+            conn.set_read_buf(read_buf);
+        }
+        if !self.keep_alive {
+            conn.disable_keep_alive();
+        }
+        if !self.h1_writev {
+            conn.set_write_strategy_flatten();
+        } else if self.h1_force_writev {
+            conn.set_write_strategy_queue();
+        }
+        if !self.h1_absolute_form {
+            conn.set_absolute_form(false);
+        }
+        if self.h1_lenient_target {
+            conn.set_lenient_target(true);
+        }
+        if self.h1_lenient_uri {
+            conn.set_lenient_uri(true);
+        }
+        if self.h1_record_request_line {
+            conn.set_record_request_line();
+        }
+        if self.h1_preserve_header_case {
+            conn.set_preserve_header_case();
+        } else if self.h1_record_header_case {
+            conn.set_record_header_case();
+        }
+        if self.h1_record_header_order {
+            conn.set_record_header_order();
         }
+        if !self.h1_lf_line_endings || self.strict {
+            conn.set_allow_lf_line_endings(false);
+        }
+        if self.strict {
+            conn.set_require_host();
+        }
+        conn.set_framing_policy(self.h1_framing);
+        if let Some(max) = self.h1_max_request_line {
+            conn.set_max_request_line(max);
+        }
+        conn.set_flush_pipeline(self.pipeline_flush);
+        // the default buffers are already allocated
+        if self.h1_buf_sizes != proto::h1::BufSizes::default() {
+            conn.set_buf_sizes(self.h1_buf_sizes);
+        }
+        if let Some(ref pool) = self.h1_buf_pool {
+            conn.set_buf_pool(pool.clone());
+        }
+        let mut sd = proto::h1::dispatch::Server::new(service);
+        if let Some(info) = proxy_info {
+            sd.set_proxy_info(info);
+        }
+        conn.set_instrument(instrument.clone());
+        sd.set_instrument(instrument);
+        proto::h1::Dispatcher::new(sd, conn)
     }
 
     /// Bind the provided `addr` with the default `Handle` and return [`Serve`](Serve).
@@ -372,17 +1119,58 @@ where
     I: AsyncRead + AsyncWrite + 'static,
     B: Payload + 'static,
 {
+    /// Returns the counters of this connection.
+    ///
+    /// The returned handle keeps being updated as the connection is
+    /// served, even once it is moved or spawned.
+    pub fn stats(&self) -> ConnectionStats {
+        self.instrument.stats()
+    }
+
+    /// Returns a snapshot of the state of this connection.
+    pub fn state(&self) -> ConnectionState {
+        match self.conn {
+            Some(Either::A(ref h1)) => h1.connection_state(),
+            Some(Either::B(ref h2)) => h2.connection_state(),
+            None => match self.proxy {
+                Some(ref proxy) => ConnectionState {
+                    version: None,
+                    reading: "proxy header",
+                    writing: "head",
+                    keep_alive: true,
+                    read_buffered: proxy.read_buf.len(),
+                    write_buffered: 0,
+                    in_flight: 0,
+                },
+                None => ConnectionState {
+                    version: None,
+                    reading: "closed",
+                    writing: "closed",
+                    keep_alive: false,
+                    read_buffered: 0,
+                    write_buffered: 0,
+                    in_flight: 0,
+                },
+            },
+        }
+    }
+
     /// Start a graceful shutdown process for this connection.
     ///
     /// This `Connection` should continue to be polled until shutdown
     /// can finish.
     pub fn graceful_shutdown(&mut self) {
-        match *self.conn.as_mut().unwrap() {
-            Either::A(ref mut h1) => {
+        match self.conn {
+            Some(Either::A(ref mut h1)) => {
                 h1.disable_keep_alive();
             },
-            Either::B(ref mut h2) => {
+            Some(Either::B(ref mut h2)) => {
                 h2.graceful_shutdown();
+            },
+            None => {
+                // Still waiting on the PROXY header, so no request has
+                // been started yet. Just close.
+                self.proxy = None;
             }
         }
     }
@@ -404,7 +1192,18 @@ where
     ///
     /// This method will return a `None` if this connection is using an h2 protocol.
     pub fn try_into_parts(self) -> Option<Parts<I, S>> {
-        match self.conn.unwrap() {
+        let conn = match self.conn {
+            Some(conn) => conn,
+            None => {
+                return self.proxy.map(|pre| Parts {
+                    io: pre.io,
+                    read_buf: pre.read_buf.freeze(),
+                    service: pre.service,
+                    _inner: (),
+                });
+            }
+        };
+        match conn {
             Either::A(h1) => {
                 let (io, read_buf, dispatch) = h1.into_inner();
                 Some(Parts {
@@ -426,10 +1225,12 @@ where
     /// but it is not desired to actally shutdown the IO object. Instead you
     /// would take it back using `into_parts`.
     pub fn poll_without_shutdown(&mut self) -> Poll<(), ::Error> {
+        try_ready!(self.poll_proxy());
         loop {
-            let polled = match *self.conn.as_mut().unwrap() {
-                Either::A(ref mut h1) => h1.poll_without_shutdown(),
-                Either::B(ref mut h2) => h2.poll(),
+            let polled = match self.conn {
+                Some(Either::A(ref mut h1)) => h1.poll_without_shutdown(),
+                Some(Either::B(ref mut h2)) => h2.poll(),
+                None => return Ok(Async::Ready(())),
             };
             match polled {
                 Ok(x) => return Ok(x),
@@ -459,13 +1260,71 @@ where
                 panic!("h2 cannot into_inner");
             }
         };
+        // the bytes already read were counted by the HTTP/1 connection
+        let mut io = Counted::new(io, Some(self.instrument.clone()));
+        io.already_read(&read_buf);
         let mut rewind_io = Rewind::new(io);
         rewind_io.rewind(read_buf);
-        let h2 = proto::h2::Server::new(rewind_io, dispatch.into_service(), Exec::Default);
+        let mut h2 = proto::h2::Server::new(
+            rewind_io,
+            dispatch.into_service(),
+            self.exec.clone(),
+            Some(self.instrument.clone()),
+            self.h2_max_concurrent_streams,
+        );
+        h2.set_proxy_info(self.proxy_info.clone());
+        h2.set_body_chunks(self.h2_body_chunks);
+        h2.set_scheduling_policy(self.h2_scheduling);
+        h2.set_spawn_streams(self.h2_spawn_streams);
 
         debug_assert!(self.conn.is_none());
         self.conn = Some(Either::B(h2));
     }
+
+    /// Read the PROXY header, if one is expected, and then start the
+    /// HTTP connection.
+    fn poll_proxy(&mut self) -> Poll<(), ::Error> {
+        loop {
+            let info = match self.proxy {
+                Some(ref mut pre) => match proxy::parse(&mut pre.read_buf) {
+                    Ok(Some(info)) => info,
+                    Ok(None) => {
+                        pre.read_buf.reserve(PROXY_READ_SIZE);
+                        let n = try_ready!(pre.io.read_buf(&mut pre.read_buf).map_err(::Error::new_io));
+                        if n == 0 {
+                            if pre.read_buf.is_empty() {
+                                debug!("read eof before PROXY header");
+                                self.proxy = None;
+                                return Ok(Async::Ready(()));
+                            }
+                            return Err(::Error::new_incomplete());
+                        }
+                        continue;
+                    },
+                    Err(proxy::Invalid) => {
+                        debug!("invalid PROXY header");
+                        return Err(Parse::Proxy.into());
+                    },
+                },
+                None => return Ok(Async::Ready(())),
+            };
+
+            debug!("received PROXY header: {:?}", info);
+            let pre = self.proxy.take().expect("proxy preamble");
+            if let Some(addr) = info.source() {
+                self.instrument.set_remote_addr(addr);
+            }
+            self.conn = Some(pre.protocol.start_connection(
+                pre.io,
+                pre.service,
+                pre.read_buf,
+                Some(info.clone()),
+                self.instrument.clone(),
+            ));
+            self.proxy_info = Some(info);
+            return Ok(Async::Ready(()));
+        }
+    }
 }
 
 impl<I, B, S> Future for Connection<I, S>
@@ -480,6 +1339,7 @@ where
     type Error = ::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        try_ready!(self.poll_proxy());
         loop {
             match self.conn.poll() {
                 Ok(x) => return Ok(x.map(|o| o.unwrap_or_else(|| ()))),
@@ -508,6 +1368,121 @@ where
     }
 }
 
+// ===== impl LocalConnection =====
+
+impl<I, B, S> LocalConnection<I, S>
+where
+    S: Service<ReqBody=Body, ResBody=B>,
+    S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+    I: AsyncRead + AsyncWrite,
+    B: Payload,
+{
+    /// Returns the counters of this connection.
+    pub fn stats(&self) -> ConnectionStats {
+        self.instrument.stats()
+    }
+
+    /// Returns a snapshot of the state of this connection.
+    pub fn state(&self) -> ConnectionState {
+        self.conn.connection_state()
+    }
+
+    /// Start a graceful shutdown process for this connection.
+    ///
+    /// This `LocalConnection` should continue to be polled until shutdown
+    /// can finish.
+    pub fn graceful_shutdown(&mut self) {
+        self.conn.disable_keep_alive();
+    }
+}
+
+impl<I, B, S> Future for LocalConnection<I, S>
+where
+    S: Service<ReqBody=Body, ResBody=B>,
+    S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+    I: AsyncRead + AsyncWrite,
+    B: Payload,
+{
+    type Item = ();
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.conn.poll()
+    }
+}
+
+impl<I, S> fmt::Debug for LocalConnection<I, S>
+where
+    S: Service,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LocalConnection")
+            .finish()
+    }
+}
+
+// ===== impl ConnectionState =====
+
+impl ConnectionState {
+    /// The HTTP version of the connection.
+    ///
+    /// This is `None` while the connection still waits on its PROXY header,
+    /// and so doesn't know yet.
+    pub fn version(&self) -> Option<Version> {
+        self.version
+    }
+
+    /// What the connection is reading.
+    ///
+    /// For HTTP/1, this is `"head"` while waiting on the next request,
+    /// `"body"` while reading a request body, `"keep-alive"` once the
+    /// request was read but its response is still being written, or
+    /// `"closed"`. For HTTP/2, this is `"handshake"`, `"frames"` or
+    /// `"closed"`, and it is `"proxy header"` while waiting on a PROXY
+    /// header.
+    pub fn reading(&self) -> &'static str {
+        self.reading
+    }
+
+    /// What the connection is writing.
+    ///
+    /// For HTTP/1, this is `"head"` while waiting on a response, `"body"`
+    /// while writing a response body, `"keep-alive"` once the response was
+    /// written but its request is still being read, or `"closed"`. For
+    /// HTTP/2, this is the same as [`reading`](ConnectionState::reading).
+    pub fn writing(&self) -> &'static str {
+        self.writing
+    }
+
+    /// Whether the connection may still serve another request, after the
+    /// ones in flight.
+    pub fn keep_alive(&self) -> bool {
+        self.keep_alive
+    }
+
+    /// The bytes read from the transport, but not parsed yet.
+    ///
+    /// HTTP/2 buffers are kept within its protocol state, and not counted.
+    pub fn read_buffered(&self) -> usize {
+        self.read_buffered
+    }
+
+    /// The bytes waiting to be written to the transport.
+    ///
+    /// HTTP/2 buffers are kept within its protocol state, and not counted.
+    pub fn write_buffered(&self) -> usize {
+        self.write_buffered
+    }
+
+    /// The requests being served.
+    ///
+    /// For HTTP/1, this is at most 1. For HTTP/2, this counts the streams
+    /// whose response wasn't fully sent yet.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+}
+
 // ===== impl Serve =====
 
 impl<I, S> Serve<I, S> {
@@ -562,7 +1537,7 @@ where
 
 impl<I, F, S, B> Future for Connecting<I, F>
 where
-    I: AsyncRead + AsyncWrite,
+    I: AsyncRead + AsyncWrite + 'static,
     F: Future<Item=S>,
     S: Service<ReqBody=Body, ResBody=B>,
     S::Future: Send + 'static,
@@ -578,6 +1553,15 @@ where
     }
 }
 
+impl<I: 'static, F> Connecting<I, F> {
+    /// Gives the remote address of the IO to the access log, if it's known.
+    fn set_remote_addr(&mut self) {
+        if let Some(addr) = self.io.as_ref().and_then(|io| super::accept::remote_addr(io)) {
+            self.protocol.set_remote_addr(addr);
+        }
+    }
+}
+
 // ===== impl SpawnAll =====
 
 #[cfg(feature = "runtime")]
@@ -610,13 +1594,47 @@ where
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         loop {
-            if let Some(connecting) = try_ready!(self.serve.poll()) {
+            if let Some(mut connecting) = try_ready!(self.serve.poll()) {
+                connecting.set_remote_addr();
                 let fut = connecting
                     .map_err(::Error::new_user_new_service)
                     // flatten basically
                     .and_then(|conn| conn)
                     .map_err(|err| debug!("conn error: {}", err));
-                self.serve.protocol.exec.execute(fut);
+                self.serve.protocol.exec.execute(TaskKind::Connection, fut);
+            } else {
+                return Ok(Async::Ready(()))
+            }
+        }
+    }
+}
+
+impl<I, S, B> SpawnAll<I, S>
+where
+    I: Stream,
+    I::Error: Into<Box<::std::error::Error + Send + Sync>>,
+    I::Item: AsyncRead + AsyncWrite + Send + 'static,
+    S: NewService<ReqBody=Body, ResBody=B> + Send + 'static,
+    S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+    S::Service: Send,
+    S::Future: Send + 'static,
+    <S::Service as Service>::Future: Send + 'static,
+    B: Payload,
+{
+    /// Like `poll`, but every spawned connection is tied to the `watch`,
+    /// so that it starts a graceful shutdown once a drain is signaled.
+    pub(super) fn poll_watch(&mut self, watch: &drain::Watch) -> Poll<(), ::Error> {
+        loop {
+            if let Some(mut connecting) = try_ready!(self.serve.poll()) {
+                connecting.set_remote_addr();
+                let watch = watch.clone();
+                let fut = connecting
+                    .map_err(::Error::new_user_new_service)
+                    .and_then(move |conn| {
+                        watch.watch(conn, |conn| conn.graceful_shutdown())
+                    })
+                    .map_err(|err| debug!("conn error: {}", err));
+                self.serve.protocol.exec.execute(TaskKind::Connection, fut);
             } else {
                 return Ok(Async::Ready(()))
             }
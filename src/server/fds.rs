@@ -0,0 +1,244 @@
+//! Listening sockets passed in by a parent process.
+
+use std::env;
+use std::io;
+use std::mem;
+use std::net::TcpListener as StdTcpListener;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{self, Command};
+
+/// The first file descriptor passed by `sd_listen_fds`.
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// The variable listing the fds handed over by `pass_listeners`.
+const HYPER_LISTEN_FDS: &str = "HYPER_LISTEN_FDS";
+
+static NO_SYSTEMD: &str = "no sockets passed by systemd";
+
+/// Take the listeners passed by systemd socket activation.
+///
+/// This follows the `sd_listen_fds` protocol: `LISTEN_PID` must match this
+/// process, and `LISTEN_FDS` is the number of sockets, starting at fd 3.
+/// The variables are removed afterwards, so that child processes don't
+/// try to take the same sockets.
+pub(super) fn systemd_listeners() -> io::Result<Vec<StdTcpListener>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let fds = systemd_fds(
+        pid.as_ref().map(String::as_str),
+        fds.as_ref().map(String::as_str),
+        process::id(),
+    )?;
+    take_listeners(fds)
+}
+
+/// The fds passed by systemd, given the values of `LISTEN_PID` and
+/// `LISTEN_FDS`, and the id of this process.
+fn systemd_fds(pid: Option<&str>, fds: Option<&str>, own_pid: u32) -> io::Result<Vec<RawFd>> {
+    match pid.and_then(|pid| pid.parse::<u32>().ok()) {
+        Some(pid) if pid == own_pid => (),
+        _ => return Err(no_listeners(NO_SYSTEMD)),
+    }
+
+    let count = fds
+        .and_then(|fds| fds.parse::<RawFd>().ok())
+        .unwrap_or(0);
+    if count <= 0 {
+        return Err(no_listeners(NO_SYSTEMD));
+    }
+
+    Ok((SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count).collect())
+}
+
+/// Configure `cmd` so the spawned process inherits the listening `fds`.
+///
+/// The fds are only made inheritable in the child, right before `exec`,
+/// so other processes spawned meanwhile don't keep the sockets open.
+pub(super) fn pass_listeners(cmd: &mut Command, fds: Vec<RawFd>) {
+    let list = fds
+        .iter()
+        .map(|fd| fd.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    cmd.env(HYPER_LISTEN_FDS, list);
+    unsafe {
+        cmd.pre_exec(move || {
+            for &fd in &fds {
+                let flags = ::libc::fcntl(fd, ::libc::F_GETFD);
+                if flags == -1 ||
+                    ::libc::fcntl(fd, ::libc::F_SETFD, flags & !::libc::FD_CLOEXEC) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Take the listeners handed over by a parent using `pass_listeners`.
+pub(super) fn inherited_listeners() -> io::Result<Vec<StdTcpListener>> {
+    let list = env::var(HYPER_LISTEN_FDS).ok();
+    env::remove_var(HYPER_LISTEN_FDS);
+
+    let fds = inherited_fds(list.as_ref().map(String::as_str))?;
+    take_listeners(fds)
+}
+
+/// The fds handed over by a parent, given the value of `HYPER_LISTEN_FDS`.
+///
+/// Each fd is listed once, even if repeated, since each becomes a listener
+/// that closes it. The standard streams, fds 0 to 2, are never sockets
+/// handed over this way.
+fn inherited_fds(list: Option<&str>) -> io::Result<Vec<RawFd>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid HYPER_LISTEN_FDS");
+    let mut fds = Vec::new();
+    for fd in list.ok_or_else(|| no_listeners("no sockets passed by parent process"))?.split(',') {
+        let fd = fd.trim().parse::<RawFd>().map_err(|_| invalid())?;
+        if fd <= 2 {
+            return Err(invalid());
+        }
+        if !fds.contains(&fd) {
+            fds.push(fd);
+        }
+    }
+    Ok(fds)
+}
+
+/// Takes ownership of `fds` as listeners, once all of them are checked to
+/// be listening TCP sockets.
+///
+/// Nothing is taken if any of them isn't one, so no fd that belongs to
+/// something else is closed.
+fn take_listeners(fds: Vec<RawFd>) -> io::Result<Vec<StdTcpListener>> {
+    for &fd in &fds {
+        check_listener(fd)?;
+    }
+    Ok(fds
+        .into_iter()
+        .map(|fd| {
+            set_cloexec(fd);
+            unsafe { StdTcpListener::from_raw_fd(fd) }
+        })
+        .collect())
+}
+
+/// Checks that `fd` is a listening IPv4 or IPv6 stream socket.
+fn check_listener(fd: RawFd) -> io::Result<()> {
+    let ty = get_socket_opt(fd, ::libc::SO_TYPE)?;
+    let accepting = get_socket_opt(fd, ::libc::SO_ACCEPTCONN)?;
+    let family = socket_family(fd)?;
+    if ty != ::libc::SOCK_STREAM || accepting == 0 ||
+        (family != ::libc::AF_INET && family != ::libc::AF_INET6) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("fd {} is not a listening TCP socket", fd),
+        ));
+    }
+    Ok(())
+}
+
+fn get_socket_opt(fd: RawFd, opt: ::libc::c_int) -> io::Result<::libc::c_int> {
+    let mut val: ::libc::c_int = 0;
+    let mut len = mem::size_of::<::libc::c_int>() as ::libc::socklen_t;
+    let ret = unsafe {
+        ::libc::getsockopt(
+            fd,
+            ::libc::SOL_SOCKET,
+            opt,
+            &mut val as *mut ::libc::c_int as *mut ::libc::c_void,
+            &mut len,
+        )
+    };
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(val)
+    }
+}
+
+fn socket_family(fd: RawFd) -> io::Result<::libc::c_int> {
+    let mut addr: ::libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<::libc::sockaddr_storage>() as ::libc::socklen_t;
+    let ret = unsafe {
+        ::libc::getsockname(
+            fd,
+            &mut addr as *mut ::libc::sockaddr_storage as *mut ::libc::sockaddr,
+            &mut len,
+        )
+    };
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(addr.ss_family as ::libc::c_int)
+    }
+}
+
+fn no_listeners(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, msg)
+}
+
+fn set_cloexec(fd: RawFd) {
+    unsafe {
+        let flags = ::libc::fcntl(fd, ::libc::F_GETFD);
+        if flags != -1 {
+            ::libc::fcntl(fd, ::libc::F_SETFD, flags | ::libc::FD_CLOEXEC);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream, UdpSocket};
+    use std::os::unix::io::{AsRawFd, IntoRawFd};
+    use std::os::unix::net::UnixListener;
+
+    use super::{inherited_fds, systemd_fds, take_listeners};
+
+    #[test]
+    fn systemd_fds_requires_matching_pid() {
+        assert_eq!(systemd_fds(Some("42"), Some("2"), 42).unwrap(), vec![3, 4]);
+        systemd_fds(Some("1"), Some("1"), 42).expect_err("LISTEN_PID is not ours");
+        systemd_fds(None, Some("1"), 42).expect_err("no LISTEN_PID");
+        systemd_fds(Some("42"), None, 42).expect_err("no LISTEN_FDS");
+        systemd_fds(Some("42"), Some("0"), 42).expect_err("no sockets");
+        systemd_fds(Some("42"), Some("-1"), 42).expect_err("negative LISTEN_FDS");
+    }
+
+    #[test]
+    fn inherited_fds_are_deduped_and_not_stdio() {
+        assert_eq!(inherited_fds(Some("3, 5,3")).unwrap(), vec![3, 5]);
+        inherited_fds(None).expect_err("no HYPER_LISTEN_FDS");
+        inherited_fds(Some("")).expect_err("empty HYPER_LISTEN_FDS");
+        inherited_fds(Some("3,x")).expect_err("not a number");
+        for stdio in &["0", "1", "2", "4,2"] {
+            inherited_fds(Some(stdio)).expect_err("standard stream");
+        }
+    }
+
+    #[test]
+    fn take_listeners_checks_sockets() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let taken = take_listeners(vec![listener.into_raw_fd()]).expect("listening TCP socket");
+        assert_eq!(taken[0].local_addr().unwrap(), addr);
+
+        let stream = TcpStream::connect(addr).unwrap();
+        take_listeners(vec![stream.as_raw_fd()]).expect_err("connected TCP socket");
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        take_listeners(vec![udp.as_raw_fd()]).expect_err("UDP socket");
+        let path = ::std::env::temp_dir().join(format!("hyper-fds-{}", ::std::process::id()));
+        let unix = UnixListener::bind(&path).unwrap();
+        let _ = ::std::fs::remove_file(&path);
+        take_listeners(vec![unix.as_raw_fd()]).expect_err("unix socket");
+
+        // nothing is taken if any fd isn't a listener
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        take_listeners(vec![listener.as_raw_fd(), udp.as_raw_fd()]).expect_err("UDP socket");
+        listener.local_addr().expect("listener still open");
+    }
+}
@@ -47,16 +47,31 @@
 //! # #[cfg(not(feature = "runtime"))]
 //! # fn main() {}
 //! ```
+//!
+//! # Other runtimes
+//!
+//! Only `Server::bind`, and the `AddrIncoming` it listens with, need tokio,
+//! and they're left out without the `runtime` feature. A server on another
+//! runtime is built with [`Server::builder`](Server::builder), given the
+//! listener of that runtime through an [`accept::Incoming`](accept::Incoming),
+//! and a [`Spawner`](::exec::Spawner) through
+//! [`Http::spawner`](conn::Http::spawner) for its background tasks. The
+//! connections then get the same HTTP/1 and HTTP/2 implementations.
 
+pub mod accept;
 pub mod conn;
+#[cfg(all(feature = "runtime", unix))] mod fds;
 #[cfg(feature = "runtime")] mod tcp;
 mod rewind;
+mod shutdown;
 
 use std::fmt;
-#[cfg(feature = "runtime")] use std::net::SocketAddr;
+use std::io;
+#[cfg(feature = "runtime")] use std::net::{SocketAddr, TcpListener as StdTcpListener};
 #[cfg(feature = "runtime")] use std::time::Duration;
 
 use futures::{Future, Stream, Poll};
+use futures::stream::{self, IterOk};
 use tokio_io::{AsyncRead, AsyncWrite};
 
 use body::{Body, Payload};
@@ -64,7 +79,10 @@ use service::{NewService, Service};
 // Renamed `Http` as `Http_` for now so that people upgrading don't see an
 // error that `hyper::server::Http` is private...
 use self::conn::{Http as Http_, SpawnAll};
-#[cfg(feature = "runtime")] use self::tcp::{AddrIncoming};
+#[cfg(feature = "runtime")] use self::tcp::{AddrIncoming, ListenOptions, MultiAddrIncoming};
+#[cfg(all(feature = "sendfile", target_os = "linux"))] pub(crate) use self::tcp::tcp_stream;
+
+pub use self::shutdown::Graceful;
 
 /// A listening HTTP server.
 ///
@@ -87,6 +105,9 @@ pub struct Builder<I> {
 
 impl<I> Server<I, ()> {
     /// Starts a [`Builder`](Builder) with the provided incoming stream.
+    ///
+    /// The incoming stream can be any `Stream` of IO objects, such as
+    /// in-memory transports, TLS-wrapped streams, or a custom listener.
     pub fn builder(incoming: I) -> Builder<I> {
         Builder {
             incoming,
@@ -95,6 +116,19 @@ impl<I> Server<I, ()> {
     }
 }
 
+impl<I: Iterator> Server<IterOk<I, io::Error>, ()> {
+    /// Starts a [`Builder`](Builder) with the provided iterator of IO objects.
+    ///
+    /// The server completes once the iterator is exhausted and every
+    /// connection has been spawned.
+    pub fn builder_from_iter<T>(iter: T) -> Builder<IterOk<I, io::Error>>
+    where
+        T: IntoIterator<IntoIter=I, Item=I::Item>,
+    {
+        Server::builder(stream::iter_ok(iter))
+    }
+}
+
 #[cfg(feature = "runtime")]
 impl Server<AddrIncoming, ()> {
     /// Binds to the provided address, and returns a [`Builder`](Builder).
@@ -116,6 +150,42 @@ impl Server<AddrIncoming, ()> {
         AddrIncoming::new(addr, None)
             .map(Server::builder)
     }
+
+    /// Creates a [`Builder`](Builder) from an already bound, listening
+    /// `std::net::TcpListener`.
+    ///
+    /// This is useful when the socket must be configured in ways not
+    /// covered by [`ListenOptions`](conn::ListenOptions), or was created
+    /// by another process.
+    pub fn from_tcp(listener: StdTcpListener) -> ::Result<Builder<AddrIncoming>> {
+        AddrIncoming::from_std(listener, None)
+            .map(Server::builder)
+    }
+
+    /// Binds to the provided address using the [`ListenOptions`](conn::ListenOptions),
+    /// and returns a [`Builder`](Builder).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate hyper;
+    /// # fn main() {}
+    /// # #[cfg(feature = "runtime")]
+    /// # fn run() -> hyper::Result<()> {
+    /// use hyper::Server;
+    /// use hyper::server::conn::ListenOptions;
+    ///
+    /// let mut opts = ListenOptions::new();
+    /// opts.reuse_port(true);
+    ///
+    /// let builder = Server::try_bind_with(&([0, 0, 0, 0], 3000).into(), &opts)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn try_bind_with(addr: &SocketAddr, opts: &ListenOptions) -> ::Result<Builder<AddrIncoming>> {
+        AddrIncoming::new_with_options(addr, None, opts)
+            .map(Server::builder)
+    }
 }
 
 #[cfg(feature = "runtime")]
@@ -124,6 +194,162 @@ impl<S> Server<AddrIncoming, S> {
     pub fn local_addr(&self) -> SocketAddr {
         self.spawn_all.local_addr()
     }
+
+    /// Configures `cmd` so the spawned process inherits this server's
+    /// listening socket.
+    ///
+    /// The new process can pick it up with
+    /// [`Server::from_inherited`](Server::from_inherited). Together with
+    /// [`with_graceful_shutdown`](Server::with_graceful_shutdown) in this
+    /// process, this allows upgrading a server without refusing any
+    /// connections: spawn the new process, wait for it to be ready, then
+    /// trigger the graceful shutdown here.
+    #[cfg(unix)]
+    pub fn pass_listener(&self, cmd: &mut ::std::process::Command) {
+        use std::os::unix::io::AsRawFd;
+        fds::pass_listeners(cmd, vec![self.spawn_all.incoming_ref().as_raw_fd()]);
+    }
+}
+
+#[cfg(feature = "runtime")]
+impl Server<MultiAddrIncoming, ()> {
+    /// Binds to each of the provided addresses, and returns a
+    /// [`Builder`](Builder) serving all of them.
+    ///
+    /// Every listener shares the same `NewService` and configuration, and
+    /// a graceful shutdown of the resulting server covers all of them.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if binding to any of the addresses fails. For
+    /// a method to bind and return a `Result`, see `Server::try_bind_all`.
+    pub fn bind_all(addrs: &[SocketAddr]) -> Builder<MultiAddrIncoming> {
+        let incoming = MultiAddrIncoming::new(addrs, None)
+            .unwrap_or_else(|e| {
+                panic!("error binding to {:?}: {}", addrs, e);
+            });
+        Server::builder(incoming)
+    }
+
+    /// Tries to bind to each of the provided addresses, and returns a
+    /// [`Builder`](Builder).
+    pub fn try_bind_all(addrs: &[SocketAddr]) -> ::Result<Builder<MultiAddrIncoming>> {
+        MultiAddrIncoming::new(addrs, None)
+            .map(Server::builder)
+    }
+
+    /// Creates a [`Builder`](Builder) serving the sockets passed by systemd
+    /// socket activation.
+    ///
+    /// The sockets are found with the `LISTEN_PID` and `LISTEN_FDS`
+    /// environment variables, as described by `sd_listen_fds(3)`. This
+    /// allows starting the server on demand, and listening on privileged
+    /// ports without running as root.
+    ///
+    /// Returns an error if no sockets were passed to this process.
+    #[cfg(unix)]
+    pub fn from_systemd() -> ::Result<Builder<MultiAddrIncoming>> {
+        fds::systemd_listeners()
+            .map_err(::Error::new_listen)
+            .and_then(Server::from_std_listeners)
+    }
+
+    /// Creates a [`Builder`](Builder) serving the sockets inherited from a
+    /// parent process that used [`Server::pass_listener`](Server::pass_listener).
+    ///
+    /// Returns an error if no sockets were passed to this process.
+    #[cfg(unix)]
+    pub fn from_inherited() -> ::Result<Builder<MultiAddrIncoming>> {
+        fds::inherited_listeners()
+            .map_err(::Error::new_listen)
+            .and_then(Server::from_std_listeners)
+    }
+
+    #[cfg(unix)]
+    fn from_std_listeners(listeners: Vec<StdTcpListener>) -> ::Result<Builder<MultiAddrIncoming>> {
+        let incomings = listeners
+            .into_iter()
+            .map(|listener| AddrIncoming::from_std(listener, None))
+            .collect::<::Result<Vec<_>>>()?;
+        Ok(Server::builder(MultiAddrIncoming::from_incomings(incomings)))
+    }
+}
+
+#[cfg(feature = "runtime")]
+impl<S> Server<MultiAddrIncoming, S> {
+    /// Returns the local addresses that this server is bound to.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.spawn_all.incoming_ref().local_addrs()
+    }
+
+    /// Configures `cmd` so the spawned process inherits this server's
+    /// listening sockets.
+    ///
+    /// See [`Server::pass_listener`](Server::pass_listener).
+    #[cfg(unix)]
+    pub fn pass_listeners(&self, cmd: &mut ::std::process::Command) {
+        fds::pass_listeners(cmd, self.spawn_all.incoming_ref().raw_fds());
+    }
+}
+
+impl<I, S, B> Server<I, S>
+where
+    I: Stream,
+    I::Error: Into<Box<::std::error::Error + Send + Sync>>,
+    I::Item: AsyncRead + AsyncWrite + Send + 'static,
+    S: NewService<ReqBody=Body, ResBody=B> + Send + 'static,
+    S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+    S::Service: Send,
+    S::Future: Send + 'static,
+    <S::Service as Service>::Future: Send + 'static,
+    B: Payload,
+{
+    /// Prepares a server to handle graceful shutdown when the provided future
+    /// completes.
+    ///
+    /// Once `signal` resolves, the server stops accepting new connections,
+    /// asks every open connection to shutdown gracefully, and the returned
+    /// future completes when all of them have finished.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate hyper;
+    /// # extern crate futures;
+    /// # use futures::Future;
+    /// # fn main() {}
+    /// # #[cfg(feature = "runtime")]
+    /// # fn run() {
+    /// # use hyper::{Body, Response, Server};
+    /// # use hyper::service::service_fn_ok;
+    /// # let new_service = || {
+    /// #     service_fn_ok(|_req| Response::new(Body::empty()))
+    /// # };
+    /// // Make a server from the previous examples...
+    /// let server = Server::bind(&([127, 0, 0, 1], 3000).into())
+    ///     .serve(new_service);
+    ///
+    /// // Prepare some signal for when the server should start
+    /// // shutting down...
+    /// let (tx, rx) = futures::sync::oneshot::channel::<()>();
+    ///
+    /// let graceful = server
+    ///     .with_graceful_shutdown(rx)
+    ///     .map_err(|err| eprintln!("server error: {}", err));
+    ///
+    /// // Spawn `server` onto an Executor...
+    /// hyper::rt::spawn(graceful);
+    ///
+    /// // And later, trigger the signal by calling `tx.send(())`.
+    /// let _ = tx.send(());
+    /// # }
+    /// ```
+    pub fn with_graceful_shutdown<F>(self, signal: F) -> Graceful<I, S, F>
+    where
+        F: Future<Item=()>
+    {
+        Graceful::new(self.spawn_all, signal)
+    }
 }
 
 impl<I, S, B> Future for Server<I, S>
@@ -175,6 +401,111 @@ impl<I> Builder<I> {
         self
     }
 
+    /// Set whether HTTP/1 requests may have an absolute-form target.
+    ///
+    /// See [`Http::http1_absolute_form`](conn::Http::http1_absolute_form).
+    ///
+    /// Default is `true`.
+    pub fn http1_absolute_form(mut self, val: bool) -> Self {
+        self.protocol.http1_absolute_form(val);
+        self
+    }
+
+    /// Set whether HTTP/1 requests with an invalid request-target are
+    /// passed to the service.
+    ///
+    /// See [`Http::http1_lenient_target`](conn::Http::http1_lenient_target).
+    ///
+    /// Default is `false`.
+    pub fn http1_lenient_target(mut self, val: bool) -> Self {
+        self.protocol.http1_lenient_target(val);
+        self
+    }
+
+    /// Set whether HTTP/1 connections will percent-encode characters in
+    /// request-targets that strict URI parsing rejects.
+    ///
+    /// See [`Http::http1_lenient_uri`](conn::Http::http1_lenient_uri).
+    ///
+    /// Default is `false`.
+    pub fn http1_lenient_uri(mut self, val: bool) -> Self {
+        self.protocol.http1_lenient_uri(val);
+        self
+    }
+
+    /// Set whether HTTP/1 connections will record the request-line of each
+    /// request as it was received.
+    ///
+    /// See [`Http::http1_record_request_line`](conn::Http::http1_record_request_line).
+    ///
+    /// Default is `false`.
+    pub fn http1_record_request_line(mut self, val: bool) -> Self {
+        self.protocol.http1_record_request_line(val);
+        self
+    }
+
+    /// Set whether HTTP/1 connections will preserve the case of header names.
+    ///
+    /// See [`Http::http1_preserve_header_case`](conn::Http::http1_preserve_header_case).
+    ///
+    /// Default is `false`.
+    pub fn http1_preserve_header_case(mut self, val: bool) -> Self {
+        self.protocol.http1_preserve_header_case(val);
+        self
+    }
+
+    /// Set whether HTTP/1 connections will record the case of header names.
+    ///
+    /// See [`Http::http1_record_header_case`](conn::Http::http1_record_header_case).
+    ///
+    /// Default is `false`.
+    pub fn http1_record_header_case(mut self, val: bool) -> Self {
+        self.protocol.http1_record_header_case(val);
+        self
+    }
+
+    /// Set whether HTTP/1 connections will record the order of headers.
+    ///
+    /// See [`Http::http1_record_header_order`](conn::Http::http1_record_header_order).
+    ///
+    /// Default is `false`.
+    pub fn http1_record_header_order(mut self, val: bool) -> Self {
+        self.protocol.http1_record_header_order(val);
+        self
+    }
+
+    /// Set whether HTTP/1 connections will accept requests whose lines end
+    /// with a bare LF instead of CRLF.
+    ///
+    /// See [`Http::http1_allow_lf_line_endings`](conn::Http::http1_allow_lf_line_endings).
+    ///
+    /// Default is `true`.
+    pub fn http1_allow_lf_line_endings(mut self, val: bool) -> Self {
+        self.protocol.http1_allow_lf_line_endings(val);
+        self
+    }
+
+    /// Set how HTTP/1 connections handle requests with ambiguous framing
+    /// headers.
+    ///
+    /// See [`Http::http1_framing_policy`](conn::Http::http1_framing_policy).
+    ///
+    /// Default is `FramingPolicy::Reject`.
+    pub fn http1_framing_policy(mut self, policy: ::proxy::FramingPolicy) -> Self {
+        self.protocol.http1_framing_policy(policy);
+        self
+    }
+
+    /// Set the maximum length of the request-line of HTTP/1 requests.
+    ///
+    /// See [`Http::http1_max_request_line`](conn::Http::http1_max_request_line).
+    ///
+    /// Default is no limit, other than the maximum buffer size.
+    pub fn http1_max_request_line(mut self, max: usize) -> Self {
+        self.protocol.http1_max_request_line(max);
+        self
+    }
+
     /// Set whether HTTP/1 connections should try to use vectored writes,
     /// or always flatten into a single buffer.
     ///
@@ -190,6 +521,67 @@ impl<I> Builder<I> {
         self
     }
 
+    /// Set whether HTTP/1 connections should always use vectored writes.
+    ///
+    /// See [`Http::http1_force_writev`](conn::Http::http1_force_writev).
+    ///
+    /// Default is `false`.
+    pub fn http1_force_writev(mut self, val: bool) -> Self {
+        self.protocol.http1_force_writev(val);
+        self
+    }
+
+    /// Set the initial size of the read buffer of HTTP/1 connections.
+    ///
+    /// See [`Http::http1_init_read_buf_size`](conn::Http::http1_init_read_buf_size).
+    ///
+    /// Default is 8192.
+    pub fn http1_init_read_buf_size(mut self, size: usize) -> Self {
+        self.protocol.http1_init_read_buf_size(size);
+        self
+    }
+
+    /// Set the maximum size of the read buffer of HTTP/1 connections.
+    ///
+    /// See [`Http::http1_max_read_buf_size`](conn::Http::http1_max_read_buf_size).
+    ///
+    /// Default is ~400kb.
+    pub fn http1_max_read_buf_size(mut self, max: usize) -> Self {
+        self.protocol.http1_max_read_buf_size(max);
+        self
+    }
+
+    /// Set the initial size of the write buffer of HTTP/1 connections.
+    ///
+    /// See [`Http::http1_init_write_buf_size`](conn::Http::http1_init_write_buf_size).
+    ///
+    /// Default is 8192.
+    pub fn http1_init_write_buf_size(mut self, size: usize) -> Self {
+        self.protocol.http1_init_write_buf_size(size);
+        self
+    }
+
+    /// Set the maximum size of the write buffer of HTTP/1 connections.
+    ///
+    /// See [`Http::http1_max_write_buf_size`](conn::Http::http1_max_write_buf_size).
+    ///
+    /// Default is ~400kb.
+    pub fn http1_max_write_buf_size(mut self, max: usize) -> Self {
+        self.protocol.http1_max_write_buf_size(max);
+        self
+    }
+
+    /// Set how many buffers of finished HTTP/1 connections are kept, to be
+    /// reused by the next connections.
+    ///
+    /// See [`Http::http1_buf_pool_size`](conn::Http::http1_buf_pool_size).
+    ///
+    /// Default is 32 of each.
+    pub fn http1_buf_pool_size(mut self, max: usize) -> Self {
+        self.protocol.http1_buf_pool_size(max);
+        self
+    }
+
     /// Sets whether HTTP/2 is required.
     ///
     /// Default is `false`.
@@ -198,6 +590,115 @@ impl<I> Builder<I> {
         self
     }
 
+    /// Set the largest body chunk sent at once on HTTP/2 connections.
+    ///
+    /// See [`Http::http2_max_send_chunk_size`](conn::Http::http2_max_send_chunk_size).
+    ///
+    /// Default is no limit.
+    ///
+    /// # Panics
+    ///
+    /// The minimum value allowed is 1.
+    pub fn http2_max_send_chunk_size(mut self, max: usize) -> Self {
+        self.protocol.http2_max_send_chunk_size(max);
+        self
+    }
+
+    /// Set the smallest body chunk sent on its own on HTTP/2 connections.
+    ///
+    /// See [`Http::http2_min_send_chunk_size`](conn::Http::http2_min_send_chunk_size).
+    ///
+    /// Default is `0`.
+    pub fn http2_min_send_chunk_size(mut self, min: usize) -> Self {
+        self.protocol.http2_min_send_chunk_size(min);
+        self
+    }
+
+    /// Set how HTTP/2 connections share their bandwidth among responses.
+    ///
+    /// See [`Http::http2_scheduling_policy`](conn::Http::http2_scheduling_policy).
+    ///
+    /// Default is `SchedulingPolicy::RoundRobin`.
+    pub fn http2_scheduling_policy(mut self, policy: conn::SchedulingPolicy) -> Self {
+        self.protocol.http2_scheduling_policy(policy);
+        self
+    }
+
+    /// Set whether the streams of HTTP/2 connections are spawned as tasks.
+    ///
+    /// See [`Http::http2_spawn_streams`](conn::Http::http2_spawn_streams).
+    ///
+    /// Default is `true`.
+    pub fn http2_spawn_streams(mut self, enabled: bool) -> Self {
+        self.protocol.http2_spawn_streams(enabled);
+        self
+    }
+
+    /// Sets whether connections start with a PROXY protocol header.
+    ///
+    /// See [`Http::proxy_protocol`](conn::Http::proxy_protocol).
+    ///
+    /// Default is `false`.
+    pub fn proxy_protocol(mut self, val: bool) -> Self {
+        self.protocol.proxy_protocol(val);
+        self
+    }
+
+    /// Sets whether connections are served in strict conformance mode.
+    ///
+    /// See [`Http::strict_conformance`](conn::Http::strict_conformance).
+    ///
+    /// Default is `false`.
+    pub fn strict_conformance(mut self, val: bool) -> Self {
+        self.protocol.strict_conformance(val);
+        self
+    }
+
+    /// Report the events of connections served to an
+    /// [`Instrumentation`](::instrument::Instrumentation).
+    ///
+    /// See [`Http::instrumentation`](conn::Http::instrumentation).
+    pub fn instrumentation<M: ::instrument::Instrumentation>(mut self, instrumentation: M) -> Self {
+        self.protocol.instrumentation(instrumentation);
+        self
+    }
+
+    /// Give a copy of the bytes of connections served to a
+    /// [`Tap`](::instrument::Tap), for debugging.
+    ///
+    /// See [`Http::tap`](conn::Http::tap).
+    pub fn tap<T: ::instrument::Tap>(mut self, tap: T) -> Self {
+        self.protocol.tap(tap);
+        self
+    }
+
+    /// Tell `observer` about the control frames of HTTP/2 connections.
+    ///
+    /// See [`Http::http2_frame_observer`](conn::Http::http2_frame_observer).
+    pub fn http2_frame_observer<O: ::instrument::FrameObserver>(mut self, observer: O) -> Self {
+        self.protocol.http2_frame_observer(observer);
+        self
+    }
+
+    /// Call `access_log` once for every request answered.
+    ///
+    /// See [`Http::access_log`](conn::Http::access_log).
+    pub fn access_log<F>(mut self, access_log: F) -> Self
+    where
+        F: Fn(&::instrument::Exchange) + Send + Sync + 'static,
+    {
+        self.protocol.access_log(access_log);
+        self
+    }
+
+    /// Give every request a [`RequestId`](::ext::RequestId).
+    ///
+    /// See [`Http::request_id`](conn::Http::request_id).
+    pub fn request_id(mut self, header: Option<::http::header::HeaderName>) -> Self {
+        self.protocol.request_id(header);
+        self
+    }
+
     /// Consume this `Builder`, creating a [`Server`](Server).
     ///
     /// # Example
@@ -244,6 +745,25 @@ impl<I> Builder<I> {
             spawn_all,
         }
     }
+
+    /// Like [`serve`](Builder::serve), but returns an error if the settings
+    /// don't work together.
+    ///
+    /// See [`Http::validate`](conn::Http::validate).
+    pub fn try_serve<S, B>(self, new_service: S) -> ::Result<Server<I, S>>
+    where
+        I: Stream,
+        I::Error: Into<Box<::std::error::Error + Send + Sync>>,
+        I::Item: AsyncRead + AsyncWrite + Send + 'static,
+        S: NewService<ReqBody=Body, ResBody=B> + Send + 'static,
+        S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+        S::Service: Send,
+        <S::Service as Service>::Future: Send + 'static,
+        B: Payload,
+    {
+        self.protocol.validate()?;
+        Ok(self.serve(new_service))
+    }
 }
 
 #[cfg(feature = "runtime")]
@@ -265,3 +785,21 @@ impl Builder<AddrIncoming> {
     }
 }
 
+#[cfg(feature = "runtime")]
+impl Builder<MultiAddrIncoming> {
+    /// Set whether TCP keepalive messages are enabled on accepted connections.
+    ///
+    /// If `None` is specified, keepalive is disabled, otherwise the duration
+    /// specified will be the time to remain idle before sending TCP keepalive
+    /// probes.
+    pub fn tcp_keepalive(mut self, keepalive: Option<Duration>) -> Self {
+        self.incoming.set_keepalive(keepalive);
+        self
+    }
+
+    /// Set the value of `TCP_NODELAY` option for accepted connections.
+    pub fn tcp_nodelay(mut self, enabled: bool) -> Self {
+        self.incoming.set_nodelay(enabled);
+        self
+    }
+}
@@ -0,0 +1,107 @@
+use std::fmt;
+
+use futures::{Async, Future, Stream, Poll};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use body::{Body, Payload};
+use common::drain::{self, Draining, Signal, Watch};
+use service::{Service, NewService};
+use super::SpawnAll;
+
+/// A future that resolves once a [`Server`](super::Server) has been
+/// gracefully shutdown.
+///
+/// Created with [`Server::with_graceful_shutdown`](super::Server::with_graceful_shutdown).
+#[must_use = "futures do nothing unless polled"]
+pub struct Graceful<I, S, F> {
+    state: State<I, S, F>,
+}
+
+enum State<I, S, F> {
+    Running {
+        drain: Option<(Signal, Watch)>,
+        spawn_all: SpawnAll<I, S>,
+        signal: F,
+    },
+    Draining(Draining),
+}
+
+impl<I, S, F> Graceful<I, S, F> {
+    pub(super) fn new(spawn_all: SpawnAll<I, S>, signal: F) -> Self {
+        let drain = Some(drain::channel());
+        Graceful {
+            state: State::Running {
+                drain,
+                spawn_all,
+                signal,
+            },
+        }
+    }
+}
+
+impl<I, S, B, F> Future for Graceful<I, S, F>
+where
+    I: Stream,
+    I::Error: Into<Box<::std::error::Error + Send + Sync>>,
+    I::Item: AsyncRead + AsyncWrite + Send + 'static,
+    S: NewService<ReqBody=Body, ResBody=B> + Send + 'static,
+    S::Error: Into<Box<::std::error::Error + Send + Sync>>,
+    S::Service: Send,
+    S::Future: Send + 'static,
+    <S::Service as Service>::Future: Send + 'static,
+    B: Payload,
+    F: Future<Item=()>,
+{
+    type Item = ();
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Running {
+                    ref mut drain,
+                    ref mut spawn_all,
+                    ref mut signal,
+                } => match signal.poll() {
+                    Ok(Async::Ready(())) | Err(_) => {
+                        debug!("signal received, starting graceful shutdown");
+                        let sig = drain
+                            .take()
+                            .expect("drain channel")
+                            .0;
+                        State::Draining(sig.drain())
+                    },
+                    Ok(Async::NotReady) => {
+                        let watch = &drain
+                            .as_ref()
+                            .expect("drain channel")
+                            .1;
+                        return spawn_all.poll_watch(watch);
+                    },
+                },
+                State::Draining(ref mut draining) => {
+                    return draining.poll()
+                        .map_err(|()| unreachable!("drain mpsc rx never errors"));
+                }
+            };
+            // Replacing the state drops the listener and the last `Watch`,
+            // so no new connections are accepted while draining.
+            self.state = next;
+        }
+    }
+}
+
+impl<I: fmt::Debug, S: fmt::Debug, F> fmt::Debug for Graceful<I, S, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut builder = f.debug_struct("Graceful");
+        match self.state {
+            State::Running { ref spawn_all, .. } => {
+                builder.field("listener", spawn_all.incoming_ref());
+            },
+            State::Draining(..) => {
+                builder.field("draining", &true);
+            },
+        }
+        builder.finish()
+    }
+}
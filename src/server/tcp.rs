@@ -1,28 +1,181 @@
+use std::any::Any;
+use std::borrow::Cow;
 use std::fmt;
 use std::io;
 use std::net::{SocketAddr, TcpListener as StdTcpListener};
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
-use futures::{Async, Future, Poll, Stream};
+use futures::{Async, Poll, Stream};
+use net2::TcpBuilder;
+#[cfg(unix)] use net2::unix::UnixTcpBuilderExt;
 use tokio_reactor::Handle;
-use tokio_tcp::TcpListener;
-use tokio_timer::Delay;
+use tokio_tcp::{TcpListener, TcpStream};
+
+use clock::{Clock, Time};
+use common::sockopt;
+
+use super::accept::{AcceptErrors, Listener};
 
 use self::addr_stream::AddrStream;
 
+/// Options for creating the listening socket of an [`AddrIncoming`](AddrIncoming).
+///
+/// These are applied before the socket is bound, which is needed for things
+/// like `SO_REUSEPORT`, where several processes shard a single port.
+#[derive(Clone, Debug)]
+pub struct ListenOptions {
+    backlog: i32,
+    only_v6: Option<bool>,
+    reuse_address: bool,
+    reuse_port: bool,
+    tcp_defer_accept: Option<Duration>,
+    tcp_fastopen: Option<u32>,
+}
+
+impl ListenOptions {
+    /// Create a new set of options, using the system defaults.
+    pub fn new() -> ListenOptions {
+        ListenOptions {
+            backlog: 1024,
+            only_v6: None,
+            reuse_address: true,
+            reuse_port: false,
+            tcp_defer_accept: None,
+            tcp_fastopen: None,
+        }
+    }
+
+    /// Set the size of the `listen` backlog.
+    ///
+    /// This is the number of connections the kernel will queue before they
+    /// are accepted. The kernel may cap it, such as with `somaxconn` on Linux.
+    ///
+    /// Default is `1024`.
+    pub fn backlog(&mut self, backlog: i32) -> &mut Self {
+        self.backlog = backlog;
+        self
+    }
+
+    /// Set the value of the `SO_REUSEADDR` option on the listener.
+    ///
+    /// Default is `true`, to match the listeners of the standard library.
+    pub fn reuse_address(&mut self, enabled: bool) -> &mut Self {
+        self.reuse_address = enabled;
+        self
+    }
+
+    /// Set the value of the `SO_REUSEPORT` option on the listener.
+    ///
+    /// This allows several listeners, usually in different processes, to
+    /// bind the same address, and have the kernel balance connections
+    /// between them. This option is ignored on platforms without it.
+    ///
+    /// Default is `false`.
+    pub fn reuse_port(&mut self, enabled: bool) -> &mut Self {
+        self.reuse_port = enabled;
+        self
+    }
+
+    /// Set the value of the `IPV6_V6ONLY` option on IPv6 listeners.
+    ///
+    /// When `true`, the listener will only accept IPv6 connections, so
+    /// an IPv4 address with the same port can be bound separately.
+    ///
+    /// Default is the system default.
+    pub fn only_v6(&mut self, only_v6: bool) -> &mut Self {
+        self.only_v6 = Some(only_v6);
+        self
+    }
+
+    /// Set whether TCP Fast Open is enabled on the listener.
+    ///
+    /// If `Some`, clients supporting it may send their request in the SYN,
+    /// saving a round trip. The value is the maximum number of pending
+    /// fast open requests. This option is ignored on platforms without it.
+    ///
+    /// Default is `None`.
+    pub fn tcp_fastopen(&mut self, queue_len: Option<u32>) -> &mut Self {
+        self.tcp_fastopen = queue_len;
+        self
+    }
+
+    /// Set whether accepting connections is deferred until data arrives.
+    ///
+    /// If `Some`, connections are only surfaced once the client has sent
+    /// some data, or the timeout has elapsed, sparing wakeups for port
+    /// scanners and idle probes. This option is ignored on platforms
+    /// without `TCP_DEFER_ACCEPT`.
+    ///
+    /// Default is `None`.
+    pub fn tcp_defer_accept(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.tcp_defer_accept = timeout;
+        self
+    }
+
+    fn bind(&self, addr: &SocketAddr) -> io::Result<StdTcpListener> {
+        let builder = match *addr {
+            SocketAddr::V4(_) => TcpBuilder::new_v4()?,
+            SocketAddr::V6(_) => TcpBuilder::new_v6()?,
+        };
+
+        if let Some(only_v6) = self.only_v6 {
+            if addr.is_ipv6() {
+                builder.only_v6(only_v6)?;
+            }
+        }
+
+        // Windows' SO_REUSEADDR allows stealing a bound port, so the
+        // standard library only sets it on unix.
+        if cfg!(unix) {
+            builder.reuse_address(self.reuse_address)?;
+        }
+        self.set_reuse_port(&builder)?;
+
+        builder.bind(addr)?;
+        if let Some(queue_len) = self.tcp_fastopen {
+            sockopt::set_tcp_fastopen(&builder, queue_len);
+        }
+        if let Some(timeout) = self.tcp_defer_accept {
+            sockopt::set_tcp_defer_accept(&builder, timeout);
+        }
+        builder.listen(self.backlog)
+    }
+
+    #[cfg(unix)]
+    fn set_reuse_port(&self, builder: &TcpBuilder) -> io::Result<()> {
+        if self.reuse_port {
+            builder.reuse_port(true)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn set_reuse_port(&self, _builder: &TcpBuilder) -> io::Result<()> {
+        if self.reuse_port {
+            debug!("SO_REUSEPORT is not supported on this platform");
+        }
+        Ok(())
+    }
+}
+
+impl Default for ListenOptions {
+    fn default() -> ListenOptions {
+        ListenOptions::new()
+    }
+}
+
 /// A stream of connections from binding to an address.
 #[must_use = "streams do nothing unless polled"]
 pub struct AddrIncoming {
     addr: SocketAddr,
     listener: TcpListener,
-    sleep_on_errors: bool,
+    errors: AcceptErrors,
     tcp_keepalive_timeout: Option<Duration>,
     tcp_nodelay: bool,
-    timeout: Option<Delay>,
 }
 
 impl AddrIncoming {
-    pub(super) fn new(addr: &SocketAddr, handle: Option<&Handle>) -> ::Result<AddrIncoming> {
+    pub(crate) fn new(addr: &SocketAddr, handle: Option<&Handle>) -> ::Result<AddrIncoming> {
         let listener = if let Some(handle) = handle {
             let std_listener = StdTcpListener::bind(addr)
                 .map_err(::Error::new_listen)?;
@@ -32,15 +185,36 @@ impl AddrIncoming {
             TcpListener::bind(addr).map_err(::Error::new_listen)?
         };
 
+        AddrIncoming::from_listener(listener)
+    }
+
+    pub(super) fn new_with_options(addr: &SocketAddr, handle: Option<&Handle>, opts: &ListenOptions) -> ::Result<AddrIncoming> {
+        let std_listener = opts.bind(addr)
+            .map_err(::Error::new_listen)?;
+        AddrIncoming::from_std(std_listener, handle)
+    }
+
+    pub(super) fn from_std(std_listener: StdTcpListener, handle: Option<&Handle>) -> ::Result<AddrIncoming> {
+        let handle = match handle {
+            Some(handle) => Cow::Borrowed(handle),
+            None => Cow::Owned(Handle::current()),
+        };
+
+        let listener = TcpListener::from_std(std_listener, &handle)
+            .map_err(::Error::new_listen)?;
+
+        AddrIncoming::from_listener(listener)
+    }
+
+    fn from_listener(listener: TcpListener) -> ::Result<AddrIncoming> {
         let addr = listener.local_addr().map_err(::Error::new_listen)?;
 
         Ok(AddrIncoming {
             addr: addr,
             listener: listener,
-            sleep_on_errors: true,
+            errors: AcceptErrors::new(true),
             tcp_keepalive_timeout: None,
             tcp_nodelay: false,
-            timeout: None,
         })
     }
 
@@ -81,7 +255,12 @@ impl AddrIncoming {
     ///
     /// Default is `true`.
     pub fn set_sleep_on_errors(&mut self, val: bool) {
-        self.sleep_on_errors = val;
+        self.errors.sleep = val;
+    }
+
+    /// Set the [`Clock`](::clock::Clock) to sleep on accept errors with.
+    pub fn set_clock<C: Clock>(&mut self, clock: C) {
+        self.errors.time = Time::new(clock);
     }
 }
 
@@ -91,86 +270,171 @@ impl Stream for AddrIncoming {
     type Error = ::std::io::Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        // Check if a previous timeout is active that was set by IO errors.
-        if let Some(ref mut to) = self.timeout {
-            match to.poll() {
-                Ok(Async::Ready(())) => {}
-                Ok(Async::NotReady) => return Ok(Async::NotReady),
-                Err(err) => {
-                    error!("sleep timer error: {}", err);
-                }
+        let (socket, addr) = try_ready!(self.errors.poll_accept(&mut self.listener));
+        if let Some(dur) = self.tcp_keepalive_timeout {
+            if let Err(e) = socket.set_keepalive(Some(dur)) {
+                trace!("error trying to set TCP keepalive: {}", e);
             }
         }
-        self.timeout = None;
-        loop {
-            match self.listener.poll_accept() {
-                Ok(Async::Ready((socket, addr))) => {
-                    if let Some(dur) = self.tcp_keepalive_timeout {
-                        if let Err(e) = socket.set_keepalive(Some(dur)) {
-                            trace!("error trying to set TCP keepalive: {}", e);
-                        }
-                    }
-                    if let Err(e) = socket.set_nodelay(self.tcp_nodelay) {
-                        trace!("error trying to set TCP nodelay: {}", e);
-                    }
-                    return Ok(Async::Ready(Some(AddrStream::new(socket, addr))));
-                },
-                Ok(Async::NotReady) => return Ok(Async::NotReady),
-                Err(e) => {
-                    if self.sleep_on_errors {
-                        // Connection errors can be ignored directly, continue by
-                        // accepting the next request.
-                        if is_connection_error(&e) {
-                            debug!("accepted connection already errored: {}", e);
-                            continue;
-                        }
-                        // Sleep 1s.
-                        let delay = Instant::now() + Duration::from_secs(1);
-                        let mut timeout = Delay::new(delay);
-
-                        match timeout.poll() {
-                            Ok(Async::Ready(())) => {
-                                // Wow, it's been a second already? Ok then...
-                                error!("accept error: {}", e);
-                                continue
-                            },
-                            Ok(Async::NotReady) => {
-                                error!("accept error: {}", e);
-                                self.timeout = Some(timeout);
-                                return Ok(Async::NotReady);
-                            },
-                            Err(timer_err) => {
-                                error!("couldn't sleep on error, timer error: {}", timer_err);
-                                return Err(e);
-                            }
-                        }
-                    } else {
-                        return Err(e);
-                    }
-                },
-            }
+        if let Err(e) = socket.set_nodelay(self.tcp_nodelay) {
+            trace!("error trying to set TCP nodelay: {}", e);
         }
+        Ok(Async::Ready(Some(AddrStream::new(socket, addr))))
+    }
+}
+
+impl Listener for TcpListener {
+    type Conn = TcpStream;
+
+    fn poll_accept(&mut self) -> Poll<(TcpStream, SocketAddr), io::Error> {
+        TcpListener::poll_accept(self)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TcpListener::local_addr(self)
     }
 }
 
-/// This function defines errors that are per-connection. Which basically
-/// means that if we get this error from `accept()` system call it means
-/// next connection might be ready to be accepted.
+/// A stream of connections from binding to several addresses.
 ///
-/// All other errors will incur a timeout before next `accept()` is performed.
-/// The timeout is useful to handle resource exhaustion errors like ENFILE
-/// and EMFILE. Otherwise, could enter into tight loop.
-fn is_connection_error(e: &io::Error) -> bool {
-    e.kind() == io::ErrorKind::ConnectionRefused ||
-    e.kind() == io::ErrorKind::ConnectionAborted ||
-    e.kind() == io::ErrorKind::ConnectionReset
+/// Connections are accepted from each listener in turn, so that a busy
+/// listener cannot starve the others.
+#[must_use = "streams do nothing unless polled"]
+pub struct MultiAddrIncoming {
+    incomings: Vec<AddrIncoming>,
+    next: usize,
+}
+
+impl MultiAddrIncoming {
+    pub(super) fn new(addrs: &[SocketAddr], handle: Option<&Handle>) -> ::Result<MultiAddrIncoming> {
+        if addrs.is_empty() {
+            return Err(::Error::new_listen(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no addresses to bind",
+            )));
+        }
+
+        let incomings = addrs
+            .iter()
+            .map(|addr| AddrIncoming::new(addr, handle))
+            .collect::<::Result<Vec<_>>>()?;
+
+        Ok(MultiAddrIncoming::from_incomings(incomings))
+    }
+
+    pub(super) fn from_incomings(incomings: Vec<AddrIncoming>) -> MultiAddrIncoming {
+        MultiAddrIncoming {
+            incomings: incomings,
+            next: 0,
+        }
+    }
+
+    /// Get the local addresses bound to these listeners.
+    ///
+    /// The addresses are in the same order they were given to bind.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.incomings
+            .iter()
+            .map(AddrIncoming::local_addr)
+            .collect()
+    }
+
+    /// Set whether TCP keepalive messages are enabled on accepted connections.
+    ///
+    /// See [`AddrIncoming::set_keepalive`](AddrIncoming::set_keepalive).
+    pub fn set_keepalive(&mut self, keepalive: Option<Duration>) -> &mut Self {
+        for incoming in &mut self.incomings {
+            incoming.set_keepalive(keepalive);
+        }
+        self
+    }
+
+    /// Set the value of `TCP_NODELAY` option for accepted connections.
+    pub fn set_nodelay(&mut self, enabled: bool) -> &mut Self {
+        for incoming in &mut self.incomings {
+            incoming.set_nodelay(enabled);
+        }
+        self
+    }
+
+    /// Set whether to sleep on accept errors.
+    ///
+    /// See [`AddrIncoming::set_sleep_on_errors`](AddrIncoming::set_sleep_on_errors).
+    pub fn set_sleep_on_errors(&mut self, val: bool) {
+        for incoming in &mut self.incomings {
+            incoming.set_sleep_on_errors(val);
+        }
+    }
+
+    /// Set the [`Clock`](::clock::Clock) to sleep on accept errors with.
+    pub fn set_clock<C: Clock>(&mut self, clock: C) {
+        let time = Time::new(clock);
+        for incoming in &mut self.incomings {
+            incoming.errors.time = time.clone();
+        }
+    }
+}
+
+impl Stream for MultiAddrIncoming {
+    type Item = AddrStream;
+    type Error = ::std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let len = self.incomings.len();
+        for _ in 0..len {
+            let idx = self.next;
+            self.next = (self.next + 1) % len;
+            // AddrIncoming never ends, so only a ready socket is a result.
+            if let Async::Ready(Some(socket)) = self.incomings[idx].poll()? {
+                return Ok(Async::Ready(Some(socket)));
+            }
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+impl fmt::Debug for MultiAddrIncoming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MultiAddrIncoming")
+            .field("incomings", &self.incomings)
+            .finish()
+    }
+}
+
+#[cfg(unix)]
+impl ::std::os::unix::io::AsRawFd for AddrIncoming {
+    fn as_raw_fd(&self) -> ::std::os::unix::io::RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+#[cfg(unix)]
+impl MultiAddrIncoming {
+    pub(super) fn raw_fds(&self) -> Vec<::std::os::unix::io::RawFd> {
+        use std::os::unix::io::AsRawFd;
+        self.incomings
+            .iter()
+            .map(AsRawFd::as_raw_fd)
+            .collect()
+    }
+}
+
+/// Returns the remote address of `io`, if it's an `AddrStream`.
+pub(super) fn remote_addr(io: &Any) -> Option<SocketAddr> {
+    io.downcast_ref::<AddrStream>().map(|stream| stream.remote_addr)
+}
+
+/// Returns the TCP stream of `io`, if it's an `AddrStream`.
+#[cfg(all(feature = "sendfile", target_os = "linux"))]
+pub(crate) fn tcp_stream(io: &Any) -> Option<&TcpStream> {
+    io.downcast_ref::<AddrStream>().map(AddrStream::tcp_stream)
 }
 
 impl fmt::Debug for AddrIncoming {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("AddrIncoming")
             .field("addr", &self.addr)
-            .field("sleep_on_errors", &self.sleep_on_errors)
+            .field("sleep_on_errors", &self.errors.sleep)
             .field("tcp_keepalive_timeout", &self.tcp_keepalive_timeout)
             .field("tcp_nodelay", &self.tcp_nodelay)
             .finish()
@@ -199,6 +463,16 @@ mod addr_stream {
                 remote_addr: addr,
             }
         }
+
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        pub(crate) fn into_parts(self) -> (TcpStream, SocketAddr) {
+            (self.inner, self.remote_addr)
+        }
+
+        #[cfg(all(feature = "sendfile", target_os = "linux"))]
+        pub(super) fn tcp_stream(&self) -> &TcpStream {
+            &self.inner
+        }
     }
 
     impl Read for AddrStream {
@@ -245,3 +519,56 @@ mod addr_stream {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ListenOptions;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn bind_sets_backlog() {
+        use std::net::TcpStream;
+        use std::time::Duration;
+
+        let mut opts = ListenOptions::new();
+        opts.backlog(1);
+        let listener = opts.bind(&"127.0.0.1:0".parse().unwrap()).expect("bind");
+        let addr = listener.local_addr().unwrap();
+
+        // Linux queues one connection more than the backlog, and drops the
+        // SYNs of others until one is accepted.
+        let _queued = (0..2)
+            .map(|_| TcpStream::connect_timeout(&addr, Duration::from_secs(5)).expect("queued"))
+            .collect::<Vec<_>>();
+        TcpStream::connect_timeout(&addr, Duration::from_millis(200)).expect_err("backlog full");
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn bind_sets_tcp_fastopen() {
+        use common::sockopt::{get_tcp_opt, TCP_FASTOPEN};
+
+        let mut opts = ListenOptions::new();
+        opts.tcp_fastopen(Some(16));
+        let listener = opts.bind(&"127.0.0.1:0".parse().unwrap()).expect("bind");
+        assert_eq!(get_tcp_opt(&listener, TCP_FASTOPEN).expect("getsockopt"), 16);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn bind_sets_tcp_defer_accept() {
+        use std::time::Duration;
+        use common::sockopt::{get_tcp_opt, TCP_DEFER_ACCEPT};
+
+        let listener = ListenOptions::new()
+            .bind(&"127.0.0.1:0".parse().unwrap())
+            .expect("bind");
+        assert_eq!(get_tcp_opt(&listener, TCP_DEFER_ACCEPT).expect("getsockopt"), 0);
+
+        let mut opts = ListenOptions::new();
+        opts.tcp_defer_accept(Some(Duration::from_secs(1)));
+        let listener = opts.bind(&"127.0.0.1:0".parse().unwrap()).expect("bind");
+        // the kernel rounds the timeout to its retransmissions
+        assert!(get_tcp_opt(&listener, TCP_DEFER_ACCEPT).expect("getsockopt") > 0);
+    }
+}
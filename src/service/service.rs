@@ -2,7 +2,7 @@ use std::error::Error as StdError;
 use std::fmt;
 use std::marker::PhantomData;
 
-use futures::{future, Future, IntoFuture};
+use futures::{future, Async, Future, IntoFuture, Poll};
 
 use body::Payload;
 use common::Never;
@@ -26,6 +26,18 @@ pub trait Service {
     /// The `Future` returned by this `Service`.
     type Future: Future<Item=Response<Self::ResBody>, Error=Self::Error>;
 
+    /// Returns `Ready` once this `Service` can take a request.
+    ///
+    /// A server doesn't read another request, or accept another HTTP/2
+    /// stream, until this is ready, so a `Service` can push back on a
+    /// connection by returning `NotReady`, once it notifies the current task
+    /// to be polled again. Returning an `Error` closes the connection.
+    ///
+    /// The default is always ready.
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
     /// Calls this `Service` with a request, returning a `Future` of the response.
     fn call(&mut self, req: Request<Self::ReqBody>) -> Self::Future;
 }
@@ -0,0 +1,993 @@
+//! Transports driven by io_uring, for Linux.
+//!
+//! A [`Uring`](Uring) is an io_uring instance that connections submit their
+//! reads and writes to, instead of waiting on the reactor to be readable or
+//! writable. [`UringIncoming`](UringIncoming) accepts server connections onto
+//! one, and a [`UringConnector`](UringConnector) does the same for the
+//! connections of a `Client`.
+//!
+//! Reads go into buffers [registered](Uring::register_buffers) with the
+//! kernel, when there's one free, which saves mapping the buffer on each
+//! read. Writes are copied into a buffer of the stream, so the kernel can
+//! write them after `write` returns; `flush` waits until they are written.
+//!
+//! The completions are reaped by a task spawned on the default executor,
+//! when the first stream is made, so the streams must be used within a tokio
+//! runtime. This needs the `io-uring` feature, and Linux 5.7 or newer.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # extern crate futures;
+//! # extern crate hyper;
+//! use futures::Future;
+//! use hyper::{Body, Response, Server};
+//! use hyper::service::service_fn_ok;
+//! use hyper::uring::{Uring, UringIncoming};
+//!
+//! # fn main() {
+//! let uring = Uring::new().expect("io_uring setup");
+//! uring.register_buffers(256, 16 * 1024).expect("register buffers");
+//!
+//! let incoming = UringIncoming::bind(&([127, 0, 0, 1], 3000).into(), uring)
+//!     .expect("bind");
+//! let server = Server::builder(incoming)
+//!     .serve(|| service_fn_ok(|_req| Response::new(Body::from("hello"))))
+//!     .map_err(|e| eprintln!("server error: {}", e));
+//!
+//! hyper::rt::run(server);
+//! # }
+//! ```
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use futures::{Async, Future, Poll, Stream};
+use futures::future::Executor;
+use futures::task::{self, Task};
+use libc;
+use mio;
+use mio::unix::EventedFd;
+use tokio_executor::DefaultExecutor;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_reactor::PollEvented;
+use tokio_tcp::TcpStream;
+
+use client::connect::{Connect, Connected, Destination};
+use client::HttpConnector;
+use server::conn::AddrIncoming;
+use self::sys::{Ring, Sqe};
+
+mod sys;
+
+/// How many entries the submission queue has.
+const RING_ENTRIES: u32 = 256;
+/// The size of a read, when there's no registered buffer free.
+const READ_SIZE: usize = 16 * 1024;
+/// The most bytes of a `write` copied into a single submission.
+const WRITE_SIZE: usize = 64 * 1024;
+/// The `user_data` of cancellations, whose completions are ignored.
+const CANCEL: u64 = u64::max_value();
+
+/// An io_uring instance, shared by the streams it drives.
+///
+/// Cloning a `Uring` gives another handle to the same instance.
+#[derive(Clone)]
+pub struct Uring {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    ring: Mutex<Ring>,
+    eventfd: RawFd,
+    ops: Mutex<Ops>,
+    fixed: Mutex<Vec<FixedBuf>>,
+    driver: Mutex<DriverState>,
+}
+
+#[derive(Default)]
+struct Ops {
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+    in_flight: usize,
+}
+
+enum Slot {
+    Free,
+    InFlight {
+        buf: OpBuf,
+        task: Option<Task>,
+        // the stream was dropped, so the buffer is freed on completion
+        orphaned: bool,
+    },
+    Done {
+        buf: OpBuf,
+        res: i32,
+    },
+}
+
+/// The memory of an operation, kept alive until it completes.
+enum OpBuf {
+    Owned(Vec<u8>),
+    Fixed(FixedBuf),
+}
+
+/// A buffer registered with the ring, by its index.
+struct FixedBuf {
+    index: u16,
+    buf: Vec<u8>,
+}
+
+#[derive(Default)]
+struct DriverState {
+    spawned: bool,
+    task: Option<Task>,
+}
+
+impl Uring {
+    /// Sets up a new io_uring instance.
+    pub fn new() -> io::Result<Uring> {
+        let ring = Ring::new(RING_ENTRIES)?;
+        let eventfd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if eventfd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if let Err(err) = ring.register_eventfd(eventfd) {
+            unsafe {
+                libc::close(eventfd);
+            }
+            return Err(err);
+        }
+        Ok(Uring {
+            shared: Arc::new(Shared {
+                ring: Mutex::new(ring),
+                eventfd: eventfd,
+                ops: Mutex::new(Ops::default()),
+                fixed: Mutex::new(Vec::new()),
+                driver: Mutex::new(DriverState::default()),
+            }),
+        })
+    }
+
+    /// Registers `count` buffers of `size` bytes with the kernel, for the
+    /// streams to read into.
+    ///
+    /// A stream holds a buffer from when it starts a read until all the
+    /// bytes read are taken out of it, so about one per stream reading at
+    /// the same time is enough. When none is free, a stream reads into a
+    /// buffer of its own.
+    ///
+    /// This can only be done once per `Uring`.
+    pub fn register_buffers(&self, count: usize, size: usize) -> io::Result<()> {
+        if count > u16::max_value() as usize || size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid io_uring buffer count or size"));
+        }
+        let mut bufs = (0..count).map(|_| vec![0; size]).collect::<Vec<_>>();
+        // the buffers are only freed once the ring is dropped, as they are
+        // put back in the pool after each read
+        unsafe {
+            self.shared.ring.lock().unwrap().register_buffers(&mut bufs)?;
+        }
+        let mut fixed = self.shared.fixed.lock().unwrap();
+        fixed.extend(bufs.into_iter().enumerate().map(|(index, buf)| FixedBuf {
+            index: index as u16,
+            buf: buf,
+        }));
+        Ok(())
+    }
+
+    /// Wraps a connected `TcpStream`, to do its reads and writes with this
+    /// `Uring`.
+    ///
+    /// This spawns the task reaping completions on the default executor, if
+    /// it's not running yet, which fails outside of a runtime.
+    pub fn wrap(&self, io: TcpStream) -> io::Result<UringStream> {
+        Shared::spawn_driver(&self.shared)?;
+        Ok(UringStream {
+            io: io,
+            shared: self.shared.clone(),
+            read: ReadState::Idle,
+            write: WriteState::Idle,
+            spare_read: None,
+            spare_write: None,
+        })
+    }
+}
+
+impl fmt::Debug for Uring {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Uring")
+            .finish()
+    }
+}
+
+impl Drop for Uring {
+    fn drop(&mut self) {
+        self.shared.notify_driver();
+    }
+}
+
+impl Shared {
+    fn spawn_driver(shared: &Arc<Shared>) -> io::Result<()> {
+        let mut driver = shared.driver.lock().unwrap();
+        if driver.spawned {
+            return Ok(());
+        }
+        let fut = Driver {
+            io: PollEvented::new(EventFd(shared.eventfd)),
+            shared: shared.clone(),
+        };
+        DefaultExecutor::current()
+            .execute(fut)
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "no executor to spawn the io_uring driver on"))?;
+        driver.spawned = true;
+        Ok(())
+    }
+
+    fn notify_driver(&self) {
+        if let Some(task) = self.driver.lock().unwrap().task.take() {
+            task.notify();
+        }
+    }
+
+    /// Submits an operation, which wakes the current task once complete.
+    ///
+    /// `prepare` fills in the entry, with the pointer to the start of `buf`.
+    fn submit<F>(&self, buf: OpBuf, prepare: F) -> io::Result<usize>
+    where
+        F: FnOnce(&mut Sqe, *mut u8),
+    {
+        let mut buf = buf;
+        let mut sqe = Sqe::default();
+        prepare(&mut sqe, buf.as_mut_ptr());
+        let index = {
+            let mut ops = self.ops.lock().unwrap();
+            let slot = Slot::InFlight {
+                buf: buf,
+                task: Some(task::current()),
+                orphaned: false,
+            };
+            let index = match ops.free.pop() {
+                Some(index) => {
+                    ops.slots[index] = slot;
+                    index
+                },
+                None => {
+                    ops.slots.push(slot);
+                    ops.slots.len() - 1
+                },
+            };
+            ops.in_flight += 1;
+            index
+        };
+        sqe.user_data = index as u64;
+        // the buffer is owned by the slot until its completion is taken
+        let res = unsafe { self.ring.lock().unwrap().submit(sqe) };
+        if let Err(err) = res {
+            let mut ops = self.ops.lock().unwrap();
+            ops.in_flight -= 1;
+            if let Slot::InFlight { buf, .. } = ops.take(index) {
+                self.recycle(buf);
+            }
+            return Err(err);
+        }
+        Ok(index)
+    }
+
+    /// Takes the result of the operation at `index`, once complete.
+    fn poll_op(&self, index: usize) -> Async<(i32, OpBuf)> {
+        self.reap();
+        let mut ops = self.ops.lock().unwrap();
+        match ops.slots[index] {
+            Slot::InFlight { ref mut task, .. } => {
+                *task = Some(task::current());
+                return Async::NotReady;
+            },
+            Slot::Done { .. } => (),
+            Slot::Free => unreachable!("poll_op of a free slot"),
+        }
+        match ops.take(index) {
+            Slot::Done { buf, res } => Async::Ready((res, buf)),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Cancels the operation at `index`, for a dropped stream.
+    fn cancel(&self, index: usize) {
+        {
+            let mut ops = self.ops.lock().unwrap();
+            match ops.slots[index] {
+                Slot::InFlight { ref mut task, ref mut orphaned, .. } => {
+                    *task = None;
+                    *orphaned = true;
+                },
+                Slot::Done { .. } => {
+                    if let Slot::Done { buf, .. } = ops.take(index) {
+                        self.recycle(buf);
+                    }
+                    return;
+                },
+                Slot::Free => return,
+            }
+        }
+        let mut sqe = Sqe::default();
+        sqe.opcode = sys::OP_ASYNC_CANCEL;
+        sqe.fd = -1;
+        sqe.addr = index as u64;
+        sqe.user_data = CANCEL;
+        // if it can't be canceled, it's freed once it completes on its own
+        if let Err(err) = unsafe { self.ring.lock().unwrap().submit(sqe) } {
+            debug!("io_uring cancel error: {}", err);
+        }
+    }
+
+    /// Takes the completions off the ring, waking the tasks waiting on them.
+    fn reap(&self) {
+        let mut woken = Vec::new();
+        {
+            let mut ring = self.ring.lock().unwrap();
+            let mut ops = self.ops.lock().unwrap();
+            while let Some((user_data, res)) = ring.complete() {
+                if user_data == CANCEL {
+                    continue;
+                }
+                let index = user_data as usize;
+                ops.in_flight -= 1;
+                match ::std::mem::replace(&mut ops.slots[index], Slot::Free) {
+                    Slot::InFlight { buf, orphaned: true, .. } => {
+                        ops.free.push(index);
+                        self.recycle(buf);
+                    },
+                    Slot::InFlight { buf, task, orphaned: false } => {
+                        ops.slots[index] = Slot::Done {
+                            buf: buf,
+                            res: res,
+                        };
+                        woken.extend(task);
+                    },
+                    _ => unreachable!("completion of an operation not in flight"),
+                }
+            }
+            if ops.in_flight == 0 {
+                // the driver may be waiting on the last operations to exit
+                if let Some(task) = self.driver.lock().unwrap().task.take() {
+                    woken.push(task);
+                }
+            }
+        }
+        for task in woken {
+            task.notify();
+        }
+    }
+
+    fn recycle(&self, buf: OpBuf) {
+        if let OpBuf::Fixed(fixed) = buf {
+            self.fixed.lock().unwrap().push(fixed);
+        }
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        // The driver can be dropped before the operations in flight have
+        // completed, such as when its runtime shuts down. The kernel may
+        // still write to their buffers then, so those are leaked instead.
+        let ops = self.ops.get_mut().unwrap_or_else(PoisonError::into_inner);
+        if ops.in_flight > 0 {
+            debug!("io_uring dropped with {} operations in flight", ops.in_flight);
+            for slot in ops.slots.drain(..) {
+                if let Slot::InFlight { buf, .. } = slot {
+                    ::std::mem::forget(buf);
+                }
+            }
+        }
+        unsafe {
+            libc::close(self.eventfd);
+        }
+    }
+}
+
+impl Ops {
+    fn take(&mut self, index: usize) -> Slot {
+        self.free.push(index);
+        ::std::mem::replace(&mut self.slots[index], Slot::Free)
+    }
+}
+
+impl OpBuf {
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        match *self {
+            OpBuf::Owned(ref mut buf) => buf.as_mut_ptr(),
+            OpBuf::Fixed(ref mut fixed) => fixed.buf.as_mut_ptr(),
+        }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        match *self {
+            OpBuf::Owned(ref buf) => buf,
+            OpBuf::Fixed(ref fixed) => &fixed.buf,
+        }
+    }
+}
+
+/// The eventfd the ring signals completions on, for the reactor.
+struct EventFd(RawFd);
+
+impl mio::Evented for EventFd {
+    fn register(&self, poll: &mio::Poll, token: mio::Token, interest: mio::Ready, opts: mio::PollOpt) -> io::Result<()> {
+        EventedFd(&self.0).register(poll, token, interest, opts)
+    }
+
+    fn reregister(&self, poll: &mio::Poll, token: mio::Token, interest: mio::Ready, opts: mio::PollOpt) -> io::Result<()> {
+        EventedFd(&self.0).reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        EventedFd(&self.0).deregister(poll)
+    }
+}
+
+/// The task reaping completions, whenever the eventfd is signaled.
+///
+/// It ends once every handle to the `Uring` is dropped, and the operations
+/// the dropped streams left in flight have completed.
+struct Driver {
+    io: PollEvented<EventFd>,
+    shared: Arc<Shared>,
+}
+
+impl Future for Driver {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        self.shared.driver.lock().unwrap().task = Some(task::current());
+        loop {
+            match self.io.poll_read_ready(mio::Ready::readable()) {
+                Ok(Async::Ready(_)) => {
+                    let mut count = [0u8; 8];
+                    unsafe {
+                        libc::read(self.shared.eventfd, count.as_mut_ptr() as *mut libc::c_void, count.len());
+                    }
+                    if let Err(err) = self.io.clear_read_ready(mio::Ready::readable()) {
+                        debug!("io_uring driver error: {}", err);
+                        return Err(());
+                    }
+                },
+                Ok(Async::NotReady) => break,
+                Err(err) => {
+                    debug!("io_uring driver error: {}", err);
+                    return Err(());
+                },
+            }
+        }
+        self.shared.reap();
+
+        let idle = self.shared.ops.lock().unwrap().in_flight == 0;
+        if idle && Arc::strong_count(&self.shared) == 1 {
+            trace!("io_uring driver finished");
+            return Ok(Async::Ready(()));
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+/// A `TcpStream` whose reads and writes are done by a [`Uring`](Uring).
+pub struct UringStream {
+    io: TcpStream,
+    shared: Arc<Shared>,
+    read: ReadState,
+    write: WriteState,
+    spare_read: Option<Vec<u8>>,
+    spare_write: Option<Vec<u8>>,
+}
+
+enum ReadState {
+    Idle,
+    Reading(usize),
+    Buffered {
+        buf: OpBuf,
+        pos: usize,
+        end: usize,
+    },
+}
+
+enum WriteState {
+    Idle,
+    Writing {
+        index: usize,
+        pos: usize,
+        end: usize,
+    },
+}
+
+impl UringStream {
+    /// Get a reference to the wrapped stream.
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.io
+    }
+
+    fn start_read(&mut self) -> io::Result<()> {
+        let fixed = self.shared.fixed.lock().unwrap().pop();
+        let buf = match fixed {
+            Some(fixed) => OpBuf::Fixed(fixed),
+            None => {
+                let buf = self.spare_read.take().unwrap_or_else(|| vec![0; READ_SIZE]);
+                OpBuf::Owned(buf)
+            },
+        };
+        let fd = self.io.as_raw_fd();
+        let (len, buf_index) = match buf {
+            OpBuf::Fixed(ref fixed) => (fixed.buf.len(), Some(fixed.index)),
+            OpBuf::Owned(ref buf) => (buf.len(), None),
+        };
+        let index = self.shared.submit(buf, |sqe, ptr| {
+            sqe.opcode = match buf_index {
+                Some(buf_index) => {
+                    sqe.buf_index = buf_index;
+                    sys::OP_READ_FIXED
+                },
+                None => sys::OP_READ,
+            };
+            sqe.fd = fd;
+            // sockets have no offset, so use the current position
+            sqe.off = u64::max_value();
+            sqe.addr = ptr as u64;
+            sqe.len = len as u32;
+        })?;
+        self.read = ReadState::Reading(index);
+        Ok(())
+    }
+
+    fn submit_write(&mut self, buf: Vec<u8>, pos: usize) -> io::Result<()> {
+        let fd = self.io.as_raw_fd();
+        let end = buf.len();
+        let index = self.shared.submit(OpBuf::Owned(buf), |sqe, ptr| {
+            sqe.opcode = sys::OP_WRITE;
+            sqe.fd = fd;
+            sqe.off = u64::max_value();
+            sqe.addr = ptr as u64 + pos as u64;
+            sqe.len = (end - pos) as u32;
+        })?;
+        self.write = WriteState::Writing {
+            index: index,
+            pos: pos,
+            end: end,
+        };
+        Ok(())
+    }
+
+    fn recycle_read(&mut self, buf: OpBuf) {
+        match buf {
+            OpBuf::Owned(buf) => self.spare_read = Some(buf),
+            fixed => self.shared.recycle(fixed),
+        }
+    }
+
+    /// Waits for the bytes already taken by `write` to be written.
+    fn poll_written(&mut self) -> Poll<(), io::Error> {
+        loop {
+            let (index, pos, end) = match self.write {
+                WriteState::Idle => return Ok(Async::Ready(())),
+                WriteState::Writing { index, pos, end } => (index, pos, end),
+            };
+            let (res, buf) = match self.shared.poll_op(index) {
+                Async::Ready(done) => done,
+                Async::NotReady => return Ok(Async::NotReady),
+            };
+            self.write = WriteState::Idle;
+            let buf = match buf {
+                OpBuf::Owned(buf) => buf,
+                OpBuf::Fixed(_) => unreachable!("writes aren't to fixed buffers"),
+            };
+            let pos = if res < 0 {
+                let err = io::Error::from_raw_os_error(-res);
+                if err.kind() != io::ErrorKind::Interrupted {
+                    self.spare_write = Some(buf);
+                    return Err(err);
+                }
+                pos
+            } else if res == 0 {
+                self.spare_write = Some(buf);
+                return Err(io::ErrorKind::WriteZero.into());
+            } else {
+                pos + res as usize
+            };
+            if pos == end {
+                self.spare_write = Some(buf);
+                return Ok(Async::Ready(()));
+            }
+            trace!("io_uring partial write, {} of {} bytes left", end - pos, end);
+            self.submit_write(buf, pos)?;
+        }
+    }
+}
+
+impl Read for UringStream {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = match self.read {
+                ReadState::Buffered { ref buf, ref mut pos, end } => {
+                    let n = ::std::cmp::min(dst.len(), end - *pos);
+                    dst[..n].copy_from_slice(&buf.as_slice()[*pos..*pos + n]);
+                    *pos += n;
+                    if *pos < end {
+                        return Ok(n);
+                    }
+                    n
+                },
+                ReadState::Reading(index) => {
+                    let (res, buf) = match self.shared.poll_op(index) {
+                        Async::Ready(done) => done,
+                        Async::NotReady => return Err(io::ErrorKind::WouldBlock.into()),
+                    };
+                    if res > 0 {
+                        self.read = ReadState::Buffered {
+                            buf: buf,
+                            pos: 0,
+                            end: res as usize,
+                        };
+                        continue;
+                    }
+                    self.read = ReadState::Idle;
+                    self.recycle_read(buf);
+                    if res == 0 {
+                        return Ok(0);
+                    }
+                    let err = io::Error::from_raw_os_error(-res);
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                    continue;
+                },
+                ReadState::Idle => {
+                    if dst.is_empty() {
+                        return Ok(0);
+                    }
+                    self.start_read()?;
+                    continue;
+                },
+            };
+            // all of the bytes read were taken, so give back the buffer
+            if let ReadState::Buffered { buf, .. } = ::std::mem::replace(&mut self.read, ReadState::Idle) {
+                self.recycle_read(buf);
+            }
+            return Ok(n);
+        }
+    }
+}
+
+impl Write for UringStream {
+    fn write(&mut self, src: &[u8]) -> io::Result<usize> {
+        match self.poll_written()? {
+            Async::Ready(()) => (),
+            Async::NotReady => return Err(io::ErrorKind::WouldBlock.into()),
+        }
+        if src.is_empty() {
+            return Ok(0);
+        }
+        let n = ::std::cmp::min(src.len(), WRITE_SIZE);
+        let mut buf = self.spare_write.take().unwrap_or_default();
+        buf.clear();
+        buf.extend_from_slice(&src[..n]);
+        self.submit_write(buf, 0)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.poll_written()? {
+            Async::Ready(()) => Ok(()),
+            Async::NotReady => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl AsyncRead for UringStream {}
+
+impl AsyncWrite for UringStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        try_ready!(self.poll_written());
+        AsyncWrite::shutdown(&mut self.io)
+    }
+}
+
+impl Drop for UringStream {
+    fn drop(&mut self) {
+        match ::std::mem::replace(&mut self.read, ReadState::Idle) {
+            ReadState::Reading(index) => self.shared.cancel(index),
+            ReadState::Buffered { buf, .. } => self.shared.recycle(buf),
+            ReadState::Idle => (),
+        }
+        if let WriteState::Writing { index, .. } = self.write {
+            self.shared.cancel(index);
+        }
+        self.shared.notify_driver();
+    }
+}
+
+impl fmt::Debug for UringStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UringStream")
+            .field("io", &self.io)
+            .finish()
+    }
+}
+
+/// Accepts TCP connections, wrapping each with a [`Uring`](Uring).
+///
+/// It can be given to [`Server::builder`](::server::Server::builder).
+pub struct UringIncoming {
+    incoming: AddrIncoming,
+    uring: Uring,
+}
+
+impl UringIncoming {
+    /// Binds to `addr`, to accept connections driven by `uring`.
+    pub fn bind(addr: &SocketAddr, uring: Uring) -> ::Result<UringIncoming> {
+        AddrIncoming::new(addr, None).map(|incoming| UringIncoming {
+            incoming: incoming,
+            uring: uring,
+        })
+    }
+
+    /// Get the local address bound to this listener.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.incoming.local_addr()
+    }
+
+    /// Set whether TCP keepalive messages are enabled on accepted connections.
+    ///
+    /// See [`AddrIncoming::set_keepalive`](::server::conn::AddrIncoming::set_keepalive).
+    pub fn set_keepalive(&mut self, keepalive: Option<Duration>) -> &mut Self {
+        self.incoming.set_keepalive(keepalive);
+        self
+    }
+
+    /// Set the value of `TCP_NODELAY` option for accepted connections.
+    pub fn set_nodelay(&mut self, enabled: bool) -> &mut Self {
+        self.incoming.set_nodelay(enabled);
+        self
+    }
+}
+
+impl Stream for UringIncoming {
+    type Item = UringStream;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match try_ready!(self.incoming.poll()) {
+            Some(stream) => {
+                let (tcp, _remote_addr) = stream.into_parts();
+                self.uring.wrap(tcp).map(|io| Async::Ready(Some(io)))
+            },
+            None => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+impl fmt::Debug for UringIncoming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UringIncoming")
+            .field("incoming", &self.incoming)
+            .finish()
+    }
+}
+
+/// A connector wrapping the TCP connections of another with a
+/// [`Uring`](Uring), such as for a `Client`.
+#[derive(Clone, Debug)]
+pub struct UringConnector<C = HttpConnector> {
+    inner: C,
+    uring: Uring,
+}
+
+impl<C> UringConnector<C> {
+    /// Wrap a connector, driving its connections with `uring`.
+    pub fn new(inner: C, uring: Uring) -> UringConnector<C> {
+        UringConnector {
+            inner: inner,
+            uring: uring,
+        }
+    }
+}
+
+impl<C> Connect for UringConnector<C>
+where
+    C: Connect<Transport=TcpStream>,
+{
+    type Transport = UringStream;
+    type Error = Box<StdError + Send + Sync>;
+    type Future = UringConnecting<C::Future>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        UringConnecting {
+            fut: self.inner.connect(dst),
+            uring: self.uring.clone(),
+        }
+    }
+}
+
+/// A Future wrapping a connection with a `Uring` once connected.
+#[must_use = "futures do nothing unless polled"]
+pub struct UringConnecting<F> {
+    fut: F,
+    uring: Uring,
+}
+
+impl<F, E> Future for UringConnecting<F>
+where
+    F: Future<Item=(TcpStream, Connected), Error=E>,
+    E: Into<Box<StdError + Send + Sync>>,
+{
+    type Item = (UringStream, Connected);
+    type Error = Box<StdError + Send + Sync>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (tcp, connected) = try_ready!(self.fut.poll().map_err(Into::into));
+        let io = self.uring.wrap(tcp)?;
+        Ok(Async::Ready((io, connected)))
+    }
+}
+
+impl<F> fmt::Debug for UringConnecting<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("UringConnecting")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use futures::{Future, Stream};
+    use tokio::runtime::Runtime;
+    use tokio_io::io as async_io;
+    use tokio_tcp::TcpStream;
+
+    use {Body, Client, Request, Response, Server};
+    use client::HttpConnector;
+    use service::service_fn;
+    use super::{Uring, UringConnector, UringIncoming};
+
+    // Kernels without io_uring, or sandboxes denying it, skip the tests.
+    fn uring() -> Option<Uring> {
+        match Uring::new() {
+            Ok(uring) => Some(uring),
+            Err(err) => {
+                eprintln!("io_uring unavailable, skipping: {}", err);
+                None
+            },
+        }
+    }
+
+    #[test]
+    fn serve_and_connect() {
+        let uring = match uring() {
+            Some(uring) => uring,
+            None => return,
+        };
+        let mut rt = Runtime::new().unwrap();
+        // only one, so that the other stream reads into its own buffers
+        uring.register_buffers(1, 4096).unwrap();
+
+        let incoming = UringIncoming::bind(&([127, 0, 0, 1], 0).into(), uring.clone()).unwrap();
+        let addr = incoming.local_addr();
+        let server = Server::builder(incoming)
+            .serve(|| service_fn(|req: Request<Body>| {
+                req.into_body().concat2().map(|body| Response::new(Body::from(body)))
+            }))
+            .map_err(|e| panic!("server error: {}", e));
+        rt.spawn(server);
+
+        let client = Client::builder()
+            .build::<_, Body>(UringConnector::new(HttpConnector::new(1), uring));
+        let body = vec![b'x'; 100_000];
+        for _ in 0..3 {
+            let req = Request::post(format!("http://{}/", addr))
+                .body(Body::from(body.clone()))
+                .unwrap();
+            let echo = rt.block_on(client.request(req).and_then(|res| {
+                assert_eq!(res.status(), 200);
+                res.into_body().concat2()
+            })).unwrap();
+            assert_eq!(echo.len(), body.len());
+            assert!(echo.iter().all(|&b| b == b'x'));
+        }
+    }
+
+    #[test]
+    fn partial_writes_are_resumed() {
+        let uring = match uring() {
+            Some(uring) => uring,
+            None => return,
+        };
+        let mut rt = Runtime::new().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let data = (0..4 * 1024 * 1024).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+        let reader = thread::spawn(move || {
+            let (mut tcp, _) = listener.accept().unwrap();
+            // the send buffer fills up meanwhile, so the kernel only
+            // writes part of each submission
+            thread::sleep(Duration::from_millis(100));
+            let mut buf = Vec::new();
+            tcp.read_to_end(&mut buf).unwrap();
+            buf
+        });
+
+        let fut = TcpStream::connect(&addr)
+            .and_then(move |tcp| {
+                tcp.set_send_buffer_size(4096)?;
+                uring.wrap(tcp)
+            })
+            .and_then(move |io| async_io::write_all(io, data))
+            .and_then(|(io, data)| async_io::shutdown(io).map(move |_| data));
+        let data = rt.block_on(fut).unwrap();
+
+        let read = reader.join().unwrap();
+        assert_eq!(read.len(), data.len());
+        assert!(read == data, "bytes written out of order");
+    }
+
+    #[test]
+    fn drop_stream_while_reading() {
+        let uring = match uring() {
+            Some(uring) => uring,
+            None => return,
+        };
+        let mut rt = Runtime::new().unwrap();
+        uring.register_buffers(1, 4096).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+        let peer = thread::spawn(move || {
+            let (_idle, _) = listener.accept().unwrap();
+            let (mut tcp, _) = listener.accept().unwrap();
+            tcp.write_all(b"hello").unwrap();
+            // the first sends nothing, so its read is still in flight once
+            // the stream is dropped
+            let _ = done_rx.recv();
+        });
+
+        let uring2 = uring.clone();
+        rt.block_on(TcpStream::connect(&addr).and_then(move |tcp| {
+            let mut io = uring2.wrap(tcp)?;
+            let mut buf = [0; 64];
+            match io.read(&mut buf) {
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => (),
+                res => panic!("read should wait on the peer: {:?}", res),
+            }
+            drop(io);
+            Ok(())
+        })).unwrap();
+
+        // the canceled read gives its registered buffer back once reaped
+        let start = Instant::now();
+        while uring.shared.fixed.lock().unwrap().len() != 1 {
+            assert!(start.elapsed() < Duration::from_secs(5), "canceled read not reaped");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(uring.shared.ops.lock().unwrap().in_flight, 0);
+
+        // and the ring keeps working for the other streams
+        let fut = TcpStream::connect(&addr)
+            .and_then(move |tcp| uring.wrap(tcp))
+            .and_then(|io| async_io::read_exact(io, [0; 5]));
+        let (_io, buf) = rt.block_on(fut).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        done_tx.send(()).unwrap();
+        peer.join().unwrap();
+    }
+}
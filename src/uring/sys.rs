@@ -0,0 +1,295 @@
+//! A minimal io_uring, over the raw system calls.
+//!
+//! The layouts are from `linux/io_uring.h`.
+
+use std::io;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use libc;
+
+pub(super) const OP_ASYNC_CANCEL: u8 = 14;
+pub(super) const OP_READ_FIXED: u8 = 4;
+pub(super) const OP_READ: u8 = 22;
+pub(super) const OP_WRITE: u8 = 23;
+
+const OFF_SQ_RING: libc::off_t = 0;
+const OFF_CQ_RING: libc::off_t = 0x800_0000;
+const OFF_SQES: libc::off_t = 0x1000_0000;
+
+const FEAT_FAST_POLL: u32 = 1 << 5;
+const REGISTER_BUFFERS: u32 = 0;
+const REGISTER_EVENTFD: u32 = 4;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+/// A submission queue entry.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub(super) struct Sqe {
+    pub(super) opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    pub(super) fd: i32,
+    pub(super) off: u64,
+    pub(super) addr: u64,
+    pub(super) len: u32,
+    op_flags: u32,
+    pub(super) user_data: u64,
+    pub(super) buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+/// A completion queue entry.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mmap {
+    fn new(fd: RawFd, len: usize, offset: libc::off_t) -> io::Result<Mmap> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap {
+            ptr: ptr,
+            len: len,
+        })
+    }
+
+    // Unsafe: `offset` must be in the mapping, and aligned for `T`.
+    unsafe fn at<T>(&self, offset: u32) -> *mut T {
+        (self.ptr as *mut u8).offset(offset as isize) as *mut T
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+/// An io_uring instance, and its mapped queues.
+///
+/// It isn't synchronized: the owner must only use it from one thread at a
+/// time, such as behind a `Mutex`.
+pub(super) struct Ring {
+    fd: RawFd,
+    sq_head: *const AtomicU32,
+    sq_tail: *const AtomicU32,
+    sq_mask: u32,
+    sq_entries: u32,
+    sq_array: *mut u32,
+    sqes: *mut Sqe,
+    cq_head: *const AtomicU32,
+    cq_tail: *const AtomicU32,
+    cq_mask: u32,
+    cqes: *const Cqe,
+    // kept mapped for the pointers above
+    _sq_ring: Mmap,
+    _cq_ring: Mmap,
+    _sqes: Mmap,
+}
+
+// The pointers are into the mappings owned by the `Ring`.
+unsafe impl Send for Ring {}
+
+impl Ring {
+    pub(super) fn new(entries: u32) -> io::Result<Ring> {
+        let mut params = Params::default();
+        let fd = unsafe {
+            libc::syscall(libc::SYS_io_uring_setup, entries, &mut params as *mut Params)
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = fd as RawFd;
+        // without fast poll, reads of an idle socket fail with `EAGAIN`,
+        // instead of waiting for it to be readable
+        if params.features & FEAT_FAST_POLL == 0 {
+            unsafe {
+                libc::close(fd);
+            }
+            return Err(io::Error::new(io::ErrorKind::Other, "io_uring needs Linux 5.7 or newer"));
+        }
+        match Ring::map(fd, &params) {
+            Ok(ring) => Ok(ring),
+            Err(err) => {
+                unsafe {
+                    libc::close(fd);
+                }
+                Err(err)
+            },
+        }
+    }
+
+    fn map(fd: RawFd, p: &Params) -> io::Result<Ring> {
+        let sq_len = p.sq_off.array as usize + p.sq_entries as usize * 4;
+        let cq_len = p.cq_off.cqes as usize + p.cq_entries as usize * ::std::mem::size_of::<Cqe>();
+        let sq_ring = Mmap::new(fd, sq_len, OFF_SQ_RING)?;
+        let cq_ring = Mmap::new(fd, cq_len, OFF_CQ_RING)?;
+        let sqes = Mmap::new(fd, p.sq_entries as usize * ::std::mem::size_of::<Sqe>(), OFF_SQES)?;
+        unsafe {
+            Ok(Ring {
+                fd: fd,
+                sq_head: sq_ring.at(p.sq_off.head),
+                sq_tail: sq_ring.at(p.sq_off.tail),
+                sq_mask: *sq_ring.at::<u32>(p.sq_off.ring_mask),
+                sq_entries: *sq_ring.at::<u32>(p.sq_off.ring_entries),
+                sq_array: sq_ring.at(p.sq_off.array),
+                sqes: sqes.at(0),
+                cq_head: cq_ring.at(p.cq_off.head),
+                cq_tail: cq_ring.at(p.cq_off.tail),
+                cq_mask: *cq_ring.at::<u32>(p.cq_off.ring_mask),
+                cqes: cq_ring.at(p.cq_off.cqes),
+                _sq_ring: sq_ring,
+                _cq_ring: cq_ring,
+                _sqes: sqes,
+            })
+        }
+    }
+
+    /// Has the completions signaled on `eventfd`.
+    pub(super) fn register_eventfd(&self, eventfd: RawFd) -> io::Result<()> {
+        self.register(REGISTER_EVENTFD, &eventfd as *const RawFd as *const libc::c_void, 1)
+    }
+
+    /// Registers `buffers` for `OP_READ_FIXED`, by their index.
+    ///
+    /// Unsafe: the buffers must stay allocated for as long as the ring.
+    pub(super) unsafe fn register_buffers(&self, buffers: &mut [Vec<u8>]) -> io::Result<()> {
+        let iovecs = buffers.iter_mut()
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            })
+            .collect::<Vec<_>>();
+        self.register(REGISTER_BUFFERS, iovecs.as_ptr() as *const libc::c_void, iovecs.len() as u32)
+    }
+
+    fn register(&self, opcode: u32, arg: *const libc::c_void, nr_args: u32) -> io::Result<()> {
+        let ret = unsafe {
+            libc::syscall(libc::SYS_io_uring_register, self.fd, opcode, arg, nr_args)
+        };
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Submits `sqe` to the kernel.
+    ///
+    /// Unsafe: any memory `sqe` points to must stay valid until its
+    /// completion.
+    pub(super) unsafe fn submit(&mut self, sqe: Sqe) -> io::Result<()> {
+        let head = (*self.sq_head).load(Ordering::Acquire);
+        let tail = (*self.sq_tail).load(Ordering::Relaxed);
+        if tail.wrapping_sub(head) >= self.sq_entries {
+            // each entry is submitted right away, so this means the kernel
+            // couldn't take the previous ones
+            return Err(io::Error::new(io::ErrorKind::Other, "io_uring submission queue is full"));
+        }
+        let index = tail & self.sq_mask;
+        *self.sqes.offset(index as isize) = sqe;
+        *self.sq_array.offset(index as isize) = index;
+        (*self.sq_tail).store(tail.wrapping_add(1), Ordering::Release);
+        loop {
+            let ret = libc::syscall(libc::SYS_io_uring_enter, self.fd, 1u32, 0u32, 0u32, ptr::null::<libc::c_void>(), 0usize);
+            if ret >= 0 {
+                return Ok(());
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+    }
+
+    /// Takes the next completion, as its `user_data` and result.
+    pub(super) fn complete(&mut self) -> Option<(u64, i32)> {
+        unsafe {
+            let head = (*self.cq_head).load(Ordering::Relaxed);
+            let tail = (*self.cq_tail).load(Ordering::Acquire);
+            if head == tail {
+                return None;
+            }
+            let cqe = *self.cqes.offset((head & self.cq_mask) as isize);
+            (*self.cq_head).store(head.wrapping_add(1), Ordering::Release);
+            Some((cqe.user_data, cqe.res))
+        }
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
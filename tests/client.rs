@@ -1,5 +1,6 @@
 #![deny(warnings)]
 extern crate bytes;
+extern crate http;
 extern crate hyper;
 extern crate futures;
 extern crate futures_timer;
@@ -708,7 +709,7 @@ test! {
 mod dispatch_impl {
     use super::*;
     use std::io::{self, Read, Write};
-    use std::sync::Arc;
+    use std::sync::{Arc, Mutex};
     use std::sync::atomic::{AtomicUsize, Ordering};
     use std::thread;
     use std::time::Duration;
@@ -1114,6 +1115,8 @@ mod dispatch_impl {
 
     #[test]
     fn client_keep_alive_0() {
+        use hyper::ext::ConnectionInfo;
+
         let _ = pretty_env_logger::try_init();
         let server = TcpListener::bind("127.0.0.1:0").unwrap();
         let addr = server.local_addr().unwrap();
@@ -1145,136 +1148,889 @@ mod dispatch_impl {
             let _ = tx2.send(());
         });
 
-
-        assert_eq!(connects.load(Ordering::SeqCst), 0);
-
+
+        assert_eq!(connects.load(Ordering::SeqCst), 0);
+
+        let rx = rx1.expect("thread panicked");
+        let req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req);
+        let res = res.join(rx).map(|r| r.0).wait().unwrap();
+        let info = res.extensions().get::<ConnectionInfo>().expect("connection info").clone();
+        assert!(!info.is_reused());
+        assert_eq!(info.pool_key(), format!("http://{}", addr));
+        assert_eq!(info.version(), hyper::Version::HTTP_11);
+        assert_eq!(info.alpn_protocol(), None);
+        assert!(!info.is_proxied());
+
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+
+        // sleep real quick to let the threadpool put connection in ready
+        // state and back into client pool
+        thread::sleep(Duration::from_millis(50));
+
+        let rx = rx2.expect("thread panicked");
+        let req = Request::builder()
+            .uri(&*format!("http://{}/b", addr))
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req);
+        let res = res.join(rx).map(|r| r.0).wait().unwrap();
+        let info2 = res.extensions().get::<ConnectionInfo>().expect("connection info");
+        assert!(info2.is_reused());
+        assert_eq!(info2.id(), info.id());
+
+        assert_eq!(connects.load(Ordering::SeqCst), 1, "second request should still only have 1 connect");
+        drop(client);
+
+        runtime.shutdown_on_idle().wait().expect("rt shutdown");
+    }
+
+    #[test]
+    fn client_records_timings() {
+        use hyper::ext::Timings;
+
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .record_timings(true)
+            .build(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        let (tx2, rx2) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            sock.read(&mut buf).expect("read 1");
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello").expect("write 1");
+            let _ = tx1.send(());
+
+            sock.read(&mut buf).expect("read 2");
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 2");
+            let _ = tx2.send(());
+        });
+
+        let rx = rx1.expect("thread panicked");
+        let req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req).and_then(|res| {
+            let timings = res.extensions().get::<Timings>().expect("timings").clone();
+            assert!(timings.response_complete().is_none());
+            res.into_body().concat2().map(move |_| timings)
+        });
+        let timings = res.join(rx).map(|r| r.0).wait().unwrap();
+
+        // an IP address isn't resolved
+        assert_eq!(timings.dns_resolved(), None);
+        assert_eq!(timings.tls_handshaken(), None);
+        let connected = timings.connected().expect("connected");
+        let written = timings.request_written().expect("request_written");
+        let first_byte = timings.first_byte().expect("first_byte");
+        let complete = timings.response_complete().expect("response_complete");
+        assert!(timings.start() <= connected);
+        assert!(connected <= written);
+        assert!(written <= first_byte);
+        assert!(first_byte <= complete);
+
+        // sleep real quick to let the threadpool put connection in ready
+        // state and back into client pool
+        thread::sleep(Duration::from_millis(50));
+
+        let rx = rx2.expect("thread panicked");
+        let req = Request::builder()
+            .uri(&*format!("http://{}/b", addr))
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req);
+        let res = res.join(rx).map(|r| r.0).wait().unwrap();
+
+        // the connection was reused
+        let timings = res.extensions().get::<Timings>().expect("timings");
+        assert_eq!(timings.connected(), None);
+        assert!(timings.request_written().is_some());
+        assert!(timings.response_complete().is_some());
+    }
+
+    #[test]
+    fn client_request_stages() {
+        use hyper::ext::{RequestStage, Timings};
+
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            sock.read(&mut buf).expect("read 1");
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello").expect("write 1");
+            let _ = tx1.send(());
+        });
+
+        let stages = Arc::new(Mutex::new(Vec::new()));
+        let stages2 = stages.clone();
+        let timings = Timings::new().on_stage(move |stage| {
+            stages2.lock().unwrap().push(stage);
+        });
+        let rx = rx1.expect("thread panicked");
+        let mut req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Body::empty())
+            .unwrap();
+        // kept, even though the client doesn't record timings itself
+        req.extensions_mut().insert(timings.clone());
+        let res = client.request(req).and_then(|res| {
+            res.into_body().concat2()
+        });
+        res.join(rx).wait().unwrap();
+
+        assert_eq!(*stages.lock().unwrap(), [
+            RequestStage::Queued,
+            RequestStage::ConnectionAssigned,
+            RequestStage::HeadersWritten,
+            RequestStage::RequestWritten,
+            RequestStage::ResponseHeaders,
+            RequestStage::ResponseComplete,
+        ]);
+        let queued = timings.queued().expect("queued");
+        let assigned = timings.connection_assigned().expect("connection_assigned");
+        let headers = timings.headers_written().expect("headers_written");
+        assert!(queued <= assigned);
+        assert!(timings.connected().expect("connected") <= assigned);
+        assert!(assigned <= headers);
+        assert!(headers <= timings.request_written().expect("request_written"));
+    }
+
+    #[test]
+    fn client_stats() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+
+        let client: Client<_, Body> = Client::builder()
+            .executor(runtime.executor())
+            .build(connector);
+
+        const RESPONSES: &'static [u8] = b"\
+            HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello\
+            HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n\
+        ";
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let mut read = sock.read(&mut buf).expect("read 1");
+            sock.write_all(&RESPONSES[..43]).expect("write 1");
+            read += sock.read(&mut buf).expect("read 2");
+            sock.write_all(&RESPONSES[43..]).expect("write 2");
+            let _ = tx1.send(read);
+        });
+
+        for path in &["a", "b"] {
+            let res = client.get(format!("http://{}/{}", addr, path).parse().unwrap())
+                .and_then(|res| res.into_body().concat2());
+            res.wait().unwrap();
+        }
+        let read = rx1.wait().expect("thread panicked");
+
+        // nothing listens on this port
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        client.get(format!("http://{}/c", closed).parse().unwrap()).wait().unwrap_err();
+
+        let stats = client.clone().stats();
+        assert_eq!(stats.requests(), 3);
+        assert_eq!(stats.success_responses(), 1);
+        assert_eq!(stats.client_error_responses(), 1);
+        assert_eq!(stats.server_error_responses(), 0);
+        assert_eq!(stats.connect_errors(), 1);
+        assert_eq!(stats.retries(), 0);
+        assert_eq!(stats.bytes_read(), RESPONSES.len());
+        assert_eq!(stats.bytes_written(), read);
+    }
+
+    #[test]
+    fn client_poll_ready_limits_in_flight() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+
+        let mut client: Client<_, Body> = Client::builder()
+            .executor(runtime.executor())
+            .max_in_flight_requests(1)
+            .build(connector);
+
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            sock.read(&mut buf).expect("read 1");
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 1");
+        });
+
+        let res = client.get(format!("http://{}/a", addr).parse().unwrap());
+        let mut other = client.clone();
+        futures::future::lazy(|| {
+            // the limit is shared by clones
+            assert!(other.poll_ready().unwrap().is_not_ready());
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+
+        res.wait().unwrap();
+        futures::future::lazy(|| {
+            assert!(client.poll_ready().unwrap().is_ready());
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn client_interceptors() {
+        use hyper::{Response, Uri};
+        use hyper::client::Interceptor;
+        use hyper::header::HeaderValue;
+
+        struct Auth;
+
+        impl Interceptor for Auth {
+            fn request(&self, req: &mut http::request::Parts) -> Option<Response<Body>> {
+                req.headers.insert("authorization", HeaderValue::from_static("secret"));
+                None
+            }
+
+            fn response(&self, _method: &Method, uri: &Uri, res: &mut Response<Body>) {
+                let path = HeaderValue::from_str(uri.path()).unwrap();
+                res.headers_mut().insert("x-path", path);
+            }
+        }
+
+        struct Cached;
+
+        impl Interceptor for Cached {
+            fn request(&self, req: &mut http::request::Parts) -> Option<Response<Body>> {
+                if req.uri.path() == "/cached" {
+                    Some(Response::new(Body::from("from cache")))
+                } else {
+                    None
+                }
+            }
+        }
+
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+
+        let client: Client<_, Body> = Client::builder()
+            .executor(runtime.executor())
+            .intercept(Auth)
+            .intercept(Cached)
+            .build(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read 1");
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 1");
+            let _ = tx1.send(String::from_utf8_lossy(&buf[..n]).into_owned());
+        });
+
+        let res = client.get(format!("http://{}/a", addr).parse().unwrap()).wait().unwrap();
+        assert_eq!(res.headers()["x-path"], "/a");
+        let req = rx1.wait().expect("thread panicked");
+        assert!(req.contains("authorization: secret\r\n"), "{:?}", req);
+
+        // answered without a connection, but still seen by `Auth`
+        let res = client.get(format!("http://{}/cached", addr).parse().unwrap()).wait().unwrap();
+        assert_eq!(res.headers()["x-path"], "/cached");
+        let body = res.into_body().concat2().wait().unwrap();
+        assert_eq!(&body[..], b"from cache");
+        assert_eq!(client.stats().requests(), 1);
+    }
+
+    #[test]
+    fn mock_connector_script() {
+        use hyper::mock::{Connector, Script};
+
+        let _ = pretty_env_logger::try_init();
+        let runtime = Runtime::new().unwrap();
+        let connector = Connector::new();
+        connector.add("mock.local", Script::new()
+            .expect("GET /a HTTP/1.1\r\nhost: mock.local\r\n\r\n")
+            .respond("HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello")
+            .expect("GET /b HTTP/1.1\r\nhost: mock.local\r\n\r\n")
+            .respond("HTTP/1.1 204 No Content\r\n\r\n"));
+
+        let client: Client<_, Body> = Client::builder()
+            .executor(runtime.executor())
+            .build(connector.clone());
+
+        let res = client.get("http://mock.local/a".parse().unwrap()).wait().unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().concat2().wait().unwrap();
+        assert_eq!(&body[..], b"hello");
+
+        // the same connection is used again
+        let res = client.get("http://mock.local/b".parse().unwrap()).wait().unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(connector.remaining(), 0);
+
+        let err = client.get("http://other.local/".parse().unwrap()).wait().unwrap_err();
+        assert!(err.is_connect(), "{:?}", err);
+    }
+
+    #[test]
+    fn mock_test_server() {
+        use hyper::Response;
+        use hyper::mock::TestServer;
+        use hyper::service::service_fn_ok;
+
+        let _ = pretty_env_logger::try_init();
+        let mut runtime = Runtime::new().unwrap();
+        let server = TestServer::spawn(|| {
+            service_fn_ok(|req| Response::new(Body::from(req.uri().path().to_owned())))
+        }, &runtime.executor());
+        assert_eq!(server.addr().ip(), ::std::net::Ipv4Addr::new(127, 0, 0, 1));
+
+        let client: Client<_, Body> = Client::builder()
+            .executor(runtime.executor())
+            .build_http();
+        let body = runtime.block_on(client.get(server.uri("/hello"))
+            .and_then(|res| res.into_body().concat2()))
+            .unwrap();
+        assert_eq!(&body[..], b"/hello");
+
+        // the idle pooled connection is closed by the shutdown
+        let addr = server.addr();
+        runtime.block_on(server.shutdown()).unwrap();
+        assert!(::std::net::TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    fn client_keep_alive_http10() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let connector = DebugConnector::new(runtime.reactor());
+        let connects = connector.connects.clone();
+
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        let (tx2, rx2) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n1 = sock.read(&mut buf).expect("read 1");
+            let req1 = s(&buf[..n1]);
+            assert!(req1.starts_with("GET /a HTTP/1.0\r\n"), "{:?}", req1);
+            assert!(req1.contains("\r\nconnection: keep-alive\r\n"), "{:?}", req1);
+            sock.write_all(b"HTTP/1.0 200 OK\r\nConnection: keep-alive\r\nContent-Length: 0\r\n\r\n").expect("write 1");
+            let _ = tx1.send(());
+
+            let n2 = sock.read(&mut buf).expect("read 2");
+            assert_ne!(n2, 0);
+            let second_get = "GET /b HTTP/1.0\r\n";
+            assert_eq!(s(&buf[..second_get.len()]), second_get);
+            sock.write_all(b"HTTP/1.0 200 OK\r\nConnection: keep-alive\r\nContent-Length: 0\r\n\r\n").expect("write 2");
+            let _ = tx2.send(());
+        });
+
+        let rx = rx1.expect("thread panicked");
+        let req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .version(hyper::Version::HTTP_10)
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req);
+        res.join(rx).map(|r| r.0).wait().unwrap();
+
+        // sleep real quick to let the threadpool put connection in ready
+        // state and back into client pool
+        thread::sleep(Duration::from_millis(50));
+
+        let rx = rx2.expect("thread panicked");
+        let req = Request::builder()
+            .uri(&*format!("http://{}/b", addr))
+            .version(hyper::Version::HTTP_10)
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req);
+        res.join(rx).map(|r| r.0).wait().unwrap();
+
+        assert_eq!(connects.load(Ordering::SeqCst), 1, "second request should still only have 1 connect");
+        drop(client);
+
+        runtime.shutdown_on_idle().wait().expect("rt shutdown");
+    }
+
+    #[test]
+    fn client_keep_alive_extra_body() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let handle = runtime.reactor();
+
+        let connector = DebugConnector::new(&handle);
+        let connects = connector.connects.clone();
+
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        let (tx2, rx2) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            sock.read(&mut buf).expect("read 1");
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello").expect("write 1");
+            // the body "hello", while ignored because its a HEAD request, should mean the connection
+            // cannot be put back in the pool
+            let _ = tx1.send(());
+
+            let mut sock2 = server.accept().unwrap().0;
+            let n2 = sock2.read(&mut buf).expect("read 2");
+            assert_ne!(n2, 0);
+            let second_get = "GET /b HTTP/1.1\r\n";
+            assert_eq!(s(&buf[..second_get.len()]), second_get);
+            sock2.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 2");
+            let _ = tx2.send(());
+        });
+
+
+        assert_eq!(connects.load(Ordering::Relaxed), 0);
+
+        let rx = rx1.expect("thread panicked");
+        let req = Request::builder()
+            .method("HEAD")
+            .uri(&*format!("http://{}/a", addr))
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req);
+        res.join(rx).map(|r| r.0).wait().unwrap();
+
+        assert_eq!(connects.load(Ordering::Relaxed), 1);
+
+        let rx = rx2.expect("thread panicked");
+        let req = Request::builder()
+            .uri(&*format!("http://{}/b", addr))
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req);
+        res.join(rx).map(|r| r.0).wait().unwrap();
+
+        assert_eq!(connects.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn connect_proxy_sends_absolute_uri() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let handle = runtime.reactor();
+        let connector = DebugConnector::new(&handle)
+            .proxy()
+            .alpn("http/1.1");
+
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            //drop(server);
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read 1");
+            let expected = format!("GET http://{addr}/foo/bar HTTP/1.1\r\nhost: {addr}\r\n\r\n", addr=addr);
+            assert_eq!(s(&buf[..n]), expected);
+
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 1");
+            let _ = tx1.send(());
+        });
+
+
+        let rx = rx1.expect("thread panicked");
+        let req = Request::builder()
+            .uri(&*format!("http://{}/foo/bar", addr))
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req);
+        let res = res.join(rx).map(|r| r.0).wait().unwrap();
+        let info = res.extensions().get::<hyper::ext::ConnectionInfo>().expect("connection info");
+        assert!(info.is_proxied());
+        assert_eq!(info.alpn_protocol(), Some(&b"http/1.1"[..]));
+    }
+
+    #[test]
+    fn client_http1_absolute_form() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let handle = runtime.reactor();
+        let connector = DebugConnector::new(&handle);
+
+        let client = Client::builder()
+            .http1_absolute_form(true)
+            .executor(runtime.executor())
+            .build(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read 1");
+            let expected = format!("GET http://{addr}/foo/bar HTTP/1.1\r\nhost: {addr}\r\n\r\n", addr=addr);
+            assert_eq!(s(&buf[..n]), expected);
+
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 1");
+            let _ = tx1.send(());
+        });
+
+        let rx = rx1.expect("thread panicked");
+        let req = Request::builder()
+            .uri(&*format!("http://{}/foo/bar", addr))
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req);
+        res.join(rx).map(|r| r.0).wait().unwrap();
+    }
+
+    #[test]
+    fn client_http1_absolute_form_per_request() {
+        use hyper::ext::RequestTarget;
+
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let handle = runtime.reactor();
+        let connector = DebugConnector::new(&handle);
+
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read 1");
+            let expected = format!("GET http://{addr}/foo/bar HTTP/1.1\r\nhost: {addr}\r\n\r\n", addr=addr);
+            assert_eq!(s(&buf[..n]), expected);
+
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 1");
+            let _ = tx1.send(());
+        });
+
+        let rx = rx1.expect("thread panicked");
+        let mut req = Request::builder()
+            .uri(&*format!("http://{}/foo/bar", addr))
+            .body(Body::empty())
+            .unwrap();
+        req.extensions_mut().insert(RequestTarget::Absolute);
+        let res = client.request(req);
+        res.join(rx).map(|r| r.0).wait().unwrap();
+    }
+
+
+    #[test]
+    fn client_http1_record_header_order() {
+        use hyper::ext::HeaderOrder;
+
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let handle = runtime.reactor();
+        let connector = DebugConnector::new(&handle);
+
+        let client = Client::builder()
+            .http1_record_header_order(true)
+            .executor(runtime.executor())
+            .build(connector);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            sock.read(&mut buf).expect("read 1");
+            sock.write_all(b"HTTP/1.1 200 OK\r\nX-A: 1\r\nX-B: 2\r\nX-A: 3\r\nContent-Length: 0\r\n\r\n")
+                .expect("write 1");
+            let _ = tx1.send(());
+        });
+
+        let rx = rx1.expect("thread panicked");
+        let req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req);
+        let res = res.join(rx).map(|r| r.0).wait().unwrap();
+
+        let order = res.extensions().get::<HeaderOrder>().expect("header order");
+        let names = order.names().iter().map(|name| name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["x-a", "x-b", "x-a", "content-length"]);
+    }
+
+    #[test]
+    fn client_http1_max_uri_length() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let handle = runtime.reactor();
+        let connector = DebugConnector::new(&handle);
+
+        let client = Client::builder()
+            .http1_max_uri_length(8)
+            .executor(runtime.executor())
+            .build(connector);
+
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            // Nothing is written before the connection is closed.
+            assert_eq!(sock.read(&mut buf).expect("read 1"), 0);
+        });
+
+        let req = Request::builder()
+            .uri(&*format!("http://{}/too/long", addr))
+            .body(Body::empty())
+            .unwrap();
+        let err = client.request(req).wait().unwrap_err();
+        assert!(err.is_user(), "{:?}", err);
+    }
+
+    #[test]
+    fn client_http1_strict_content_length() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let handle = runtime.reactor();
+        let connector = DebugConnector::new(&handle);
+
+        let client = Client::builder()
+            .http1_strict_content_length(true)
+            .executor(runtime.executor())
+            .build(connector);
+
+        thread::spawn(move || {
+            let replies: [&[u8]; 2] = [
+                b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort",
+                b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nlong body",
+            ];
+            for reply in replies.iter() {
+                let mut sock = server.accept().unwrap().0;
+                sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+                let mut buf = [0; 4096];
+                sock.read(&mut buf).expect("read 1");
+                sock.write_all(reply).expect("write 1");
+            }
+        });
+
+        for _ in 0..2 {
+            let req = Request::builder()
+                .uri(&*format!("http://{}/a", addr))
+                .body(Body::empty())
+                .unwrap();
+            let err = client.request(req)
+                .and_then(|res| res.into_body().concat2())
+                .wait()
+                .unwrap_err();
+            assert!(err.is_content_length_mismatch(), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn connect_with_tcp_fastopen() {
+        let _ = pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let mut http = HttpConnector::new_with_handle(1, runtime.reactor().clone());
+        http.set_tcp_fastopen(true);
+
+        let client = Client::builder()
+            .executor(runtime.executor())
+            .build::<_, Body>(http);
+
+        let (tx1, rx1) = oneshot::channel();
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            sock.read(&mut buf).expect("read 1");
+            let expected = "GET /a HTTP/1.1\r\n";
+            assert_eq!(s(&buf[..expected.len()]), expected);
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 1");
+            let _ = tx1.send(());
+        });
+
         let rx = rx1.expect("thread panicked");
         let req = Request::builder()
             .uri(&*format!("http://{}/a", addr))
             .body(Body::empty())
             .unwrap();
         let res = client.request(req);
-        res.join(rx).map(|r| r.0).wait().unwrap();
-
-        assert_eq!(connects.load(Ordering::SeqCst), 1);
-
-        // sleep real quick to let the threadpool put connection in ready
-        // state and back into client pool
-        thread::sleep(Duration::from_millis(50));
-
-        let rx = rx2.expect("thread panicked");
-        let req = Request::builder()
-            .uri(&*format!("http://{}/b", addr))
-            .body(Body::empty())
-            .unwrap();
-        let res = client.request(req);
-        res.join(rx).map(|r| r.0).wait().unwrap();
-
-        assert_eq!(connects.load(Ordering::SeqCst), 1, "second request should still only have 1 connect");
-        drop(client);
-
-        runtime.shutdown_on_idle().wait().expect("rt shutdown");
+        let res = res.join(rx).map(|r| r.0).wait().unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
     }
 
     #[test]
-    fn client_keep_alive_extra_body() {
+    fn connect_with_proxy_header() {
+        use hyper::client::connect::{ProxyHeaderConnector, ProxyInfo};
+
         let _ = pretty_env_logger::try_init();
         let server = TcpListener::bind("127.0.0.1:0").unwrap();
         let addr = server.local_addr().unwrap();
         let runtime = Runtime::new().unwrap();
-        let handle = runtime.reactor();
-
-        let connector = DebugConnector::new(&handle);
-        let connects = connector.connects.clone();
+        let http = HttpConnector::new_with_handle(1, runtime.reactor().clone());
+        let info = ProxyInfo::new(
+            "192.168.0.1:56324".parse().unwrap(),
+            "10.0.0.1:443".parse().unwrap(),
+        );
+        let connector = ProxyHeaderConnector::new(http, info);
 
         let client = Client::builder()
             .executor(runtime.executor())
-            .build(connector);
+            .build::<_, Body>(connector);
 
         let (tx1, rx1) = oneshot::channel();
-        let (tx2, rx2) = oneshot::channel();
         thread::spawn(move || {
             let mut sock = server.accept().unwrap().0;
             sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
             sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut header = [0; 28];
+            sock.read_exact(&mut header).expect("read header");
+            assert_eq!(&header[..12], b"\r\n\r\n\0\r\nQUIT\n");
+            assert_eq!(&header[12..16], &[0x21, 0x11, 0, 12]);
+            assert_eq!(&header[16..24], &[192, 168, 0, 1, 10, 0, 0, 1]);
+            assert_eq!(&header[24..], &[0xDC, 0x04, 0x01, 0xBB]);
+
             let mut buf = [0; 4096];
             sock.read(&mut buf).expect("read 1");
-            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello").expect("write 1");
-            // the body "hello", while ignored because its a HEAD request, should mean the connection
-            // cannot be put back in the pool
+            let expected = "GET /a HTTP/1.1\r\n";
+            assert_eq!(s(&buf[..expected.len()]), expected);
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 1");
             let _ = tx1.send(());
-
-            let mut sock2 = server.accept().unwrap().0;
-            let n2 = sock2.read(&mut buf).expect("read 2");
-            assert_ne!(n2, 0);
-            let second_get = "GET /b HTTP/1.1\r\n";
-            assert_eq!(s(&buf[..second_get.len()]), second_get);
-            sock2.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 2");
-            let _ = tx2.send(());
         });
 
-
-        assert_eq!(connects.load(Ordering::Relaxed), 0);
-
         let rx = rx1.expect("thread panicked");
         let req = Request::builder()
-            .method("HEAD")
             .uri(&*format!("http://{}/a", addr))
             .body(Body::empty())
             .unwrap();
         let res = client.request(req);
-        res.join(rx).map(|r| r.0).wait().unwrap();
-
-        assert_eq!(connects.load(Ordering::Relaxed), 1);
-
-        let rx = rx2.expect("thread panicked");
-        let req = Request::builder()
-            .uri(&*format!("http://{}/b", addr))
-            .body(Body::empty())
-            .unwrap();
-        let res = client.request(req);
-        res.join(rx).map(|r| r.0).wait().unwrap();
-
-        assert_eq!(connects.load(Ordering::Relaxed), 2);
+        let res = res.join(rx).map(|r| r.0).wait().unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
     }
 
     #[test]
-    fn connect_proxy_sends_absolute_uri() {
+    fn connect_with_dial_connector() {
+        use std::net::ToSocketAddrs;
+        use hyper::client::connect::{Dial, DialConnector};
+
+        // dials as the `TcpStream` of another runtime would
+        struct TokioDial(Arc<Mutex<Vec<(String, u16)>>>);
+
+        impl Dial for TokioDial {
+            type Conn = TcpStream;
+            type Future = Box<Future<Item=TcpStream, Error=io::Error> + Send>;
+
+            fn dial(&self, host: &str, port: u16) -> Self::Future {
+                self.0.lock().unwrap().push((host.to_owned(), port));
+                let addr = (host, port).to_socket_addrs().unwrap()
+                    .find(|addr| addr.is_ipv4())
+                    .unwrap();
+                Box::new(TcpStream::connect(&addr))
+            }
+        }
+
         let _ = pretty_env_logger::try_init();
         let server = TcpListener::bind("127.0.0.1:0").unwrap();
         let addr = server.local_addr().unwrap();
         let runtime = Runtime::new().unwrap();
-        let handle = runtime.reactor();
-        let connector = DebugConnector::new(&handle)
-            .proxy();
+        let dialed = Arc::new(Mutex::new(Vec::new()));
+        let connector = DialConnector::new(TokioDial(dialed.clone()));
 
         let client = Client::builder()
             .executor(runtime.executor())
-            .build(connector);
+            .build::<_, Body>(connector);
 
         let (tx1, rx1) = oneshot::channel();
         thread::spawn(move || {
             let mut sock = server.accept().unwrap().0;
-            //drop(server);
             sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
             sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
             let mut buf = [0; 4096];
-            let n = sock.read(&mut buf).expect("read 1");
-            let expected = format!("GET http://{addr}/foo/bar HTTP/1.1\r\nhost: {addr}\r\n\r\n", addr=addr);
-            assert_eq!(s(&buf[..n]), expected);
-
+            sock.read(&mut buf).expect("read 1");
+            let expected = "GET /a HTTP/1.1\r\n";
+            assert_eq!(s(&buf[..expected.len()]), expected);
             sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").expect("write 1");
             let _ = tx1.send(());
         });
 
-
         let rx = rx1.expect("thread panicked");
         let req = Request::builder()
-            .uri(&*format!("http://{}/foo/bar", addr))
+            .uri(&*format!("http://localhost:{}/a", addr.port()))
             .body(Body::empty())
             .unwrap();
         let res = client.request(req);
-        res.join(rx).map(|r| r.0).wait().unwrap();
+        let res = res.join(rx).map(|r| r.0).wait().unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(*dialed.lock().unwrap(), [("localhost".to_owned(), addr.port())]);
     }
 
 
@@ -1283,6 +2039,7 @@ mod dispatch_impl {
         closes: mpsc::Sender<()>,
         connects: Arc<AtomicUsize>,
         is_proxy: bool,
+        alpn: Option<&'static str>,
     }
 
     impl DebugConnector {
@@ -1298,6 +2055,7 @@ mod dispatch_impl {
                 closes: closes,
                 connects: Arc::new(AtomicUsize::new(0)),
                 is_proxy: false,
+                alpn: None,
             }
         }
 
@@ -1305,6 +2063,11 @@ mod dispatch_impl {
             self.is_proxy = true;
             self
         }
+
+        fn alpn(mut self, protocol: &'static str) -> Self {
+            self.alpn = Some(protocol);
+            self
+        }
     }
 
     impl Connect for DebugConnector {
@@ -1316,7 +2079,12 @@ mod dispatch_impl {
             self.connects.fetch_add(1, Ordering::SeqCst);
             let closes = self.closes.clone();
             let is_proxy = self.is_proxy;
+            let alpn = self.alpn;
             Box::new(self.http.connect(dst).map(move |(s, c)| {
+                let c = match alpn {
+                    Some(protocol) => c.alpn_protocol(protocol),
+                    None => c,
+                };
                 (DebugStream(s, closes), c.proxy(is_proxy))
             }))
         }
@@ -1522,7 +2290,54 @@ mod conn {
             .body(body)
             .unwrap();
         let res = client.send_request(req);
-        res.wait().unwrap_err();
+        let err = res.wait().unwrap_err();
+        assert!(err.is_body_write_aborted(), "{:?}", err);
+
+        server.join().expect("server thread panicked");
+        sender.join().expect("sender thread panicked");
+    }
+
+    #[test]
+    fn channel_body_with_trailers() {
+        let _ = ::pretty_env_logger::try_init();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let server = thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let expected = "POST / HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n\
+                            5\r\nhello\r\n0\r\ngrpc-status: 0\r\n\r\n";
+            let mut buf = vec![0; expected.len()];
+            sock.read_exact(&mut buf).expect("read 1");
+            assert_eq!(s(&buf), expected);
+
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+        });
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let (mut client, conn) = conn::handshake(tcp).wait().unwrap();
+
+        runtime.spawn(conn.map(|_| ()).map_err(|e| panic!("conn error: {}", e)));
+
+        let (mut sender, body) = Body::channel();
+        let sender = thread::spawn(move || {
+            sender.send_data("hello".into()).ok().unwrap();
+            let mut trailers = hyper::HeaderMap::new();
+            trailers.insert("grpc-status", hyper::header::HeaderValue::from_static("0"));
+            sender.send_trailers(trailers).unwrap();
+        });
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/")
+            .body(body)
+            .unwrap();
+        let res = client.send_request(req).wait().unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
 
         server.join().expect("server thread panicked");
         sender.join().expect("sender thread panicked");
@@ -1574,6 +2389,194 @@ mod conn {
         res.join(rx).map(|r| r.0).wait().unwrap();
     }
 
+    #[test]
+    fn uri_asterisk_form() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let (tx1, rx1) = oneshot::channel();
+
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read 1");
+
+            let expected = "OPTIONS * HTTP/1.1\r\n\r\n";
+            assert_eq!(s(&buf[..n]), expected);
+
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+            let _ = tx1.send(());
+        });
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let (mut client, conn) = conn::handshake(tcp).wait().unwrap();
+
+        runtime.spawn(conn.map(|_| ()).map_err(|e| panic!("conn error: {}", e)));
+
+        let req = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("*")
+            .body(Default::default())
+            .unwrap();
+
+        let res = client.send_request(req).and_then(move |res| {
+            assert_eq!(res.status(), hyper::StatusCode::OK);
+            res.into_body().concat2()
+        });
+        let rx = rx1.expect("thread panicked");
+
+        let timeout = Delay::new(Duration::from_millis(200));
+        let rx = rx.and_then(move |_| timeout.expect("timeout"));
+        res.join(rx).map(|r| r.0).wait().unwrap();
+    }
+
+    #[test]
+    fn cancel_closes_connection() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let (tx1, rx1) = oneshot::channel();
+
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            sock.read(&mut buf).expect("read 1");
+            let _ = tx1.send(());
+
+            // no response is sent, the client closes the connection
+            let n = sock.read(&mut buf).expect("read 2");
+            assert_eq!(n, 0);
+        });
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let (mut client, conn) = conn::handshake(tcp).wait().unwrap();
+
+        runtime.spawn(conn.map(|_| ()).map_err(|e| panic!("conn error: {}", e)));
+
+        let req = Request::builder()
+            .uri("/a")
+            .body(Default::default())
+            .unwrap();
+        let res = client.send_request(req);
+        let cancel = res.cancel_handle();
+
+        let rx = rx1.expect("thread panicked").map(move |_| cancel.cancel());
+        let err = res.join(rx).wait().expect_err("canceled");
+        assert!(err.is_request_canceled(), "{:?}", err);
+
+        // once the connection task closes the connection
+        poll_fn(|| client.poll_ready())
+            .wait()
+            .expect_err("connection closed");
+    }
+
+    #[test]
+    fn dropped_request_still_sent() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read 1");
+            assert_eq!(s(&buf[..n]), "GET /a HTTP/1.1\r\n\r\n");
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello").unwrap();
+
+            // the same connection is used again
+            let n = sock.read(&mut buf).expect("read 2");
+            assert_eq!(s(&buf[..n]), "GET /b HTTP/1.1\r\n\r\n");
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+        });
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let (mut client, conn) = conn::Builder::new()
+            .cancel_dropped_requests(false)
+            .handshake::<_, Body>(tcp)
+            .wait()
+            .unwrap();
+
+        runtime.spawn(conn.map(|_| ()).map_err(|e| panic!("conn error: {}", e)));
+
+        let req = Request::builder()
+            .uri("/a")
+            .body(Default::default())
+            .unwrap();
+        drop(client.send_request(req));
+
+        // ready again once the dropped response was read
+        {
+            let ready = poll_fn(|| client.poll_ready());
+            let timeout = Delay::new(Duration::from_secs(5));
+            match ready.select2(timeout).wait() {
+                Ok(::futures::future::Either::A(_)) => (),
+                _ => panic!("connection wasn't ready again"),
+            }
+        }
+
+        let req = Request::builder()
+            .uri("/b")
+            .body(Default::default())
+            .unwrap();
+        let res = client.send_request(req).wait().unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+    }
+
+    #[test]
+    fn dropped_request_long_body_closes() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let (closed_tx, closed_rx) = ::std::sync::mpsc::channel();
+        let server_thread = thread::spawn(move || {
+            let mut sock = server.accept().unwrap().0;
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            sock.set_write_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read 1");
+            assert_eq!(s(&buf[..n]), "GET /a HTTP/1.1\r\n\r\n");
+            let len = 1024 * 1024;
+            write!(sock, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", len).unwrap();
+            // the client stops reading partway, once it closes
+            let _ = sock.write_all(&vec![b'x'; len]);
+            closed_rx.recv().unwrap();
+        });
+
+        let tcp = tcp_connect(&addr).wait().unwrap();
+
+        let (mut client, conn) = conn::Builder::new()
+            .cancel_dropped_requests(false)
+            .handshake::<_, Body>(tcp)
+            .wait()
+            .unwrap();
+
+        let req = Request::builder()
+            .uri("/a")
+            .body(Default::default())
+            .unwrap();
+        drop(client.send_request(req));
+
+        // the body is too long to drain, so the connection closes
+        let timeout = Delay::new(Duration::from_secs(5));
+        match conn.select2(timeout).wait() {
+            Ok(::futures::future::Either::A(_)) => (),
+            _ => panic!("connection wasn't closed"),
+        }
+        closed_tx.send(()).unwrap();
+        server_thread.join().unwrap();
+    }
+
     #[test]
     fn pipeline() {
         let server = TcpListener::bind("127.0.0.1:0").unwrap();
@@ -1615,8 +2618,10 @@ mod conn {
             .unwrap();
         let res2 = client.send_request(req)
             .then(|result| {
-                let err = result.expect_err("res2");
+                let mut err = result.expect_err("res2");
                 assert!(err.is_canceled(), "err not canceled, {:?}", err);
+                let req = err.take_unsent_request::<Body>().expect("unsent request");
+                assert_eq!(req.uri(), "/b");
                 Ok(())
             });
 
@@ -491,479 +491,2577 @@ fn post_with_chunked_body() {
 }
 
 #[test]
-fn post_with_incomplete_body() {
-    extern crate pretty_env_logger;
-    let _ = pretty_env_logger::try_init();
-    let server = serve();
-    let mut req = connect(server.addr());
-    req.write_all(b"\
-        POST / HTTP/1.1\r\n\
-        Host: example.domain\r\n\
-        Content-Length: 10\r\n\
-        \r\n\
-        12345\
-    ").expect("write");
-    req.shutdown(Shutdown::Write).expect("shutdown write");
+fn post_with_chunk_extensions() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
 
-    server.body_err();
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            POST / HTTP/1.1\r\n\
+            Connection: close\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            1;sig=a\r\n\
+            q\r\n\
+            2\r\n\
+            we\r\n\
+            0;sig=b\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).unwrap();
+        assert!(s(&buf).ends_with("q:a,we:,;b"), "{:?}", s(&buf));
+    });
 
-    req.read(&mut [0; 256]).expect("read");
-}
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    future::loop_fn((req.into_body(), String::new()), |(body, mut acc)| {
+                        body.into_future()
+                            .map_err(|(err, _body)| err)
+                            .map(move |(chunk, mut body)| match chunk {
+                                Some(chunk) => {
+                                    let sig = chunk.extensions()
+                                        .and_then(|ext| ext.get("sig"))
+                                        .map(|sig| s(sig).to_owned())
+                                        .unwrap_or_default();
+                                    acc.push_str(&format!("{}:{},", s(&chunk), sig));
+                                    future::Loop::Continue((body, acc))
+                                },
+                                None => {
+                                    let sig = body.last_chunk_extensions()
+                                        .and_then(|ext| ext.get("sig"))
+                                        .map(|sig| s(sig).to_owned())
+                                        .unwrap_or_default();
+                                    acc.push_str(&format!(";{}", sig));
+                                    future::Loop::Break(acc)
+                                },
+                            })
+                    })
+                    .map(|acc| Response::new(Body::from(acc)))
+                }))
+        });
 
+    fut.wait().unwrap();
+    client.join().unwrap();
+}
 
 #[test]
-fn head_response_can_send_content_length() {
-    extern crate pretty_env_logger;
-    let _ = pretty_env_logger::try_init();
-    let server = serve();
-    server.reply()
-        .header("content-length", "1024");
-    let mut req = connect(server.addr());
-    req.write_all(b"\
-        HEAD / HTTP/1.1\r\n\
-        Host: example.domain\r\n\
-        Connection: close\r\n\
-        \r\n\
-    ").unwrap();
+fn response_with_chunk_extensions() {
+    use hyper::body::ChunkExtensions;
 
-    let mut response = String::new();
-    req.read_to_string(&mut response).unwrap();
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
 
-    assert!(response.contains("content-length: 1024\r\n"));
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).unwrap();
+        let expected = "\r\n\r\n5;sig=a\r\nhello\r\n0;sig=b\r\n\r\n";
+        assert!(s(&buf).ends_with(expected), "{:?}", s(&buf));
+    });
 
-    let mut lines = response.lines();
-    assert_eq!(lines.next(), Some("HTTP/1.1 200 OK"));
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, service_fn(|_req| {
+                    let mut data = hyper::Chunk::from("hello");
+                    data.set_extensions(ChunkExtensions::from_static("sig=a"));
+                    let mut last = hyper::Chunk::default();
+                    last.set_extensions(ChunkExtensions::from_static("sig=b"));
+                    let chunks = futures::stream::iter_ok::<_, hyper::Error>(vec![data, last]);
+                    Ok::<_, hyper::Error>(Response::new(Body::wrap_stream(chunks)))
+                }))
+        });
 
-    let mut lines = lines.skip_while(|line| !line.is_empty());
-    assert_eq!(lines.next(), Some(""));
-    assert_eq!(lines.next(), None);
+    fut.wait().unwrap();
+    client.join().unwrap();
 }
 
 #[test]
-fn head_response_doesnt_send_body() {
-    extern crate pretty_env_logger;
-    let _ = pretty_env_logger::try_init();
-    let foo_bar = b"foo bar baz";
-    let server = serve();
-    server.reply()
-        .body(foo_bar);
-    let mut req = connect(server.addr());
-    req.write_all(b"\
-        HEAD / HTTP/1.1\r\n\
-        Host: example.domain\r\n\
-        Connection: close\r\n\
-        \r\n\
-    ").unwrap();
-
-    let mut response = String::new();
-    req.read_to_string(&mut response).unwrap();
-
-    assert!(response.contains("content-length: 11\r\n"));
+fn response_body_not_matching_content_length() {
+    fn check(content_length: &'static str, full: bool) {
+        let runtime = Runtime::new().unwrap();
+        let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut tcp = connect(&addr);
+            tcp.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+            let mut buf = vec![];
+            tcp.read_to_end(&mut buf).unwrap();
+            buf
+        });
 
-    let mut lines = response.lines();
-    assert_eq!(lines.next(), Some("HTTP/1.1 200 OK"));
+        let fut = listener.incoming()
+            .into_future()
+            .map_err(|_| unreachable!())
+            .and_then(move |(item, _incoming)| {
+                let socket = item.unwrap();
+                Http::new()
+                    .serve_connection(socket, service_fn(move |_req| {
+                        let body = if full {
+                            Body::from("hello")
+                        } else {
+                            let chunks = futures::stream::iter_ok::<_, hyper::Error>(vec!["hello"]);
+                            Body::wrap_stream(chunks)
+                        };
+                        Response::builder()
+                            .header("content-length", content_length)
+                            .body(body)
+                    }))
+            });
+
+        let err = fut.wait().unwrap_err();
+        assert!(err.is_user(), "{:?}", err);
+
+        // the connection is closed without writing a body that doesn't fit
+        let buf = client.join().unwrap();
+        assert!(!s(&buf).contains("hello"), "{:?}", s(&buf));
+    }
 
-    let mut lines = lines.skip_while(|line| !line.is_empty());
-    assert_eq!(lines.next(), Some(""));
-    assert_eq!(lines.next(), None);
+    check("3", false);
+    check("10", false);
+    check("3", true);
+    check("10", true);
 }
 
 #[test]
-fn response_does_not_set_chunked_if_body_not_allowed() {
-    extern crate pretty_env_logger;
-    let _ = pretty_env_logger::try_init();
-    let server = serve();
-    server.reply()
-        .status(hyper::StatusCode::NOT_MODIFIED)
-        .header("transfer-encoding", "chunked");
-    let mut req = connect(server.addr());
-    req.write_all(b"\
-        GET / HTTP/1.1\r\n\
-        Host: example.domain\r\n\
-        Connection: close\r\n\
-        \r\n\
-    ").unwrap();
+fn limited_request_body_replies_payload_too_large() {
+    use hyper::body::Limited;
 
-    let mut response = String::new();
-    req.read_to_string(&mut response).unwrap();
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
 
-    assert!(!response.contains("transfer-encoding"));
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            POST / HTTP/1.1\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            5\r\n\
+            hello\r\n\
+            6\r\n\
+            world!\r\n\
+            0\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).unwrap();
+        let response = s(&buf);
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"), "{:?}", response);
+        assert!(response.contains("connection: close\r\n"), "{:?}", response);
+    });
 
-    let mut lines = response.lines();
-    assert_eq!(lines.next(), Some("HTTP/1.1 304 Not Modified"));
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    Limited::new(req.into_body(), 8)
+                        .concat2()
+                        .map(|_| Response::new(Body::empty()))
+                }))
+        });
 
-    // no body or 0\r\n\r\n
-    let mut lines = lines.skip_while(|line| !line.is_empty());
-    assert_eq!(lines.next(), Some(""));
-    assert_eq!(lines.next(), None);
+    let err = fut.wait().unwrap_err();
+    assert!(err.is_body_too_large(), "{:?}", err);
+    client.join().unwrap();
 }
 
 #[test]
-fn keep_alive() {
-    let foo_bar = b"foo bar baz";
-    let server = serve();
-    server.reply()
-        .header("content-length", foo_bar.len().to_string())
-        .body(foo_bar);
-    let mut req = connect(server.addr());
-    req.write_all(b"\
-        GET / HTTP/1.1\r\n\
-        Host: example.domain\r\n\
-        \r\n\
-    ").expect("writing 1");
-
-    let mut buf = [0; 1024 * 8];
-    loop {
-        let n = req.read(&mut buf[..]).expect("reading 1");
-        if n < buf.len() {
-            if &buf[n - foo_bar.len()..n] == foo_bar {
-                break;
+fn body_data_timeout() {
+    use hyper::body::DataTimeout;
+
+    fn post(chunks: usize, pause: Duration, stall: bool) -> Result<hyper::Chunk, hyper::Error> {
+        let mut runtime = Runtime::new().unwrap();
+        let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let mut tcp = connect(&addr);
+            write!(tcp, "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", chunks + 1).unwrap();
+            for _ in 0..chunks {
+                tcp.write_all(b"a").unwrap();
+                thread::sleep(pause);
             }
-        }
-    }
+            if stall {
+                thread::sleep(Duration::from_millis(500));
+            }
+            let _ = tcp.write_all(b"a");
+        });
 
-    // try again!
+        let (tx, rx) = oneshot::channel();
+        let tx = Arc::new(Mutex::new(Some(tx)));
+        let fut = listener.incoming()
+            .into_future()
+            .map_err(|_| unreachable!())
+            .and_then(move |(item, _incoming)| {
+                let socket = item.unwrap();
+                Http::new()
+                    .serve_connection(socket, service_fn(move |req: Request<Body>| {
+                        let tx = tx.lock().unwrap().take().unwrap();
+                        DataTimeout::new(req.into_body(), Duration::from_millis(200))
+                            .concat2()
+                            .then(move |result| {
+                                let _ = tx.send(result);
+                                Ok::<_, hyper::Error>(Response::new(Body::empty()))
+                            })
+                    }))
+                    .map_err(|_| ())
+            });
+        runtime.spawn(fut);
+
+        runtime.block_on(rx).unwrap()
+    }
 
-    let quux = b"zar quux";
-    server.reply()
-        .header("content-length", quux.len().to_string())
-        .body(quux);
-    req.write_all(b"\
-        GET /quux HTTP/1.1\r\n\
-        Host: example.domain\r\n\
-        Connection: close\r\n\
-        \r\n\
-    ").expect("writing 2");
+    // the whole body takes longer than the timeout, but data keeps arriving
+    let body = post(4, Duration::from_millis(100), false).unwrap();
+    assert_eq!(&body[..], b"aaaaa");
 
-    let mut buf = [0; 1024 * 8];
-    loop {
-        let n = req.read(&mut buf[..]).expect("reading 2");
-        assert!(n > 0, "n = {}", n);
-        if n < buf.len() {
-            if &buf[n - quux.len()..n] == quux {
-                break;
-            }
-        }
-    }
+    let err = post(4, Duration::from_millis(100), true).unwrap_err();
+    assert!(err.is_body_timeout(), "{:?}", err);
 }
 
 #[test]
-fn http_10_keep_alive() {
-    let foo_bar = b"foo bar baz";
-    let server = serve();
-    server.reply()
-        .header("content-length", foo_bar.len().to_string())
-        .body(foo_bar);
-    let mut req = connect(server.addr());
-    req.write_all(b"\
-        GET / HTTP/1.0\r\n\
-        Host: example.domain\r\n\
-        Connection: keep-alive\r\n\
-        \r\n\
-    ").expect("writing 1");
+fn response_body_sender_notices_reset() {
+    use net2::TcpStreamExt;
 
-    let mut buf = [0; 1024 * 8];
-    loop {
-        let n = req.read(&mut buf[..]).expect("reading 1");
-        if n < buf.len() {
-            if &buf[n - foo_bar.len()..n] == foo_bar {
-                break;
-            }
-        }
-    }
+    let mut runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
 
-    // try again!
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut buf = [0; 256];
+        let n = tcp.read(&mut buf).unwrap();
+        assert!(s(&buf[..n]).starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", s(&buf[..n]));
+        // reset the connection, instead of a graceful close
+        tcp.set_linger(Some(Duration::from_secs(0))).unwrap();
+    });
 
-    let quux = b"zar quux";
-    server.reply()
-        .header("content-length", quux.len().to_string())
-        .body(quux);
-    req.write_all(b"\
-        GET /quux HTTP/1.0\r\n\
-        Host: example.domain\r\n\
-        \r\n\
-    ").expect("writing 2");
+    let (closed_tx, closed_rx) = oneshot::channel::<()>();
+    let closed_tx = Arc::new(Mutex::new(Some(closed_tx)));
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, service_fn(move |_req| {
+                    let (mut tx, body) = Body::channel();
+                    tx.send_data("hello".into()).unwrap();
+                    let closed_tx = closed_tx.lock().unwrap().take().unwrap();
+                    let closed = future::poll_fn(move || Ok::<_, ()>(tx.poll_closed()))
+                        .map(move |()| {
+                            let _ = closed_tx.send(());
+                        });
+                    tokio::spawn(closed);
+                    Ok::<_, hyper::Error>(Response::new(body))
+                }))
+                .map_err(|_| ())
+        });
+    runtime.spawn(fut);
 
-    let mut buf = [0; 1024 * 8];
-    loop {
-        let n = req.read(&mut buf[..]).expect("reading 2");
-        assert!(n > 0, "n = {}", n);
-        if n < buf.len() {
-            if &buf[n - quux.len()..n] == quux {
-                break;
-            }
-        }
+    let timeout = Delay::new(Duration::from_secs(5));
+    match runtime.block_on(closed_rx.select2(timeout)) {
+        Ok(Either::A(_)) => (),
+        _ => panic!("sender not notified of reset"),
     }
+    client.join().unwrap();
 }
 
 #[test]
-fn disable_keep_alive() {
-    let foo_bar = b"foo bar baz";
-    let server = serve_with_options(ServeOptions {
-        keep_alive_disabled: true,
-        .. Default::default()
+fn response_body_sent_after_client_half_close() {
+    let mut runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        // a clean EOF isn't a reset, the response is still wanted
+        tcp.shutdown(Shutdown::Write).unwrap();
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).unwrap();
+        assert!(s(&buf).ends_with("5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n"), "{:?}", s(&buf));
     });
-    server.reply()
-        .header("content-length", foo_bar.len().to_string())
-        .body(foo_bar);
-    let mut req = connect(server.addr());
-    req.write_all(b"\
-        GET / HTTP/1.1\r\n\
-        Host: example.domain\r\n\
-        Connection: keep-alive\r\n\
-        \r\n\
-    ").expect("writing 1");
 
-    let mut buf = [0; 1024 * 8];
-    loop {
-        let n = req.read(&mut buf[..]).expect("reading 1");
-        if n < buf.len() {
-            if &buf[n - foo_bar.len()..n] == foo_bar {
-                break;
-            } else {
-            }
-        }
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, service_fn(move |_req| {
+                    let (mut tx, body) = Body::channel();
+                    tx.send_data("hello".into()).unwrap();
+                    let later = Delay::new(Duration::from_millis(100))
+                        .map(move |()| {
+                            let _ = tx.send_data(" world".into());
+                        })
+                        .map_err(|_| ());
+                    tokio::spawn(later);
+                    Ok::<_, hyper::Error>(Response::new(body))
+                }))
+                .map_err(|_| ())
+        });
+    runtime.block_on(fut).unwrap();
+    client.join().unwrap();
+}
+
+// Fails to read the files, so their bodies can only be sent with sendfile.
+#[cfg(all(feature = "sendfile", target_os = "linux"))]
+struct NoRead;
+
+#[cfg(all(feature = "sendfile", target_os = "linux"))]
+impl futures::future::Executor<hyper::body::BlockingRead> for NoRead {
+    fn execute(&self, read: hyper::body::BlockingRead) -> Result<(), futures::future::ExecuteError<hyper::body::BlockingRead>> {
+        Err(futures::future::ExecuteError::new(futures::future::ExecuteErrorKind::Shutdown, read))
     }
+}
 
-    // try again!
+#[cfg(all(feature = "sendfile", target_os = "linux"))]
+#[test]
+fn response_file_body_with_sendfile() {
+    use std::fs::{self, File};
 
-    let quux = b"zar quux";
-    server.reply()
-        .header("content-length", quux.len().to_string())
-        .body(quux);
+    let _ = pretty_env_logger::try_init();
+    let data = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+    let path = ::std::env::temp_dir().join("hyper-response-file-body-with-sendfile");
+    File::create(&path).unwrap().write_all(&data).unwrap();
 
-    // the write can possibly succeed, since it fills the kernel buffer on the first write
-    let _ = req.write_all(b"\
-        GET /quux HTTP/1.1\r\n\
-        Host: example.domain\r\n\
-        Connection: close\r\n\
-        \r\n\
-    ");
+    let mut runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
 
-    let mut buf = [0; 1024 * 8];
-    match req.read(&mut buf[..]) {
-        // Ok(0) means EOF, so a proper shutdown
-        // Err(_) could mean ConnReset or something, also fine
-        Ok(0) |
-        Err(_) => {}
-        Ok(n) => {
-            panic!("read {} bytes on a disabled keep-alive socket", n);
-        }
-    }
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        // the socket fills up before the body is read
+        thread::sleep(Duration::from_millis(100));
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).unwrap();
+        let body_start = buf.windows(4).position(|w| w == b"\r\n\r\n").expect("end of head") + 4;
+        let head = s(&buf[..body_start]);
+        assert!(head.contains("content-length: 3145728\r\n"), "{:?}", head);
+        assert!(&buf[body_start..] == &data[..], "body doesn't match the file");
+    });
+
+    let file_path = path.clone();
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, service_fn(move |_req| {
+                    let file = File::open(&file_path).unwrap();
+                    Body::from_file(file, &NoRead).map(Response::new)
+                }))
+                .map_err(|e| panic!("server error: {}", e))
+        });
+    runtime.block_on(fut).unwrap();
+    client.join().unwrap();
+    fs::remove_file(&path).unwrap();
 }
 
+#[cfg(all(feature = "sendfile", target_os = "linux"))]
 #[test]
-fn header_connection_close() {
-    let foo_bar = b"foo bar baz";
-    let server = serve();
-    server.reply()
-        .header("content-length", foo_bar.len().to_string())
-        .header("connection", "close")
-        .body(foo_bar);
-    let mut req = connect(server.addr());
-    req.write_all(b"\
-        GET / HTTP/1.1\r\n\
-        Host: example.domain\r\n\
-        Connection: keep-alive\r\n\
-        \r\n\
-    ").expect("writing 1");
+fn response_file_body_shorter_than_its_length() {
+    use std::fs::{self, File, OpenOptions};
 
-    let mut buf = [0; 1024 * 8];
-    loop {
-        let n = req.read(&mut buf[..]).expect("reading 1");
-        if n < buf.len() {
-            if &buf[n - foo_bar.len()..n] == foo_bar {
-                break;
-            } else {
-            }
-        }
-    }
+    let _ = pretty_env_logger::try_init();
+    let path = ::std::env::temp_dir().join("hyper-response-file-body-shorter-than-its-length");
+    File::create(&path).unwrap().write_all(&[b'x'; 100]).unwrap();
 
-    // try again!
-    // but since the server responded with connection: close, the internal
-    // state should have noticed and shutdown
+    let mut runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
 
-    let quux = b"zar quux";
-    server.reply()
-        .header("content-length", quux.len().to_string())
-        .body(quux);
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut buf = vec![];
+        // the connection is closed once the file ends
+        tcp.read_to_end(&mut buf).unwrap();
+        let body_start = buf.windows(4).position(|w| w == b"\r\n\r\n").expect("end of head") + 4;
+        assert!(s(&buf[..body_start]).contains("content-length: 100\r\n"), "{:?}", s(&buf));
+        assert_eq!(s(&buf[body_start..]), "x".repeat(50));
+    });
 
-    // the write can possibly succeed, since it fills the kernel buffer on the first write
-    let _ = req.write_all(b"\
-        GET /quux HTTP/1.1\r\n\
-        Host: example.domain\r\n\
-        Connection: close\r\n\
-        \r\n\
-    ");
+    let file_path = path.clone();
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, service_fn(move |_req| {
+                    let file = File::open(&file_path).unwrap();
+                    let body = Body::from_file(file, &NoRead);
+                    OpenOptions::new().write(true).open(&file_path).unwrap().set_len(50).unwrap();
+                    body.map(Response::new)
+                }))
+        });
+    let err = runtime.block_on(fut).unwrap_err();
+    assert!(err.to_string().contains("shorter"), "{}", err);
+    client.join().unwrap();
+    fs::remove_file(&path).unwrap();
+}
 
-    let mut buf = [0; 1024 * 8];
-    match req.read(&mut buf[..]) {
-        // Ok(0) means EOF, so a proper shutdown
-        // Err(_) could mean ConnReset or something, also fine
-        Ok(0) |
-        Err(_) => {}
-        Ok(n) => {
-            panic!("read {} bytes on a disabled keep-alive socket", n);
+#[test]
+fn response_payload_exact_size_hint() {
+    use hyper::body::{Payload, SizeHint};
+
+    struct Hinted(Option<hyper::Chunk>);
+
+    impl Payload for Hinted {
+        type Data = hyper::Chunk;
+        type Error = hyper::Error;
+
+        fn poll_data(&mut self) -> futures::Poll<Option<hyper::Chunk>, hyper::Error> {
+            Ok(futures::Async::Ready(self.0.take()))
+        }
+
+        fn size_hint(&self) -> SizeHint {
+            SizeHint::with_exact(5)
         }
     }
+
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).unwrap();
+        let response = s(&buf);
+        assert!(response.contains("content-length: 5\r\n"), "{:?}", response);
+        assert!(response.ends_with("\r\n\r\nhello"), "{:?}", response);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, service_fn(|_req| {
+                    Ok::<_, hyper::Error>(Response::new(Hinted(Some("hello".into()))))
+                }))
+        });
+
+    fut.wait().unwrap();
+    client.join().unwrap();
 }
 
 #[test]
-fn expect_continue() {
-    let server = serve();
-    let mut req = connect(server.addr());
-    server.reply();
+fn response_chunk_flush_hint() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (received_tx, received_rx) = mpsc::channel();
 
-    req.write_all(b"\
-        POST /foo HTTP/1.1\r\n\
-        Host: example.domain\r\n\
-        Expect: 100-continue\r\n\
-        Content-Length: 5\r\n\
-        Connection: Close\r\n\
-        \r\n\
-    ").expect("write 1");
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let mut buf = vec![];
+        let mut tmp = [0; 256];
+        while !s(&buf).contains("\r\nevent: 1\n\r\n") {
+            let n = tcp.read(&mut tmp).expect("read first event");
+            assert_ne!(n, 0, "eof before first event");
+            buf.extend_from_slice(&tmp[..n]);
+        }
+        received_tx.send(()).unwrap();
+        tcp.read_to_end(&mut buf).unwrap();
+        assert!(s(&buf).contains("\r\nevent: 2\n\r\n"), "{:?}", s(&buf));
+    });
 
-    let msg = b"HTTP/1.1 100 Continue\r\n\r\n";
-    let mut buf = vec![0; msg.len()];
-    req.read_exact(&mut buf).expect("read 1");
-    assert_eq!(buf, msg);
+    let received_rx = Mutex::new(Some(received_rx));
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, service_fn(move |_req| {
+                    let received_rx = received_rx.lock().unwrap().take().unwrap();
+                    let mut first = hyper::Chunk::from("event: 1\n");
+                    first.set_flush_hint(true);
+                    // The next event is ready right away, but only once the
+                    // client got the first one, which requires it to be
+                    // written without waiting for more.
+                    let second = future::lazy(move || {
+                        received_rx.recv_timeout(Duration::from_secs(1)).expect("first event not flushed");
+                        Ok::<_, hyper::Error>(hyper::Chunk::from("event: 2\n"))
+                    });
+                    let chunks = futures::stream::once(Ok(first)).chain(second.into_stream());
+                    Ok::<_, hyper::Error>(Response::new(Body::wrap_stream(chunks)))
+                }))
+        });
 
-    let msg = b"hello";
-    req.write_all(msg).expect("write 2");
+    fut.wait().unwrap();
+    client.join().unwrap();
+}
 
-    let mut body = String::new();
-    req.read_to_string(&mut body).expect("read 2");
+#[test]
+fn http2_response_chunk_sizes() {
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
 
-    let body = server.body();
-    assert_eq!(body, msg);
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http2_only(true)
+                .http2_max_send_chunk_size(4)
+                .http2_min_send_chunk_size(3)
+                .serve_connection(socket, service_fn(|_req| {
+                    let chunks = futures::stream::iter_ok::<_, hyper::Error>(vec!["a", "b", "c", "defghijkl"]);
+                    Ok::<_, hyper::Error>(Response::new(Body::wrap_stream(chunks)))
+                }))
+                .map_err(|e| panic!("server error: {}", e))
+        });
+    runtime.spawn(fut);
+
+    let client: Client<_, hyper::Body> = Client::builder().http2_only(true).build_http();
+    let uri = format!("http://{}", addr).parse::<hyper::Uri>().unwrap();
+    let chunks = runtime.block_on(client.get(uri).and_then(|res| {
+        // the body ends with an empty DATA frame
+        res.into_body()
+            .filter(|chunk| !chunk.is_empty())
+            .map(|chunk| s(&chunk).to_owned())
+            .collect()
+    })).unwrap();
+    // the first 3 chunks are coalesced, and the last one is split
+    assert_eq!(chunks, ["abc", "defg", "hijk", "l"]);
 }
 
 #[test]
-fn pipeline_disabled() {
-    let server = serve();
-    let mut req = connect(server.addr());
-    server.reply()
-        .header("content-length", "12")
-        .body("Hello World!");
-    server.reply()
-        .header("content-length", "12")
-        .body("Hello World!");
+fn http2_scheduling_policy() {
+    use hyper::server::conn::SchedulingPolicy;
 
-    req.write_all(b"\
-        GET / HTTP/1.1\r\n\
-        Host: example.domain\r\n\
-        \r\n\
-        GET / HTTP/1.1\r\n\
-        Host: example.domain\r\n\
-        \r\n\
-    ").expect("write 1");
+    let _ = pretty_env_logger::try_init();
+    for &policy in &[SchedulingPolicy::RoundRobin, SchedulingPolicy::Sequential] {
+        let mut runtime = Runtime::new().unwrap();
+        let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let fut = listener.incoming()
+            .into_future()
+            .map_err(|_| unreachable!())
+            .and_then(move |(item, _incoming)| {
+                let socket = item.unwrap();
+                Http::new()
+                    .http2_only(true)
+                    .http2_scheduling_policy(policy)
+                    .serve_connection(socket, service_fn(|req: Request<Body>| {
+                        let mut res = Response::new(Body::from(vec![b'x'; 100 * 1024]));
+                        // the service can override the priority of the request
+                        if req.uri().path() == "/urgent" {
+                            res.headers_mut().insert("priority", HeaderValue::from_static("u=0"));
+                        }
+                        Ok::<_, hyper::Error>(res)
+                    }))
+                    .map_err(|e| panic!("server error: {}", e))
+            });
+        runtime.spawn(fut);
 
-    let mut buf = vec![0; 4096];
-    let n = req.read(&mut buf).expect("read 1");
-    assert_ne!(n, 0);
-    // Woah there. What?
-    //
-    // This test is wishy-washy because of race conditions in access of the
-    // socket. The test is still useful, since it allows for the responses
-    // to be received in 2 reads. But it might sometimes come in 1 read.
-    //
-    // TODO: add in a delay to the `ServeReply` interface, to allow this
-    // delay to prevent the 2 writes from happening before this test thread
-    // can read from the socket.
-    match req.read(&mut buf) {
-        Ok(n) => {
-            // won't be 0, because we didn't say to close, and so socket
-            // will be open until `server` drops
-            assert_ne!(n, 0);
-        }
-        Err(_) => (),
+        // more than fits in the connection's flow control window at once
+        let client: Client<_, hyper::Body> = Client::builder().http2_only(true).build_http();
+        let priorities = ["u=7", "u=1, i", "i=?0", "", "u=3", "u=5, i"];
+        let reqs = priorities.iter().enumerate().map(|(i, priority)| {
+            let path = if i == 2 { "/urgent" } else { "/" };
+            let mut req = Request::get(format!("http://{}{}", addr, path)).body(Body::empty()).unwrap();
+            if !priority.is_empty() {
+                req.headers_mut().insert("priority", HeaderValue::from_static(priority));
+            }
+            client.request(req).and_then(|res| res.into_body().concat2()).map(|body| body.len())
+        }).collect::<Vec<_>>();
+        let lens = runtime.block_on(future::join_all(reqs)).unwrap();
+        assert_eq!(lens, vec![100 * 1024; priorities.len()], "{:?}", policy);
     }
 }
 
 #[test]
-fn pipeline_enabled() {
-    let server = serve_with_options(ServeOptions {
-        pipeline: true,
-        .. Default::default()
-    });
-    let mut req = connect(server.addr());
-    server.reply()
-        .header("content-length", "12")
-        .body("Hello World\n");
-    server.reply()
-        .header("content-length", "12")
-        .body("Hello World\n");
+fn http2_client_resets_response_body() {
+    use hyper::error::Reason;
 
-    req.write_all(b"\
-        GET / HTTP/1.1\r\n\
-        Host: example.domain\r\n\
-        \r\n\
-        GET / HTTP/1.1\r\n\
-        Host: example.domain\r\n\
-        Connection: close\r\n\
-        \r\n\
-    ").expect("write 1");
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
 
-    let mut buf = vec![0; 4096];
-    let n = req.read(&mut buf).expect("read 1");
-    assert_ne!(n, 0);
+    let (reason_tx, reason_rx) = mpsc::channel();
+    let reason_tx = Mutex::new(reason_tx);
+    // keeps the response body open
+    let res_tx = Arc::new(Mutex::new(None));
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http2_only(true)
+                .serve_connection(socket, service_fn(move |req: Request<Body>| {
+                    let reason_tx = reason_tx.lock().unwrap().clone();
+                    hyper::rt::spawn(req.into_body().for_each(|_| Ok(())).then(move |res| {
+                        let _ = reason_tx.send(res.err().and_then(|e| e.h2_reason()));
+                        Ok(())
+                    }));
+                    let (mut tx, body) = Body::channel();
+                    tx.send_data("hello".into()).expect("send_data");
+                    *res_tx.lock().unwrap() = Some(tx);
+                    Ok::<_, hyper::Error>(Response::new(body))
+                }))
+                .map_err(|e| panic!("server error: {}", e))
+        });
+    runtime.spawn(fut);
+
+    let client: Client<_, hyper::Body> = Client::builder().http2_only(true).build_http();
+    let (_req_tx, req_body) = Body::channel();
+    let req = Request::post(format!("http://{}", addr))
+        .body(req_body)
+        .unwrap();
+    let (chunk, mut body) = runtime.block_on(client.request(req).and_then(|res| {
+        res.into_body()
+            .into_future()
+            .map_err(|(err, _)| err)
+    })).unwrap();
+    assert_eq!(chunk.as_ref().map(|chunk| s(chunk)), Some("hello"));
+
+    body.reset(Reason::ENHANCE_YOUR_CALM);
+    let reason = reason_rx.recv_timeout(Duration::from_secs(5)).expect("request body result");
+    assert_eq!(reason, Some(Reason::ENHANCE_YOUR_CALM));
+}
 
-    {
-        let mut lines = buf.split(|&b| b == b'\n');
-        assert_eq!(s(lines.next().unwrap()), "HTTP/1.1 200 OK\r");
-        assert_eq!(s(lines.next().unwrap()), "content-length: 12\r");
-        lines.next().unwrap(); // Date
-        assert_eq!(s(lines.next().unwrap()), "\r");
-        assert_eq!(s(lines.next().unwrap()), "Hello World");
+#[test]
+fn http2_client_resets_response_body_without_request_body() {
+    use hyper::error::Reason;
 
-        assert_eq!(s(lines.next().unwrap()), "HTTP/1.1 200 OK\r");
-        assert_eq!(s(lines.next().unwrap()), "content-length: 12\r");
-        lines.next().unwrap(); // Date
-        assert_eq!(s(lines.next().unwrap()), "\r");
-        assert_eq!(s(lines.next().unwrap()), "Hello World");
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (closed_tx, closed_rx) = oneshot::channel::<()>();
+    let closed_tx = Arc::new(Mutex::new(Some(closed_tx)));
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http2_only(true)
+                .serve_connection(socket, service_fn(move |_req| {
+                    let (mut tx, body) = Body::channel();
+                    tx.send_data("hello".into()).expect("send_data");
+                    let closed_tx = closed_tx.lock().unwrap().take().unwrap();
+                    let closed = future::poll_fn(move || Ok::<_, ()>(tx.poll_closed()))
+                        .map(move |()| {
+                            let _ = closed_tx.send(());
+                        });
+                    hyper::rt::spawn(closed);
+                    Ok::<_, hyper::Error>(Response::new(body))
+                }))
+                .map_err(|e| panic!("server error: {}", e))
+        });
+    runtime.spawn(fut);
+
+    // the GET has no body to pipe, so its response body resets the stream
+    let client: Client<_, hyper::Body> = Client::builder().http2_only(true).build_http();
+    let req = Request::get(format!("http://{}", addr))
+        .body(Body::empty())
+        .unwrap();
+    let (chunk, mut body) = runtime.block_on(client.request(req).and_then(|res| {
+        res.into_body()
+            .into_future()
+            .map_err(|(err, _)| err)
+    })).unwrap();
+    assert_eq!(chunk.as_ref().map(|chunk| s(chunk)), Some("hello"));
+
+    body.reset(Reason::ENHANCE_YOUR_CALM);
+    let timeout = Delay::new(Duration::from_secs(5));
+    match runtime.block_on(closed_rx.select2(timeout)) {
+        Ok(Either::A(_)) => (),
+        _ => panic!("response body sender not notified of reset"),
     }
+}
 
+#[test]
+fn http2_client_cancels_request() {
+    use hyper::error::Reason;
 
-    // with pipeline enabled, both responses should have been in the first read
-    // so a second read should be EOF
-    let n = req.read(&mut buf).expect("read 2");
-    assert_eq!(n, 0);
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (reason_tx, reason_rx) = mpsc::channel();
+    let reason_tx = Mutex::new(reason_tx);
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http2_only(true)
+                .serve_connection(socket, service_fn(move |req: Request<Body>| {
+                    // never responds, and waits for the request body
+                    let reason_tx = reason_tx.lock().unwrap().clone();
+                    req.into_body().for_each(|_| Ok(())).then(move |res| {
+                        let _ = reason_tx.send(res.err().and_then(|e| e.h2_reason()));
+                        Ok::<_, hyper::Error>(Response::new(Body::empty()))
+                    })
+                }))
+                .map_err(|e| panic!("server error: {}", e))
+        });
+    runtime.spawn(fut);
+
+    let client: Client<_, hyper::Body> = Client::builder().http2_only(true).build_http();
+    let (_req_tx, req_body) = Body::channel();
+    let req = Request::post(format!("http://{}", addr))
+        .body(req_body)
+        .unwrap();
+    let res = client.request(req);
+    let cancel = res.cancel_handle();
+    // canceled once the request was sent
+    runtime.spawn(Delay::new(Duration::from_millis(100)).then(move |_| {
+        cancel.cancel();
+        Ok(())
+    }));
+
+    let err = runtime.block_on(res).unwrap_err();
+    assert!(err.is_request_canceled(), "{:?}", err);
+    let reason = reason_rx.recv_timeout(Duration::from_secs(5)).expect("request body result");
+    assert_eq!(reason, Some(Reason::CANCEL));
 }
 
 #[test]
-fn http_10_request_receives_http_10_response() {
-    let server = serve();
+fn instrumentation_reports_events() {
+    use std::sync::atomic::AtomicUsize;
+    use hyper::instrument::{Instrumentation, PoolEvent};
+
+    #[derive(Default)]
+    struct Events {
+        opened: AtomicUsize,
+        closed: AtomicUsize,
+        finished: Mutex<Vec<Option<StatusCode>>>,
+        read: AtomicUsize,
+        written: AtomicUsize,
+        checkouts: Mutex<Vec<bool>>,
+        waits: AtomicUsize,
+    }
+
+    impl Instrumentation for Events {
+        fn connection_opened(&self) {
+            self.opened.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn connection_closed(&self) {
+            self.closed.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn request_finished(&self, status: Option<StatusCode>, _elapsed: Duration) {
+            self.finished.lock().unwrap().push(status);
+        }
+
+        fn bytes_read(&self, n: usize) {
+            self.read.fetch_add(n, Ordering::SeqCst);
+        }
+
+        fn bytes_written(&self, n: usize) {
+            self.written.fetch_add(n, Ordering::SeqCst);
+        }
+
+        fn pool_checkout(&self, reused: bool) {
+            self.checkouts.lock().unwrap().push(reused);
+        }
+
+        fn pool_event(&self, event: PoolEvent) {
+            if let PoolEvent::CheckoutWaited(..) = event {
+                self.waits.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    fn run(http2: bool) {
+        let _ = pretty_env_logger::try_init();
+        let mut runtime = Runtime::new().unwrap();
+        let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server_events = Arc::new(Events::default());
+        let events = server_events.clone();
+        let (done_tx, done_rx) = oneshot::channel();
+        let fut = listener.incoming()
+            .into_future()
+            .map_err(|_| unreachable!())
+            .and_then(move |(item, _incoming)| {
+                let socket = item.unwrap();
+                Http::new()
+                    .http2_only(http2)
+                    .instrumentation(events)
+                    .serve_connection(socket, service_fn(|_req| {
+                        Ok::<_, hyper::Error>(Response::new(Body::from("hello")))
+                    }))
+                    .map_err(|e| panic!("server error: {}", e))
+            })
+            .then(move |_| done_tx.send(()));
+        runtime.spawn(fut.map_err(|_| ()));
+
+        let client_events = Arc::new(Events::default());
+        let client: Client<_, Body> = Client::builder()
+            .http2_only(http2)
+            .instrumentation(client_events.clone())
+            .build_http();
+        for _ in 0..2 {
+            let res = runtime.block_on(client.get(format!("http://{}", addr).parse().unwrap())).unwrap();
+            runtime.block_on(res.into_body().concat2()).unwrap();
+            // let the connection go back to the pool
+            thread::sleep(Duration::from_millis(50));
+        }
+        drop(client);
+        runtime.block_on(done_rx).unwrap();
+
+        for events in &[&server_events, &client_events] {
+            assert_eq!(events.opened.load(Ordering::SeqCst), 1, "http2 = {}", http2);
+            assert_eq!(*events.finished.lock().unwrap(), [Some(StatusCode::OK), Some(StatusCode::OK)]);
+            assert!(events.read.load(Ordering::SeqCst) > 0);
+            assert!(events.written.load(Ordering::SeqCst) > 0);
+        }
+        assert_eq!(server_events.closed.load(Ordering::SeqCst), 1);
+        assert_eq!(*client_events.checkouts.lock().unwrap(), [false, true]);
+        assert_eq!(client_events.waits.load(Ordering::SeqCst), 2);
+    }
+
+    run(false);
+    run(true);
+}
+
+#[test]
+fn spawner_is_given_tasks() {
+    use hyper::exec::{Spawner, Task, TaskKind};
+
+    #[derive(Default)]
+    struct Kinds(Mutex<Vec<TaskKind>>);
+
+    impl Spawner for Kinds {
+        fn spawn(&self, task: Task) {
+            self.0.lock().unwrap().push(task.kind());
+            hyper::rt::spawn(task);
+        }
+    }
+
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_kinds = Arc::new(Kinds::default());
+    let kinds = server_kinds.clone();
+    let (done_tx, done_rx) = oneshot::channel();
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http2_only(true)
+                .spawner(kinds)
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    req.into_body().concat2().map(|_| Response::new(Body::from("hello")))
+                }))
+                .map_err(|e| panic!("server error: {}", e))
+        })
+        .then(move |_| done_tx.send(()));
+    runtime.spawn(fut.map_err(|_| ()));
+
+    let client_kinds = Arc::new(Kinds::default());
+    let client: Client<_, Body> = Client::builder()
+        .http2_only(true)
+        .spawner(client_kinds.clone())
+        .build_http();
+    let req = Request::post(format!("http://{}", addr))
+        .body(Body::from("abc"))
+        .unwrap();
+    let res = runtime.block_on(client.request(req)).unwrap();
+    runtime.block_on(res.into_body().concat2()).unwrap();
+    drop(client);
+    runtime.block_on(done_rx).unwrap();
+
+    assert_eq!(*server_kinds.0.lock().unwrap(), [TaskKind::Stream]);
+    let kinds = client_kinds.0.lock().unwrap();
+    for kind in &[TaskKind::Connection, TaskKind::RequestBody, TaskKind::Response] {
+        assert!(kinds.contains(kind), "{:?} in {:?}", kind, *kinds);
+    }
+}
+
+#[test]
+fn http2_streams_polled_by_connection() {
+    use hyper::exec::{Spawner, Task, TaskKind};
+
+    #[derive(Default)]
+    struct Kinds(Mutex<Vec<TaskKind>>);
+
+    impl Spawner for Kinds {
+        fn spawn(&self, task: Task) {
+            self.0.lock().unwrap().push(task.kind());
+            hyper::rt::spawn(task);
+        }
+    }
+
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_kinds = Arc::new(Kinds::default());
+    let kinds = server_kinds.clone();
+    let (done_tx, done_rx) = oneshot::channel();
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http2_only(true)
+                .http2_spawn_streams(false)
+                .spawner(kinds)
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    req.into_body().concat2().map(|body| Response::new(Body::from(body)))
+                }))
+                .map_err(|e| panic!("server error: {}", e))
+        })
+        .then(move |_| done_tx.send(()));
+    runtime.spawn(fut.map_err(|_| ()));
+
+    let client_kinds = Arc::new(Kinds::default());
+    let client: Client<_, Body> = Client::builder()
+        .http2_only(true)
+        .http2_spawn_streams(false)
+        .spawner(client_kinds.clone())
+        .build_http();
+    let responses = (0..10).map(|i| {
+        let req = Request::post(format!("http://{}", addr))
+            .body(Body::from(format!("request {}", i)))
+            .unwrap();
+        client.request(req)
+            .and_then(|res| res.into_body().concat2())
+            .map(move |body| assert_eq!(body.as_ref(), format!("request {}", i).as_bytes()))
+    }).collect::<Vec<_>>();
+    runtime.block_on(future::join_all(responses)).unwrap();
+    drop(client);
+    runtime.block_on(done_rx).unwrap();
+
+    assert_eq!(*server_kinds.0.lock().unwrap(), []);
+    let kinds = client_kinds.0.lock().unwrap();
+    assert!(kinds.contains(&TaskKind::Connection), "{:?}", *kinds);
+    for kind in &[TaskKind::RequestBody, TaskKind::Response] {
+        assert!(!kinds.contains(kind), "{:?} in {:?}", kind, *kinds);
+    }
+}
+
+#[test]
+fn http2_connections_per_host_share_requests() {
+    use std::collections::HashSet;
+    use std::sync::atomic::AtomicUsize;
 
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = accepted.clone();
+    let fut = listener.incoming()
+        .map_err(|e| panic!("accept error: {}", e))
+        .for_each(move |socket| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let conn = Http::new()
+                .http2_only(true)
+                .serve_connection(socket, service_fn(|_req: Request<Body>| {
+                    Ok::<_, hyper::Error>(Response::new(Body::from("hello")))
+                }))
+                .map_err(|e| panic!("server error: {}", e));
+            hyper::rt::spawn(conn);
+            Ok(())
+        });
+    runtime.spawn(fut);
+
+    let client: Client<_, Body> = Client::builder()
+        .http2_only(true)
+        .http2_connections_per_host(2)
+        .build_http();
+    let uri: hyper::Uri = format!("http://{}", addr).parse().unwrap();
+    let mut ids = HashSet::new();
+    for _ in 0..50 {
+        let res = runtime.block_on(client.get(uri.clone())).unwrap();
+        ids.insert(res.extensions().get::<hyper::ext::ConnectionInfo>().expect("connection info").id());
+        if ids.len() == 2 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(ids.len(), 2, "requests shared by two connections");
+
+    let responses = (0..10).map(|_| client.get(uri.clone())).collect::<Vec<_>>();
+    runtime.block_on(future::join_all(responses)).unwrap();
+    assert_eq!(accepted.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn http2_saturated_connections_open_another() {
+    extern crate h2;
+    use std::sync::atomic::AtomicUsize;
+    use hyper::ext::ConnectionInfo;
+
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // a peer allowing one stream at a time, that answers slowly
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = accepted.clone();
+    let fut = listener.incoming()
+        .map_err(|e| panic!("accept error: {}", e))
+        .for_each(move |socket| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let conn = h2::server::Builder::new()
+                .max_concurrent_streams(1)
+                .handshake::<_, &'static [u8]>(socket)
+                .and_then(|conn| conn.for_each(|(_req, mut respond)| {
+                    hyper::rt::spawn(Delay::new(Duration::from_millis(200)).then(move |_| {
+                        respond.send_response(http::Response::new(()), true).unwrap();
+                        Ok(())
+                    }));
+                    Ok(())
+                }))
+                .map_err(|e| panic!("server error: {}", e));
+            hyper::rt::spawn(conn);
+            Ok(())
+        });
+    runtime.spawn(fut);
+
+    let client: Client<_, Body> = Client::builder()
+        .http2_only(true)
+        .http2_max_connections_per_host(3)
+        .build_http();
+    let uri: hyper::Uri = format!("http://{}", addr).parse().unwrap();
+    // so the client got the peer's settings
+    runtime.block_on(client.get(uri.clone())).unwrap();
+
+    // the second request waits for the first, saturating the connection
+    let (tx, rx) = oneshot::channel();
+    let saturating = client.get(uri.clone()).join(client.get(uri.clone()));
+    runtime.spawn(saturating.then(move |res| {
+        let _ = tx.send(res);
+        Ok(())
+    }));
+    thread::sleep(Duration::from_millis(50));
+
+    let res = runtime.block_on(client.get(uri.clone())).unwrap();
+    let (first, second) = runtime.block_on(rx).unwrap().unwrap();
+    let id = |res: &Response<Body>| res.extensions().get::<ConnectionInfo>().expect("connection info").id();
+    assert_eq!(id(&first), id(&second));
+    assert_ne!(id(&res), id(&first), "sent on another connection");
+    assert_eq!(accepted.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn affinity_pins_requests_to_one_connection() {
+    use std::sync::atomic::AtomicUsize;
+    use hyper::client::Affinity;
+    use hyper::ext::ConnectionInfo;
+
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = accepted.clone();
+    let fut = listener.incoming()
+        .map_err(|e| panic!("accept error: {}", e))
+        .for_each(move |socket| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let conn = Http::new()
+                .serve_connection(socket, service_fn(|_req: Request<Body>| {
+                    Ok::<_, hyper::Error>(Response::new(Body::from("hello")))
+                }))
+                .map_err(|e| panic!("server error: {}", e));
+            hyper::rt::spawn(conn);
+            Ok(())
+        });
+    runtime.spawn(fut);
+
+    let client = Client::new();
+    let affinity = Affinity::new();
+    let get = |uri: String, affinity: Option<&Affinity>| {
+        let mut req = Request::new(Body::empty());
+        *req.uri_mut() = uri.parse().unwrap();
+        if let Some(affinity) = affinity {
+            req.extensions_mut().insert(affinity.clone());
+        }
+        client.request(req).and_then(|res| {
+            let id = res.extensions().get::<ConnectionInfo>().expect("connection info").id();
+            res.into_body().concat2().map(move |_| id)
+        })
+    };
+    let uri = format!("http://{}", addr);
+
+    let pinned = runtime.block_on(get(uri.clone(), Some(&affinity))).unwrap();
+    assert_eq!(affinity.connection_id(), Some(pinned));
+    for _ in 0..3 {
+        let both = get(uri.clone(), Some(&affinity)).join(get(uri.clone(), None));
+        let (id, other) = runtime.block_on(both).unwrap();
+        assert_eq!(id, pinned);
+        assert_ne!(other, pinned);
+        thread::sleep(Duration::from_millis(10));
+    }
+    // the other requests didn't need the pinned connection
+    assert_eq!(accepted.load(Ordering::SeqCst), 2);
+
+    let uri = format!("http://localhost:{}", addr.port());
+    let err = runtime.block_on(get(uri, Some(&affinity))).unwrap_err();
+    assert!(err.is_affinity_lost(), "{:?}", err);
+}
+
+#[test]
+fn tap_copies_connection_bytes() {
+    use hyper::instrument::Tap;
+
+    #[derive(Default)]
+    struct Bytes {
+        read: Mutex<Vec<u8>>,
+        written: Mutex<Vec<u8>>,
+    }
+
+    impl Tap for Bytes {
+        fn data_read(&self, data: &[u8]) {
+            self.read.lock().unwrap().extend_from_slice(data);
+        }
+
+        fn data_written(&self, data: &[u8]) {
+            self.written.lock().unwrap().extend_from_slice(data);
+        }
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    fn run(http2: bool) {
+        let _ = pretty_env_logger::try_init();
+        let mut runtime = Runtime::new().unwrap();
+        let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server_tap = Arc::new(Bytes::default());
+        let tap = server_tap.clone();
+        let (done_tx, done_rx) = oneshot::channel();
+        let fut = listener.incoming()
+            .into_future()
+            .map_err(|_| unreachable!())
+            .and_then(move |(item, _incoming)| {
+                let socket = item.unwrap();
+                Http::new()
+                    .http2_only(http2)
+                    .tap(tap)
+                    .serve_connection(socket, service_fn(|_req| {
+                        Ok::<_, hyper::Error>(Response::new(Body::from("hello")))
+                    }))
+                    .map_err(|e| panic!("server error: {}", e))
+            })
+            .then(move |_| done_tx.send(()));
+        runtime.spawn(fut.map_err(|_| ()));
+
+        let client_tap = Arc::new(Bytes::default());
+        let client: Client<_, Body> = Client::builder()
+            .http2_only(http2)
+            .tap(client_tap.clone())
+            .build_http();
+        let res = runtime.block_on(client.get(format!("http://{}", addr).parse().unwrap())).unwrap();
+        runtime.block_on(res.into_body().concat2()).unwrap();
+        drop(client);
+        runtime.block_on(done_rx).unwrap();
+
+        let server_read = server_tap.read.lock().unwrap();
+        let server_written = server_tap.written.lock().unwrap();
+        if http2 {
+            assert!(server_read.starts_with(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n"));
+        } else {
+            assert!(server_read.starts_with(b"GET / HTTP/1.1\r\n"));
+            assert!(server_written.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        }
+        assert!(contains(&server_written, b"hello"), "http2 = {}", http2);
+
+        // both ends saw the same bytes
+        assert_eq!(*client_tap.written.lock().unwrap(), *server_read);
+        assert!(server_written.starts_with(&client_tap.read.lock().unwrap()));
+    }
+
+    run(false);
+    run(true);
+}
+
+#[test]
+fn post_with_chunked_trailers() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            POST / HTTP/1.1\r\n\
+            Connection: close\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            5\r\n\
+            hello\r\n\
+            0\r\n\
+            Checksum: abc\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).unwrap();
+        assert!(s(&buf).ends_with("hello abc"), "{:?}", s(&buf));
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    let mut body = req.into_body();
+                    let mut data = Vec::new();
+                    future::poll_fn(move || {
+                        while let Some(chunk) = try_ready!(body.poll()) {
+                            data.extend_from_slice(&chunk);
+                        }
+                        let trailers = try_ready!(body.trailers().poll()).expect("trailers");
+                        let reply = format!("{} {}", s(&data), s(trailers["checksum"].as_bytes()));
+                        Ok::<_, hyper::Error>(futures::Async::Ready(Response::new(Body::from(reply))))
+                    })
+                }))
+        });
+
+    fut.wait().unwrap();
+    client.join().unwrap();
+}
+
+#[test]
+fn response_with_chunked_trailers() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            TE: trailers\r\n\
+            \r\n\
+            GET / HTTP/1.1\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).unwrap();
+        let with_trailers = "\r\n\r\n5\r\nhello\r\n0\r\nchecksum: abc\r\n\r\n";
+        let without_trailers = "\r\n\r\n5\r\nhello\r\n0\r\n\r\n";
+        let response = s(&buf);
+        let first = response.find(with_trailers).expect("trailers in first response");
+        assert!(response[first..].ends_with(without_trailers), "{:?}", response);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, service_fn(|_req| {
+                    let (mut tx, body) = Body::channel();
+                    tx.send_data("hello".into()).unwrap();
+                    let mut trailers = ::hyper::HeaderMap::new();
+                    trailers.insert("checksum", HeaderValue::from_static("abc"));
+                    tx.send_trailers(trailers).unwrap();
+                    Ok::<_, hyper::Error>(Response::new(body))
+                }))
+        });
+
+    fut.wait().unwrap();
+    client.join().unwrap();
+}
+
+#[test]
+fn post_with_incomplete_body() {
+    extern crate pretty_env_logger;
+    let _ = pretty_env_logger::try_init();
+    let server = serve();
     let mut req = connect(server.addr());
     req.write_all(b"\
-        GET / HTTP/1.0\r\n\
+        POST / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Content-Length: 10\r\n\
+        \r\n\
+        12345\
+    ").expect("write");
+    req.shutdown(Shutdown::Write).expect("shutdown write");
+
+    server.body_err();
+
+    req.read(&mut [0; 256]).expect("read");
+}
+
+
+#[test]
+fn head_response_can_send_content_length() {
+    extern crate pretty_env_logger;
+    let _ = pretty_env_logger::try_init();
+    let server = serve();
+    server.reply()
+        .header("content-length", "1024");
+    let mut req = connect(server.addr());
+    req.write_all(b"\
+        HEAD / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
         \r\n\
     ").unwrap();
 
-    let expected = "HTTP/1.0 200 OK\r\ncontent-length: 0\r\n";
-    let mut buf = [0; 256];
-    let n = req.read(&mut buf).unwrap();
-    assert!(n >= expected.len(), "read: {:?} >= {:?}", n, expected.len());
-    assert_eq!(s(&buf[..expected.len()]), expected);
+    let mut response = String::new();
+    req.read_to_string(&mut response).unwrap();
+
+    assert!(response.contains("content-length: 1024\r\n"));
+
+    let mut lines = response.lines();
+    assert_eq!(lines.next(), Some("HTTP/1.1 200 OK"));
+
+    let mut lines = lines.skip_while(|line| !line.is_empty());
+    assert_eq!(lines.next(), Some(""));
+    assert_eq!(lines.next(), None);
+}
+
+#[test]
+fn head_response_doesnt_send_body() {
+    extern crate pretty_env_logger;
+    let _ = pretty_env_logger::try_init();
+    let foo_bar = b"foo bar baz";
+    let server = serve();
+    server.reply()
+        .body(foo_bar);
+    let mut req = connect(server.addr());
+    req.write_all(b"\
+        HEAD / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ").unwrap();
+
+    let mut response = String::new();
+    req.read_to_string(&mut response).unwrap();
+
+    assert!(response.contains("content-length: 11\r\n"));
+
+    let mut lines = response.lines();
+    assert_eq!(lines.next(), Some("HTTP/1.1 200 OK"));
+
+    let mut lines = lines.skip_while(|line| !line.is_empty());
+    assert_eq!(lines.next(), Some(""));
+    assert_eq!(lines.next(), None);
+}
+
+#[test]
+fn response_does_not_set_chunked_if_body_not_allowed() {
+    extern crate pretty_env_logger;
+    let _ = pretty_env_logger::try_init();
+    let server = serve();
+    server.reply()
+        .status(hyper::StatusCode::NOT_MODIFIED)
+        .header("transfer-encoding", "chunked");
+    let mut req = connect(server.addr());
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ").unwrap();
+
+    let mut response = String::new();
+    req.read_to_string(&mut response).unwrap();
+
+    assert!(!response.contains("transfer-encoding"));
+
+    let mut lines = response.lines();
+    assert_eq!(lines.next(), Some("HTTP/1.1 304 Not Modified"));
+
+    // no body or 0\r\n\r\n
+    let mut lines = lines.skip_while(|line| !line.is_empty());
+    assert_eq!(lines.next(), Some(""));
+    assert_eq!(lines.next(), None);
+}
+
+#[test]
+fn keep_alive() {
+    let foo_bar = b"foo bar baz";
+    let server = serve();
+    server.reply()
+        .header("content-length", foo_bar.len().to_string())
+        .body(foo_bar);
+    let mut req = connect(server.addr());
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+    ").expect("writing 1");
+
+    let mut buf = [0; 1024 * 8];
+    loop {
+        let n = req.read(&mut buf[..]).expect("reading 1");
+        if n < buf.len() {
+            if &buf[n - foo_bar.len()..n] == foo_bar {
+                break;
+            }
+        }
+    }
+
+    // try again!
+
+    let quux = b"zar quux";
+    server.reply()
+        .header("content-length", quux.len().to_string())
+        .body(quux);
+    req.write_all(b"\
+        GET /quux HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ").expect("writing 2");
+
+    let mut buf = [0; 1024 * 8];
+    loop {
+        let n = req.read(&mut buf[..]).expect("reading 2");
+        assert!(n > 0, "n = {}", n);
+        if n < buf.len() {
+            if &buf[n - quux.len()..n] == quux {
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn http_10_keep_alive() {
+    let foo_bar = b"foo bar baz";
+    let server = serve();
+    server.reply()
+        .header("content-length", foo_bar.len().to_string())
+        .body(foo_bar);
+    let mut req = connect(server.addr());
+    req.write_all(b"\
+        GET / HTTP/1.0\r\n\
+        Host: example.domain\r\n\
+        Connection: keep-alive\r\n\
+        \r\n\
+    ").expect("writing 1");
+
+    let mut buf = [0; 1024 * 8];
+    loop {
+        let n = req.read(&mut buf[..]).expect("reading 1");
+        if n < buf.len() {
+            if &buf[n - foo_bar.len()..n] == foo_bar {
+                break;
+            }
+        }
+    }
+
+    // try again!
+
+    let quux = b"zar quux";
+    server.reply()
+        .header("content-length", quux.len().to_string())
+        .body(quux);
+    req.write_all(b"\
+        GET /quux HTTP/1.0\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+    ").expect("writing 2");
+
+    let mut buf = [0; 1024 * 8];
+    loop {
+        let n = req.read(&mut buf[..]).expect("reading 2");
+        assert!(n > 0, "n = {}", n);
+        if n < buf.len() {
+            if &buf[n - quux.len()..n] == quux {
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn http_10_keep_alive_response_header() {
+    let server = serve();
+    server.reply()
+        .header("content-length", "0");
+    let mut req = connect(server.addr());
+    req.write_all(b"\
+        GET / HTTP/1.0\r\n\
+        Connection: keep-alive\r\n\
+        \r\n\
+    ").expect("writing 1");
+
+    let mut buf = [0; 1024 * 8];
+    let n = req.read(&mut buf[..]).expect("reading 1");
+    let res = s(&buf[..n]);
+    assert!(res.starts_with("HTTP/1.0 200 OK\r\n"), "{:?}", res);
+    assert!(res.contains("\r\nconnection: keep-alive\r\n"), "{:?}", res);
+}
+
+#[test]
+fn http_10_keep_alive_close_delimited_response() {
+    let server = serve();
+    let (mut tx, body) = hyper::Body::channel();
+    tx.send_data("foo bar baz".into()).expect("send_data");
+    drop(tx);
+    server.reply().body_stream(body);
+    let mut req = connect(server.addr());
+    req.write_all(b"\
+        GET / HTTP/1.0\r\n\
+        Connection: keep-alive\r\n\
+        \r\n\
+    ").expect("writing 1");
+
+    // the body has no length, so it ends when the connection closes
+    let mut res = String::new();
+    req.read_to_string(&mut res).expect("reading 1");
+    assert!(res.starts_with("HTTP/1.0 200 OK\r\n"), "{:?}", res);
+    assert!(!has_header(&res, "connection:"), "{:?}", res);
+    assert!(res.ends_with("\r\n\r\nfoo bar baz"), "{:?}", res);
+}
+
+#[test]
+fn disable_keep_alive() {
+    let foo_bar = b"foo bar baz";
+    let server = serve_with_options(ServeOptions {
+        keep_alive_disabled: true,
+        .. Default::default()
+    });
+    server.reply()
+        .header("content-length", foo_bar.len().to_string())
+        .body(foo_bar);
+    let mut req = connect(server.addr());
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: keep-alive\r\n\
+        \r\n\
+    ").expect("writing 1");
+
+    let mut buf = [0; 1024 * 8];
+    loop {
+        let n = req.read(&mut buf[..]).expect("reading 1");
+        if n < buf.len() {
+            if &buf[n - foo_bar.len()..n] == foo_bar {
+                break;
+            } else {
+            }
+        }
+    }
+
+    // try again!
+
+    let quux = b"zar quux";
+    server.reply()
+        .header("content-length", quux.len().to_string())
+        .body(quux);
+
+    // the write can possibly succeed, since it fills the kernel buffer on the first write
+    let _ = req.write_all(b"\
+        GET /quux HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ");
+
+    let mut buf = [0; 1024 * 8];
+    match req.read(&mut buf[..]) {
+        // Ok(0) means EOF, so a proper shutdown
+        // Err(_) could mean ConnReset or something, also fine
+        Ok(0) |
+        Err(_) => {}
+        Ok(n) => {
+            panic!("read {} bytes on a disabled keep-alive socket", n);
+        }
+    }
+}
+
+#[test]
+fn header_connection_close() {
+    let foo_bar = b"foo bar baz";
+    let server = serve();
+    server.reply()
+        .header("content-length", foo_bar.len().to_string())
+        .header("connection", "close")
+        .body(foo_bar);
+    let mut req = connect(server.addr());
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: keep-alive\r\n\
+        \r\n\
+    ").expect("writing 1");
+
+    let mut buf = [0; 1024 * 8];
+    loop {
+        let n = req.read(&mut buf[..]).expect("reading 1");
+        if n < buf.len() {
+            if &buf[n - foo_bar.len()..n] == foo_bar {
+                break;
+            } else {
+            }
+        }
+    }
+
+    // try again!
+    // but since the server responded with connection: close, the internal
+    // state should have noticed and shutdown
+
+    let quux = b"zar quux";
+    server.reply()
+        .header("content-length", quux.len().to_string())
+        .body(quux);
+
+    // the write can possibly succeed, since it fills the kernel buffer on the first write
+    let _ = req.write_all(b"\
+        GET /quux HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ");
+
+    let mut buf = [0; 1024 * 8];
+    match req.read(&mut buf[..]) {
+        // Ok(0) means EOF, so a proper shutdown
+        // Err(_) could mean ConnReset or something, also fine
+        Ok(0) |
+        Err(_) => {}
+        Ok(n) => {
+            panic!("read {} bytes on a disabled keep-alive socket", n);
+        }
+    }
+}
+
+#[test]
+fn expect_continue() {
+    let server = serve();
+    let mut req = connect(server.addr());
+    server.reply();
+
+    req.write_all(b"\
+        POST /foo HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Expect: 100-continue\r\n\
+        Content-Length: 5\r\n\
+        Connection: Close\r\n\
+        \r\n\
+    ").expect("write 1");
+
+    let msg = b"HTTP/1.1 100 Continue\r\n\r\n";
+    let mut buf = vec![0; msg.len()];
+    req.read_exact(&mut buf).expect("read 1");
+    assert_eq!(buf, msg);
+
+    let msg = b"hello";
+    req.write_all(msg).expect("write 2");
+
+    let mut body = String::new();
+    req.read_to_string(&mut body).expect("read 2");
+
+    let body = server.body();
+    assert_eq!(body, msg);
+}
+
+#[test]
+fn pipeline_disabled() {
+    let server = serve();
+    let mut req = connect(server.addr());
+    server.reply()
+        .header("content-length", "12")
+        .body("Hello World!");
+    server.reply()
+        .header("content-length", "12")
+        .body("Hello World!");
+
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+    ").expect("write 1");
+
+    let mut buf = vec![0; 4096];
+    let n = req.read(&mut buf).expect("read 1");
+    assert_ne!(n, 0);
+    // Woah there. What?
+    //
+    // This test is wishy-washy because of race conditions in access of the
+    // socket. The test is still useful, since it allows for the responses
+    // to be received in 2 reads. But it might sometimes come in 1 read.
+    //
+    // TODO: add in a delay to the `ServeReply` interface, to allow this
+    // delay to prevent the 2 writes from happening before this test thread
+    // can read from the socket.
+    match req.read(&mut buf) {
+        Ok(n) => {
+            // won't be 0, because we didn't say to close, and so socket
+            // will be open until `server` drops
+            assert_ne!(n, 0);
+        }
+        Err(_) => (),
+    }
+}
+
+#[test]
+fn pipeline_enabled() {
+    let server = serve_with_options(ServeOptions {
+        pipeline: true,
+        .. Default::default()
+    });
+    let mut req = connect(server.addr());
+    server.reply()
+        .header("content-length", "12")
+        .body("Hello World\n");
+    server.reply()
+        .header("content-length", "12")
+        .body("Hello World\n");
+
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ").expect("write 1");
+
+    let mut buf = vec![0; 4096];
+    let n = req.read(&mut buf).expect("read 1");
+    assert_ne!(n, 0);
+
+    {
+        let mut lines = buf.split(|&b| b == b'\n');
+        assert_eq!(s(lines.next().unwrap()), "HTTP/1.1 200 OK\r");
+        assert_eq!(s(lines.next().unwrap()), "content-length: 12\r");
+        lines.next().unwrap(); // Date
+        assert_eq!(s(lines.next().unwrap()), "\r");
+        assert_eq!(s(lines.next().unwrap()), "Hello World");
+
+        assert_eq!(s(lines.next().unwrap()), "HTTP/1.1 200 OK\r");
+        assert_eq!(s(lines.next().unwrap()), "content-length: 12\r");
+        lines.next().unwrap(); // Date
+        assert_eq!(s(lines.next().unwrap()), "\r");
+        assert_eq!(s(lines.next().unwrap()), "Hello World");
+    }
+
+
+    // with pipeline enabled, both responses should have been in the first read
+    // so a second read should be EOF
+    let n = req.read(&mut buf).expect("read 2");
+    assert_eq!(n, 0);
+}
+
+#[test]
+fn http_10_request_receives_http_10_response() {
+    let server = serve();
+
+    let mut req = connect(server.addr());
+    req.write_all(b"\
+        GET / HTTP/1.0\r\n\
+        \r\n\
+    ").unwrap();
+
+    let expected = "HTTP/1.0 200 OK\r\ncontent-length: 0\r\n";
+    let mut buf = [0; 256];
+    let n = req.read(&mut buf).unwrap();
+    assert!(n >= expected.len(), "read: {:?} >= {:?}", n, expected.len());
+    assert_eq!(s(&buf[..expected.len()]), expected);
+}
+
+#[test]
+fn disable_keep_alive_mid_request() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx1, rx1) = oneshot::channel();
+    let (tx2, rx2) = oneshot::channel();
+
+    let child = thread::spawn(move || {
+        let mut req = connect(&addr);
+        req.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        tx1.send(()).unwrap();
+        rx2.wait().unwrap();
+        req.write_all(b"Host: localhost\r\n\r\n").unwrap();
+        let mut buf = vec![];
+        req.read_to_end(&mut buf).unwrap();
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new().serve_connection(socket, HelloWorld)
+                .select2(rx1)
+                .then(|r| {
+                    match r {
+                        Ok(Either::A(_)) => panic!("expected rx first"),
+                        Ok(Either::B(((), mut conn))) => {
+                            conn.graceful_shutdown();
+                            tx2.send(()).unwrap();
+                            conn
+                        }
+                        Err(Either::A((e, _))) => panic!("unexpected error {}", e),
+                        Err(Either::B((e, _))) => panic!("unexpected error {}", e),
+                    }
+                })
+        });
+
+    fut.wait().unwrap();
+    child.join().unwrap();
+}
+
+#[test]
+fn disable_keep_alive_post_request() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx1, rx1) = oneshot::channel();
+
+    let child = thread::spawn(move || {
+        let mut req = connect(&addr);
+        req.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Host: localhost\r\n\
+            \r\n\
+        ").unwrap();
+
+        let mut buf = [0; 1024 * 8];
+        loop {
+            let n = req.read(&mut buf).expect("reading 1");
+            if &buf[n - HELLO.len()..n] == HELLO.as_bytes() {
+                break;
+            }
+            if n == 0 {
+                panic!("unexpected eof");
+            }
+        }
+
+        tx1.send(()).unwrap();
+
+        let nread = req.read(&mut buf).expect("keep-alive reading");
+        assert_eq!(nread, 0);
+    });
+
+    let dropped = Dropped::new();
+    let dropped2 = dropped.clone();
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.expect("accepted socket");
+            let transport = DebugStream {
+                stream: socket,
+                _debug: dropped2,
+            };
+            Http::new().serve_connection(transport, HelloWorld)
+                .select2(rx1)
+                .then(|r| {
+                    match r {
+                        Ok(Either::A(_)) => panic!("expected rx first"),
+                        Ok(Either::B(((), mut conn))) => {
+                            conn.graceful_shutdown();
+                            conn
+                        }
+                        Err(Either::A((e, _))) => panic!("unexpected error {}", e),
+                        Err(Either::B((e, _))) => panic!("unexpected error {}", e),
+                    }
+                })
+        });
+
+    assert!(!dropped.load());
+    fut.wait().unwrap();
+    // we must poll the Core one more time in order for Windows to drop
+    // the read-blocked socket.
+    //
+    // See https://github.com/carllerche/mio/issues/776
+    let timeout = Delay::new(Duration::from_millis(10));
+    timeout.wait().unwrap();
+    assert!(dropped.load());
+    child.join().unwrap();
+}
+
+#[test]
+fn empty_parse_eof_does_not_return_error() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let _tcp = connect(&addr);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new().serve_connection(socket, HelloWorld)
+        });
+
+    fut.wait().unwrap();
+}
+
+#[test]
+fn nonempty_parse_eof_returns_error() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET / HTTP/1.1").unwrap();
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new().serve_connection(socket, HelloWorld)
+        });
+
+    fut.wait().unwrap_err();
+}
+
+#[test]
+fn returning_1xx_response_is_error() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut buf = [0; 256];
+        tcp.read(&mut buf).unwrap();
+
+        let expected = "HTTP/1.1 500 ";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, service_fn(|_| {
+                    Ok::<_, hyper::Error>(Response::builder()
+                        .status(StatusCode::CONTINUE)
+                        .body(Body::empty())
+                        .unwrap())
+                }))
+        });
+
+    fut.wait().unwrap_err();
+}
+
+#[test]
+fn upgrades() {
+    use tokio_io::io::{read_to_end, write_all};
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = oneshot::channel();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Upgrade: foobar\r\n\
+            Connection: upgrade\r\n\
+            \r\n\
+            eagerly optimistic\
+        ").expect("write 1");
+        let mut buf = [0; 256];
+        tcp.read(&mut buf).expect("read 1");
+
+        let expected = "HTTP/1.1 101 Switching Protocols\r\n";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+        let _ = tx.send(());
+
+        let n = tcp.read(&mut buf).expect("read 2");
+        assert_eq!(s(&buf[..n]), "foo=bar");
+        tcp.write_all(b"bar=foo").expect("write 2");
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| -> hyper::Error { unreachable!() })
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            let conn = Http::new()
+                .serve_connection(socket, service_fn(|_| {
+                    let res = Response::builder()
+                        .status(101)
+                        .header("upgrade", "foobar")
+                        .body(hyper::Body::empty())
+                        .unwrap();
+                    Ok::<_, hyper::Error>(res)
+                }));
+
+            let mut conn_opt = Some(conn);
+            future::poll_fn(move || {
+                try_ready!(conn_opt.as_mut().unwrap().poll_without_shutdown());
+                // conn is done with HTTP now
+                Ok(conn_opt.take().unwrap().into())
+            })
+        });
+
+    let conn = fut.wait().unwrap();
+
+    // wait so that we don't write until other side saw 101 response
+    rx.wait().unwrap();
+
+    let parts = conn.into_parts();
+    let io = parts.io;
+    assert_eq!(parts.read_buf, "eagerly optimistic");
+
+    let io = write_all(io, b"foo=bar").wait().unwrap().0;
+    let vec = read_to_end(io, vec![]).wait().unwrap().1;
+    assert_eq!(vec, b"bar=foo");
+}
+
+#[test]
+fn http_connect() {
+    use tokio_io::io::{read_to_end, write_all};
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = oneshot::channel();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            CONNECT localhost:80 HTTP/1.1\r\n\
+            \r\n\
+            eagerly optimistic\
+        ").expect("write 1");
+        let mut buf = [0; 256];
+        tcp.read(&mut buf).expect("read 1");
+
+        let expected = "HTTP/1.1 200 OK\r\n";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+        let _ = tx.send(());
+
+        let n = tcp.read(&mut buf).expect("read 2");
+        assert_eq!(s(&buf[..n]), "foo=bar");
+        tcp.write_all(b"bar=foo").expect("write 2");
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| -> hyper::Error { unreachable!() })
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            let conn = Http::new()
+                .serve_connection(socket, service_fn(|_| {
+                    let res = Response::builder()
+                        .status(200)
+                        .body(hyper::Body::empty())
+                        .unwrap();
+                    Ok::<_, hyper::Error>(res)
+                }));
+
+            let mut conn_opt = Some(conn);
+            future::poll_fn(move || {
+                try_ready!(conn_opt.as_mut().unwrap().poll_without_shutdown());
+                // conn is done with HTTP now
+                Ok(conn_opt.take().unwrap().into())
+            })
+        });
+
+    let conn = fut.wait().unwrap();
+
+    // wait so that we don't write until other side saw 101 response
+    rx.wait().unwrap();
+
+    let parts = conn.into_parts();
+    let io = parts.io;
+    assert_eq!(parts.read_buf, "eagerly optimistic");
+
+    let io = write_all(io, b"foo=bar").wait().unwrap().0;
+    let vec = read_to_end(io, vec![]).wait().unwrap().1;
+    assert_eq!(vec, b"bar=foo");
+}
+
+#[test]
+fn parse_errors_send_4xx_response() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GE T / HTTP/1.1\r\n\r\n").unwrap();
+        let mut buf = [0; 256];
+        tcp.read(&mut buf).unwrap();
+
+        let expected = "HTTP/1.1 400 ";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, HelloWorld)
+        });
+
+    fut.wait().unwrap_err();
+}
+
+#[test]
+fn lenient_uri_percent_encodes_target() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET /old device{1} HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).unwrap();
+
+        let expected = "HTTP/1.1 200 OK\r\n";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+        assert!(s(&buf).ends_with("/old%20device%7B1%7D"), "{:?}", s(&buf));
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http1_lenient_uri(true)
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    Ok::<_, hyper::Error>(Response::new(Body::from(req.uri().to_string())))
+                }))
+        });
+
+    fut.wait().unwrap();
+    client.join().unwrap();
+}
+
+#[test]
+fn record_request_line_passes_line_to_service() {
+    use hyper::ext::RawRequestLine;
+
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET /old device HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).unwrap();
+
+        let expected = "HTTP/1.1 200 OK\r\n";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+        assert!(s(&buf).ends_with("GET /old device HTTP/1.1"), "{:?}", s(&buf));
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http1_lenient_uri(true)
+                .http1_record_request_line(true)
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    let line = req.extensions().get::<RawRequestLine>().expect("raw request line");
+                    Ok::<_, hyper::Error>(Response::new(Body::from(line.as_bytes().to_vec())))
+                }))
+        });
+
+    fut.wait().unwrap();
+    client.join().unwrap();
+}
+
+#[test]
+fn lenient_target_passes_raw_target_to_service() {
+    use hyper::ext::RawTarget;
+
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET htt:p// HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).unwrap();
+
+        let expected = "HTTP/1.1 200 OK\r\n";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+        assert!(s(&buf).ends_with("htt:p//"));
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http1_lenient_target(true)
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    assert_eq!(req.uri(), "/");
+                    let raw = req.extensions()
+                        .get::<RawTarget>()
+                        .expect("raw target")
+                        .as_bytes()
+                        .to_vec();
+                    Ok::<_, hyper::Error>(Response::new(Body::from(raw)))
+                }))
+        });
+
+    fut.wait().unwrap();
+    client.join().unwrap();
+}
+
+#[test]
+fn http1_preserve_header_case() {
+    use hyper::ext::HeaderCaseMap;
+
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET / HTTP/1.1\r\nX-Echo-ME: hi\r\nConnection: close\r\n\r\n").unwrap();
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).unwrap();
+
+        assert!(s(&buf).contains("\r\nX-Echo-ME: hi\r\n"), "{:?}", s(&buf));
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http1_preserve_header_case(true)
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    let case = req.extensions()
+                        .get::<HeaderCaseMap>()
+                        .expect("header case map")
+                        .clone();
+                    let mut res = Response::new(Body::empty());
+                    res.headers_mut().insert("x-echo-me", req.headers()["x-echo-me"].clone());
+                    res.extensions_mut().insert(case);
+                    Ok::<_, hyper::Error>(res)
+                }))
+        });
+
+    fut.wait().unwrap();
+    client.join().unwrap();
+}
+
+#[test]
+fn http1_raw_framing() {
+    use hyper::ext::Framing;
+
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).unwrap();
+
+        assert_eq!(s(&buf), "HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n3;x=1\r\nfoo\r\n0\r\n\r\n");
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, service_fn(|_req: Request<Body>| {
+                    let mut res = Response::new(Body::from("3;x=1\r\nfoo\r\n0\r\n\r\n"));
+                    res.headers_mut().insert("transfer-encoding", "chunked".parse().unwrap());
+                    res.extensions_mut().insert(Framing::Raw);
+                    Ok::<_, hyper::Error>(res)
+                }))
+        });
+
+    fut.wait().unwrap();
+    client.join().unwrap();
+}
+
+#[test]
+fn http1_record_header_case() {
+    use hyper::ext::HeaderCaseMap;
+
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET / HTTP/1.1\r\nX-Echo-ME: hi\r\nConnection: close\r\n\r\n").unwrap();
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).unwrap();
+
+        assert!(s(&buf).contains("\r\nx-echo-me: hi\r\n"), "{:?}", s(&buf));
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http1_record_header_case(true)
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    let case = req.extensions()
+                        .get::<HeaderCaseMap>()
+                        .expect("header case map")
+                        .clone();
+                    let name = "x-echo-me".parse().unwrap();
+                    assert_eq!(case.get_all(&name).collect::<Vec<_>>(), ["X-Echo-ME"]);
+                    let mut res = Response::new(Body::empty());
+                    res.headers_mut().insert("x-echo-me", req.headers()["x-echo-me"].clone());
+                    res.extensions_mut().insert(case);
+                    Ok::<_, hyper::Error>(res)
+                }))
+        });
+
+    fut.wait().unwrap();
+    client.join().unwrap();
+}
+
+#[test]
+fn conflicting_framing_headers_return_400_response() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            POST / HTTP/1.1\r\n\
+            Content-Length: 5\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            0\r\n\r\n\
+        ").unwrap();
+        let mut buf = [0; 256];
+        tcp.read(&mut buf).unwrap();
+
+        let expected = "HTTP/1.1 400 ";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, HelloWorld)
+        });
+
+    fut.wait().unwrap_err();
+    client.join().unwrap();
+}
+
+#[test]
+fn long_request_line_returns_414_response() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET /this/is/too/long HTTP/1.1\r\n\r\n").unwrap();
+        let mut buf = [0; 256];
+        tcp.read(&mut buf).unwrap();
+
+        let expected = "HTTP/1.1 414 ";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .http1_max_request_line(20)
+                .serve_connection(socket, HelloWorld)
+        });
+
+    fut.wait().unwrap_err();
+    client.join().unwrap();
+}
+
+#[test]
+fn illegal_request_length_returns_400_response() {
+    let runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"POST / HTTP/1.1\r\nContent-Length: foo\r\n\r\n").unwrap();
+        let mut buf = [0; 256];
+        tcp.read(&mut buf).unwrap();
+
+        let expected = "HTTP/1.1 400 ";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let socket = item.unwrap();
+            Http::new()
+                .serve_connection(socket, HelloWorld)
+        });
+
+    fut.wait().unwrap_err();
+}
+
+#[test]
+#[should_panic]
+fn max_buf_size_panic_too_small() {
+    const MAX: usize = 8191;
+    Http::new().max_buf_size(MAX);
+}
+#[test]
+fn max_buf_size_no_panic() {
+    const MAX: usize = 8193;
+    Http::new().max_buf_size(MAX);
+}
+
+#[test]
+fn http_validate() {
+    Http::new().validate().expect("default is valid");
+
+    let err = Http::new()
+        .http2_only(true)
+        .http1_lenient_target(true)
+        .validate()
+        .unwrap_err();
+    assert!(err.is_config());
+    assert!(err.to_string().contains("http1_lenient_target"), "{}", err);
+
+    let err = Http::new()
+        .http1_only(true)
+        .http2_min_send_chunk_size(1024)
+        .validate()
+        .unwrap_err();
+    assert!(err.is_config());
+
+    let err = Http::new()
+        .http1_only(true)
+        .http2_scheduling_policy(hyper::server::conn::SchedulingPolicy::Sequential)
+        .validate()
+        .unwrap_err();
+    assert!(err.to_string().contains("http2_scheduling_policy"), "{}", err);
+
+    let err = Http::new()
+        .http1_init_write_buf_size(1 << 20)
+        .validate()
+        .unwrap_err();
+    assert!(err.is_config());
+
+    let err = Http::new()
+        .strict_conformance(true)
+        .http1_lenient_uri(true)
+        .validate()
+        .unwrap_err();
+    assert!(err.to_string().contains("http1_lenient_uri"), "{}", err);
 }
 
 #[test]
-fn disable_keep_alive_mid_request() {
+fn max_buf_size() {
+    let _ = pretty_env_logger::try_init();
     let runtime = Runtime::new().unwrap();
     let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
     let addr = listener.local_addr().unwrap();
 
-    let (tx1, rx1) = oneshot::channel();
-    let (tx2, rx2) = oneshot::channel();
+    const MAX: usize = 16_000;
 
-    let child = thread::spawn(move || {
-        let mut req = connect(&addr);
-        req.write_all(b"GET / HTTP/1.1\r\n").unwrap();
-        tx1.send(()).unwrap();
-        rx2.wait().unwrap();
-        req.write_all(b"Host: localhost\r\n\r\n").unwrap();
-        let mut buf = vec![];
-        req.read_to_end(&mut buf).unwrap();
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"POST /").expect("write 1");
+        tcp.write_all(&vec![b'a'; MAX]).expect("write 2");
+        let mut buf = [0; 256];
+        tcp.read(&mut buf).expect("read 1");
+
+        let expected = "HTTP/1.1 431 ";
+        assert_eq!(s(&buf[..expected.len()]), expected);
     });
 
     let fut = listener.incoming()
@@ -971,320 +3069,698 @@ fn disable_keep_alive_mid_request() {
         .map_err(|_| unreachable!())
         .and_then(|(item, _incoming)| {
             let socket = item.unwrap();
-            Http::new().serve_connection(socket, HelloWorld)
-                .select2(rx1)
-                .then(|r| {
-                    match r {
-                        Ok(Either::A(_)) => panic!("expected rx first"),
-                        Ok(Either::B(((), mut conn))) => {
-                            conn.graceful_shutdown();
-                            tx2.send(()).unwrap();
-                            conn
-                        }
-                        Err(Either::A((e, _))) => panic!("unexpected error {}", e),
-                        Err(Either::B((e, _))) => panic!("unexpected error {}", e),
-                    }
-                })
+            Http::new()
+                .max_buf_size(MAX)
+                .serve_connection(socket, HelloWorld)
         });
 
-    fut.wait().unwrap();
-    child.join().unwrap();
+    fut.wait().unwrap_err();
 }
 
 #[test]
-fn disable_keep_alive_post_request() {
+fn small_init_read_buf_size_grows() {
     let _ = pretty_env_logger::try_init();
     let runtime = Runtime::new().unwrap();
     let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
     let addr = listener.local_addr().unwrap();
 
-    let (tx1, rx1) = oneshot::channel();
-
-    let child = thread::spawn(move || {
-        let mut req = connect(&addr);
-        req.write_all(b"\
-            GET / HTTP/1.1\r\n\
-            Host: localhost\r\n\
-            \r\n\
-        ").unwrap();
-
-        let mut buf = [0; 1024 * 8];
-        loop {
-            let n = req.read(&mut buf).expect("reading 1");
-            if &buf[n - HELLO.len()..n] == HELLO.as_bytes() {
-                break;
-            }
-            if n == 0 {
-                panic!("unexpected eof");
-            }
-        }
-
-        tx1.send(()).unwrap();
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        let long = vec![b'a'; 12_000];
+        tcp.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\nX-Long: ").expect("write 1");
+        tcp.write_all(&long).expect("write 2");
+        tcp.write_all(b"\r\n\r\n").expect("write 3");
+        let mut buf = [0; 256];
+        tcp.read(&mut buf).expect("read 1");
 
-        let nread = req.read(&mut buf).expect("keep-alive reading");
-        assert_eq!(nread, 0);
+        let expected = "HTTP/1.1 200 ";
+        assert_eq!(s(&buf[..expected.len()]), expected);
     });
 
-    let dropped = Dropped::new();
-    let dropped2 = dropped.clone();
     let fut = listener.incoming()
         .into_future()
         .map_err(|_| unreachable!())
         .and_then(|(item, _incoming)| {
-            let socket = item.expect("accepted socket");
-            let transport = DebugStream {
-                stream: socket,
-                _debug: dropped2,
-            };
-            Http::new().serve_connection(transport, HelloWorld)
-                .select2(rx1)
-                .then(|r| {
-                    match r {
-                        Ok(Either::A(_)) => panic!("expected rx first"),
-                        Ok(Either::B(((), mut conn))) => {
-                            conn.graceful_shutdown();
-                            conn
-                        }
-                        Err(Either::A((e, _))) => panic!("unexpected error {}", e),
-                        Err(Either::B((e, _))) => panic!("unexpected error {}", e),
-                    }
-                })
+            let socket = item.unwrap();
+            Http::new()
+                .http1_init_read_buf_size(64)
+                .http1_max_read_buf_size(16_000)
+                .http1_init_write_buf_size(64)
+                .serve_connection(socket, HelloWorld)
         });
 
-    assert!(!dropped.load());
     fut.wait().unwrap();
-    // we must poll the Core one more time in order for Windows to drop
-    // the read-blocked socket.
-    //
-    // See https://github.com/carllerche/mio/issues/776
-    let timeout = Delay::new(Duration::from_millis(10));
-    timeout.wait().unwrap();
-    assert!(dropped.load());
-    child.join().unwrap();
+    client.join().unwrap();
 }
 
 #[test]
-fn empty_parse_eof_does_not_return_error() {
+fn server_builder_from_iter() {
+    let _ = pretty_env_logger::try_init();
+    let std_listener = StdTcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = std_listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).expect("read");
+        let expected = "HTTP/1.1 200 OK\r\n";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+    });
+
+    let (std_tcp, _) = std_listener.accept().unwrap();
+
     let runtime = Runtime::new().unwrap();
-    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
-    let addr = listener.local_addr().unwrap();
+    let tcp = tokio::net::TcpStream::from_std(std_tcp, &runtime.reactor()).unwrap();
+    let server = hyper::Server::builder_from_iter(vec![tcp])
+        .serve(|| {
+            service_fn(|_| Ok::<_, hyper::Error>(Response::new(Body::empty())))
+        });
 
-    thread::spawn(move || {
-        let _tcp = connect(&addr);
+    // the server future completes once the iterator is exhausted
+    runtime.block_on_all(server).unwrap();
+    client.join().unwrap();
+}
+
+#[test]
+fn graceful_shutdown_finishes_in_flight_requests() {
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    let shutdown_tx = Arc::new(Mutex::new(Some(shutdown_tx)));
+
+    let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into())
+        .serve(move || {
+            let shutdown_tx = shutdown_tx.clone();
+            service_fn(move |_| {
+                // start shutting down while this request is in flight
+                if let Some(tx) = shutdown_tx.lock().unwrap().take() {
+                    let _ = tx.send(());
+                }
+                Delay::new(Duration::from_millis(50))
+                    .map(|_| Response::new(Body::from("done")))
+                    .map_err(|e| -> hyper::Error { panic!("timer error: {}", e) })
+            })
+        });
+    let addr = server.local_addr();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut buf = vec![];
+        // the connection closes after the response, despite keep-alive
+        tcp.read_to_end(&mut buf).expect("read");
+        let resp = s(&buf);
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+        assert!(resp.ends_with("done"), "{}", resp);
+    });
+
+    let graceful = server.with_graceful_shutdown(shutdown_rx.map_err(|_| ()));
+    runtime.block_on(graceful).unwrap();
+    client.join().unwrap();
+}
+
+#[test]
+fn server_bind_all() {
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    let addrs = [
+        ([127, 0, 0, 1], 0).into(),
+        ([127, 0, 0, 1], 0).into(),
+    ];
+    let server = hyper::Server::bind_all(&addrs)
+        .serve(|| {
+            service_fn(|_| Ok::<_, hyper::Error>(Response::new(Body::empty())))
+        });
+    let local_addrs = server.local_addrs();
+    assert_eq!(local_addrs.len(), 2);
+    assert_ne!(local_addrs[0], local_addrs[1]);
+
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    let client = thread::spawn(move || {
+        for addr in &local_addrs {
+            let mut tcp = connect(addr);
+            tcp.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+            let mut buf = vec![];
+            tcp.read_to_end(&mut buf).expect("read");
+            let expected = "HTTP/1.1 200 OK\r\n";
+            assert_eq!(s(&buf[..expected.len()]), expected);
+        }
+        let _ = shutdown_tx.send(());
     });
 
+    let graceful = server.with_graceful_shutdown(shutdown_rx.map_err(|_| ()));
+    runtime.block_on(graceful).unwrap();
+    client.join().unwrap();
+}
+
+#[test]
+fn http2_frame_observer_sees_control_frames() {
+    use hyper::instrument::{ControlFrame, FrameObserver};
+
+    #[derive(Default)]
+    struct Frames {
+        received: Mutex<Vec<ControlFrame>>,
+        sent: Mutex<Vec<ControlFrame>>,
+    }
+
+    impl FrameObserver for Frames {
+        fn frame_received(&self, frame: &ControlFrame) {
+            self.received.lock().unwrap().push(frame.clone());
+        }
+
+        fn frame_sent(&self, frame: &ControlFrame) {
+            self.sent.lock().unwrap().push(frame.clone());
+        }
+    }
+
+    fn is_settings(frame: &ControlFrame, is_ack: bool) -> bool {
+        match *frame {
+            ControlFrame::Settings { ack, .. } => ack == is_ack,
+            _ => false,
+        }
+    }
+
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_frames = Arc::new(Frames::default());
+    let frames = server_frames.clone();
+    let (done_tx, done_rx) = oneshot::channel();
     let fut = listener.incoming()
         .into_future()
         .map_err(|_| unreachable!())
-        .and_then(|(item, _incoming)| {
+        .and_then(move |(item, _incoming)| {
             let socket = item.unwrap();
-            Http::new().serve_connection(socket, HelloWorld)
-        });
+            Http::new()
+                .http2_only(true)
+                .http2_frame_observer(frames)
+                .serve_connection(socket, service_fn(|_req| {
+                    Ok::<_, hyper::Error>(Response::new(Body::from("hello")))
+                }))
+                .map_err(|e| panic!("server error: {}", e))
+        })
+        .then(move |_| done_tx.send(()));
+    runtime.spawn(fut.map_err(|_| ()));
+
+    let client_frames = Arc::new(Frames::default());
+    let client: Client<_, Body> = Client::builder()
+        .http2_only(true)
+        .http2_frame_observer(client_frames.clone())
+        .build_http();
+    let res = runtime.block_on(client.get(format!("http://{}", addr).parse().unwrap())).unwrap();
+    runtime.block_on(res.into_body().concat2()).unwrap();
+    drop(client);
+    runtime.block_on(done_rx).unwrap();
+
+    for frames in &[&server_frames, &client_frames] {
+        let received = frames.received.lock().unwrap();
+        let sent = frames.sent.lock().unwrap();
+        assert!(is_settings(&received[0], false), "received: {:?}", received);
+        assert!(received.iter().any(|frame| is_settings(frame, true)), "received: {:?}", received);
+        assert!(is_settings(&sent[0], false), "sent: {:?}", sent);
+        assert!(sent.iter().any(|frame| is_settings(frame, true)), "sent: {:?}", sent);
+    }
+    // both ends saw the same frames
+    assert_eq!(*server_frames.received.lock().unwrap(), *client_frames.sent.lock().unwrap());
+}
 
-    fut.wait().unwrap();
+#[test]
+fn strict_conformance_reports_errors_sent() {
+    use futures::Async;
+    use hyper::instrument::{Instrumentation, SentError};
+
+    #[derive(Default)]
+    struct Errors(Mutex<Vec<SentError>>);
+
+    impl Instrumentation for Errors {
+        fn error_sent(&self, error: &SentError) {
+            self.0.lock().unwrap().push(error.clone());
+        }
+    }
+
+    fn serve(http: &Http, request: &[u8]) -> Vec<u8> {
+        let (mut client_io, server_io) = hyper::mock::duplex(4096);
+        let mut conn = http.serve_connection(server_io, service_fn(|_req| {
+            Ok::<_, hyper::Error>(Response::new(Body::empty()))
+        }));
+        client_io.write_all(request).unwrap();
+        client_io.shutdown().unwrap();
+        while let Ok(Async::NotReady) = conn.poll() {}
+        drop(conn);
+        let mut written = Vec::new();
+        client_io.read_to_end(&mut written).unwrap();
+        written
+    }
+
+    let _ = pretty_env_logger::try_init();
+    future::lazy(|| {
+        let errors = Arc::new(Errors::default());
+        let mut http = Http::new();
+        http.strict_conformance(true).instrumentation(errors.clone());
+
+        let written = serve(&http, b"GET / HTTP/1.1\r\n\r\n");
+        assert!(written.starts_with(b"HTTP/1.1 400 "), "{:?}", String::from_utf8_lossy(&written));
+        assert_eq!(*errors.0.lock().unwrap(), [SentError::Status(StatusCode::BAD_REQUEST)]);
+        errors.0.lock().unwrap().clear();
+
+        // a PING on a stream is a connection error
+        let mut h2 = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n".to_vec();
+        h2.extend_from_slice(&[0, 0, 0, 4, 0, 0, 0, 0, 0]);
+        h2.extend_from_slice(&[0, 0, 8, 6, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let written = serve(&http.http2_only(true), &h2);
+        // SETTINGS_MAX_CONCURRENT_STREAMS = 100
+        assert!(written.windows(6).any(|param| param == [0, 3, 0, 0, 0, 100]), "{:?}", written);
+        assert_eq!(*errors.0.lock().unwrap(), [SentError::GoAway {
+            last_stream_id: 0,
+            reason: hyper::error::Reason::PROTOCOL_ERROR,
+        }]);
+        Ok::<(), ()>(())
+    }).wait().unwrap();
 }
 
 #[test]
-fn nonempty_parse_eof_returns_error() {
-    let runtime = Runtime::new().unwrap();
-    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
-    let addr = listener.local_addr().unwrap();
+fn server_access_log() {
+    use hyper::instrument::Exchange;
+
+    fn entry(exchange: &Exchange) -> String {
+        format!(
+            "{} {} {:?} {:?} {} {}",
+            exchange.method(),
+            exchange.uri(),
+            exchange.version(),
+            exchange.status().map(|s| s.as_u16()),
+            exchange.bytes_read(),
+            exchange.bytes_written(),
+        )
+    }
 
-    thread::spawn(move || {
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let log2 = log.clone();
+    let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into())
+        .access_log(move |exchange| {
+            log2.lock().unwrap().push((entry(exchange), exchange.remote_addr()));
+        })
+        .serve(|| {
+            service_fn(|req: Request<Body>| {
+                req.into_body().concat2().map(|_| Response::new(Body::from("hello")))
+            })
+        });
+    let addr = server.local_addr();
+
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    let client = thread::spawn(move || {
         let mut tcp = connect(&addr);
-        tcp.write_all(b"GET / HTTP/1.1").unwrap();
+        tcp.write_all(b"\
+            POST /path?q HTTP/1.1\r\n\
+            Content-Length: 3\r\n\
+            Connection: close\r\n\
+            \r\n\
+            abc\
+        ").unwrap();
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).expect("read");
+        assert!(s(&buf).ends_with("hello"));
+        let _ = shutdown_tx.send(());
+        tcp.local_addr().unwrap()
     });
 
+    let graceful = server.with_graceful_shutdown(shutdown_rx.map_err(|_| ()));
+    runtime.block_on(graceful).unwrap();
+    let client_addr = client.join().unwrap();
+    assert_eq!(*log.lock().unwrap(), [
+        ("POST /path?q HTTP/1.1 Some(200) 3 5".to_owned(), Some(client_addr)),
+    ]);
+
+    // HTTP/2, through `Http`, which doesn't know the remote address
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let log2 = log.clone();
+    let (done_tx, done_rx) = oneshot::channel();
     let fut = listener.incoming()
         .into_future()
         .map_err(|_| unreachable!())
-        .and_then(|(item, _incoming)| {
+        .and_then(move |(item, _incoming)| {
             let socket = item.unwrap();
-            Http::new().serve_connection(socket, HelloWorld)
+            Http::new()
+                .http2_only(true)
+                .access_log(move |exchange| {
+                    log2.lock().unwrap().push((entry(exchange), exchange.remote_addr()));
+                })
+                .serve_connection(socket, service_fn(|req: Request<Body>| {
+                    req.into_body().concat2().map(|_| Response::new(Body::from("hello")))
+                }))
+                .map_err(|e| panic!("server error: {}", e))
+        })
+        .then(move |_| done_tx.send(()));
+    runtime.spawn(fut.map_err(|_| ()));
+
+    let client: Client<_, Body> = Client::builder()
+        .http2_only(true)
+        .build_http();
+    let req = Request::post(format!("http://{}/h2", addr))
+        .body(Body::from("abc"))
+        .unwrap();
+    let res = runtime.block_on(client.request(req)).unwrap();
+    runtime.block_on(res.into_body().concat2()).unwrap();
+    drop(client);
+    runtime.block_on(done_rx).unwrap();
+    assert_eq!(*log.lock().unwrap(), [
+        (format!("POST http://{}/h2 HTTP/2.0 Some(200) 3 5", addr), None),
+    ]);
+}
+
+#[test]
+fn server_accepts_from_listener() {
+    use hyper::server::accept::{Incoming, Listener};
+
+    // accepts as the `TcpListener` of another runtime would
+    struct OtherListener(TcpListener);
+
+    impl Listener for OtherListener {
+        type Conn = ::tokio::net::TcpStream;
+
+        fn poll_accept(&mut self) -> futures::Poll<(Self::Conn, SocketAddr), io::Error> {
+            self.0.poll_accept()
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.0.local_addr()
+        }
+    }
+
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let incoming = Incoming::new(OtherListener(listener));
+    let addr = incoming.local_addr().unwrap();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let log2 = log.clone();
+    let server = hyper::Server::builder(incoming)
+        .access_log(move |exchange| {
+            log2.lock().unwrap().push((exchange.uri().clone(), exchange.remote_addr()));
+        })
+        .serve(|| {
+            service_fn(|_req: Request<Body>| {
+                future::ok::<_, hyper::Error>(Response::new(Body::from("hello")))
+            })
         });
 
-    fut.wait().unwrap_err();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET /a HTTP/1.1\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).expect("read");
+        assert!(s(&buf).ends_with("hello"));
+        let _ = shutdown_tx.send(());
+        tcp.local_addr().unwrap()
+    });
+
+    let graceful = server.with_graceful_shutdown(shutdown_rx.map_err(|_| ()));
+    runtime.block_on(graceful).unwrap();
+    let client_addr = client.join().unwrap();
+    assert_eq!(*log.lock().unwrap(), [("/a".parse().unwrap(), Some(client_addr))]);
 }
 
 #[test]
-fn returning_1xx_response_is_error() {
-    let runtime = Runtime::new().unwrap();
-    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+fn request_ids() {
+    use hyper::ext::RequestId;
+
+    fn run(http2: bool) {
+        let _ = pretty_env_logger::try_init();
+        let mut runtime = Runtime::new().unwrap();
+        let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let log2 = log.clone();
+        let (done_tx, done_rx) = oneshot::channel();
+        let fut = listener.incoming()
+            .into_future()
+            .map_err(|_| unreachable!())
+            .and_then(move |(item, _incoming)| {
+                let socket = item.unwrap();
+                Http::new()
+                    .http2_only(http2)
+                    .request_id(Some("x-request-id".parse().unwrap()))
+                    .access_log(move |exchange| {
+                        log2.lock().unwrap().push(exchange.request_id().unwrap().to_string());
+                    })
+                    .serve_connection(socket, service_fn(|req: Request<Body>| {
+                        let id = req.extensions().get::<RequestId>().unwrap().as_str().to_owned();
+                        Ok::<_, hyper::Error>(Response::new(Body::from(id)))
+                    }))
+                    .map_err(|e| panic!("server error: {}", e))
+            })
+            .then(move |_| done_tx.send(()));
+        runtime.spawn(fut.map_err(|_| ()));
+
+        let client: Client<_, Body> = Client::builder()
+            .http2_only(http2)
+            .build_http();
+        let mut ids = Vec::new();
+        for adopted in &[Some("abc-123"), None, None] {
+            let mut req = Request::get(format!("http://{}", addr));
+            if let Some(id) = *adopted {
+                req.header("x-request-id", id);
+            }
+            let res = runtime.block_on(client.request(req.body(Body::empty()).unwrap())).unwrap();
+            let header = res.headers()["x-request-id"].to_str().unwrap().to_owned();
+            let body = runtime.block_on(res.into_body().concat2()).unwrap();
+            assert_eq!(header, s(&body));
+            if let Some(id) = *adopted {
+                assert_eq!(header, id);
+            }
+            ids.push(header);
+        }
+        drop(client);
+        runtime.block_on(done_rx).unwrap();
+
+        assert_ne!(ids[1], ids[2], "http2 = {}", http2);
+        // the log may be written after the client read the response
+        let mut logged = log.lock().unwrap().clone();
+        logged.sort();
+        ids.sort();
+        assert_eq!(logged, ids);
+    }
+
+    run(false);
+    run(true);
+}
+
+#[test]
+fn serve_connection_local() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use tokio::runtime::current_thread;
+
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = current_thread::Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &Handle::default()).unwrap();
     let addr = listener.local_addr().unwrap();
 
-    thread::spawn(move || {
+    let client = thread::spawn(move || {
         let mut tcp = connect(&addr);
-        tcp.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
-        let mut buf = [0; 256];
-        tcp.read(&mut buf).unwrap();
-
-        let expected = "HTTP/1.1 500 ";
-        assert_eq!(s(&buf[..expected.len()]), expected);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            \r\n\
+            GET / HTTP/1.1\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).expect("read");
+        s(&buf).to_owned()
     });
 
+    // a service that isn't `Send`
+    let count = Rc::new(Cell::new(0));
+    let count2 = count.clone();
     let fut = listener.incoming()
         .into_future()
         .map_err(|_| unreachable!())
-        .and_then(|(item, _incoming)| {
+        .and_then(move |(item, _incoming)| {
             let socket = item.unwrap();
             Http::new()
-                .serve_connection(socket, service_fn(|_| {
-                    Ok::<_, hyper::Error>(Response::builder()
-                        .status(StatusCode::CONTINUE)
-                        .body(Body::empty())
-                        .unwrap())
+                .serve_connection_local(socket, service_fn(move |_req| {
+                    count2.set(count2.get() + 1);
+                    Ok::<_, hyper::Error>(Response::new(Body::from(format!("request {}", count2.get()))))
                 }))
         });
+    runtime.block_on(fut).unwrap();
 
-    fut.wait().unwrap_err();
+    let res = client.join().unwrap();
+    assert!(res.contains("request 1"), "{:?}", res);
+    assert!(res.ends_with("request 2"), "{:?}", res);
+    assert_eq!(count.get(), 2);
 }
 
 #[test]
-fn upgrades() {
-    use tokio_io::io::{read_to_end, write_all};
+fn service_poll_ready_is_respected() {
+    use futures::{Async, Poll};
+
+    struct Gate {
+        ready: Option<oneshot::Receiver<()>>,
+        called: Arc<AtomicBool>,
+    }
+
+    impl Service for Gate {
+        type ReqBody = Body;
+        type ResBody = Body;
+        type Error = hyper::Error;
+        type Future = FutureResult<Response<Body>, hyper::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), hyper::Error> {
+            if let Some(mut ready) = self.ready.take() {
+                if let Ok(Async::NotReady) = ready.poll() {
+                    self.ready = Some(ready);
+                    return Ok(Async::NotReady);
+                }
+            }
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _req: Request<Body>) -> Self::Future {
+            self.called.store(true, Ordering::SeqCst);
+            future::ok(Response::new(Body::from("ready")))
+        }
+    }
+
     let _ = pretty_env_logger::try_init();
-    let runtime = Runtime::new().unwrap();
+    let mut runtime = Runtime::new().unwrap();
     let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
     let addr = listener.local_addr().unwrap();
-    let (tx, rx) = oneshot::channel();
 
-    thread::spawn(move || {
+    let (ready_tx, ready_rx) = oneshot::channel();
+    let called = Arc::new(AtomicBool::new(false));
+    let called2 = called.clone();
+    let client = thread::spawn(move || {
         let mut tcp = connect(&addr);
         tcp.write_all(b"\
             GET / HTTP/1.1\r\n\
-            Upgrade: foobar\r\n\
-            Connection: upgrade\r\n\
+            Connection: close\r\n\
             \r\n\
-            eagerly optimistic\
-        ").expect("write 1");
-        let mut buf = [0; 256];
-        tcp.read(&mut buf).expect("read 1");
-
-        let expected = "HTTP/1.1 101 Switching Protocols\r\n";
-        assert_eq!(s(&buf[..expected.len()]), expected);
-        let _ = tx.send(());
-
-        let n = tcp.read(&mut buf).expect("read 2");
-        assert_eq!(s(&buf[..n]), "foo=bar");
-        tcp.write_all(b"bar=foo").expect("write 2");
+        ").unwrap();
+        thread::sleep(Duration::from_millis(100));
+        // the request isn't given to the service until it's ready
+        assert!(!called2.load(Ordering::SeqCst));
+        ready_tx.send(()).unwrap();
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).expect("read");
+        s(&buf).to_owned()
     });
 
+    let called2 = called.clone();
     let fut = listener.incoming()
         .into_future()
-        .map_err(|_| -> hyper::Error { unreachable!() })
-        .and_then(|(item, _incoming)| {
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
             let socket = item.unwrap();
-            let conn = Http::new()
-                .serve_connection(socket, service_fn(|_| {
-                    let res = Response::builder()
-                        .status(101)
-                        .header("upgrade", "foobar")
-                        .body(hyper::Body::empty())
-                        .unwrap();
-                    Ok::<_, hyper::Error>(res)
-                }));
-
-            let mut conn_opt = Some(conn);
-            future::poll_fn(move || {
-                try_ready!(conn_opt.as_mut().unwrap().poll_without_shutdown());
-                // conn is done with HTTP now
-                Ok(conn_opt.take().unwrap().into())
-            })
+            Http::new()
+                .serve_connection(socket, Gate {
+                    ready: Some(ready_rx),
+                    called: called2,
+                })
         });
+    runtime.block_on(fut).unwrap();
 
-    let conn = fut.wait().unwrap();
+    let res = client.join().unwrap();
+    assert!(res.ends_with("ready"), "{:?}", res);
+    assert!(called.load(Ordering::SeqCst));
+}
 
-    // wait so that we don't write until other side saw 101 response
-    rx.wait().unwrap();
+#[test]
+fn service_poll_ready_error_closes_connection() {
+    use futures::{Async, Poll};
 
-    let parts = conn.into_parts();
-    let io = parts.io;
-    assert_eq!(parts.read_buf, "eagerly optimistic");
+    struct Broken;
 
-    let io = write_all(io, b"foo=bar").wait().unwrap().0;
-    let vec = read_to_end(io, vec![]).wait().unwrap().1;
-    assert_eq!(vec, b"bar=foo");
-}
+    impl Service for Broken {
+        type ReqBody = Body;
+        type ResBody = Body;
+        type Error = io::Error;
+        type Future = FutureResult<Response<Body>, io::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), io::Error> {
+            Err(io::Error::new(io::ErrorKind::Other, "broken"))
+        }
+
+        fn call(&mut self, _req: Request<Body>) -> Self::Future {
+            unreachable!("service isn't ready");
+        }
+    }
 
-#[test]
-fn http_connect() {
-    use tokio_io::io::{read_to_end, write_all};
-    let _ = pretty_env_logger::try_init();
     let runtime = Runtime::new().unwrap();
     let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
     let addr = listener.local_addr().unwrap();
-    let (tx, rx) = oneshot::channel();
 
-    thread::spawn(move || {
+    let client = thread::spawn(move || {
         let mut tcp = connect(&addr);
-        tcp.write_all(b"\
-            CONNECT localhost:80 HTTP/1.1\r\n\
-            \r\n\
-            eagerly optimistic\
-        ").expect("write 1");
-        let mut buf = [0; 256];
-        tcp.read(&mut buf).expect("read 1");
-
-        let expected = "HTTP/1.1 200 OK\r\n";
-        assert_eq!(s(&buf[..expected.len()]), expected);
-        let _ = tx.send(());
-
-        let n = tcp.read(&mut buf).expect("read 2");
-        assert_eq!(s(&buf[..n]), "foo=bar");
-        tcp.write_all(b"bar=foo").expect("write 2");
+        tcp.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut buf = vec![];
+        let _ = tcp.read_to_end(&mut buf);
     });
 
     let fut = listener.incoming()
         .into_future()
-        .map_err(|_| -> hyper::Error { unreachable!() })
+        .map_err(|_| unreachable!())
         .and_then(|(item, _incoming)| {
             let socket = item.unwrap();
-            let conn = Http::new()
-                .serve_connection(socket, service_fn(|_| {
-                    let res = Response::builder()
-                        .status(200)
-                        .body(hyper::Body::empty())
-                        .unwrap();
-                    Ok::<_, hyper::Error>(res)
-                }));
-
-            let mut conn_opt = Some(conn);
-            future::poll_fn(move || {
-                try_ready!(conn_opt.as_mut().unwrap().poll_without_shutdown());
-                // conn is done with HTTP now
-                Ok(conn_opt.take().unwrap().into())
-            })
+            Http::new().serve_connection(socket, Broken)
         });
 
-    let conn = fut.wait().unwrap();
+    let err = fut.wait().unwrap_err();
+    assert!(err.is_user(), "{:?}", err);
+    client.join().unwrap();
+}
 
-    // wait so that we don't write until other side saw 101 response
-    rx.wait().unwrap();
+#[test]
+fn http2_service_not_ready_still_drives_streams() {
+    use futures::{Async, Poll};
 
-    let parts = conn.into_parts();
-    let io = parts.io;
-    assert_eq!(parts.read_buf, "eagerly optimistic");
+    // ready for a single request, which is then responded to slowly
+    struct Once(bool);
 
-    let io = write_all(io, b"foo=bar").wait().unwrap().0;
-    let vec = read_to_end(io, vec![]).wait().unwrap().1;
-    assert_eq!(vec, b"bar=foo");
-}
+    impl Service for Once {
+        type ReqBody = Body;
+        type ResBody = Body;
+        type Error = hyper::Error;
+        type Future = FutureResult<Response<Body>, hyper::Error>;
 
-#[test]
-fn parse_errors_send_4xx_response() {
-    let runtime = Runtime::new().unwrap();
-    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
-    let addr = listener.local_addr().unwrap();
+        fn poll_ready(&mut self) -> Poll<(), hyper::Error> {
+            if self.0 {
+                Ok(Async::NotReady)
+            } else {
+                Ok(Async::Ready(()))
+            }
+        }
 
-    thread::spawn(move || {
-        let mut tcp = connect(&addr);
-        tcp.write_all(b"GE T / HTTP/1.1\r\n\r\n").unwrap();
-        let mut buf = [0; 256];
-        tcp.read(&mut buf).unwrap();
+        fn call(&mut self, _req: Request<Body>) -> Self::Future {
+            self.0 = true;
+            let (mut tx, body) = Body::channel();
+            let later = Delay::new(Duration::from_millis(50))
+                .map(move |()| {
+                    let _ = tx.send_data("hello".into());
+                })
+                .map_err(|_| ());
+            tokio::spawn(later);
+            future::ok(Response::new(body))
+        }
+    }
 
-        let expected = "HTTP/1.1 400 ";
-        assert_eq!(s(&buf[..expected.len()]), expected);
-    });
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
 
     let fut = listener.incoming()
         .into_future()
@@ -1292,70 +3768,293 @@ fn parse_errors_send_4xx_response() {
         .and_then(|(item, _incoming)| {
             let socket = item.unwrap();
             Http::new()
-                .serve_connection(socket, HelloWorld)
+                .http2_only(true)
+                .serve_connection(socket, Once(false))
+                .map_err(|e| panic!("server error: {}", e))
         });
+    runtime.spawn(fut);
+
+    let client: Client<_, hyper::Body> = Client::builder().http2_only(true).build_http();
+    let uri = format!("http://{}", addr).parse::<hyper::Uri>().unwrap();
+    let body = client.get(uri).and_then(|res| res.into_body().concat2());
+    let timeout = Delay::new(Duration::from_secs(5));
+    match runtime.block_on(body.select2(timeout)) {
+        Ok(Either::A((body, _))) => assert_eq!(s(&body), "hello"),
+        _ => panic!("response body wasn't sent"),
+    }
+}
 
-    fut.wait().unwrap_err();
+#[test]
+fn mock_duplex_small_capacity() {
+    use hyper::client::conn;
+
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    // far less than the bodies, so both sides wait on each other
+    let (client_io, server_io) = hyper::mock::duplex(64);
+    let body = vec![b'x'; 16 * 1024];
+
+    let server = Http::new()
+        .serve_connection(server_io, service_fn(|req: Request<Body>| {
+            req.into_body().concat2().map(|body| Response::new(Body::from(body)))
+        }))
+        .map_err(|e| panic!("server error: {}", e));
+    runtime.spawn(server);
+
+    let req_body = body.clone();
+    let res = runtime.block_on(conn::handshake(client_io).and_then(move |(mut tx, conn)| {
+        hyper::rt::spawn(conn.map_err(|e| panic!("client conn error: {}", e)));
+        tx.send_request(Request::post("/").body(Body::from(req_body)).unwrap())
+            .and_then(|res| res.into_body().concat2())
+    })).unwrap();
+    assert_eq!(res.len(), body.len());
+    assert_eq!(&res[..], &body[..]);
 }
 
 #[test]
-fn illegal_request_length_returns_400_response() {
-    let runtime = Runtime::new().unwrap();
-    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
-    let addr = listener.local_addr().unwrap();
+fn mock_faults_partial_writes_and_latency() {
+    use std::time::Duration;
+    use hyper::client::conn;
+    use hyper::mock::Faults;
 
-    thread::spawn(move || {
-        let mut tcp = connect(&addr);
-        tcp.write_all(b"POST / HTTP/1.1\r\nContent-Length: foo\r\n\r\n").unwrap();
-        let mut buf = [0; 256];
-        tcp.read(&mut buf).unwrap();
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    let (client_io, server_io) = hyper::mock::duplex(1024);
+    let faults = Faults::new(0x5eed)
+        .partial_writes(0.8)
+        .latency(Duration::from_millis(2));
+    let body = vec![b'x'; 16 * 1024];
+
+    let server = Http::new()
+        .serve_connection(faults.wrap(server_io), service_fn(|req: Request<Body>| {
+            req.into_body().concat2().map(|body| Response::new(Body::from(body)))
+        }))
+        .map_err(|e| panic!("server error: {}", e));
+    runtime.spawn(server);
+
+    let req_body = body.clone();
+    let res = runtime.block_on(conn::handshake(faults.wrap(client_io)).and_then(move |(mut tx, conn)| {
+        hyper::rt::spawn(conn.map_err(|e| panic!("client conn error: {}", e)));
+        tx.send_request(Request::post("/").body(Body::from(req_body)).unwrap())
+            .and_then(|res| res.into_body().concat2())
+    })).unwrap();
+    assert_eq!(&res[..], &body[..]);
+}
 
-        let expected = "HTTP/1.1 400 ";
-        assert_eq!(s(&buf[..expected.len()]), expected);
-    });
+#[test]
+fn connection_state() {
+    use std::io::Write;
+
+    let _ = pretty_env_logger::try_init();
+    future::lazy(|| {
+        let (mut client_io, server_io) = hyper::mock::duplex(1024);
+        let mut conn = Http::new()
+            .serve_connection(server_io, service_fn(|req| {
+                // keep the request body, so it isn't discarded
+                future::empty::<(), hyper::Error>().map(move |()| {
+                    drop(req);
+                    Response::new(Body::empty())
+                })
+            }));
+
+        assert!(conn.poll().unwrap().is_not_ready());
+        let state = conn.state();
+        assert_eq!(state.version(), Some(hyper::Version::HTTP_11));
+        assert_eq!(state.reading(), "head");
+        assert_eq!(state.in_flight(), 0);
+        assert!(state.keep_alive());
+
+        client_io.write_all(b"POST / HTTP/1.1\r\ncontent-length: 5\r\n\r\nhel").unwrap();
+        assert!(conn.poll().unwrap().is_not_ready());
+        let state = conn.state();
+        assert_eq!(state.reading(), "body");
+        assert_eq!(state.writing(), "head");
+        assert_eq!(state.in_flight(), 1);
+        assert!(format!("{:?}", state).contains("reading: \"body\""));
+        Ok::<(), ()>(())
+    }).wait().unwrap();
+}
+
+#[test]
+fn pipelined_requests_yield_to_other_tasks() {
+    use std::io::{Read, Write};
+
+    let _ = pretty_env_logger::try_init();
+    future::lazy(|| {
+        let (mut client_io, server_io) = hyper::mock::duplex(16 * 1024);
+        let mut conn = Http::new()
+            .pipeline_flush(true)
+            .serve_connection(server_io, hyper::service::service_fn_ok(|_req| Response::new(Body::empty())));
+
+        let requests = 100;
+        for _ in 0..requests {
+            client_io.write_all(b"GET / HTTP/1.1\r\nHost: example.domain\r\n\r\n").unwrap();
+        }
+
+        let mut responses = Vec::new();
+        let mut polls = 0;
+        while responses.windows(12).filter(|w| w == b"HTTP/1.1 200").count() < requests {
+            assert!(conn.poll().unwrap().is_not_ready());
+            polls += 1;
+            assert!(polls <= requests, "connection stopped making progress");
+            let mut buf = [0; 16 * 1024];
+            match client_io.read(&mut buf) {
+                Ok(n) => responses.extend_from_slice(&buf[..n]),
+                Err(ref e) if e.kind() == ::std::io::ErrorKind::WouldBlock => (),
+                Err(e) => panic!("read error: {}", e),
+            }
+        }
+        // a connection with endless ready work doesn't keep the thread
+        assert!(polls > 1, "all requests were served in a single poll");
+        Ok::<(), ()>(())
+    }).wait().unwrap();
+}
+
+#[test]
+fn connection_stats() {
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
+    let addr = listener.local_addr().unwrap();
 
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let log2 = log.clone();
+    let (stats_tx, stats_rx) = oneshot::channel();
     let fut = listener.incoming()
         .into_future()
         .map_err(|_| unreachable!())
-        .and_then(|(item, _incoming)| {
+        .and_then(move |(item, _incoming)| {
             let socket = item.unwrap();
-            Http::new()
-                .serve_connection(socket, HelloWorld)
+            let conn = Http::new()
+                .access_log(move |exchange| {
+                    log2.lock().unwrap().push(exchange.connection().requests());
+                })
+                .serve_connection(socket, service_fn(|_req| {
+                    Ok::<_, hyper::Error>(Response::new(Body::from("hello")))
+                }));
+            let _ = stats_tx.send(conn.stats());
+            conn.map_err(|e| panic!("server error: {}", e))
         });
+    runtime.spawn(fut.map_err(|_| ()));
 
-    fut.wait().unwrap_err();
+    let mut tcp = connect(&addr);
+    let req = b"GET / HTTP/1.1\r\n\r\n";
+    let last = b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n";
+    tcp.write_all(req).unwrap();
+    let mut buf = [0; 256];
+    let n = tcp.read(&mut buf).unwrap();
+    assert!(s(&buf[..n]).ends_with("hello"));
+    tcp.write_all(last).unwrap();
+    let mut rest = vec![];
+    tcp.read_to_end(&mut rest).unwrap();
+
+    let stats = runtime.block_on(stats_rx).unwrap();
+    assert_eq!(stats.requests(), 2);
+    assert_eq!(stats.bytes_read(), req.len() + last.len());
+    assert_eq!(stats.bytes_written(), n + rest.len());
+    assert_eq!(*log.lock().unwrap(), [1, 2]);
 }
 
 #[test]
-#[should_panic]
-fn max_buf_size_panic_too_small() {
-    const MAX: usize = 8191;
-    Http::new().max_buf_size(MAX);
+fn server_try_bind_all_empty_is_error() {
+    let err = hyper::Server::try_bind_all(&[]).unwrap_err();
+    assert_eq!(err.to_string(), "error creating server listener: no addresses to bind");
 }
+
+#[cfg(unix)]
 #[test]
-fn max_buf_size_no_panic() {
-    const MAX: usize = 8193;
-    Http::new().max_buf_size(MAX);
+fn server_bind_with_reuse_port() {
+    use hyper::server::conn::ListenOptions;
+
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    let mut opts = ListenOptions::new();
+    opts.reuse_port(true);
+
+    runtime.block_on(future::lazy(move || {
+        let first = hyper::Server::try_bind_with(&([127, 0, 0, 1], 0).into(), &opts)
+            .expect("first bind")
+            .serve(|| {
+                service_fn(|_| Ok::<_, hyper::Error>(Response::new(Body::empty())))
+            });
+        let addr = first.local_addr();
+
+        // binding the same port again only works with SO_REUSEPORT
+        let second = hyper::Server::try_bind_with(&addr, &opts)
+            .expect("second bind")
+            .serve(|| {
+                service_fn(|_| Ok::<_, hyper::Error>(Response::new(Body::empty())))
+            });
+        assert_eq!(second.local_addr(), addr);
+
+        hyper::Server::try_bind(&addr).expect_err("bind without reuse_port");
+        Ok::<(), ()>(())
+    })).unwrap();
 }
 
 #[test]
-fn max_buf_size() {
+fn server_from_tcp() {
+    let _ = pretty_env_logger::try_init();
+    let mut runtime = Runtime::new().unwrap();
+    let std_listener = StdTcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = std_listener.local_addr().unwrap();
+
+    let server = runtime.block_on(future::lazy(move || {
+        hyper::Server::from_tcp(std_listener)
+            .map(|builder| builder.serve(|| {
+                service_fn(|_| Ok::<_, hyper::Error>(Response::new(Body::empty())))
+            }))
+    })).expect("from_tcp");
+    assert_eq!(server.local_addr(), addr);
+
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).expect("read");
+        let expected = "HTTP/1.1 200 OK\r\n";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+        let _ = shutdown_tx.send(());
+    });
+
+    let graceful = server.with_graceful_shutdown(shutdown_rx.map_err(|_| ()));
+    runtime.block_on(graceful).unwrap();
+    client.join().unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn server_pass_listener_to_child() {
+    use std::process::Command;
+
+    let mut runtime = Runtime::new().unwrap();
+    let server = runtime.block_on(future::lazy(|| {
+        hyper::Server::try_bind(&([127, 0, 0, 1], 0).into())
+            .map(|builder| builder.serve(|| {
+                service_fn(|_| Ok::<_, hyper::Error>(Response::new(Body::empty())))
+            }))
+    })).expect("bind");
+
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg("test -e /proc/self/fd/$HYPER_LISTEN_FDS");
+    server.pass_listener(&mut cmd);
+    assert!(cmd.status().expect("spawn sh").success(), "listener fd inherited");
+}
+
+fn proxy_protocol_echo_source(preamble: &'static [u8]) -> String {
     let _ = pretty_env_logger::try_init();
     let runtime = Runtime::new().unwrap();
     let listener = tcp_bind(&"127.0.0.1:0".parse().unwrap(), &runtime.reactor()).unwrap();
     let addr = listener.local_addr().unwrap();
 
-    const MAX: usize = 16_000;
-
-    thread::spawn(move || {
+    let client = thread::spawn(move || {
         let mut tcp = connect(&addr);
-        tcp.write_all(b"POST /").expect("write 1");
-        tcp.write_all(&vec![b'a'; MAX]).expect("write 2");
-        let mut buf = [0; 256];
-        tcp.read(&mut buf).expect("read 1");
-
-        let expected = "HTTP/1.1 431 ";
-        assert_eq!(s(&buf[..expected.len()]), expected);
+        tcp.write_all(preamble).unwrap();
+        tcp.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let mut buf = vec![];
+        tcp.read_to_end(&mut buf).expect("read");
+        String::from_utf8(buf).unwrap()
     });
 
     let fut = listener.incoming()
@@ -1364,11 +4063,43 @@ fn max_buf_size() {
         .and_then(|(item, _incoming)| {
             let socket = item.unwrap();
             Http::new()
-                .max_buf_size(MAX)
-                .serve_connection(socket, HelloWorld)
+                .proxy_protocol(true)
+                .serve_connection(socket, service_fn(|req| {
+                    let src = req.extensions()
+                        .get::<hyper::server::conn::ProxyInfo>()
+                        .and_then(|info| info.source())
+                        .map(|addr| addr.to_string())
+                        .unwrap_or_default();
+                    Ok::<_, hyper::Error>(Response::new(Body::from(src)))
+                }))
         });
 
-    fut.wait().unwrap_err();
+    let _ = fut.wait();
+    client.join().unwrap()
+}
+
+#[test]
+fn proxy_protocol_v1() {
+    let resp = proxy_protocol_echo_source(
+        b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n",
+    );
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+    assert!(resp.ends_with("192.168.0.1:56324"), "{}", resp);
+}
+
+#[test]
+fn proxy_protocol_v2() {
+    let resp = proxy_protocol_echo_source(
+        b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c\x0a\x00\x00\x01\x0a\x00\x00\x02\x1f\x90\x00\x50",
+    );
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+    assert!(resp.ends_with("10.0.0.1:8080"), "{}", resp);
+}
+
+#[test]
+fn proxy_protocol_missing_header_closes() {
+    let resp = proxy_protocol_echo_source(b"");
+    assert_eq!(resp, "");
 }
 
 #[test]